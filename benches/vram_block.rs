@@ -0,0 +1,21 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use gba_rustmulator::system::{MemoryInterface, SystemBus, VRAM_ADDR};
+
+const FILL_HALFWORDS: usize = 0xc000;
+
+fn bench_vram_fill(c: &mut Criterion) {
+	let mut bus = SystemBus::new(Vec::<u8>::new().into_boxed_slice());
+	let data: Vec<u16> = (0..FILL_HALFWORDS).map(|i| i as u16).collect();
+
+	c.bench_function("VRAM fill (per halfword)", |b| {
+		b.iter(|| {
+			for (i, value) in data.iter().enumerate() {
+				bus.write_16(VRAM_ADDR + i as u32 * 2, *value);
+			}
+		})
+	});
+	c.bench_function("VRAM fill (block)", |b| b.iter(|| bus.write_block_16(VRAM_ADDR, &data)));
+}
+
+criterion_group!(benches, bench_vram_fill);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! Run a ROM headlessly and save the last frame
+//!
+//! Usage: cargo run --release --example screenshot -- <bios> <rom>
+
+use std::path::Path;
+
+use gba_rustmulator::debugging::frame_diff::save_frame_png;
+use gba_rustmulator::gba::Gba;
+use gba_rustmulator::system::cartridge::{get_cartridge_type, load_rom_file};
+
+const FRAMES: u32 = 300;
+
+fn main() -> std::io::Result<()> {
+	let args: Vec<String> = std::env::args().collect();
+	if args.len() < 3 {
+		eprintln!("Usage: {} <bios> <rom>", args[0]);
+		std::process::exit(1);
+	}
+
+	let bios_data = std::fs::read(&args[1])?;
+	let cartridge_data = load_rom_file(Path::new(&args[2]))?;
+	let mut gba = Gba::new_with_type(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice(), get_cartridge_type(Path::new(&args[2])));
+
+	for _ in 0..FRAMES {
+		gba.run_frame();
+	}
+
+	save_frame_png(Path::new("out.png"), &gba.render())?;
+	println!("Saved frame {} to out.png", FRAMES);
+
+	Ok(())
+}
@@ -0,0 +1,1097 @@
+use std::collections::VecDeque;
+use std::io::Result;
+
+use bitfield::*;
+
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::system::MemoryInterface;
+
+pub const APU_REGISTERS_START: u32 = 0x60;
+pub const APU_REGISTERS_END: u32 = 0xa7;
+
+pub const SOUND1_CNT_L_ADDRESS: u32 = 0x60;
+pub const SOUND1_CNT_H_ADDRESS: u32 = 0x62;
+pub const SOUND1_CNT_X_ADDRESS: u32 = 0x64;
+pub const SOUND2_CNT_L_ADDRESS: u32 = 0x68;
+pub const SOUND2_CNT_H_ADDRESS: u32 = 0x6c;
+pub const SOUND3_CNT_L_ADDRESS: u32 = 0x70;
+pub const SOUND3_CNT_H_ADDRESS: u32 = 0x72;
+pub const SOUND3_CNT_X_ADDRESS: u32 = 0x74;
+pub const SOUND4_CNT_L_ADDRESS: u32 = 0x78;
+pub const SOUND4_CNT_H_ADDRESS: u32 = 0x7c;
+pub const SOUND_CNT_L_ADDRESS: u32 = 0x80;
+pub const SOUND_CNT_H_ADDRESS: u32 = 0x82;
+pub const SOUND_CNT_X_ADDRESS: u32 = 0x84;
+pub const SOUND_BIAS_ADDRESS: u32 = 0x88;
+pub const WAVE_RAM_START_ADDRESS: u32 = 0x90;
+pub const WAVE_RAM_END_ADDRESS: u32 = 0x9f;
+pub const FIFO_A_ADDRESS: u32 = 0xa0;
+pub const FIFO_B_ADDRESS: u32 = 0xa4;
+
+/// Rate of the samples produced by the mixer
+pub const SAMPLE_RATE: u32 = 32768;
+const CYCLES_PER_SAMPLE: u32 = 16_777_216 / SAMPLE_RATE;
+/// The frame sequencer clocks the lengths, sweep and envelopes at 512Hz
+const CYCLES_PER_FRAME_SEQUENCER_STEP: u32 = 16_777_216 / 512;
+/// Stereo samples kept when the frontend doesn't take them (1 second)
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize * 2;
+
+const WAVE_RAM_BANK_SIZE: usize = 16;
+
+/// The Direct Sound FIFOs hold 32 samples (8 words)
+const FIFO_LENGTH: usize = 32;
+/// DMA is asked for 4 more words once half of the FIFO has been played
+const FIFO_REFILL_LENGTH: usize = 16;
+
+/// Square waveforms for 12.5%, 25%, 50% and 75% duty cycles
+const DUTY_PATTERNS: [[bool; 8]; 4] = [
+	[false, false, false, false, false, false, false, true],
+	[true, false, false, false, false, false, false, true],
+	[true, false, false, false, false, true, true, true],
+	[false, true, true, true, true, true, true, false],
+];
+
+bitfield! {
+	/// Channel 1 Sweep register (R/W)
+	#[derive(Clone, Copy)]
+	pub struct SweepControl(u16);
+	impl Debug;
+	pub u8, get_shift, _: 2, 0;
+	pub get_decrease, _: 3;
+	pub u8, get_time, _: 6, 4;
+}
+
+bitfield! {
+	/// Channel 1/2/4 Duty/Length/Envelope (R/W, length W)
+	#[derive(Clone, Copy)]
+	pub struct DutyLengthEnvelope(u16);
+	impl Debug;
+	pub u8, get_length, _: 5, 0;
+	pub u8, get_duty, _: 7, 6;
+	pub u8, get_envelope_step_time, _: 10, 8;
+	pub get_envelope_increase, _: 11;
+	pub u8, get_initial_volume, _: 15, 12;
+}
+
+bitfield! {
+	/// Channel 1/2/3 Frequency/Control (R/W, frequency W)
+	#[derive(Clone, Copy)]
+	pub struct FrequencyControl(u16);
+	impl Debug;
+	pub u16, get_frequency, set_frequency: 10, 0;
+	pub get_length_flag, _: 14;
+	pub get_restart, _: 15;
+}
+
+bitfield! {
+	/// Channel 3 Stop/Wave RAM select (R/W)
+	#[derive(Clone, Copy)]
+	pub struct WaveSelect(u16);
+	impl Debug;
+	pub get_two_banks, _: 5;
+	pub get_bank, _: 6;
+	pub get_playback, _: 7;
+}
+
+bitfield! {
+	/// Channel 3 Length/Volume (R/W, length W)
+	#[derive(Clone, Copy)]
+	pub struct WaveLengthVolume(u16);
+	impl Debug;
+	pub u8, get_length, _: 7, 0;
+	pub u8, get_volume, _: 14, 13;
+	pub get_force_volume, _: 15;
+}
+
+bitfield! {
+	/// Channel 4 Frequency/Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct NoiseControl(u16);
+	impl Debug;
+	pub u8, get_dividing_ratio, _: 2, 0;
+	pub get_is_7bit, _: 3;
+	pub u8, get_shift_clock, _: 7, 4;
+	pub get_length_flag, _: 14;
+	pub get_restart, _: 15;
+}
+
+bitfield! {
+	/// Control Stereo/Volume/Enable (R/W)
+	#[derive(Clone, Copy)]
+	pub struct SoundControlL(u16);
+	impl Debug;
+	pub u8, get_right_volume, _: 2, 0;
+	pub u8, get_left_volume, _: 6, 4;
+	pub u8, get_right_enables, _: 11, 8;
+	pub u8, get_left_enables, _: 15, 12;
+}
+
+bitfield! {
+	/// Control Mixing/DMA Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct SoundControlH(u16);
+	impl Debug;
+	pub u8, get_psg_volume, _: 1, 0;
+	pub get_dma_sound_a_full_volume, _: 2;
+	pub get_dma_sound_b_full_volume, _: 3;
+	pub get_dma_sound_a_right_enable, _: 8;
+	pub get_dma_sound_a_left_enable, _: 9;
+	pub get_dma_sound_a_timer, _: 10;
+	pub get_dma_sound_a_reset, _: 11;
+	pub get_dma_sound_b_right_enable, _: 12;
+	pub get_dma_sound_b_left_enable, _: 13;
+	pub get_dma_sound_b_timer, _: 14;
+	pub get_dma_sound_b_reset, _: 15;
+}
+
+bitfield! {
+	/// Sound PWM Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct SoundBias(u32);
+	impl Debug;
+	pub u16, get_bias_level, set_bias_level: 9, 1;
+	pub u8, get_amplitude_res, _: 15, 14;
+}
+
+/// Volume envelope of the square and noise channels
+#[derive(Debug, Copy, Clone, Default)]
+struct Envelope {
+	volume: u8,
+	increase: bool,
+	step_time: u8,
+	timer: u8,
+}
+
+impl Envelope {
+	fn restart(&mut self, control: DutyLengthEnvelope) {
+		self.volume = control.get_initial_volume();
+		self.increase = control.get_envelope_increase();
+		self.step_time = control.get_envelope_step_time();
+		self.timer = self.step_time;
+	}
+
+	/// 64Hz
+	fn clock(&mut self) {
+		if self.step_time == 0 {
+			return;
+		}
+
+		self.timer -= 1;
+		if self.timer == 0 {
+			self.timer = self.step_time;
+			if self.increase && self.volume < 15 {
+				self.volume += 1;
+			} else if !self.increase && self.volume > 0 {
+				self.volume -= 1;
+			}
+		}
+	}
+}
+
+/// Channel 1 (with sweep) and 2
+#[derive(Debug, Copy, Clone)]
+pub struct SquareChannel {
+	sweep_control: SweepControl,
+	duty_length_envelope: DutyLengthEnvelope,
+	frequency_control: FrequencyControl,
+	on: bool,
+	envelope: Envelope,
+	length: u16,
+	timer: u32,
+	duty_step: usize,
+	sweep_timer: u8,
+	sweep_enabled: bool,
+	shadow_frequency: u16,
+}
+
+impl SquareChannel {
+	fn new() -> Self {
+		Self {
+			sweep_control: SweepControl(0),
+			duty_length_envelope: DutyLengthEnvelope(0),
+			frequency_control: FrequencyControl(0),
+			on: false,
+			envelope: Envelope::default(),
+			length: 0,
+			timer: 0,
+			duty_step: 0,
+			sweep_timer: 0,
+			sweep_enabled: false,
+			shadow_frequency: 0,
+		}
+	}
+
+	pub fn is_on(&self) -> bool {
+		self.on
+	}
+
+	fn get_period(&self) -> u32 {
+		(2048 - self.frequency_control.get_frequency() as u32) * 16
+	}
+
+	fn set_duty_length_envelope(&mut self, value: u16) {
+		self.duty_length_envelope.0 = value;
+		self.length = 64 - self.duty_length_envelope.get_length() as u16;
+	}
+
+	fn set_frequency_control(&mut self, value: u16) {
+		// NOTE: The restart bit is write-only, it should not trigger again on the next write of the other byte
+		self.frequency_control.0 = value & !0x8000;
+		if value.bit(15) {
+			self.restart();
+		}
+	}
+
+	fn restart(&mut self) {
+		// NOTE: The DAC is off when the envelope can only stay at 0
+		self.on = self.duty_length_envelope.get_initial_volume() != 0 || self.duty_length_envelope.get_envelope_increase();
+		if self.length == 0 {
+			self.length = 64;
+		}
+		self.timer = self.get_period();
+		self.envelope.restart(self.duty_length_envelope);
+
+		self.shadow_frequency = self.frequency_control.get_frequency();
+		self.sweep_timer = self.get_sweep_time();
+		self.sweep_enabled = self.sweep_control.get_time() != 0 || self.sweep_control.get_shift() != 0;
+		if self.sweep_control.get_shift() != 0 {
+			self.calculate_sweep();
+		}
+	}
+
+	/// The sweep time 0 is handled as 8 by the timer
+	fn get_sweep_time(&self) -> u8 {
+		match self.sweep_control.get_time() {
+			0 => 8,
+			time => time,
+		}
+	}
+
+	/// Next frequency of the sweep, the channel is turned off when it overflows
+	fn calculate_sweep(&mut self) -> u16 {
+		let delta = self.shadow_frequency >> self.sweep_control.get_shift();
+		let frequency = if self.sweep_control.get_decrease() {
+			self.shadow_frequency.saturating_sub(delta)
+		} else {
+			self.shadow_frequency + delta
+		};
+
+		if frequency > 2047 {
+			self.on = false;
+		}
+
+		frequency
+	}
+
+	/// 128Hz
+	fn clock_sweep(&mut self) {
+		self.sweep_timer = self.sweep_timer.saturating_sub(1);
+		if self.sweep_timer > 0 {
+			return;
+		}
+
+		self.sweep_timer = self.get_sweep_time();
+		if self.sweep_enabled && self.sweep_control.get_time() != 0 {
+			let frequency = self.calculate_sweep();
+			if frequency <= 2047 && self.sweep_control.get_shift() != 0 {
+				self.shadow_frequency = frequency;
+				self.frequency_control.set_frequency(frequency);
+				self.calculate_sweep();
+			}
+		}
+	}
+
+	/// 256Hz
+	fn clock_length(&mut self) {
+		if self.frequency_control.get_length_flag() && self.length > 0 {
+			self.length -= 1;
+			if self.length == 0 {
+				self.on = false;
+			}
+		}
+	}
+
+	fn step(&mut self) {
+		self.timer -= 1;
+		if self.timer == 0 {
+			self.timer = self.get_period();
+			self.duty_step = (self.duty_step + 1) % 8;
+		}
+	}
+
+	/// Current output in [-1, 1]
+	fn get_output(&self) -> f32 {
+		if !self.on {
+			return 0.0;
+		}
+
+		let volume = self.envelope.volume as f32 / 15.0;
+		if DUTY_PATTERNS[self.duty_length_envelope.get_duty() as usize][self.duty_step] {
+			volume
+		} else {
+			-volume
+		}
+	}
+}
+
+/// Channel 3, plays 4 bit samples from the wave RAM
+#[derive(Debug, Copy, Clone)]
+pub struct WaveChannel {
+	wave_select: WaveSelect,
+	length_volume: WaveLengthVolume,
+	frequency_control: FrequencyControl,
+	wave_ram: [u8; WAVE_RAM_BANK_SIZE * 2],
+	on: bool,
+	length: u16,
+	timer: u32,
+	position: usize,
+}
+
+impl WaveChannel {
+	fn new() -> Self {
+		Self {
+			wave_select: WaveSelect(0),
+			length_volume: WaveLengthVolume(0),
+			frequency_control: FrequencyControl(0),
+			wave_ram: [0; WAVE_RAM_BANK_SIZE * 2],
+			on: false,
+			length: 0,
+			timer: 0,
+			position: 0,
+		}
+	}
+
+	pub fn is_on(&self) -> bool {
+		self.on
+	}
+
+	fn get_period(&self) -> u32 {
+		(2048 - self.frequency_control.get_frequency() as u32) * 8
+	}
+
+	fn set_wave_select(&mut self, value: u16) {
+		self.wave_select.0 = value;
+		if !self.wave_select.get_playback() {
+			self.on = false;
+		}
+	}
+
+	fn set_length_volume(&mut self, value: u16) {
+		self.length_volume.0 = value;
+		self.length = 256 - self.length_volume.get_length() as u16;
+	}
+
+	fn set_frequency_control(&mut self, value: u16) {
+		self.frequency_control.0 = value & !0x8000;
+		if value.bit(15) {
+			self.on = self.wave_select.get_playback();
+			if self.length == 0 {
+				self.length = 256;
+			}
+			self.timer = self.get_period();
+			self.position = 0;
+		}
+	}
+
+	/// The CPU accesses the bank that isn't being played
+	fn get_wave_ram_offset(&self, address: u32) -> usize {
+		let bank = if self.wave_select.get_bank() { 0 } else { WAVE_RAM_BANK_SIZE };
+		bank + (address - WAVE_RAM_START_ADDRESS) as usize
+	}
+
+	/// 256Hz
+	fn clock_length(&mut self) {
+		if self.frequency_control.get_length_flag() && self.length > 0 {
+			self.length -= 1;
+			if self.length == 0 {
+				self.on = false;
+			}
+		}
+	}
+
+	fn step(&mut self) {
+		self.timer -= 1;
+		if self.timer == 0 {
+			self.timer = self.get_period();
+			// NOTE: 32 samples per bank, or 64 when both banks are played one after the other
+			let sample_count = if self.wave_select.get_two_banks() { 64 } else { 32 };
+			self.position = (self.position + 1) % sample_count;
+		}
+	}
+
+	fn get_output(&self) -> f32 {
+		if !self.on {
+			return 0.0;
+		}
+
+		let bank = if self.wave_select.get_bank() { WAVE_RAM_BANK_SIZE } else { 0 };
+		let byte = self.wave_ram[(bank + self.position / 2) % self.wave_ram.len()];
+		// NOTE: The upper nibble is played first
+		let sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0xf };
+		let volume = if self.length_volume.get_force_volume() {
+			0.75
+		} else {
+			match self.length_volume.get_volume() {
+				0 => 0.0,
+				1 => 1.0,
+				2 => 0.5,
+				_ => 0.25,
+			}
+		};
+
+		(sample as f32 / 7.5 - 1.0) * volume
+	}
+}
+
+/// Channel 4, pseudo random noise from a linear feedback shift register
+#[derive(Debug, Copy, Clone)]
+pub struct NoiseChannel {
+	length_envelope: DutyLengthEnvelope,
+	control: NoiseControl,
+	on: bool,
+	envelope: Envelope,
+	length: u16,
+	timer: u32,
+	lfsr: u16,
+}
+
+impl NoiseChannel {
+	fn new() -> Self {
+		Self {
+			length_envelope: DutyLengthEnvelope(0),
+			control: NoiseControl(0),
+			on: false,
+			envelope: Envelope::default(),
+			length: 0,
+			timer: 0,
+			lfsr: 0x7fff,
+		}
+	}
+
+	pub fn is_on(&self) -> bool {
+		self.on
+	}
+
+	fn get_period(&self) -> u32 {
+		let divisor = match self.control.get_dividing_ratio() {
+			0 => 32,
+			ratio => ratio as u32 * 64,
+		};
+
+		divisor << self.control.get_shift_clock()
+	}
+
+	fn set_length_envelope(&mut self, value: u16) {
+		self.length_envelope.0 = value;
+		self.length = 64 - self.length_envelope.get_length() as u16;
+	}
+
+	fn set_control(&mut self, value: u16) {
+		self.control.0 = value & !0x8000;
+		if value.bit(15) {
+			self.on = self.length_envelope.get_initial_volume() != 0 || self.length_envelope.get_envelope_increase();
+			if self.length == 0 {
+				self.length = 64;
+			}
+			self.timer = self.get_period();
+			self.envelope.restart(self.length_envelope);
+			self.lfsr = if self.control.get_is_7bit() { 0x7f } else { 0x7fff };
+		}
+	}
+
+	/// 256Hz
+	fn clock_length(&mut self) {
+		if self.control.get_length_flag() && self.length > 0 {
+			self.length -= 1;
+			if self.length == 0 {
+				self.on = false;
+			}
+		}
+	}
+
+	fn step(&mut self) {
+		self.timer -= 1;
+		if self.timer == 0 {
+			self.timer = self.get_period();
+
+			let carry = self.lfsr & 0x1;
+			self.lfsr >>= 1;
+			if carry != 0 {
+				self.lfsr ^= if self.control.get_is_7bit() { 0x60 } else { 0x6000 };
+			}
+		}
+	}
+
+	fn get_output(&self) -> f32 {
+		if !self.on {
+			return 0.0;
+		}
+
+		let volume = self.envelope.volume as f32 / 15.0;
+		if self.lfsr & 0x1 != 0 {
+			volume
+		} else {
+			-volume
+		}
+	}
+}
+
+/// Direct Sound channel A or B, plays the signed 8 bit samples queued in its FIFO by DMA
+#[derive(Debug, Clone)]
+pub struct DirectSoundChannel {
+	fifo: VecDeque<i8>,
+	/// Sample being output, changed on every overflow of the selected timer
+	sample: i8,
+}
+
+impl DirectSoundChannel {
+	fn new() -> Self {
+		Self {
+			fifo: VecDeque::with_capacity(FIFO_LENGTH),
+			sample: 0,
+		}
+	}
+
+	pub fn get_fifo_length(&self) -> usize {
+		self.fifo.len()
+	}
+
+	/// NOTE: Writes to a full FIFO are lost
+	fn push(&mut self, value: u8) {
+		if self.fifo.len() < FIFO_LENGTH {
+			self.fifo.push_back(value as i8);
+		}
+	}
+
+	fn reset(&mut self) {
+		self.fifo.clear();
+		self.sample = 0;
+	}
+
+	/// Play the next sample, returns whether the FIFO needs to be refilled
+	fn clock(&mut self) -> bool {
+		if let Some(sample) = self.fifo.pop_front() {
+			self.sample = sample;
+		}
+
+		self.fifo.len() <= FIFO_REFILL_LENGTH
+	}
+
+	fn get_output(&self, full_volume: bool) -> f32 {
+		let volume = if full_volume { 1.0 } else { 0.5 };
+		self.sample as f32 / 128.0 * volume
+	}
+}
+
+/// Audio Processing Unit
+///
+/// Emulates the 4 PSG channels inherited from the GB and the 2 Direct Sound channels (A/B) and mixes them into a buffer of stereo samples for
+/// the frontend.
+pub struct APU {
+	// Channels
+	square1: SquareChannel,
+	square2: SquareChannel,
+	wave: WaveChannel,
+	noise: NoiseChannel,
+	direct_sound: [DirectSoundChannel; 2],
+
+	// Registers
+	sound_cnt_l: SoundControlL,
+	sound_cnt_h: SoundControlH,
+	master_enable: bool,
+	sound_bias: SoundBias,
+
+	// Timing
+	frame_sequencer_cycles: u32,
+	frame_sequencer_step: u8,
+	sample_cycles: u32,
+
+	/// Interleaved left/right samples in [-1, 1]
+	samples: Vec<f32>,
+}
+
+impl APU {
+	pub fn new() -> Self {
+		Self {
+			square1: SquareChannel::new(),
+			square2: SquareChannel::new(),
+			wave: WaveChannel::new(),
+			noise: NoiseChannel::new(),
+			direct_sound: [DirectSoundChannel::new(), DirectSoundChannel::new()],
+			sound_cnt_l: SoundControlL(0),
+			sound_cnt_h: SoundControlH(0),
+			master_enable: false,
+			sound_bias: SoundBias(0x200),
+			frame_sequencer_cycles: 0,
+			frame_sequencer_step: 0,
+			sample_cycles: 0,
+			samples: Vec::with_capacity(MAX_BUFFERED_SAMPLES),
+		}
+	}
+
+	pub fn get_sound_bias(&self) -> &SoundBias {
+		&self.sound_bias
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.master_enable
+	}
+
+	/// Status of the PSG channels (square 1, square 2, wave, noise)
+	pub fn get_channels_on(&self) -> [bool; 4] {
+		[self.square1.is_on(), self.square2.is_on(), self.wave.is_on(), self.noise.is_on()]
+	}
+
+	/// Direct Sound channels A and B
+	pub fn get_direct_sound(&self) -> &[DirectSoundChannel] {
+		&self.direct_sound
+	}
+
+	/// Feed the timer overflows to the Direct Sound channels, which play a sample on each overflow of their timer (0 or 1)
+	/// Returns which FIFOs (A, B) are running low and need a DMA refill
+	pub fn on_timer_overflow(&mut self, overflows: u8) -> [bool; 2] {
+		let mut refill_requests = [false; 2];
+		if !self.master_enable {
+			return refill_requests;
+		}
+
+		let timers = [self.sound_cnt_h.get_dma_sound_a_timer(), self.sound_cnt_h.get_dma_sound_b_timer()];
+		for (i, timer) in timers.iter().enumerate() {
+			if overflows.bit(*timer as usize) {
+				refill_requests[i] = self.direct_sound[i].clock();
+			}
+		}
+
+		refill_requests
+	}
+
+	/// Take the samples mixed since the last call, interleaved left/right at SAMPLE_RATE
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		std::mem::replace(&mut self.samples, Vec::with_capacity(MAX_BUFFERED_SAMPLES))
+	}
+
+	/// Advance the APU by one cycle
+	pub fn step(&mut self) {
+		if self.master_enable {
+			if self.square1.on {
+				self.square1.step();
+			}
+			if self.square2.on {
+				self.square2.step();
+			}
+			if self.wave.on {
+				self.wave.step();
+			}
+			if self.noise.on {
+				self.noise.step();
+			}
+
+			self.frame_sequencer_cycles += 1;
+			if self.frame_sequencer_cycles == CYCLES_PER_FRAME_SEQUENCER_STEP {
+				self.frame_sequencer_cycles = 0;
+				self.clock_frame_sequencer();
+			}
+		}
+
+		self.sample_cycles += 1;
+		if self.sample_cycles == CYCLES_PER_SAMPLE {
+			self.sample_cycles = 0;
+			self.mix_sample();
+		}
+	}
+
+	fn clock_frame_sequencer(&mut self) {
+		let step = self.frame_sequencer_step;
+		self.frame_sequencer_step = (step + 1) % 8;
+
+		if step % 2 == 0 {
+			self.square1.clock_length();
+			self.square2.clock_length();
+			self.wave.clock_length();
+			self.noise.clock_length();
+		}
+		if step == 2 || step == 6 {
+			self.square1.clock_sweep();
+		}
+		if step == 7 {
+			self.square1.envelope.clock();
+			self.square2.envelope.clock();
+			self.noise.envelope.clock();
+		}
+	}
+
+	fn mix_sample(&mut self) {
+		let (mut left, mut right) = (0.0, 0.0);
+		if self.master_enable {
+			let outputs = [self.square1.get_output(), self.square2.get_output(), self.wave.get_output(), self.noise.get_output()];
+			let (left_enables, right_enables) = (self.sound_cnt_l.get_left_enables(), self.sound_cnt_l.get_right_enables());
+			for (i, output) in outputs.iter().enumerate() {
+				if left_enables.bit(i) {
+					left += output;
+				}
+				if right_enables.bit(i) {
+					right += output;
+				}
+			}
+
+			let psg_volume = match self.sound_cnt_h.get_psg_volume() {
+				0 => 0.25,
+				1 => 0.5,
+				_ => 1.0,
+			};
+			left *= psg_volume * (self.sound_cnt_l.get_left_volume() + 1) as f32 / 8.0 / 4.0;
+			right *= psg_volume * (self.sound_cnt_l.get_right_volume() + 1) as f32 / 8.0 / 4.0;
+
+			let control = self.sound_cnt_h;
+			let direct_sound_outputs = [
+				(
+					self.direct_sound[0].get_output(control.get_dma_sound_a_full_volume()),
+					control.get_dma_sound_a_left_enable(),
+					control.get_dma_sound_a_right_enable(),
+				),
+				(
+					self.direct_sound[1].get_output(control.get_dma_sound_b_full_volume()),
+					control.get_dma_sound_b_left_enable(),
+					control.get_dma_sound_b_right_enable(),
+				),
+			];
+			for (output, left_enable, right_enable) in direct_sound_outputs.iter() {
+				if *left_enable {
+					left += output;
+				}
+				if *right_enable {
+					right += output;
+				}
+			}
+
+			// NOTE: The hardware clips the mixed output to its 10 bit range
+			left = left.clamp(-1.0, 1.0);
+			right = right.clamp(-1.0, 1.0);
+		}
+
+		if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+			self.samples.drain(..2);
+		}
+		self.samples.push(left);
+		self.samples.push(right);
+	}
+
+	fn get_sound_cnt_x(&self) -> u16 {
+		let channels_on = self.get_channels_on().iter().enumerate().fold(0, |flags, (i, on)| flags | ((*on as u16) << i));
+		channels_on | ((self.master_enable as u16) << 7)
+	}
+
+	/// Turning the sound off resets all the PSG registers
+	fn set_sound_cnt_x(&mut self, value: u16) {
+		self.master_enable = value.bit(7);
+		if !self.master_enable {
+			let wave_ram = self.wave.wave_ram;
+			self.square1 = SquareChannel::new();
+			self.square2 = SquareChannel::new();
+			self.wave = WaveChannel::new();
+			self.wave.wave_ram = wave_ram;
+			self.noise = NoiseChannel::new();
+			self.sound_cnt_l.0 = 0;
+			self.frame_sequencer_step = 0;
+		}
+	}
+
+	/// The reset bits clear the FIFOs and always read as 0
+	fn set_sound_cnt_h(&mut self, value: u16) {
+		self.sound_cnt_h.0 = value & 0x770f;
+		if value.bit(11) {
+			self.direct_sound[0].reset();
+		}
+		if value.bit(15) {
+			self.direct_sound[1].reset();
+		}
+	}
+
+	fn push_fifo(&mut self, address: u32, value: u8) {
+		let channel = if address < FIFO_B_ADDRESS { 0 } else { 1 };
+		self.direct_sound[channel].push(value);
+	}
+}
+
+impl SaveState for Envelope {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u8(self.volume)?;
+		writer.write_bool(self.increase)?;
+		writer.write_u8(self.step_time)?;
+		writer.write_u8(self.timer)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.volume = reader.read_u8()?;
+		self.increase = reader.read_bool()?;
+		self.step_time = reader.read_u8()?;
+		self.timer = reader.read_u8()?;
+		Ok(())
+	}
+}
+
+impl SaveState for SquareChannel {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u16(self.sweep_control.0)?;
+		writer.write_u16(self.duty_length_envelope.0)?;
+		writer.write_u16(self.frequency_control.0)?;
+		writer.write_bool(self.on)?;
+		self.envelope.save_state(writer)?;
+		writer.write_u16(self.length)?;
+		writer.write_u32(self.timer)?;
+		writer.write_u8(self.duty_step as u8)?;
+		writer.write_u8(self.sweep_timer)?;
+		writer.write_bool(self.sweep_enabled)?;
+		writer.write_u16(self.shadow_frequency)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.sweep_control.0 = reader.read_u16()?;
+		self.duty_length_envelope.0 = reader.read_u16()?;
+		self.frequency_control.0 = reader.read_u16()?;
+		self.on = reader.read_bool()?;
+		self.envelope.load_state(reader)?;
+		self.length = reader.read_u16()?;
+		self.timer = reader.read_u32()?;
+		self.duty_step = reader.read_u8()? as usize % 8;
+		self.sweep_timer = reader.read_u8()?;
+		self.sweep_enabled = reader.read_bool()?;
+		self.shadow_frequency = reader.read_u16()?;
+		Ok(())
+	}
+}
+
+impl SaveState for WaveChannel {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u16(self.wave_select.0)?;
+		writer.write_u16(self.length_volume.0)?;
+		writer.write_u16(self.frequency_control.0)?;
+		writer.write_bytes(&self.wave_ram)?;
+		writer.write_bool(self.on)?;
+		writer.write_u16(self.length)?;
+		writer.write_u32(self.timer)?;
+		writer.write_u8(self.position as u8)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.wave_select.0 = reader.read_u16()?;
+		self.length_volume.0 = reader.read_u16()?;
+		self.frequency_control.0 = reader.read_u16()?;
+		reader.read_bytes(&mut self.wave_ram)?;
+		self.on = reader.read_bool()?;
+		self.length = reader.read_u16()?;
+		self.timer = reader.read_u32()?;
+		// NOTE: 2 samples per byte of both banks
+		self.position = reader.read_u8()? as usize % (WAVE_RAM_BANK_SIZE * 4);
+		Ok(())
+	}
+}
+
+impl SaveState for NoiseChannel {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u16(self.length_envelope.0)?;
+		writer.write_u16(self.control.0)?;
+		writer.write_bool(self.on)?;
+		self.envelope.save_state(writer)?;
+		writer.write_u16(self.length)?;
+		writer.write_u32(self.timer)?;
+		writer.write_u16(self.lfsr)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.length_envelope.0 = reader.read_u16()?;
+		self.control.0 = reader.read_u16()?;
+		self.on = reader.read_bool()?;
+		self.envelope.load_state(reader)?;
+		self.length = reader.read_u16()?;
+		self.timer = reader.read_u32()?;
+		self.lfsr = reader.read_u16()?;
+		Ok(())
+	}
+}
+
+impl SaveState for DirectSoundChannel {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		let fifo: Vec<u8> = self.fifo.iter().map(|sample| *sample as u8).collect();
+		writer.write_sized_bytes(&fifo)?;
+		writer.write_u8(self.sample as u8)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		let fifo = reader.read_sized_bytes(FIFO_LENGTH)?;
+		self.fifo = fifo.iter().map(|sample| *sample as i8).collect();
+		self.sample = reader.read_u8()? as i8;
+		Ok(())
+	}
+}
+
+impl SaveState for APU {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		self.square1.save_state(writer)?;
+		self.square2.save_state(writer)?;
+		self.wave.save_state(writer)?;
+		self.noise.save_state(writer)?;
+		for channel in self.direct_sound.iter() {
+			channel.save_state(writer)?;
+		}
+
+		writer.write_u16(self.sound_cnt_l.0)?;
+		writer.write_u16(self.sound_cnt_h.0)?;
+		writer.write_bool(self.master_enable)?;
+		writer.write_u32(self.sound_bias.0)?;
+		writer.write_u32(self.frame_sequencer_cycles)?;
+		writer.write_u8(self.frame_sequencer_step)?;
+		writer.write_u32(self.sample_cycles)
+	}
+
+	/// NOTE: The samples not taken by the frontend yet are dropped, they belong to the timeline that was left
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.square1.load_state(reader)?;
+		self.square2.load_state(reader)?;
+		self.wave.load_state(reader)?;
+		self.noise.load_state(reader)?;
+		for channel in self.direct_sound.iter_mut() {
+			channel.load_state(reader)?;
+		}
+
+		self.sound_cnt_l.0 = reader.read_u16()?;
+		self.sound_cnt_h.0 = reader.read_u16()?;
+		self.master_enable = reader.read_bool()?;
+		self.sound_bias.0 = reader.read_u32()?;
+		self.frame_sequencer_cycles = reader.read_u32()? % CYCLES_PER_FRAME_SEQUENCER_STEP;
+		self.frame_sequencer_step = reader.read_u8()? % 8;
+		self.sample_cycles = reader.read_u32()? % CYCLES_PER_SAMPLE;
+		self.samples.clear();
+		Ok(())
+	}
+}
+
+impl Default for APU {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl MemoryInterface for APU {
+	fn read_8(&self, address: u32) -> u8 {
+		let addr = address & 0x00ff_ffff;
+		match addr {
+			WAVE_RAM_START_ADDRESS..=WAVE_RAM_END_ADDRESS => self.wave.wave_ram[self.wave.get_wave_ram_offset(addr)],
+			_ => (self.read_16(addr & !0x1) >> ((addr & 0x1) * 8)) as u8,
+		}
+	}
+
+	fn write_8(&mut self, address: u32, value: u8) {
+		let addr = address & 0x00ff_ffff;
+		match addr {
+			WAVE_RAM_START_ADDRESS..=WAVE_RAM_END_ADDRESS => {
+				let offset = self.wave.get_wave_ram_offset(addr);
+				self.wave.wave_ram[offset] = value;
+			}
+			FIFO_A_ADDRESS..=APU_REGISTERS_END => self.push_fifo(addr, value),
+			_ => {
+				// NOTE: Write-only bits are kept so writing one byte doesn't clear the other half
+				let aligned_addr = addr & !0x1;
+				let shift = (addr & 0x1) * 8;
+				let current = match aligned_addr {
+					SOUND1_CNT_L_ADDRESS => self.square1.sweep_control.0,
+					SOUND1_CNT_H_ADDRESS => self.square1.duty_length_envelope.0,
+					SOUND1_CNT_X_ADDRESS => self.square1.frequency_control.0,
+					SOUND2_CNT_L_ADDRESS => self.square2.duty_length_envelope.0,
+					SOUND2_CNT_H_ADDRESS => self.square2.frequency_control.0,
+					SOUND3_CNT_L_ADDRESS => self.wave.wave_select.0,
+					SOUND3_CNT_H_ADDRESS => self.wave.length_volume.0,
+					SOUND3_CNT_X_ADDRESS => self.wave.frequency_control.0,
+					SOUND4_CNT_L_ADDRESS => self.noise.length_envelope.0,
+					SOUND4_CNT_H_ADDRESS => self.noise.control.0,
+					SOUND_CNT_L_ADDRESS => self.sound_cnt_l.0,
+					SOUND_CNT_H_ADDRESS => self.sound_cnt_h.0,
+					SOUND_BIAS_ADDRESS => self.sound_bias.0 as u16,
+					_ => self.read_16(aligned_addr),
+				};
+
+				self.write_16(aligned_addr, (current & !(0xff << shift)) | ((value as u16) << shift));
+			}
+		}
+	}
+
+	fn read_16(&self, address: u32) -> u16 {
+		let addr = address & 0x00ff_ffff;
+		match addr {
+			SOUND1_CNT_L_ADDRESS => self.square1.sweep_control.0 & 0x7f,
+			SOUND1_CNT_H_ADDRESS => self.square1.duty_length_envelope.0 & 0xffc0,
+			SOUND1_CNT_X_ADDRESS => self.square1.frequency_control.0 & 0x4000,
+			SOUND2_CNT_L_ADDRESS => self.square2.duty_length_envelope.0 & 0xffc0,
+			SOUND2_CNT_H_ADDRESS => self.square2.frequency_control.0 & 0x4000,
+			SOUND3_CNT_L_ADDRESS => self.wave.wave_select.0 & 0xe0,
+			SOUND3_CNT_H_ADDRESS => self.wave.length_volume.0 & 0xe000,
+			SOUND3_CNT_X_ADDRESS => self.wave.frequency_control.0 & 0x4000,
+			SOUND4_CNT_L_ADDRESS => self.noise.length_envelope.0 & 0xff00,
+			SOUND4_CNT_H_ADDRESS => self.noise.control.0 & 0x40ff,
+			SOUND_CNT_L_ADDRESS => self.sound_cnt_l.0 & 0xff77,
+			SOUND_CNT_H_ADDRESS => self.sound_cnt_h.0,
+			SOUND_CNT_X_ADDRESS => self.get_sound_cnt_x(),
+			SOUND_BIAS_ADDRESS => self.sound_bias.0 as u16,
+			WAVE_RAM_START_ADDRESS..=WAVE_RAM_END_ADDRESS => self.read_8(addr) as u16 | (self.read_8(addr + 1) as u16) << 8,
+			_ => 0x0,
+		}
+	}
+
+	fn write_16(&mut self, address: u32, value: u16) {
+		let addr = address & 0x00ff_ffff;
+		// NOTE: The PSG registers can't be written while the sound is off
+		if !self.master_enable && addr < SOUND_CNT_H_ADDRESS {
+			return;
+		}
+
+		match addr {
+			SOUND1_CNT_L_ADDRESS => self.square1.sweep_control.0 = value,
+			SOUND1_CNT_H_ADDRESS => self.square1.set_duty_length_envelope(value),
+			SOUND1_CNT_X_ADDRESS => self.square1.set_frequency_control(value),
+			SOUND2_CNT_L_ADDRESS => self.square2.set_duty_length_envelope(value),
+			SOUND2_CNT_H_ADDRESS => self.square2.set_frequency_control(value),
+			SOUND3_CNT_L_ADDRESS => self.wave.set_wave_select(value),
+			SOUND3_CNT_H_ADDRESS => self.wave.set_length_volume(value),
+			SOUND3_CNT_X_ADDRESS => self.wave.set_frequency_control(value),
+			SOUND4_CNT_L_ADDRESS => self.noise.set_length_envelope(value),
+			SOUND4_CNT_H_ADDRESS => self.noise.set_control(value),
+			SOUND_CNT_L_ADDRESS => self.sound_cnt_l.0 = value,
+			SOUND_CNT_H_ADDRESS => self.set_sound_cnt_h(value),
+			SOUND_CNT_X_ADDRESS => self.set_sound_cnt_x(value),
+			SOUND_BIAS_ADDRESS => self.sound_bias.0 = (self.sound_bias.0 & 0xffff_0000) | value as u32,
+			WAVE_RAM_START_ADDRESS..=WAVE_RAM_END_ADDRESS => {
+				self.write_8(addr, value as u8);
+				self.write_8(addr + 1, (value >> 8) as u8);
+			}
+			FIFO_A_ADDRESS..=APU_REGISTERS_END => {
+				self.push_fifo(addr, value as u8);
+				self.push_fifo(addr, (value >> 8) as u8);
+			}
+			_ => {}
+		}
+	}
+
+	fn read_32(&self, address: u32) -> u32 {
+		let addr = address & 0x00ff_ffff;
+		match addr {
+			SOUND_BIAS_ADDRESS => self.sound_bias.0,
+			_ => self.read_16(addr) as u32 | (self.read_16(addr + 2) as u32) << 16,
+		}
+	}
+
+	fn write_32(&mut self, address: u32, value: u32) {
+		let addr = address & 0x00ff_ffff;
+		match addr {
+			SOUND_BIAS_ADDRESS => self.sound_bias.0 = value,
+			FIFO_A_ADDRESS | FIFO_B_ADDRESS => {
+				for i in 0..4 {
+					self.push_fifo(addr, (value >> (i * 8)) as u8);
+				}
+			}
+			_ => {
+				self.write_16(addr, value as u16);
+				self.write_16(addr + 2, (value >> 16) as u16);
+			}
+		}
+	}
+}
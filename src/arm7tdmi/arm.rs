@@ -4,8 +4,9 @@ use bitfield::*;
 use num_traits::{FromPrimitive, PrimInt};
 
 use crate::arm7tdmi::cpu::{CpuResult, CPU, LINK_REGISTER_REGISTER, PROGRAM_COUNTER_REGISTER};
-use crate::arm7tdmi::{cond_passed, load_32_from_memory, sign_extend, EExceptionType, EOperatingMode, EShiftType};
-use crate::system::{MemoryInterface, SystemBus};
+use crate::arm7tdmi::{check_alignment, cond_passed, load_32_from_memory, sign_extend, EExceptionType, EOperatingMode, EShiftType};
+use crate::bios;
+use crate::system::{EAccessWidth, EAnomaly, MemoryInterface, SystemBus};
 
 bitfield! {
 	/// Exposes common information about an encoded ARM instruction
@@ -66,6 +67,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 			return CpuResult::FlushPipeline;
 		} else if (0x0e00_0010 & raw_instruction) == 0x0600_0010 {
 			// Undefined instruction
+			bus.report_anomaly(EAnomaly::UndefinedInstruction(raw_instruction));
 			cpu.exception(EExceptionType::Undefined);
 			return CpuResult::FlushPipeline;
 		} else if (0x0fb0_0ff0 & raw_instruction) == 0x0100_0090 {
@@ -85,10 +87,12 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 				if (rn & 0x0000_0003) == 0 {
 					temp = bus.read_32(rn);
 				} else {
+					check_alignment(cpu, rn, EAccessWidth::Word, false);
 					// NOTE: Forced alignment and rotation of data! (UNPREDICTABLE)
 					temp = bus.read_32(rn & !0x0000_0003).rotate_right((rn & 0x0000_0003) * 8);
 				}
 
+				check_alignment(cpu, rn, EAccessWidth::Word, true);
 				// NOTE: Forced alignment! (UNPREDICTABLE)
 				bus.write_32(rn & !0x0000_0003, rm);
 				cpu.set_register_value(rd_index, temp);
@@ -383,7 +387,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 					bus.write_8(address, rd as u8);
 				}
 			} else if l {
-				let data = load_32_from_memory(bus, address);
+				let data = load_32_from_memory(cpu, bus, address);
 
 				if rd_index == PROGRAM_COUNTER_REGISTER {
 					cpu.set_register_value(rd_index, data & !0x3);
@@ -396,6 +400,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 				} else {
 					cpu.get_register_value(rd_index)
 				};
+				check_alignment(cpu, address, EAccessWidth::Word, true);
 				// NOTE: Forced alignment! (UNPREDICTABLE)
 				bus.write_32(address & !0x0000_0003, rd);
 			}
@@ -474,12 +479,14 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 						if (address & 0x0000_0001) == 0 {
 							data = bus.read_16(address) as i16 as u32;
 						} else {
+							check_alignment(cpu, address, EAccessWidth::Halfword, false);
 							// NOTE: Read byte! (UNPREDICTABLE)
 							data = bus.read_8(address) as i8 as u32;
 						}
 					} else if (address & 0x0000_0001) == 0 {
 						data = bus.read_16(address) as u32;
 					} else {
+						check_alignment(cpu, address, EAccessWidth::Halfword, false);
 						// NOTE: Forced alignment and rotation of data! (UNPREDICTABLE)
 						data = (bus.read_16(address & !0x1) as u32).rotate_right(8);
 					}
@@ -500,6 +507,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 				} else {
 					cpu.get_register_value(rd_index)
 				};
+				check_alignment(cpu, address, EAccessWidth::Halfword, true);
 				// NOTE: Forced alignment! (UNPREDICTABLE)
 				bus.write_16(address & !0x1, rd as u16);
 			}
@@ -531,6 +539,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 			let rn_index = instruction.get_rn_index();
 			let rn = cpu.get_register_value(rn_index);
 			let reg_list = instruction.get_register_list();
+			check_alignment(cpu, rn, EAccessWidth::Word, !l);
 
 			// NOTE: UNPREDICTABLE!!!
 			if reg_list == 0 {
@@ -558,7 +567,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 				}
 
 				if l {
-					let value = load_32_from_memory(bus, address);
+					let value = load_32_from_memory(cpu, bus, address);
 					cpu.set_register_value(PROGRAM_COUNTER_REGISTER, value & !0x3);
 
 					return CpuResult::FlushPipeline;
@@ -620,7 +629,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 				if l {
 					for i in 0..15 {
 						if reg_list.bit(i) {
-							let value = load_32_from_memory(bus, address);
+							let value = load_32_from_memory(cpu, bus, address);
 							cpu.set_register_value(i as u8, value);
 							address = address.wrapping_add(4);
 						}
@@ -636,7 +645,7 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 							cpu.change_operating_mode(new_mode, old_mode);
 						}
 
-						let value = load_32_from_memory(bus, address) & !0x3;
+						let value = load_32_from_memory(cpu, bus, address) & !0x3;
 						cpu.set_register_value(PROGRAM_COUNTER_REGISTER, value);
 						address = address.wrapping_add(4);
 					}
@@ -675,6 +684,10 @@ pub fn execute_arm(cpu: &mut CPU, bus: &mut SystemBus, raw_instruction: u32) ->
 			}
 		} else if (0x0f00_0000 & raw_instruction) == 0x0f00_0000 {
 			// SWI Software Interrupt Exception
+			if !bus.is_bios_loaded() {
+				return bios::execute_swi(cpu, bus, BitRange::<u8>::bit_range(&instruction, 23, 16));
+			}
+
 			cpu.exception(EExceptionType::SoftwareInterrupt);
 			return CpuResult::FlushPipeline;
 		} else if (0x0c00_0000 & raw_instruction) == 0x0000_0000 {
@@ -1,8 +1,11 @@
+use std::io::Result;
+
 use num_traits::{FromPrimitive, ToPrimitive};
 
 use crate::arm7tdmi::psr::PSR;
 use crate::arm7tdmi::{arm, thumb, EExceptionType, EOperatingMode};
-use crate::system::{MemoryInterface, SystemBus};
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::system::{EAccessWidth, MemoryInterface, SystemBus};
 
 // Special registers
 pub const STACK_POINTER_REGISTER: u8 = 13;
@@ -10,7 +13,7 @@ pub const LINK_REGISTER_REGISTER: u8 = 14;
 pub const PROGRAM_COUNTER_REGISTER: u8 = 15;
 
 /// Result of a CPU instruction
-pub enum CpuResult {
+pub(crate) enum CpuResult {
 	Continue,
 	FlushPipeline,
 }
@@ -34,6 +37,47 @@ impl BankedRegisters {
 			banked_fiq_registers: [0; 5],
 		}
 	}
+
+	/// Index of the R13/R14 bank of a mode
+	pub fn get_bank_index(mode: EOperatingMode) -> usize {
+		match mode {
+			EOperatingMode::UserMode | EOperatingMode::SystemMode => 0,
+			EOperatingMode::FiqMode => 1,
+			EOperatingMode::IrqMode => 2,
+			EOperatingMode::SupervisorMode => 3,
+			EOperatingMode::AbortMode => 4,
+			EOperatingMode::UndefinedMode => 5,
+		}
+	}
+
+	/// Banked R13 values, in order User/System, FIQ, IRQ, SVC, ABT, UND
+	pub fn get_banked_r13s(&self) -> &[u32] {
+		&self.banked_r13s
+	}
+
+	/// Banked R14 values, in order User/System, FIQ, IRQ, SVC, ABT, UND
+	pub fn get_banked_r14s(&self) -> &[u32] {
+		&self.banked_r14s
+	}
+
+	/// R8-R12 of every mode but FIQ
+	pub fn get_banked_user_registers(&self) -> &[u32] {
+		&self.banked_user_registers
+	}
+
+	/// R8-R12 of FIQ mode
+	pub fn get_banked_fiq_registers(&self) -> &[u32] {
+		&self.banked_fiq_registers
+	}
+}
+
+/// State of the CPU right after an exception was taken
+#[derive(Debug, Copy, Clone)]
+pub struct ExceptionRecord {
+	pub exception_type: EExceptionType,
+	pub vector: u32,
+	pub return_address: u32,
+	pub spsr: u32,
 }
 
 pub struct CPU {
@@ -52,6 +96,18 @@ pub struct CPU {
 
 	// Banked Registers
 	banks: BankedRegisters,
+
+	// Whether the next opcode fetch follows the previous one (no branch in between)
+	sequential_fetch: bool,
+
+	// Cycles left of the last instruction, the rest of the system keeps running meanwhile
+	wait_cycles: u32,
+
+	// Log the accesses that need forced alignment/rotation
+	alignment_diagnostics: bool,
+
+	// Last exception taken, until the debugger takes it
+	last_exception: Option<ExceptionRecord>,
 }
 
 impl CPU {
@@ -65,6 +121,10 @@ impl CPU {
 			spsr_irq: PSR::new(),
 			spsr_und: PSR::new(),
 			banks: BankedRegisters::new(),
+			sequential_fetch: false,
+			wait_cycles: 0,
+			alignment_diagnostics: false,
+			last_exception: None,
 		}
 	}
 
@@ -72,6 +132,24 @@ impl CPU {
 		&self.registers
 	}
 
+	/// Values of the banked registers not mapped in the current mode
+	pub fn get_banks(&self) -> &BankedRegisters {
+		&self.banks
+	}
+
+	/// Last exception taken since the previous call
+	pub fn take_last_exception(&mut self) -> Option<ExceptionRecord> {
+		self.last_exception.take()
+	}
+
+	pub fn is_alignment_diagnostics_enabled(&self) -> bool {
+		self.alignment_diagnostics
+	}
+
+	pub fn set_alignment_diagnostics(&mut self, value: bool) {
+		self.alignment_diagnostics = value;
+	}
+
 	pub fn get_current_pc(&self) -> u32 {
 		self.registers[PROGRAM_COUNTER_REGISTER as usize]
 	}
@@ -99,6 +177,35 @@ impl CPU {
 		self.registers[index as usize] = value;
 	}
 
+	/// Jump to an address from the debugger, the next opcode fetch is non sequential
+	pub fn set_pc(&mut self, value: u32) {
+		self.sequential_fetch = false;
+		self.set_register_value(PROGRAM_COUNTER_REGISTER, value);
+	}
+
+	/// Move PC past the current instruction without executing it
+	pub fn skip_instruction(&mut self) {
+		self.set_pc(self.get_current_pc() + self.get_instruction_length());
+	}
+
+	/// Write a whole PSR from the debugger, mode is the one used by get_spsr
+	/// NOTE: Changing the mode bits of the CPSR swaps the banked registers, invalid modes are refused
+	pub fn set_psr_value(&mut self, mode: EOperatingMode, value: u32) -> bool {
+		let new_mode: Option<EOperatingMode> = FromPrimitive::from_u32(value & 0x1f);
+		let new_mode = match new_mode {
+			Some(new_mode) => new_mode,
+			None => return false,
+		};
+
+		if std::ptr::eq(self.get_spsr(mode), &self.cpsr) {
+			let old_mode = self.get_operating_mode();
+			self.change_operating_mode(new_mode, old_mode);
+		}
+
+		self.get_mut_spsr(mode).0 = value;
+		true
+	}
+
 	pub fn get_cpsr(&self) -> &PSR {
 		&self.cpsr
 	}
@@ -136,25 +243,8 @@ impl CPU {
 	pub fn change_operating_mode(&mut self, new_mode: EOperatingMode, old_mode: EOperatingMode) {
 		self.cpsr.set_mode_bits(new_mode.to_u8().unwrap());
 
-		let new_index = match new_mode {
-			EOperatingMode::UserMode => 0,
-			EOperatingMode::FiqMode => 1,
-			EOperatingMode::IrqMode => 2,
-			EOperatingMode::SupervisorMode => 3,
-			EOperatingMode::AbortMode => 4,
-			EOperatingMode::UndefinedMode => 5,
-			EOperatingMode::SystemMode => 0,
-		};
-
-		let old_index = match old_mode {
-			EOperatingMode::UserMode => 0,
-			EOperatingMode::FiqMode => 1,
-			EOperatingMode::IrqMode => 2,
-			EOperatingMode::SupervisorMode => 3,
-			EOperatingMode::AbortMode => 4,
-			EOperatingMode::UndefinedMode => 5,
-			EOperatingMode::SystemMode => 0,
-		};
+		let new_index = BankedRegisters::get_bank_index(new_mode);
+		let old_index = BankedRegisters::get_bank_index(old_mode);
 
 		if new_index == old_index {
 			return;
@@ -184,6 +274,11 @@ impl CPU {
 		}
 	}
 
+	/// Enter an exception, saving CPSR in the SPSR of the exception mode
+	///
+	/// LR is set as in the ARM7TDMI manual, so that the documented return instruction resumes at the right place:
+	/// - Undefined/SWI: next instruction (`MOVS PC, LR`), raised while executing the instruction in both ARM and THUMB
+	/// - IRQ/FIQ: next instruction + 4 (`SUBS PC, LR, #4`), raised between instructions
 	pub fn exception(&mut self, exception_type: EExceptionType) {
 		let exception_vector_address;
 		let return_address_offset;
@@ -239,27 +334,220 @@ impl CPU {
 		self.cpsr.set_i(true);
 
 		// Return address
-		self.set_register_value(LINK_REGISTER_REGISTER, self.get_current_pc() + return_address_offset);
+		let return_address = self.get_current_pc() + return_address_offset;
+		self.set_register_value(LINK_REGISTER_REGISTER, return_address);
 
 		self.set_register_value(PROGRAM_COUNTER_REGISTER, exception_vector_address);
+
+		self.last_exception = Some(ExceptionRecord {
+			exception_type,
+			vector: exception_vector_address,
+			return_address,
+			spsr: self.get_spsr(operating_mode).0,
+		});
 	}
 
 	/// Step the CPU by executing 1 instruction
-	// TODO: Calculate cycles and update system
-	pub fn step(&mut self, bus: &mut SystemBus) {
+	/// Returns the cycles taken by the opcode fetch and the data accesses, the CPU then waits them out with wait_cycle
+	// TODO: Internal cycles (multiplications, register shifts, loads) and the pipeline refill after a branch
+	pub fn step(&mut self, bus: &mut SystemBus) -> u32 {
+		// NOTE: Drop the accesses made outside of the CPU (eg. debugger windows, DMA)
+		bus.take_access_cycles();
+
 		// NOTE: Read CPU state
 		let pc = self.get_current_pc();
-		let result = if self.get_cpsr().get_t() {
+		let (result, fetch_cycles) = if self.get_cpsr().get_t() {
 			let instruction = bus.read_16(pc);
-			thumb::execute_thumb(instruction, self, bus)
+			bus.latch_opcode(instruction as u32, EAccessWidth::Halfword);
+			let fetch_cycles = bus.fetch_opcode_cycles(pc, EAccessWidth::Halfword, self.sequential_fetch);
+			// NOTE: The fetch cycles depend on the sequential fetches, not on the data access model, and fetches aren't data accesses for the watchpoints
+			bus.take_access_cycles();
+			bus.take_watchpoint_hit();
+			(thumb::execute_thumb(instruction, self, bus), fetch_cycles)
 		} else {
 			let instruction = bus.read_32(pc);
-			arm::execute_arm(self, bus, instruction)
+			bus.latch_opcode(instruction, EAccessWidth::Word);
+			let fetch_cycles = bus.fetch_opcode_cycles(pc, EAccessWidth::Word, self.sequential_fetch);
+			bus.take_access_cycles();
+			bus.take_watchpoint_hit();
+			(arm::execute_arm(self, bus, instruction), fetch_cycles)
 		};
+		let (data_cycles, gamepak_accessed) = bus.take_access_cycles();
+		bus.run_prefetch(data_cycles, gamepak_accessed);
+		let cycles = fetch_cycles + data_cycles;
 
 		match result {
-			CpuResult::Continue => self.set_register_value(PROGRAM_COUNTER_REGISTER, self.get_current_pc() + self.get_instruction_length()),
-			CpuResult::FlushPipeline => self.set_register_value(PROGRAM_COUNTER_REGISTER, self.get_current_pc() & !0x1),
+			CpuResult::Continue => {
+				self.sequential_fetch = true;
+				self.set_register_value(PROGRAM_COUNTER_REGISTER, self.get_current_pc() + self.get_instruction_length())
+			}
+			CpuResult::FlushPipeline => {
+				self.sequential_fetch = false;
+				self.set_register_value(PROGRAM_COUNTER_REGISTER, self.get_current_pc() & !0x1)
+			}
+		}
+
+		// NOTE: The instruction takes effect on its first cycle
+		self.wait_cycles = cycles - 1;
+		cycles
+	}
+
+	/// Keep the CPU waiting for some more cycles, eg. while a DMA transfer holds the bus
+	pub fn stall(&mut self, cycles: u32) {
+		self.wait_cycles += cycles;
+	}
+
+	/// Spend one cycle of the last instruction, returns false once the CPU is ready to execute the next one
+	pub fn wait_cycle(&mut self) -> bool {
+		if self.wait_cycles == 0 {
+			return false;
+		}
+
+		self.wait_cycles -= 1;
+		true
+	}
+}
+
+impl SaveState for CPU {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		let banks = &self.banks;
+		let registers = self.registers.iter().chain(&banks.banked_r13s).chain(&banks.banked_r14s);
+		for value in registers.chain(&banks.banked_user_registers).chain(&banks.banked_fiq_registers) {
+			writer.write_u32(*value)?;
+		}
+		for psr in [&self.cpsr, &self.spsr_fiq, &self.spsr_svc, &self.spsr_abt, &self.spsr_irq, &self.spsr_und].iter() {
+			writer.write_u32(psr.0)?;
+		}
+
+		writer.write_bool(self.sequential_fetch)?;
+		writer.write_u32(self.wait_cycles)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		let banks = &mut self.banks;
+		let registers = self.registers.iter_mut().chain(banks.banked_r13s.iter_mut()).chain(banks.banked_r14s.iter_mut());
+		for value in registers.chain(banks.banked_user_registers.iter_mut()).chain(banks.banked_fiq_registers.iter_mut()) {
+			*value = reader.read_u32()?;
+		}
+		for psr in [
+			&mut self.cpsr,
+			&mut self.spsr_fiq,
+			&mut self.spsr_svc,
+			&mut self.spsr_abt,
+			&mut self.spsr_irq,
+			&mut self.spsr_und,
+		]
+		.iter_mut()
+		{
+			psr.0 = reader.read_u32()?;
+		}
+
+		self.sequential_fetch = reader.read_bool()?;
+		self.wait_cycles = reader.read_u32()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CODE_ADDRESS: u32 = 0x0300_0000;
+
+	/// CPU in System mode running from IWRAM, with a BIOS image loaded so SWIs take the exception instead of the HLE
+	fn setup(instructions: &[u32], thumb: bool) -> (CPU, SystemBus) {
+		let mut bus = SystemBus::new(vec![0; 0x4000].into_boxed_slice());
+		for (i, instruction) in instructions.iter().enumerate() {
+			if thumb {
+				bus.write_16(CODE_ADDRESS + i as u32 * 2, *instruction as u16);
+			} else {
+				bus.write_32(CODE_ADDRESS + i as u32 * 4, *instruction);
+			}
 		}
+
+		let mut cpu = CPU::new();
+		cpu.get_mut_cpsr().set_t(thumb);
+		cpu.set_pc(CODE_ADDRESS);
+		(cpu, bus)
+	}
+
+	#[test]
+	fn arm_undefined_returns_to_next_instruction() {
+		let (mut cpu, mut bus) = setup(&[0xe7f0_00f0], false);
+		cpu.step(&mut bus);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::UndefinedMode);
+		assert_eq!(cpu.get_current_pc(), 0x4);
+		assert_eq!(cpu.get_register_value(LINK_REGISTER_REGISTER), CODE_ADDRESS + 4);
+		assert_eq!(cpu.get_spsr(EOperatingMode::UndefinedMode).get_mode_bits(), EOperatingMode::SystemMode as u8);
+	}
+
+	#[test]
+	fn thumb_undefined_returns_to_next_instruction() {
+		let (mut cpu, _) = setup(&[], true);
+		cpu.exception(EExceptionType::Undefined);
+
+		assert_eq!(cpu.get_register_value(LINK_REGISTER_REGISTER), CODE_ADDRESS + 2);
+		assert!(!cpu.get_cpsr().get_t());
+		assert!(cpu.get_spsr(EOperatingMode::UndefinedMode).get_t());
+	}
+
+	#[test]
+	fn arm_swi_returns_to_next_instruction() {
+		let (mut cpu, mut bus) = setup(&[0xef00_0000], false);
+		cpu.step(&mut bus);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::SupervisorMode);
+		assert_eq!(cpu.get_current_pc(), 0x8);
+		assert_eq!(cpu.get_register_value(LINK_REGISTER_REGISTER), CODE_ADDRESS + 4);
+		assert!(cpu.get_cpsr().get_i());
+	}
+
+	#[test]
+	fn thumb_swi_returns_to_next_instruction() {
+		let (mut cpu, mut bus) = setup(&[0xdf00], true);
+		cpu.step(&mut bus);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::SupervisorMode);
+		assert_eq!(cpu.get_current_pc(), 0x8);
+		assert_eq!(cpu.get_register_value(LINK_REGISTER_REGISTER), CODE_ADDRESS + 2);
+		assert!(!cpu.get_cpsr().get_t());
+		assert!(cpu.get_spsr(EOperatingMode::SupervisorMode).get_t());
+	}
+
+	#[test]
+	fn movs_pc_lr_restores_cpsr_after_swi() {
+		// NOTE: THUMB SWI, then the handler returns with MOVS PC, LR placed after it
+		let (mut cpu, mut bus) = setup(&[0xdf00], true);
+		bus.write_32(CODE_ADDRESS + 0x100, 0xe1b0_f00e);
+		cpu.get_mut_cpsr().set_z(true);
+		cpu.step(&mut bus);
+		cpu.set_pc(CODE_ADDRESS + 0x100);
+		cpu.step(&mut bus);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::SystemMode);
+		assert_eq!(cpu.get_current_pc(), CODE_ADDRESS + 2);
+		assert!(cpu.get_cpsr().get_t());
+		assert!(cpu.get_cpsr().get_z());
+		assert!(!cpu.get_cpsr().get_i());
+	}
+
+	#[test]
+	fn subs_pc_lr_4_restores_cpsr_after_irq() {
+		let (mut cpu, mut bus) = setup(&[], false);
+		bus.write_32(CODE_ADDRESS + 0x100, 0xe25e_f004);
+		cpu.get_mut_cpsr().set_c(true);
+		cpu.exception(EExceptionType::Irq);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::IrqMode);
+		assert_eq!(cpu.get_register_value(LINK_REGISTER_REGISTER), CODE_ADDRESS + 4);
+
+		cpu.set_pc(CODE_ADDRESS + 0x100);
+		cpu.step(&mut bus);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::SystemMode);
+		assert_eq!(cpu.get_current_pc(), CODE_ADDRESS);
+		assert!(cpu.get_cpsr().get_c());
+		assert!(!cpu.get_cpsr().get_i());
 	}
 }
@@ -2,13 +2,15 @@ use num_derive::*;
 use num_traits::{AsPrimitive, PrimInt};
 
 use crate::arm7tdmi::cpu::CPU;
-use crate::system::{MemoryInterface, SystemBus};
+use crate::system::{EAccessWidth, MemoryInterface, SystemBus};
 
 mod arm;
 pub mod cpu;
 mod psr;
 mod thumb;
 
+pub use psr::PSR;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive, ToPrimitive)]
 pub enum EOperatingMode {
 	UserMode = 0x10,
@@ -21,6 +23,7 @@ pub enum EOperatingMode {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum EExceptionType {
 	Reset,
 	Undefined,
@@ -31,6 +34,18 @@ pub enum EExceptionType {
 	Fiq,
 }
 
+impl EExceptionType {
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			EExceptionType::Reset => "Reset",
+			EExceptionType::Undefined => "Undefined",
+			EExceptionType::SoftwareInterrupt => "SWI",
+			EExceptionType::Irq => "IRQ",
+			EExceptionType::Fiq => "FIQ",
+		}
+	}
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive)]
 pub enum EShiftType {
 	LSL,
@@ -39,7 +54,7 @@ pub enum EShiftType {
 	ROR,
 }
 
-pub fn sign_extend<T>(x: T, bits: u8) -> i32
+pub(crate) fn sign_extend<T>(x: T, bits: u8) -> i32
 where
 	T: PrimInt + AsPrimitive<i32>,
 {
@@ -47,7 +62,7 @@ where
 	(x.as_() ^ m) - m
 }
 
-pub fn cond_passed(cpu: &CPU, cond: u8) -> bool {
+pub(crate) fn cond_passed(cpu: &CPU, cond: u8) -> bool {
 	match cond {
 		0x0 => cpu.get_cpsr().get_z(),                                                      // Equal (Zero)
 		0x1 => !cpu.get_cpsr().get_z(),                                                     // Not Equal (Nonzero)
@@ -67,7 +82,25 @@ pub fn cond_passed(cpu: &CPU, cond: u8) -> bool {
 	}
 }
 
-pub fn load_32_from_memory(bus: &SystemBus, address: u32) -> u32 {
+/// Report accesses that take the forced alignment/rotation (UNPREDICTABLE) paths when the alignment diagnostics are enabled
+///
+/// These are usually bugs in the game or in the decoding of the instruction.
+pub(crate) fn check_alignment(cpu: &CPU, address: u32, width: EAccessWidth, is_write: bool) {
+	let misaligned = match width {
+		EAccessWidth::Byte => false,
+		EAccessWidth::Halfword => address & 0x1 != 0,
+		EAccessWidth::Word => address & 0x3 != 0,
+	};
+
+	if misaligned && cpu.is_alignment_diagnostics_enabled() {
+		let access = if is_write { "write" } else { "read" };
+		eprintln!("Misaligned {:?} {} at {:#010x} (PC: {:#010x})", width, access, address, cpu.get_current_pc());
+	}
+}
+
+pub(crate) fn load_32_from_memory(cpu: &CPU, bus: &SystemBus, address: u32) -> u32 {
+	check_alignment(cpu, address, EAccessWidth::Word, false);
+
 	let data;
 	if (address & 0x0000_0003) == 0 {
 		data = bus.read_32(address);
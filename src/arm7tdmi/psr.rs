@@ -30,3 +30,9 @@ impl PSR {
 		Self(EOperatingMode::SystemMode.to_u32().unwrap())
 	}
 }
+
+impl Default for PSR {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -2,8 +2,9 @@ use bitfield::*;
 use num_traits::{FromPrimitive, PrimInt};
 
 use crate::arm7tdmi::cpu::{CpuResult, CPU, LINK_REGISTER_REGISTER, PROGRAM_COUNTER_REGISTER, STACK_POINTER_REGISTER};
-use crate::arm7tdmi::{cond_passed, load_32_from_memory, sign_extend, EExceptionType, EShiftType};
-use crate::system::{MemoryInterface, SystemBus};
+use crate::arm7tdmi::{check_alignment, cond_passed, load_32_from_memory, sign_extend, EExceptionType, EShiftType};
+use crate::bios;
+use crate::system::{EAccessWidth, MemoryInterface, SystemBus};
 
 bitfield! {
 	/// Exposes common information about an encoded THUMB instruction
@@ -495,7 +496,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			if b {
 				data = bus.read_8(address) as u32;
 			} else {
-				data = load_32_from_memory(bus, address);
+				data = load_32_from_memory(cpu, bus, address);
 			}
 			cpu.set_register_value(rd_index, data);
 		} else {
@@ -503,6 +504,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			if b {
 				bus.write_8(address, rd as u8);
 			} else {
+				check_alignment(cpu, address, EAccessWidth::Word, true);
 				// NOTE: Forced alignment! (UNPREDICTABLE)
 				bus.write_32(address & !0x0000_0003, rd);
 			}
@@ -523,6 +525,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 		// STRH
 		if !l && !s {
 			let rd = cpu.get_register_value(rd_index);
+			check_alignment(cpu, address, EAccessWidth::Halfword, true);
 			// NOTE: Forced alignment! (UNPREDICTABLE)
 			bus.write_16(address & !0x1, rd as u16);
 		} else {
@@ -532,6 +535,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 				if (address & 0x0000_0001) == 0 {
 					data = bus.read_16(address) as i16 as u32;
 				} else {
+					check_alignment(cpu, address, EAccessWidth::Halfword, false);
 					// NOTE: Read byte! (UNPREDICTABLE)
 					data = bus.read_8(address) as i8 as u32;
 				}
@@ -545,6 +549,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 				if (address & 0x0000_0001) == 0 {
 					data = bus.read_16(address) as u32;
 				} else {
+					check_alignment(cpu, address, EAccessWidth::Halfword, false);
 					// NOTE: Forced alignment and rotation of data! (UNPREDICTABLE)
 					data = (bus.read_16(address & !0x1) as u32).rotate_right(8);
 				}
@@ -570,7 +575,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			if b {
 				data = bus.read_8(address) as u32;
 			} else {
-				data = load_32_from_memory(bus, address);
+				data = load_32_from_memory(cpu, bus, address);
 			}
 
 			cpu.set_register_value(rd_index, data);
@@ -579,6 +584,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			if b {
 				bus.write_8(address, rd as u8);
 			} else {
+				check_alignment(cpu, address, EAccessWidth::Word, true);
 				// NOTE: Forced alignment! (UNPREDICTABLE)
 				bus.write_32(address & !0x0000_0003, rd);
 			}
@@ -597,6 +603,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			if (address & 0x0000_0001) == 0 {
 				data = bus.read_16(address) as u32;
 			} else {
+				check_alignment(cpu, address, EAccessWidth::Halfword, false);
 				// NOTE: Forced alignment and rotation of data! (UNPREDICTABLE)
 				data = (bus.read_16(address & !0x0000_0001) as u32).rotate_right(8);
 			}
@@ -604,6 +611,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			cpu.set_register_value(rd_index, data);
 		} else {
 			let rd = cpu.get_register_value(rd_index);
+			check_alignment(cpu, address, EAccessWidth::Halfword, true);
 			// NOTE: Forced alignment! (UNPREDICTABLE)
 			bus.write_16(address & !0x0000_0001, rd as u16);
 		}
@@ -616,11 +624,12 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 
 		let address = cpu.get_register_value(STACK_POINTER_REGISTER).wrapping_add(offset * 4);
 		if l {
-			let data = load_32_from_memory(bus, address);
+			let data = load_32_from_memory(cpu, bus, address);
 
 			cpu.set_register_value(rd_index, data);
 		} else {
 			let rd = cpu.get_register_value(rd_index);
+			check_alignment(cpu, address, EAccessWidth::Word, true);
 			// NOTE: Forced alignment! (UNPREDICTABLE)
 			bus.write_32(address & !0x0000_0003, rd);
 		}
@@ -655,6 +664,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 		let r = instruction.get_r();
 		let sp = cpu.get_register_value(STACK_POINTER_REGISTER);
 		let reg_list = instruction.get_register_list();
+		check_alignment(cpu, sp, EAccessWidth::Word, !pop);
 
 		if pop {
 			// NOTE: Forced alignment!
@@ -708,6 +718,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 		let rn_index = instruction.get_rs_index();
 		let rn = cpu.get_register_value(rn_index);
 		let reg_list = instruction.get_register_list();
+		check_alignment(cpu, rn, EAccessWidth::Word, !l);
 
 		// NOTE: UNPREDICTABLE!!!
 		if reg_list == 0 {
@@ -716,7 +727,7 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 			cpu.set_register_value(rn_index, rn.wrapping_add(0x40));
 
 			if l {
-				let value = load_32_from_memory(bus, address);
+				let value = load_32_from_memory(cpu, bus, address);
 				cpu.set_register_value(PROGRAM_COUNTER_REGISTER, value);
 
 				return CpuResult::FlushPipeline;
@@ -762,6 +773,10 @@ pub fn execute_thumb(raw_instruction: u16, cpu: &mut CPU, bus: &mut SystemBus) -
 		}
 	} else if (0xff00 & raw_instruction) == 0xdf00 {
 		// SWI Software Interrupt Exception
+		if !bus.is_bios_loaded() {
+			return bios::execute_swi(cpu, bus, raw_instruction as u8);
+		}
+
 		cpu.exception(EExceptionType::SoftwareInterrupt);
 		return CpuResult::FlushPipeline;
 	} else if (0xf000 & raw_instruction) == 0xd000 {
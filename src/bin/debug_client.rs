@@ -0,0 +1,128 @@
+use std::path::Path;
+use std::process::exit;
+
+use gba_rustmulator::debugging::remote::{DebugClient, DEFAULT_DEBUG_SERVER_ADDRESS, REMOTE_CPSR_INDEX};
+use gba_rustmulator::debugging::symbols::SymbolTable;
+
+/// A number, or a name of the symbol table (eg. "player_hp" or "main+0x10")
+fn parse_number(text: &str, symbols: &SymbolTable) -> u32 {
+	let result = match text.strip_prefix("0x") {
+		Some(hex) => u32::from_str_radix(hex, 16),
+		None => text.parse(),
+	};
+
+	result.ok().or_else(|| symbols.resolve(text)).unwrap_or_else(|| {
+		eprintln!("{} isn't a number or a known symbol", text);
+		exit(2);
+	})
+}
+
+/// Bookmarks file of the emulator, or the .elf or .sym of the ROM
+fn load_symbols(path: &Path) -> SymbolTable {
+	let mut symbols = SymbolTable::new();
+	let extension = path.extension().and_then(|extension| extension.to_str());
+	let result = match extension {
+		Some("elf") => std::fs::read(path).and_then(|data| symbols.load_elf_symbols(&data)).map(|_| ()),
+		Some("sym") => std::fs::read_to_string(path).and_then(|text| symbols.load_sym_symbols(&text)).map(|_| ()),
+		_ => std::fs::read_to_string(path).and_then(|text| symbols.parse_bookmarks(&text)),
+	};
+
+	if let Err(e) = result {
+		eprintln!("Failed to load the symbols of {}: {}", path.display(), e);
+		exit(1);
+	}
+	symbols
+}
+
+/// Send one debugger command to a core running debug_server
+///
+/// Usage: debug_client [--address <address>] [--symbols <bookmarks, .elf or .sym file>] <command> [arguments]
+/// Commands: status, pause, resume, step, registers, set-register <index|cpsr> <value>, read <address> <length>,
+/// write <address> <byte>..., break <address>, clear <address>
+/// Addresses can be symbol names when a symbols file is given
+fn main() {
+	let mut args: Vec<String> = std::env::args().skip(1).collect();
+	let mut address = DEFAULT_DEBUG_SERVER_ADDRESS.to_string();
+	if args.first().map(String::as_str) == Some("--address") && args.len() >= 2 {
+		address = args.remove(1);
+		args.remove(0);
+	}
+	let mut symbols = SymbolTable::new();
+	if args.first().map(String::as_str) == Some("--symbols") && args.len() >= 2 {
+		symbols = load_symbols(Path::new(&args.remove(1)));
+		args.remove(0);
+	}
+
+	if args.is_empty() {
+		eprintln!("Usage: debug_client [--address <address>] [--symbols <file>] <command> [arguments]");
+		exit(2);
+	}
+
+	let mut client = match DebugClient::connect(address.as_str()) {
+		Ok(client) => client,
+		Err(e) => {
+			eprintln!("Failed to attach to {}: {}", address, e);
+			exit(1);
+		}
+	};
+
+	let argument = |i: usize| {
+		parse_number(
+			args.get(i).map(String::as_str).unwrap_or_else(|| {
+				eprintln!("Missing argument for {}", args[0]);
+				exit(2);
+			}),
+			&symbols,
+		)
+	};
+
+	let result = match args[0].as_str() {
+		"status" => client.get_status().map(|status| {
+			println!(
+				"{} at PC {:#010x} (cycle {})",
+				if status.paused { "Paused" } else { "Running" },
+				status.pc,
+				status.current_cycle
+			);
+		}),
+		"pause" => client.pause(),
+		"resume" => client.resume(),
+		"step" => client.step().map(|pc| println!("PC: {:#010x}", pc)),
+		"registers" => client.read_registers().map(|registers| {
+			for (i, register) in registers.registers.iter().enumerate() {
+				println!("r{}: {:#010x}", i, register);
+			}
+			println!("CPSR: {:#010x}", registers.cpsr);
+			println!("SPSR: {:#010x}", registers.spsr);
+		}),
+		"set-register" => {
+			let index = if args.get(1).map(String::as_str) == Some("cpsr") {
+				REMOTE_CPSR_INDEX
+			} else {
+				argument(1) as u8
+			};
+			client.write_register(index, argument(2))
+		}
+		"read" => client.read_memory(argument(1), argument(2)).map(|data| {
+			for (i, line) in data.chunks(16).enumerate() {
+				let bytes: Vec<String> = line.iter().map(|value| format!("{:02x}", value)).collect();
+				println!("{:#010x}: {}", argument(1).wrapping_add(i as u32 * 16), bytes.join(" "));
+			}
+		}),
+		"write" => {
+			let data: Vec<u8> = (2..args.len()).map(|i| argument(i) as u8).collect();
+			client.write_memory(argument(1), &data)
+		}
+		"break" => client.set_breakpoint(argument(1)),
+		"clear" => client.clear_breakpoint(argument(1)),
+		command => {
+			eprintln!("Unknown command {}", command);
+			exit(2);
+		}
+	};
+
+	if let Err(e) = result {
+		eprintln!("{} failed: {}", args[0], e);
+		exit(1);
+	}
+}
@@ -0,0 +1,49 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
+
+use gba_rustmulator::debugging::remote::{DebugServer, DEFAULT_DEBUG_SERVER_ADDRESS};
+use gba_rustmulator::gba::{ERefreshRate, Gba};
+use gba_rustmulator::system::cartridge::get_cartridge_type;
+
+/// Run a ROM headlessly at the hardware speed, with a debugger backend other processes can attach to (see debug_client)
+///
+/// Usage: debug_server <bios> <rom> [address]
+fn main() {
+	let args: Vec<String> = std::env::args().collect();
+	if args.len() < 3 {
+		eprintln!("Usage: {} <bios> <rom> [address]", args[0]);
+		exit(2);
+	}
+
+	let mut bios_data = Vec::<u8>::new();
+	File::open(&args[1]).expect("Bios couldn't be opened!").read_to_end(&mut bios_data).unwrap();
+	let mut cartridge_data = Vec::<u8>::new();
+	File::open(&args[2]).expect("Cartridge couldn't be opened!").read_to_end(&mut cartridge_data).unwrap();
+
+	let address = args.get(3).map(String::as_str).unwrap_or(DEFAULT_DEBUG_SERVER_ADDRESS);
+	let mut server = match DebugServer::bind(address) {
+		Ok(server) => server,
+		Err(e) => {
+			eprintln!("Failed to listen on {}: {}", address, e);
+			exit(1);
+		}
+	};
+	println!("Debug server listening on {}", server.get_local_address().unwrap());
+
+	let mut gba = Gba::new_with_type(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice(), get_cartridge_type(Path::new(&args[2])));
+	let frame_duration = ERefreshRate::Hardware.get_frame_duration();
+	loop {
+		let frame_start = Instant::now();
+		server.poll(&mut gba);
+		server.run_frame(&mut gba);
+
+		// NOTE: Paced like the hardware (and the requests of a paused core answered about as often)
+		let elapsed = frame_start.elapsed();
+		if elapsed < frame_duration {
+			std::thread::sleep(frame_duration - elapsed);
+		}
+	}
+}
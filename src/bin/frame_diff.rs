@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::exit;
+
+use gba_rustmulator::debugging::frame_diff::{diff_frames, load_reference_png, save_frame_png};
+use gba_rustmulator::gba::Gba;
+use gba_rustmulator::system::cartridge::get_cartridge_type;
+
+/// Compare the emulator output at a given frame against a reference emulator screenshot
+///
+/// Usage: frame_diff <bios> <rom> <frame> <reference.png> [diff.png]
+fn main() {
+	let args: Vec<String> = std::env::args().collect();
+	if args.len() < 5 {
+		eprintln!("Usage: {} <bios> <rom> <frame> <reference.png> [diff.png]", args[0]);
+		exit(2);
+	}
+
+	let frame: u32 = args[3].parse().expect("Frame must be a number!");
+	let reference = load_reference_png(Path::new(&args[4])).expect("Reference couldn't be loaded!");
+	let diff_path = args.get(5).map(String::as_str).unwrap_or("diff.png");
+
+	let mut bios_data = Vec::<u8>::new();
+	File::open(&args[1]).expect("Bios couldn't be opened!").read_to_end(&mut bios_data).unwrap();
+	let mut cartridge_data = Vec::<u8>::new();
+	File::open(&args[2]).expect("Cartridge couldn't be opened!").read_to_end(&mut cartridge_data).unwrap();
+
+	let mut gba = Gba::new_with_type(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice(), get_cartridge_type(Path::new(&args[2])));
+	for _ in 0..frame {
+		gba.run_frame();
+	}
+
+	let diff = diff_frames(&gba.render(), &reference);
+	save_frame_png(Path::new(diff_path), &diff.pixels).expect("Diff couldn't be written!");
+
+	match diff.first_mismatch {
+		Some((x, y)) => {
+			println!("{} mismatched pixels (first at {}, {}), diff written to {}", diff.mismatched_pixels, x, y, diff_path);
+			exit(1);
+		}
+		None => println!("Frame {} matches the reference", frame),
+	}
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
+
+use gba_rustmulator::debugging::frame_diff::save_frame_png;
+use gba_rustmulator::gba::{Gba, CPU_FREQUENCY, CYCLES_PER_FRAME};
+use gba_rustmulator::system::cartridge::{get_cartridge_type, load_rom_file};
+
+/// Run a ROM for a number of frames without any window and save the last frame, for CI regression tests and benchmarks
+/// The BIOS calls are high level emulated unless a BIOS is given
+///
+/// Usage: gba_headless <rom> <frames> [output.png] [--bios <bios>]
+/// Exits with 0 on success, 1 if the ROM, the BIOS or the output can't be read or written and 2 on invalid arguments
+fn main() {
+	let mut args: Vec<String> = std::env::args().collect();
+	let bios_path = args.iter().position(|arg| arg == "--bios").map(|i| {
+		let path = args.get(i + 1).cloned().unwrap_or_else(|| usage(&args[0]));
+		args.drain(i..i + 2);
+		path
+	});
+	if args.len() < 3 {
+		usage(&args[0]);
+	}
+
+	let frames: u32 = args[2].parse().unwrap_or_else(|_| usage(&args[0]));
+	let output_path = args.get(3).map(String::as_str).unwrap_or("out.png");
+
+	let bios_data = match bios_path.as_ref().map(std::fs::read) {
+		Some(Ok(data)) => data,
+		Some(Err(e)) => fail(&format!("Failed to read the BIOS: {}", e)),
+		None => Vec::new(),
+	};
+	let cartridge_data = load_rom_file(Path::new(&args[1])).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", args[1], e)));
+
+	let mut gba = Gba::new_with_type(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice(), get_cartridge_type(Path::new(&args[1])));
+	let start = Instant::now();
+	for _ in 0..frames {
+		gba.run_frame();
+	}
+	let elapsed = start.elapsed().as_secs_f64();
+
+	if let Err(e) = save_frame_png(Path::new(output_path), &gba.render()) {
+		fail(&format!("Failed to write {}: {}", output_path, e));
+	}
+
+	let speed = frames as f64 * CYCLES_PER_FRAME as f64 / CPU_FREQUENCY as f64 / elapsed.max(f64::EPSILON) * 100.0;
+	println!(
+		"Ran {} frames in {:.2}s ({:.0}% of the hardware speed), last frame saved to {}",
+		frames, elapsed, speed, output_path
+	);
+}
+
+fn usage(program: &str) -> ! {
+	eprintln!("Usage: {} <rom> <frames> [output.png] [--bios <bios>]", program);
+	exit(2);
+}
+
+fn fail(message: &str) -> ! {
+	eprintln!("{}", message);
+	exit(1);
+}
@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::exit;
+use std::time::Instant;
+
+use gba_rustmulator::debugging::gdbstub::{GdbServer, DEFAULT_GDB_SERVER_ADDRESS};
+use gba_rustmulator::gba::{ERefreshRate, Gba};
+use gba_rustmulator::system::cartridge::{get_cartridge_type, load_rom_file};
+
+/// Run a ROM headlessly at the hardware speed with a GDB server, attach with `gdb-multiarch -ex "target remote <address>"`
+/// The BIOS calls are high level emulated unless a BIOS is given
+///
+/// Usage: gdb_server <rom> [address] [--bios <bios>]
+fn main() {
+	let mut args: Vec<String> = std::env::args().collect();
+	let bios_path = args.iter().position(|arg| arg == "--bios").map(|i| {
+		let path = args.get(i + 1).cloned().unwrap_or_else(|| usage(&args[0]));
+		args.drain(i..i + 2);
+		path
+	});
+	if args.len() < 2 {
+		usage(&args[0]);
+	}
+
+	let bios_data = match bios_path.as_ref().map(std::fs::read) {
+		Some(Ok(data)) => data,
+		Some(Err(e)) => fail(&format!("Failed to read the BIOS: {}", e)),
+		None => Vec::new(),
+	};
+	let cartridge_data = load_rom_file(Path::new(&args[1])).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", args[1], e)));
+
+	let address = args.get(2).map(String::as_str).unwrap_or(DEFAULT_GDB_SERVER_ADDRESS);
+	let mut server = GdbServer::bind(address).unwrap_or_else(|e| fail(&format!("Failed to listen on {}: {}", address, e)));
+	println!("GDB server listening on {}", server.get_local_address().unwrap());
+
+	let mut gba = Gba::new_with_type(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice(), get_cartridge_type(Path::new(&args[1])));
+	let frame_duration = ERefreshRate::Hardware.get_frame_duration();
+	loop {
+		let frame_start = Instant::now();
+		server.poll(&mut gba);
+		server.run_frame(&mut gba);
+
+		let elapsed = frame_start.elapsed();
+		if elapsed < frame_duration {
+			std::thread::sleep(frame_duration - elapsed);
+		}
+	}
+}
+
+fn usage(program: &str) -> ! {
+	eprintln!("Usage: {} <rom> [address] [--bios <bios>]", program);
+	exit(2);
+}
+
+fn fail(message: &str) -> ! {
+	eprintln!("{}", message);
+	exit(1);
+}
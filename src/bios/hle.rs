@@ -0,0 +1,200 @@
+use crate::system::{MemoryInterface, SystemBus, BIOS_SIZE};
+
+/// Interrupt flags the IRQ handler of the game acknowledges for IntrWait (BIOS IF)
+pub const INTERRUPT_CHECK_FLAGS_ADDRESS: u32 = 0x0300_7ff8;
+const IME_ADDRESS: u32 = 0x0400_0208;
+const POSTFLG_ADDRESS: u32 = 0x0400_0300;
+
+/// CpuSet control bits: the word count is in bits 0-20
+const CPU_SET_COUNT_MASK: u32 = 0x1f_ffff;
+const CPU_SET_FILL_BIT: u32 = 1 << 24;
+const CPU_SET_WORD_BIT: u32 = 1 << 26;
+/// Type of the LZ77 compressed data, in the upper 4 bits of the first header byte
+const LZ77_TYPE: u8 = 0x10;
+
+/// ARM code of the BIOS replacement, the SWI calls themselves never reach it
+const HLE_IMAGE: [(u32, u32); 12] = [
+	// Reset: ldr pc, [pc, #0x18] (jump to the ROM)
+	(0x00, 0xe59f_f018),
+	// Undefined instruction: movs pc, lr
+	(0x04, 0xe1b0_f00e),
+	// Unsupported SWI: movs pc, lr
+	(0x08, 0xe1b0_f00e),
+	// IRQ: b 0x128
+	(0x18, 0xea00_0042),
+	// FIQ: subs pc, lr, #4
+	(0x1c, 0xe25e_f004),
+	(0x20, 0x0800_0000),
+	// IRQ handler, calls the handler of the game stored at 0x3007FFC (through the 0x3FFFFFC mirror) like the BIOS
+	// stmfd sp!, {r0-r3, r12, lr}
+	(0x128, 0xe92d_500f),
+	// mov r0, #0x4000000
+	(0x12c, 0xe3a0_0301),
+	// add lr, pc, #0
+	(0x130, 0xe28f_e000),
+	// ldr pc, [r0, #-4]
+	(0x134, 0xe510_f004),
+	// ldmfd sp!, {r0-r3, r12, lr}
+	(0x138, 0xe8bd_500f),
+	// subs pc, lr, #4
+	(0x13c, 0xe25e_f004),
+];
+
+/// BIOS image used when no BIOS is loaded: the exception vectors and the IRQ handler, the SWI calls are high level emulated
+pub fn build_hle_image() -> Box<[u8]> {
+	let mut image = vec![0; BIOS_SIZE];
+	for (address, opcode) in HLE_IMAGE.iter() {
+		image[*address as usize..*address as usize + 4].copy_from_slice(&opcode.to_le_bytes());
+	}
+
+	image.into_boxed_slice()
+}
+
+/// Set the I/O registers the boot code of the BIOS leaves set
+pub fn init_io_registers(bus: &mut SystemBus) {
+	bus.write_8(POSTFLG_ADDRESS, 1);
+}
+
+/// SWI 06h - Div, returns (number / denom, number % denom, abs(number / denom))
+/// NOTE: The BIOS never returns when dividing by 0, the result is the one of other emulators instead of a hang
+pub fn div(number: i32, denom: i32) -> (u32, u32, u32) {
+	if denom == 0 {
+		let sign = if number < 0 { -1 } else { 1 };
+		return (sign as u32, number as u32, 1);
+	}
+
+	let quotient = number.wrapping_div(denom);
+	(quotient as u32, number.wrapping_rem(denom) as u32, quotient.wrapping_abs() as u32)
+}
+
+/// SWI 08h - Sqrt
+pub fn sqrt(value: u32) -> u32 {
+	(value as f64).sqrt() as u32
+}
+
+/// SWI 09h - ArcTan, the tangent is a 1.14 fixed point number, the angle is in 0xc000-0x4000 (-PI/2 to PI/2)
+pub fn arc_tan(tan: i16) -> u32 {
+	to_bios_angle((tan as f64 / 16384.0).atan()) as i16 as i32 as u32
+}
+
+/// SWI 0Ah - ArcTan2, angle of the point in 0-0xffff (0 to 2*PI)
+pub fn arc_tan2(x: i16, y: i16) -> u32 {
+	to_bios_angle((y as f64).atan2(x as f64)) as u16 as u32
+}
+
+fn to_bios_angle(radians: f64) -> i32 {
+	(radians / (std::f64::consts::PI * 2.0) * 65536.0).round() as i32
+}
+
+/// SWI 0Bh - CpuSet, copies or fills (fixed source) 16 or 32 bit units
+pub fn cpu_set(bus: &mut SystemBus, source: u32, destination: u32, control: u32) {
+	let count = control & CPU_SET_COUNT_MASK;
+	let step = if control & CPU_SET_FILL_BIT != 0 { 0 } else { 1 };
+	if control & CPU_SET_WORD_BIT != 0 {
+		let (source, destination) = (source & !0x3, destination & !0x3);
+		for i in 0..count {
+			let value = bus.read_32(source.wrapping_add(i * step * 4));
+			bus.write_32(destination.wrapping_add(i * 4), value);
+		}
+	} else {
+		let (source, destination) = (source & !0x1, destination & !0x1);
+		for i in 0..count {
+			let value = bus.read_16(source.wrapping_add(i * step * 2));
+			bus.write_16(destination.wrapping_add(i * 2), value);
+		}
+	}
+}
+
+/// SWI 0Ch - CpuFastSet, like CpuSet with 32 bit units only, the count is rounded up to a multiple of 8 words
+pub fn cpu_fast_set(bus: &mut SystemBus, source: u32, destination: u32, control: u32) {
+	let count = ((control & CPU_SET_COUNT_MASK) + 7) & !0x7;
+	cpu_set(bus, source, destination, (control & CPU_SET_FILL_BIT) | CPU_SET_WORD_BIT | count);
+}
+
+/// SWI 0Fh - ObjAffineSet, computes the rotation/scaling parameters of count entries (scale x, scale y, angle)
+/// Parameters PA, PB, PC and PD are written offset bytes apart (2 for a packed array, 8 for OAM)
+pub fn obj_affine_set(bus: &mut SystemBus, source: u32, destination: u32, count: u32, offset: u32) {
+	for i in 0..count {
+		let entry = source.wrapping_add(i * 8);
+		let scale_x = bus.read_16(entry) as i16 as i32;
+		let scale_y = bus.read_16(entry + 2) as i16 as i32;
+		// NOTE: The BIOS sine table has 256 entries, the low byte of the angle is ignored
+		let angle = (bus.read_16(entry + 4) >> 8) as f64 * std::f64::consts::PI * 2.0 / 256.0;
+		let sin = (angle.sin() * 16384.0).round() as i32;
+		let cos = (angle.cos() * 16384.0).round() as i32;
+
+		let parameters = [(scale_x * cos) >> 14, (-scale_x * sin) >> 14, (scale_y * sin) >> 14, (scale_y * cos) >> 14];
+		let entry_destination = destination.wrapping_add(i * offset * 4);
+		for (j, parameter) in parameters.iter().enumerate() {
+			bus.write_16(entry_destination.wrapping_add(j as u32 * offset), *parameter as u16);
+		}
+	}
+}
+
+/// SWI 11h/12h - LZ77UnCompWram/LZ77UnCompVram, VRAM only takes 16 bit writes so the data is written by halfwords there
+pub fn lz77_uncompress(bus: &mut SystemBus, source: u32, destination: u32, halfword_writes: bool) {
+	let header = bus.read_32(source & !0x3);
+	if header as u8 & 0xf0 != LZ77_TYPE {
+		return;
+	}
+
+	let length = (header >> 8) as usize;
+	let mut data = Vec::with_capacity(length);
+	let mut address = (source & !0x3) + 4;
+	let mut read_byte = || {
+		address += 1;
+		bus.read_8(address - 1)
+	};
+
+	while data.len() < length {
+		let flags = read_byte();
+		for block in (0..8).rev() {
+			if data.len() >= length {
+				break;
+			}
+
+			if flags & (1 << block) == 0 {
+				data.push(read_byte());
+				continue;
+			}
+
+			let (first, second) = (read_byte(), read_byte());
+			let distance = (((first as usize & 0xf) << 8) | second as usize) + 1;
+			for _ in 0..(first >> 4) + 3 {
+				let value = data.len().checked_sub(distance).map_or(0, |index| data[index]);
+				data.push(value);
+			}
+		}
+	}
+	data.truncate(length);
+
+	if halfword_writes {
+		for (i, bytes) in data.chunks(2).enumerate() {
+			let value = bytes[0] as u16 | (*bytes.get(1).unwrap_or(&0) as u16) << 8;
+			bus.write_16(destination.wrapping_add(i as u32 * 2), value);
+		}
+	} else {
+		for (i, value) in data.iter().enumerate() {
+			bus.write_8(destination.wrapping_add(i as u32), *value);
+		}
+	}
+}
+
+/// SWI 04h - IntrWait, returns whether one of the flags was raised, the CPU is halted until the next interrupt otherwise
+/// NOTE: The call is executed again after each interrupt, the old flags are only discarded the first time
+pub fn intr_wait(bus: &mut SystemBus, discard_old_flags: bool, flags: u16) -> bool {
+	let check_flags = bus.read_16(INTERRUPT_CHECK_FLAGS_ADDRESS);
+	if discard_old_flags && !bus.io_regs.intr_waiting {
+		bus.write_16(INTERRUPT_CHECK_FLAGS_ADDRESS, check_flags & !flags);
+	} else if check_flags & flags != 0 {
+		bus.write_16(INTERRUPT_CHECK_FLAGS_ADDRESS, check_flags & !flags);
+		bus.io_regs.intr_waiting = false;
+		return true;
+	}
+
+	// NOTE: The BIOS enables the interrupts, otherwise nothing would wake the CPU up
+	bus.write_16(IME_ADDRESS, 1);
+	bus.io_regs.halted = true;
+	bus.io_regs.intr_waiting = true;
+	false
+}
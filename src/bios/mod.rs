@@ -0,0 +1,205 @@
+use crate::arm7tdmi::cpu::{CpuResult, CPU, LINK_REGISTER_REGISTER, PROGRAM_COUNTER_REGISTER, STACK_POINTER_REGISTER};
+use crate::arm7tdmi::{EExceptionType, EOperatingMode};
+use crate::system::{MemoryInterface, SystemBus, CARTRIDGE_WS0_LO, EWRAM_ADDR};
+
+pub(crate) mod hle;
+
+// SWI numbers
+pub const SWI_SOFT_RESET: u8 = 0x00;
+pub const SWI_HALT: u8 = 0x02;
+pub const SWI_INTR_WAIT: u8 = 0x04;
+pub const SWI_VBLANK_INTR_WAIT: u8 = 0x05;
+pub const SWI_DIV: u8 = 0x06;
+pub const SWI_DIV_ARM: u8 = 0x07;
+pub const SWI_SQRT: u8 = 0x08;
+pub const SWI_ARC_TAN: u8 = 0x09;
+pub const SWI_ARC_TAN2: u8 = 0x0a;
+pub const SWI_CPU_SET: u8 = 0x0b;
+pub const SWI_CPU_FAST_SET: u8 = 0x0c;
+pub const SWI_OBJ_AFFINE_SET: u8 = 0x0f;
+pub const SWI_LZ77_UNCOMP_WRAM: u8 = 0x11;
+pub const SWI_LZ77_UNCOMP_VRAM: u8 = 0x12;
+
+/// Flag selecting the SoftReset return address (0 = ROM, otherwise EWRAM)
+pub const SOFT_RESET_FLAG_ADDRESS: u32 = 0x0300_7ffa;
+/// Start of the IWRAM area cleared by SoftReset (up to the end of IWRAM)
+pub const SOFT_RESET_CLEAR_START_ADDRESS: u32 = 0x0300_7e00;
+pub const SOFT_RESET_CLEAR_END_ADDRESS: u32 = 0x0300_8000;
+
+// Stack pointers set up by the BIOS
+pub const SP_SVC_ADDRESS: u32 = 0x0300_7fe0;
+pub const SP_IRQ_ADDRESS: u32 = 0x0300_7fa0;
+pub const SP_SYS_ADDRESS: u32 = 0x0300_7f00;
+
+/// High level emulation of a BIOS call, arguments and results are in r0-r3 like for the BIOS
+///
+/// Unsupported calls fall back to the regular SWI exception, so they still reach whatever is mapped at the vector
+pub(crate) fn execute_swi(cpu: &mut CPU, bus: &mut SystemBus, comment: u8) -> CpuResult {
+	let r0 = cpu.get_register_value(0);
+	let r1 = cpu.get_register_value(1);
+	let r2 = cpu.get_register_value(2);
+	match comment {
+		SWI_SOFT_RESET => {
+			soft_reset(cpu, bus);
+			return CpuResult::FlushPipeline;
+		}
+		SWI_HALT => bus.io_regs.halted = true,
+		SWI_INTR_WAIT | SWI_VBLANK_INTR_WAIT => {
+			let (discard_old_flags, flags) = if comment == SWI_VBLANK_INTR_WAIT { (true, 0x1) } else { (r0 != 0, r1 as u16) };
+			if !hle::intr_wait(bus, discard_old_flags, flags) {
+				// NOTE: Execute the SWI again once an interrupt woke the CPU up
+				return CpuResult::FlushPipeline;
+			}
+		}
+		SWI_DIV | SWI_DIV_ARM => {
+			let (number, denom) = if comment == SWI_DIV { (r0, r1) } else { (r1, r0) };
+			let (quotient, remainder, abs_quotient) = hle::div(number as i32, denom as i32);
+			cpu.set_register_value(0, quotient);
+			cpu.set_register_value(1, remainder);
+			cpu.set_register_value(3, abs_quotient);
+		}
+		SWI_SQRT => cpu.set_register_value(0, hle::sqrt(r0)),
+		SWI_ARC_TAN => cpu.set_register_value(0, hle::arc_tan(r0 as i16)),
+		SWI_ARC_TAN2 => cpu.set_register_value(0, hle::arc_tan2(r0 as i16, r1 as i16)),
+		SWI_CPU_SET => hle::cpu_set(bus, r0, r1, r2),
+		SWI_CPU_FAST_SET => hle::cpu_fast_set(bus, r0, r1, r2),
+		SWI_OBJ_AFFINE_SET => hle::obj_affine_set(bus, r0, r1, r2, cpu.get_register_value(3)),
+		SWI_LZ77_UNCOMP_WRAM | SWI_LZ77_UNCOMP_VRAM => hle::lz77_uncompress(bus, r0, r1, comment == SWI_LZ77_UNCOMP_VRAM),
+		_ => {
+			cpu.exception(EExceptionType::SoftwareInterrupt);
+			return CpuResult::FlushPipeline;
+		}
+	}
+
+	CpuResult::Continue
+}
+
+/// Start the game without running the boot code, when no BIOS is loaded
+/// NOTE: The state is the one the BIOS leaves: stacks set up, POSTFLG set and the game started from ROM
+pub fn skip_boot(cpu: &mut CPU, bus: &mut SystemBus) {
+	hle::init_io_registers(bus);
+	bus.write_8(SOFT_RESET_FLAG_ADDRESS, 0);
+	soft_reset(cpu, bus);
+}
+
+/// Boot a multiboot image already copied to EWRAM, like the BIOS does once the download finishes
+pub fn start_multiboot(cpu: &mut CPU, bus: &mut SystemBus) {
+	hle::init_io_registers(bus);
+	bus.write_8(SOFT_RESET_FLAG_ADDRESS, 1);
+	soft_reset(cpu, bus);
+}
+
+/// SWI 00h - SoftReset
+///
+/// Clears 3007E00h-3007FFFh, resets the SVC/IRQ/SYS stacks and registers, then jumps to ROM or EWRAM
+/// depending on the flag at 3007FFAh (read before the RAM gets cleared).
+pub fn soft_reset(cpu: &mut CPU, bus: &mut SystemBus) {
+	let return_address = if bus.read_8(SOFT_RESET_FLAG_ADDRESS) == 0 { CARTRIDGE_WS0_LO } else { EWRAM_ADDR };
+
+	for address in (SOFT_RESET_CLEAR_START_ADDRESS..SOFT_RESET_CLEAR_END_ADDRESS).step_by(4) {
+		bus.write_32(address, 0);
+	}
+
+	let mut current_mode = cpu.get_operating_mode();
+	for (mode, stack_pointer) in [(EOperatingMode::SupervisorMode, SP_SVC_ADDRESS), (EOperatingMode::IrqMode, SP_IRQ_ADDRESS)]
+		.iter()
+		.cloned()
+	{
+		cpu.change_operating_mode(mode, current_mode);
+		current_mode = mode;
+
+		cpu.set_register_value(STACK_POINTER_REGISTER, stack_pointer);
+		cpu.set_register_value(LINK_REGISTER_REGISTER, 0);
+		cpu.get_mut_spsr(mode).0 = 0;
+	}
+
+	cpu.change_operating_mode(EOperatingMode::SystemMode, current_mode);
+	cpu.set_register_value(STACK_POINTER_REGISTER, SP_SYS_ADDRESS);
+	for i in 0..=12 {
+		cpu.set_register_value(i, 0);
+	}
+
+	cpu.get_mut_cpsr().set_t(false);
+	cpu.get_mut_cpsr().set_i(false);
+	cpu.get_mut_cpsr().set_f(false);
+	cpu.set_register_value(PROGRAM_COUNTER_REGISTER, return_address);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::arm7tdmi::cpu::BankedRegisters;
+
+	const CODE_ADDRESS: u32 = 0x0300_0000;
+
+	/// THUMB `swi 0` running in IRQ mode with every register, the banked ones included, and the top of IWRAM dirty
+	fn setup(soft_reset_flag: u8) -> (CPU, SystemBus) {
+		let mut bus = SystemBus::new(Box::new([]));
+		bus.write_16(CODE_ADDRESS, 0xdf00);
+		for address in (SOFT_RESET_CLEAR_START_ADDRESS - 0x4..SOFT_RESET_CLEAR_END_ADDRESS).step_by(4) {
+			bus.write_32(address, 0xdead_beef);
+		}
+		bus.write_8(SOFT_RESET_FLAG_ADDRESS, soft_reset_flag);
+
+		let mut cpu = CPU::new();
+		let mut current_mode = cpu.get_operating_mode();
+		for mode in [EOperatingMode::SupervisorMode, EOperatingMode::IrqMode] {
+			cpu.change_operating_mode(mode, current_mode);
+			current_mode = mode;
+			cpu.set_register_value(STACK_POINTER_REGISTER, 0x0200_1000);
+			cpu.set_register_value(LINK_REGISTER_REGISTER, 0x0800_1234);
+			cpu.get_mut_spsr(mode).0 = 0xf000_001f;
+		}
+		for i in 0..=12 {
+			cpu.set_register_value(i, 0x1111_1111 * (i as u32 + 1));
+		}
+		cpu.get_mut_cpsr().set_i(true);
+		cpu.get_mut_cpsr().set_f(true);
+		cpu.get_mut_cpsr().set_t(true);
+		cpu.set_pc(CODE_ADDRESS);
+		(cpu, bus)
+	}
+
+	#[test]
+	fn soft_reset_sets_up_the_registers_and_stacks() {
+		let (mut cpu, mut bus) = setup(0);
+		cpu.step(&mut bus);
+
+		assert_eq!(cpu.get_operating_mode(), EOperatingMode::SystemMode);
+		for i in 0..=12 {
+			assert_eq!(cpu.get_register_value(i), 0, "r{}", i);
+		}
+		assert_eq!(cpu.get_register_value(STACK_POINTER_REGISTER), SP_SYS_ADDRESS);
+		assert!(!cpu.get_cpsr().get_t());
+		assert!(!cpu.get_cpsr().get_i());
+		assert!(!cpu.get_cpsr().get_f());
+
+		let banks = cpu.get_banks();
+		for (mode, stack_pointer) in [(EOperatingMode::SupervisorMode, SP_SVC_ADDRESS), (EOperatingMode::IrqMode, SP_IRQ_ADDRESS)] {
+			let index = BankedRegisters::get_bank_index(mode);
+			assert_eq!(banks.get_banked_r13s()[index], stack_pointer, "{:?}", mode);
+			assert_eq!(banks.get_banked_r14s()[index], 0, "{:?}", mode);
+			assert_eq!(cpu.get_spsr(mode).0, 0, "{:?}", mode);
+		}
+	}
+
+	#[test]
+	fn soft_reset_clears_the_top_of_iwram() {
+		let (mut cpu, mut bus) = setup(0);
+		cpu.step(&mut bus);
+
+		for address in (SOFT_RESET_CLEAR_START_ADDRESS..SOFT_RESET_CLEAR_END_ADDRESS).step_by(4) {
+			assert_eq!(bus.read_32(address), 0, "{:#010x}", address);
+		}
+		assert_eq!(bus.read_32(SOFT_RESET_CLEAR_START_ADDRESS - 0x4), 0xdead_beef);
+	}
+
+	#[test]
+	fn soft_reset_returns_to_rom_or_ewram() {
+		for (flag, return_address) in [(0, CARTRIDGE_WS0_LO), (1, EWRAM_ADDR), (0xff, EWRAM_ADDR)] {
+			let (mut cpu, mut bus) = setup(flag);
+			cpu.step(&mut bus);
+			assert_eq!(cpu.get_current_pc(), return_address, "flag {:#x}", flag);
+		}
+	}
+}
@@ -0,0 +1,699 @@
+use crate::system::{EAccessWidth, MemoryInterface, SystemBus};
+
+/// TEA keys of the encrypted GameShark v1/v2 codes
+const GAMESHARK_SEEDS: [u32; 4] = [0x09f4_fbbd, 0x9681_884a, 0x3520_27e9, 0xf3de_e5a7];
+/// TEA keys of the encrypted Action Replay (and GameShark v3) codes
+const ACTION_REPLAY_SEEDS: [u32; 4] = [0x7aa9_648f, 0x7fae_6994, 0xc0ef_aad5, 0x4271_2c57];
+const TEA_DELTA: u32 = 0x9e37_79b9;
+
+const KEY_INPUT_ADDR: u32 = 0x0400_0130;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ECheatFormat {
+	/// Encrypted "XXXXXXXX YYYYYYYY" codes
+	GameShark,
+	/// Encrypted "XXXXXXXX YYYYYYYY" codes, GameShark v3 codes are the same
+	ActionReplay,
+	/// Plain "XXXXXXXX YYYY" codes
+	CodeBreaker,
+}
+
+impl ECheatFormat {
+	pub const ALL: [ECheatFormat; 3] = [ECheatFormat::GameShark, ECheatFormat::ActionReplay, ECheatFormat::CodeBreaker];
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ECheatFormat::GameShark => "GameShark v1/v2",
+			ECheatFormat::ActionReplay => "Action Replay v3",
+			ECheatFormat::CodeBreaker => "CodeBreaker",
+		}
+	}
+
+	/// Hexadecimal digits of the address and the value of a line
+	fn get_line_digits(&self) -> (usize, usize) {
+		match self {
+			ECheatFormat::GameShark | ECheatFormat::ActionReplay => (8, 8),
+			ECheatFormat::CodeBreaker => (8, 4),
+		}
+	}
+}
+
+/// Test of the value in memory of a condition code, against the value of the code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ECheatComparison {
+	Equal,
+	NotEqual,
+	Less,
+	Greater,
+	LessUnsigned,
+	GreaterUnsigned,
+	/// Any bit of the value set in memory
+	AnySet,
+	/// No bit of the value set in memory, eg. keys held down in KEYINPUT
+	NoneSet,
+}
+
+impl ECheatComparison {
+	fn compare(&self, width: EAccessWidth, left: u32, right: u32) -> bool {
+		let sign_extend = |value: u32| match width {
+			EAccessWidth::Byte => value as u8 as i8 as i32,
+			EAccessWidth::Halfword => value as u16 as i16 as i32,
+			EAccessWidth::Word => value as i32,
+		};
+		match self {
+			ECheatComparison::Equal => left == right,
+			ECheatComparison::NotEqual => left != right,
+			ECheatComparison::Less => sign_extend(left) < sign_extend(right),
+			ECheatComparison::Greater => sign_extend(left) > sign_extend(right),
+			ECheatComparison::LessUnsigned => left < right,
+			ECheatComparison::GreaterUnsigned => left > right,
+			ECheatComparison::AnySet => left & right != 0,
+			ECheatComparison::NoneSet => left & right == 0,
+		}
+	}
+}
+
+/// What a code does each frame, codes of all the formats are decoded to these
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ECheatOperation {
+	Write {
+		address: u32,
+		width: EAccessWidth,
+		value: u32,
+	},
+	Add {
+		address: u32,
+		width: EAccessWidth,
+		value: u32,
+	},
+	Or {
+		address: u32,
+		value: u16,
+	},
+	And {
+		address: u32,
+		value: u16,
+	},
+	/// Write `count` values, the address and the value increasing by their steps after each one
+	Slide {
+		address: u32,
+		width: EAccessWidth,
+		value: u32,
+		count: u32,
+		value_step: u32,
+		address_step: u32,
+	},
+	/// Replace a halfword of the ROM, the code the game runs included
+	RomPatch {
+		address: u32,
+		value: u16,
+	},
+	/// Skip the next `count` operations unless the comparison is true
+	If {
+		address: u32,
+		width: EAccessWidth,
+		comparison: ECheatComparison,
+		value: u32,
+		count: usize,
+	},
+}
+
+/// Cheat code as entered by the user and the operations it was decoded to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+	pub name: String,
+	pub format: ECheatFormat,
+	/// One code per line, as entered
+	pub code: String,
+	pub enabled: bool,
+	operations: Vec<ECheatOperation>,
+}
+
+impl Cheat {
+	/// Decode the lines of a code, decrypting GameShark and Action Replay ones
+	/// NOTE: Reseeding GameShark codes (DEADFACE) and encrypted CodeBreaker codes (type 9) aren't supported
+	pub fn parse(name: &str, format: ECheatFormat, code: &str) -> Result<Self, String> {
+		let lines = parse_lines(code, format)?;
+		if lines.is_empty() {
+			return Err("The code is empty".to_string());
+		}
+
+		let operations = match format {
+			ECheatFormat::GameShark => {
+				let lines = lines.iter().map(|&(address, value)| decrypt(address, value, &GAMESHARK_SEEDS)).collect::<Vec<_>>();
+				parse_gameshark(&lines)?
+			}
+			ECheatFormat::ActionReplay => {
+				let lines = lines.iter().map(|&(address, value)| decrypt(address, value, &ACTION_REPLAY_SEEDS)).collect::<Vec<_>>();
+				parse_action_replay(&lines)?
+			}
+			ECheatFormat::CodeBreaker => parse_codebreaker(&lines)?,
+		};
+
+		Ok(Self {
+			name: name.to_string(),
+			format,
+			code: code.trim().to_string(),
+			enabled: true,
+			operations,
+		})
+	}
+
+	pub fn get_operations(&self) -> &[ECheatOperation] {
+		&self.operations
+	}
+
+	/// Run the operations of the code once, call every frame
+	pub fn apply(&self, bus: &mut SystemBus) {
+		let mut index = 0;
+		while index < self.operations.len() {
+			match self.operations[index] {
+				ECheatOperation::Write { address, width, value } => write(bus, address, width, value),
+				ECheatOperation::Add { address, width, value } => {
+					let current = read(bus, address, width);
+					write(bus, address, width, current.wrapping_add(value));
+				}
+				ECheatOperation::Or { address, value } => {
+					let current = bus.read_16(address);
+					bus.patch(address, &(current | value).to_le_bytes());
+				}
+				ECheatOperation::And { address, value } => {
+					let current = bus.read_16(address);
+					bus.patch(address, &(current & value).to_le_bytes());
+				}
+				ECheatOperation::Slide {
+					address,
+					width,
+					value,
+					count,
+					value_step,
+					address_step,
+				} => {
+					for i in 0..count {
+						write(
+							bus,
+							address.wrapping_add(i.wrapping_mul(address_step)),
+							width,
+							value.wrapping_add(i.wrapping_mul(value_step)),
+						);
+					}
+				}
+				ECheatOperation::RomPatch { address, value } => bus.patch(address, &value.to_le_bytes()),
+				ECheatOperation::If {
+					address,
+					width,
+					comparison,
+					value,
+					count,
+				} => {
+					if !comparison.compare(width, read(bus, address, width), value) {
+						index += count;
+					}
+				}
+			}
+			index += 1;
+		}
+	}
+}
+
+/// Cheats of the current game, applied in order every frame
+#[derive(Debug, Clone, Default)]
+pub struct CheatManager {
+	cheats: Vec<Cheat>,
+}
+
+impl CheatManager {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn get_cheats(&self) -> &[Cheat] {
+		&self.cheats
+	}
+
+	/// Replace all the cheats, eg. with the ones of a profile
+	pub fn set_cheats(&mut self, cheats: Vec<Cheat>) {
+		self.cheats = cheats;
+	}
+
+	pub fn get_mut_cheat(&mut self, index: usize) -> Option<&mut Cheat> {
+		self.cheats.get_mut(index)
+	}
+
+	pub fn add(&mut self, cheat: Cheat) {
+		self.cheats.push(cheat);
+	}
+
+	pub fn remove(&mut self, index: usize) {
+		if index < self.cheats.len() {
+			self.cheats.remove(index);
+		}
+	}
+
+	/// Apply the enabled cheats, call once per frame before it's emulated
+	/// NOTE: The reads of the conditions aren't accesses of the game, the watchpoint hits are dropped
+	pub fn apply(&self, bus: &mut SystemBus) {
+		for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+			cheat.apply(bus);
+		}
+		bus.take_anomaly();
+		bus.take_watchpoint_hit();
+	}
+}
+
+fn read(bus: &SystemBus, address: u32, width: EAccessWidth) -> u32 {
+	match width {
+		EAccessWidth::Byte => bus.read_8(address) as u32,
+		EAccessWidth::Halfword => bus.read_16(address) as u32,
+		EAccessWidth::Word => bus.read_32(address),
+	}
+}
+
+fn write(bus: &mut SystemBus, address: u32, width: EAccessWidth, value: u32) {
+	let bytes = value.to_le_bytes();
+	match width {
+		EAccessWidth::Byte => bus.patch(address, &bytes[..1]),
+		EAccessWidth::Halfword => bus.patch(address, &bytes[..2]),
+		EAccessWidth::Word => bus.patch(address, &bytes),
+	}
+}
+
+/// Split the code in (address, value) pairs, spaces and colons between the two halves are optional
+fn parse_lines(code: &str, format: ECheatFormat) -> Result<Vec<(u32, u32)>, String> {
+	let (address_digits, value_digits) = format.get_line_digits();
+	code.lines()
+		.enumerate()
+		.map(|(index, line)| (index, line.chars().filter(|c| !c.is_whitespace() && *c != ':').collect::<String>()))
+		.filter(|(_, line)| !line.is_empty())
+		.map(|(index, line)| {
+			let error = || {
+				format!(
+					"Line {} isn't a {} code: expected {} hexadecimal digits",
+					index + 1,
+					format.get_name(),
+					address_digits + value_digits
+				)
+			};
+			if line.len() != address_digits + value_digits || !line.chars().all(|c| c.is_ascii_hexdigit()) {
+				return Err(error());
+			}
+			let address = u32::from_str_radix(&line[..address_digits], 16).map_err(|_| error())?;
+			let value = u32::from_str_radix(&line[address_digits..], 16).map_err(|_| error())?;
+			Ok((address, value))
+		})
+		.collect()
+}
+
+/// TEA decryption of a line of an encrypted code
+fn decrypt(mut address: u32, mut value: u32, seeds: &[u32; 4]) -> (u32, u32) {
+	let mut sum = TEA_DELTA.wrapping_mul(32);
+	for _ in 0..32 {
+		value = value.wrapping_sub((address << 4).wrapping_add(seeds[2]) ^ address.wrapping_add(sum) ^ (address >> 5).wrapping_add(seeds[3]));
+		address = address.wrapping_sub((value << 4).wrapping_add(seeds[0]) ^ value.wrapping_add(sum) ^ (value >> 5).wrapping_add(seeds[1]));
+		sum = sum.wrapping_sub(TEA_DELTA);
+	}
+	(address, value)
+}
+
+fn parse_gameshark(lines: &[(u32, u32)]) -> Result<Vec<ECheatOperation>, String> {
+	let mut operations = Vec::new();
+	let mut lines = lines.iter().cloned();
+	while let Some((op1, op2)) = lines.next() {
+		let address = op1 & 0x0fff_ffff;
+		match op1 >> 28 {
+			0x0 => operations.push(ECheatOperation::Write {
+				address,
+				width: EAccessWidth::Byte,
+				value: op2 & 0xff,
+			}),
+			0x1 => operations.push(ECheatOperation::Write {
+				address,
+				width: EAccessWidth::Halfword,
+				value: op2 & 0xffff,
+			}),
+			0x2 => operations.push(ECheatOperation::Write {
+				address,
+				width: EAccessWidth::Word,
+				value: op2,
+			}),
+			// NOTE: "3000CCCC VVVVVVVV" writes the word to the CCCC addresses of the next lines, two per line
+			0x3 => {
+				let count = (op1 & 0xffff) as usize;
+				let addresses = lines
+					.by_ref()
+					.take(count.div_ceil(2))
+					.flat_map(|(first, second)| [first, second])
+					.take(count)
+					.collect::<Vec<_>>();
+				if addresses.len() < count {
+					return Err("The list of addresses of a group write is incomplete".to_string());
+				}
+				operations.extend(addresses.into_iter().map(|address| ECheatOperation::Write {
+					address: address & 0x0fff_ffff,
+					width: EAccessWidth::Word,
+					value: op2,
+				}));
+			}
+			0x6 => operations.push(ECheatOperation::RomPatch {
+				address: ((op1 & 0x00ff_ffff) << 1).wrapping_add(0x0800_0000),
+				value: op2 as u16,
+			}),
+			0xd if op1 == 0xdead_face => return Err("Reseeding GameShark codes (DEADFACE) aren't supported".to_string()),
+			0xd => operations.push(ECheatOperation::If {
+				address,
+				width: EAccessWidth::Halfword,
+				comparison: ECheatComparison::Equal,
+				value: op2 & 0xffff,
+				count: 1,
+			}),
+			// NOTE: "E0CCVVVV AAAAAAAA", the address is in the second half
+			0xe => operations.push(ECheatOperation::If {
+				address: op2 & 0x0fff_ffff,
+				width: EAccessWidth::Halfword,
+				comparison: ECheatComparison::Equal,
+				value: op1 & 0xffff,
+				count: ((op1 >> 16) & 0xff) as usize,
+			}),
+			// NOTE: Master codes, hooking the cartridge's code on hardware, not needed here
+			0xf => {}
+			code_type => return Err(format!("Unsupported GameShark code type {:X} ({:08X} {:08X})", code_type, op1, op2)),
+		}
+	}
+	Ok(operations)
+}
+
+/// Action Replay addresses keep the region in bits 20-23
+fn get_action_replay_address(op1: u32) -> u32 {
+	((op1 & 0x00f0_0000) << 4) | (op1 & 0x000f_ffff)
+}
+
+/// Make the condition at `start` cover the operations after it
+fn end_block(operations: &mut [ECheatOperation], start: usize) {
+	let length = operations.len();
+	if let ECheatOperation::If { count, .. } = &mut operations[start] {
+		*count = length - start - 1;
+	}
+}
+
+fn parse_action_replay(lines: &[(u32, u32)]) -> Result<Vec<ECheatOperation>, String> {
+	let mut operations = Vec::new();
+	// NOTE: Indices of the conditions of the blocks not ended yet
+	let mut blocks = Vec::new();
+	let mut lines = lines.iter().cloned();
+	while let Some((op1, op2)) = lines.next() {
+		let unsupported = || format!("Unsupported Action Replay code ({:08X} {:08X})", op1, op2);
+
+		if op1 == 0 {
+			match op2 >> 24 {
+				0x00 if op2 == 0 => {}
+				// NOTE: End if
+				0x40 if op2 == 0x4000_0000 => {
+					let start = blocks.pop().ok_or("End if without a condition block")?;
+					end_block(&mut operations, start);
+				}
+				// NOTE: ROM patch, the halfword is in the next line
+				0x18 => {
+					let (value, _) = lines.next().ok_or("ROM patch without its value")?;
+					operations.push(ECheatOperation::RomPatch {
+						address: ((op2 & 0x00ff_ffff) << 1).wrapping_add(0x0800_0000),
+						value: value as u16,
+					});
+				}
+				_ => return Err(unsupported()),
+			}
+			continue;
+		}
+
+		let address = get_action_replay_address(op1);
+		let width = match (op1 >> 25) & 0x3 {
+			0 => EAccessWidth::Byte,
+			1 => EAccessWidth::Halfword,
+			2 => EAccessWidth::Word,
+			_ => return Err(unsupported()),
+		};
+		let comparison = match op1 & 0x3800_0000 {
+			0x0000_0000 => None,
+			0x0800_0000 => Some(ECheatComparison::Equal),
+			0x1000_0000 => Some(ECheatComparison::NotEqual),
+			0x1800_0000 => Some(ECheatComparison::Less),
+			0x2000_0000 => Some(ECheatComparison::Greater),
+			0x2800_0000 => Some(ECheatComparison::LessUnsigned),
+			0x3000_0000 => Some(ECheatComparison::GreaterUnsigned),
+			_ => Some(ECheatComparison::AnySet),
+		};
+
+		match (comparison, op1 >> 30) {
+			// NOTE: Conditions run the next operation, the next two or the block up to the end if
+			(Some(comparison), action @ 0..=2) => {
+				if action == 2 {
+					blocks.push(operations.len());
+				}
+				operations.push(ECheatOperation::If {
+					address,
+					width,
+					comparison,
+					value: op2,
+					count: action as usize + 1,
+				});
+			}
+			// NOTE: 8 and 16-bit writes are fills, the upper bits of the value count the extra bytes or halfwords
+			(None, 0) => operations.push(match width {
+				EAccessWidth::Byte => ECheatOperation::Slide {
+					address,
+					width,
+					value: op2 & 0xff,
+					count: (op2 >> 8) + 1,
+					value_step: 0,
+					address_step: 1,
+				},
+				EAccessWidth::Halfword => ECheatOperation::Slide {
+					address,
+					width,
+					value: op2 & 0xffff,
+					count: (op2 >> 16) + 1,
+					value_step: 0,
+					address_step: 2,
+				},
+				EAccessWidth::Word => ECheatOperation::Write { address, width, value: op2 },
+			}),
+			(None, 2) => operations.push(ECheatOperation::Add { address, width, value: op2 }),
+			// NOTE: Master codes, hooking the cartridge's code on hardware, not needed here
+			(None, 3) if op1 >> 24 == 0xc4 => {}
+			_ => return Err(unsupported()),
+		}
+	}
+
+	// NOTE: Blocks without an end if last until the end of the code
+	for start in blocks {
+		end_block(&mut operations, start);
+	}
+	Ok(operations)
+}
+
+fn parse_codebreaker(lines: &[(u32, u32)]) -> Result<Vec<ECheatOperation>, String> {
+	let mut operations = Vec::new();
+	let mut lines = lines.iter().cloned();
+	while let Some((op1, op2)) = lines.next() {
+		let address = op1 & 0x0fff_ffff;
+		let condition = |comparison| ECheatOperation::If {
+			address,
+			width: EAccessWidth::Halfword,
+			comparison,
+			value: op2,
+			count: 1,
+		};
+		match op1 >> 28 {
+			// NOTE: Master and game ID codes, not needed here
+			0x0 | 0x1 => {}
+			0x2 => operations.push(ECheatOperation::Or { address, value: op2 as u16 }),
+			0x3 => operations.push(ECheatOperation::Write {
+				address,
+				width: EAccessWidth::Byte,
+				value: op2 & 0xff,
+			}),
+			// NOTE: "4AAAAAAA VVVV" followed by "SSSSCCCC IIII", the value step, the count and the address step
+			0x4 => {
+				let (parameters, address_step) = lines.next().ok_or("Slide code without its second line")?;
+				operations.push(ECheatOperation::Slide {
+					address,
+					width: EAccessWidth::Halfword,
+					value: op2,
+					count: parameters & 0xffff,
+					value_step: parameters >> 16,
+					address_step,
+				});
+			}
+			// NOTE: "5AAAAAAA CCCC" writes the CCCC bytes of the next lines, six per line
+			0x5 => {
+				let count = op2 as usize;
+				let bytes = lines
+					.by_ref()
+					.take(count.div_ceil(6))
+					.flat_map(|(first, second)| {
+						let mut bytes = first.to_be_bytes().to_vec();
+						bytes.extend_from_slice(&(second as u16).to_be_bytes());
+						bytes
+					})
+					.take(count)
+					.collect::<Vec<_>>();
+				if bytes.len() < count {
+					return Err("The bytes of a super code are incomplete".to_string());
+				}
+				operations.extend(bytes.into_iter().enumerate().map(|(offset, byte)| ECheatOperation::Write {
+					address: address.wrapping_add(offset as u32),
+					width: EAccessWidth::Byte,
+					value: byte as u32,
+				}));
+			}
+			0x6 => operations.push(ECheatOperation::And { address, value: op2 as u16 }),
+			0x7 => operations.push(condition(ECheatComparison::Equal)),
+			0x8 => operations.push(ECheatOperation::Write {
+				address,
+				width: EAccessWidth::Halfword,
+				value: op2,
+			}),
+			0x9 => return Err("Encrypted CodeBreaker codes aren't supported".to_string()),
+			0xa => operations.push(condition(ECheatComparison::NotEqual)),
+			0xb => operations.push(condition(ECheatComparison::LessUnsigned)),
+			0xc => operations.push(condition(ECheatComparison::GreaterUnsigned)),
+			// NOTE: "D0000020 KKKK" runs the next line while the keys are held down, KEYINPUT bits are cleared when pressed
+			0xd => operations.push(ECheatOperation::If {
+				address: KEY_INPUT_ADDR,
+				width: EAccessWidth::Halfword,
+				comparison: ECheatComparison::NoneSet,
+				value: op2,
+				count: 1,
+			}),
+			0xe => operations.push(ECheatOperation::Add {
+				address,
+				width: EAccessWidth::Halfword,
+				value: op2,
+			}),
+			0xf => operations.push(condition(ECheatComparison::AnySet)),
+			_ => unreachable!(),
+		}
+	}
+	Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn operations(format: ECheatFormat, code: &str) -> Vec<ECheatOperation> {
+		Cheat::parse("Test", format, code).unwrap().get_operations().to_vec()
+	}
+
+	#[test]
+	fn decrypt_is_the_tea_decryption() {
+		// NOTE: TEA reference vector, a null block encrypted with a null key
+		assert_eq!(decrypt(0x41ea_3a0a, 0x94ba_a940, &[0; 4]), (0, 0));
+		assert_eq!(decrypt(0x9577_1fdb, 0x640b_c1a0, &GAMESHARK_SEEDS), (0x1200_0100, 0x3e7));
+		assert_eq!(decrypt(0xb59d_b70f, 0x8cbc_0c85, &ACTION_REPLAY_SEEDS), (0x0220_0100, 0x3e7));
+	}
+
+	#[test]
+	fn gameshark_codes_are_decrypted_and_decoded() {
+		let code = "95771FDB 640BC1A0\nED3EDF78 4B0E8D9E\n5A45B91F F7A1AC9C";
+		assert_eq!(
+			operations(ECheatFormat::GameShark, code),
+			vec![
+				ECheatOperation::Write {
+					address: 0x0200_0100,
+					width: EAccessWidth::Halfword,
+					value: 0x3e7,
+				},
+				ECheatOperation::If {
+					address: 0x0200_0200,
+					width: EAccessWidth::Halfword,
+					comparison: ECheatComparison::Equal,
+					value: 0x63,
+					count: 1,
+				},
+				ECheatOperation::Write {
+					address: 0x0200_0300,
+					width: EAccessWidth::Byte,
+					value: 0x78,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn action_replay_codes_are_decrypted_and_decoded() {
+		assert_eq!(
+			operations(ECheatFormat::ActionReplay, "B59DB70F 8CBC0C85"),
+			vec![ECheatOperation::Slide {
+				address: 0x0200_0100,
+				width: EAccessWidth::Halfword,
+				value: 0x3e7,
+				count: 1,
+				value_step: 0,
+				address_step: 2,
+			}]
+		);
+
+		// NOTE: The condition block ends at the end if
+		let code = "608DA09E 386D37D2\nA566EE33 C4D43E01\n1E27D83B BBFB6923";
+		assert_eq!(
+			operations(ECheatFormat::ActionReplay, code),
+			vec![
+				ECheatOperation::If {
+					address: 0x0300_0010,
+					width: EAccessWidth::Word,
+					comparison: ECheatComparison::Equal,
+					value: 5,
+					count: 1,
+				},
+				ECheatOperation::Write {
+					address: 0x0200_0400,
+					width: EAccessWidth::Word,
+					value: 0xffff,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn codebreaker_codes_are_decoded() {
+		let code = "82000100 03E7\nD0000020 0001\n32000200 0063\n42000100 0001\n00010003 0002";
+		assert_eq!(
+			operations(ECheatFormat::CodeBreaker, code),
+			vec![
+				ECheatOperation::Write {
+					address: 0x0200_0100,
+					width: EAccessWidth::Halfword,
+					value: 0x3e7,
+				},
+				ECheatOperation::If {
+					address: KEY_INPUT_ADDR,
+					width: EAccessWidth::Halfword,
+					comparison: ECheatComparison::NoneSet,
+					value: 1,
+					count: 1,
+				},
+				ECheatOperation::Write {
+					address: 0x0200_0200,
+					width: EAccessWidth::Byte,
+					value: 0x63,
+				},
+				ECheatOperation::Slide {
+					address: 0x0200_0100,
+					width: EAccessWidth::Halfword,
+					value: 1,
+					count: 3,
+					value_step: 1,
+					address_step: 2,
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn invalid_codes_are_rejected() {
+		assert!(Cheat::parse("Test", ECheatFormat::CodeBreaker, "").is_err());
+		assert!(Cheat::parse("Test", ECheatFormat::CodeBreaker, "82000100 03E").is_err());
+		assert!(Cheat::parse("Test", ECheatFormat::GameShark, "95771FDB 640BC1AZ").is_err());
+		assert!(Cheat::parse("Test", ECheatFormat::CodeBreaker, "92000100 03E7").is_err());
+	}
+}
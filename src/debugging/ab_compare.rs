@@ -0,0 +1,163 @@
+use crate::gba::Gba;
+use crate::system::cartridge::{Cartridge, ECartridgeType};
+use crate::system::{EInputLatching, ERamInit, KeyInput, SystemBus, RAM_INIT_SEED};
+
+/// Emulation option toggled on instance B, A keeps the settings of the frontend
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EAbOption {
+	InputLatching,
+	RamInit,
+}
+
+impl EAbOption {
+	pub const ALL: [EAbOption; 2] = [EAbOption::InputLatching, EAbOption::RamInit];
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			EAbOption::InputLatching => "Input Latching",
+			EAbOption::RamInit => "Initial RAM",
+		}
+	}
+
+	/// Value of the option on an instance
+	pub fn get_value_name(&self, gba: &Gba) -> &'static str {
+		match self {
+			EAbOption::InputLatching => match gba.bus.io_regs.get_input_latching() {
+				EInputLatching::Immediate => "Immediate",
+				EInputLatching::VBlank => "V-Blank",
+			},
+			EAbOption::RamInit => gba.bus.get_ram_init().get_name(),
+		}
+	}
+}
+
+/// Hash of a rendered frame (RGB triplets), channels are quantized to 8 bits first
+pub fn hash_frame(pixels: &[f32]) -> u32 {
+	let data: Vec<u8> = pixels.iter().map(|channel| (channel * 255.0).round() as u8).collect();
+	crc32fast::hash(&data)
+}
+
+/// Two emulator instances fed the same ROM and inputs, used to find which option changes the behavior of a game
+///
+/// Instance B is expected to differ from A by a single option, the first frame where their output diverges is recorded.
+pub struct AbComparison {
+	pub a: Gba,
+	pub b: Gba,
+	frame: u32,
+	divergent_frame: Option<u32>,
+	frames: (Vec<f32>, Vec<f32>),
+	hashes: (u32, u32),
+}
+
+impl AbComparison {
+	pub fn new(a: Gba, b: Gba) -> Self {
+		Self {
+			a,
+			b,
+			frame: 0,
+			divergent_frame: None,
+			frames: (Vec::new(), Vec::new()),
+			hashes: (0, 0),
+		}
+	}
+
+	/// Start A with the settings of the frontend and B with the same settings except the option, which is toggled
+	pub fn new_with_option(bios_data: &[u8], cartridge_data: &[u8], cartridge_type: ECartridgeType, input_latching: EInputLatching, ram_init: ERamInit, option: EAbOption) -> Self {
+		let new_gba = |input_latching: EInputLatching, ram_init: ERamInit| {
+			let cartridge = Cartridge::new_with_type(cartridge_data.to_vec().into_boxed_slice(), cartridge_type);
+			let mut gba = Gba::new_with_bus(SystemBus::new_with_ram_init(bios_data.to_vec().into_boxed_slice(), cartridge, ram_init));
+			gba.bus.io_regs.set_input_latching(input_latching);
+			gba
+		};
+
+		let (b_input_latching, b_ram_init) = match option {
+			EAbOption::InputLatching => match input_latching {
+				EInputLatching::Immediate => (EInputLatching::VBlank, ram_init),
+				EInputLatching::VBlank => (EInputLatching::Immediate, ram_init),
+			},
+			EAbOption::RamInit => match ram_init {
+				ERamInit::Zero => (input_latching, ERamInit::PseudoRandom(RAM_INIT_SEED)),
+				ERamInit::PseudoRandom(_) => (input_latching, ERamInit::Zero),
+			},
+		};
+
+		Self::new(new_gba(input_latching, ram_init), new_gba(b_input_latching, b_ram_init))
+	}
+
+	/// Run both instances for one frame with the same key state and compare their output
+	pub fn run_frame(&mut self, key_input: &KeyInput) {
+		for gba in [&mut self.a, &mut self.b].iter_mut() {
+			*gba.bus.io_regs.get_mut_key_input() = *key_input;
+			gba.run_frame();
+		}
+
+		self.frames = (self.a.render(), self.b.render());
+		self.hashes = (hash_frame(&self.frames.0), hash_frame(&self.frames.1));
+		self.frame += 1;
+
+		if self.divergent_frame.is_none() && self.hashes.0 != self.hashes.1 {
+			self.divergent_frame = Some(self.frame);
+		}
+	}
+
+	/// Number of frames run so far
+	pub fn get_frame(&self) -> u32 {
+		self.frame
+	}
+
+	/// First frame where the output of A and B differed
+	pub fn get_divergent_frame(&self) -> Option<u32> {
+		self.divergent_frame
+	}
+
+	/// Last rendered frames of A and B
+	pub fn get_frames(&self) -> (&[f32], &[f32]) {
+		(&self.frames.0, &self.frames.1)
+	}
+
+	/// Hashes of the last rendered frames of A and B
+	pub fn get_hashes(&self) -> (u32, u32) {
+		self.hashes
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::system::{MemoryInterface, IO_ADDR};
+
+	fn setup(option: EAbOption) -> AbComparison {
+		AbComparison::new_with_option(&[], &[0; 0x200], ECartridgeType::RomSram, EInputLatching::Immediate, ERamInit::Zero, option)
+	}
+
+	#[test]
+	fn input_latching_is_toggled_on_b_only() {
+		let comparison = setup(EAbOption::InputLatching);
+		assert_eq!(comparison.a.bus.io_regs.get_input_latching(), EInputLatching::Immediate);
+		assert_eq!(comparison.b.bus.io_regs.get_input_latching(), EInputLatching::VBlank);
+		assert_eq!(comparison.a.bus.get_ram_init(), comparison.b.bus.get_ram_init());
+		assert_eq!(EAbOption::InputLatching.get_value_name(&comparison.b), "V-Blank");
+	}
+
+	#[test]
+	fn ram_init_is_toggled_on_b_only() {
+		let comparison = setup(EAbOption::RamInit);
+		assert_eq!(comparison.a.bus.get_ram_init(), ERamInit::Zero);
+		assert_eq!(comparison.b.bus.get_ram_init(), ERamInit::PseudoRandom(RAM_INIT_SEED));
+		assert_eq!(comparison.a.bus.io_regs.get_input_latching(), comparison.b.bus.io_regs.get_input_latching());
+		assert_eq!(EAbOption::RamInit.get_value_name(&comparison.b), "Pseudo-Random");
+	}
+
+	#[test]
+	fn toggled_option_diverges() {
+		let mut comparison = setup(EAbOption::RamInit);
+		// NOTE: Mode 3 with BG2, VRAM is displayed as is
+		comparison.a.bus.write_16(IO_ADDR, 0x0403);
+		comparison.b.bus.write_16(IO_ADDR, 0x0403);
+		let key_input = *comparison.a.bus.io_regs.get_host_key_input();
+		comparison.run_frame(&key_input);
+		assert_eq!(comparison.get_frame(), 1);
+		// NOTE: The pseudo-random VRAM of B shows up in the frame, the zeroed VRAM of A doesn't
+		assert_eq!(comparison.get_divergent_frame(), Some(1));
+	}
+}
@@ -0,0 +1,898 @@
+use std::cmp::Reverse;
+
+use crate::debugging::disassembling::{disassemble_cond, DATA_PROCESSING_OPS, REGISTER_NAMES, THUMB_ALU_OPS};
+use crate::debugging::symbols::SymbolTable;
+
+const SHIFT_NAMES: [&str; 4] = ["LSL", "LSR", "ASR", "ROR"];
+
+/// ARM mnemonics and the suffixes they accept, the condition goes before or after the suffix (eg. "LDRHEQ" or "LDREQH")
+const ARM_MNEMONICS: [(&str, &[&str]); 36] = [
+	("AND", &["", "S"]),
+	("EOR", &["", "S"]),
+	("SUB", &["", "S"]),
+	("RSB", &["", "S"]),
+	("ADD", &["", "S"]),
+	("ADC", &["", "S"]),
+	("SBC", &["", "S"]),
+	("RSC", &["", "S"]),
+	("TST", &["", "S"]),
+	("TEQ", &["", "S"]),
+	("CMP", &["", "S"]),
+	("CMN", &["", "S"]),
+	("ORR", &["", "S"]),
+	("MOV", &["", "S"]),
+	("BIC", &["", "S"]),
+	("MVN", &["", "S"]),
+	("MUL", &["", "S"]),
+	("MLA", &["", "S"]),
+	("UMULL", &["", "S"]),
+	("UMLAL", &["", "S"]),
+	("SMULL", &["", "S"]),
+	("SMLAL", &["", "S"]),
+	("LDR", &["", "B", "T", "BT", "H", "SB", "SH"]),
+	("STR", &["", "B", "T", "BT", "H"]),
+	("LDM", &["IA", "IB", "DA", "DB", "FD", "ED", "FA", "EA"]),
+	("STM", &["IA", "IB", "DA", "DB", "FD", "ED", "FA", "EA"]),
+	("PUSH", &[""]),
+	("POP", &[""]),
+	("SWP", &["", "B"]),
+	("MRS", &[""]),
+	("MSR", &[""]),
+	("B", &[""]),
+	("BL", &[""]),
+	("BX", &[""]),
+	("SWI", &[""]),
+	("NOP", &[""]),
+];
+
+/// Offset of a load/store
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EOffset {
+	Immediate(i64),
+	/// Shift already encoded in bits 4-11
+	Register {
+		rm: u32,
+		subtract: bool,
+		shift: u32,
+	},
+}
+
+/// Memory operand of a load/store, eg. "[R1, #4]!" or "[R1], -R2"
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct MemoryOperand {
+	rn: u32,
+	offset: EOffset,
+	pre_indexed: bool,
+	write_back: bool,
+}
+
+/// Assemble one instruction to the bytes to write at an address, in the state the CPU runs it in
+/// Branch targets are symbols or hexadecimal addresses like in the Go to field, the other numbers are hexadecimal with a 0x prefix and decimal otherwise
+pub fn assemble(text: &str, address: u32, is_thumb: bool, symbols: &SymbolTable) -> Result<Vec<u8>, String> {
+	if is_thumb {
+		Ok(assemble_thumb(text, address, symbols)?.iter().flat_map(|halfword| halfword.to_le_bytes()).collect())
+	} else {
+		Ok(assemble_arm(text, address, symbols)?.to_le_bytes().to_vec())
+	}
+}
+
+/// Split the mnemonic (uppercased) from the operands, anything after a ';' is a comment
+fn split_instruction(text: &str) -> (String, Vec<String>) {
+	let text = text.split(';').next().unwrap_or_default().trim();
+	let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+	(mnemonic.to_uppercase(), split_operands(operands))
+}
+
+/// Split at the commas outside of brackets and braces
+fn split_operands(text: &str) -> Vec<String> {
+	let mut operands = Vec::new();
+	let mut depth = 0;
+	let mut start = 0;
+	for (i, c) in text.char_indices() {
+		match c {
+			'[' | '{' => depth += 1,
+			']' | '}' => depth -= 1,
+			',' if depth == 0 => {
+				operands.push(text[start..i].trim().to_string());
+				start = i + 1;
+			}
+			_ => {}
+		}
+	}
+	if !text[start..].trim().is_empty() {
+		operands.push(text[start..].trim().to_string());
+	}
+
+	operands
+}
+
+fn expect_operands(operands: &[String], count: usize) -> Result<(), String> {
+	if operands.len() != count {
+		return Err(format!("Expected {} operands, got {}", count, operands.len()));
+	}
+	Ok(())
+}
+
+fn parse_register(text: &str) -> Result<u32, String> {
+	let name = text.trim().to_uppercase();
+	let index = match name.as_str() {
+		"R13" => Some(13),
+		"R14" => Some(14),
+		"R15" => Some(15),
+		_ => REGISTER_NAMES.iter().position(|register| *register == name),
+	};
+	index.map(|index| index as u32).ok_or_else(|| format!("{} isn't a register", text))
+}
+
+/// R0-R7, the registers most THUMB instructions can use
+fn parse_low_register(text: &str) -> Result<u32, String> {
+	match parse_register(text)? {
+		register if register < 8 => Ok(register),
+		_ => Err(format!("{} isn't one of R0-R7", text)),
+	}
+}
+
+fn is_register(text: &str) -> bool {
+	parse_register(text).is_ok()
+}
+
+/// Number with an optional '#' and sign, hexadecimal with a 0x prefix and decimal otherwise
+fn parse_immediate(text: &str) -> Result<i64, String> {
+	let number = text.trim();
+	let number = number.strip_prefix('#').unwrap_or(number).trim();
+	let (negative, number) = match number.strip_prefix('-') {
+		Some(number) => (true, number),
+		None => (false, number.strip_prefix('+').unwrap_or(number)),
+	};
+	let value = match number.strip_prefix("0x").or_else(|| number.strip_prefix("0X")) {
+		Some(hex) => i64::from_str_radix(hex, 16),
+		None => number.parse(),
+	}
+	.map_err(|_| format!("{} isn't a number", text))?;
+
+	Ok(if negative { -value } else { value })
+}
+
+fn parse_unsigned(text: &str, max: i64) -> Result<u32, String> {
+	match parse_immediate(text)? {
+		value if (0..=max).contains(&value) => Ok(value as u32),
+		_ => Err(format!("{} is out of range (0-{:#X})", text, max)),
+	}
+}
+
+/// Immediate of a THUMB instruction, multiple of scale up to max
+fn parse_scaled(text: &str, scale: i64, max: i64) -> Result<u32, String> {
+	let value = parse_unsigned(text, max)?;
+	if value as i64 % scale != 0 {
+		return Err(format!("{} isn't a multiple of {}", text, scale));
+	}
+	Ok(value / scale as u32)
+}
+
+/// Registers of a LDM/STM/PUSH/POP, eg. "{ R0-R3, LR }"
+fn parse_register_list(text: &str) -> Result<u16, String> {
+	let list = text
+		.trim()
+		.strip_prefix('{')
+		.and_then(|list| list.strip_suffix('}'))
+		.ok_or_else(|| format!("{} isn't a register list", text))?;
+
+	let mut registers = 0u16;
+	for item in list.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+		let (first, last) = match item.split_once('-') {
+			Some((first, last)) => (parse_register(first)?, parse_register(last)?),
+			None => (parse_register(item)?, parse_register(item)?),
+		};
+		if first > last {
+			return Err(format!("{} isn't a valid register range", item));
+		}
+		for register in first..=last {
+			registers |= 1 << register;
+		}
+	}
+
+	if registers == 0 {
+		return Err("Empty register list".to_string());
+	}
+	Ok(registers)
+}
+
+/// Jump target of a branch, a symbol or an address, eg. "main", "0x08000120" or "main+0x10"
+fn parse_target(text: &str, symbols: &SymbolTable) -> Result<u32, String> {
+	let target = text.trim();
+	symbols
+		.resolve(target.strip_prefix('#').unwrap_or(target))
+		.ok_or_else(|| format!("{} isn't a symbol or an address", text))
+}
+
+/// Offset from the PC (address + 8 in ARM, + 4 in THUMB) to a branch target, checked for the alignment and the range of the instruction
+fn branch_offset(address: u32, target: u32, is_thumb: bool, bits: u32) -> Result<i32, String> {
+	let (pc, alignment) = if is_thumb { (address.wrapping_add(4), 2) } else { (address.wrapping_add(8), 4) };
+	let offset = target.wrapping_sub(pc) as i32;
+	if offset % alignment != 0 {
+		return Err(format!("{:#010X} isn't aligned to {} bytes", target, alignment));
+	}
+
+	let range = alignment << (bits - 1);
+	if offset < -range || offset >= range {
+		return Err(format!("{:#010X} is out of range of the branch", target));
+	}
+	Ok(offset)
+}
+
+/// Condition of a mnemonic suffix, "" is always
+fn parse_cond(text: &str) -> Option<u32> {
+	match text {
+		"" | "AL" => Some(0xe),
+		"HS" => Some(0x2),
+		"LO" => Some(0x3),
+		_ => (0..0xe).find(|cond| disassemble_cond(*cond as u8) == text),
+	}
+}
+
+/// Split an ARM mnemonic in its base, suffix and condition, eg. "LDREQH" is ("LDR", "H", 0x0)
+/// NOTE: The longest base is tried first, "BLS" isn't "BL" with an "S" but "B" with the LS condition
+fn parse_arm_mnemonic(mnemonic: &str) -> Option<(&'static str, &'static str, u32)> {
+	let mut candidates: Vec<&(&str, &[&str])> = ARM_MNEMONICS.iter().filter(|(base, _)| mnemonic.starts_with(base)).collect();
+	candidates.sort_by_key(|(base, _)| Reverse(base.len()));
+
+	for (base, suffixes) in candidates {
+		let rest = &mnemonic[base.len()..];
+		for suffix in suffixes.iter() {
+			let cond = rest.strip_prefix(suffix).and_then(parse_cond).or_else(|| rest.strip_suffix(suffix).and_then(parse_cond));
+			if let Some(cond) = cond {
+				return Some((base, suffix, cond));
+			}
+		}
+	}
+
+	None
+}
+
+/// Encode an ARM immediate as an 8 bit value rotated right by an even amount
+fn encode_arm_immediate(value: u32) -> Option<u32> {
+	(0..16).find_map(|rotation| {
+		let immediate = value.rotate_left(rotation * 2);
+		(immediate <= 0xff).then_some(rotation << 8 | immediate)
+	})
+}
+
+/// Shift of a register operand, eg. "LSL #2", "ASR R3" or "RRX", encoded in bits 4-11
+fn encode_shift(text: Option<&String>, allow_register: bool) -> Result<u32, String> {
+	let text = match text {
+		Some(text) => text.trim().to_uppercase(),
+		None => return Ok(0),
+	};
+	if text == "RRX" {
+		return Ok(3 << 5);
+	}
+
+	let (name, amount) = text.split_once(char::is_whitespace).ok_or_else(|| format!("{} isn't a shift", text))?;
+	let shift_type = match name {
+		"ASL" => 0,
+		name => SHIFT_NAMES.iter().position(|shift| *shift == name).ok_or_else(|| format!("{} isn't a shift", name))? as u32,
+	};
+	if is_register(amount) {
+		if !allow_register {
+			return Err("Register shifts aren't allowed here".to_string());
+		}
+		return Ok(parse_register(amount)? << 8 | shift_type << 5 | 1 << 4);
+	}
+
+	// NOTE: LSR/ASR #32 are encoded as 0, LSL #0 is no shift, ROR #0 would be RRX
+	let amount = match (shift_type, parse_immediate(amount)?) {
+		(0, amount @ 0..=31) | (3, amount @ 1..=31) | (1..=2, amount @ 1..=31) => amount as u32,
+		(1..=2, 32) => 0,
+		_ => return Err(format!("{} is out of range of the shift", amount)),
+	};
+	Ok(amount << 7 | shift_type << 5)
+}
+
+/// Register or immediate offset of a load/store, the register one can be subtracted and shifted
+fn parse_offset(operands: &[String]) -> Result<EOffset, String> {
+	let first = operands[0].trim();
+	if first.starts_with('#') {
+		if operands.len() > 1 {
+			return Err("Immediate offsets can't be shifted".to_string());
+		}
+		return Ok(EOffset::Immediate(parse_immediate(first)?));
+	}
+
+	let (subtract, register) = match first.strip_prefix('-') {
+		Some(register) => (true, register),
+		None => (false, first.strip_prefix('+').unwrap_or(first)),
+	};
+	if operands.len() > 2 {
+		return Err(format!("Too many operands in the offset {}", operands.join(", ")));
+	}
+	Ok(EOffset::Register {
+		rm: parse_register(register)?,
+		subtract,
+		shift: encode_shift(operands.get(1), false)?,
+	})
+}
+
+/// Parse "[Rn]", "[Rn, offset]", "[Rn, offset]!" or "[Rn], offset", the operands after the transferred register
+fn parse_memory_operand(operands: &[String]) -> Result<MemoryOperand, String> {
+	let first = operands.first().ok_or("Missing address")?.trim();
+	let close = first.find(']').filter(|_| first.starts_with('[')).ok_or_else(|| format!("{} isn't an address", first))?;
+	let write_back = match first[close + 1..].trim() {
+		"" => false,
+		"!" => true,
+		_ => return Err(format!("{} isn't an address", first)),
+	};
+
+	let inner = split_operands(&first[1..close]);
+	let rn = parse_register(inner.first().ok_or("Missing base register")?)?;
+	if inner.len() > 1 {
+		if operands.len() > 1 {
+			return Err("Pre-indexed addresses can't have a second offset".to_string());
+		}
+		return Ok(MemoryOperand {
+			rn,
+			offset: parse_offset(&inner[1..])?,
+			pre_indexed: true,
+			write_back,
+		});
+	}
+
+	if operands.len() > 1 {
+		if write_back {
+			return Err("Post-indexed addresses always write back, '!' isn't allowed".to_string());
+		}
+		return Ok(MemoryOperand {
+			rn,
+			offset: parse_offset(&operands[1..])?,
+			pre_indexed: false,
+			write_back: false,
+		});
+	}
+
+	Ok(MemoryOperand {
+		rn,
+		offset: EOffset::Immediate(0),
+		pre_indexed: true,
+		write_back,
+	})
+}
+
+/// Assemble an ARM instruction, eg. "MOVEQ R0, #1" or "LDRH R0, [R1, #2]!"
+pub fn assemble_arm(text: &str, address: u32, symbols: &SymbolTable) -> Result<u32, String> {
+	let (mnemonic, operands) = split_instruction(text);
+	let (base, suffix, cond) = parse_arm_mnemonic(&mnemonic).ok_or_else(|| format!("Unknown ARM instruction {}", mnemonic))?;
+	let cond = cond << 28;
+	let s = if suffix == "S" { 1 << 20 } else { 0 };
+
+	match base {
+		"NOP" => {
+			expect_operands(&operands, 0)?;
+			Ok(cond | 0x01a0_0000)
+		}
+		"B" | "BL" => {
+			expect_operands(&operands, 1)?;
+			let offset = branch_offset(address, parse_target(&operands[0], symbols)?, false, 24)?;
+			let link = if base == "BL" { 1 << 24 } else { 0 };
+			Ok(cond | 0x0a00_0000 | link | ((offset >> 2) as u32 & 0x00ff_ffff))
+		}
+		"BX" => {
+			expect_operands(&operands, 1)?;
+			Ok(cond | 0x012f_ff10 | parse_register(&operands[0])?)
+		}
+		"SWI" => {
+			expect_operands(&operands, 1)?;
+			Ok(cond | 0x0f00_0000 | parse_unsigned(&operands[0], 0x00ff_ffff)?)
+		}
+		"SWP" => {
+			expect_operands(&operands, 3)?;
+			let rn = operands[2]
+				.trim()
+				.strip_prefix('[')
+				.and_then(|rn| rn.strip_suffix(']'))
+				.ok_or_else(|| format!("{} isn't an address", operands[2]))?;
+			let b = if suffix == "B" { 1 << 22 } else { 0 };
+			Ok(cond | 0x0100_0090 | b | parse_register(rn)? << 16 | parse_register(&operands[0])? << 12 | parse_register(&operands[1])?)
+		}
+		"MRS" => {
+			expect_operands(&operands, 2)?;
+			let spsr = match operands[1].to_uppercase().as_str() {
+				"CPSR" => 0,
+				"SPSR" => 1 << 22,
+				_ => return Err(format!("{} isn't CPSR or SPSR", operands[1])),
+			};
+			Ok(cond | 0x010f_0000 | spsr | parse_register(&operands[0])? << 12)
+		}
+		"MSR" => assemble_msr(cond, &operands),
+		"MUL" | "MLA" => {
+			expect_operands(&operands, if base == "MLA" { 4 } else { 3 })?;
+			let registers = operands.iter().map(|register| parse_register(register)).collect::<Result<Vec<u32>, String>>()?;
+			let accumulate = if base == "MLA" { 1 << 21 } else { 0 };
+			let rn = registers.get(3).cloned().unwrap_or_default();
+			Ok(cond | accumulate | s | registers[0] << 16 | rn << 12 | registers[2] << 8 | 0x90 | registers[1])
+		}
+		"UMULL" | "UMLAL" | "SMULL" | "SMLAL" => {
+			expect_operands(&operands, 4)?;
+			let registers = operands.iter().map(|register| parse_register(register)).collect::<Result<Vec<u32>, String>>()?;
+			let op = ["UMULL", "UMLAL", "SMULL", "SMLAL"].iter().position(|op| *op == base).unwrap_or_default() as u32;
+			Ok(cond | (0x4 | op) << 21 | s | registers[1] << 16 | registers[0] << 12 | registers[3] << 8 | 0x90 | registers[2])
+		}
+		"LDR" | "STR" => assemble_arm_load_store(cond, base == "LDR", suffix, &operands),
+		"LDM" | "STM" | "PUSH" | "POP" => assemble_arm_block_transfer(cond, base, suffix, &operands),
+		_ => assemble_data_processing(cond, base, s, &operands),
+	}
+}
+
+fn assemble_msr(cond: u32, operands: &[String]) -> Result<u32, String> {
+	expect_operands(operands, 2)?;
+	let destination = operands[0].to_uppercase();
+	let (psr, fields) = destination.split_once('_').unwrap_or((&destination, ""));
+	let spsr = match psr {
+		"CPSR" => 0,
+		"SPSR" => 1 << 22,
+		_ => return Err(format!("{} isn't CPSR or SPSR", psr)),
+	};
+
+	// NOTE: No fields is the flags and the control bits, like the old "_all"
+	let mask = match fields {
+		"" | "ALL" => 0x9,
+		"FLG" => 0x8,
+		"CTL" => 0x1,
+		fields => fields.chars().try_fold(0, |mask, field| match field {
+			'F' => Ok(mask | 0x8),
+			'S' => Ok(mask | 0x4),
+			'X' => Ok(mask | 0x2),
+			'C' => Ok(mask | 0x1),
+			_ => Err(format!("{} isn't a PSR field", field)),
+		})?,
+	};
+
+	let source = if is_register(&operands[1]) {
+		parse_register(&operands[1])?
+	} else {
+		let value = parse_immediate(&operands[1])? as u32;
+		1 << 25 | encode_arm_immediate(value).ok_or_else(|| format!("{:#X} can't be encoded as an immediate", value))?
+	};
+	Ok(cond | 0x0120_f000 | spsr | mask << 16 | source)
+}
+
+fn assemble_data_processing(cond: u32, base: &str, s: u32, operands: &[String]) -> Result<u32, String> {
+	let mut opcode = DATA_PROCESSING_OPS.iter().position(|op| *op == base).unwrap_or_default() as u32;
+	// NOTE: The comparisons have no destination, MOV/MVN no first operand, "ADD R0, #1" is short for "ADD R0, R0, #1"
+	let (rd, rn, operand_2) = match opcode {
+		0x8..=0xb if operands.len() >= 2 => (0, parse_register(&operands[0])?, &operands[1..]),
+		0xd | 0xf if operands.len() >= 2 => (parse_register(&operands[0])?, 0, &operands[1..]),
+		0x8..=0xb | 0xd | 0xf => return Err(format!("Expected at least 2 operands, got {}", operands.len())),
+		_ if operands.len() == 2 => (parse_register(&operands[0])?, parse_register(&operands[0])?, &operands[1..]),
+		_ if operands.len() >= 3 => (parse_register(&operands[0])?, parse_register(&operands[1])?, &operands[2..]),
+		_ => return Err(format!("Expected at least 2 operands, got {}", operands.len())),
+	};
+	let s = if (0x8..=0xb).contains(&opcode) { 1 << 20 } else { s };
+
+	let operand_2 = if is_register(&operand_2[0]) {
+		if operand_2.len() > 2 {
+			return Err(format!("Too many operands in {}", operand_2.join(", ")));
+		}
+		parse_register(&operand_2[0])? | encode_shift(operand_2.get(1), true)?
+	} else {
+		if operand_2.len() > 1 {
+			return Err("Immediates can't be shifted".to_string());
+		}
+		let value = parse_immediate(&operand_2[0])? as u32;
+		// NOTE: Immediates that can't be encoded can be with the complementary instruction, eg. "MOV R0, #-1" is "MVN R0, #0"
+		let alternative = match opcode {
+			0x0 => Some((0xe, !value)),
+			0xe => Some((0x0, !value)),
+			0x2 => Some((0x4, value.wrapping_neg())),
+			0x4 => Some((0x2, value.wrapping_neg())),
+			0x5 => Some((0x6, !value)),
+			0x6 => Some((0x5, !value)),
+			0xa => Some((0xb, value.wrapping_neg())),
+			0xb => Some((0xa, value.wrapping_neg())),
+			0xd => Some((0xf, !value)),
+			0xf => Some((0xd, !value)),
+			_ => None,
+		};
+		let immediate = match encode_arm_immediate(value) {
+			Some(immediate) => immediate,
+			None => {
+				let (alternative_opcode, immediate) = alternative
+					.and_then(|(alternative_opcode, value)| Some((alternative_opcode, encode_arm_immediate(value)?)))
+					.ok_or_else(|| format!("{:#X} can't be encoded as an immediate", value))?;
+				opcode = alternative_opcode;
+				immediate
+			}
+		};
+		1 << 25 | immediate
+	};
+
+	Ok(cond | opcode << 21 | s | rn << 16 | rd << 12 | operand_2)
+}
+
+fn assemble_arm_load_store(cond: u32, load: bool, suffix: &str, operands: &[String]) -> Result<u32, String> {
+	if operands.len() < 2 {
+		return Err(format!("Expected at least 2 operands, got {}", operands.len()));
+	}
+	let rd = parse_register(&operands[0])?;
+	let memory = parse_memory_operand(&operands[1..])?;
+	let l = if load { 1 << 20 } else { 0 };
+	let p = if memory.pre_indexed { 1 << 24 } else { 0 };
+	let w = if memory.write_back { 1 << 21 } else { 0 };
+
+	if let Some(sh) = ["H", "SB", "SH"].iter().position(|op| *op == suffix) {
+		let offset = match memory.offset {
+			EOffset::Immediate(value) if value.abs() <= 0xff => {
+				let magnitude = value.unsigned_abs() as u32;
+				let u = if value >= 0 { 1 << 23 } else { 0 };
+				u | 1 << 22 | (magnitude & 0xf0) << 4 | (magnitude & 0xf)
+			}
+			EOffset::Immediate(value) => return Err(format!("{:#X} is out of range of the offset (-0xFF-0xFF)", value)),
+			EOffset::Register { rm, subtract, shift: 0 } => (if subtract { 0 } else { 1 << 23 }) | rm,
+			EOffset::Register { .. } => return Err("Halfword offsets can't be shifted".to_string()),
+		};
+		return Ok(cond | p | w | l | memory.rn << 16 | rd << 12 | 0x90 | (sh as u32 + 1) << 5 | offset);
+	}
+
+	// NOTE: The T variants always write back, they're post-indexed only
+	let translate = suffix.contains('T');
+	if translate && memory.pre_indexed {
+		return Err("LDRT/STRT only take post-indexed addresses".to_string());
+	}
+	let w = if translate { 1 << 21 } else { w };
+	let b = if suffix.contains('B') { 1 << 22 } else { 0 };
+	let offset = match memory.offset {
+		EOffset::Immediate(value) if value.abs() <= 0xfff => (if value >= 0 { 1 << 23 } else { 0 }) | value.unsigned_abs() as u32,
+		EOffset::Immediate(value) => return Err(format!("{:#X} is out of range of the offset (-0xFFF-0xFFF)", value)),
+		EOffset::Register { rm, subtract, shift } => 1 << 25 | (if subtract { 0 } else { 1 << 23 }) | shift | rm,
+	};
+	Ok(cond | 0x0400_0000 | p | b | w | l | memory.rn << 16 | rd << 12 | offset)
+}
+
+fn assemble_arm_block_transfer(cond: u32, base: &str, suffix: &str, operands: &[String]) -> Result<u32, String> {
+	// NOTE: PUSH/POP are STMDB SP!/LDMIA SP!
+	let (load, mode, rn, write_back, list) = match base {
+		"PUSH" | "POP" => {
+			expect_operands(operands, 1)?;
+			let load = base == "POP";
+			(load, if load { "IA" } else { "DB" }, 13, true, operands[0].as_str())
+		}
+		_ => {
+			expect_operands(operands, 2)?;
+			let rn = operands[0].trim();
+			let (rn, write_back) = match rn.strip_suffix('!') {
+				Some(rn) => (rn, true),
+				None => (rn, false),
+			};
+			(base == "LDM", suffix, parse_register(rn)?, write_back, operands[1].as_str())
+		}
+	};
+
+	// NOTE: The stack modes (full/empty, descending/ascending) are the opposite addressing modes for loads and stores
+	let mode = match (mode, load) {
+		("FD", true) | ("EA", false) => "IA",
+		("ED", true) | ("FA", false) => "IB",
+		("FA", true) | ("ED", false) => "DA",
+		("EA", true) | ("FD", false) => "DB",
+		(mode, _) => mode,
+	};
+	let (p, u) = match mode {
+		"IA" => (0, 1),
+		"IB" => (1, 1),
+		"DA" => (0, 0),
+		_ => (1, 0),
+	};
+
+	let list = list.trim();
+	let (list, user_bank) = match list.strip_suffix('^') {
+		Some(list) => (list, 1 << 22),
+		None => (list, 0),
+	};
+	let l = if load { 1 << 20 } else { 0 };
+	let w = if write_back { 1 << 21 } else { 0 };
+	Ok(cond | 0x0800_0000 | p << 24 | u << 23 | user_bank | w | l | rn << 16 | parse_register_list(list)? as u32)
+}
+
+/// Assemble a THUMB instruction, eg. "ADD R0, #1" or "BL 0x08000120", a BL is two halfwords
+pub fn assemble_thumb(text: &str, address: u32, symbols: &SymbolTable) -> Result<Vec<u16>, String> {
+	let (mnemonic, operands) = split_instruction(text);
+	let mnemonic = mnemonic.as_str();
+	let instruction = match mnemonic {
+		"NOP" => {
+			expect_operands(&operands, 0)?;
+			// NOTE: MOV R8, R8
+			0x46c0
+		}
+		"BL" => {
+			expect_operands(&operands, 1)?;
+			let offset = branch_offset(address, parse_target(&operands[0], symbols)?, true, 22)?;
+			return Ok(vec![0xf000 | ((offset >> 12) as u16 & 0x07ff), 0xf800 | ((offset >> 1) as u16 & 0x07ff)]);
+		}
+		"B" => {
+			expect_operands(&operands, 1)?;
+			let offset = branch_offset(address, parse_target(&operands[0], symbols)?, true, 11)?;
+			0xe000 | ((offset >> 1) as u16 & 0x07ff)
+		}
+		"BX" => {
+			expect_operands(&operands, 1)?;
+			0x4700 | (parse_register(&operands[0])? << 3) as u16
+		}
+		"SWI" => {
+			expect_operands(&operands, 1)?;
+			0xdf00 | parse_unsigned(&operands[0], 0xff)? as u16
+		}
+		"PUSH" | "POP" => {
+			expect_operands(&operands, 1)?;
+			let pop = mnemonic == "POP";
+			let list = parse_register_list(&operands[0])?;
+			let extra = if pop { 1 << 15 } else { 1 << 14 };
+			if list & !(0xff | extra) != 0 {
+				return Err(format!("{} can only take R0-R7 and {}", mnemonic, if pop { "PC" } else { "LR" }));
+			}
+			let r = if list & extra != 0 { 0x0100 } else { 0 };
+			(if pop { 0xbc00 } else { 0xb400 }) | r | (list & 0xff)
+		}
+		"LDMIA" | "STMIA" | "LDM" | "STM" => {
+			expect_operands(&operands, 2)?;
+			let rb = operands[0].trim().strip_suffix('!').ok_or("THUMB LDMIA/STMIA always write back, the base needs a '!'")?;
+			let list = parse_register_list(&operands[1])?;
+			if list > 0xff {
+				return Err("THUMB LDMIA/STMIA can only take R0-R7".to_string());
+			}
+			let l = if mnemonic.starts_with("LDM") { 0x0800 } else { 0 };
+			0xc000 | l | (parse_low_register(rb)? << 8) as u16 | list
+		}
+		"LDR" | "STR" | "LDRB" | "STRB" | "LDRH" | "STRH" | "LDRSB" | "LDRSH" | "LDSB" | "LDSH" => assemble_thumb_load_store(mnemonic, &operands)?,
+		"ADD" | "SUB" => assemble_thumb_add_sub(mnemonic == "SUB", &operands)?,
+		"MOV" | "CMP" if operands.len() == 2 && !is_register(&operands[1]) => {
+			let op = if mnemonic == "MOV" { 0 } else { 1 };
+			0x2000 | op << 11 | (parse_low_register(&operands[0])? << 8) as u16 | parse_unsigned(&operands[1], 0xff)? as u16
+		}
+		"MOV" | "CMP" => {
+			expect_operands(&operands, 2)?;
+			let rd = parse_register(&operands[0])?;
+			let rs = parse_register(&operands[1])?;
+			match (mnemonic, rd < 8 && rs < 8) {
+				// NOTE: THUMB has no MOV between low registers, assemblers use ADD Rd, Rs, #0
+				("MOV", true) => 0x1c00 | (rs << 3 | rd) as u16,
+				("CMP", true) => 0x4280 | (rs << 3 | rd) as u16,
+				_ => hi_register_op(if mnemonic == "MOV" { 2 } else { 1 }, rd, rs),
+			}
+		}
+		"LSL" | "LSR" | "ASR" if operands.len() == 3 => {
+			let op = SHIFT_NAMES.iter().position(|shift| *shift == mnemonic).unwrap_or_default() as u16;
+			let shift = match (op, parse_immediate(&operands[2])?) {
+				(0, shift @ 0..=31) | (1..=2, shift @ 1..=31) => shift as u16,
+				(1..=2, 32) => 0,
+				_ => return Err(format!("{} is out of range of the shift", operands[2])),
+			};
+			op << 11 | shift << 6 | (parse_low_register(&operands[1])? << 3 | parse_low_register(&operands[0])?) as u16
+		}
+		_ if mnemonic.len() == 3 && mnemonic.starts_with('B') && parse_cond(&mnemonic[1..]).filter(|cond| *cond < 0xe).is_some() => {
+			expect_operands(&operands, 1)?;
+			let cond = parse_cond(&mnemonic[1..]).unwrap_or_default() as u16;
+			let offset = branch_offset(address, parse_target(&operands[0], symbols)?, true, 8)?;
+			0xd000 | cond << 8 | ((offset >> 1) as u16 & 0x00ff)
+		}
+		_ => {
+			let op = THUMB_ALU_OPS
+				.iter()
+				.position(|op| *op == mnemonic)
+				.ok_or_else(|| format!("Unknown THUMB instruction {}", mnemonic))?;
+			expect_operands(&operands, 2)?;
+			0x4000 | (op as u16) << 6 | (parse_low_register(&operands[1])? << 3 | parse_low_register(&operands[0])?) as u16
+		}
+	};
+
+	Ok(vec![instruction])
+}
+
+/// ADD/CMP/MOV with a high register
+fn hi_register_op(op: u16, rd: u32, rs: u32) -> u16 {
+	0x4400 | op << 8 | ((rd & 0x8) << 4 | (rs << 3) | (rd & 0x7)) as u16
+}
+
+fn assemble_thumb_add_sub(subtract: bool, operands: &[String]) -> Result<u16, String> {
+	let sub = if subtract { 1 } else { 0 };
+	match operands.len() {
+		3 => {
+			let rd = parse_low_register(&operands[0])?;
+			// NOTE: "ADD Rd, PC/SP, #imm" computes an address relative to the PC or the SP
+			match parse_register(&operands[1])? {
+				13 if !subtract => return Ok(0xa800 | (rd << 8) as u16 | parse_scaled(&operands[2], 4, 0x3fc)? as u16),
+				15 if !subtract => return Ok(0xa000 | (rd << 8) as u16 | parse_scaled(&operands[2], 4, 0x3fc)? as u16),
+				_ => {}
+			}
+
+			let rs = parse_low_register(&operands[1])?;
+			let (immediate, rn) = if is_register(&operands[2]) {
+				(0, parse_low_register(&operands[2])?)
+			} else {
+				(1, parse_unsigned(&operands[2], 0x7)?)
+			};
+			Ok(0x1800 | immediate << 10 | sub << 9 | (rn << 6 | rs << 3 | rd) as u16)
+		}
+		2 => {
+			let rd = parse_register(&operands[0])?;
+			if rd == 13 && !is_register(&operands[1]) {
+				let value = parse_immediate(&operands[1])?;
+				let negative = (value < 0) != subtract;
+				let offset = parse_scaled(&format!("{}", value.abs()), 4, 0x1fc)?;
+				return Ok(0xb000 | if negative { 0x0080 } else { 0 } | offset as u16);
+			}
+
+			if !is_register(&operands[1]) {
+				return Ok(0x3000 | sub << 11 | (parse_low_register(&operands[0])? << 8) as u16 | parse_unsigned(&operands[1], 0xff)? as u16);
+			}
+
+			let rs = parse_register(&operands[1])?;
+			if rd < 8 && rs < 8 {
+				Ok(0x1800 | sub << 9 | (rs << 6 | rd << 3 | rd) as u16)
+			} else if !subtract {
+				Ok(hi_register_op(0, rd, rs))
+			} else {
+				Err("THUMB SUB can only take R0-R7".to_string())
+			}
+		}
+		count => Err(format!("Expected 2 or 3 operands, got {}", count)),
+	}
+}
+
+fn assemble_thumb_load_store(mnemonic: &str, operands: &[String]) -> Result<u16, String> {
+	expect_operands(operands, 2)?;
+	let memory = parse_memory_operand(&operands[1..])?;
+	if !memory.pre_indexed || memory.write_back {
+		return Err("THUMB loads and stores can't write back".to_string());
+	}
+
+	let rd = parse_low_register(&operands[0])?;
+	match memory.offset {
+		EOffset::Register { rm, subtract: false, shift: 0 } => {
+			let op = match mnemonic {
+				"STR" => 0x5000,
+				"STRH" => 0x5200,
+				"STRB" => 0x5400,
+				"LDRSB" | "LDSB" => 0x5600,
+				"LDR" => 0x5800,
+				"LDRH" => 0x5a00,
+				"LDRB" => 0x5c00,
+				_ => 0x5e00,
+			};
+			if rm > 7 || memory.rn > 7 {
+				return Err("THUMB register offsets can only take R0-R7".to_string());
+			}
+			Ok(op | (rm << 6 | memory.rn << 3 | rd) as u16)
+		}
+		EOffset::Register { .. } => Err("THUMB register offsets can't be subtracted or shifted".to_string()),
+		EOffset::Immediate(offset) => {
+			let offset = format!("{}", offset);
+			match (mnemonic, memory.rn) {
+				("LDR", 15) => Ok(0x4800 | (rd << 8) as u16 | parse_scaled(&offset, 4, 0x3fc)? as u16),
+				("LDR", 13) | ("STR", 13) => {
+					let l = if mnemonic == "LDR" { 0x0800 } else { 0 };
+					Ok(0x9000 | l | (rd << 8) as u16 | parse_scaled(&offset, 4, 0x3fc)? as u16)
+				}
+				(_, rn) if rn > 7 => Err("THUMB immediate offsets can only take R0-R7, SP or PC (LDR)".to_string()),
+				_ => {
+					let (op, scale) = match mnemonic {
+						"STR" => (0x6000, 4),
+						"LDR" => (0x6800, 4),
+						"STRB" => (0x7000, 1),
+						"LDRB" => (0x7800, 1),
+						"STRH" => (0x8000, 2),
+						"LDRH" => (0x8800, 2),
+						_ => return Err(format!("THUMB {} only takes register offsets", mnemonic)),
+					};
+					let offset = parse_scaled(&offset, scale, 31 * scale)?;
+					Ok(op | (offset << 6 | memory.rn << 3 | rd) as u16)
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::debugging::disassembling::{disassemble_arm, disassemble_thumb};
+
+	const ADDRESS: u32 = 0x0800_0000;
+
+	/// One instruction of each ARM format
+	const ARM_OPCODES: [u32; 30] = [
+		0xe281_0010, // ADD R0, R1, #0x10
+		0xe1b0_2203, // MOVS R2, R3, LSL #4
+		0xe185_4736, // ORR R4, R5, R6, LSR R7
+		0x0350_0001, // CMPEQ R0, #1
+		0xe10f_0000, // MRS R0, CPSR
+		0xe14f_0000, // MRS R0, SPSR
+		0xe129_f001, // MSR CPSR_fc, R1
+		0xe328_f20f, // MSR CPSR_f, #0xF0000000
+		0xe000_0291, // MUL R0, R1, R2
+		0xe033_6594, // MLAS R3, R4, R5, R6
+		0xe081_0392, // UMULL R0, R1, R2, R3
+		0xe0e5_4796, // SMLAL R4, R5, R6, R7
+		0xe102_0091, // SWP R0, R1, [R2]
+		0xe142_0091, // SWPB R0, R1, [R2]
+		0xe591_0004, // LDR R0, [R1, #4]
+		0xe523_2008, // STR R2, [R3, #-8]!
+		0xe4d5_4001, // LDRB R4, [R5], #1
+		0xe797_6108, // LDR R6, [R7, R8, LSL #2]
+		0xe1d1_00b2, // LDRH R0, [R1, #2]
+		0xe003_20b4, // STRH R2, [R3], -R4
+		0xe156_50d1, // LDRSB R5, [R6, #-1]
+		0xe190_70f1, // LDRSH R7, [R0, R1]
+		0xe8b0_000e, // LDMIA R0!, {R1-R3}
+		0xe92d_4010, // STMDB SP!, {R4, LR}
+		0xe8bd_8010, // LDMIA SP!, {R4, PC}
+		0xea00_003e, // B 0x08000100
+		0x1b00_003e, // BLNE 0x08000100
+		0xe12f_ff1e, // BX LR
+		0xef06_0000, // SWI #0x60000
+		0xe1a0_0000, // MOV R0, R0
+	];
+
+	/// One instruction of each THUMB format, BL is both of its halves
+	const THUMB_OPCODES: [u32; 42] = [
+		0x0088,      // LSL R0, R1, #2
+		0x081a,      // LSR R2, R3, #32
+		0x1888,      // ADD R0, R1, R2
+		0x1e63,      // SUB R3, R4, #1
+		0x20ff,      // MOV R0, #0xFF
+		0x2901,      // CMP R1, #1
+		0x3201,      // ADD R2, #1
+		0x3b01,      // SUB R3, #1
+		0x4008,      // AND R0, R1
+		0x435a,      // MUL R2, R3
+		0x426c,      // NEG R4, R5
+		0x4480,      // ADD R8, R0
+		0x4548,      // CMP R0, R9
+		0x46f7,      // MOV PC, LR
+		0x4770,      // BX LR
+		0x4801,      // LDR R0, [PC, #4]
+		0x5088,      // STR R0, [R1, R2]
+		0x5d63,      // LDRB R3, [R4, R5]
+		0x5288,      // STRH R0, [R1, R2]
+		0x5688,      // LDRSB R0, [R1, R2]
+		0x5a88,      // LDRH R0, [R1, R2]
+		0x5e88,      // LDRSH R0, [R1, R2]
+		0x6048,      // STR R0, [R1, #4]
+		0x795a,      // LDRB R2, [R3, #5]
+		0x8048,      // STRH R0, [R1, #2]
+		0x88c8,      // LDRH R0, [R1, #6]
+		0x9002,      // STR R0, [SP, #8]
+		0x9901,      // LDR R1, [SP, #4]
+		0xa002,      // ADD R0, PC, #8
+		0xa901,      // ADD R1, SP, #4
+		0xb004,      // ADD SP, #0x10
+		0xb084,      // SUB SP, #0x10
+		0xb510,      // PUSH {R4, LR}
+		0xbd10,      // POP {R4, PC}
+		0xc006,      // STMIA R0!, {R1, R2}
+		0xcb01,      // LDMIA R3!, {R0}
+		0xd006,      // BEQ 0x08000010
+		0xdf05,      // SWI #5
+		0xe006,      // B 0x08000010
+		0xf808_f000, // BL 0x08000014
+		0xe7fe,      // B 0x08000000
+		0xd1fd,      // BNE 0x07FFFFFE
+	];
+
+	#[test]
+	fn arm_instructions_assemble_back_to_their_opcode() {
+		for opcode in ARM_OPCODES {
+			let text = disassemble_arm(ADDRESS, opcode).to_string();
+			assert_eq!(assemble_arm(&text, ADDRESS, &SymbolTable::new()), Ok(opcode), "{:08x} {}", opcode, text);
+		}
+	}
+
+	#[test]
+	fn thumb_instructions_assemble_back_to_their_opcode() {
+		for opcode in THUMB_OPCODES {
+			let text = disassemble_thumb(ADDRESS, opcode).to_string();
+			let halfwords = if opcode > 0xffff {
+				vec![opcode as u16, (opcode >> 16) as u16]
+			} else {
+				vec![opcode as u16]
+			};
+			assert_eq!(assemble_thumb(&text, ADDRESS, &SymbolTable::new()), Ok(halfwords), "{:04x} {}", opcode, text);
+		}
+	}
+}
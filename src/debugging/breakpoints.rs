@@ -0,0 +1,185 @@
+use crate::arm7tdmi::cpu::CPU;
+
+/// Names of the registers a condition can test, in CPU order, the CPSR last
+const REGISTER_NAMES: [&str; 17] = ["r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc", "cpsr"];
+const CPSR_INDEX: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EComparison {
+	Equal,
+	NotEqual,
+	Less,
+	LessOrEqual,
+	Greater,
+	GreaterOrEqual,
+}
+
+impl EComparison {
+	/// NOTE: Two character operators first, so "<=" isn't taken for "<"
+	const OPERATORS: [(&'static str, EComparison); 6] = [
+		("==", EComparison::Equal),
+		("!=", EComparison::NotEqual),
+		("<=", EComparison::LessOrEqual),
+		(">=", EComparison::GreaterOrEqual),
+		("<", EComparison::Less),
+		(">", EComparison::Greater),
+	];
+
+	pub fn get_operator(&self) -> &'static str {
+		Self::OPERATORS.iter().find(|(_, comparison)| comparison == self).map_or("", |(operator, _)| operator)
+	}
+
+	fn compare(&self, left: u32, right: u32) -> bool {
+		match self {
+			EComparison::Equal => left == right,
+			EComparison::NotEqual => left != right,
+			EComparison::Less => left < right,
+			EComparison::LessOrEqual => left <= right,
+			EComparison::Greater => left > right,
+			EComparison::GreaterOrEqual => left >= right,
+		}
+	}
+}
+
+/// Register compared to a value, eg. "r0 == 0x5" or "cpsr != 0x1f", values are unsigned
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BreakpointCondition {
+	/// Index in REGISTER_NAMES
+	register: usize,
+	comparison: EComparison,
+	value: u32,
+}
+
+impl BreakpointCondition {
+	/// Parse "<register> <operator> <value>", the value is hexadecimal with a 0x prefix and decimal otherwise
+	pub fn parse(text: &str) -> Option<Self> {
+		let (position, operator, comparison) = EComparison::OPERATORS
+			.iter()
+			.find_map(|(operator, comparison)| text.find(operator).map(|position| (position, operator, *comparison)))?;
+		let register = text[..position].trim().to_lowercase();
+		let value = text[position + operator.len()..].trim();
+
+		let register = REGISTER_NAMES.iter().position(|name| *name == register)?;
+		let value = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+			Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+			None => value.parse().ok()?,
+		};
+		Some(Self { register, comparison, value })
+	}
+
+	pub fn get_text(&self) -> String {
+		format!("{} {} {:#x}", REGISTER_NAMES[self.register], self.comparison.get_operator(), self.value)
+	}
+
+	pub fn matches(&self, cpu: &CPU) -> bool {
+		let value = match self.register {
+			CPSR_INDEX => cpu.get_cpsr().0,
+			index => cpu.get_registers()[index],
+		};
+		self.comparison.compare(value, self.value)
+	}
+}
+
+/// Break before the instruction at an address is executed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+	pub address: u32,
+	/// Only counts as a hit when the condition is true
+	pub condition: Option<BreakpointCondition>,
+	/// Hits to let through before breaking, eg. 2 breaks on the third hit
+	pub ignore_count: u32,
+	pub enabled: bool,
+}
+
+impl Breakpoint {
+	pub fn new(address: u32) -> Self {
+		Self {
+			address,
+			condition: None,
+			ignore_count: 0,
+			enabled: true,
+		}
+	}
+}
+
+/// Breakpoints of the debugger and the number of times each one was hit
+/// NOTE: The hit counts are kept apart from the breakpoints, only the breakpoints are part of the profile of a game
+#[derive(Debug, Clone, Default)]
+pub struct BreakpointManager {
+	breakpoints: Vec<Breakpoint>,
+	hit_counts: Vec<u32>,
+}
+
+impl BreakpointManager {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn get_breakpoints(&self) -> &[Breakpoint] {
+		&self.breakpoints
+	}
+
+	/// Replace all the breakpoints, eg. with the ones of a profile, and reset the hit counts
+	pub fn set_breakpoints(&mut self, breakpoints: Vec<Breakpoint>) {
+		self.hit_counts = vec![0; breakpoints.len()];
+		self.breakpoints = breakpoints;
+	}
+
+	pub fn get_mut_breakpoint(&mut self, index: usize) -> Option<&mut Breakpoint> {
+		self.breakpoints.get_mut(index)
+	}
+
+	pub fn get_hit_count(&self, index: usize) -> u32 {
+		self.hit_counts.get(index).cloned().unwrap_or_default()
+	}
+
+	pub fn add(&mut self, breakpoint: Breakpoint) {
+		self.breakpoints.push(breakpoint);
+		self.hit_counts.push(0);
+	}
+
+	pub fn remove(&mut self, index: usize) {
+		if index < self.breakpoints.len() {
+			self.breakpoints.remove(index);
+			self.hit_counts.remove(index);
+		}
+	}
+
+	pub fn contains(&self, address: u32) -> bool {
+		self.breakpoints.iter().any(|breakpoint| breakpoint.address == address)
+	}
+
+	/// Remove the breakpoints at an address, or add an unconditional one if there is none
+	pub fn toggle(&mut self, address: u32) {
+		if self.contains(address) {
+			while let Some(index) = self.breakpoints.iter().position(|breakpoint| breakpoint.address == address) {
+				self.remove(index);
+			}
+		} else {
+			self.add(Breakpoint::new(address));
+		}
+	}
+
+	pub fn reset_hit_counts(&mut self) {
+		self.hit_counts.iter_mut().for_each(|count| *count = 0);
+	}
+
+	/// Count the hits of the breakpoints at the current PC, call before each instruction
+	/// Returns the index of the first breakpoint to break on
+	pub fn check(&mut self, cpu: &CPU) -> Option<usize> {
+		let pc = cpu.get_current_pc();
+		let mut triggered = None;
+		for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+			if !breakpoint.enabled || breakpoint.address != pc || !breakpoint.condition.is_none_or(|condition| condition.matches(cpu)) {
+				continue;
+			}
+
+			self.hit_counts[index] += 1;
+			if self.hit_counts[index] > breakpoint.ignore_count && triggered.is_none() {
+				triggered = Some(index);
+			}
+		}
+
+		triggered
+	}
+}
@@ -0,0 +1,112 @@
+use crate::arm7tdmi::cpu::{ExceptionRecord, CPU, LINK_REGISTER_REGISTER};
+
+/// Calls deeper than this drop the outermost ones, eg. with code that never returns (longjmp, tail calls through BL)
+const MAX_CALL_DEPTH: usize = 256;
+
+/// Call the game made and didn't return from yet
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+	/// Address of the instruction making the call (the second half of a THUMB BL)
+	pub call_address: u32,
+	pub target: u32,
+	pub return_address: u32,
+	/// State the target runs in
+	pub is_thumb: bool,
+}
+
+/// Calls made by the game, found from the effect of the instructions rather than by decoding them:
+/// an instruction setting LR to the next instruction and jumping elsewhere is a call (BL, `MOV LR, PC` + `BX`, SWI)
+/// and jumping to the return address of a frame returns from it and the frames above it
+/// NOTE: Interrupts are pushed by on_interrupt since they aren't taken by an instruction
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+	frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Frames from the outermost to the innermost
+	pub fn get_frames(&self) -> &[CallFrame] {
+		&self.frames
+	}
+
+	pub fn get_depth(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn clear(&mut self) {
+		self.frames.clear();
+	}
+
+	/// Update after the instruction at pc ran, is_thumb is the state it ran in
+	pub fn on_instruction(&mut self, pc: u32, is_thumb: bool, cpu: &CPU) {
+		let next = pc.wrapping_add(if is_thumb { 2 } else { 4 });
+		let new_pc = cpu.get_current_pc();
+		if new_pc == next {
+			return;
+		}
+
+		if cpu.get_registers()[LINK_REGISTER_REGISTER as usize] & !0x1 == next {
+			self.push(CallFrame {
+				call_address: pc,
+				target: new_pc,
+				return_address: next,
+				is_thumb: cpu.get_cpsr().get_t(),
+			});
+		} else if let Some(index) = self.frames.iter().rposition(|frame| frame.return_address == new_pc) {
+			self.frames.truncate(index);
+		}
+	}
+
+	/// Push the frame of an IRQ/FIQ, returning resumes the instruction before which it was taken
+	pub fn on_interrupt(&mut self, record: &ExceptionRecord) {
+		let return_address = record.return_address.wrapping_sub(4);
+		self.push(CallFrame {
+			call_address: return_address,
+			target: record.vector,
+			return_address,
+			is_thumb: false,
+		});
+	}
+
+	fn push(&mut self, frame: CallFrame) {
+		if self.frames.len() == MAX_CALL_DEPTH {
+			self.frames.remove(0);
+		}
+		self.frames.push(frame);
+	}
+}
+
+/// Where the debugger stops again when resuming with step over, step out or run to cursor
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ERunTarget {
+	/// After an instruction once the call stack is this deep or less: the depth of the current frame steps over the calls, the depth of the caller steps out
+	Depth(usize),
+	Address(u32),
+}
+
+impl ERunTarget {
+	/// Step over the current instruction, running the whole call if it's one
+	pub fn step_over(call_stack: &CallStack) -> Self {
+		ERunTarget::Depth(call_stack.get_depth())
+	}
+
+	/// Run until the current function returns, to LR if the call wasn't seen (eg. the debugger was opened in the middle of it)
+	pub fn step_out(call_stack: &CallStack, cpu: &CPU) -> Self {
+		match call_stack.get_depth() {
+			0 => ERunTarget::Address(cpu.get_registers()[LINK_REGISTER_REGISTER as usize] & !0x1),
+			depth => ERunTarget::Depth(depth - 1),
+		}
+	}
+
+	/// Check after each instruction
+	pub fn is_reached(&self, cpu: &CPU, call_stack: &CallStack) -> bool {
+		match self {
+			ERunTarget::Depth(depth) => call_stack.get_depth() <= *depth,
+			ERunTarget::Address(address) => cpu.get_current_pc() == *address,
+		}
+	}
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use crate::system::cartridge::get_game_code;
+use crate::system::SystemBus;
+
+/// The database shipped with the emulator
+const BUILTIN_DATABASE: &str = include_str!("../../compat.toml");
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ECompatStatus {
+	Working,
+	Issues,
+	Broken,
+}
+
+impl ECompatStatus {
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ECompatStatus::Working => "Working",
+			ECompatStatus::Issues => "Issues",
+			ECompatStatus::Broken => "Broken",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<Self> {
+		[ECompatStatus::Working, ECompatStatus::Issues, ECompatStatus::Broken]
+			.iter()
+			.cloned()
+			.find(|status| status.get_name() == name)
+	}
+}
+
+/// What is known about a game
+#[derive(Debug, Clone)]
+pub struct CompatEntry {
+	pub name: String,
+	pub status: ECompatStatus,
+	pub notes: String,
+	pub features: Vec<String>,
+}
+
+impl CompatEntry {
+	/// One line message shown when the game is loaded, eg. "Known issue: needs RTC, unimplemented"
+	pub fn get_summary(&self) -> String {
+		match self.status {
+			ECompatStatus::Working => format!("{}: Working", self.name),
+			_ if self.notes.is_empty() => format!("Known issue: {}", self.status.get_name()),
+			_ => format!("Known issue: {}", self.notes),
+		}
+	}
+}
+
+/// Game code → compatibility entry
+pub struct CompatDatabase {
+	entries: BTreeMap<String, CompatEntry>,
+}
+
+impl CompatDatabase {
+	pub fn builtin() -> Self {
+		Self::parse(BUILTIN_DATABASE).expect("Invalid builtin compat.toml")
+	}
+
+	/// Parse a database in the compat.toml format, entries without a valid status are skipped
+	pub fn parse(data: &str) -> Result<Self, toml::de::Error> {
+		let value: toml::Value = toml::from_str(data)?;
+		let entries = match value.as_table() {
+			Some(table) => table
+				.iter()
+				.filter_map(|(game_code, entry)| {
+					let status = ECompatStatus::from_name(entry.get("status")?.as_str()?)?;
+					let get_string = |key: &str| entry.get(key).and_then(|value| value.as_str()).unwrap_or_default().to_string();
+					let features = match entry.get("features").and_then(|features| features.as_array()) {
+						Some(features) => features.iter().filter_map(|feature| feature.as_str().map(|feature| feature.to_string())).collect(),
+						None => Vec::new(),
+					};
+
+					Some((
+						game_code.clone(),
+						CompatEntry {
+							name: get_string("name"),
+							status,
+							notes: get_string("notes"),
+							features,
+						},
+					))
+				})
+				.collect(),
+			None => BTreeMap::new(),
+		};
+
+		Ok(Self { entries })
+	}
+
+	pub fn get(&self, game_code: &str) -> Option<&CompatEntry> {
+		self.entries.get(game_code)
+	}
+
+	pub fn get_entries(&self) -> &BTreeMap<String, CompatEntry> {
+		&self.entries
+	}
+}
+
+/// Bug report template for a game, filled with what is needed to reproduce it
+pub fn build_bug_report(rom: &[u8], bus: &SystemBus, entry: Option<&CompatEntry>) -> String {
+	let mut report = String::new();
+	report += "### Game\n";
+	report += &format!("- Game code: {}\n", get_game_code(rom));
+	report += &format!("- ROM CRC32: {:08x}\n", crc32fast::hash(rom));
+	if let Some(entry) = entry {
+		report += &format!("- Known status: {} ({})\n", entry.status.get_name(), entry.notes);
+	}
+	report += "\n### Emulator\n";
+	report += &format!("- Version: {}\n", env!("CARGO_PKG_VERSION"));
+	report += &format!("- Save type: {}\n", bus.get_cartridge().get_backup().get_type().get_name());
+	report += &format!("- ROM write attempts: {}\n", bus.get_rom_write_attempts());
+	report += "\n### What happened\n\n";
+	report += "\n### Expected behavior\n\n";
+	report += "\n### Steps to reproduce\n\n";
+
+	report
+}
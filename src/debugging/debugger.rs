@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use imgui::ImString;
+
+use crate::arm7tdmi::cpu::CPU;
+use crate::arm7tdmi::EExceptionType;
+use crate::debugging::breakpoints::BreakpointManager;
+use crate::debugging::call_stack::{CallStack, ERunTarget};
+use crate::debugging::profiler::CpuProfiler;
+use crate::debugging::symbols::SymbolTable;
+use crate::debugging::trace::{InstructionTrace, DEFAULT_TRACE_PATH};
+use crate::debugging::{describe_exception, describe_watchpoint_hit, ExceptionBreakpoints};
+use crate::gba::EmulationHooks;
+use crate::system::{EMemoryRegion, SystemBus};
+
+/// State of the debugger kept across frames: what the game breaks on, what is recorded while it runs and the inputs of the debug windows
+/// NOTE: The watchpoints are checked by the bus, they live there
+pub struct DebuggerState {
+	/// Paused, the game only runs a step at a time
+	pub debug_mode: bool,
+	/// Run a single instruction on the next frame
+	pub execute_step: bool,
+	pub breakpoints: BreakpointManager,
+	pub exception_breakpoints: ExceptionBreakpoints,
+	/// Step over/out or run to cursor in progress
+	pub run_target: Option<ERunTarget>,
+	pub call_stack: CallStack,
+	pub trace: InstructionTrace,
+	pub trace_path: ImString,
+	pub profiler: CpuProfiler,
+	pub symbols: SymbolTable,
+	pub bookmarks_path: PathBuf,
+	/// Address shown by the memory window, the other windows show theirs there
+	pub inspected_address: u32,
+	pub anomaly_message: Option<String>,
+	pub exception_message: Option<String>,
+	pub watchpoint_message: Option<String>,
+	/// Where the trace leading to the last breakpoint was saved
+	pub trace_message: Option<String>,
+	pub bookmark_name: ImString,
+	pub go_to_address: ImString,
+	pub watch_length: i32,
+	pub watch_kind: usize,
+	pub patch_instruction: ImString,
+	pub selected_region: usize,
+	pub region_file_path: ImString,
+	pub breakpoint_address_text: ImString,
+	pub breakpoint_condition_text: ImString,
+	pub breakpoint_ignore_count: i32,
+}
+
+impl DebuggerState {
+	pub fn new(symbols: SymbolTable, bookmarks_path: PathBuf, debug_mode: bool) -> Self {
+		Self {
+			debug_mode,
+			execute_step: false,
+			breakpoints: BreakpointManager::new(),
+			exception_breakpoints: ExceptionBreakpoints::default(),
+			run_target: None,
+			call_stack: CallStack::new(),
+			trace: InstructionTrace::default(),
+			trace_path: ImString::new(DEFAULT_TRACE_PATH),
+			profiler: CpuProfiler::new(),
+			symbols,
+			bookmarks_path,
+			inspected_address: 0,
+			anomaly_message: None,
+			exception_message: None,
+			watchpoint_message: None,
+			trace_message: None,
+			bookmark_name: ImString::with_capacity(64),
+			go_to_address: ImString::with_capacity(64),
+			watch_length: 4,
+			watch_kind: 0,
+			patch_instruction: ImString::with_capacity(64),
+			selected_region: 0,
+			region_file_path: ImString::with_capacity(256),
+			breakpoint_address_text: ImString::with_capacity(64),
+			breakpoint_condition_text: ImString::with_capacity(64),
+			breakpoint_ignore_count: 0,
+		}
+	}
+
+	/// Forget the execution recorded so far, it doesn't lead to the current state anymore (eg. after a state load or a rewind)
+	pub fn clear_history(&mut self) {
+		self.trace.clear();
+		self.call_stack.clear();
+	}
+
+	/// Forget why the game last stopped
+	pub fn clear_messages(&mut self) {
+		self.anomaly_message = None;
+		self.exception_message = None;
+		self.watchpoint_message = None;
+		self.trace_message = None;
+	}
+
+	/// Whether an exception stops the emulation, any exception does while stepping
+	fn breaks_on(&self, exception_type: EExceptionType) -> bool {
+		self.debug_mode || self.exception_breakpoints.matches(exception_type)
+	}
+
+	fn pause(&mut self) -> bool {
+		self.debug_mode = true;
+		true
+	}
+}
+
+impl EmulationHooks for DebuggerState {
+	fn on_hardware_step(&mut self, cpu: &mut CPU, bus: &mut SystemBus) -> bool {
+		// NOTE: Watchpoint hit by a DMA, checked before the next opcode fetch drops it
+		if let Some((watchpoint, address)) = bus.take_watchpoint_hit() {
+			self.watchpoint_message = Some(describe_watchpoint_hit(&watchpoint, address, "DMA"));
+			return self.pause();
+		}
+
+		// NOTE: IRQs are taken between instructions
+		let interrupt = cpu.take_last_exception();
+		if let Some(record) = &interrupt {
+			self.call_stack.on_interrupt(record);
+		}
+		if let Some(record) = interrupt.filter(|record| self.breaks_on(record.exception_type)) {
+			self.exception_message = Some(describe_exception(&record, bus));
+			return self.pause();
+		}
+
+		false
+	}
+
+	fn before_instruction(&mut self, cpu: &CPU, bus: &SystemBus) {
+		self.trace.record(cpu, bus);
+		self.profiler.record(cpu.get_current_pc());
+	}
+
+	fn after_instruction(&mut self, pc: u32, is_thumb: bool, cpu: &mut CPU, bus: &mut SystemBus) -> bool {
+		self.call_stack.on_instruction(pc, is_thumb, cpu);
+
+		// NOTE: The BIOS does some undocumented accesses (eg. 0x4000410) so only game code counts
+		if let Some(anomaly) = bus.take_anomaly().filter(|_| SystemBus::resolve(pc).0 != EMemoryRegion::Bios) {
+			self.anomaly_message = Some(format!("{} (PC: {})", anomaly.get_description(), self.symbols.format_address(pc)));
+			return self.pause();
+		}
+
+		if let Some((watchpoint, address)) = bus.take_watchpoint_hit() {
+			self.watchpoint_message = Some(describe_watchpoint_hit(&watchpoint, address, &self.symbols.format_address(pc)));
+			return self.pause();
+		}
+
+		// NOTE: SWI/Undefined are taken while executing the instruction
+		if let Some(record) = cpu.take_last_exception().filter(|record| self.breaks_on(record.exception_type)) {
+			self.exception_message = Some(describe_exception(&record, bus));
+			return self.pause();
+		}
+
+		// NOTE: Breakpoints are checked before the instruction at them runs, their hit counts only count while the game runs
+		if !self.debug_mode && (self.breakpoints.check(cpu).is_some() || self.run_target.is_some_and(|target| target.is_reached(cpu, &self.call_stack))) {
+			// NOTE: The trace leading to the breakpoint
+			if self.trace.is_enabled() {
+				self.trace_message = Some(match self.trace.save(Path::new(self.trace_path.to_str()), &self.symbols) {
+					Ok(path) => format!("Trace saved to {}", path.display()),
+					Err(e) => format!("Failed to save the trace: {}", e),
+				});
+			}
+			return self.pause();
+		}
+
+		false
+	}
+}
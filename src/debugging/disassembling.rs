@@ -1,3 +1,5 @@
+use std::fmt;
+
 use bitfield::*;
 use num_traits::FromPrimitive;
 
@@ -5,15 +7,69 @@ use crate::arm7tdmi::cpu::CPU;
 use crate::arm7tdmi::{sign_extend, EShiftType};
 use crate::system::{MemoryInterface, SystemBus};
 
-pub fn disassemble_instruction(cpu: &CPU, bus: &SystemBus) -> String {
-	// NOTE: Read CPU state
-	let pc = cpu.get_current_pc();
-	if cpu.get_cpsr().get_t() {
-		let instruction = bus.read_16(pc);
-		disassemble_thumb(instruction)
+pub(crate) const REGISTER_NAMES: [&str; 16] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "R11", "R12", "SP", "LR", "PC"];
+
+pub(crate) const DATA_PROCESSING_OPS: [&str; 16] = [
+	"AND", "EOR", "SUB", "RSB", "ADD", "ADC", "SBC", "RSC", "TST", "TEQ", "CMP", "CMN", "ORR", "MOV", "BIC", "MVN",
+];
+
+pub(crate) const THUMB_ALU_OPS: [&str; 16] = [
+	"AND", "EOR", "LSL", "LSR", "ASR", "ADC", "SBC", "ROR", "TST", "NEG", "CMP", "CMN", "ORR", "MUL", "BIC", "MVN",
+];
+
+/// Decoded instruction, the mnemonic includes the condition and the suffixes (eg. "LDRHNE", "ADDS")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+	pub mnemonic: String,
+	pub operands: String,
+	/// Absolute address a B/BL jumps to
+	pub branch_target: Option<u32>,
+}
+
+impl Instruction {
+	fn new(mnemonic: impl Into<String>, operands: impl Into<String>) -> Self {
+		Self {
+			mnemonic: mnemonic.into(),
+			operands: operands.into(),
+			branch_target: None,
+		}
+	}
+
+	fn branch(mnemonic: impl Into<String>, target: u32) -> Self {
+		Self {
+			mnemonic: mnemonic.into(),
+			operands: format!("{:#010X}", target),
+			branch_target: Some(target),
+		}
+	}
+
+	fn undefined() -> Self {
+		Self::new("UNDEFINED", "")
+	}
+}
+
+impl fmt::Display for Instruction {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.operands.is_empty() {
+			write!(f, "{}", self.mnemonic)
+		} else {
+			write!(f, "{} {}", self.mnemonic, self.operands)
+		}
+	}
+}
+
+/// Disassemble the instruction the CPU is about to execute
+pub fn disassemble_instruction(cpu: &CPU, bus: &SystemBus) -> Instruction {
+	disassemble_at(bus, cpu.get_current_pc(), cpu.get_cpsr().get_t())
+}
+
+/// Disassemble the instruction at an address, a THUMB BL is merged with its second half
+pub fn disassemble_at(bus: &SystemBus, address: u32, is_thumb: bool) -> Instruction {
+	if is_thumb {
+		let opcode = bus.read_16(address) as u32 | (bus.read_16(address.wrapping_add(2)) as u32) << 16;
+		disassemble_thumb(address, opcode)
 	} else {
-		let instruction = bus.read_32(pc);
-		disassemble_arm(instruction)
+		disassemble_arm(address, bus.read_32(address))
 	}
 }
 
@@ -37,483 +93,315 @@ pub fn disassemble_cond(cond: u8) -> &'static str {
 	}
 }
 
-pub fn get_register_list(instruction: u32, thumb: bool) -> String {
-	let mut regs = String::from("{ ");
-	let bits = if thumb { 8 } else { 16 };
-
-	let reg_list: u16 = instruction.bit_range(bits - 1, 0);
-	for i in 0..bits {
-		if reg_list.bit(i) {
-			if i > 0 && reg_list.bit(i - 1) {
-				if i < bits - 1 && reg_list.bit(i + 1) {
-					continue;
-				} else {
-					regs += &*format!("-R{}", i);
-					continue;
-				}
-			}
+fn register(index: u32) -> &'static str {
+	REGISTER_NAMES[(index & 0xf) as usize]
+}
+
+/// Registers of a LDM/STM/PUSH/POP, consecutive registers are merged into ranges, eg. "{ R0-R3, LR }"
+pub fn get_register_list(reg_list: u16) -> String {
+	let mut ranges: Vec<String> = Vec::new();
+	let mut i = 0;
+	while i < 16 {
+		if !reg_list.bit(i) {
+			i += 1;
+			continue;
+		}
 
-			let comma = if regs.len() > 2 { ", " } else { "" };
-			regs += &*format!("{}R{}", comma, i);
+		let start = i;
+		while i < 15 && reg_list.bit(i + 1) {
+			i += 1;
 		}
+		ranges.push(match i - start {
+			0 => register(start as u32).to_string(),
+			1 => format!("{}, {}", register(start as u32), register(i as u32)),
+			_ => format!("{}-{}", register(start as u32), register(i as u32)),
+		});
+		i += 1;
 	}
-	regs += " }";
 
-	regs
+	format!("{{ {} }}", ranges.join(", "))
 }
 
-pub fn disassemble_thumb(instruction: u16) -> String {
+/// Small values in decimal, others in hexadecimal
+fn immediate(value: u32) -> String {
+	if value < 10 {
+		format!("#{}", value)
+	} else {
+		format!("#{:#X}", value)
+	}
+}
+
+fn signed_immediate(value: u32, negative: bool) -> String {
+	format!("{}{}", if negative { "#-" } else { "#" }, &immediate(value)[1..])
+}
+
+/// Register operand with its shift, eg. "R1", "R1, LSL #2", "R1, ASR R2" or "R1, RRX"
+fn shifted_register(instruction: u32, register_shift: bool) -> String {
+	let rm = register(instruction);
+	let shift_type: EShiftType = FromPrimitive::from_u32((instruction & 0x0000_0060) >> 5).unwrap();
+	if register_shift {
+		return format!("{}, {:?} {}", rm, shift_type, register(instruction >> 8));
+	}
+
+	// NOTE: A shift of 0 encodes LSR/ASR #32 and RRX
+	match (shift_type, (instruction & 0x0000_0f80) >> 7) {
+		(EShiftType::LSL, 0) => rm.to_string(),
+		(EShiftType::ROR, 0) => format!("{}, RRX", rm),
+		(_, 0) => format!("{}, {:?} #32", rm, shift_type),
+		(_, shift) => format!("{}, {:?} #{}", rm, shift_type, shift),
+	}
+}
+
+/// Memory operand of a load/store, no offset is None
+fn indexed_address(rn: u32, offset: Option<String>, pre_indexed: bool, write_back: bool) -> String {
+	match offset {
+		None => format!("[{}]", register(rn)),
+		Some(offset) if pre_indexed => format!("[{}, {}]{}", register(rn), offset, if write_back { "!" } else { "" }),
+		Some(offset) => format!("[{}], {}", register(rn), offset),
+	}
+}
+
+/// Disassemble a THUMB instruction at an address, the upper 16 bits of the opcode are the next instruction to merge the halves of a BL
+pub fn disassemble_thumb(address: u32, opcode: u32) -> Instruction {
+	let instruction = opcode as u16;
+	let rd = register((instruction & 0x0007) as u32);
+	let rs = register(((instruction & 0x0038) >> 3) as u32);
+
 	if (0xf800 & instruction) == 0x1800 {
 		let op = if (0x0200 & instruction) != 0 { "SUB" } else { "ADD" };
-		let i = (0x0400 & instruction) != 0;
-		let rn = if i {
-			format!("#{}", (0x01c0 & instruction) >> 6)
+		let operand = if (0x0400 & instruction) != 0 {
+			immediate(((0x01c0 & instruction) >> 6) as u32)
 		} else {
-			format!("R{}", (0x01c0 & instruction) >> 6)
+			register(((0x01c0 & instruction) >> 6) as u32).to_string()
 		};
 
-		format!("{} R{}, R{}, {}", op, instruction & 0x0007, (instruction & 0x0038) >> 3, rn)
+		Instruction::new(op, format!("{}, {}, {}", rd, rs, operand))
 	} else if (0xe000 & instruction) == 0x0000 {
-		let op;
-		match (0x1800 & instruction) >> 11 {
-			0x0 => op = "LSL",
-			0x1 => op = "LSR",
-			0x2 => op = "ASR",
-			_ => panic!("ERROR!!!"),
-		}
+		let shift_type: EShiftType = FromPrimitive::from_u16((0x1800 & instruction) >> 11).unwrap();
+		// NOTE: A shift of 0 encodes LSR/ASR #32
+		let shift = match ((instruction & 0x07c0) >> 6) as u32 {
+			0 if shift_type != EShiftType::LSL => 32,
+			shift => shift,
+		};
 
-		format!("{} R{}, R{}, #{}", op, instruction & 0x0007, (instruction & 0x0038) >> 3, (instruction & 0x07c0) >> 6)
+		Instruction::new(format!("{:?}", shift_type), format!("{}, {}, #{}", rd, rs, shift))
 	} else if (0xe000 & instruction) == 0x2000 {
-		let op;
-		match (0x1800 & instruction) >> 11 {
-			0x0 => op = "MOV",
-			0x1 => op = "CMP",
-			0x2 => op = "ADD",
-			0x3 => op = "SUB",
-			_ => panic!("ERROR!!!"),
-		}
-
-		format!("{} R{}, #{}", op, (instruction & 0x0700) >> 8, instruction & 0x00ff)
+		let op = ["MOV", "CMP", "ADD", "SUB"][((0x1800 & instruction) >> 11) as usize];
+		Instruction::new(
+			op,
+			format!("{}, {}", register(((instruction & 0x0700) >> 8) as u32), immediate((instruction & 0x00ff) as u32)),
+		)
 	} else if (0xfc00 & instruction) == 0x4000 {
-		let op;
-		match (0x03c0 & instruction) >> 6 {
-			0x0 => op = "AND",
-			0x1 => op = "EOR",
-			0x2 => op = "LSL",
-			0x3 => op = "LSR",
-			0x4 => op = "ASR",
-			0x5 => op = "ADC",
-			0x6 => op = "SBC",
-			0x7 => op = "ROR",
-			0x8 => op = "TST",
-			0x9 => op = "NEG",
-			0xa => op = "CMP",
-			0xb => op = "CMN",
-			0xc => op = "ORR",
-			0xd => op = "MUL",
-			0xe => op = "BIC",
-			0xf => op = "MVN",
-			_ => panic!("ERROR!!!"),
-		}
-
-		format!("{} R{}, R{}", op, instruction & 0x0007, (instruction & 0x0038) >> 3)
+		Instruction::new(THUMB_ALU_OPS[((0x03c0 & instruction) >> 6) as usize], format!("{}, {}", rd, rs))
 	} else if (0xfc00 & instruction) == 0x4400 {
-		let op;
+		let rm = register(((instruction & 0x0078) >> 3) as u32);
 		match (0x0300 & instruction) >> 8 {
-			0x0 => op = "ADD",
-			0x1 => op = "CMP",
-			0x2 => op = "MOV",
-			0x3 => op = "BX",
-			_ => panic!("ERROR!!!"),
+			0x3 => Instruction::new("BX", rm),
+			op => {
+				let rd = register(((instruction & 0x0007) | ((instruction & 0x0080) >> 4)) as u32);
+				Instruction::new(["ADD", "CMP", "MOV"][op as usize], format!("{}, {}", rd, rm))
+			}
 		}
-
-		let rm = (instruction & 0x0078) >> 3;
-		let rd = if op == "BX" {
-			String::from("")
-		} else {
-			format!("R{}, ", (instruction & 0x0007) | ((instruction & 0x0080) >> 4))
-		};
-
-		format!("{} {}R{}", op, rd, rm)
 	} else if (0xf800 & instruction) == 0x4800 {
-		format!("LDR R{}, [PC, #{}]", (instruction & 0x0700) >> 8, instruction & 0x00ff)
+		let offset = ((instruction & 0x00ff) as u32) << 2;
+		Instruction::new("LDR", format!("{}, [PC, {}]", register(((instruction & 0x0700) >> 8) as u32), immediate(offset)))
 	} else if (0xf200 & instruction) == 0x5000 {
-		let op;
-		match (0x0c00 & instruction) >> 10 {
-			0x0 => op = "STR",
-			0x1 => op = "STRB",
-			0x2 => op = "LDR",
-			0x3 => op = "LDRB",
-			_ => panic!("ERROR!!!"),
-		}
-
-		format!("{} R{}, [R{}, R{}]", op, instruction & 0x0007, (instruction & 0x0038) >> 3, (instruction & 0x01c0) >> 6)
+		let op = ["STR", "STRB", "LDR", "LDRB"][((0x0c00 & instruction) >> 10) as usize];
+		Instruction::new(op, format!("{}, [{}, {}]", rd, rs, register(((instruction & 0x01c0) >> 6) as u32)))
 	} else if (0xf200 & instruction) == 0x5200 {
-		let op;
-		match (0x0c00 & instruction) >> 10 {
-			0x0 => op = "STRH",
-			0x1 => op = "LDSB",
-			0x2 => op = "LDRH",
-			0x3 => op = "LDSH",
-			_ => panic!("ERROR!!!"),
-		}
-
-		format!("{} R{}, [R{}, R{}]", op, instruction & 0x0007, (instruction & 0x0038) >> 3, (instruction & 0x01c0) >> 6)
+		let op = ["STRH", "LDRSB", "LDRH", "LDRSH"][((0x0c00 & instruction) >> 10) as usize];
+		Instruction::new(op, format!("{}, [{}, {}]", rd, rs, register(((instruction & 0x01c0) >> 6) as u32)))
 	} else if (0xe000 & instruction) == 0x6000 {
-		let op;
-		match (0x1800 & instruction) >> 11 {
-			0x0 => op = "STR",
-			0x1 => op = "LDR",
-			0x2 => op = "STRB",
-			0x3 => op = "LDRB",
-			_ => panic!("ERROR!!!"),
-		}
-
-		format!("{} R{}, [R{}, #{}]", op, instruction & 0x0007, (instruction & 0x0038) >> 3, (instruction & 0x07c0) >> 6)
+		let op = ["STR", "LDR", "STRB", "LDRB"][((0x1800 & instruction) >> 11) as usize];
+		// NOTE: Word offsets are in words
+		let offset = ((instruction & 0x07c0) >> 6) as u32;
+		let offset = if (0x1000 & instruction) != 0 { offset } else { offset << 2 };
+		Instruction::new(
+			op,
+			format!("{}, {}", rd, indexed_address(((instruction & 0x0038) >> 3) as u32, Some(immediate(offset)), true, false)),
+		)
 	} else if (0xf000 & instruction) == 0x8000 {
-		let op = if (0x0800 & instruction) > 0 { "LDRH" } else { "STRH" };
-		format!("{} R{}, [R{}, #{}]", op, instruction & 0x0007, (instruction & 0x0038) >> 3, (instruction & 0x07c0) >> 6)
+		let op = if (0x0800 & instruction) != 0 { "LDRH" } else { "STRH" };
+		let offset = (((instruction & 0x07c0) >> 6) as u32) << 1;
+		Instruction::new(
+			op,
+			format!("{}, {}", rd, indexed_address(((instruction & 0x0038) >> 3) as u32, Some(immediate(offset)), true, false)),
+		)
 	} else if (0xf000 & instruction) == 0x9000 {
-		let op = if (0x0800 & instruction) > 0 { "LDR" } else { "STR" };
-		format!("{} R{}, SP, #{}", op, (instruction & 0x0700) >> 8, (instruction & 0x00ff) << 2)
+		let op = if (0x0800 & instruction) != 0 { "LDR" } else { "STR" };
+		let offset = ((instruction & 0x00ff) as u32) << 2;
+		Instruction::new(op, format!("{}, [SP, {}]", register(((instruction & 0x0700) >> 8) as u32), immediate(offset)))
 	} else if (0xf000 & instruction) == 0xa000 {
-		let op = if (0x0800 & instruction) > 0 { "SP" } else { "PC" };
-		format!("ADD R{}, {}, #{}", (instruction & 0x0700) >> 8, op, instruction & 0x00ff)
+		let source = if (0x0800 & instruction) != 0 { "SP" } else { "PC" };
+		let offset = ((instruction & 0x00ff) as u32) << 2;
+		Instruction::new("ADD", format!("{}, {}, {}", register(((instruction & 0x0700) >> 8) as u32), source, immediate(offset)))
 	} else if (0xff00 & instruction) == 0xb000 {
 		let op = if (0x0080 & instruction) != 0 { "SUB" } else { "ADD" };
-		format!("{} SP, #{}", op, (instruction & 0x007f) << 2)
+		Instruction::new(op, format!("SP, {}", immediate(((instruction & 0x007f) as u32) << 2)))
 	} else if (0xf600 & instruction) == 0xb400 {
-		let op = if (0x0800 & instruction) > 0 { "POP" } else { "PUSH" };
-		let r = if (0x0100 & instruction) > 0 {
-			if op == "PUSH" {
-				", LR"
-			} else {
-				", PC"
-			}
-		} else {
-			""
-		};
-
-		let regs = get_register_list(instruction as u32, true);
+		let pop = (0x0800 & instruction) != 0;
+		let mut reg_list = instruction & 0x00ff;
+		if (0x0100 & instruction) != 0 {
+			reg_list |= if pop { 1 << 15 } else { 1 << 14 };
+		}
 
-		format!("{} {}{}", op, regs, r)
+		Instruction::new(if pop { "POP" } else { "PUSH" }, get_register_list(reg_list))
 	} else if (0xf000 & instruction) == 0xc000 {
-		let op = if (0x0800 & instruction) > 0 { "LDMIA" } else { "STMIA" };
-
-		let regs = get_register_list(instruction as u32, true);
-		format!("{} R{}!, {}", op, (instruction & 0x0700) >> 8, regs)
+		let op = if (0x0800 & instruction) != 0 { "LDMIA" } else { "STMIA" };
+		Instruction::new(
+			op,
+			format!("{}!, {}", register(((instruction & 0x0700) >> 8) as u32), get_register_list(instruction & 0x00ff)),
+		)
 	} else if (0xff00 & instruction) == 0xdf00 {
-		let nn = 0x00ff & instruction;
-		format!("SWI #{:#X}", nn)
+		Instruction::new("SWI", immediate((0x00ff & instruction) as u32))
 	} else if (0xf000 & instruction) == 0xd000 {
-		let op;
-		match (0x0f00 & instruction) >> 8 {
-			0x0 => op = "BEQ",
-			0x1 => op = "BNE",
-			0x2 => op = "BCS",
-			0x3 => op = "BCC",
-			0x4 => op = "BMI",
-			0x5 => op = "BPL",
-			0x6 => op = "BVS",
-			0x7 => op = "BVC",
-			0x8 => op = "BHI",
-			0x9 => op = "BLS",
-			0xa => op = "BGE",
-			0xb => op = "BLT",
-			0xc => op = "BGT",
-			0xd => op = "BLE",
-			0xe => op = "UNDEFINED",
-			_ => panic!("ERROR!!!"),
+		let cond = ((0x0f00 & instruction) >> 8) as u8;
+		if cond == 0xe {
+			return Instruction::undefined();
 		}
 
 		let offset = sign_extend(instruction & 0x00ff, 8) << 1;
-		format!("{} Offset: {}", op, offset)
+		Instruction::branch(format!("B{}", disassemble_cond(cond)), address.wrapping_add(4).wrapping_add(offset as u32))
 	} else if (0xf800 & instruction) == 0xe000 {
 		let offset = sign_extend(instruction & 0x07ff, 11) << 1;
-		format!("B Offset: #{}", offset)
+		Instruction::branch("B", address.wrapping_add(4).wrapping_add(offset as u32))
 	} else if (0xf800 & instruction) == 0xf000 {
-		let hi = sign_extend(instruction & 0x07ff, 11);
-		format!("BL Target: #{} + ", hi << 12)
+		let high = sign_extend(instruction & 0x07ff, 11) << 12;
+		let second_half = (opcode >> 16) as u16;
+		if (0xf800 & second_half) == 0xf800 {
+			let low = ((second_half & 0x07ff) as i32) << 1;
+			Instruction::branch("BL", address.wrapping_add(4).wrapping_add((high + low) as u32))
+		} else {
+			// NOTE: First half without its second half, only sets LR
+			Instruction::new("BL", format!("LR = PC + {}", signed_immediate(high.unsigned_abs(), high < 0)))
+		}
 	} else if (0xf800 & instruction) == 0xf800 {
-		let lo = sign_extend(instruction & 0x07ff, 11);
-		format!("#{}", lo << 1)
+		// NOTE: Second half alone, jumps relative to the LR set by the first half
+		Instruction::new("BL", format!("LR + {}", immediate(((instruction & 0x07ff) as u32) << 1)))
 	} else {
-		"Missing instruction!".to_string()
+		Instruction::undefined()
 	}
 }
 
-pub fn disassemble_arm(instruction: u32) -> String {
-	let cond = (instruction >> (32 - 4)) as u8;
+/// Disassemble an ARM instruction at an address
+pub fn disassemble_arm(address: u32, instruction: u32) -> Instruction {
+	let cond = disassemble_cond((instruction >> (32 - 4)) as u8);
+	let rn = (instruction & 0x000f_0000) >> 16;
+	let rd = (instruction & 0x0000_f000) >> 12;
+
 	if (0x0fff_fff0 & instruction) == 0x012f_ff10 {
-		return format!("BX {} R{}", disassemble_cond(cond), instruction & 0x0000_000f);
+		Instruction::new(format!("BX{}", cond), register(instruction))
 	} else if (0x0e00_0000 & instruction) == 0x0a00_0000 {
-		if 1 << 24 & instruction > 0 {
-			return format!("BL {} #{}", disassemble_cond(cond), instruction & 0x00ff_ffff);
-		} else {
-			return format!("B {} #{}", disassemble_cond(cond), instruction & 0x00ff_ffff);
-		}
+		let op = if (0x0100_0000 & instruction) != 0 { "BL" } else { "B" };
+		let offset = sign_extend(instruction & 0x00ff_ffff, 24) << 2;
+		Instruction::branch(format!("{}{}", op, cond), address.wrapping_add(8).wrapping_add(offset as u32))
 	} else if (0x0e00_0010 & instruction) == 0x0600_0010 {
-		"Undefined instruction!".to_string()
+		Instruction::undefined()
 	} else if (0x0fb0_0ff0 & instruction) == 0x0100_0090 {
-		if 1 << 22 & instruction > 0 {
-			return format!(
-				"SWPB R{}, R{}, R{}",
-				(instruction & 0x0000_f000) >> 12,
-				instruction & 0x0000_000f,
-				(instruction & 0x000f_0000) >> 16
-			);
-		} else {
-			return format!(
-				"SWP R{}, R{}, R{}",
-				(instruction & 0x0000_f000) >> 12,
-				instruction & 0x0000_000f,
-				(instruction & 0x000f_0000) >> 16
-			);
-		}
+		let b = if (0x0040_0000 & instruction) != 0 { "B" } else { "" };
+		Instruction::new(format!("SWP{}{}", b, cond), format!("{}, {}, [{}]", register(rd), register(instruction), register(rn)))
 	} else if (0x0f00_00f0 & instruction) == 0x0000_0090 {
-		let s = if (0x0010_0000 & instruction) > 0 { "S" } else { "" };
-
-		let rd;
-		let rn;
-		let rs;
-		let rm;
-		let op;
-		match (0x01e0_0000 & instruction) >> 21 {
-			0x0 => {
-				op = "MUL";
-				rd = format!("R{}", (instruction & 0x000f_0000) >> 16);
-				rm = format!(", R{}", instruction & 0x0000_000f);
-				rs = format!(", R{}", (instruction & 0x0000_0f00) >> 8);
-				rn = String::from("");
-			}
-			0x1 => {
-				op = "MLA";
-				rd = format!("R{}", (instruction & 0x000f_0000) >> 16);
-				rm = format!(", R{}", instruction & 0x0000_000f);
-				rs = format!(", R{}", (instruction & 0x0000_0f00) >> 8);
-				rn = format!(", R{}", (instruction & 0x0000_f000) >> 12);
-			}
-			0x4 => {
-				op = "UMULL";
-				rd = format!("R{}", (instruction & 0x000f_0000) >> 16);
-				rm = format!(", R{}", (instruction & 0x0000_f000) >> 12);
-				rs = format!(", R{}", instruction & 0x0000_000f);
-				rn = format!(", R{}", (instruction & 0x0000_0f00) >> 8);
-			}
-			0x5 => {
-				op = "UMLAL";
-				rd = format!("R{}", (instruction & 0x000f_0000) >> 16);
-				rm = format!(", R{}", (instruction & 0x0000_f000) >> 12);
-				rs = format!(", R{}", instruction & 0x0000_000f);
-				rn = format!(", R{}", (instruction & 0x0000_0f00) >> 8);
-			}
-			0x6 => {
-				op = "SMULL";
-				rd = format!("R{}", (instruction & 0x000f_0000) >> 16);
-				rm = format!(", R{}", (instruction & 0x0000_f000) >> 12);
-				rs = format!(", R{}", instruction & 0x0000_000f);
-				rn = format!(", R{}", (instruction & 0x0000_0f00) >> 8);
-			}
-			0x7 => {
-				op = "SMLAL";
-				rd = format!("R{}", (instruction & 0x000f_0000) >> 16);
-				rm = format!(", R{}", (instruction & 0x0000_f000) >> 12);
-				rs = format!(", R{}", instruction & 0x0000_000f);
-				rn = format!(", R{}", (instruction & 0x0000_0f00) >> 8);
-			}
-			_ => {
-				op = "ERROR";
-				rd = String::from("");
-				rm = String::from("");
-				rs = String::from("");
-				rn = String::from("");
-			}
-		}
+		let s = if (0x0010_0000 & instruction) != 0 { "S" } else { "" };
+		let rm = register(instruction);
+		let rs = register(instruction >> 8);
+		let (op, operands) = match (0x01e0_0000 & instruction) >> 21 {
+			0x0 => ("MUL", format!("{}, {}, {}", register(rn), rm, rs)),
+			0x1 => ("MLA", format!("{}, {}, {}, {}", register(rn), rm, rs, register(rd))),
+			// NOTE: Long multiplies write RdLo (bits 12-15) and RdHi (bits 16-19)
+			op @ 0x4..=0x7 => (
+				["UMULL", "UMLAL", "SMULL", "SMLAL"][(op - 0x4) as usize],
+				format!("{}, {}, {}, {}", register(rd), register(rn), rm, rs),
+			),
+			_ => return Instruction::undefined(),
+		};
 
-		// TODO: Revisit params!!!
-		return format!("{}{} {} {}{}{}{}", op, s, disassemble_cond(cond), rd, rm, rs, rn);
+		Instruction::new(format!("{}{}{}", op, s, cond), operands)
 	} else if (0x0fbf_0fff & instruction) == 0x010f_0000 {
-		return if (instruction & 0x0040_0000) != 0 {
-			format!("MRS {} R{}, SPSR", disassemble_cond(cond), (instruction & 0x0000_f000) >> 12)
-		} else {
-			format!("MRS {} R{}, CPSR", disassemble_cond(cond), (instruction & 0x0000_f000) >> 12)
-		};
+		let psr = if (instruction & 0x0040_0000) != 0 { "SPSR" } else { "CPSR" };
+		Instruction::new(format!("MRS{}", cond), format!("{}, {}", register(rd), psr))
 	} else if (0x0db0_f000 & instruction) == 0x0120_f000 {
-		let mut fields = String::from("");
-		if (0x0008_0000 & instruction) > 0 {
-			fields += "f";
-		}
-		if (0x0004_0000 & instruction) > 0 {
-			fields += "s";
-		}
-		if (0x0002_0000 & instruction) > 0 {
-			fields += "x";
-		}
-		if (0x0001_0000 & instruction) > 0 {
-			fields += "c";
-		}
+		let psr = if (instruction & 0x0040_0000) != 0 { "SPSR" } else { "CPSR" };
+		let mut fields: String = [(0x0008_0000, 'f'), (0x0004_0000, 's'), (0x0002_0000, 'x'), (0x0001_0000, 'c')]
+			.iter()
+			.filter(|(mask, _)| (instruction & mask) != 0)
+			.map(|(_, field)| field)
+			.collect();
 		if !fields.is_empty() {
-			fields = String::from("_") + &*fields;
+			fields.insert(0, '_');
 		}
-		let psr = if (instruction & 0x0040_0000) > 0 { "SPSR" } else { "CPSR" };
-		return if (instruction & 0x0200_0000) > 0 {
-			format!("MSR {} {}{}, #{}", disassemble_cond(cond), psr, fields, instruction & 0x0000_00ff)
+		let source = if (instruction & 0x0200_0000) != 0 {
+			let rot = (0x0000_0f00 & instruction) >> 8;
+			immediate((0x0000_00ff & instruction).rotate_right(rot * 2))
 		} else {
-			format!("MSR {} {}{}, R{}", disassemble_cond(cond), psr, fields, instruction & 0x0000_00ff)
+			register(instruction).to_string()
 		};
+
+		Instruction::new(format!("MSR{}", cond), format!("{}{}, {}", psr, fields, source))
 	} else if (0x0c00_0000 & instruction) == 0x0400_0000 {
 		let p = (0x0100_0000 & instruction) != 0;
 		let w = (0x0020_0000 & instruction) != 0;
-		let i = (0x0200_0000 & instruction) != 0;
-		let u = if (0x0080_0000 & instruction) != 0 { "+" } else { "-" };
+		let negative = (0x0080_0000 & instruction) == 0;
 		let b = if (0x0040_0000 & instruction) != 0 { "B" } else { "" };
 		let l = if (0x0010_0000 & instruction) != 0 { "LDR" } else { "STR" };
 		let t = if !p && w { "T" } else { "" };
 
-		let rn = (instruction & 0x000f_0000) >> 16;
-		let address;
-		if i {
-			let rm = instruction & 0x0000_000f;
-			let shift_type: EShiftType = FromPrimitive::from_u32((instruction & 0x0000_0060) >> 5).unwrap();
-			let shift = (0x0000_0f80 & instruction) >> 7;
-
-			let shift_type_text = if shift_type == EShiftType::ROR && shift == 0 {
-				String::from("RRX")
-			} else {
-				format!("{:?}", shift_type)
-			};
-			address = format!("[R{}, R{}, {} #{}]", rn, rm, shift_type_text, shift);
-		} else if p {
-			let pre = if w { "!" } else { "" };
-			address = format!("[R{}, #{}{}]{}", rn, u, instruction & 0x0000_0fff, pre);
+		let offset = if (0x0200_0000 & instruction) != 0 {
+			Some(format!("{}{}", if negative { "-" } else { "" }, shifted_register(instruction, false)))
 		} else {
-			address = format!("[R{}], #{}{}", rn, u, instruction & 0x0000_0fff);
-		}
+			Some(instruction & 0x0000_0fff)
+				.filter(|offset| *offset != 0)
+				.map(|offset| signed_immediate(offset, negative))
+		};
 
-		return format!("{}{}{} {} R{}, {}", l, b, t, disassemble_cond(cond), (instruction & 0x0000_f000) >> 12, address);
+		Instruction::new(format!("{}{}{}{}", l, b, t, cond), format!("{}, {}", register(rd), indexed_address(rn, offset, p, w)))
 	} else if (0x0e00_0090 & instruction) == 0x0000_0090 {
 		let p = (0x0100_0000 & instruction) != 0;
-		let u = if (0x0080_0000 & instruction) != 0 { "+" } else { "-" };
-		let i = (0x0040_0000 & instruction) != 0;
+		let negative = (0x0080_0000 & instruction) == 0;
 		let w = (0x0020_0000 & instruction) != 0;
-		let l_bool = (0x0010_0000 & instruction) != 0;
-		let l = if l_bool { "LDR" } else { "STR" };
-		let op;
-		if (0x0000_0020 & instruction) != 0 {
-			op = "H"
-		} else if (0x0000_0040 & instruction) != 0 {
-			op = "SB"
-		} else if (0x0000_0060 & instruction) != 0 {
-			op = "SH"
-		} else {
-			op = "ERROR!!!";
-		}
-
-		let rn = (instruction & 0x000f_0000) >> 16;
-		let offset;
-		if i {
-			let nn = ((instruction & 0x0000_0f00) >> 4) | (instruction & 0x0000_000f);
-			offset = format!("#{}", nn);
-		} else {
-			let rm = instruction & 0x0000_000f;
-			offset = format!("R{}", rm);
-		}
+		let l = if (0x0010_0000 & instruction) != 0 { "LDR" } else { "STR" };
+		let op = match (0x0000_0060 & instruction) >> 5 {
+			0x1 => "H",
+			0x2 => "SB",
+			_ => "SH",
+		};
 
-		let address;
-		if p {
-			let pre = if w { "!" } else { "" };
-			address = format!("[R{}, {}{}]{}", rn, u, offset, pre);
+		let offset = if (0x0040_0000 & instruction) != 0 {
+			let offset = ((instruction & 0x0000_0f00) >> 4) | (instruction & 0x0000_000f);
+			Some(offset).filter(|offset| *offset != 0).map(|offset| signed_immediate(offset, negative))
 		} else {
-			address = format!("[R{}], {}{}", rn, u, offset);
-		}
+			Some(format!("{}{}", if negative { "-" } else { "" }, register(instruction)))
+		};
 
-		return format!("{}{} {} R{}, {}", l, op, disassemble_cond(cond), (instruction & 0x0000_f000) >> 12, address);
+		Instruction::new(format!("{}{}{}", l, op, cond), format!("{}, {}", register(rd), indexed_address(rn, offset, p, w)))
 	} else if (0x0e00_0000 & instruction) == 0x0800_0000 {
-		let l = if (0x0010_0000 & instruction) > 0 { "LDM" } else { "STM" };
-		let w = if (0x0020_0000 & instruction) > 0 { "!" } else { "" };
-		let s = if (0x0040_0000 & instruction) > 0 { "^" } else { "" };
-		let u = if (0x0080_0000 & instruction) > 0 { "I" } else { "D" };
-		let p = if (0x0100_0000 & instruction) > 0 { "B" } else { "A" };
-
-		let regs = get_register_list(instruction, false);
-
-		return format!("{}{}{} {} R{}{}, {}{}", l, u, p, disassemble_cond(cond), (instruction & 0x000f_0000) >> 16, w, regs, s);
+		let l = if (0x0010_0000 & instruction) != 0 { "LDM" } else { "STM" };
+		let w = if (0x0020_0000 & instruction) != 0 { "!" } else { "" };
+		let s = if (0x0040_0000 & instruction) != 0 { "^" } else { "" };
+		let u = if (0x0080_0000 & instruction) != 0 { "I" } else { "D" };
+		let p = if (0x0100_0000 & instruction) != 0 { "B" } else { "A" };
+
+		let regs = get_register_list(instruction as u16);
+		Instruction::new(format!("{}{}{}{}", l, u, p, cond), format!("{}{}, {}{}", register(rn), w, regs, s))
 	} else if (0x0f00_0000 & instruction) == 0x0f00_0000 {
-		let nn = 0x00ff_ffff & instruction;
-		return format!("SWI #{:#x}", nn);
+		Instruction::new(format!("SWI{}", cond), immediate(0x00ff_ffff & instruction))
 	} else if (0x0c00_0000 & instruction) == 0x0000_0000 {
-		let i = (0x0200_0000 & instruction) > 0;
-		let mut s = if (0x0010_0000 & instruction) > 0 { "S" } else { "" };
-		let mut rn = &*format!("R{},", (instruction & 0x000f_0000) >> 16);
-		let mut rd = &*format!("R{},", (instruction & 0x0000_f000) >> 12);
-
-		let op;
-		match (0x01e0_0000 & instruction) >> 21 {
-			0x0 => op = "AND",
-			0x1 => op = "EOR",
-			0x2 => op = "SUB",
-			0x3 => op = "RSB",
-			0x4 => op = "ADD",
-			0x5 => op = "ADC",
-			0x6 => op = "SBC",
-			0x7 => op = "RSC",
-			0x8 => {
-				op = "TST";
-				rd = "";
-				s = "";
-			}
-			0x9 => {
-				op = "TEQ";
-				rd = "";
-				s = "";
-			}
-			0xa => {
-				op = "CMP";
-				rd = "";
-				s = "";
-			}
-			0xb => {
-				op = "CMN";
-				rd = "";
-				s = "";
-			}
-			0xc => op = "ORR",
-			0xd => {
-				op = "MOV";
-				rn = "";
-			}
-			0xe => op = "BIC",
-			0xf => {
-				op = "MVN";
-				rn = "";
-			}
-			_ => panic!("ERROR!!!"),
-		}
-
-		let shifter_operand;
-		if i {
+		let opcode = (0x01e0_0000 & instruction) >> 21;
+		let s = if (0x0010_0000 & instruction) != 0 { "S" } else { "" };
+		let operand = if (0x0200_0000 & instruction) != 0 {
 			let rot = (0x0000_0f00 & instruction) >> 8;
-			shifter_operand = format!("#{}", (0x0000_00ff & instruction).rotate_right(rot * 2));
+			immediate((0x0000_00ff & instruction).rotate_right(rot * 2))
 		} else {
-			let rm = instruction & 0x0000_000f;
-			let r = (instruction & 0x0000_0010) > 0;
-			let shift_type: EShiftType = FromPrimitive::from_u32((instruction & 0x0000_0060) >> 5).unwrap();
-			if r {
-				let rs = (0x0000_0f00 & instruction) >> 8;
-				shifter_operand = format!("R{}, {:?} R{}", rm, shift_type, rs);
-			} else {
-				let shift = (0x0000_0f80 & instruction) >> 7;
-				let shift_type_text = if shift_type == EShiftType::ROR && shift == 0 {
-					String::from("RRX")
-				} else {
-					format!("{:?}", shift_type)
-				};
-				shifter_operand = format!("R{}, {} #{}", rm, shift_type_text, shift);
-			}
-		}
+			shifted_register(instruction, (instruction & 0x0000_0010) != 0)
+		};
 
-		return format!("{}{} {} {}{} {}", op, s, disassemble_cond(cond), rd, rn, shifter_operand);
+		let op = DATA_PROCESSING_OPS[opcode as usize];
+		match opcode {
+			// NOTE: The comparisons always set the flags and have no destination
+			0x8..=0xb => Instruction::new(format!("{}{}", op, cond), format!("{}, {}", register(rn), operand)),
+			0xd | 0xf => Instruction::new(format!("{}{}{}", op, s, cond), format!("{}, {}", register(rd), operand)),
+			_ => Instruction::new(format!("{}{}{}", op, s, cond), format!("{}, {}, {}", register(rd), register(rn), operand)),
+		}
 	} else {
-		"Missing instruction!".to_string()
+		Instruction::undefined()
 	}
 }
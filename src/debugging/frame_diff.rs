@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::debugging::ab_compare::hash_frame;
+
+pub const FRAME_WIDTH: usize = 240;
+pub const FRAME_HEIGHT: usize = 160;
+
+/// Golden frames of assert_frame_matches!, <fixture>.png
+pub const FRAME_FIXTURES_DIRECTORY: &str = "tests/frames";
+/// Where assert_frame_matches! writes the frames of the failed comparisons
+pub const FRAME_FAILURES_DIRECTORY: &str = "target/frame_failures";
+
+/// Result of comparing a rendered frame against a reference screenshot
+pub struct FrameDiff {
+	pub mismatched_pixels: usize,
+	/// (x, y) of the first mismatched pixel in scanline order
+	pub first_mismatch: Option<(usize, usize)>,
+	/// RGB triplets of the frame dimmed, with mismatched pixels highlighted in red
+	pub pixels: Vec<f32>,
+}
+
+/// Load a 240x160 screenshot (eg. from mGBA or NO$GBA) as RGB8 triplets
+pub fn load_reference_png(path: &Path) -> std::io::Result<Vec<u8>> {
+	let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+	let mut reader = decoder.read_info().map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+	let mut buffer = vec![0; reader.output_buffer_size()];
+	let info = reader.next_frame(&mut buffer).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+	if info.width as usize != FRAME_WIDTH || info.height as usize != FRAME_HEIGHT || info.bit_depth != png::BitDepth::Eight {
+		return Err(Error::new(ErrorKind::InvalidData, "Reference must be a 240x160 8 bit PNG"));
+	}
+
+	let channels = match info.color_type {
+		png::ColorType::Rgb => 3,
+		png::ColorType::Rgba => 4,
+		_ => return Err(Error::new(ErrorKind::InvalidData, "Reference must be an RGB(A) PNG")),
+	};
+
+	Ok(buffer[..info.buffer_size()].chunks(channels).flat_map(|pixel| pixel[..3].iter().cloned()).collect())
+}
+
+/// Write RGB triplets (240x160) to a PNG file
+pub fn save_frame_png(path: &Path, pixels: &[f32]) -> std::io::Result<()> {
+	let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), FRAME_WIDTH as u32, FRAME_HEIGHT as u32);
+	encoder.set_color(png::ColorType::Rgb);
+	encoder.set_depth(png::BitDepth::Eight);
+
+	let data: Vec<u8> = pixels.iter().map(|channel| (channel * 255.0).round() as u8).collect();
+	let mut writer = encoder.write_header().map_err(Error::other)?;
+	writer.write_image_data(&data).map_err(Error::other)
+}
+
+/// Compare a rendered frame against a reference screenshot
+///
+/// Channels are compared at the GBA's 5 bit precision, so different 5 to 8 bit expansions don't count as mismatches
+pub fn diff_frames(frame: &[f32], reference: &[u8]) -> FrameDiff {
+	let mut mismatched_pixels = 0;
+	let mut first_mismatch = None;
+	let mut pixels = vec![0.0; FRAME_WIDTH * FRAME_HEIGHT * 3];
+
+	for i in 0..FRAME_WIDTH * FRAME_HEIGHT {
+		let rendered = &frame[i * 3..i * 3 + 3];
+		let expected = &reference[i * 3..i * 3 + 3];
+
+		let matches = rendered.iter().zip(expected).all(|(r, e)| ((r * 255.0).round() as u8) >> 3 == e >> 3);
+		if matches {
+			for channel in 0..3 {
+				pixels[i * 3 + channel] = rendered[channel] * 0.25;
+			}
+		} else {
+			if first_mismatch.is_none() {
+				first_mismatch = Some((i % FRAME_WIDTH, i / FRAME_WIDTH));
+			}
+			mismatched_pixels += 1;
+
+			pixels[i * 3] = 1.0;
+		}
+	}
+
+	FrameDiff {
+		mismatched_pixels,
+		first_mismatch,
+		pixels,
+	}
+}
+
+/// Compare a rendered frame against the golden frame of a fixture, the error describes the mismatch
+///
+/// On failure <fixture>_actual.png, <fixture>_expected.png and <fixture>_diff.png are written to FRAME_FAILURES_DIRECTORY.
+/// NOTE: A missing golden frame fails too, the rendered frame is written so it can be reviewed and copied to FRAME_FIXTURES_DIRECTORY
+pub fn check_frame_matches(frame: &[f32], fixture: &str) -> Result<(), String> {
+	let fixture_path = Path::new(FRAME_FIXTURES_DIRECTORY).join(format!("{}.png", fixture));
+	let failure_path = |suffix: &str| -> PathBuf { Path::new(FRAME_FAILURES_DIRECTORY).join(format!("{}_{}.png", fixture, suffix)) };
+	let write_failure = |suffix: &str, pixels: &[f32]| {
+		let path = failure_path(suffix);
+		if let Err(e) = std::fs::create_dir_all(FRAME_FAILURES_DIRECTORY).and_then(|_| save_frame_png(&path, pixels)) {
+			eprintln!("Failed to write {}: {}", path.display(), e);
+		}
+	};
+
+	let reference = match load_reference_png(&fixture_path) {
+		Ok(reference) => reference,
+		Err(e) => {
+			write_failure("actual", frame);
+			return Err(format!(
+				"Golden frame {} couldn't be loaded ({}), the rendered frame is in {}",
+				fixture_path.display(),
+				e,
+				failure_path("actual").display()
+			));
+		}
+	};
+
+	// NOTE: Identical hashes skip the per pixel comparison, which only runs to allow the 5 to 8 bit expansion differences
+	if hash_frame(frame) == crc32fast::hash(&reference) {
+		return Ok(());
+	}
+
+	let diff = diff_frames(frame, &reference);
+	if diff.mismatched_pixels == 0 {
+		return Ok(());
+	}
+
+	let expected: Vec<f32> = reference.iter().map(|channel| *channel as f32 / 255.0).collect();
+	write_failure("actual", frame);
+	write_failure("expected", &expected);
+	write_failure("diff", &diff.pixels);
+	let (x, y) = diff.first_mismatch.unwrap_or_default();
+	Err(format!(
+		"Frame doesn't match {}: {} pixels differ, first at ({}, {}), see {}",
+		fixture_path.display(),
+		diff.mismatched_pixels,
+		x,
+		y,
+		failure_path("diff").display()
+	))
+}
+
+/// Render the frame of a Gba and compare it against the golden frame of a fixture, see check_frame_matches
+///
+/// `assert_frame_matches!(gba, "hello")` compares against tests/frames/hello.png
+#[macro_export]
+macro_rules! assert_frame_matches {
+	($gba:expr, $fixture:expr) => {
+		if let Err(message) = $crate::debugging::frame_diff::check_frame_matches(&$gba.render(), $fixture) {
+			panic!("{}", message);
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gba::Gba;
+
+	fn run_rom(rom: &[u8], frames: usize) -> Gba {
+		let mut gba = Gba::new(Box::new([]), rom.to_vec().into_boxed_slice());
+		for _ in 0..frames {
+			gba.run_frame();
+		}
+		gba
+	}
+
+	#[test]
+	fn hello_matches_its_golden_frame() {
+		let mut gba = run_rom(include_bytes!("../../tests/ppu/hello.gba"), 10);
+		crate::assert_frame_matches!(gba, "hello");
+	}
+
+	#[test]
+	fn stripes_matches_its_golden_frame() {
+		let mut gba = run_rom(include_bytes!("../../tests/ppu/stripes.gba"), 10);
+		crate::assert_frame_matches!(gba, "stripes");
+	}
+
+	#[test]
+	fn shades_matches_its_golden_frame() {
+		let mut gba = run_rom(include_bytes!("../../tests/ppu/shades.gba"), 10);
+		crate::assert_frame_matches!(gba, "shades");
+	}
+
+	#[test]
+	fn mismatched_frame_is_reported() {
+		let mut frame = run_rom(include_bytes!("../../tests/ppu/stripes.gba"), 10).render();
+		frame[(3 + 2 * FRAME_WIDTH) * 3] = 1.0 - frame[(3 + 2 * FRAME_WIDTH) * 3];
+		let message = check_frame_matches(&frame, "stripes").unwrap_err();
+		assert!(message.contains("1 pixels differ, first at (3, 2)"), "{}", message);
+	}
+}
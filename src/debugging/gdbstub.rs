@@ -0,0 +1,504 @@
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::arm7tdmi::cpu::PROGRAM_COUNTER_REGISTER;
+use crate::arm7tdmi::EOperatingMode;
+use crate::gba::{Gba, CYCLES_PER_FRAME};
+use crate::system::{EWatchKind, MemoryInterface, Watchpoint};
+
+/// Address the GDB server listens on by default, only reachable from this machine
+pub const DEFAULT_GDB_SERVER_ADDRESS: &str = "127.0.0.1:2345";
+/// Longest packet accepted, also announced to GDB so it splits the memory accesses
+const MAX_PACKET_LENGTH: usize = 0x4000;
+/// GDB number of the CPSR in the ARM target description, the registers in between are the FPA ones the GBA doesn't have
+const GDB_CPSR_REGISTER: usize = 25;
+
+/// Registers GDB knows the CPU by, sent as qXfer:features:read:target.xml
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<architecture>armv4t</architecture>
+<feature name="org.gnu.gdb.arm.core">
+<reg name="r0" bitsize="32" type="uint32"/>
+<reg name="r1" bitsize="32" type="uint32"/>
+<reg name="r2" bitsize="32" type="uint32"/>
+<reg name="r3" bitsize="32" type="uint32"/>
+<reg name="r4" bitsize="32" type="uint32"/>
+<reg name="r5" bitsize="32" type="uint32"/>
+<reg name="r6" bitsize="32" type="uint32"/>
+<reg name="r7" bitsize="32" type="uint32"/>
+<reg name="r8" bitsize="32" type="uint32"/>
+<reg name="r9" bitsize="32" type="uint32"/>
+<reg name="r10" bitsize="32" type="uint32"/>
+<reg name="r11" bitsize="32" type="uint32"/>
+<reg name="r12" bitsize="32" type="uint32"/>
+<reg name="sp" bitsize="32" type="data_ptr"/>
+<reg name="lr" bitsize="32"/>
+<reg name="pc" bitsize="32" type="code_ptr"/>
+<reg name="cpsr" bitsize="32" regnum="25"/>
+</feature>
+</target>
+"#;
+
+/// Why the core stopped, reported to GDB in the stop reply
+#[derive(Debug, Copy, Clone)]
+enum EStopReason {
+	/// Breakpoint, single step or a pause requested with Ctrl-C
+	Trap,
+	Interrupt,
+	Watchpoint(Watchpoint, u32),
+}
+
+impl EStopReason {
+	fn get_reply(&self) -> String {
+		match self {
+			EStopReason::Trap => "S05".to_string(),
+			EStopReason::Interrupt => "S02".to_string(),
+			EStopReason::Watchpoint(watchpoint, address) => {
+				let name = match watchpoint.kind {
+					EWatchKind::Read => "rwatch",
+					EWatchKind::Write => "watch",
+					EWatchKind::Access => "awatch",
+				};
+				format!("T05{}:{:08x};", name, address)
+			}
+		}
+	}
+}
+
+fn encode_hex(data: &[u8]) -> String {
+	data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+	if text.len() % 2 != 0 {
+		return None;
+	}
+
+	(0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// A register as GDB sends it: 4 bytes of hex, little endian
+fn decode_register(text: &str) -> Option<u32> {
+	let bytes = decode_hex(text)?;
+	Some(u32::from_le_bytes([*bytes.first()?, *bytes.get(1)?, *bytes.get(2)?, *bytes.get(3)?]))
+}
+
+/// "address,length" arguments of the memory and breakpoint packets, in hex
+fn parse_address_length(text: &str) -> Option<(u32, u32)> {
+	let (address, length) = text.split_once(',')?;
+	Some((u32::from_str_radix(address, 16).ok()?, u32::from_str_radix(length, 16).ok()?))
+}
+
+/// Server of the GDB remote serial protocol, so gdb-multiarch (`target remote`) or Ghidra's debugger can debug the game
+///
+/// Supports the registers, memory, single steps and software/hardware breakpoints and watchpoints.
+/// NOTE: One client at a time, the breakpoints and watchpoints are removed and the core resumes when it detaches
+pub struct GdbServer {
+	listener: TcpListener,
+	client: Option<TcpStream>,
+	/// Bytes received that don't make a whole packet yet
+	input: Vec<u8>,
+	breakpoints: Vec<u32>,
+	paused: bool,
+	/// GDB asked to stop acknowledging the packets (QStartNoAckMode)
+	no_ack: bool,
+}
+
+impl GdbServer {
+	pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+		let listener = TcpListener::bind(address)?;
+		listener.set_nonblocking(true)?;
+		Ok(Self {
+			listener,
+			client: None,
+			input: Vec::new(),
+			breakpoints: Vec::new(),
+			paused: false,
+			no_ack: false,
+		})
+	}
+
+	pub fn get_local_address(&self) -> Result<SocketAddr> {
+		self.listener.local_addr()
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	pub fn is_attached(&self) -> bool {
+		self.client.is_some()
+	}
+
+	/// Accept a client and answer its packets, call regularly from the loop running the core
+	/// NOTE: The core is paused when GDB attaches, like a process stopped by ptrace
+	pub fn poll(&mut self, gba: &mut Gba) {
+		if self.client.is_none() {
+			match self.listener.accept() {
+				Ok((stream, _)) => match stream.set_nonblocking(true).and_then(|_| stream.set_nodelay(true)) {
+					Ok(()) => {
+						self.client = Some(stream);
+						self.input.clear();
+						self.no_ack = false;
+						self.paused = true;
+					}
+					Err(e) => eprintln!("Failed to set up the GDB connection: {}", e),
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+				Err(e) => eprintln!("Failed to accept a GDB connection: {}", e),
+			}
+		}
+
+		let mut buffer = [0; 0x1000];
+		while let Some(stream) = self.client.as_mut() {
+			match stream.read(&mut buffer) {
+				Ok(0) => {
+					self.detach(gba);
+					return;
+				}
+				Ok(length) => self.input.extend_from_slice(&buffer[..length]),
+				Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+				Err(e) => {
+					eprintln!("GDB connection lost: {}", e);
+					self.detach(gba);
+					return;
+				}
+			}
+		}
+
+		if let Err(e) = self.handle_input(gba) {
+			eprintln!("GDB connection lost: {}", e);
+			self.detach(gba);
+		}
+	}
+
+	/// Run a frame unless paused, stopping at the breakpoints and watchpoints
+	pub fn run_frame(&mut self, gba: &mut Gba) {
+		if self.paused {
+			return;
+		}
+
+		for _ in 0..CYCLES_PER_FRAME {
+			let executed = gba.step();
+			if let Some((watchpoint, address)) = gba.bus.take_watchpoint_hit() {
+				self.stop(EStopReason::Watchpoint(watchpoint, address));
+				return;
+			}
+			if executed && self.breakpoints.contains(&gba.cpu.get_current_pc()) {
+				self.stop(EStopReason::Trap);
+				return;
+			}
+		}
+	}
+
+	fn stop(&mut self, reason: EStopReason) {
+		self.paused = true;
+		if let Err(e) = self.send_packet(&reason.get_reply()) {
+			eprintln!("GDB connection lost: {}", e);
+			self.client = None;
+		}
+	}
+
+	fn detach(&mut self, gba: &mut Gba) {
+		self.client = None;
+		self.breakpoints.clear();
+		for watchpoint in gba.bus.get_watchpoints().to_vec() {
+			gba.bus.remove_watchpoint(watchpoint);
+		}
+		self.paused = false;
+	}
+
+	/// Answer the whole packets received, packets are "$data#checksum" and acknowledged with '+', Ctrl-C (0x03) pauses the core
+	fn handle_input(&mut self, gba: &mut Gba) -> Result<()> {
+		loop {
+			match self.input.first() {
+				None => return Ok(()),
+				Some(0x03) => {
+					self.input.remove(0);
+					if !self.paused {
+						self.stop(EStopReason::Interrupt);
+					}
+					continue;
+				}
+				Some(b'$') => {}
+				// NOTE: Acknowledgements of our packets and line noise, nothing is resent
+				Some(_) => {
+					self.input.remove(0);
+					continue;
+				}
+			}
+
+			let end = match self.input.iter().position(|byte| *byte == b'#') {
+				Some(end) if self.input.len() >= end + 3 => end,
+				_ if self.input.len() > MAX_PACKET_LENGTH * 2 => {
+					self.input.clear();
+					return Ok(());
+				}
+				_ => return Ok(()),
+			};
+
+			let data = String::from_utf8_lossy(&self.input[1..end]).into_owned();
+			let checksum = std::str::from_utf8(&self.input[end + 1..end + 3]).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+			self.input.drain(..end + 3);
+
+			let is_valid = checksum == Some(data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte)));
+			if !self.no_ack {
+				self.send_raw(if is_valid { b"+" } else { b"-" })?;
+			}
+			if is_valid {
+				if let Some(reply) = self.execute_packet(&data, gba) {
+					self.send_packet(&reply)?;
+				}
+				if self.client.is_none() {
+					return Ok(());
+				}
+			}
+		}
+	}
+
+	fn send_raw(&mut self, data: &[u8]) -> Result<()> {
+		match self.client.as_mut() {
+			Some(stream) => {
+				// NOTE: The stream is non blocking for the reads, the replies are small enough to be written at once
+				stream.set_nonblocking(false)?;
+				stream.write_all(data)?;
+				stream.set_nonblocking(true)
+			}
+			None => Ok(()),
+		}
+	}
+
+	fn send_packet(&mut self, data: &str) -> Result<()> {
+		let mut escaped = Vec::with_capacity(data.len() + 4);
+		for byte in data.bytes() {
+			if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+				escaped.extend_from_slice(&[b'}', byte ^ 0x20]);
+			} else {
+				escaped.push(byte);
+			}
+		}
+		let checksum = escaped.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+		let mut packet = vec![b'$'];
+		packet.extend_from_slice(&escaped);
+		packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+		self.send_raw(&packet)
+	}
+
+	/// Reply to a packet, None when the reply comes later (the stop reply of a continue)
+	/// NOTE: Unsupported packets get an empty reply, GDB then falls back to simpler ones
+	fn execute_packet(&mut self, data: &str, gba: &mut Gba) -> Option<String> {
+		let (command, arguments) = data.split_at(data.chars().next().map_or(0, |c| c.len_utf8()));
+		let reply = match command {
+			"?" => EStopReason::Trap.get_reply(),
+			"g" => {
+				let mut registers: Vec<u8> = (0..16).flat_map(|index| self.read_register(gba, index).to_le_bytes()).collect();
+				registers.extend_from_slice(&self.read_register(gba, GDB_CPSR_REGISTER).to_le_bytes());
+				encode_hex(&registers)
+			}
+			"G" => {
+				let values: Option<Vec<u32>> = (0..17).map(|i| arguments.get(i * 8..i * 8 + 8).and_then(decode_register)).collect();
+				match values {
+					Some(values) => {
+						for (index, value) in values.iter().enumerate() {
+							self.write_register(gba, if index == 16 { GDB_CPSR_REGISTER } else { index }, *value);
+						}
+						"OK".to_string()
+					}
+					None => "E01".to_string(),
+				}
+			}
+			"p" => match usize::from_str_radix(arguments, 16) {
+				Ok(index) if index < 16 || index == GDB_CPSR_REGISTER => encode_hex(&self.read_register(gba, index).to_le_bytes()),
+				// NOTE: The FPA registers of the old ARM target descriptions
+				Ok(_) => "00000000".to_string(),
+				Err(_) => "E01".to_string(),
+			},
+			"P" => {
+				let register = arguments
+					.split_once('=')
+					.and_then(|(index, value)| Some((usize::from_str_radix(index, 16).ok()?, decode_register(value)?)));
+				match register {
+					Some((index, value)) if index < 16 || index == GDB_CPSR_REGISTER => {
+						if self.write_register(gba, index, value) {
+							"OK".to_string()
+						} else {
+							"E02".to_string()
+						}
+					}
+					_ => "E01".to_string(),
+				}
+			}
+			"m" => match parse_address_length(arguments) {
+				Some((address, length)) if (length as usize) < MAX_PACKET_LENGTH / 2 => {
+					let data: Vec<u8> = (0..length).map(|offset| gba.bus.read_8(address.wrapping_add(offset))).collect();
+					// NOTE: Accesses of the debugger aren't anomalies of the game and don't hit the watchpoints
+					gba.bus.take_anomaly();
+					gba.bus.take_watchpoint_hit();
+					encode_hex(&data)
+				}
+				_ => "E01".to_string(),
+			},
+			"M" => {
+				let write = arguments.split_once(':').and_then(|(range, data)| Some((parse_address_length(range)?, decode_hex(data)?)));
+				match write {
+					Some(((address, length), data)) if data.len() == length as usize => {
+						// NOTE: Patched like the other debugger writes, so breakpoints can be set in ROM
+						gba.bus.patch(address, &data);
+						"OK".to_string()
+					}
+					_ => "E01".to_string(),
+				}
+			}
+			"c" => {
+				if let Ok(address) = u32::from_str_radix(arguments, 16) {
+					gba.cpu.set_pc(address);
+				}
+				self.paused = false;
+				return None;
+			}
+			"s" => {
+				if let Ok(address) = u32::from_str_radix(arguments, 16) {
+					gba.cpu.set_pc(address);
+				}
+				gba.step_instruction();
+				gba.bus.take_watchpoint_hit();
+				self.paused = true;
+				EStopReason::Trap.get_reply()
+			}
+			"Z" | "z" => self.set_breakpoint(gba, command == "Z", arguments),
+			"D" => {
+				self.send_packet("OK").ok();
+				self.detach(gba);
+				return None;
+			}
+			"k" => {
+				self.detach(gba);
+				return None;
+			}
+			// NOTE: The GBA has a single thread
+			"H" | "T" => "OK".to_string(),
+			"q" | "Q" | "v" => self.execute_query(data),
+			_ => String::new(),
+		};
+
+		Some(reply)
+	}
+
+	fn execute_query(&mut self, data: &str) -> String {
+		if data.starts_with("qSupported") {
+			return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", MAX_PACKET_LENGTH);
+		}
+		if let Some(range) = data.strip_prefix("qXfer:features:read:target.xml:") {
+			return match parse_address_length(range) {
+				Some((offset, length)) => {
+					let start = (offset as usize).min(TARGET_XML.len());
+					let end = (start + length as usize).min(TARGET_XML.len());
+					format!("{}{}", if end == TARGET_XML.len() { 'l' } else { 'm' }, &TARGET_XML[start..end])
+				}
+				None => "E01".to_string(),
+			};
+		}
+
+		match data {
+			"QStartNoAckMode" => {
+				self.no_ack = true;
+				"OK".to_string()
+			}
+			"qAttached" => "1".to_string(),
+			"qC" => "QC1".to_string(),
+			"qfThreadInfo" => "m1".to_string(),
+			"qsThreadInfo" => "l".to_string(),
+			_ => String::new(),
+		}
+	}
+
+	/// Z/z type,address,kind: type 0 and 1 are breakpoints, 2, 3 and 4 are write, read and access watchpoints of kind bytes
+	fn set_breakpoint(&mut self, gba: &mut Gba, insert: bool, arguments: &str) -> String {
+		let mut fields = arguments.splitn(3, ',');
+		let kind = fields.next();
+		let address = fields.next().and_then(|address| u32::from_str_radix(address, 16).ok());
+		let length = fields.next().and_then(|length| u32::from_str_radix(length, 16).ok());
+		let (address, length) = match (address, length) {
+			(Some(address), Some(length)) => (address, length),
+			_ => return "E01".to_string(),
+		};
+
+		let watch_kind = match kind {
+			Some("0") | Some("1") => {
+				if insert && !self.breakpoints.contains(&address) {
+					self.breakpoints.push(address);
+				} else if !insert {
+					self.breakpoints.retain(|breakpoint| *breakpoint != address);
+				}
+				return "OK".to_string();
+			}
+			Some("2") => EWatchKind::Write,
+			Some("3") => EWatchKind::Read,
+			Some("4") => EWatchKind::Access,
+			_ => return String::new(),
+		};
+
+		let watchpoint = Watchpoint {
+			address,
+			length,
+			kind: watch_kind,
+		};
+		if insert {
+			gba.bus.add_watchpoint(watchpoint);
+		} else {
+			gba.bus.remove_watchpoint(watchpoint);
+		}
+		"OK".to_string()
+	}
+
+	/// R0-R15 or the CPSR (GDB_CPSR_REGISTER), PC is the address of the next instruction
+	fn read_register(&self, gba: &Gba, index: usize) -> u32 {
+		match index {
+			GDB_CPSR_REGISTER => gba.cpu.get_cpsr().0,
+			_ => gba.cpu.get_registers()[index],
+		}
+	}
+
+	/// Returns false if the value is refused (a CPSR with an invalid mode)
+	fn write_register(&self, gba: &mut Gba, index: usize, value: u32) -> bool {
+		match index {
+			GDB_CPSR_REGISTER => gba.cpu.set_psr_value(EOperatingMode::UserMode, value),
+			_ if index == PROGRAM_COUNTER_REGISTER as usize => {
+				gba.cpu.set_pc(value);
+				true
+			}
+			_ => {
+				gba.cpu.set_register_value(index as u8, value);
+				true
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn setup() -> (GdbServer, Gba) {
+		let server = GdbServer::bind("127.0.0.1:0").unwrap();
+		let gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		(server, gba)
+	}
+
+	#[test]
+	fn write_memory_patches_the_rom() {
+		let (mut server, mut gba) = setup();
+		assert_eq!(server.execute_packet("M8000100,4:01020304", &mut gba), Some("OK".to_string()));
+		assert_eq!(gba.bus.read_32(0x0800_0100), 0x0403_0201);
+		assert_eq!(gba.bus.get_rom_write_attempts(), 0);
+	}
+
+	#[test]
+	fn write_memory_writes_every_byte() {
+		let (mut server, mut gba) = setup();
+		assert_eq!(server.execute_packet("M3000001,3:aabbcc", &mut gba), Some("OK".to_string()));
+		assert_eq!(server.execute_packet("m3000000,4", &mut gba), Some("00aabbcc".to_string()));
+	}
+}
@@ -0,0 +1,130 @@
+use crate::debugging::ram_search::get_size;
+use crate::ppu::VRAM_SIZE;
+use crate::system::{EAccessWidth, MemoryInterface, SystemBus, VRAM_ADDR};
+
+/// Bytes shown per row of the hex view
+pub const HEX_EDITOR_ROW_LENGTH: u32 = 16;
+
+/// Editable hex view of the memory from a base address, VRAM by default
+/// NOTE: Values are little endian and aligned to their width, like the accesses of the game
+#[derive(Debug, Clone)]
+pub struct HexEditor {
+	base_address: u32,
+	length: u32,
+	width: EAccessWidth,
+	selected: Option<u32>,
+	/// Whether the editing field of the selected value should take the keyboard focus, set when the selection moves
+	focus_selected: bool,
+}
+
+impl HexEditor {
+	pub fn new() -> Self {
+		Self {
+			base_address: VRAM_ADDR,
+			length: VRAM_SIZE as u32,
+			width: EAccessWidth::Byte,
+			selected: None,
+			focus_selected: false,
+		}
+	}
+
+	pub fn get_base_address(&self) -> u32 {
+		self.base_address
+	}
+
+	pub fn get_length(&self) -> u32 {
+		self.length
+	}
+
+	/// Show a region of the memory, the view starts at the row of the address and ends at the end of the address space
+	pub fn set_region(&mut self, address: u32, length: u32) {
+		self.base_address = address & !(HEX_EDITOR_ROW_LENGTH - 1);
+		let max_length = (u32::MAX - self.base_address).saturating_add(1) & !(HEX_EDITOR_ROW_LENGTH - 1);
+		self.length = length.saturating_add(HEX_EDITOR_ROW_LENGTH - 1).clamp(HEX_EDITOR_ROW_LENGTH, max_length) & !(HEX_EDITOR_ROW_LENGTH - 1);
+		self.selected = self.selected.filter(|address| self.contains(*address));
+	}
+
+	pub fn get_row_count(&self) -> u32 {
+		self.length / HEX_EDITOR_ROW_LENGTH
+	}
+
+	pub fn contains(&self, address: u32) -> bool {
+		address >= self.base_address && address - self.base_address < self.length
+	}
+
+	pub fn get_width(&self) -> EAccessWidth {
+		self.width
+	}
+
+	pub fn set_width(&mut self, width: EAccessWidth) {
+		self.width = width;
+		self.selected = self.selected.map(|address| self.align(address));
+	}
+
+	pub fn get_selected(&self) -> Option<u32> {
+		self.selected
+	}
+
+	pub fn select(&mut self, address: Option<u32>) {
+		self.selected = address.map(|address| self.align(address)).filter(|address| self.contains(*address));
+		self.focus_selected = self.selected.is_some();
+	}
+
+	/// Select the value after the selected one, the next to edit once a value is written
+	pub fn select_next(&mut self) {
+		let next = self.selected.and_then(|address| address.checked_add(get_size(self.width) as u32));
+		self.select(next);
+	}
+
+	/// Whether the editing field should take the keyboard focus, only once per selection
+	pub fn take_focus_selected(&mut self) -> bool {
+		std::mem::take(&mut self.focus_selected)
+	}
+
+	/// Show the region around an address and select it, the current region is kept if it's already inside
+	pub fn go_to(&mut self, address: u32) {
+		if !self.contains(address) {
+			self.set_region(address, self.length);
+		}
+		self.select(Some(address));
+	}
+
+	/// Write a value of the current width at an address
+	/// NOTE: The writes go through the bus like those of the game (eg. writing DMA or sound registers triggers them), except on the ROM which is patched
+	pub fn write(&self, bus: &mut SystemBus, address: u32, value: u32) {
+		let address = self.align(address);
+		if SystemBus::resolve(address).0.is_cartridge_rom() {
+			bus.patch(address, &value.to_le_bytes()[..get_size(self.width)]);
+			return;
+		}
+
+		match self.width {
+			EAccessWidth::Byte => bus.write_8(address, value as u8),
+			EAccessWidth::Halfword => bus.write_16(address, value as u16),
+			EAccessWidth::Word => bus.write_32(address, value),
+		}
+		// NOTE: Not an access of the game, it isn't an anomaly or a watchpoint hit
+		bus.take_anomaly();
+		bus.take_watchpoint_hit();
+	}
+
+	fn align(&self, address: u32) -> u32 {
+		address & !(get_size(self.width) as u32 - 1)
+	}
+}
+
+impl Default for HexEditor {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Value of the current width at an offset of the bytes of a row
+pub fn get_row_value(row: &[u8], offset: usize, width: EAccessWidth) -> u32 {
+	row[offset..offset + get_size(width)].iter().rev().fold(0, |value, byte| value << 8 | *byte as u32)
+}
+
+/// ASCII pane of a row, non printable bytes are shown as dots
+pub fn to_ascii(row: &[u8]) -> String {
+	row.iter().map(|byte| if (0x20..0x7f).contains(byte) { *byte as char } else { '.' }).collect()
+}
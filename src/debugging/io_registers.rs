@@ -0,0 +1,472 @@
+use crate::system::IO_ADDR;
+use crate::{apu, ppu};
+
+/// Hardware block an I/O register belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EIoGroup {
+	Lcd,
+	Sound,
+	Dma,
+	Timer,
+	Serial,
+	Keypad,
+	Interrupt,
+	System,
+}
+
+impl EIoGroup {
+	pub const ALL: [EIoGroup; 8] = [
+		EIoGroup::Lcd,
+		EIoGroup::Sound,
+		EIoGroup::Dma,
+		EIoGroup::Timer,
+		EIoGroup::Serial,
+		EIoGroup::Keypad,
+		EIoGroup::Interrupt,
+		EIoGroup::System,
+	];
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			EIoGroup::Lcd => "LCD",
+			EIoGroup::Sound => "Sound",
+			EIoGroup::Dma => "DMA",
+			EIoGroup::Timer => "Timer",
+			EIoGroup::Serial => "Serial",
+			EIoGroup::Keypad => "Keypad",
+			EIoGroup::Interrupt => "Interrupt",
+			EIoGroup::System => "System",
+		}
+	}
+}
+
+/// Named bit field of a register: (name, first bit, last bit)
+pub type IoField = (&'static str, usize, usize);
+
+/// 16 bit I/O register, 32 bit ones are split in their low and high halves like in GBATEK
+#[derive(Debug, Copy, Clone)]
+pub struct IoRegister {
+	pub name: &'static str,
+	pub address: u32,
+	pub group: EIoGroup,
+	/// Empty when the whole halfword is a single value (scroll, counter, address...)
+	pub fields: &'static [IoField],
+}
+
+impl IoRegister {
+	const fn new(name: &'static str, address: u32, group: EIoGroup, fields: &'static [IoField]) -> Self {
+		Self { name, address, group, fields }
+	}
+}
+
+/// Value of a field in a register value
+pub fn get_field_value(value: u16, (_, first, last): IoField) -> u16 {
+	(value >> first) & (u16::MAX >> (15 - (last - first)))
+}
+
+/// Register value with a field replaced, the value is truncated to the width of the field
+pub fn set_field_value(value: u16, (_, first, last): IoField, field_value: u16) -> u16 {
+	let mask = (u16::MAX >> (15 - (last - first))) << first;
+	(value & !mask) | ((field_value << first) & mask)
+}
+
+const BG_CNT_FIELDS: &[IoField] = &[
+	("Priority", 0, 1),
+	("Character Base", 2, 3),
+	("Mosaic", 6, 6),
+	("256 Colors", 7, 7),
+	("Screen Base", 8, 12),
+	("Wraparound", 13, 13),
+	("Size", 14, 15),
+];
+const BG_OFFSET_FIELDS: &[IoField] = &[("Offset", 0, 8)];
+const WINDOW_H_FIELDS: &[IoField] = &[("X2", 0, 7), ("X1", 8, 15)];
+const WINDOW_V_FIELDS: &[IoField] = &[("Y2", 0, 7), ("Y1", 8, 15)];
+const SQUARE_DUTY_FIELDS: &[IoField] = &[
+	("Length", 0, 5),
+	("Duty", 6, 7),
+	("Envelope Step", 8, 10),
+	("Envelope Increase", 11, 11),
+	("Initial Volume", 12, 15),
+];
+const FREQUENCY_FIELDS: &[IoField] = &[("Frequency", 0, 10), ("Length Flag", 14, 14), ("Restart", 15, 15)];
+const DMA_CNT_H_FIELDS: &[IoField] = &[
+	("Destination Control", 5, 6),
+	("Source Control", 7, 8),
+	("Repeat", 9, 9),
+	("32 Bit", 10, 10),
+	("Game Pak DRQ", 11, 11),
+	("Timing", 12, 13),
+	("IRQ", 14, 14),
+	("Enable", 15, 15),
+];
+const TIMER_CNT_H_FIELDS: &[IoField] = &[("Prescaler", 0, 1), ("Count-Up", 2, 2), ("IRQ", 6, 6), ("Enable", 7, 7)];
+const KEY_FIELDS: &[IoField] = &[
+	("A", 0, 0),
+	("B", 1, 1),
+	("Select", 2, 2),
+	("Start", 3, 3),
+	("Right", 4, 4),
+	("Left", 5, 5),
+	("Up", 6, 6),
+	("Down", 7, 7),
+	("R", 8, 8),
+	("L", 9, 9),
+];
+const INTERRUPT_FIELDS: &[IoField] = &[
+	("V-Blank", 0, 0),
+	("H-Blank", 1, 1),
+	("V-Count", 2, 2),
+	("Timer 0", 3, 3),
+	("Timer 1", 4, 4),
+	("Timer 2", 5, 5),
+	("Timer 3", 6, 6),
+	("Serial", 7, 7),
+	("DMA 0", 8, 8),
+	("DMA 1", 9, 9),
+	("DMA 2", 10, 10),
+	("DMA 3", 11, 11),
+	("Keypad", 12, 12),
+	("Game Pak", 13, 13),
+];
+
+/// The mapped I/O registers, in address order
+pub const IO_REGISTERS: &[IoRegister] = &[
+	IoRegister::new(
+		"DISPCNT",
+		IO_ADDR + ppu::DISP_CNT_ADDRESS,
+		EIoGroup::Lcd,
+		&[
+			("BG Mode", 0, 2),
+			("CGB Mode", 3, 3),
+			("Display Frame", 4, 4),
+			("H-Blank Interval Free", 5, 5),
+			("OBJ 1D Mapping", 6, 6),
+			("Forced Blank", 7, 7),
+			("BG0", 8, 8),
+			("BG1", 9, 9),
+			("BG2", 10, 10),
+			("BG3", 11, 11),
+			("OBJ", 12, 12),
+			("Window 0", 13, 13),
+			("Window 1", 14, 14),
+			("OBJ Window", 15, 15),
+		],
+	),
+	IoRegister::new(
+		"DISPSTAT",
+		IO_ADDR + ppu::DISP_STAT_ADDRESS,
+		EIoGroup::Lcd,
+		&[
+			("V-Blank", 0, 0),
+			("H-Blank", 1, 1),
+			("V-Count Match", 2, 2),
+			("V-Blank IRQ", 3, 3),
+			("H-Blank IRQ", 4, 4),
+			("V-Count IRQ", 5, 5),
+			("V-Count Setting", 8, 15),
+		],
+	),
+	IoRegister::new("VCOUNT", IO_ADDR + ppu::VCOUNT_ADDRESS, EIoGroup::Lcd, &[("Line", 0, 7)]),
+	IoRegister::new("BG0CNT", IO_ADDR + ppu::BG0_CNT_ADDRESS, EIoGroup::Lcd, BG_CNT_FIELDS),
+	IoRegister::new("BG1CNT", IO_ADDR + ppu::BG1_CNT_ADDRESS, EIoGroup::Lcd, BG_CNT_FIELDS),
+	IoRegister::new("BG2CNT", IO_ADDR + ppu::BG2_CNT_ADDRESS, EIoGroup::Lcd, BG_CNT_FIELDS),
+	IoRegister::new("BG3CNT", IO_ADDR + ppu::BG3_CNT_ADDRESS, EIoGroup::Lcd, BG_CNT_FIELDS),
+	IoRegister::new("BG0HOFS", IO_ADDR + ppu::BG0_HOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG0VOFS", IO_ADDR + ppu::BG0_VOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG1HOFS", IO_ADDR + ppu::BG1_HOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG1VOFS", IO_ADDR + ppu::BG1_VOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG2HOFS", IO_ADDR + ppu::BG2_HOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG2VOFS", IO_ADDR + ppu::BG2_VOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG3HOFS", IO_ADDR + ppu::BG3_HOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG3VOFS", IO_ADDR + ppu::BG3_VOFS_ADDRESS, EIoGroup::Lcd, BG_OFFSET_FIELDS),
+	IoRegister::new("BG2PA", IO_ADDR + ppu::BG2_PA_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2PB", IO_ADDR + ppu::BG2_PB_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2PC", IO_ADDR + ppu::BG2_PC_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2PD", IO_ADDR + ppu::BG2_PD_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2X_L", IO_ADDR + ppu::BG2_X_LO_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2X_H", IO_ADDR + ppu::BG2_X_HI_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2Y_L", IO_ADDR + ppu::BG2_Y_LO_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG2Y_H", IO_ADDR + ppu::BG2_Y_HI_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3PA", IO_ADDR + ppu::BG3_PA_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3PB", IO_ADDR + ppu::BG3_PB_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3PC", IO_ADDR + ppu::BG3_PC_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3PD", IO_ADDR + ppu::BG3_PD_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3X_L", IO_ADDR + ppu::BG3_X_LO_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3X_H", IO_ADDR + ppu::BG3_X_HI_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3Y_L", IO_ADDR + ppu::BG3_Y_LO_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("BG3Y_H", IO_ADDR + ppu::BG3_Y_HI_ADDRESS, EIoGroup::Lcd, &[]),
+	IoRegister::new("WIN0H", IO_ADDR + ppu::WIN0_H_ADDRESS, EIoGroup::Lcd, WINDOW_H_FIELDS),
+	IoRegister::new("WIN1H", IO_ADDR + ppu::WIN1_H_ADDRESS, EIoGroup::Lcd, WINDOW_H_FIELDS),
+	IoRegister::new("WIN0V", IO_ADDR + ppu::WIN0_V_ADDRESS, EIoGroup::Lcd, WINDOW_V_FIELDS),
+	IoRegister::new("WIN1V", IO_ADDR + ppu::WIN1_V_ADDRESS, EIoGroup::Lcd, WINDOW_V_FIELDS),
+	IoRegister::new(
+		"WININ",
+		IO_ADDR + ppu::WIN_IN_ADDRESS,
+		EIoGroup::Lcd,
+		&[
+			("Window 0 BG0", 0, 0),
+			("Window 0 BG1", 1, 1),
+			("Window 0 BG2", 2, 2),
+			("Window 0 BG3", 3, 3),
+			("Window 0 OBJ", 4, 4),
+			("Window 0 Effects", 5, 5),
+			("Window 1 BG0", 8, 8),
+			("Window 1 BG1", 9, 9),
+			("Window 1 BG2", 10, 10),
+			("Window 1 BG3", 11, 11),
+			("Window 1 OBJ", 12, 12),
+			("Window 1 Effects", 13, 13),
+		],
+	),
+	IoRegister::new(
+		"WINOUT",
+		IO_ADDR + ppu::WIN_OUT_ADDRESS,
+		EIoGroup::Lcd,
+		&[
+			("Outside BG0", 0, 0),
+			("Outside BG1", 1, 1),
+			("Outside BG2", 2, 2),
+			("Outside BG3", 3, 3),
+			("Outside OBJ", 4, 4),
+			("Outside Effects", 5, 5),
+			("OBJ Window BG0", 8, 8),
+			("OBJ Window BG1", 9, 9),
+			("OBJ Window BG2", 10, 10),
+			("OBJ Window BG3", 11, 11),
+			("OBJ Window OBJ", 12, 12),
+			("OBJ Window Effects", 13, 13),
+		],
+	),
+	IoRegister::new(
+		"MOSAIC",
+		IO_ADDR + ppu::MOSAIC_LO_ADDRESS,
+		EIoGroup::Lcd,
+		&[("BG Width", 0, 3), ("BG Height", 4, 7), ("OBJ Width", 8, 11), ("OBJ Height", 12, 15)],
+	),
+	IoRegister::new(
+		"BLDCNT",
+		IO_ADDR + ppu::BLD_CNT_ADDRESS,
+		EIoGroup::Lcd,
+		&[
+			("First BG0", 0, 0),
+			("First BG1", 1, 1),
+			("First BG2", 2, 2),
+			("First BG3", 3, 3),
+			("First OBJ", 4, 4),
+			("First Backdrop", 5, 5),
+			("Effect", 6, 7),
+			("Second BG0", 8, 8),
+			("Second BG1", 9, 9),
+			("Second BG2", 10, 10),
+			("Second BG3", 11, 11),
+			("Second OBJ", 12, 12),
+			("Second Backdrop", 13, 13),
+		],
+	),
+	IoRegister::new("BLDALPHA", IO_ADDR + ppu::BLD_ALPHA_ADDRESS, EIoGroup::Lcd, &[("EVA", 0, 4), ("EVB", 8, 12)]),
+	IoRegister::new("BLDY", IO_ADDR + ppu::BLD_Y_LO_ADDRESS, EIoGroup::Lcd, &[("EVY", 0, 4)]),
+	IoRegister::new(
+		"SOUND1CNT_L",
+		IO_ADDR + apu::SOUND1_CNT_L_ADDRESS,
+		EIoGroup::Sound,
+		&[("Sweep Shift", 0, 2), ("Sweep Decrease", 3, 3), ("Sweep Time", 4, 6)],
+	),
+	IoRegister::new("SOUND1CNT_H", IO_ADDR + apu::SOUND1_CNT_H_ADDRESS, EIoGroup::Sound, SQUARE_DUTY_FIELDS),
+	IoRegister::new("SOUND1CNT_X", IO_ADDR + apu::SOUND1_CNT_X_ADDRESS, EIoGroup::Sound, FREQUENCY_FIELDS),
+	IoRegister::new("SOUND2CNT_L", IO_ADDR + apu::SOUND2_CNT_L_ADDRESS, EIoGroup::Sound, SQUARE_DUTY_FIELDS),
+	IoRegister::new("SOUND2CNT_H", IO_ADDR + apu::SOUND2_CNT_H_ADDRESS, EIoGroup::Sound, FREQUENCY_FIELDS),
+	IoRegister::new(
+		"SOUND3CNT_L",
+		IO_ADDR + apu::SOUND3_CNT_L_ADDRESS,
+		EIoGroup::Sound,
+		&[("Two Banks", 5, 5), ("Bank", 6, 6), ("Playback", 7, 7)],
+	),
+	IoRegister::new(
+		"SOUND3CNT_H",
+		IO_ADDR + apu::SOUND3_CNT_H_ADDRESS,
+		EIoGroup::Sound,
+		&[("Length", 0, 7), ("Volume", 13, 14), ("Force 75%", 15, 15)],
+	),
+	IoRegister::new("SOUND3CNT_X", IO_ADDR + apu::SOUND3_CNT_X_ADDRESS, EIoGroup::Sound, FREQUENCY_FIELDS),
+	IoRegister::new(
+		"SOUND4CNT_L",
+		IO_ADDR + apu::SOUND4_CNT_L_ADDRESS,
+		EIoGroup::Sound,
+		&[("Length", 0, 5), ("Envelope Step", 8, 10), ("Envelope Increase", 11, 11), ("Initial Volume", 12, 15)],
+	),
+	IoRegister::new(
+		"SOUND4CNT_H",
+		IO_ADDR + apu::SOUND4_CNT_H_ADDRESS,
+		EIoGroup::Sound,
+		&[
+			("Dividing Ratio", 0, 2),
+			("7 Bit Counter", 3, 3),
+			("Shift", 4, 7),
+			("Length Flag", 14, 14),
+			("Restart", 15, 15),
+		],
+	),
+	IoRegister::new(
+		"SOUNDCNT_L",
+		IO_ADDR + apu::SOUND_CNT_L_ADDRESS,
+		EIoGroup::Sound,
+		&[
+			("Right Volume", 0, 2),
+			("Left Volume", 4, 6),
+			("Sound 1 Right", 8, 8),
+			("Sound 2 Right", 9, 9),
+			("Sound 3 Right", 10, 10),
+			("Sound 4 Right", 11, 11),
+			("Sound 1 Left", 12, 12),
+			("Sound 2 Left", 13, 13),
+			("Sound 3 Left", 14, 14),
+			("Sound 4 Left", 15, 15),
+		],
+	),
+	IoRegister::new(
+		"SOUNDCNT_H",
+		IO_ADDR + apu::SOUND_CNT_H_ADDRESS,
+		EIoGroup::Sound,
+		&[
+			("PSG Volume", 0, 1),
+			("FIFO A Full Volume", 2, 2),
+			("FIFO B Full Volume", 3, 3),
+			("FIFO A Right", 8, 8),
+			("FIFO A Left", 9, 9),
+			("FIFO A Timer 1", 10, 10),
+			("FIFO A Reset", 11, 11),
+			("FIFO B Right", 12, 12),
+			("FIFO B Left", 13, 13),
+			("FIFO B Timer 1", 14, 14),
+			("FIFO B Reset", 15, 15),
+		],
+	),
+	IoRegister::new(
+		"SOUNDCNT_X",
+		IO_ADDR + apu::SOUND_CNT_X_ADDRESS,
+		EIoGroup::Sound,
+		&[
+			("Sound 1 On", 0, 0),
+			("Sound 2 On", 1, 1),
+			("Sound 3 On", 2, 2),
+			("Sound 4 On", 3, 3),
+			("Master Enable", 7, 7),
+		],
+	),
+	IoRegister::new(
+		"SOUNDBIAS",
+		IO_ADDR + apu::SOUND_BIAS_ADDRESS,
+		EIoGroup::Sound,
+		&[("Bias Level", 1, 9), ("Resolution", 14, 15)],
+	),
+	IoRegister::new("DMA0SAD_L", 0x0400_00b0, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA0SAD_H", 0x0400_00b2, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA0DAD_L", 0x0400_00b4, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA0DAD_H", 0x0400_00b6, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA0CNT_L", 0x0400_00b8, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA0CNT_H", 0x0400_00ba, EIoGroup::Dma, DMA_CNT_H_FIELDS),
+	IoRegister::new("DMA1SAD_L", 0x0400_00bc, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA1SAD_H", 0x0400_00be, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA1DAD_L", 0x0400_00c0, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA1DAD_H", 0x0400_00c2, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA1CNT_L", 0x0400_00c4, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA1CNT_H", 0x0400_00c6, EIoGroup::Dma, DMA_CNT_H_FIELDS),
+	IoRegister::new("DMA2SAD_L", 0x0400_00c8, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA2SAD_H", 0x0400_00ca, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA2DAD_L", 0x0400_00cc, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA2DAD_H", 0x0400_00ce, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA2CNT_L", 0x0400_00d0, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA2CNT_H", 0x0400_00d2, EIoGroup::Dma, DMA_CNT_H_FIELDS),
+	IoRegister::new("DMA3SAD_L", 0x0400_00d4, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA3SAD_H", 0x0400_00d6, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA3DAD_L", 0x0400_00d8, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA3DAD_H", 0x0400_00da, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA3CNT_L", 0x0400_00dc, EIoGroup::Dma, &[]),
+	IoRegister::new("DMA3CNT_H", 0x0400_00de, EIoGroup::Dma, DMA_CNT_H_FIELDS),
+	IoRegister::new("TM0CNT_L", 0x0400_0100, EIoGroup::Timer, &[]),
+	IoRegister::new("TM0CNT_H", 0x0400_0102, EIoGroup::Timer, TIMER_CNT_H_FIELDS),
+	IoRegister::new("TM1CNT_L", 0x0400_0104, EIoGroup::Timer, &[]),
+	IoRegister::new("TM1CNT_H", 0x0400_0106, EIoGroup::Timer, TIMER_CNT_H_FIELDS),
+	IoRegister::new("TM2CNT_L", 0x0400_0108, EIoGroup::Timer, &[]),
+	IoRegister::new("TM2CNT_H", 0x0400_010a, EIoGroup::Timer, TIMER_CNT_H_FIELDS),
+	IoRegister::new("TM3CNT_L", 0x0400_010c, EIoGroup::Timer, &[]),
+	IoRegister::new("TM3CNT_H", 0x0400_010e, EIoGroup::Timer, TIMER_CNT_H_FIELDS),
+	IoRegister::new("SIODATA32_L", 0x0400_0120, EIoGroup::Serial, &[]),
+	IoRegister::new("SIODATA32_H", 0x0400_0122, EIoGroup::Serial, &[]),
+	IoRegister::new("SIOMULTI2", 0x0400_0124, EIoGroup::Serial, &[]),
+	IoRegister::new("SIOMULTI3", 0x0400_0126, EIoGroup::Serial, &[]),
+	IoRegister::new(
+		"SIOCNT",
+		0x0400_0128,
+		EIoGroup::Serial,
+		&[
+			("Baud Rate", 0, 1),
+			("SI Terminal", 2, 2),
+			("SD Terminal", 3, 3),
+			("Multiplayer ID", 4, 5),
+			("Error", 6, 6),
+			("Start", 7, 7),
+			("Mode", 12, 13),
+			("IRQ", 14, 14),
+		],
+	),
+	IoRegister::new("SIODATA8", 0x0400_012a, EIoGroup::Serial, &[]),
+	IoRegister::new("KEYINPUT", 0x0400_0130, EIoGroup::Keypad, KEY_FIELDS),
+	IoRegister::new(
+		"KEYCNT",
+		0x0400_0132,
+		EIoGroup::Keypad,
+		&[
+			("A", 0, 0),
+			("B", 1, 1),
+			("Select", 2, 2),
+			("Start", 3, 3),
+			("Right", 4, 4),
+			("Left", 5, 5),
+			("Up", 6, 6),
+			("Down", 7, 7),
+			("R", 8, 8),
+			("L", 9, 9),
+			("IRQ", 14, 14),
+			("All Keys", 15, 15),
+		],
+	),
+	IoRegister::new(
+		"RCNT",
+		0x0400_0134,
+		EIoGroup::Serial,
+		&[
+			("SC Data", 0, 0),
+			("SD Data", 1, 1),
+			("SI Data", 2, 2),
+			("SO Data", 3, 3),
+			("SC Output", 4, 4),
+			("SD Output", 5, 5),
+			("SI Output", 6, 6),
+			("SO Output", 7, 7),
+			("SI IRQ", 8, 8),
+			("Mode", 14, 15),
+		],
+	),
+	IoRegister::new("IE", 0x0400_0200, EIoGroup::Interrupt, INTERRUPT_FIELDS),
+	IoRegister::new("IF", 0x0400_0202, EIoGroup::Interrupt, INTERRUPT_FIELDS),
+	IoRegister::new(
+		"WAITCNT",
+		0x0400_0204,
+		EIoGroup::System,
+		&[
+			("SRAM Wait", 0, 1),
+			("WS0 First Access", 2, 3),
+			("WS0 Second Access", 4, 4),
+			("WS1 First Access", 5, 6),
+			("WS1 Second Access", 7, 7),
+			("WS2 First Access", 8, 9),
+			("WS2 Second Access", 10, 10),
+			("PHI Output", 11, 12),
+			("Prefetch", 14, 14),
+			("Game Pak Type", 15, 15),
+		],
+	),
+	IoRegister::new("IME", 0x0400_0208, EIoGroup::Interrupt, &[("Enable", 0, 0)]),
+];
@@ -1,344 +1,248 @@
-use bitfield::Bit;
-use imgui::*;
-
-use crate::arm7tdmi::cpu::CPU;
-use crate::arm7tdmi::EOperatingMode;
-use crate::debugging::disassembling::{disassemble_arm, disassemble_thumb};
-use crate::ppu::{Color, PALETTE_RAM_SIZE};
-use crate::system::{MemoryInterface, SystemBus, PALETTE_RAM_ADDR};
-
+use std::path::Path;
+
+use num_traits::FromPrimitive;
+
+use crate::arm7tdmi::cpu::{BankedRegisters, ExceptionRecord, CPU};
+use crate::arm7tdmi::{EExceptionType, EOperatingMode};
+use crate::ppu::{OAM_SIZE, PALETTE_RAM_SIZE, VRAM_SIZE};
+use crate::system::{
+	EWatchKind, MemoryInterface, SystemBus, Watchpoint, CARTRIDGE_SRAM_LO, CARTRIDGE_SRAM_SIZE, EWRAM_ADDR, EWRAM_SIZE, IWRAM_ADDR, IWRAM_SIZE, OAM_ADDR, PALETTE_RAM_ADDR,
+	VRAM_ADDR,
+};
+
+pub mod ab_compare;
+pub(crate) mod assembling;
+pub mod breakpoints;
+pub mod call_stack;
+pub mod compat;
+#[cfg(feature = "gui")]
+mod debugger;
 pub mod disassembling;
+pub mod frame_diff;
+pub mod gdbstub;
+pub mod hex_editor;
+pub(crate) mod io_registers;
+pub mod profiler;
+pub mod ram_search;
+pub mod remote;
+pub mod state_dump;
+pub mod symbols;
+pub mod trace;
+#[cfg(feature = "gui")]
+mod windows;
+
+#[cfg(feature = "gui")]
+pub use debugger::*;
+#[cfg(feature = "gui")]
+pub use windows::*;
+
+/// Memory regions that can be dumped to/loaded from files: (name, start address, size)
+pub const MEMORY_REGIONS: [(&str, u32, usize); 6] = [
+	("VRAM", VRAM_ADDR, VRAM_SIZE),
+	("Palette", PALETTE_RAM_ADDR, PALETTE_RAM_SIZE),
+	("OAM", OAM_ADDR, OAM_SIZE),
+	("EWRAM", EWRAM_ADDR, EWRAM_SIZE),
+	("IWRAM", IWRAM_ADDR, IWRAM_SIZE),
+	("SRAM", CARTRIDGE_SRAM_LO, CARTRIDGE_SRAM_SIZE),
+];
+
+/// Address of the user IRQ handler called by the BIOS interrupt handler
+pub const IRQ_HANDLER_ADDRESS: u32 = 0x0300_7ffc;
+
+/// Exception types the debugger breaks on
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ExceptionBreakpoints {
+	pub swi: bool,
+	pub irq: bool,
+	pub undefined: bool,
+}
 
-pub fn build_memory_debug_window(
-	cpu: &CPU,
-	bus: &SystemBus,
-	show_memory_window: &mut bool,
-	address: &mut u32,
-	debug_mode: &mut bool,
-	execute_step: &mut bool,
-	breakpoint_set: &mut bool,
-	write_flow_to_file: &mut bool,
-	breakpoint_address: &mut u32,
-	ui: &&mut Ui,
-) {
-	Window::new(im_str!("Current Memory"))
-		.size([600.0, 500.0], Condition::FirstUseEver)
-		.opened(show_memory_window)
-		.position([750.0, 75.0], Condition::FirstUseEver)
-		.build(ui, || {
-			if !*debug_mode {
-				if *breakpoint_set {
-					if *address == cpu.get_current_pc() {
-						*debug_mode = true;
-					}
-				} else {
-					*address = cpu.get_current_pc();
-				}
-			}
-
-			let pc_offset = if cpu.get_cpsr().get_t() { 4 } else { 8 };
-
-			ui.text("Current instruction highlighted");
-
-			if ui.button(im_str!("Step"), [0.0, 0.0]) || ui.is_key_down(Key::Space) && *debug_mode {
-				*execute_step = true;
-				*address = cpu.get_current_pc();
-			}
-			ui.same_line(0.0);
-			ui.checkbox(im_str!("Debug"), debug_mode);
+impl ExceptionBreakpoints {
+	pub fn all(&self) -> bool {
+		self.swi && self.irq && self.undefined
+	}
+
+	pub fn matches(&self, exception_type: EExceptionType) -> bool {
+		match exception_type {
+			EExceptionType::SoftwareInterrupt => self.swi,
+			EExceptionType::Irq => self.irq,
+			EExceptionType::Undefined => self.undefined,
+			_ => false,
+		}
+	}
+}
 
-			let mut new_address = if *breakpoint_set { *breakpoint_address } else { *address } as i32;
-			if ui.button(im_str!("Current PC"), [0.0, 0.0]) {
-				*address = cpu.get_current_pc();
-			}
+/// Modes owning a PSR, in the order shown by the debugger (CPSR first, then the SPSRs)
+pub const PSR_MODES: [(&str, EOperatingMode); 6] = [
+	("CPSR", EOperatingMode::UserMode),
+	("SPSR_fiq", EOperatingMode::FiqMode),
+	("SPSR_svc", EOperatingMode::SupervisorMode),
+	("SPSR_abt", EOperatingMode::AbortMode),
+	("SPSR_irq", EOperatingMode::IrqMode),
+	("SPSR_und", EOperatingMode::UndefinedMode),
+];
+
+/// Kinds of watchpoints, in the order shown by the debugger
+pub const WATCH_KINDS: [(&str, EWatchKind); 3] = [("Write", EWatchKind::Write), ("Read", EWatchKind::Read), ("Read/Write", EWatchKind::Access)];
+
+/// Names of the register banks, in BankedRegisters order
+pub const BANK_NAMES: [&str; 6] = ["User/System", "FIQ", "IRQ", "SVC", "ABT", "UND"];
+
+/// Register values of the CPU, taken when the emulation resumes so the debugger can show what changed until the next pause
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuSnapshot {
+	pub registers: [u32; 16],
+	/// In PSR_MODES order
+	pub psrs: [u32; 6],
+	pub banked_r13s: [u32; 6],
+	pub banked_r14s: [u32; 6],
+	pub banked_user_registers: [u32; 5],
+	pub banked_fiq_registers: [u32; 5],
+}
 
-			ui.same_line(0.0);
-			if ui.input_int(im_str!("Address"), &mut new_address).step(4).chars_hexadecimal(true).build() && *debug_mode {
-				if *breakpoint_set {
-					*breakpoint_address = new_address as u32;
+impl CpuSnapshot {
+	pub fn new(cpu: &CPU) -> Self {
+		let mut snapshot = Self {
+			registers: [0; 16],
+			psrs: [0; 6],
+			banked_r13s: [0; 6],
+			banked_r14s: [0; 6],
+			banked_user_registers: [0; 5],
+			banked_fiq_registers: [0; 5],
+		};
+
+		let banks = cpu.get_banks();
+		snapshot.registers.copy_from_slice(cpu.get_registers());
+		for (psr, (_, mode)) in snapshot.psrs.iter_mut().zip(PSR_MODES.iter()) {
+			*psr = cpu.get_spsr(*mode).0;
+		}
+		snapshot.banked_r13s.copy_from_slice(banks.get_banked_r13s());
+		snapshot.banked_r14s.copy_from_slice(banks.get_banked_r14s());
+		snapshot.banked_user_registers.copy_from_slice(banks.get_banked_user_registers());
+		snapshot.banked_fiq_registers.copy_from_slice(banks.get_banked_fiq_registers());
+		snapshot
+	}
+
+	/// Bank of the mode in the CPSR
+	pub fn get_active_bank(&self) -> usize {
+		FromPrimitive::from_u32(self.psrs[0] & 0x1f).map_or(0, BankedRegisters::get_bank_index)
+	}
+
+	/// Index and value of the registers of a bank, R8-R12 are only part of the User/System and FIQ banks
+	/// NOTE: The values saved for the active bank are stale, its registers are the GPRs
+	pub fn get_bank_registers(&self, bank: usize) -> Vec<(usize, u32)> {
+		let active_bank = self.get_active_bank();
+		let mut registers = Vec::new();
+		if bank <= 1 {
+			// R8-R12 are only swapped when entering or leaving FIQ
+			let in_gprs = (bank == 1) == (active_bank == 1);
+			for i in 0..5 {
+				let value = if in_gprs {
+					self.registers[i + 8]
+				} else if bank == 1 {
+					self.banked_fiq_registers[i]
 				} else {
-					*address = new_address as u32;
-				}
+					self.banked_user_registers[i]
+				};
+				registers.push((i + 8, value));
 			}
+		}
 
-			if ui.button(im_str!("Set/Unset Breakpoint"), [0.0, 0.0]) && *debug_mode {
-				*breakpoint_set = !*breakpoint_set;
-				*breakpoint_address = new_address as u32;
-			}
+		if bank == active_bank {
+			registers.push((13, self.registers[13]));
+			registers.push((14, self.registers[14]));
+		} else {
+			registers.push((13, self.banked_r13s[bank]));
+			registers.push((14, self.banked_r14s[bank]));
+		}
 
-			ui.same_line(0.0);
-			ui.checkbox(im_str!("Write Flow"), write_flow_to_file);
-
-			ui.separator();
-			if let Some(scroll_token) = ChildWindow::new(im_str!("##ScrollingRegion")).begin(&ui) {
-				ui.columns(3, im_str!("system"), true);
-				ui.set_column_width(0, 95.0);
-
-				const ENTRIES: i32 = 20;
-				let starting_address = (if *breakpoint_set { cpu.get_current_pc() } else { *address }).saturating_sub((pc_offset / 2) * (ENTRIES / 2) as u32);
-				let mut list_clipper = ListClipper::new(ENTRIES).begin(&ui);
-				while list_clipper.step() {
-					for row in list_clipper.display_start()..list_clipper.display_end() {
-						let address = starting_address.saturating_add(row as u32 * (pc_offset / 2));
-						if address <= u32::max_value() - (pc_offset / 2) {
-							Selectable::new(&*im_str!("{:#010X}:", address))
-								.selected(address == cpu.get_current_pc())
-								.span_all_columns(true)
-								.build(&ui);
-							ui.next_column();
-
-							for j in 0..pc_offset / 2 {
-								let value = bus.read_8(address as u32 + j);
-								let color = if value == 0 { [0.5, 0.5, 0.5, 0.5] } else { [1.0, 1.0, 1.0, 1.0] };
-								ui.text_colored(color, format!("{:02X}", value));
-								if j != 3 {
-									ui.same_line(0.0);
-								}
-							}
-
-							ui.next_column();
-							ui.text(if cpu.get_cpsr().get_t() {
-								disassemble_thumb(bus.read_16(address as u32))
-							} else {
-								disassemble_arm(bus.read_32(address as u32))
-							});
-							ui.next_column();
-							ui.separator();
-						}
-					}
-				}
-				ui.columns(1, im_str!(""), false);
-
-				scroll_token.end(&ui);
-			}
-		});
+		registers
+	}
 }
 
-pub fn build_tiles_debug_window(bus: &SystemBus, show_tiles_window: &mut bool, is_palette: &mut bool, texture_id: TextureId, ui: &&mut Ui) {
-	Window::new(im_str!("Tiles"))
-		.size([0.0, 0.0], Condition::FirstUseEver)
-		.opened(show_tiles_window)
-		.position([1400.0, 75.0], Condition::FirstUseEver)
-		.build(ui, || {
-			ui.text("Palette:");
-			for (index, color) in bus.ppu.get_palettes_colors().iter().enumerate() {
-				if index > 0 && index % 16 != 0 {
-					ui.same_line(0.0);
-				}
-
-				imgui::ColorButton::new(im_str!(""), [color.get_red(), color.get_green(), color.get_blue(), 1.0])
-					.border(false)
-					.size([6.0, 6.0])
-					.tooltip(true)
-					.build(&ui);
-			}
-
-			ui.checkbox(im_str!("256 Colors"), is_palette);
-			if let Some(child_token) = ChildWindow::new(im_str!("##memory")).begin(&ui) {
-				Image::new(texture_id, [256.0, 384.0]).build(&ui);
-				child_token.end(&ui);
-			}
-		});
+/// Summary of an exception for the debugger, IRQs also show the user handler the BIOS will jump to
+pub fn describe_exception(record: &ExceptionRecord, bus: &SystemBus) -> String {
+	let mut description = format!(
+		"{} taken (Vector: {:#010x}, Return: {:#010x}, SPSR: {:#010x})",
+		record.exception_type.get_name(),
+		record.vector,
+		record.return_address,
+		record.spsr
+	);
+	if record.exception_type == EExceptionType::Irq {
+		description += &format!(" Handler: {:#010x}", bus.read_32(IRQ_HANDLER_ADDRESS));
+	}
+
+	description
 }
 
-pub fn build_sprites_debug_window(show_sprites_window: &mut bool, texture_ids: &[TextureId], ui: &&mut Ui) {
-	Window::new(im_str!("Sprites"))
-		.size([600.0, 700.0], Condition::FirstUseEver)
-		.opened(show_sprites_window)
-		.position([1400.0, 75.0], Condition::FirstUseEver)
-		.build(ui, || {
-			ui.columns(8, im_str!(""), true);
-			for texture_id in texture_ids {
-				Image::new(*texture_id, [64.0, 64.0]).build(&ui);
-				ui.next_column();
-			}
-		});
+/// Summary of a watchpoint hit for the debugger, pc is the instruction (or DMA) that accessed the memory
+pub fn describe_watchpoint_hit(watchpoint: &Watchpoint, address: u32, pc: &str) -> String {
+	let kind = WATCH_KINDS.iter().find(|(_, kind)| *kind == watchpoint.kind).map_or("", |(name, _)| name);
+	format!(
+		"{} watchpoint hit at {:#010x} (Watch: {:#010x}-{:#010x}, PC: {})",
+		kind,
+		address,
+		watchpoint.address,
+		watchpoint.address.wrapping_add(watchpoint.length.saturating_sub(1)),
+		pc
+	)
 }
 
-pub fn build_cpu_debug_window(cpu: &CPU, ui: &&mut Ui, opened: &mut bool) {
-	Window::new(im_str!("CPU")).size([650.0, 600.0], Condition::FirstUseEver).opened(opened).build(ui, || {
-		ui.text(im_str!("Mode: {:?}", cpu.get_operating_mode()));
-
-		if CollapsingHeader::new(im_str!("GPRs")).default_open(true).build(&ui) {
-			ui.columns(2, im_str!("Registers"), true);
-			for (i, register) in cpu.get_registers().iter().enumerate() {
-				ui.text(format!("r{}:", i));
-				ui.next_column();
-				ui.text(format!("{:#X}", register));
-				ui.next_column();
-				ui.separator();
-			}
-			ui.columns(1, im_str!(""), false);
-		}
-
-		if CollapsingHeader::new(im_str!("CPSRs")).default_open(true).build(&ui) {
-			ui.columns(9, im_str!("cpsr"), true);
-			ui.next_column();
-			ui.text("N");
-			ui.next_column();
-			ui.text("Z");
-			ui.next_column();
-			ui.text("C");
-			ui.next_column();
-			ui.text("V");
-			ui.next_column();
-			ui.text("I");
-			ui.next_column();
-			ui.text("F");
-			ui.next_column();
-			ui.text("T");
-			ui.next_column();
-			ui.text("Mode");
-			ui.separator();
-
-			let cpsr_names = ["CPSR", "SPSR_fiq", "SPSR_svc", "SPSR_abt", "SPSR_irq", "SPSR_und"];
-			for (i, cpsr) in [
-				cpu.get_spsr(EOperatingMode::UserMode),
-				cpu.get_spsr(EOperatingMode::FiqMode),
-				cpu.get_spsr(EOperatingMode::SupervisorMode),
-				cpu.get_spsr(EOperatingMode::AbortMode),
-				cpu.get_spsr(EOperatingMode::IrqMode),
-				cpu.get_spsr(EOperatingMode::UndefinedMode),
-			]
-			.iter()
-			.enumerate()
-			{
-				ui.next_column();
-				ui.text(cpsr_names[i]);
-				ui.next_column();
-				ui.text(cpsr.get_n().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_z().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_c().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_v().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_i().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_f().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_t().to_string());
-				ui.next_column();
-				ui.text(cpsr.get_mode_bits().to_string());
-				ui.separator();
-			}
+/// Write the whole content of one of MEMORY_REGIONS to a file
+pub fn dump_memory_region(bus: &SystemBus, region: usize, path: &Path) -> std::io::Result<()> {
+	let (_, address, size) = MEMORY_REGIONS[region];
+	let mut data = vec![0; size];
+	bus.read_block(address, &mut data);
 
-			ui.columns(1, im_str!(""), false);
-		}
-	});
+	std::fs::write(path, data)
 }
 
-pub fn build_io_registers_window(bus: &SystemBus, show_io_registers_window: &mut bool, selected_register: &mut usize, ui: &&mut Ui) {
-	Window::new(im_str!("I/O Registers"))
-		.size([400.0, 150.0], Condition::FirstUseEver)
-		.opened(show_io_registers_window)
-		.position([200.0, 1300.0], Condition::FirstUseEver)
-		.build(ui, || {
-			let registers = [
-				im_str!("0x04000000: DISPCNT"),
-				im_str!("0x04000004: DISPSTAT"),
-				im_str!("0x04000006: VCOUNT"),
-				im_str!("0x04000008: BG0CNT"),
-				im_str!("0x0400000a: BG1CNT"),
-				im_str!("0x0400000c: BG2CNT"),
-				im_str!("0x0400000e: BG3CNT"),
-				im_str!("0x04000010: BG0HOFS"),
-				im_str!("0x04000012: BG0VOFS"),
-				im_str!("0x04000014: BG1HOFS"),
-				im_str!("0x04000016: BG1VOFS"),
-				im_str!("0x04000018: BG2HOFS"),
-				im_str!("0x0400001a: BG2VOFS"),
-				im_str!("0x0400001c: BG3HOFS"),
-				im_str!("0x0400001e: BG3VOFS"),
-				im_str!("0x04000020: BG2PA"),
-				im_str!("0x04000022: BG2PB"),
-				im_str!("0x04000024: BG2PC"),
-				im_str!("0x04000026: BG2PD"),
-				im_str!("0x04000028: BG2X"),
-				im_str!("0x0400002c: BG2Y"),
-				im_str!("0x04000030: BG3PA"),
-				im_str!("0x04000032: BG3PB"),
-				im_str!("0x04000034: BG3PC"),
-				im_str!("0x04000036: BG3PD"),
-				im_str!("0x04000038: BG3X"),
-				im_str!("0x0400003c: BG3Y"),
-				im_str!("0x0400004: WIN0H"),
-				im_str!("0x04000042: WIN1H"),
-				im_str!("0x04000044: WIN0V"),
-				im_str!("0x04000046: WIN1V"),
-				im_str!("0x04000048: WININ"),
-				im_str!("0x0400004a: WINOUT"),
-				im_str!("0x0400004c: MOSAIC"),
-				im_str!("0x04000050: BLDCNT"),
-				im_str!("0x04000052: BLDALPHA"),
-				im_str!("0x04000054: BLDY"),
-				im_str!("0x04000200: IE"),
-				im_str!("0x04000202: IF"),
-				im_str!("0x04000208: IME"),
-			];
-
-			let register_addresses = [
-				0x0400_0000 + crate::ppu::DISP_CNT_ADDRESS,
-				0x0400_0000 + crate::ppu::DISP_STAT_ADDRESS,
-				0x0400_0000 + crate::ppu::VCOUNT_ADDRESS,
-				0x0400_0000 + crate::ppu::BG0_CNT_ADDRESS,
-				0x0400_0000 + crate::ppu::BG1_CNT_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_CNT_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_CNT_ADDRESS,
-				0x0400_0000 + crate::ppu::BG0_HOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG0_VOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG1_HOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG1_VOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_HOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_VOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_HOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_VOFS_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_PA_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_PB_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_PC_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_PD_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_X_LO_ADDRESS,
-				0x0400_0000 + crate::ppu::BG2_Y_LO_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_PA_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_PB_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_PC_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_PD_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_X_LO_ADDRESS,
-				0x0400_0000 + crate::ppu::BG3_Y_LO_ADDRESS,
-				0x0400_0000 + crate::ppu::WIN0_H_ADDRESS,
-				0x0400_0000 + crate::ppu::WIN1_H_ADDRESS,
-				0x0400_0000 + crate::ppu::WIN0_V_ADDRESS,
-				0x0400_0000 + crate::ppu::WIN1_V_ADDRESS,
-				0x0400_0000 + crate::ppu::WIN_IN_ADDRESS,
-				0x0400_0000 + crate::ppu::WIN_OUT_ADDRESS,
-				0x0400_0000 + crate::ppu::MOSAIC_LO_ADDRESS,
-				0x0400_0000 + crate::ppu::BLD_CNT_ADDRESS,
-				0x0400_0000 + crate::ppu::BLD_ALPHA_ADDRESS,
-				0x0400_0000 + crate::ppu::BLD_Y_LO_ADDRESS,
-				0x0400_0200,
-				0x0400_0202,
-				0x0400_0208,
-			];
-
-			ComboBox::new(im_str!("")).build_simple_string(ui, selected_register, &registers);
-
-			let selected_register_address = register_addresses[*selected_register as usize];
-			let register_value = bus.read_16(selected_register_address);
-			ui.text(im_str!("{}", register_value));
-
-			ui.columns(16, im_str!("Bits"), true);
-			for bit in 0..16 {
-				let mut bit_value = register_value.bit(bit);
-				ui.checkbox(&*im_str!(""), &mut bit_value);
-				ui.next_column();
-			}
+/// Load a file into one of MEMORY_REGIONS, files bigger than the region are truncated
+pub fn load_memory_region(bus: &mut SystemBus, region: usize, path: &Path) -> std::io::Result<()> {
+	let (_, address, size) = MEMORY_REGIONS[region];
+	let mut data = std::fs::read(path)?;
+	data.truncate(size);
+	bus.patch(address, &data);
 
-			ui.separator();
+	Ok(())
+}
 
-			for i in 0..16 {
-				ui.text(im_str!("{}", i));
-				ui.next_column();
-			}
-		});
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::system::cartridge::Cartridge;
+
+	const SRAM_REGION: usize = 5;
+
+	fn load_sram_region(library: &[u8], data: &[u8]) -> SystemBus {
+		let mut rom = library.to_vec();
+		rom.resize(0x100, 0);
+		let mut bus = SystemBus::new_with_cartridge(Vec::new().into_boxed_slice(), Cartridge::new(rom.into_boxed_slice()));
+
+		let path = std::env::temp_dir().join(format!("gba_rustmulator_region_{}.bin", String::from_utf8_lossy(library)));
+		std::fs::write(&path, data).unwrap();
+		load_memory_region(&mut bus, SRAM_REGION, &path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		bus
+	}
+
+	#[test]
+	fn sram_region_loads_every_byte() {
+		let bus = load_sram_region(b"SRAM_V113", &[0x01, 0x02, 0x03, 0x04, 0x05]);
+
+		assert_eq!(&bus.get_cartridge().get_backup().get_data()[..6], &[0x01, 0x02, 0x03, 0x04, 0x05, 0xff]);
+	}
+
+	#[test]
+	fn flash_region_loads_bytes_instead_of_commands() {
+		// NOTE: The data starts with the Flash unlock sequence, it must be stored and not executed
+		let mut data = vec![0; 0x5556];
+		data[0x5555] = 0xaa;
+		data[0x2aaa] = 0x55;
+		let bus = load_sram_region(b"FLASH_V126", &data);
+
+		assert_eq!(&bus.get_cartridge().get_backup().get_data()[..0x5556], &data[..]);
+		assert_eq!(bus.read_8(CARTRIDGE_SRAM_LO + 0x5555), 0xaa);
+	}
 }
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use crate::system::{EMemoryRegion, SystemBus};
+
+/// Sizes of the address buckets the instructions are counted in, from single THUMB instructions to small functions
+pub const PROFILER_BUCKET_SIZES: [u32; 5] = [0x2, 0x10, 0x40, 0x100, 0x400];
+
+/// Counts the instructions executed per address bucket, to find the code the game spends its time in
+/// NOTE: Counts instructions rather than cycles, code in ROM runs slower per instruction than in IWRAM because of the wait states
+#[derive(Debug, Clone)]
+pub struct CpuProfiler {
+	enabled: bool,
+	bucket_size: u32,
+	counts: HashMap<u32, u64>,
+	total: u64,
+}
+
+impl CpuProfiler {
+	pub fn new() -> Self {
+		Self {
+			enabled: false,
+			bucket_size: PROFILER_BUCKET_SIZES[1],
+			counts: HashMap::new(),
+			total: 0,
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.enabled
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.enabled = enabled;
+	}
+
+	pub fn get_bucket_size(&self) -> u32 {
+		self.bucket_size
+	}
+
+	/// NOTE: Resets the counts, they can't be split into smaller buckets
+	pub fn set_bucket_size(&mut self, bucket_size: u32) {
+		let bucket_size = bucket_size.max(1).next_power_of_two();
+		if bucket_size != self.bucket_size {
+			self.bucket_size = bucket_size;
+			self.clear();
+		}
+	}
+
+	pub fn get_total(&self) -> u64 {
+		self.total
+	}
+
+	pub fn clear(&mut self) {
+		self.counts.clear();
+		self.total = 0;
+	}
+
+	/// Count the instruction at pc, call for each instruction executed
+	pub fn record(&mut self, pc: u32) {
+		if !self.enabled {
+			return;
+		}
+
+		*self.counts.entry(pc & !(self.bucket_size - 1)).or_insert(0) += 1;
+		self.total += 1;
+	}
+
+	/// Start address and instruction count of the buckets executed the most, the hottest first
+	pub fn get_hot_buckets(&self, count: usize) -> Vec<(u32, u64)> {
+		let mut buckets: Vec<(u32, u64)> = self.counts.iter().map(|(address, count)| (*address, *count)).collect();
+		buckets.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+		buckets.truncate(count);
+		buckets
+	}
+
+	/// Instructions executed per memory region (BIOS, IWRAM, ROM...), the busiest first
+	pub fn get_region_counts(&self) -> Vec<(EMemoryRegion, u64)> {
+		let mut regions: Vec<(EMemoryRegion, u64)> = Vec::new();
+		for (address, count) in self.counts.iter() {
+			let region = SystemBus::resolve(*address).0;
+			match regions.iter_mut().find(|(current, _)| *current == region) {
+				Some((_, total)) => *total += count,
+				None => regions.push((region, *count)),
+			}
+		}
+		regions.sort_unstable_by_key(|(_, count)| std::cmp::Reverse(*count));
+		regions
+	}
+}
+
+impl Default for CpuProfiler {
+	fn default() -> Self {
+		Self::new()
+	}
+}
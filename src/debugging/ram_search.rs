@@ -0,0 +1,162 @@
+use crate::system::{EAccessWidth, MemoryInterface, SystemBus, EWRAM_ADDR, EWRAM_SIZE, IWRAM_ADDR, IWRAM_SIZE};
+
+/// Memory searched for values: (start address, size), the work RAM the game state lives in
+pub const RAM_SEARCH_REGIONS: [(u32, usize); 2] = [(EWRAM_ADDR, EWRAM_SIZE), (IWRAM_ADDR, IWRAM_SIZE)];
+pub const RAM_SEARCH_WIDTHS: [(&str, EAccessWidth); 3] = [("8-bit", EAccessWidth::Byte), ("16-bit", EAccessWidth::Halfword), ("32-bit", EAccessWidth::Word)];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ESearchComparison {
+	Equal,
+	NotEqual,
+	Greater,
+	Less,
+}
+
+impl ESearchComparison {
+	pub const ALL: [ESearchComparison; 4] = [ESearchComparison::Equal, ESearchComparison::NotEqual, ESearchComparison::Greater, ESearchComparison::Less];
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ESearchComparison::Equal => "Equal",
+			ESearchComparison::NotEqual => "Not Equal",
+			ESearchComparison::Greater => "Greater",
+			ESearchComparison::Less => "Less",
+		}
+	}
+
+	fn compare(&self, left: u32, right: u32) -> bool {
+		match self {
+			ESearchComparison::Equal => left == right,
+			ESearchComparison::NotEqual => left != right,
+			ESearchComparison::Greater => left > right,
+			ESearchComparison::Less => left < right,
+		}
+	}
+}
+
+/// What the current values of the candidates are compared to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ESearchTarget {
+	/// Their value at the previous search, eg. Not Equal keeps the ones that changed since
+	Previous,
+	Value(u32),
+}
+
+/// Narrows down the addresses of a value (lives, money...) by searching the work RAM again after the value changes in game
+/// NOTE: Values are unsigned and aligned to their width
+#[derive(Debug, Clone)]
+pub struct RamSearch {
+	width: EAccessWidth,
+	/// Addresses still matching and their value at the previous search
+	candidates: Vec<(u32, u32)>,
+	started: bool,
+}
+
+impl RamSearch {
+	pub fn new() -> Self {
+		Self {
+			width: EAccessWidth::Byte,
+			candidates: Vec::new(),
+			started: false,
+		}
+	}
+
+	pub fn get_width(&self) -> EAccessWidth {
+		self.width
+	}
+
+	/// NOTE: Resets the search, the candidates of another width aren't comparable
+	pub fn set_width(&mut self, width: EAccessWidth) {
+		if width != self.width {
+			self.width = width;
+			self.reset();
+		}
+	}
+
+	pub fn is_started(&self) -> bool {
+		self.started
+	}
+
+	pub fn get_candidates(&self) -> &[(u32, u32)] {
+		&self.candidates
+	}
+
+	/// Snapshot every address of the searched regions, they all match until the first filter
+	pub fn start(&mut self, bus: &SystemBus) {
+		let width = self.width;
+		let size = get_size(width);
+		self.candidates = RAM_SEARCH_REGIONS
+			.iter()
+			.flat_map(|&(start, length)| {
+				let data = read_region(bus, start, length);
+				(0..length).step_by(size).map(move |offset| (start + offset as u32, get_value(&data, offset, width)))
+			})
+			.collect();
+		self.started = true;
+	}
+
+	/// Keep the candidates whose current value compares true to the target, their values become the previous ones
+	pub fn filter(&mut self, bus: &SystemBus, comparison: ESearchComparison, target: ESearchTarget) {
+		let regions: Vec<(u32, Vec<u8>)> = RAM_SEARCH_REGIONS.iter().map(|&(start, length)| (start, read_region(bus, start, length))).collect();
+		let width = self.width;
+		self.candidates.retain_mut(|(address, previous)| {
+			let current = regions
+				.iter()
+				.find(|(start, data)| *address >= *start && ((*address - *start) as usize) < data.len())
+				.map_or(0, |(start, data)| get_value(data, (*address - *start) as usize, width));
+			let matches = match target {
+				ESearchTarget::Previous => comparison.compare(current, *previous),
+				ESearchTarget::Value(value) => comparison.compare(current, value),
+			};
+			*previous = current;
+			matches
+		});
+	}
+
+	pub fn reset(&mut self) {
+		self.candidates.clear();
+		self.started = false;
+	}
+
+	/// Current value of a candidate, for the result list
+	pub fn read(&self, bus: &SystemBus, address: u32) -> u32 {
+		match self.width {
+			EAccessWidth::Byte => bus.read_8(address) as u32,
+			EAccessWidth::Halfword => bus.read_16(address) as u32,
+			EAccessWidth::Word => bus.read_32(address),
+		}
+	}
+}
+
+impl Default for RamSearch {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+pub fn get_size(width: EAccessWidth) -> usize {
+	match width {
+		EAccessWidth::Byte => 1,
+		EAccessWidth::Halfword => 2,
+		EAccessWidth::Word => 4,
+	}
+}
+
+fn read_region(bus: &SystemBus, start: u32, length: usize) -> Vec<u8> {
+	let mut data = vec![0; length];
+	bus.read_block(start, &mut data);
+	data
+}
+
+fn get_value(data: &[u8], offset: usize, width: EAccessWidth) -> u32 {
+	data[offset..offset + get_size(width)].iter().rev().fold(0, |value, byte| value << 8 | *byte as u32)
+}
+
+/// Parse a searched value, hexadecimal with a 0x prefix and decimal otherwise
+pub fn parse_value(text: &str) -> Option<u32> {
+	let text = text.trim();
+	match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+		Some(hex) => u32::from_str_radix(hex, 16).ok(),
+		None => text.parse().ok(),
+	}
+}
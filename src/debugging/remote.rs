@@ -0,0 +1,359 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use crate::arm7tdmi::cpu::PROGRAM_COUNTER_REGISTER;
+use crate::gba::{Gba, CYCLES_PER_FRAME};
+use crate::savestate::{StateReader, StateWriter};
+use crate::system::MemoryInterface;
+
+/// Address the debug server listens on by default, only reachable from this machine
+pub const DEFAULT_DEBUG_SERVER_ADDRESS: &str = "127.0.0.1:4267";
+/// Longest message accepted, enough for a read/write of the whole EWRAM
+const MAX_MESSAGE_LENGTH: usize = 0x10_0000;
+/// How long the server waits for the rest of a message once it started arriving
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Index of the CPSR for ERemoteCommand::WriteRegister, after the GPRs
+pub const REMOTE_CPSR_INDEX: u8 = 16;
+
+/// Requests of the debug protocol
+///
+/// Every message is a little endian u32 length followed by the payload, a request payload is the command byte and its arguments.
+/// Responses start with a status byte (0 = Ok, 1 = Error followed by the error message).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive, ToPrimitive)]
+pub enum ERemoteCommand {
+	/// -> paused (bool), PC (u32), current cycle (u32)
+	Status,
+	Pause,
+	Resume,
+	/// Run a single cycle while paused -> PC (u32)
+	Step,
+	/// -> R0-R15, CPSR, SPSR of the current mode (u32 each)
+	ReadRegisters,
+	/// index (u8, see REMOTE_CPSR_INDEX), value (u32)
+	WriteRegister,
+	/// address (u32), length (u32) -> data (sized bytes)
+	ReadMemory,
+	/// address (u32), data (sized bytes)
+	WriteMemory,
+	/// address (u32)
+	SetBreakpoint,
+	/// address (u32)
+	ClearBreakpoint,
+}
+
+/// State of the core reported by ERemoteCommand::Status
+#[derive(Debug, Copy, Clone)]
+pub struct RemoteStatus {
+	pub paused: bool,
+	pub pc: u32,
+	pub current_cycle: u32,
+}
+
+/// Registers reported by ERemoteCommand::ReadRegisters
+#[derive(Debug, Copy, Clone)]
+pub struct RemoteRegisters {
+	pub registers: [u32; 16],
+	pub cpsr: u32,
+	pub spsr: u32,
+}
+
+fn write_message(stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+	stream.write_all(&(payload.len() as u32).to_le_bytes())?;
+	stream.write_all(payload)?;
+	stream.flush()
+}
+
+fn read_message(stream: &mut TcpStream) -> Result<Vec<u8>> {
+	let mut length = [0; 4];
+	stream.read_exact(&mut length)?;
+	let length = u32::from_le_bytes(length) as usize;
+	if length > MAX_MESSAGE_LENGTH {
+		return Err(Error::new(ErrorKind::InvalidData, "Debug message is too long"));
+	}
+
+	let mut payload = vec![0; length];
+	stream.read_exact(&mut payload)?;
+	Ok(payload)
+}
+
+/// Debugger backend of a core running in this process, which a DebugClient of any process can attach to
+/// NOTE: One client at a time, the core keeps running (or stays paused) when it detaches
+pub struct DebugServer {
+	listener: TcpListener,
+	client: Option<TcpStream>,
+	breakpoints: Vec<u32>,
+	paused: bool,
+}
+
+impl DebugServer {
+	pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+		let listener = TcpListener::bind(address)?;
+		listener.set_nonblocking(true)?;
+		Ok(Self {
+			listener,
+			client: None,
+			breakpoints: Vec::new(),
+			paused: false,
+		})
+	}
+
+	pub fn get_local_address(&self) -> Result<SocketAddr> {
+		self.listener.local_addr()
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	pub fn is_attached(&self) -> bool {
+		self.client.is_some()
+	}
+
+	/// Accept a client and answer its requests, call regularly from the loop running the core
+	pub fn poll(&mut self, gba: &mut Gba) {
+		if self.client.is_none() {
+			match self.listener.accept() {
+				Ok((stream, _)) => match stream.set_nonblocking(true) {
+					Ok(()) => self.client = Some(stream),
+					Err(e) => eprintln!("Failed to set up the debugger connection: {}", e),
+				},
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+				Err(e) => eprintln!("Failed to accept a debugger connection: {}", e),
+			}
+		}
+
+		while let Some(stream) = self.client.as_mut() {
+			// NOTE: Only wait for the whole message once one started arriving, the core keeps running meanwhile
+			let mut first_byte = [0; 1];
+			let request = match stream.peek(&mut first_byte) {
+				Ok(0) => Err(Error::new(ErrorKind::UnexpectedEof, "Debugger detached")),
+				Ok(_) => stream
+					.set_nonblocking(false)
+					.and_then(|_| stream.set_read_timeout(Some(MESSAGE_TIMEOUT)))
+					.and_then(|_| read_message(stream))
+					.and_then(|request| stream.set_nonblocking(true).map(|_| request)),
+				Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+				Err(e) => Err(e),
+			};
+
+			let result = request.and_then(|request| {
+				let response = self.handle_request(&request, gba);
+				write_message(self.client.as_mut().unwrap(), &response)
+			});
+			if let Err(e) = result {
+				if e.kind() != ErrorKind::UnexpectedEof {
+					eprintln!("Debugger connection lost: {}", e);
+				}
+				self.client = None;
+			}
+		}
+	}
+
+	/// Run a frame unless paused, pausing at the breakpoints
+	pub fn run_frame(&mut self, gba: &mut Gba) {
+		if self.paused {
+			return;
+		}
+
+		for _ in 0..CYCLES_PER_FRAME {
+			if gba.step() && self.breakpoints.contains(&gba.cpu.get_current_pc()) {
+				self.paused = true;
+				return;
+			}
+		}
+	}
+
+	fn handle_request(&mut self, request: &[u8], gba: &mut Gba) -> Vec<u8> {
+		let mut response = vec![0];
+		if let Err(e) = self.execute_request(request, gba, &mut response) {
+			response = vec![1];
+			response.extend_from_slice(e.to_string().as_bytes());
+		}
+
+		response
+	}
+
+	fn execute_request(&mut self, request: &[u8], gba: &mut Gba, response: &mut Vec<u8>) -> Result<()> {
+		let mut request = request;
+		let mut reader = StateReader::new(&mut request);
+		let mut writer = StateWriter::new(response);
+		let command = ERemoteCommand::from_u8(reader.read_u8()?).ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unknown debug command"))?;
+		match command {
+			ERemoteCommand::Status => {
+				writer.write_bool(self.paused)?;
+				writer.write_u32(gba.cpu.get_current_pc())?;
+				writer.write_u32(gba.get_current_cycle())?;
+			}
+			ERemoteCommand::Pause => self.paused = true,
+			ERemoteCommand::Resume => self.paused = false,
+			ERemoteCommand::Step => {
+				if !self.paused {
+					return Err(Error::other("The core must be paused to step"));
+				}
+				gba.step_instruction();
+				writer.write_u32(gba.cpu.get_current_pc())?;
+			}
+			ERemoteCommand::ReadRegisters => {
+				for register in gba.cpu.get_registers().iter() {
+					writer.write_u32(*register)?;
+				}
+				writer.write_u32(gba.cpu.get_cpsr().0)?;
+				writer.write_u32(gba.cpu.get_spsr(gba.cpu.get_operating_mode()).0)?;
+			}
+			ERemoteCommand::WriteRegister => {
+				let index = reader.read_u8()?;
+				let value = reader.read_u32()?;
+				match index {
+					PROGRAM_COUNTER_REGISTER => gba.cpu.set_pc(value),
+					0..=14 => gba.cpu.set_register_value(index, value),
+					REMOTE_CPSR_INDEX => {
+						let mode = gba.cpu.get_operating_mode();
+						if !gba.cpu.set_psr_value(mode, value) {
+							return Err(Error::new(ErrorKind::InvalidInput, "Invalid CPSR mode"));
+						}
+					}
+					_ => return Err(Error::new(ErrorKind::InvalidInput, "Invalid register index")),
+				}
+			}
+			ERemoteCommand::ReadMemory => {
+				let address = reader.read_u32()?;
+				let length = reader.read_u32()? as usize;
+				if length > MAX_MESSAGE_LENGTH / 2 {
+					return Err(Error::new(ErrorKind::InvalidInput, "Memory read is too long"));
+				}
+
+				let data: Vec<u8> = (0..length as u32).map(|offset| gba.bus.read_8(address.wrapping_add(offset))).collect();
+				// NOTE: Accesses of the debugger aren't anomalies of the game
+				gba.bus.take_anomaly();
+				writer.write_sized_bytes(&data)?;
+			}
+			ERemoteCommand::WriteMemory => {
+				let address = reader.read_u32()?;
+				let data = reader.read_sized_bytes(MAX_MESSAGE_LENGTH)?;
+				for (offset, value) in data.iter().enumerate() {
+					gba.bus.write_8(address.wrapping_add(offset as u32), *value);
+				}
+				gba.bus.take_anomaly();
+			}
+			ERemoteCommand::SetBreakpoint => {
+				let address = reader.read_u32()?;
+				if !self.breakpoints.contains(&address) {
+					self.breakpoints.push(address);
+				}
+			}
+			ERemoteCommand::ClearBreakpoint => {
+				let address = reader.read_u32()?;
+				self.breakpoints.retain(|breakpoint| *breakpoint != address);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Attaches to a DebugServer, every call waits for the answer of the core
+pub struct DebugClient {
+	stream: TcpStream,
+}
+
+impl DebugClient {
+	pub fn connect(address: impl ToSocketAddrs) -> Result<Self> {
+		let stream = TcpStream::connect(address)?;
+		stream.set_nodelay(true)?;
+		Ok(Self { stream })
+	}
+
+	/// Send a request and return the payload of its response
+	fn request(&mut self, command: ERemoteCommand, write_arguments: impl FnOnce(&mut StateWriter) -> Result<()>) -> Result<Vec<u8>> {
+		let mut request = Vec::new();
+		let mut writer = StateWriter::new(&mut request);
+		writer.write_u8(command.to_u8().unwrap())?;
+		write_arguments(&mut writer)?;
+		write_message(&mut self.stream, &request)?;
+
+		let mut response = read_message(&mut self.stream)?;
+		match response.first() {
+			Some(0) => Ok(response.split_off(1)),
+			Some(_) => Err(Error::other(String::from_utf8_lossy(&response[1..]).into_owned())),
+			None => Err(Error::new(ErrorKind::InvalidData, "Empty debug response")),
+		}
+	}
+
+	pub fn get_status(&mut self) -> Result<RemoteStatus> {
+		let response = self.request(ERemoteCommand::Status, |_| Ok(()))?;
+		let mut response = response.as_slice();
+		let mut reader = StateReader::new(&mut response);
+		Ok(RemoteStatus {
+			paused: reader.read_bool()?,
+			pc: reader.read_u32()?,
+			current_cycle: reader.read_u32()?,
+		})
+	}
+
+	pub fn pause(&mut self) -> Result<()> {
+		self.request(ERemoteCommand::Pause, |_| Ok(())).map(|_| ())
+	}
+
+	pub fn resume(&mut self) -> Result<()> {
+		self.request(ERemoteCommand::Resume, |_| Ok(())).map(|_| ())
+	}
+
+	/// Returns the PC after the step
+	pub fn step(&mut self) -> Result<u32> {
+		let response = self.request(ERemoteCommand::Step, |_| Ok(()))?;
+		StateReader::new(&mut response.as_slice()).read_u32()
+	}
+
+	pub fn read_registers(&mut self) -> Result<RemoteRegisters> {
+		let response = self.request(ERemoteCommand::ReadRegisters, |_| Ok(()))?;
+		let mut response = response.as_slice();
+		let mut reader = StateReader::new(&mut response);
+		let mut registers = [0; 16];
+		for register in registers.iter_mut() {
+			*register = reader.read_u32()?;
+		}
+
+		Ok(RemoteRegisters {
+			registers,
+			cpsr: reader.read_u32()?,
+			spsr: reader.read_u32()?,
+		})
+	}
+
+	/// R0-R15, or the CPSR with REMOTE_CPSR_INDEX
+	pub fn write_register(&mut self, index: u8, value: u32) -> Result<()> {
+		self.request(ERemoteCommand::WriteRegister, |writer| {
+			writer.write_u8(index)?;
+			writer.write_u32(value)
+		})
+		.map(|_| ())
+	}
+
+	pub fn read_memory(&mut self, address: u32, length: u32) -> Result<Vec<u8>> {
+		let response = self.request(ERemoteCommand::ReadMemory, |writer| {
+			writer.write_u32(address)?;
+			writer.write_u32(length)
+		})?;
+		StateReader::new(&mut response.as_slice()).read_sized_bytes(MAX_MESSAGE_LENGTH)
+	}
+
+	pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<()> {
+		self.request(ERemoteCommand::WriteMemory, |writer| {
+			writer.write_u32(address)?;
+			writer.write_sized_bytes(data)
+		})
+		.map(|_| ())
+	}
+
+	pub fn set_breakpoint(&mut self, address: u32) -> Result<()> {
+		self.request(ERemoteCommand::SetBreakpoint, |writer| writer.write_u32(address)).map(|_| ())
+	}
+
+	pub fn clear_breakpoint(&mut self, address: u32) -> Result<()> {
+		self.request(ERemoteCommand::ClearBreakpoint, |writer| writer.write_u32(address)).map(|_| ())
+	}
+}
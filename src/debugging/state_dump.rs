@@ -0,0 +1,93 @@
+use serde_json::{json, Map, Value};
+
+use crate::arm7tdmi::cpu::CPU;
+use crate::arm7tdmi::EOperatingMode;
+use crate::debugging::io_registers::IO_REGISTERS;
+use crate::debugging::MEMORY_REGIONS;
+use crate::system::SystemBus;
+
+// NOTE: Bump whenever the layout of the dump changes, so diffs across emulator versions can be told apart
+pub const STATE_DUMP_VERSION: u32 = 2;
+
+fn hex(value: u32) -> Value {
+	Value::String(format!("{:#010x}", value))
+}
+
+fn hex_list(values: &[u32]) -> Value {
+	Value::Array(values.iter().cloned().map(hex).collect())
+}
+
+/// Human readable snapshot of the CPU registers, PSRs, IO registers and memory checksums
+///
+/// Keys are sorted and values are hex strings, so two dumps can be compared with standard text diff tools.
+pub fn dump_state_json(cpu: &CPU, bus: &SystemBus, frame: u32) -> Value {
+	let banks = cpu.get_banks();
+	let psrs = [
+		("cpsr", EOperatingMode::UserMode),
+		("spsr_fiq", EOperatingMode::FiqMode),
+		("spsr_svc", EOperatingMode::SupervisorMode),
+		("spsr_abt", EOperatingMode::AbortMode),
+		("spsr_irq", EOperatingMode::IrqMode),
+		("spsr_und", EOperatingMode::UndefinedMode),
+	];
+
+	let mut psr_values = Map::new();
+	for (name, mode) in psrs.iter().cloned() {
+		psr_values.insert(name.to_string(), hex(cpu.get_spsr(mode).0));
+	}
+
+	let mut io_values = Map::new();
+	for register in IO_REGISTERS.iter() {
+		io_values.insert(register.name.to_string(), Value::String(format!("{:#06x}", bus.peek_16(register.address))));
+	}
+
+	let mut checksums = Map::new();
+	for (name, address, size) in MEMORY_REGIONS.iter() {
+		let mut data = vec![0; *size];
+		bus.peek_block(*address, &mut data);
+		checksums.insert(name.to_string(), hex(crc32fast::hash(&data)));
+	}
+
+	json!({
+		"version": STATE_DUMP_VERSION,
+		"frame": frame,
+		"cpu": {
+			"registers": hex_list(cpu.get_registers()),
+			"psrs": psr_values,
+			"banked_r13s": hex_list(banks.get_banked_r13s()),
+			"banked_r14s": hex_list(banks.get_banked_r14s()),
+			"banked_user_registers": hex_list(banks.get_banked_user_registers()),
+			"banked_fiq_registers": hex_list(banks.get_banked_fiq_registers()),
+		},
+		"io_registers": io_values,
+		"halted": bus.io_regs.halted,
+		"memory_crc32": checksums,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::system::{EWatchKind, Watchpoint, EWRAM_ADDR, IO_ADDR};
+
+	#[test]
+	fn dumping_is_not_an_access_of_the_game() {
+		let mut bus = SystemBus::new(Box::new([]));
+		bus.set_break_on_anomaly(true);
+		bus.add_watchpoint(Watchpoint {
+			address: IO_ADDR,
+			length: 0x400,
+			kind: EWatchKind::Access,
+		});
+		bus.add_watchpoint(Watchpoint {
+			address: EWRAM_ADDR,
+			length: 0x4,
+			kind: EWatchKind::Read,
+		});
+
+		dump_state_json(&CPU::new(), &bus, 0);
+		assert_eq!(bus.take_anomaly(), None);
+		assert!(bus.take_watchpoint_hit().is_none());
+		assert_eq!(bus.take_access_cycles(), (0, false));
+	}
+}
@@ -0,0 +1,400 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// ELF section type of the symbol table
+const SHT_SYMTAB: u32 = 2;
+/// ELF symbol types worth naming: data objects and functions
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// Where a symbol comes from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ESymbolSource {
+	/// Named by the user, saved with the game
+	Bookmark,
+	/// Read from the ELF of the ROM, loaded again with it instead of being saved
+	Elf,
+	/// Read from the .sym file of the ROM (no$gba format), loaded again with it too
+	SymFile,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+	pub name: String,
+	pub address: u32,
+	/// Bytes covered by the symbol, bookmarks only name their address
+	pub size: u32,
+	pub source: ESymbolSource,
+}
+
+impl Symbol {
+	pub fn contains(&self, address: u32) -> bool {
+		address.wrapping_sub(self.address) < self.size.max(1)
+	}
+}
+
+/// Names of addresses shared by every debugger view, they display them and accept them instead of addresses
+/// NOTE: Names are unique, symbols are kept sorted by address
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+	symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+	pub fn new() -> Self {
+		Self { symbols: Vec::new() }
+	}
+
+	pub fn get_symbols(&self) -> &[Symbol] {
+		&self.symbols
+	}
+
+	pub fn get_bookmarks(&self) -> impl Iterator<Item = &Symbol> {
+		self.symbols.iter().filter(|symbol| symbol.source == ESymbolSource::Bookmark)
+	}
+
+	/// Letters, digits, '_' and '.', not starting with a digit so names can't be mistaken for addresses
+	pub fn is_valid_name(name: &str) -> bool {
+		name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+	}
+
+	/// Add a symbol, replacing the one with the same name
+	pub fn insert(&mut self, symbol: Symbol) -> Result<()> {
+		if !Self::is_valid_name(&symbol.name) {
+			return Err(Error::new(ErrorKind::InvalidInput, format!("{} isn't a valid symbol name", symbol.name)));
+		}
+
+		self.remove(&symbol.name);
+		let index = self.symbols.partition_point(|other| other.address <= symbol.address);
+		self.symbols.insert(index, symbol);
+		Ok(())
+	}
+
+	pub fn add_bookmark(&mut self, name: &str, address: u32) -> Result<()> {
+		self.insert(Symbol {
+			name: name.to_string(),
+			address,
+			size: 0,
+			source: ESymbolSource::Bookmark,
+		})
+	}
+
+	/// Returns whether a symbol had this name
+	pub fn remove(&mut self, name: &str) -> bool {
+		let length = self.symbols.len();
+		self.symbols.retain(|symbol| symbol.name != name);
+		self.symbols.len() != length
+	}
+
+	pub fn get_address(&self, name: &str) -> Option<u32> {
+		self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.address)
+	}
+
+	/// Closest symbol covering an address, with the offset of the address in it
+	pub fn find(&self, address: u32) -> Option<(&Symbol, u32)> {
+		let end = self.symbols.partition_point(|symbol| symbol.address <= address);
+		self.symbols[..end]
+			.iter()
+			.rev()
+			.find(|symbol| symbol.contains(address))
+			.map(|symbol| (symbol, address - symbol.address))
+	}
+
+	/// Name of a symbol starting exactly at an address
+	pub fn get_name(&self, address: u32) -> Option<&str> {
+		self.find(address).filter(|(_, offset)| *offset == 0).map(|(symbol, _)| symbol.name.as_str())
+	}
+
+	/// "name" or "name+0x4" when the address is inside a symbol
+	pub fn get_label(&self, address: u32) -> Option<String> {
+		self.find(address).map(|(symbol, offset)| match offset {
+			0 => symbol.name.clone(),
+			_ => format!("{}+{:#x}", symbol.name, offset),
+		})
+	}
+
+	/// Address in hex, followed by its label if it has one
+	pub fn format_address(&self, address: u32) -> String {
+		match self.get_label(address) {
+			Some(label) => format!("{:#010x} ({})", address, label),
+			None => format!("{:#010x}", address),
+		}
+	}
+
+	/// Parse an address typed by the user: a name, "name+offset", or a number in hex (0x prefix optional)
+	/// NOTE: Names are looked up first, so a symbol named like a hex number (eg. "face") shadows it
+	pub fn resolve(&self, text: &str) -> Option<u32> {
+		let text = text.trim();
+		if let Some(address) = self.get_address(text) {
+			return Some(address);
+		}
+
+		if let Some((name, offset)) = text.split_once('+') {
+			return Some(self.get_address(name.trim())?.wrapping_add(parse_hex(offset.trim())?));
+		}
+
+		parse_hex(text)
+	}
+
+	/// Bookmarks file content, one "name = 0xaddress" per line
+	pub fn to_bookmarks_text(&self) -> String {
+		self.get_bookmarks().map(|symbol| format!("{} = {:#010x}\n", symbol.name, symbol.address)).collect()
+	}
+
+	/// Add the bookmarks of a file written by to_bookmarks_text, empty lines and lines starting with '#' are skipped
+	pub fn parse_bookmarks(&mut self, text: &str) -> Result<()> {
+		for (i, line) in text.lines().enumerate().map(|(i, line)| (i, line.trim())) {
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+
+			let invalid_line = || Error::new(ErrorKind::InvalidData, format!("Line {} isn't \"name = address\": {}", i + 1, line));
+			let (name, address) = line.split_once('=').ok_or_else(invalid_line)?;
+			let address = parse_hex(address.trim()).ok_or_else(invalid_line)?;
+			self.add_bookmark(name.trim(), address)?;
+		}
+
+		Ok(())
+	}
+
+	/// Load the bookmarks saved for a game, a missing file is an empty list
+	pub fn load_bookmarks(&mut self, path: &Path) -> Result<()> {
+		match std::fs::read_to_string(path) {
+			Ok(text) => self.parse_bookmarks(&text),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e),
+		}
+	}
+
+	pub fn save_bookmarks(&self, path: &Path) -> Result<()> {
+		if let Some(directory) = path.parent() {
+			std::fs::create_dir_all(directory)?;
+		}
+
+		std::fs::write(path, self.to_bookmarks_text())
+	}
+
+	/// Replace the .sym symbols by the ones of a no$gba symbol file: "08000100 main" per line
+	/// Returns the number of symbols added
+	/// NOTE: The file has no sizes, each symbol covers the addresses up to the next one in the same region. Directives (".arm", ".thumb", ".byt:0004"...) and comments (';') are skipped
+	pub fn load_sym_symbols(&mut self, text: &str) -> Result<usize> {
+		let mut entries: Vec<(u32, &str)> = Vec::new();
+		for (i, line) in text.lines().enumerate().map(|(i, line)| (i, line.trim())) {
+			if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+				continue;
+			}
+
+			let invalid_line = || Error::new(ErrorKind::InvalidData, format!("Line {} isn't \"address name\": {}", i + 1, line));
+			let (address, name) = line.split_once(char::is_whitespace).ok_or_else(invalid_line)?;
+			let address = parse_hex(address).ok_or_else(invalid_line)?;
+			let name = name.trim();
+			if !name.starts_with('.') && Self::is_valid_name(name) {
+				entries.push((address, name));
+			}
+		}
+		entries.sort_by_key(|(address, _)| *address);
+
+		self.symbols.retain(|symbol| symbol.source != ESymbolSource::SymFile);
+		let mut count = 0;
+		for (i, (address, name)) in entries.iter().enumerate() {
+			if self.get_address(name).is_some() {
+				continue;
+			}
+
+			let size = match entries.get(i + 1) {
+				Some((next, _)) if next >> 24 == address >> 24 => next - address,
+				_ => 0,
+			};
+			self.insert(Symbol {
+				name: name.to_string(),
+				address: *address,
+				size,
+				source: ESymbolSource::SymFile,
+			})?;
+			count += 1;
+		}
+
+		Ok(count)
+	}
+
+	/// Replace the ELF symbols by the named functions and objects of an ELF32 little endian file (eg. the .elf a ROM was built from)
+	/// Returns the number of symbols added
+	/// NOTE: Bit 0 of Thumb function addresses is cleared, bookmarks keep their name if an ELF symbol has the same one
+	pub fn load_elf_symbols(&mut self, data: &[u8]) -> Result<usize> {
+		let invalid_elf = |message: &str| Error::new(ErrorKind::InvalidData, format!("Invalid ELF: {}", message));
+		if data.len() < 52 || &data[0..4] != b"\x7fELF" {
+			return Err(invalid_elf("bad header"));
+		}
+		if data[4] != 1 || data[5] != 1 {
+			return Err(invalid_elf("only 32 bit little endian files are supported"));
+		}
+
+		let read_16 = |offset: usize| data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize);
+		let read_32 = |offset: usize| data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+		let truncated = || invalid_elf("truncated");
+
+		let section_headers = read_32(0x20).ok_or_else(truncated)? as usize;
+		let section_header_size = read_16(0x2e).ok_or_else(truncated)?;
+		let section_count = read_16(0x30).ok_or_else(truncated)?;
+		let section = |index: usize| section_headers + index * section_header_size;
+
+		self.symbols.retain(|symbol| symbol.source != ESymbolSource::Elf);
+		let mut count = 0;
+		for i in 0..section_count {
+			if read_32(section(i) + 0x4).ok_or_else(truncated)? != SHT_SYMTAB {
+				continue;
+			}
+
+			let symbols_offset = read_32(section(i) + 0x10).ok_or_else(truncated)? as usize;
+			let symbols_size = read_32(section(i) + 0x14).ok_or_else(truncated)? as usize;
+			let strings_section = read_32(section(i) + 0x18).ok_or_else(truncated)? as usize;
+			let symbol_size = (read_32(section(i) + 0x24).ok_or_else(truncated)? as usize).max(16);
+			let strings_offset = read_32(section(strings_section) + 0x10).ok_or_else(truncated)? as usize;
+
+			for symbol_offset in (symbols_offset..symbols_offset + symbols_size).step_by(symbol_size) {
+				let name_offset = strings_offset + read_32(symbol_offset).ok_or_else(truncated)? as usize;
+				let value = read_32(symbol_offset + 0x4).ok_or_else(truncated)?;
+				let size = read_32(symbol_offset + 0x8).ok_or_else(truncated)?;
+				let symbol_type = *data.get(symbol_offset + 0xc).ok_or_else(truncated)? & 0xf;
+				if symbol_type != STT_OBJECT && symbol_type != STT_FUNC {
+					continue;
+				}
+
+				let name_end = data[name_offset.min(data.len())..].iter().position(|c| *c == 0).ok_or_else(truncated)?;
+				let name = String::from_utf8_lossy(&data[name_offset..name_offset + name_end]).into_owned();
+				if !Self::is_valid_name(&name) || self.get_address(&name).is_some() {
+					continue;
+				}
+
+				let address = if symbol_type == STT_FUNC { value & !0x1 } else { value };
+				self.insert(Symbol {
+					name,
+					address,
+					size,
+					source: ESymbolSource::Elf,
+				})?;
+				count += 1;
+			}
+		}
+
+		Ok(count)
+	}
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+	let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+	u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SECTION_HEADER_SIZE: usize = 40;
+	const SYMBOL_SIZE: usize = 16;
+
+	/// Minimal ELF32 file: the header, the symbol table, its string table and the section headers (null, .symtab, .strtab)
+	fn build_elf(symbols: &[(&str, u32, u32, u8)]) -> Vec<u8> {
+		let mut strings = vec![0u8];
+		let mut symbol_table = vec![0u8; SYMBOL_SIZE];
+		for (name, value, size, symbol_type) in symbols {
+			symbol_table.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+			symbol_table.extend_from_slice(&value.to_le_bytes());
+			symbol_table.extend_from_slice(&size.to_le_bytes());
+			symbol_table.extend_from_slice(&[*symbol_type, 0, 0, 0]);
+			strings.extend_from_slice(name.as_bytes());
+			strings.push(0);
+		}
+
+		let symbols_offset = 52;
+		let strings_offset = symbols_offset + symbol_table.len();
+		let section_headers = strings_offset + strings.len();
+		let mut data = vec![0u8; 52];
+		data[0..6].copy_from_slice(b"\x7fELF\x01\x01");
+		data[0x20..0x24].copy_from_slice(&(section_headers as u32).to_le_bytes());
+		data[0x2e..0x30].copy_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+		data[0x30..0x32].copy_from_slice(&3u16.to_le_bytes());
+		data.extend_from_slice(&symbol_table);
+		data.extend_from_slice(&strings);
+
+		let mut section_header = |section_type: u32, offset: usize, size: usize, link: u32, entry_size: u32| {
+			let mut header = [0u8; SECTION_HEADER_SIZE];
+			header[0x4..0x8].copy_from_slice(&section_type.to_le_bytes());
+			header[0x10..0x14].copy_from_slice(&(offset as u32).to_le_bytes());
+			header[0x14..0x18].copy_from_slice(&(size as u32).to_le_bytes());
+			header[0x18..0x1c].copy_from_slice(&link.to_le_bytes());
+			header[0x24..0x28].copy_from_slice(&entry_size.to_le_bytes());
+			data.extend_from_slice(&header);
+		};
+		section_header(0, 0, 0, 0, 0);
+		section_header(SHT_SYMTAB, symbols_offset, symbol_table.len(), 2, SYMBOL_SIZE as u32);
+		section_header(3, strings_offset, strings.len(), 0, 0);
+		data
+	}
+
+	#[test]
+	fn sym_symbols_cover_the_addresses_up_to_the_next_one() {
+		let mut symbols = SymbolTable::new();
+		let text = "; no$gba symbols\n08000000 start\n08000100 .thumb\n08000100 main\n02000000 buffer\n08000200 loop\n08000200 .byt:0004\n";
+		assert_eq!(symbols.load_sym_symbols(text).unwrap(), 4);
+
+		let sizes: Vec<(&str, u32, u32)> = symbols.get_symbols().iter().map(|symbol| (symbol.name.as_str(), symbol.address, symbol.size)).collect();
+		// NOTE: The last symbol of a region has no size
+		assert_eq!(
+			sizes,
+			[
+				("buffer", 0x0200_0000, 0),
+				("start", 0x0800_0000, 0x100),
+				("main", 0x0800_0100, 0x100),
+				("loop", 0x0800_0200, 0)
+			]
+		);
+		assert_eq!(symbols.get_label(0x0800_0180).as_deref(), Some("main+0x80"));
+	}
+
+	#[test]
+	fn sym_symbols_replace_the_previous_ones() {
+		let mut symbols = SymbolTable::new();
+		symbols.add_bookmark("main", 0x0800_0400).unwrap();
+		symbols.load_sym_symbols("08000000 start\n").unwrap();
+		assert_eq!(symbols.load_sym_symbols("08000100 main\n08000200 loop\n").unwrap(), 1);
+
+		assert_eq!(symbols.get_address("start"), None);
+		assert_eq!(symbols.get_address("main"), Some(0x0800_0400));
+		assert_eq!(symbols.get_address("loop"), Some(0x0800_0200));
+		assert_eq!(symbols.load_sym_symbols("main\n").unwrap_err().kind(), ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn elf_functions_and_objects_are_loaded() {
+		let mut symbols = SymbolTable::new();
+		let elf = build_elf(&[("main", 0x0800_0101, 0x20, STT_FUNC), ("buffer", 0x0200_0000, 0x100, STT_OBJECT), ("main.c", 0, 0, 4)]);
+		assert_eq!(symbols.load_elf_symbols(&elf).unwrap(), 2);
+
+		// NOTE: The Thumb bit of the function address is cleared
+		let main = &symbols.get_symbols()[1];
+		assert_eq!((main.name.as_str(), main.address, main.size, main.source), ("main", 0x0800_0100, 0x20, ESymbolSource::Elf));
+		assert_eq!(symbols.get_label(0x0800_0110).as_deref(), Some("main+0x10"));
+		assert_eq!(symbols.get_label(0x0200_00ff).as_deref(), Some("buffer+0xff"));
+		assert_eq!(symbols.get_label(0x0200_0100), None);
+	}
+
+	#[test]
+	fn invalid_elf_files_are_rejected() {
+		let mut symbols = SymbolTable::new();
+		assert_eq!(symbols.load_elf_symbols(b"not an ELF file").unwrap_err().kind(), ErrorKind::InvalidData);
+
+		let elf = build_elf(&[("main", 0x0800_0000, 0x20, STT_FUNC)]);
+		let mut elf64 = elf.clone();
+		elf64[4] = 2;
+		assert_eq!(symbols.load_elf_symbols(&elf64).unwrap_err().kind(), ErrorKind::InvalidData);
+
+		// NOTE: Only the offset of the string table is read from its section header, the last one
+		let used_length = elf.len() - (SECTION_HEADER_SIZE - 0x14);
+		assert!(symbols.load_elf_symbols(&elf[..used_length]).is_ok());
+		for length in 0..used_length {
+			let result = symbols.load_elf_symbols(&elf[..length]);
+			assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData), "Truncated to {} bytes", length);
+		}
+	}
+}
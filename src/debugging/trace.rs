@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::arm7tdmi::cpu::CPU;
+use crate::arm7tdmi::PSR;
+use crate::debugging::disassembling::{disassemble_arm, disassemble_thumb};
+use crate::debugging::symbols::SymbolTable;
+use crate::system::{MemoryInterface, SystemBus};
+
+pub const DEFAULT_TRACE_PATH: &str = "trace.txt";
+/// Instructions kept by default, a few frames of a busy game
+pub const DEFAULT_TRACE_CAPACITY: usize = 200_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ETraceFormat {
+	/// Address, symbol and disassembly of each instruction
+	Disassembly,
+	/// The disassembly followed by the registers and the flags before the instruction runs
+	Registers,
+}
+
+impl ETraceFormat {
+	pub const ALL: [ETraceFormat; 2] = [ETraceFormat::Disassembly, ETraceFormat::Registers];
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ETraceFormat::Disassembly => "Disassembly",
+			ETraceFormat::Registers => "Registers",
+		}
+	}
+}
+
+/// Instruction about to be executed, disassembled only when the trace is saved
+#[derive(Debug, Copy, Clone)]
+struct TraceEntry {
+	/// THUMB instructions are followed by the next one, the second half of a BL
+	opcode: u32,
+	registers: [u32; 16],
+	cpsr: u32,
+}
+
+/// Ring buffer of the last instructions executed, saved to a text file (gzip compressed if enabled) to follow what led to a bug
+/// NOTE: Disabled by default, recording every instruction slows the emulation down
+pub struct InstructionTrace {
+	entries: VecDeque<TraceEntry>,
+	capacity: usize,
+	enabled: bool,
+	format: ETraceFormat,
+	compressed: bool,
+}
+
+impl InstructionTrace {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			entries: VecDeque::new(),
+			capacity: capacity.max(1),
+			enabled: false,
+			format: ETraceFormat::Disassembly,
+			compressed: false,
+		}
+	}
+
+	pub fn is_enabled(&self) -> bool {
+		self.enabled
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.enabled = enabled;
+	}
+
+	pub fn get_format(&self) -> ETraceFormat {
+		self.format
+	}
+
+	pub fn set_format(&mut self, format: ETraceFormat) {
+		self.format = format;
+	}
+
+	pub fn is_compressed(&self) -> bool {
+		self.compressed
+	}
+
+	pub fn set_compressed(&mut self, compressed: bool) {
+		self.compressed = compressed;
+	}
+
+	pub fn get_capacity(&self) -> usize {
+		self.capacity
+	}
+
+	/// Drops the oldest instructions if the trace is longer than the new capacity
+	pub fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity.max(1);
+		while self.entries.len() > self.capacity {
+			self.entries.pop_front();
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// NOTE: Call when the execution jumps (rewind, state load, new game), the trace wouldn't lead to the current state anymore
+	pub fn clear(&mut self) {
+		self.entries.clear();
+	}
+
+	/// Record the instruction at PC, call right before the CPU executes it
+	pub fn record(&mut self, cpu: &CPU, bus: &SystemBus) {
+		if !self.enabled {
+			return;
+		}
+
+		let pc = cpu.get_current_pc();
+		let mut registers = [0; 16];
+		registers.copy_from_slice(cpu.get_registers());
+		let opcode = if cpu.get_cpsr().get_t() {
+			bus.read_16(pc) as u32 | (bus.read_16(pc.wrapping_add(2)) as u32) << 16
+		} else {
+			bus.read_32(pc)
+		};
+		if self.entries.len() == self.capacity {
+			self.entries.pop_front();
+		}
+		self.entries.push_back(TraceEntry {
+			opcode,
+			registers,
+			cpsr: cpu.get_cpsr().0,
+		});
+	}
+
+	/// Write the trace, oldest instruction first, ".gz" is appended to the path of a compressed trace
+	/// Returns the path written
+	pub fn save(&self, path: &Path, symbols: &SymbolTable) -> Result<PathBuf> {
+		let path = match self.compressed {
+			true if path.extension().is_none_or(|extension| extension != "gz") => PathBuf::from(format!("{}.gz", path.display())),
+			_ => path.to_path_buf(),
+		};
+		if let Some(directory) = path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+			std::fs::create_dir_all(directory)?;
+		}
+
+		let file = BufWriter::new(File::create(&path)?);
+		if self.compressed {
+			let mut encoder = GzEncoder::new(file, Compression::default());
+			self.write_entries(&mut encoder, symbols)?;
+			encoder.finish()?.flush()?;
+		} else {
+			let mut file = file;
+			self.write_entries(&mut file, symbols)?;
+			file.flush()?;
+		}
+
+		Ok(path)
+	}
+
+	fn write_entries(&self, writer: &mut impl Write, symbols: &SymbolTable) -> Result<()> {
+		for entry in self.entries.iter() {
+			let pc = entry.registers[15];
+			let cpsr = PSR(entry.cpsr);
+			let label = symbols.get_label(pc).map(|label| format!(" <{}>", label)).unwrap_or_default();
+			let instruction = if cpsr.get_t() {
+				disassemble_thumb(pc, entry.opcode)
+			} else {
+				disassemble_arm(pc, entry.opcode)
+			};
+			write!(writer, "{:#010X}{}: {}", pc, label, instruction)?;
+			if let Some(target) = instruction.branch_target.and_then(|target| symbols.get_label(target)) {
+				write!(writer, " ; {}", target)?;
+			}
+
+			if self.format == ETraceFormat::Registers {
+				for (index, value) in entry.registers[..15].iter().enumerate() {
+					write!(writer, " r{}={:08X}", index, value)?;
+				}
+				let flag = |set: bool, name: char| if set { name } else { name.to_ascii_lowercase() };
+				write!(
+					writer,
+					" cpsr={:08X} {}{}{}{}{}",
+					entry.cpsr,
+					flag(cpsr.get_n(), 'N'),
+					flag(cpsr.get_z(), 'Z'),
+					flag(cpsr.get_c(), 'C'),
+					flag(cpsr.get_v(), 'V'),
+					flag(cpsr.get_t(), 'T')
+				)?;
+			}
+			writeln!(writer)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for InstructionTrace {
+	fn default() -> Self {
+		Self::new(DEFAULT_TRACE_CAPACITY)
+	}
+}
@@ -0,0 +1,1302 @@
+use std::path::Path;
+
+use bitfield::Bit;
+use imgui::*;
+
+use crate::arm7tdmi::cpu::{CPU, PROGRAM_COUNTER_REGISTER};
+use crate::cheats::{Cheat, CheatManager, ECheatFormat};
+use crate::debugging::ab_compare::{AbComparison, EAbOption};
+use crate::debugging::assembling::assemble;
+use crate::debugging::breakpoints::{Breakpoint, BreakpointCondition};
+use crate::debugging::call_stack::{CallStack, ERunTarget};
+use crate::debugging::compat::{build_bug_report, CompatEntry};
+use crate::debugging::disassembling::disassemble_at;
+use crate::debugging::hex_editor::{get_row_value, to_ascii, HexEditor, HEX_EDITOR_ROW_LENGTH};
+use crate::debugging::io_registers::{get_field_value, set_field_value, EIoGroup, IO_REGISTERS};
+use crate::debugging::profiler::{CpuProfiler, PROFILER_BUCKET_SIZES};
+use crate::debugging::ram_search::{get_size, parse_value, ESearchComparison, ESearchTarget, RamSearch, RAM_SEARCH_WIDTHS};
+use crate::debugging::symbols::SymbolTable;
+use crate::debugging::trace::ETraceFormat;
+use crate::debugging::{dump_memory_region, load_memory_region, CpuSnapshot, DebuggerState, BANK_NAMES, MEMORY_REGIONS, PSR_MODES, WATCH_KINDS};
+use crate::ppu::tiles::TILE_WIDTH;
+use crate::ppu::{to_rgb15, ESpriteMode, SPRITE_PALETTE_START_INDEX};
+use crate::system::{EWatchKind, MemoryInterface, SystemBus, Watchpoint, PALETTE_RAM_ADDR};
+
+/// Color of the symbol names labelling the disassembly
+const SYMBOL_COLOR: [f32; 4] = [0.4, 1.0, 0.6, 1.0];
+
+pub fn build_memory_debug_window(cpu: &CPU, bus: &mut SystemBus, debugger: &mut DebuggerState, show_memory_window: &mut bool, ui: &&mut Ui) {
+	let DebuggerState {
+		inspected_address: address,
+		debug_mode,
+		execute_step,
+		breakpoints,
+		call_stack,
+		run_target,
+		trace,
+		trace_path,
+		selected_region,
+		region_file_path,
+		symbols,
+		bookmarks_path,
+		bookmark_name,
+		go_to_address,
+		watch_length,
+		watch_kind,
+		patch_instruction,
+		..
+	} = debugger;
+
+	Window::new(im_str!("Current Memory"))
+		.size([600.0, 500.0], Condition::FirstUseEver)
+		.opened(show_memory_window)
+		.position([750.0, 75.0], Condition::FirstUseEver)
+		.build(ui, || {
+			if !*debug_mode {
+				*address = cpu.get_current_pc();
+			}
+
+			let pc_offset = if cpu.get_cpsr().get_t() { 4 } else { 8 };
+
+			ui.text("Current instruction highlighted");
+
+			if ui.button(im_str!("Step"), [0.0, 0.0]) || ui.is_key_down(Key::Space) && *debug_mode {
+				*execute_step = true;
+				*address = cpu.get_current_pc();
+			}
+			ui.same_line(0.0);
+			if ui.button(im_str!("Step Over"), [0.0, 0.0]) && *debug_mode {
+				*run_target = Some(ERunTarget::step_over(call_stack));
+				*debug_mode = false;
+			}
+			ui.same_line(0.0);
+			if ui.button(im_str!("Step Out"), [0.0, 0.0]) && *debug_mode {
+				*run_target = Some(ERunTarget::step_out(call_stack, cpu));
+				*debug_mode = false;
+			}
+			ui.same_line(0.0);
+			ui.checkbox(im_str!("Debug"), debug_mode);
+
+			let mut new_address = *address as i32;
+			if ui.button(im_str!("Current PC"), [0.0, 0.0]) {
+				*address = cpu.get_current_pc();
+			}
+
+			ui.same_line(0.0);
+			let mut go_to = None;
+			if ui.input_int(im_str!("Address"), &mut new_address).step(4).chars_hexadecimal(true).build() {
+				go_to = Some(new_address as u32);
+			}
+			// NOTE: Accepts the names of the symbol table, eg. "player_hp" or "main+0x10"
+			if ui.input_text(im_str!("Go to"), go_to_address).resize_buffer(true).enter_returns_true(true).build() {
+				go_to = symbols.resolve(go_to_address.to_str());
+				if go_to.is_none() {
+					eprintln!("Failed to go to {}: unknown symbol or address", go_to_address.to_str());
+				}
+			}
+
+			let (region, offset) = SystemBus::resolve(new_address as u32);
+			match symbols.get_label(new_address as u32) {
+				Some(label) => ui.text(format!("{} + {:#x} ({})", region.get_name(), offset, label)),
+				None => ui.text(format!("{} + {:#x}", region.get_name(), offset)),
+			}
+
+			// NOTE: Conditions and hit counts are set in the Breakpoints window
+			if ui.button(im_str!("Set/Unset Breakpoint"), [0.0, 0.0]) && *debug_mode {
+				breakpoints.toggle(new_address as u32);
+			}
+
+			ui.same_line(0.0);
+			let mut tracing = trace.is_enabled();
+			if ui.checkbox(im_str!("Trace"), &mut tracing) {
+				trace.set_enabled(tracing);
+			}
+			if breakpoints.contains(new_address as u32) {
+				ui.text(format!("Breakpoint: {}", symbols.format_address(new_address as u32)));
+			}
+
+			if CollapsingHeader::new(im_str!("Bookmarks")).build(ui) {
+				ui.input_text(im_str!("Name"), bookmark_name).resize_buffer(true).build();
+				ui.same_line(0.0);
+				if ui.button(im_str!("Bookmark Address"), [0.0, 0.0]) {
+					match symbols
+						.add_bookmark(bookmark_name.to_str(), new_address as u32)
+						.and_then(|_| symbols.save_bookmarks(bookmarks_path))
+					{
+						Ok(()) => bookmark_name.clear(),
+						Err(e) => eprintln!("Failed to add the bookmark: {}", e),
+					}
+				}
+
+				// NOTE: The bookmarks double as watches, their current value is shown next to them
+				let mut removed_bookmark = None;
+				ui.columns(4, im_str!("bookmarks"), true);
+				for symbol in symbols.get_bookmarks() {
+					if Selectable::new(&im_str!("{}", symbol.name)).build(ui) {
+						go_to = Some(symbol.address);
+					}
+					ui.next_column();
+					ui.text(format!("{:#010X}", symbol.address));
+					ui.next_column();
+					ui.text(format!("{:#010X}", bus.read_32(symbol.address & !0x3)));
+					ui.next_column();
+					if ui.small_button(&im_str!("Remove##{}", symbol.name)) {
+						removed_bookmark = Some(symbol.name.clone());
+					}
+					ui.next_column();
+				}
+				ui.columns(1, im_str!(""), false);
+
+				if let Some(name) = removed_bookmark {
+					symbols.remove(&name);
+					if let Err(e) = symbols.save_bookmarks(bookmarks_path) {
+						eprintln!("Failed to save the bookmarks: {}", e);
+					}
+				}
+			}
+
+			// NOTE: The emulation loop breaks into debug mode on the first access of the game to a watched range
+			if CollapsingHeader::new(im_str!("Watchpoints")).build(ui) {
+				ui.input_int(im_str!("Length"), watch_length).chars_hexadecimal(true).build();
+				*watch_length = (*watch_length).max(1);
+				let kind_names: Vec<ImString> = WATCH_KINDS.iter().map(|(name, _)| ImString::new(*name)).collect();
+				let kind_names: Vec<&ImStr> = kind_names.iter().map(|name| name.as_ref()).collect();
+				ComboBox::new(im_str!("Kind")).build_simple_string(ui, watch_kind, &kind_names);
+				if ui.button(im_str!("Watch Address"), [0.0, 0.0]) {
+					bus.add_watchpoint(Watchpoint {
+						address: new_address as u32,
+						length: *watch_length as u32,
+						kind: WATCH_KINDS[*watch_kind].1,
+					});
+				}
+
+				let mut removed_watchpoint = None;
+				ui.columns(3, im_str!("watchpoints"), true);
+				for (index, watchpoint) in bus.get_watchpoints().iter().enumerate() {
+					if Selectable::new(&im_str!("{}##watch{}", symbols.format_address(watchpoint.address), index)).build(ui) {
+						go_to = Some(watchpoint.address);
+					}
+					ui.next_column();
+					let kind = WATCH_KINDS.iter().find(|(_, kind)| *kind == watchpoint.kind).map_or("", |(name, _)| name);
+					ui.text(format!("{:#x} bytes, {}", watchpoint.length, kind));
+					ui.next_column();
+					if ui.small_button(&im_str!("Remove##watch{}", index)) {
+						removed_watchpoint = Some(*watchpoint);
+					}
+					ui.next_column();
+				}
+				ui.columns(1, im_str!(""), false);
+
+				if let Some(watchpoint) = removed_watchpoint {
+					bus.remove_watchpoint(watchpoint);
+				}
+			}
+
+			// NOTE: Assembled in the state the CPU runs in, like the disassembly, patches to the ROM last until it's reloaded
+			if CollapsingHeader::new(im_str!("Patch")).build(ui) {
+				let submitted = ui
+					.input_text(im_str!("Instruction"), patch_instruction)
+					.resize_buffer(true)
+					.enter_returns_true(true)
+					.build();
+				ui.same_line(0.0);
+				if ui.button(im_str!("Assemble"), [0.0, 0.0]) || submitted {
+					let is_thumb = cpu.get_cpsr().get_t();
+					let patch_address = new_address as u32 & if is_thumb { !0x1 } else { !0x3 };
+					match assemble(patch_instruction.to_str(), patch_address, is_thumb, symbols) {
+						Ok(bytes) => bus.patch(patch_address, &bytes),
+						Err(e) => eprintln!("Failed to assemble {}: {}", patch_instruction.to_str(), e),
+					}
+				}
+			}
+
+			if let Some(new_address) = go_to.filter(|_| *debug_mode) {
+				*address = new_address;
+			}
+
+			// NOTE: The trace is also saved when the game hits a breakpoint
+			if CollapsingHeader::new(im_str!("Instruction Trace")).build(ui) {
+				ui.input_text(im_str!("Trace File"), trace_path).resize_buffer(true).build();
+				let format_names: Vec<ImString> = ETraceFormat::ALL.iter().map(|format| ImString::new(format.get_name())).collect();
+				let format_names: Vec<&ImStr> = format_names.iter().map(|name| name.as_ref()).collect();
+				let mut format_index = ETraceFormat::ALL.iter().position(|format| *format == trace.get_format()).unwrap_or_default();
+				if ComboBox::new(im_str!("Format")).build_simple_string(ui, &mut format_index, &format_names) {
+					trace.set_format(ETraceFormat::ALL[format_index]);
+				}
+				let mut capacity = trace.get_capacity() as i32;
+				if ui.input_int(im_str!("Instructions"), &mut capacity).step(10_000).build() {
+					trace.set_capacity(capacity.max(1) as usize);
+				}
+				let mut compressed = trace.is_compressed();
+				if ui.checkbox(im_str!("Compress (gzip)"), &mut compressed) {
+					trace.set_compressed(compressed);
+				}
+
+				ui.text(format!("{} instructions recorded", trace.len()));
+				if ui.button(im_str!("Save Trace"), [0.0, 0.0]) {
+					match trace.save(Path::new(trace_path.to_str()), symbols) {
+						Ok(path) => println!("Trace saved to {}", path.display()),
+						Err(e) => eprintln!("Failed to save the trace: {}", e),
+					}
+				}
+				ui.same_line(0.0);
+				if ui.button(im_str!("Clear Trace"), [0.0, 0.0]) {
+					trace.clear();
+				}
+			}
+
+			if CollapsingHeader::new(im_str!("Regions")).build(&ui) {
+				let region_names: Vec<ImString> = MEMORY_REGIONS.iter().map(|(name, address, _)| im_str!("{:#010X}: {}", address, name)).collect();
+				let region_names: Vec<&ImStr> = region_names.iter().map(|name| name.as_ref()).collect();
+				ComboBox::new(im_str!("Region")).build_simple_string(ui, selected_region, &region_names);
+				ui.input_text(im_str!("File"), region_file_path).resize_buffer(true).build();
+
+				let path = Path::new(region_file_path.to_str());
+				if ui.button(im_str!("Dump region to file..."), [0.0, 0.0]) {
+					if let Err(error) = dump_memory_region(bus, *selected_region, path) {
+						eprintln!("Failed to dump memory region: {}", error);
+					}
+				}
+				ui.same_line(0.0);
+				if ui.button(im_str!("Load file into region..."), [0.0, 0.0]) {
+					if let Err(error) = load_memory_region(bus, *selected_region, path) {
+						eprintln!("Failed to load memory region: {}", error);
+					}
+				}
+			}
+
+			ui.separator();
+			if let Some(scroll_token) = ChildWindow::new(im_str!("##ScrollingRegion")).begin(&ui) {
+				ui.columns(3, im_str!("system"), true);
+				ui.set_column_width(0, 95.0);
+
+				const ENTRIES: i32 = 20;
+				let starting_address = address.saturating_sub((pc_offset / 2) * (ENTRIES / 2) as u32);
+				let mut list_clipper = ListClipper::new(ENTRIES).begin(&ui);
+				while list_clipper.step() {
+					for row in list_clipper.display_start()..list_clipper.display_end() {
+						let address = starting_address.saturating_add(row as u32 * (pc_offset / 2));
+						if address <= u32::max_value() - (pc_offset / 2) {
+							Selectable::new(&*im_str!("{:#010X}:", address))
+								.selected(address == cpu.get_current_pc())
+								.span_all_columns(true)
+								.build(&ui);
+							let popup_id = im_str!("instruction{}", address);
+							if ui.is_item_clicked(MouseButton::Right) {
+								ui.open_popup(&popup_id);
+							}
+							ui.popup(&popup_id, || {
+								if MenuItem::new(im_str!("Run to Here")).enabled(*debug_mode).build(ui) {
+									*run_target = Some(ERunTarget::Address(address));
+									*debug_mode = false;
+								}
+								if MenuItem::new(im_str!("Set/Unset Breakpoint")).build(ui) {
+									breakpoints.toggle(address);
+								}
+							});
+							ui.next_column();
+
+							for j in 0..pc_offset / 2 {
+								let value = bus.read_8(address as u32 + j);
+								let color = if value == 0 { [0.5, 0.5, 0.5, 0.5] } else { [1.0, 1.0, 1.0, 1.0] };
+								ui.text_colored(color, format!("{:02X}", value));
+								if j != 3 {
+									ui.same_line(0.0);
+								}
+							}
+
+							ui.next_column();
+							if let Some(name) = symbols.get_name(address) {
+								ui.text_colored(SYMBOL_COLOR, format!("{}:", name));
+								ui.same_line(0.0);
+							}
+							let instruction = disassemble_at(bus, address, cpu.get_cpsr().get_t());
+							ui.text(instruction.to_string());
+							// NOTE: Name of the function or label a branch goes to
+							if let Some(label) = instruction.branch_target.and_then(|target| symbols.get_label(target)) {
+								ui.same_line(0.0);
+								ui.text_colored(SYMBOL_COLOR, format!("; {}", label));
+							}
+							ui.next_column();
+							ui.separator();
+						}
+					}
+				}
+				ui.columns(1, im_str!(""), false);
+
+				scroll_token.end(&ui);
+			}
+		});
+}
+
+/// Backtrace of the calls tracked by the call stack, the innermost first like in other debuggers
+/// Clicking a function or a call site shows it in the memory window
+pub fn build_callstack_debug_window(call_stack: &CallStack, bus: &SystemBus, symbols: &SymbolTable, address: &mut u32, opened: &mut bool, ui: &&mut Ui) {
+	Window::new(im_str!("Call Stack"))
+		.size([550.0, 300.0], Condition::FirstUseEver)
+		.opened(opened)
+		.build(ui, || {
+			if call_stack.get_frames().is_empty() {
+				ui.text_disabled("No calls tracked yet");
+			}
+
+			ui.columns(3, im_str!("callstack"), true);
+			for (index, frame) in call_stack.get_frames().iter().enumerate().rev() {
+				if Selectable::new(&im_str!("#{} {}", index, symbols.format_address(frame.target))).build(ui) {
+					*address = frame.target;
+				}
+				ui.next_column();
+				ui.text(disassemble_at(bus, frame.target, frame.is_thumb).to_string());
+				ui.next_column();
+				if Selectable::new(&im_str!("from {}##call{}", symbols.format_address(frame.call_address), index)).build(ui) {
+					*address = frame.call_address;
+				}
+				ui.next_column();
+			}
+			ui.columns(1, im_str!(""), false);
+		});
+}
+
+/// Instructions executed per memory region and the hottest address buckets, clicking a bucket shows it in the memory window
+pub fn build_profiler_window(profiler: &mut CpuProfiler, symbols: &SymbolTable, address: &mut u32, opened: &mut bool, ui: &&mut Ui) {
+	const HOT_BUCKETS: usize = 50;
+
+	Window::new(im_str!("Profiler")).size([450.0, 500.0], Condition::FirstUseEver).opened(opened).build(ui, || {
+		let mut enabled = profiler.is_enabled();
+		if ui.checkbox(im_str!("Profile"), &mut enabled) {
+			profiler.set_enabled(enabled);
+		}
+		ui.same_line(0.0);
+		if ui.button(im_str!("Reset"), [0.0, 0.0]) {
+			profiler.clear();
+		}
+
+		let size_names: Vec<ImString> = PROFILER_BUCKET_SIZES.iter().map(|size| im_str!("{:#x} bytes", size)).collect();
+		let size_names: Vec<&ImStr> = size_names.iter().map(|name| name.as_ref()).collect();
+		let mut size_index = PROFILER_BUCKET_SIZES.iter().position(|size| *size == profiler.get_bucket_size()).unwrap_or_default();
+		if ComboBox::new(im_str!("Bucket")).build_simple_string(ui, &mut size_index, &size_names) {
+			profiler.set_bucket_size(PROFILER_BUCKET_SIZES[size_index]);
+		}
+
+		let total = profiler.get_total().max(1) as f32;
+		ui.text(format!("{} instructions", profiler.get_total()));
+		ui.separator();
+		for (region, count) in profiler.get_region_counts() {
+			let fraction = count as f32 / total;
+			ProgressBar::new(fraction).size([150.0, 0.0]).overlay_text(&im_str!("{:.1}%", fraction * 100.0)).build(ui);
+			ui.same_line(0.0);
+			ui.text(region.get_name());
+		}
+
+		ui.separator();
+		ui.columns(3, im_str!("hot_buckets"), true);
+		for (start, count) in profiler.get_hot_buckets(HOT_BUCKETS) {
+			if Selectable::new(&im_str!("{}##bucket", symbols.format_address(start))).build(ui) {
+				*address = start;
+			}
+			ui.next_column();
+			ui.text(format!("{}", count));
+			ui.next_column();
+			ui.text(format!("{:.2}%", count as f32 / total * 100.0));
+			ui.next_column();
+		}
+		ui.columns(1, im_str!(""), false);
+	});
+}
+
+/// Searches the work RAM for the address of a value, the value is compared to the previous search when left empty
+pub fn build_ram_search_window(search: &mut RamSearch, bus: &mut SystemBus, comparison: &mut usize, value_text: &mut ImString, address: &mut u32, opened: &mut bool, ui: &&mut Ui) {
+	const RESULTS_SHOWN: usize = 100;
+
+	Window::new(im_str!("RAM Search"))
+		.size([400.0, 450.0], Condition::FirstUseEver)
+		.opened(opened)
+		.build(ui, || {
+			let width_names: Vec<ImString> = RAM_SEARCH_WIDTHS.iter().map(|(name, _)| ImString::new(*name)).collect();
+			let width_names: Vec<&ImStr> = width_names.iter().map(|name| name.as_ref()).collect();
+			let mut width_index = RAM_SEARCH_WIDTHS.iter().position(|(_, width)| *width == search.get_width()).unwrap_or_default();
+			if ComboBox::new(im_str!("Width")).build_simple_string(ui, &mut width_index, &width_names) {
+				search.set_width(RAM_SEARCH_WIDTHS[width_index].1);
+			}
+			let comparison_names: Vec<ImString> = ESearchComparison::ALL.iter().map(|comparison| ImString::new(comparison.get_name())).collect();
+			let comparison_names: Vec<&ImStr> = comparison_names.iter().map(|name| name.as_ref()).collect();
+			ComboBox::new(im_str!("Comparison")).build_simple_string(ui, comparison, &comparison_names);
+			ui.input_text(im_str!("Value"), value_text).resize_buffer(true).build();
+
+			let target = match value_text.to_str().trim() {
+				"" => Some(ESearchTarget::Previous),
+				text => parse_value(text).map(ESearchTarget::Value),
+			};
+			if ui.button(im_str!("New Search"), [0.0, 0.0]) {
+				search.start(bus);
+			}
+			ui.same_line(0.0);
+			if ui.button(im_str!("Search"), [0.0, 0.0]) {
+				if !search.is_started() {
+					search.start(bus);
+				}
+				match target {
+					Some(target) => search.filter(bus, ESearchComparison::ALL[*comparison], target),
+					None => eprintln!("Failed to search: invalid value {}", value_text.to_str()),
+				}
+			}
+			ui.same_line(0.0);
+			if ui.button(im_str!("Reset"), [0.0, 0.0]) {
+				search.reset();
+			}
+			ui.text_disabled("Empty value: compare to the previous search (eg. Not Equal for changed)");
+
+			ui.separator();
+			if !search.is_started() {
+				ui.text("No search started");
+				return;
+			}
+			let candidates = search.get_candidates();
+			ui.text(format!("{} results", candidates.len()));
+			let size = get_size(search.get_width());
+			let mut watched_address = None;
+			ui.columns(4, im_str!("ram_search"), true);
+			for (address_found, previous) in candidates.iter().take(RESULTS_SHOWN) {
+				if Selectable::new(&im_str!("{:#010X}##result", address_found)).build(ui) {
+					*address = *address_found;
+				}
+				ui.next_column();
+				ui.text(format!("{:#0width$X}", search.read(bus, *address_found), width = size * 2 + 2));
+				ui.next_column();
+				ui.text_disabled(format!("{:#0width$X}", previous, width = size * 2 + 2));
+				ui.next_column();
+				if ui.small_button(&im_str!("Watch##{}", address_found)) {
+					watched_address = Some(*address_found);
+				}
+				ui.next_column();
+			}
+			ui.columns(1, im_str!(""), false);
+			if candidates.len() > RESULTS_SHOWN {
+				ui.text_disabled(format!("{} more, search again to narrow them down", candidates.len() - RESULTS_SHOWN));
+			}
+
+			// NOTE: Breaks into the debugger on the next write, to find the code changing the value
+			if let Some(watched_address) = watched_address {
+				bus.add_watchpoint(Watchpoint {
+					address: watched_address,
+					length: size as u32,
+					kind: EWatchKind::Write,
+				});
+			}
+		});
+}
+
+/// Editable hex view of the memory from a base address with an ASCII pane, values are written with the selected width
+/// NOTE: The text field resolves addresses and symbols to go to, and names the bookmarks of the selected address
+pub fn build_hex_editor_window(editor: &mut HexEditor, bus: &mut SystemBus, debugger: &mut DebuggerState, text: &mut ImString, opened: &mut bool, ui: &&mut Ui) {
+	let DebuggerState { symbols, bookmarks_path, .. } = debugger;
+
+	Window::new(im_str!("Hex Editor"))
+		.size([640.0, 500.0], Condition::FirstUseEver)
+		.opened(opened)
+		.build(ui, || {
+			let region_names: Vec<ImString> = MEMORY_REGIONS
+				.iter()
+				.map(|(name, address, _)| im_str!("{:#010X}: {}", address, name))
+				.chain(std::iter::once(ImString::new("Custom")))
+				.collect();
+			let region_names: Vec<&ImStr> = region_names.iter().map(|name| name.as_ref()).collect();
+			let mut region_index = MEMORY_REGIONS
+				.iter()
+				.position(|(_, address, size)| *address == editor.get_base_address() && *size as u32 == editor.get_length())
+				.unwrap_or(MEMORY_REGIONS.len());
+			if ComboBox::new(im_str!("Region")).build_simple_string(ui, &mut region_index, &region_names) {
+				if let Some((_, address, size)) = MEMORY_REGIONS.get(region_index) {
+					editor.set_region(*address, *size as u32);
+				}
+			}
+
+			let mut base_address = editor.get_base_address() as i32;
+			if ui
+				.input_int(im_str!("Base"), &mut base_address)
+				.chars_hexadecimal(true)
+				.step(0)
+				.enter_returns_true(true)
+				.build()
+			{
+				editor.set_region(base_address as u32, editor.get_length());
+			}
+			let mut length = editor.get_length() as i32;
+			if ui
+				.input_int(im_str!("Length"), &mut length)
+				.chars_hexadecimal(true)
+				.step(0)
+				.enter_returns_true(true)
+				.build()
+			{
+				editor.set_region(editor.get_base_address(), length as u32);
+			}
+
+			let width_names: Vec<ImString> = RAM_SEARCH_WIDTHS.iter().map(|(name, _)| ImString::new(*name)).collect();
+			let width_names: Vec<&ImStr> = width_names.iter().map(|name| name.as_ref()).collect();
+			let mut width_index = RAM_SEARCH_WIDTHS.iter().position(|(_, width)| *width == editor.get_width()).unwrap_or_default();
+			if ComboBox::new(im_str!("Width")).build_simple_string(ui, &mut width_index, &width_names) {
+				editor.set_width(RAM_SEARCH_WIDTHS[width_index].1);
+			}
+
+			let submitted = ui.input_text(im_str!("Address / Name"), text).resize_buffer(true).enter_returns_true(true).build();
+			if ui.button(im_str!("Go to"), [0.0, 0.0]) || submitted {
+				match symbols.resolve(text.to_str()) {
+					Some(address) => editor.go_to(address),
+					None => eprintln!("Failed to go to {}: unknown symbol or address", text.to_str()),
+				}
+			}
+			ui.same_line(0.0);
+			if ui.button(im_str!("Bookmark Selected"), [0.0, 0.0]) {
+				let address = editor.get_selected().unwrap_or_else(|| editor.get_base_address());
+				match symbols.add_bookmark(text.to_str(), address).and_then(|_| symbols.save_bookmarks(bookmarks_path)) {
+					Ok(()) => text.clear(),
+					Err(e) => eprintln!("Failed to add the bookmark: {}", e),
+				}
+			}
+
+			if CollapsingHeader::new(im_str!("Bookmarks")).build(ui) {
+				let mut go_to = None;
+				for symbol in symbols.get_bookmarks() {
+					if Selectable::new(&im_str!("{:#010X} {}", symbol.address, symbol.name)).build(ui) {
+						go_to = Some(symbol.address);
+					}
+				}
+				if let Some(address) = go_to {
+					editor.go_to(address);
+				}
+			}
+
+			match editor.get_selected() {
+				Some(address) => ui.text(format!("Selected: {}", symbols.format_address(address))),
+				None => ui.text_disabled("Click a value to edit it, Enter writes it and Escape cancels"),
+			}
+
+			ui.separator();
+			if let Some(scroll_token) = ChildWindow::new(im_str!("##HexEditor")).horizontal_scrollbar(true).begin(ui) {
+				let width = editor.get_width();
+				let size = get_size(width);
+				let cell_width = ui.calc_text_size(&ImString::new("0".repeat(size * 2)), false, 0.0)[0];
+				let mut list_clipper = ListClipper::new(editor.get_row_count() as i32).begin(ui);
+				while list_clipper.step() {
+					for row in list_clipper.display_start()..list_clipper.display_end() {
+						let row_address = editor.get_base_address() + row as u32 * HEX_EDITOR_ROW_LENGTH;
+						let mut bytes = [0; HEX_EDITOR_ROW_LENGTH as usize];
+						bus.read_block(row_address, &mut bytes);
+
+						ui.text(format!("{:08X}:", row_address));
+						for offset in (0..bytes.len()).step_by(size) {
+							let address = row_address + offset as u32;
+							let value = get_row_value(&bytes, offset, width);
+							ui.same_line(0.0);
+							if editor.get_selected() != Some(address) {
+								if Selectable::new(&im_str!("{:0width$X}##{}", value, address, width = size * 2))
+									.size([cell_width, 0.0])
+									.build(ui)
+								{
+									editor.select(Some(address));
+								}
+								continue;
+							}
+
+							if editor.take_focus_selected() {
+								ui.set_keyboard_focus_here(FocusedWidget::Next);
+							}
+							ui.set_next_item_width(cell_width + 2.0 * ui.clone_style().frame_padding[0]);
+							let mut input = im_str!("{:0width$X}", value, width = size * 2);
+							let entered = ui
+								.input_text(&im_str!("##edit{}", address), &mut input)
+								.chars_hexadecimal(true)
+								.resize_buffer(true)
+								.enter_returns_true(true)
+								.build();
+							match Some(input.to_str().trim()).filter(|_| entered).and_then(|input| u32::from_str_radix(input, 16).ok()) {
+								Some(new_value) => {
+									editor.write(bus, address, new_value);
+									editor.select_next();
+								}
+								// NOTE: Clicking elsewhere or pressing Escape leaves the value unchanged
+								None if ui.is_item_deactivated() => editor.select(None),
+								None => {}
+							}
+						}
+						ui.same_line(0.0);
+						ui.text(to_ascii(&bytes));
+					}
+				}
+				scroll_token.end(ui);
+			}
+		});
+}
+
+/// List of the breakpoints with their hit counts, breakpoints are added from an address (or symbol), an optional condition and a number of hits to ignore
+pub fn build_breakpoints_window(debugger: &mut DebuggerState, opened: &mut bool, ui: &&mut Ui) {
+	let DebuggerState {
+		breakpoints,
+		symbols,
+		breakpoint_address_text: address_text,
+		breakpoint_condition_text: condition_text,
+		breakpoint_ignore_count: ignore_count,
+		..
+	} = debugger;
+
+	Window::new(im_str!("Breakpoints"))
+		.size([450.0, 300.0], Condition::FirstUseEver)
+		.opened(opened)
+		.build(ui, || {
+			ui.input_text(im_str!("Address"), address_text).resize_buffer(true).build();
+			ui.input_text(im_str!("Condition"), condition_text).resize_buffer(true).build();
+			ui.input_int(im_str!("Ignore Hits"), ignore_count).build();
+			*ignore_count = (*ignore_count).max(0);
+
+			let address = symbols.resolve(address_text.to_str());
+			let condition = Some(condition_text.to_str().trim()).filter(|text| !text.is_empty()).map(BreakpointCondition::parse);
+			if ui.button(im_str!("Add"), [0.0, 0.0]) {
+				match (address, condition) {
+					(None, _) => eprintln!("Failed to add the breakpoint: unknown symbol or address {}", address_text.to_str()),
+					(_, Some(None)) => eprintln!("Failed to add the breakpoint: invalid condition {}", condition_text.to_str()),
+					(Some(address), condition) => {
+						breakpoints.add(Breakpoint {
+							condition: condition.flatten(),
+							ignore_count: *ignore_count as u32,
+							..Breakpoint::new(address)
+						});
+						condition_text.clear();
+						*ignore_count = 0;
+					}
+				}
+			}
+			ui.same_line(0.0);
+			if ui.button(im_str!("Reset Hits"), [0.0, 0.0]) {
+				breakpoints.reset_hit_counts();
+			}
+			ui.text_disabled("Conditions compare a register to a value, eg. r0 == 0x5 or sp < 0x3007e00");
+
+			ui.separator();
+			let mut removed_breakpoint = None;
+			ui.columns(5, im_str!("breakpoints"), true);
+			for index in 0..breakpoints.get_breakpoints().len() {
+				let hit_count = breakpoints.get_hit_count(index);
+				let breakpoint = match breakpoints.get_mut_breakpoint(index) {
+					Some(breakpoint) => breakpoint,
+					None => break,
+				};
+
+				ui.checkbox(&im_str!("{}##breakpoint{}", symbols.format_address(breakpoint.address), index), &mut breakpoint.enabled);
+				ui.next_column();
+				ui.text(breakpoint.condition.map(|condition| condition.get_text()).unwrap_or_default());
+				ui.next_column();
+				let mut ignored = breakpoint.ignore_count as i32;
+				if ui.input_int(&im_str!("##ignore{}", index), &mut ignored).build() {
+					breakpoint.ignore_count = ignored.max(0) as u32;
+				}
+				ui.next_column();
+				ui.text(format!("{} hits", hit_count));
+				ui.next_column();
+				if ui.small_button(&im_str!("Remove##breakpoint{}", index)) {
+					removed_breakpoint = Some(index);
+				}
+				ui.next_column();
+			}
+			ui.columns(1, im_str!(""), false);
+
+			if let Some(index) = removed_breakpoint {
+				breakpoints.remove(index);
+			}
+		});
+}
+
+pub fn build_cheats_window(cheats: &mut CheatManager, name: &mut ImString, code: &mut ImString, format: &mut usize, opened: &mut bool, ui: &&mut Ui) {
+	Window::new(im_str!("Cheats")).size([400.0, 350.0], Condition::FirstUseEver).opened(opened).build(ui, || {
+		ui.input_text(im_str!("Name"), name).resize_buffer(true).build();
+		let format_names: Vec<ImString> = ECheatFormat::ALL.iter().map(|format| ImString::new(format.get_name())).collect();
+		let format_names: Vec<&ImStr> = format_names.iter().map(|name| name.as_ref()).collect();
+		ComboBox::new(im_str!("Format")).build_simple_string(ui, format, &format_names);
+		ui.input_text_multiline(im_str!("Code"), code, [0.0, 100.0]).resize_buffer(true).build();
+		if ui.button(im_str!("Add"), [0.0, 0.0]) {
+			match Cheat::parse(name.to_str().trim(), ECheatFormat::ALL[*format], code.to_str()) {
+				Ok(cheat) => {
+					cheats.add(cheat);
+					name.clear();
+					code.clear();
+				}
+				Err(e) => eprintln!("Failed to add the cheat: {}", e),
+			}
+		}
+		ui.text_disabled("One code per line, the enabled cheats are applied every frame");
+
+		ui.separator();
+		let mut removed_cheat = None;
+		ui.columns(3, im_str!("cheats"), true);
+		for index in 0..cheats.get_cheats().len() {
+			let cheat = match cheats.get_mut_cheat(index) {
+				Some(cheat) => cheat,
+				None => break,
+			};
+
+			let label = if cheat.name.is_empty() {
+				cheat.code.lines().next().unwrap_or_default()
+			} else {
+				&cheat.name
+			};
+			ui.checkbox(&im_str!("{}##cheat{}", label, index), &mut cheat.enabled);
+			if ui.is_item_hovered() {
+				ui.tooltip_text(&cheat.code);
+			}
+			ui.next_column();
+			ui.text(cheat.format.get_name());
+			ui.next_column();
+			if ui.small_button(&im_str!("Remove##cheat{}", index)) {
+				removed_cheat = Some(index);
+			}
+			ui.next_column();
+		}
+		ui.columns(1, im_str!(""), false);
+
+		if let Some(index) = removed_cheat {
+			cheats.remove(index);
+		}
+	});
+}
+
+/// BG and OBJ palettes in 16x16 grids, click a color to edit it
+/// NOTE: Edits are written to Palette RAM like a game would, the next frame shows them until the game writes the color again
+pub fn build_palette_debug_window(bus: &mut SystemBus, selected_color: &mut usize, opened: &mut bool, ui: &&mut Ui) {
+	const COLOR_SIZE: f32 = 14.0;
+
+	Window::new(im_str!("Palettes")).size([520.0, 420.0], Condition::FirstUseEver).opened(opened).build(ui, || {
+		for (name, start) in [("BG", 0), ("OBJ", SPRITE_PALETTE_START_INDEX)].iter() {
+			ui.group(|| {
+				ui.text(name);
+				for offset in 0..SPRITE_PALETTE_START_INDEX {
+					let index = start + offset;
+					let color = bus.ppu.get_palettes_colors()[index];
+					if offset % 16 != 0 {
+						ui.same_line_with_spacing(0.0, 1.0);
+					}
+					if ColorButton::new(&im_str!("##color{}", index), [color.get_red(), color.get_green(), color.get_blue(), 1.0])
+						.border(index == *selected_color)
+						.size([COLOR_SIZE, COLOR_SIZE])
+						.tooltip(false)
+						.build(ui)
+					{
+						*selected_color = index;
+					}
+					if ui.is_item_hovered() {
+						ui.tooltip_text(format!(
+							"{} palette {}, color {}\n{:#010X}: {:#06X}",
+							name,
+							offset / 16,
+							offset % 16,
+							PALETTE_RAM_ADDR + index as u32 * 2,
+							bus.ppu.get_raw_palette_ram()[index]
+						));
+					}
+				}
+			});
+			ui.same_line(0.0);
+		}
+		ui.new_line();
+
+		ui.separator();
+		let index = *selected_color;
+		let (name, offset) = if index < SPRITE_PALETTE_START_INDEX {
+			("BG", index)
+		} else {
+			("OBJ", index - SPRITE_PALETTE_START_INDEX)
+		};
+		ui.text(format!(
+			"{} palette {}, color {} ({:#010X})",
+			name,
+			offset / 16,
+			offset % 16,
+			PALETTE_RAM_ADDR + index as u32 * 2
+		));
+		let color = bus.ppu.get_palettes_colors()[index];
+		let mut rgb = [color.get_red(), color.get_green(), color.get_blue()];
+		let mut raw = bus.ppu.get_raw_palette_ram()[index] as i32;
+		let edited = if ColorEdit::new(im_str!("Color"), &mut rgb).build(ui) {
+			Some(to_rgb15(rgb))
+		} else if ui.input_int(im_str!("BGR555"), &mut raw).chars_hexadecimal(true).step(0).enter_returns_true(true).build() {
+			Some(raw as u16 & 0x7fff)
+		} else {
+			None
+		};
+		if let Some(value) = edited {
+			bus.patch(PALETTE_RAM_ADDR + index as u32 * 2, &value.to_le_bytes());
+		}
+	});
+}
+
+pub fn build_tiles_debug_window(show_tiles_window: &mut bool, is_palette: &mut bool, texture_id: TextureId, texture_size: [f32; 2], ui: &&mut Ui) {
+	Window::new(im_str!("Tiles"))
+		.size([0.0, 0.0], Condition::FirstUseEver)
+		.opened(show_tiles_window)
+		.position([1400.0, 75.0], Condition::FirstUseEver)
+		.build(ui, || {
+			ui.checkbox(im_str!("256 Colors"), is_palette);
+			if let Some(child_token) = ChildWindow::new(im_str!("##memory")).begin(&ui) {
+				Image::new(texture_id, texture_size).build(&ui);
+				child_token.end(&ui);
+			}
+		});
+}
+
+/// Whole maps of the enabled tiled BGs (BG number, texture, size) with the part shown on screen outlined, hover a tile for its map entry
+pub fn build_bg_map_debug_window(bus: &SystemBus, maps: &[(usize, TextureId, [f32; 2])], show_grid: &mut bool, opened: &mut bool, ui: &&mut Ui) {
+	const SCREEN_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+	const GRID_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.2];
+
+	Window::new(im_str!("BG Maps"))
+		.size([560.0, 600.0], Condition::FirstUseEver)
+		.horizontal_scrollbar(true)
+		.opened(opened)
+		.build(ui, || {
+			ui.checkbox(im_str!("Tile Grid"), show_grid);
+			if maps.is_empty() {
+				ui.text("No tiled BG enabled");
+			}
+
+			for (bg, texture_id, size) in maps {
+				let is_affine = bus.ppu.is_bg_affine(*bg) == Some(true);
+				let label = im_str!("BG{} ({}, {}x{})", bg, if is_affine { "Affine" } else { "Regular" }, size[0], size[1]);
+				if !CollapsingHeader::new(&label).default_open(true).build(ui) {
+					continue;
+				}
+
+				Image::new(*texture_id, *size).build(ui);
+				let origin = ui.item_rect_min();
+				let point = |x: f32, y: f32| [origin[0] + x, origin[1] + y];
+				let draw_list = ui.get_window_draw_list();
+				draw_list.with_clip_rect_intersect(origin, point(size[0], size[1]), || {
+					if *show_grid {
+						for x in (TILE_WIDTH..size[0] as usize).step_by(TILE_WIDTH) {
+							draw_list.add_line(point(x as f32, 0.0), point(x as f32, size[1]), GRID_COLOR).build();
+						}
+						for y in (TILE_WIDTH..size[1] as usize).step_by(TILE_WIDTH) {
+							draw_list.add_line(point(0.0, y as f32), point(size[0], y as f32), GRID_COLOR).build();
+						}
+					}
+
+					// NOTE: Regular BGs wrap around, the part past the right or bottom edge shows on the other side
+					let corners = bus.ppu.get_bg_screen_corners(*bg);
+					let shifts: &[[f32; 2]] = if is_affine {
+						&[[0.0, 0.0]]
+					} else {
+						&[[0.0, 0.0], [-size[0], 0.0], [0.0, -size[1]], [-size[0], -size[1]]]
+					};
+					for shift in shifts {
+						for (index, corner) in corners.iter().enumerate() {
+							let next = corners[(index + 1) % corners.len()];
+							draw_list
+								.add_line(
+									point(corner[0] as f32 + shift[0], corner[1] as f32 + shift[1]),
+									point(next[0] as f32 + shift[0], next[1] as f32 + shift[1]),
+									SCREEN_COLOR,
+								)
+								.thickness(2.0)
+								.build();
+						}
+					}
+				});
+
+				if ui.is_item_hovered() {
+					let mouse = ui.io().mouse_pos;
+					let tx = ((mouse[0] - origin[0]).max(0.0) as usize / TILE_WIDTH).min(size[0] as usize / TILE_WIDTH - 1);
+					let ty = ((mouse[1] - origin[1]).max(0.0) as usize / TILE_WIDTH).min(size[1] as usize / TILE_WIDTH - 1);
+					let tile = bus.ppu.get_bg_map_tile(*bg, tx, ty);
+					let palette = tile.palette_number.map_or("256 colors".to_string(), |palette| palette.to_string());
+					let flip = match (tile.h_flip, tile.v_flip) {
+						(false, false) => "None",
+						(true, false) => "Horizontal",
+						(false, true) => "Vertical",
+						(true, true) => "Both",
+					};
+					ui.tooltip_text(format!("Tile ({}, {})\nNumber: {}\nPalette: {}\nFlip: {}", tx, ty, tile.tile_number, palette, flip));
+				}
+			}
+		});
+}
+
+/// OAM entries with their decoded attributes next to the rendered sprites, the selected one is outlined in the render window
+pub fn build_sprites_debug_window(bus: &SystemBus, show_sprites_window: &mut bool, texture_ids: &[TextureId], selected_sprite: &mut Option<usize>, ui: &&mut Ui) {
+	const PREVIEW_SIZE: f32 = 32.0;
+
+	Window::new(im_str!("Sprites"))
+		.size([600.0, 700.0], Condition::FirstUseEver)
+		.opened(show_sprites_window)
+		.position([1400.0, 75.0], Condition::FirstUseEver)
+		.build(ui, || {
+			ui.columns(9, im_str!("oam"), true);
+			for title in ["#", "Sprite", "Position", "Size", "Mode", "Priority", "Palette", "Tile", "Affine"].iter() {
+				ui.text(title);
+				ui.next_column();
+			}
+			ui.separator();
+
+			for (index, (sprite, texture_id)) in bus.ppu.get_sprites().iter().zip(texture_ids).enumerate() {
+				let is_selected = *selected_sprite == Some(index);
+				if Selectable::new(&im_str!("{}", index))
+					.selected(is_selected)
+					.span_all_columns(true)
+					.size([0.0, PREVIEW_SIZE])
+					.build(ui)
+				{
+					*selected_sprite = if is_selected { None } else { Some(index) };
+				}
+				ui.next_column();
+
+				// NOTE: Keep the aspect ratio of the sprite
+				let (width, height) = sprite.get_size();
+				let preview_scale = PREVIEW_SIZE / width.max(height) as f32;
+				Image::new(*texture_id, [width as f32 * preview_scale, height as f32 * preview_scale]).build(ui);
+				ui.next_column();
+				ui.text(format!("{}, {}", sprite.get_x_coord(), sprite.get_y_coord()));
+				ui.next_column();
+				ui.text(format!("{}x{}", width, height));
+				ui.next_column();
+				ui.text(match sprite.get_sprite_mode() {
+					_ if sprite.is_hidden() => "Hidden",
+					ESpriteMode::Normal => "Normal",
+					ESpriteMode::SemiTransparent => "Semi-Transparent",
+					ESpriteMode::ObjWindow => "OBJ Window",
+				});
+				ui.next_column();
+				ui.text(format!("{}", sprite.get_priority()));
+				ui.next_column();
+				if sprite.get_is_256_palette() {
+					ui.text("256 colors");
+				} else {
+					ui.text(format!("{}", sprite.get_palette_number()));
+				}
+				ui.next_column();
+				ui.text(format!("{:#05X}", sprite.get_tile_index()));
+				ui.next_column();
+				if sprite.get_is_affine() {
+					ui.text(format!(
+						"{}{}",
+						sprite.get_affine_matrix_index(),
+						if sprite.get_is_virtual_double_sized() { " (Double)" } else { "" }
+					));
+				} else {
+					ui.text_disabled("-");
+				}
+				ui.next_column();
+			}
+			ui.columns(1, im_str!(""), false);
+		});
+}
+
+/// Color of the values modified since the emulation was last paused
+const MODIFIED_VALUE_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+/// Color of the register bank of the current mode
+const ACTIVE_BANK_COLOR: [f32; 4] = [0.4, 0.8, 1.0, 1.0];
+
+fn register_text(ui: &Ui, text: String, modified: bool) {
+	if modified {
+		ui.text_colored(MODIFIED_VALUE_COLOR, text);
+	} else {
+		ui.text(text);
+	}
+}
+
+/// Hex input of a register while paused, returns the new value once Enter is pressed
+fn register_input(ui: &Ui, id: &ImStr, value: u32, modified: bool) -> Option<u32> {
+	let color_token = if modified {
+		Some(ui.push_style_color(StyleColor::Text, MODIFIED_VALUE_COLOR))
+	} else {
+		None
+	};
+	let mut input = value as i32;
+	let entered = ui.input_int(id, &mut input).chars_hexadecimal(true).step(0).enter_returns_true(true).build();
+	if let Some(color_token) = color_token {
+		color_token.pop(ui);
+	}
+
+	Some(input as u32).filter(|_| entered)
+}
+
+/// Checkbox of a PSR flag while paused, returns true when it was toggled
+fn flag_checkbox(ui: &Ui, id: &ImStr, value: bool, modified: bool) -> bool {
+	let color_token = if modified {
+		Some(ui.push_style_color(StyleColor::Text, MODIFIED_VALUE_COLOR))
+	} else {
+		None
+	};
+	let mut value = value;
+	let toggled = ui.checkbox(id, &mut value);
+	if let Some(color_token) = color_token {
+		color_token.pop(ui);
+	}
+
+	toggled
+}
+
+/// NOTE: previous is the state when the emulation resumed, the values that changed since are highlighted while paused
+/// The GPRs and PSRs can be edited while paused, and the current instruction skipped
+pub fn build_cpu_debug_window(cpu: &mut CPU, previous: Option<&CpuSnapshot>, paused: bool, ui: &&mut Ui, opened: &mut bool) {
+	Window::new(im_str!("CPU")).size([650.0, 600.0], Condition::FirstUseEver).opened(opened).build(ui, || {
+		let current = CpuSnapshot::new(cpu);
+		let modified = |current: u32, previous: Option<u32>| previous.is_some_and(|previous| previous != current);
+
+		ui.text(im_str!("Mode: {:?}", cpu.get_operating_mode()));
+		if paused {
+			ui.same_line(0.0);
+			if ui.button(im_str!("Skip Instruction"), [0.0, 0.0]) {
+				cpu.skip_instruction();
+			}
+		}
+
+		if CollapsingHeader::new(im_str!("GPRs")).default_open(true).build(&ui) {
+			ui.columns(2, im_str!("Registers"), true);
+			for (i, register) in current.registers.iter().enumerate() {
+				let register_modified = modified(*register, previous.map(|previous| previous.registers[i]));
+				ui.text(format!("r{}:", i));
+				ui.next_column();
+				if paused {
+					if let Some(value) = register_input(ui, &im_str!("##r{}", i), *register, register_modified) {
+						if i == PROGRAM_COUNTER_REGISTER as usize {
+							cpu.set_pc(value);
+						} else {
+							cpu.set_register_value(i as u8, value);
+						}
+					}
+				} else {
+					register_text(ui, format!("{:#X}", register), register_modified);
+				}
+				ui.next_column();
+				ui.separator();
+			}
+			ui.columns(1, im_str!(""), false);
+		}
+
+		if CollapsingHeader::new(im_str!("Banked Registers")).build(ui) {
+			let active_bank = current.get_active_bank();
+			for (bank, name) in BANK_NAMES.iter().enumerate() {
+				let id = im_str!("##Bank{}", bank);
+				let label = if bank == active_bank { im_str!("{} (active)", name) } else { im_str!("{}", name) };
+				let color_token = if bank == active_bank {
+					Some(ui.push_style_color(StyleColor::Text, ACTIVE_BANK_COLOR))
+				} else {
+					None
+				};
+				let node = TreeNode::new(&id).label(&label).default_open(bank == active_bank).push(ui);
+				if let Some(color_token) = color_token {
+					color_token.pop(ui);
+				}
+
+				if let Some(node) = node {
+					let previous_registers = previous.map(|previous| previous.get_bank_registers(bank));
+					ui.columns(2, &id, true);
+					for (i, (index, value)) in current.get_bank_registers(bank).into_iter().enumerate() {
+						ui.text(format!("r{}:", index));
+						ui.next_column();
+						register_text(ui, format!("{:#X}", value), modified(value, previous_registers.as_ref().map(|registers| registers[i].1)));
+						ui.next_column();
+						ui.separator();
+					}
+					ui.columns(1, im_str!(""), false);
+					node.pop(ui);
+				}
+			}
+		}
+
+		if CollapsingHeader::new(im_str!("CPSRs")).default_open(true).build(&ui) {
+			ui.columns(9, im_str!("cpsr"), true);
+			ui.next_column();
+			ui.text("N");
+			ui.next_column();
+			ui.text("Z");
+			ui.next_column();
+			ui.text("C");
+			ui.next_column();
+			ui.text("V");
+			ui.next_column();
+			ui.text("I");
+			ui.next_column();
+			ui.text("F");
+			ui.next_column();
+			ui.text("T");
+			ui.next_column();
+			ui.text("Mode");
+			ui.separator();
+
+			for (i, (name, mode)) in PSR_MODES.iter().enumerate() {
+				let cpsr = cpu.get_spsr(*mode).clone();
+				let previous_cpsr = previous.map(|previous| previous.psrs[i]);
+				let flag_modified = |bit: usize| modified(cpsr.bit(bit) as u32, previous_cpsr.map(|previous| previous.bit(bit) as u32));
+				let mode_modified = modified(cpsr.get_mode_bits() as u32, previous_cpsr.map(|previous| previous & 0x1f));
+
+				ui.next_column();
+				ui.text(name);
+				for (bit, value) in [
+					(31, cpsr.get_n()),
+					(30, cpsr.get_z()),
+					(29, cpsr.get_c()),
+					(28, cpsr.get_v()),
+					(7, cpsr.get_i()),
+					(6, cpsr.get_f()),
+					(5, cpsr.get_t()),
+				] {
+					ui.next_column();
+					if paused {
+						if flag_checkbox(ui, &im_str!("##{}{}", name, bit), value, flag_modified(bit)) {
+							cpu.set_psr_value(*mode, current.psrs[i] ^ (1 << bit));
+						}
+					} else {
+						register_text(ui, value.to_string(), flag_modified(bit));
+					}
+				}
+				ui.next_column();
+				if paused {
+					if let Some(value) = register_input(ui, &im_str!("##{}Mode", name), cpsr.get_mode_bits() as u32, mode_modified) {
+						// NOTE: Invalid modes are refused and the previous value is shown again
+						cpu.set_psr_value(*mode, (current.psrs[i] & !0x1f) | (value & 0x1f));
+					}
+				} else {
+					register_text(ui, cpsr.get_mode_bits().to_string(), mode_modified);
+				}
+				ui.separator();
+			}
+
+			ui.columns(1, im_str!(""), false);
+		}
+	});
+}
+
+pub fn build_io_registers_window(bus: &mut SystemBus, show_io_registers_window: &mut bool, ui: &&mut Ui) {
+	Window::new(im_str!("I/O Registers"))
+		.size([400.0, 500.0], Condition::FirstUseEver)
+		.opened(show_io_registers_window)
+		.position([200.0, 1300.0], Condition::FirstUseEver)
+		.build(ui, || {
+			for group in EIoGroup::ALL.iter() {
+				if !CollapsingHeader::new(&im_str!("{}", group.get_name())).default_open(*group == EIoGroup::Lcd).build(ui) {
+					continue;
+				}
+
+				for register in IO_REGISTERS.iter().filter(|register| register.group == *group) {
+					let value = bus.read_16(register.address);
+					let mut new_value = value;
+					let label = im_str!("{:#010X} {} = {:#06X}", register.address, register.name, value);
+					if let Some(node) = TreeNode::new(&im_str!("{}", register.name)).label(&label).push(ui) {
+						ui.set_next_item_width(100.0);
+						if let Some(input) = register_input(ui, im_str!("Value"), value as u32, false) {
+							new_value = input as u16;
+						}
+
+						for field in register.fields.iter() {
+							let (name, first, last) = *field;
+							let field_value = get_field_value(value, *field);
+							if first == last {
+								let mut set = field_value != 0;
+								if ui.checkbox(&im_str!("{}##{}", name, first), &mut set) {
+									new_value = set_field_value(new_value, *field, set as u16);
+								}
+							} else {
+								let mut input = field_value as i32;
+								ui.set_next_item_width(100.0);
+								if ui.input_int(&im_str!("{} ({}-{})##{}", name, first, last, first), &mut input).build() {
+									let max = get_field_value(u16::MAX, *field) as i32;
+									new_value = set_field_value(new_value, *field, input.clamp(0, max) as u16);
+								}
+							}
+						}
+						node.pop(ui);
+					}
+
+					if new_value != value {
+						// NOTE: IF bits are acknowledged by writing 1 to them, only write the bits that were toggled
+						let written = if register.name == "IF" { value ^ new_value } else { new_value };
+						bus.patch(register.address, &written.to_le_bytes());
+					}
+				}
+			}
+		});
+}
+
+pub fn build_health_window(bus: &SystemBus, rom: &[u8], compat_entry: Option<&CompatEntry>, opened: &mut bool, ui: &&mut Ui) {
+	Window::new(im_str!("Health"))
+		.size([300.0, 100.0], Condition::FirstUseEver)
+		.opened(opened)
+		.position([200.0, 1150.0], Condition::FirstUseEver)
+		.build(ui, || {
+			ui.text("Compatibility warnings:");
+			let rom_write_attempts = bus.get_rom_write_attempts();
+			let color = if rom_write_attempts == 0 { [0.5, 0.5, 0.5, 1.0] } else { [1.0, 0.8, 0.0, 1.0] };
+			ui.text_colored(color, format!("ROM write attempts: {}", rom_write_attempts));
+
+			if let Some(entry) = compat_entry {
+				ui.separator();
+				ui.text(format!("{}: {}", entry.name, entry.status.get_name()));
+				if !entry.notes.is_empty() {
+					ui.text_wrapped(&im_str!("{}", entry.notes));
+				}
+				if !entry.features.is_empty() {
+					ui.text(format!("Requires: {}", entry.features.join(", ")));
+				}
+			}
+
+			ui.separator();
+			if ui.button(im_str!("Copy Bug Report"), [0.0, 0.0]) {
+				ui.set_clipboard_text(&ImString::new(build_bug_report(rom, bus, compat_entry)));
+			}
+		});
+}
+
+pub fn build_ppu_stats_window(bus: &SystemBus, opened: &mut bool, ui: &&mut Ui) {
+	Window::new(im_str!("PPU Stats"))
+		.size([300.0, 120.0], Condition::FirstUseEver)
+		.opened(opened)
+		.position([500.0, 1150.0], Condition::FirstUseEver)
+		.build(ui, || {
+			let stats = bus.ppu.get_render_stats();
+			ui.text(format!("OBJ pixels: {}", stats.obj_pixels));
+			ui.text(format!("Visible sprites: {} / 128", stats.visible_sprites));
+			ui.text(format!("Affine sprites: {}", stats.affine_sprites));
+			ui.text(format!("Unique tiles: {}", stats.unique_tiles));
+		});
+}
+
+/// Instance B latches the input at V-Blank, A uses the current input latching
+pub fn build_ab_compare_window(
+	comparison: &Option<AbComparison>,
+	option: &mut EAbOption,
+	opened: &mut bool,
+	restart: &mut bool,
+	texture_ids: Option<(TextureId, TextureId)>,
+	ui: &&mut Ui,
+) {
+	Window::new(im_str!("A/B Compare"))
+		.size([1000.0, 450.0], Condition::FirstUseEver)
+		.opened(opened)
+		.position([200.0, 600.0], Condition::FirstUseEver)
+		.build(ui, || {
+			*restart = ui.button(im_str!("Restart"), [0.0, 0.0]);
+			ui.same_line(0.0);
+			// NOTE: B only differs from A by the toggled option, changing it starts both over
+			let option_names: Vec<ImString> = EAbOption::ALL.iter().map(|option| ImString::new(option.get_name())).collect();
+			let option_names: Vec<&ImStr> = option_names.iter().map(|name| name.as_ref()).collect();
+			let mut option_index = EAbOption::ALL.iter().position(|other| other == option).unwrap_or_default();
+			if ComboBox::new(im_str!("Toggled on B")).build_simple_string(ui, &mut option_index, &option_names) {
+				*option = EAbOption::ALL[option_index];
+				*restart = true;
+			}
+
+			if let Some(comparison) = comparison {
+				ui.text(format!("A: {} | B: {}", option.get_value_name(&comparison.a), option.get_value_name(&comparison.b)));
+				let (hash_a, hash_b) = comparison.get_hashes();
+				ui.text(format!("Frame: {} | A: {:#010x} | B: {:#010x}", comparison.get_frame(), hash_a, hash_b));
+				match comparison.get_divergent_frame() {
+					Some(frame) => ui.text_colored([1.0, 0.0, 0.0, 1.0], format!("Diverged at frame {}", frame)),
+					None => ui.text_colored([0.0, 1.0, 0.0, 1.0], "Identical so far"),
+				}
+			}
+
+			if let Some((texture_a, texture_b)) = texture_ids {
+				Image::new(texture_a, [480.0, 320.0]).build(ui);
+				ui.same_line(0.0);
+				Image::new(texture_b, [480.0, 320.0]).build(ui);
+			}
+		});
+}
@@ -0,0 +1,467 @@
+use std::io::{Read, Result, Write};
+use std::time::{Duration, Instant};
+
+use crate::arm7tdmi::cpu::CPU;
+use crate::bios;
+use crate::gba::speed::{ESubsystem, SpeedMeter};
+use crate::savestate;
+use crate::savestate::rewind::RewindBuffer;
+use crate::system::cartridge::{Cartridge, ECartridgeType};
+use crate::system::{KeyInput, SystemBus};
+
+pub mod speed;
+
+pub const CYCLES_PER_FRAME: u32 = 280_896;
+/// 16.78MHz
+pub const CPU_FREQUENCY: u32 = 16_777_216;
+
+/// Rate the frame limiter runs the emulation at
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ERefreshRate {
+	/// The LCD refresh rate of the hardware (~59.7275Hz), keeps audio pitch and movie timing exact
+	Hardware,
+	/// 60Hz, matches most host monitors at the cost of running ~0.5% fast
+	MatchMonitor,
+}
+
+impl ERefreshRate {
+	pub fn get_frequency(self) -> f64 {
+		match self {
+			ERefreshRate::Hardware => CPU_FREQUENCY as f64 / CYCLES_PER_FRAME as f64,
+			ERefreshRate::MatchMonitor => 60.0,
+		}
+	}
+
+	pub fn get_frame_duration(self) -> Duration {
+		Duration::from_secs_f64(1.0 / self.get_frequency())
+	}
+}
+
+/// Request the PPU and keypad interrupts in IF and deliver pending interrupts to the CPU
+pub fn dispatch_interrupts(cpu: &mut CPU, bus: &mut SystemBus, current_cycle: u32, h_blank_irq: bool, v_blank_irq: bool) {
+	bus.request_ppu_interrupts(current_cycle, h_blank_irq, v_blank_irq);
+	bus.io_regs.request_keypad_interrupt();
+	if let Some(exception_type) = bus.poll_interrupts(cpu) {
+		cpu.exception(exception_type);
+	}
+}
+
+/// Hooks of a debugger into the emulation loop, a hook returning true stops the emulation (eg. a breakpoint was hit)
+pub trait EmulationHooks {
+	/// The hardware stepped a cycle and the pending interrupts were dispatched
+	fn on_hardware_step(&mut self, _cpu: &mut CPU, _bus: &mut SystemBus) -> bool {
+		false
+	}
+
+	/// The CPU is about to execute the instruction at its current PC
+	fn before_instruction(&mut self, _cpu: &CPU, _bus: &SystemBus) {}
+
+	/// The CPU executed the instruction at pc
+	fn after_instruction(&mut self, _pc: u32, _is_thumb: bool, _cpu: &mut CPU, _bus: &mut SystemBus) -> bool {
+		false
+	}
+}
+
+/// No hooks, the emulation runs uninterrupted
+impl EmulationHooks for () {}
+
+/// A whole GBA system (CPU + bus) that can be advanced deterministically without any frontend
+/// Frontends (headless, libretro, WASM...) embed it without the gui feature: run frames, set the keys, take the frame and the audio
+pub struct Gba {
+	pub cpu: CPU,
+	pub bus: SystemBus,
+	current_cycle: u32,
+	rewind_buffer: Option<RewindBuffer>,
+	speed_meter: SpeedMeter,
+}
+
+impl Gba {
+	pub fn new(bios_data: Box<[u8]>, cartridge_data: Box<[u8]>) -> Self {
+		Self::new_with_type(bios_data, cartridge_data, ECartridgeType::RomSram)
+	}
+
+	/// NOTE: Frontends loading a file pass the type of its extension (see get_cartridge_type), so .mb images boot as multiboot
+	pub fn new_with_type(bios_data: Box<[u8]>, cartridge_data: Box<[u8]>, cartridge_type: ECartridgeType) -> Self {
+		Self::new_with_bus(SystemBus::new_with_cartridge(bios_data, Cartridge::new_with_type(cartridge_data, cartridge_type)))
+	}
+
+	pub fn new_with_bus(mut bus: SystemBus) -> Self {
+		let mut cpu = CPU::new();
+		// Start in System mode
+		cpu.get_mut_cpsr().set_mode_bits(0x1f);
+		if bus.get_cartridge().get_type() == ECartridgeType::Multiboot {
+			bios::start_multiboot(&mut cpu, &mut bus);
+		} else if !bus.is_bios_loaded() {
+			bios::skip_boot(&mut cpu, &mut bus);
+		}
+
+		Self {
+			cpu,
+			bus,
+			current_cycle: 0,
+			rewind_buffer: None,
+			speed_meter: SpeedMeter::new(),
+		}
+	}
+
+	pub fn get_current_cycle(&self) -> u32 {
+		self.current_cycle
+	}
+
+	/// Advance the system by one cycle, dispatching the PPU interrupts
+	/// Returns whether the CPU executed an instruction, it waits out the cycles of the previous one otherwise
+	pub fn step(&mut self) -> bool {
+		self.step_with(&mut ()).0
+	}
+
+	/// Advance the system by one cycle like step, calling the hooks of a debugger
+	/// Returns whether the CPU executed an instruction and whether a hook stopped the emulation
+	pub fn step_with(&mut self, hooks: &mut impl EmulationHooks) -> (bool, bool) {
+		self.current_cycle = (self.current_cycle + 1) % CYCLES_PER_FRAME;
+		let (h_blank_irq, v_blank_irq) = self.bus.step_hardware(self.current_cycle);
+		// NOTE: A halted CPU isn't using the bus, it doesn't have to wait for the transfer
+		let dma_cycles = self.bus.take_dma_cycles();
+		if !self.bus.io_regs.halted {
+			self.cpu.stall(dma_cycles);
+		}
+		dispatch_interrupts(&mut self.cpu, &mut self.bus, self.current_cycle, h_blank_irq, v_blank_irq);
+		if hooks.on_hardware_step(&mut self.cpu, &mut self.bus) {
+			return (false, true);
+		}
+
+		if self.bus.io_regs.halted || self.cpu.wait_cycle() {
+			return (false, false);
+		}
+
+		hooks.before_instruction(&self.cpu, &self.bus);
+		let pc = self.cpu.get_current_pc();
+		let is_thumb = self.cpu.get_cpsr().get_t();
+		self.cpu.step(&mut self.bus);
+		(true, hooks.after_instruction(pc, is_thumb, &mut self.cpu, &mut self.bus))
+	}
+
+	/// Run until the CPU executes an instruction, for a frame at most if it's halted
+	/// Returns whether an instruction was executed
+	pub fn step_instruction(&mut self) -> bool {
+		self.step_instruction_with(&mut ())
+	}
+
+	/// Run until the CPU executes an instruction like step_instruction, or until a hook stops the emulation
+	pub fn step_instruction_with(&mut self, hooks: &mut impl EmulationHooks) -> bool {
+		for _ in 0..CYCLES_PER_FRAME {
+			let (executed, stopped) = self.step_with(hooks);
+			if executed || stopped {
+				return executed;
+			}
+		}
+		false
+	}
+
+	/// Advance the system by one whole frame
+	pub fn run_frame(&mut self) {
+		self.run_frame_with(&mut ());
+	}
+
+	/// Advance the system by one frame like run_frame, the frame ends early when a hook stops the emulation
+	/// Returns whether a hook stopped it
+	pub fn run_frame_with(&mut self, hooks: &mut impl EmulationHooks) -> bool {
+		let start = Instant::now();
+		let mut cycles = 0;
+		let mut stopped = false;
+		while cycles < CYCLES_PER_FRAME && !stopped {
+			stopped = self.step_with(hooks).1;
+			cycles += 1;
+		}
+		self.speed_meter.add_cycles(cycles);
+		self.speed_meter.add_time(ESubsystem::Emulation, start.elapsed());
+
+		if let Some(rewind_buffer) = self.rewind_buffer.as_mut() {
+			if let Err(e) = rewind_buffer.on_frame(&self.cpu, &self.bus, self.current_cycle) {
+				eprintln!("Failed to take a rewind snapshot: {}", e);
+			}
+		}
+		stopped
+	}
+
+	/// Record the last seconds of gameplay while running frames, disabled by default as each snapshot costs a compressed save state
+	pub fn set_rewind_enabled(&mut self, enabled: bool) {
+		if enabled != self.rewind_buffer.is_some() {
+			self.rewind_buffer = if enabled { Some(RewindBuffer::default()) } else { None };
+		}
+	}
+
+	pub fn get_rewind_buffer(&self) -> Option<&RewindBuffer> {
+		self.rewind_buffer.as_ref()
+	}
+
+	pub fn get_mut_rewind_buffer(&mut self) -> Option<&mut RewindBuffer> {
+		self.rewind_buffer.as_mut()
+	}
+
+	/// Go back to the previous rewind snapshot, returns false when there is nothing (left) to rewind
+	pub fn rewind(&mut self) -> Result<bool> {
+		match self.rewind_buffer.as_mut() {
+			Some(rewind_buffer) => rewind_buffer.rewind(&mut self.cpu, &mut self.bus, &mut self.current_cycle),
+			None => Ok(false),
+		}
+	}
+
+	/// Speed of the frames run compared to the hardware, the frontend adds the time of its own subsystems and updates it once per frame
+	pub fn get_speed_meter(&self) -> &SpeedMeter {
+		&self.speed_meter
+	}
+
+	pub fn get_mut_speed_meter(&mut self) -> &mut SpeedMeter {
+		&mut self.speed_meter
+	}
+
+	/// Save the whole emulated system, see savestate::save_state
+	pub fn save_state(&self, writer: &mut dyn Write) -> Result<()> {
+		savestate::save_state(writer, &self.cpu, &self.bus, self.current_cycle)
+	}
+
+	/// Load a state saved with the same ROM, the system is left untouched if the state is invalid
+	pub fn load_state(&mut self, reader: &mut dyn Read) -> Result<()> {
+		savestate::load_state(reader, &mut self.cpu, &mut self.bus, &mut self.current_cycle)
+	}
+
+	/// Render the current frame as RGB triplets (240x160)
+	pub fn render(&mut self) -> Vec<f32> {
+		self.bus.ppu.render()
+	}
+
+	/// Render the current frame as 8 bit RGB triplets (240x160), the format most frontends upload
+	pub fn render_rgb8(&mut self) -> Vec<u8> {
+		self.render().iter().map(|channel| (channel * 255.0).round() as u8).collect()
+	}
+
+	/// Set the keys held on the host, active low like KEYINPUT (0 = pressed)
+	pub fn set_keys(&mut self, keys: KeyInput) {
+		*self.bus.io_regs.get_mut_key_input() = keys;
+	}
+
+	/// Take the audio mixed since the last call, interleaved left/right samples in [-1, 1] at apu::SAMPLE_RATE
+	pub fn take_audio_samples(&mut self) -> Vec<f32> {
+		self.bus.apu.take_samples()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::arm7tdmi::EOperatingMode;
+	use crate::system::{EIrq, MemoryInterface, IO_ADDR};
+
+	const IE_ADDRESS: u32 = IO_ADDR + 0x200;
+	const IF_ADDRESS: u32 = IO_ADDR + 0x202;
+	const IME_ADDRESS: u32 = IO_ADDR + 0x208;
+	const IRQ_VECTOR: u32 = 0x18;
+
+	/// Game started from ROM with the V-Blank interrupt enabled in IE and IME, and requested in IF
+	fn setup() -> Gba {
+		let mut gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		gba.bus.write_16(IE_ADDRESS, 1 << EIrq::VBlank as u16);
+		gba.bus.write_16(IME_ADDRESS, 0x1);
+		gba.bus.io_regs.request(EIrq::VBlank);
+		gba
+	}
+
+	fn dispatch(gba: &mut Gba) {
+		dispatch_interrupts(&mut gba.cpu, &mut gba.bus, 1, false, false);
+	}
+
+	#[test]
+	fn irq_is_taken_when_enabled() {
+		let mut gba = setup();
+		dispatch(&mut gba);
+		assert_eq!(gba.cpu.get_operating_mode(), EOperatingMode::IrqMode);
+		assert_eq!(gba.cpu.get_current_pc(), IRQ_VECTOR);
+	}
+
+	#[test]
+	fn irq_does_not_preempt_while_cpsr_i_is_set() {
+		let mut gba = setup();
+		gba.cpu.get_mut_cpsr().set_i(true);
+		let pc = gba.cpu.get_current_pc();
+		for _ in 0..3 {
+			dispatch(&mut gba);
+			assert_eq!(gba.cpu.get_operating_mode(), EOperatingMode::SystemMode);
+			assert_eq!(gba.cpu.get_current_pc(), pc);
+		}
+		// NOTE: The request stays pending in IF and is delivered once CPSR.I is cleared
+		assert_eq!(gba.bus.read_16(IF_ADDRESS), 1 << EIrq::VBlank as u16);
+		gba.cpu.get_mut_cpsr().set_i(false);
+		dispatch(&mut gba);
+		assert_eq!(gba.cpu.get_operating_mode(), EOperatingMode::IrqMode);
+		assert_eq!(gba.cpu.get_current_pc(), IRQ_VECTOR);
+	}
+
+	#[test]
+	fn irq_does_not_preempt_while_ime_is_off() {
+		let mut gba = setup();
+		gba.bus.write_16(IME_ADDRESS, 0x0);
+		dispatch(&mut gba);
+		assert_eq!(gba.cpu.get_operating_mode(), EOperatingMode::SystemMode);
+	}
+
+	#[test]
+	fn step_instruction_takes_pending_irqs() {
+		let mut gba = setup();
+		assert!(gba.step_instruction());
+		assert_eq!(gba.cpu.get_operating_mode(), EOperatingMode::IrqMode);
+	}
+
+	#[test]
+	fn step_instruction_waits_while_halted() {
+		let mut gba = setup();
+		gba.bus.write_16(IF_ADDRESS, 0xffff);
+		gba.bus.io_regs.halted = true;
+		let pc = gba.cpu.get_current_pc();
+		assert!(!gba.step_instruction());
+		assert_eq!(gba.cpu.get_current_pc(), pc);
+		assert!(gba.bus.io_regs.halted);
+
+		// NOTE: The interrupt wakes the CPU up, which takes it right away
+		gba.bus.io_regs.request(EIrq::VBlank);
+		assert!(gba.step_instruction());
+		assert!(!gba.bus.io_regs.halted);
+		assert_eq!(gba.cpu.get_operating_mode(), EOperatingMode::IrqMode);
+	}
+
+	const DISP_STAT_ADDRESS: u32 = IO_ADDR + 0x4;
+	const VCOUNT_ADDRESS: u32 = IO_ADDR + 0x6;
+	const CYCLES_PER_LINE: u32 = 1232;
+
+	/// Game started from ROM with the V-Blank and V-Counter match interrupts enabled in DISPSTAT, IME left off
+	fn setup_lcd(v_count_trigger: u8) -> Gba {
+		let mut gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		gba.bus.write_16(DISP_STAT_ADDRESS, (v_count_trigger as u16) << 8 | 0x28);
+		gba
+	}
+
+	fn run_until(gba: &mut Gba, cycle: u32) {
+		while gba.get_current_cycle() != cycle {
+			gba.step();
+		}
+	}
+
+	fn get_requested(gba: &Gba, irq: EIrq) -> bool {
+		gba.bus.read_16(IF_ADDRESS) & (1 << irq as u16) != 0
+	}
+
+	fn acknowledge(gba: &mut Gba, irq: EIrq) {
+		gba.bus.write_16(IF_ADDRESS, 1 << irq as u16);
+	}
+
+	#[test]
+	fn v_blank_starts_at_line_160() {
+		let mut gba = setup_lcd(0xff);
+		run_until(&mut gba, 160 * CYCLES_PER_LINE - 1);
+		assert_eq!(gba.bus.read_16(VCOUNT_ADDRESS), 159);
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x1, 0x0);
+		assert!(!get_requested(&gba, EIrq::VBlank));
+
+		gba.step();
+		assert_eq!(gba.bus.read_16(VCOUNT_ADDRESS), 160);
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x1, 0x1);
+		assert!(get_requested(&gba, EIrq::VBlank));
+	}
+
+	#[test]
+	fn line_227_wraps_around_to_0() {
+		let mut gba = setup_lcd(0xff);
+		run_until(&mut gba, CYCLES_PER_FRAME - 1);
+		assert_eq!(gba.bus.read_16(VCOUNT_ADDRESS), 227);
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x1, 0x1);
+
+		gba.step();
+		assert_eq!(gba.get_current_cycle(), 0);
+		assert_eq!(gba.bus.read_16(VCOUNT_ADDRESS), 0);
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x1, 0x0);
+	}
+
+	#[test]
+	fn v_counter_match_is_requested_once_when_the_line_starts() {
+		let mut gba = setup_lcd(100);
+		run_until(&mut gba, 100 * CYCLES_PER_LINE - 1);
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x4, 0x0);
+		assert!(!get_requested(&gba, EIrq::VCounterMatch));
+
+		gba.step();
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x4, 0x4);
+		assert!(get_requested(&gba, EIrq::VCounterMatch));
+
+		// NOTE: Not requested again for the rest of the line, and the flag clears on the next one
+		acknowledge(&mut gba, EIrq::VCounterMatch);
+		run_until(&mut gba, 101 * CYCLES_PER_LINE);
+		assert!(!get_requested(&gba, EIrq::VCounterMatch));
+		assert_eq!(gba.bus.read_16(DISP_STAT_ADDRESS) & 0x4, 0x0);
+	}
+
+	#[test]
+	fn v_counter_match_at_the_first_and_last_lines() {
+		for (v_count_trigger, line_start) in [(0, 0), (227, 227 * CYCLES_PER_LINE)] {
+			let mut gba = setup_lcd(v_count_trigger);
+			run_until(&mut gba, (line_start + CYCLES_PER_FRAME - 1) % CYCLES_PER_FRAME);
+			assert!(!get_requested(&gba, EIrq::VCounterMatch), "LYC {}", v_count_trigger);
+			gba.step();
+			assert!(get_requested(&gba, EIrq::VCounterMatch), "LYC {}", v_count_trigger);
+		}
+	}
+
+	#[test]
+	fn v_counter_never_matches_past_the_last_line() {
+		for v_count_trigger in [228, 0xff] {
+			let mut gba = setup_lcd(v_count_trigger);
+			gba.run_frame();
+			gba.run_frame();
+			assert!(!get_requested(&gba, EIrq::VCounterMatch), "LYC {}", v_count_trigger);
+		}
+	}
+
+	#[test]
+	fn dma_transfers_stall_the_cpu() {
+		let mut gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		// NOTE: DMA3 immediately copying 4 halfwords from EWRAM to IWRAM, 2N + 2(n-1)S + 2I = 18 cycles
+		gba.bus.write_32(IO_ADDR + 0xd4, crate::system::EWRAM_ADDR);
+		gba.bus.write_32(IO_ADDR + 0xd8, crate::system::IWRAM_ADDR);
+		gba.bus.write_32(IO_ADDR + 0xdc, 0x8000_0004);
+		let pc = gba.cpu.get_current_pc();
+		for _ in 0..18 {
+			assert!(!gba.step());
+		}
+		assert_eq!(gba.cpu.get_current_pc(), pc);
+		assert!(gba.step());
+	}
+
+	#[test]
+	fn multiboot_images_boot_from_ewram() {
+		let gba = Gba::new_with_type(Box::new([]), vec![0; 0x200].into_boxed_slice(), ECartridgeType::Multiboot);
+		assert_eq!(gba.cpu.get_current_pc(), crate::system::EWRAM_ADDR);
+
+		let gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		assert_eq!(gba.cpu.get_current_pc(), crate::system::CARTRIDGE_WS0_LO);
+	}
+
+	/// Stops the emulation once the CPU executed a number of instructions
+	struct StopAfter(u32);
+
+	impl EmulationHooks for StopAfter {
+		fn after_instruction(&mut self, _pc: u32, _is_thumb: bool, _cpu: &mut CPU, _bus: &mut SystemBus) -> bool {
+			self.0 -= 1;
+			self.0 == 0
+		}
+	}
+
+	#[test]
+	fn a_hook_ends_the_frame_early() {
+		let mut gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		assert!(gba.run_frame_with(&mut StopAfter(3)));
+		assert!(gba.get_current_cycle() < CYCLES_PER_FRAME - 1);
+
+		// NOTE: Without a hook stopping it, the frame runs to the next one
+		let mut gba = Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		assert!(!gba.run_frame_with(&mut ()));
+		assert_eq!(gba.get_current_cycle(), 0);
+	}
+}
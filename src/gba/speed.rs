@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use crate::gba::CPU_FREQUENCY;
+
+/// Real time over which the speed is averaged
+pub const SPEED_WINDOW: Duration = Duration::from_secs(1);
+/// Speed (%) under which a window counts as slow
+pub const SLOWDOWN_THRESHOLD: f64 = 90.0;
+/// Consecutive slow windows before a slowdown is reported, short hitches (eg. loading a ROM) aren't worth a report
+pub const SLOWDOWN_WINDOWS: u32 = 3;
+
+/// Where the frontend spends the real time of a frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ESubsystem {
+	/// CPU, PPU, APU, DMA and timers stepping
+	Emulation,
+	/// Frame filters and texture upload
+	Rendering,
+	/// Debugger windows and menus
+	Interface,
+	/// Waiting for the frame limiter or the audio device
+	Idle,
+}
+
+impl ESubsystem {
+	pub const ALL: [ESubsystem; 4] = [ESubsystem::Emulation, ESubsystem::Rendering, ESubsystem::Interface, ESubsystem::Idle];
+
+	pub fn get_name(self) -> &'static str {
+		match self {
+			ESubsystem::Emulation => "Emulation",
+			ESubsystem::Rendering => "Rendering",
+			ESubsystem::Interface => "Interface",
+			ESubsystem::Idle => "Idle",
+		}
+	}
+}
+
+/// A sustained slowdown, with the share of the real time each subsystem took
+#[derive(Debug, Clone)]
+pub struct SlowdownReport {
+	pub speed: f64,
+	pub duration: Duration,
+	pub breakdown: Vec<(ESubsystem, Duration)>,
+}
+
+impl SlowdownReport {
+	pub fn get_description(&self) -> String {
+		let total = self.breakdown.iter().map(|(_, time)| time.as_secs_f64()).sum::<f64>().max(f64::EPSILON);
+		let breakdown: Vec<String> = self
+			.breakdown
+			.iter()
+			.map(|(subsystem, time)| format!("{} {:.0}%", subsystem.get_name(), time.as_secs_f64() / total * 100.0))
+			.collect();
+		format!("Running at {:.0}% for {:.0}s ({})", self.speed, self.duration.as_secs_f64(), breakdown.join(" | "))
+	}
+}
+
+/// Measures the achieved emulation speed against real time, 100% being the hardware speed
+pub struct SpeedMeter {
+	window_start: Instant,
+	emulated_cycles: u64,
+	window_times: [Duration; 4],
+	speed: Option<f64>,
+	slow_windows: u32,
+	slow_times: [Duration; 4],
+	slow_speed_sum: f64,
+	slowdown_reported: bool,
+}
+
+impl SpeedMeter {
+	pub fn new() -> Self {
+		Self {
+			window_start: Instant::now(),
+			emulated_cycles: 0,
+			window_times: [Duration::ZERO; 4],
+			speed: None,
+			slow_windows: 0,
+			slow_times: [Duration::ZERO; 4],
+			slow_speed_sum: 0.0,
+			slowdown_reported: false,
+		}
+	}
+
+	/// Speed of the last window in % of the hardware speed, None until a whole window was measured
+	pub fn get_speed(&self) -> Option<f64> {
+		self.speed
+	}
+
+	pub fn add_cycles(&mut self, cycles: u32) {
+		self.emulated_cycles += cycles as u64;
+	}
+
+	pub fn add_time(&mut self, subsystem: ESubsystem, time: Duration) {
+		let index = ESubsystem::ALL.iter().position(|s| *s == subsystem).unwrap();
+		self.window_times[index] += time;
+	}
+
+	/// Start measuring again, eg. when resuming from pause so the paused time doesn't count as a slowdown
+	pub fn reset(&mut self) {
+		*self = Self::new();
+	}
+
+	/// Call once per frame, closes the window once it lasted SPEED_WINDOW
+	/// Returns a report when the speed has just stayed under SLOWDOWN_THRESHOLD for SLOWDOWN_WINDOWS windows, once per slowdown
+	pub fn update(&mut self) -> Option<SlowdownReport> {
+		let elapsed = self.window_start.elapsed();
+		if elapsed < SPEED_WINDOW {
+			return None;
+		}
+
+		let speed = self.emulated_cycles as f64 / CPU_FREQUENCY as f64 / elapsed.as_secs_f64() * 100.0;
+		self.speed = Some(speed);
+
+		let mut report = None;
+		if speed < SLOWDOWN_THRESHOLD {
+			self.slow_windows += 1;
+			self.slow_speed_sum += speed;
+			for (slow_time, window_time) in self.slow_times.iter_mut().zip(self.window_times.iter()) {
+				*slow_time += *window_time;
+			}
+
+			if self.slow_windows >= SLOWDOWN_WINDOWS && !self.slowdown_reported {
+				self.slowdown_reported = true;
+				report = Some(SlowdownReport {
+					speed: self.slow_speed_sum / self.slow_windows as f64,
+					duration: SPEED_WINDOW * self.slow_windows,
+					breakdown: ESubsystem::ALL.iter().cloned().zip(self.slow_times.iter().cloned()).collect(),
+				});
+			}
+		} else {
+			self.slow_windows = 0;
+			self.slow_times = [Duration::ZERO; 4];
+			self.slow_speed_sum = 0.0;
+			self.slowdown_reported = false;
+		}
+
+		self.window_start = Instant::now();
+		self.emulated_cycles = 0;
+		self.window_times = [Duration::ZERO; 4];
+		report
+	}
+}
+
+impl Default for SpeedMeter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
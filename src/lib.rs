@@ -1,5 +1,20 @@
+pub mod apu;
 pub mod arm7tdmi;
+pub mod bios;
+pub mod cheats;
 pub mod debugging;
+pub mod gba;
 pub mod ppu;
+pub mod savestate;
 pub mod system;
-pub mod windowing;
\ No newline at end of file
+#[cfg(feature = "gui")]
+pub mod windowing;
+
+/// The stable public surface of the emulator core, meant to be glob imported by frontends
+pub mod prelude {
+	pub use crate::arm7tdmi::cpu::CPU;
+	pub use crate::arm7tdmi::{EExceptionType, EOperatingMode, PSR};
+	pub use crate::gba::Gba;
+	pub use crate::ppu::{Color, EVideoMode, PPU, SCREEN_TOTAL_PIXELS};
+	pub use crate::system::{EMemoryRegion, IORegisters, KeyInput, MemoryInterface, SystemBus};
+}
@@ -1,62 +1,217 @@
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use clap::{value_t, App, Arg};
 use glium::glutin::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
 use glium::glutin::event_loop::ControlFlow;
 use glium::uniforms::{SamplerBehavior, SamplerWrapFunction};
 use glium::Surface;
 use imgui::*;
 
+use gba_rustmulator::system::backup::EBackupType;
+use gba_rustmulator::system::cartridge::{get_cartridge_type, get_game_code, get_save_path, is_rom_file, load_rom_file, Cartridge};
+use gba_rustmulator::system::sensors::SOLAR_SENSOR_MAX_LEVEL;
 use gba_rustmulator::system::*;
 use gba_rustmulator::{
-	arm7tdmi::{cpu::*, EExceptionType},
+	bios,
+	gba::{
+		speed::{ESubsystem, SLOWDOWN_THRESHOLD},
+		ERefreshRate, Gba,
+	},
+	savestate::rewind::RewindBuffer,
 	windowing,
 };
 
-use gba_rustmulator::debugging::disassembling::disassemble_instruction;
-use gba_rustmulator::debugging::{build_cpu_debug_window, build_io_registers_window, build_memory_debug_window, build_sprites_debug_window, build_tiles_debug_window};
-use gba_rustmulator::ppu::{EVideoMode, SpriteEntry, OAM_SIZE, SPRITE_PALETTE_START_INDEX, SPRITE_TILES_START_ADDRESS, VRAM_SIZE};
-use gba_rustmulator::windowing::System;
+use gba_rustmulator::cheats::CheatManager;
+use gba_rustmulator::debugging::ab_compare::{AbComparison, EAbOption};
+use gba_rustmulator::debugging::compat::{CompatDatabase, ECompatStatus};
+use gba_rustmulator::debugging::frame_diff::{FRAME_HEIGHT, FRAME_WIDTH};
+use gba_rustmulator::debugging::hex_editor::HexEditor;
+use gba_rustmulator::debugging::ram_search::RamSearch;
+use gba_rustmulator::debugging::state_dump::dump_state_json;
+use gba_rustmulator::debugging::symbols::SymbolTable;
+use gba_rustmulator::debugging::{
+	build_ab_compare_window, build_bg_map_debug_window, build_breakpoints_window, build_callstack_debug_window, build_cheats_window, build_cpu_debug_window, build_health_window,
+	build_hex_editor_window, build_io_registers_window, build_memory_debug_window, build_palette_debug_window, build_ppu_stats_window, build_profiler_window,
+	build_ram_search_window, build_sprites_debug_window, build_tiles_debug_window, CpuSnapshot, DebuggerState, ExceptionBreakpoints,
+};
+use gba_rustmulator::ppu::tiles::{decode_tile, get_palette_index, ETileFormat, TILE_WIDTH};
+use gba_rustmulator::ppu::{ELayer, EVideoMode, SpriteEntry, OAM_SIZE, SPRITE_PALETTE_START_INDEX, SPRITE_TILES_START_ADDRESS, VRAM_SIZE};
+use gba_rustmulator::windowing::audio::AudioOutput;
+use gba_rustmulator::windowing::filters::{EColorFilter, FrameBlender, MAX_FRAME_BLEND_WEIGHT};
+use gba_rustmulator::windowing::profile::{get_backup_snapshot_path, get_bookmarks_path, get_profile_path, get_save_state_path, Profile, DEFAULT_GYRO_KEYS};
+use gba_rustmulator::windowing::{ERotation, System, BINDABLE_KEYS};
+
+/// How often the save memory is written to the .sav file while the game runs
+const BACKUP_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Command line options
+struct Options {
+	rom_path: PathBuf,
+	bios_path: PathBuf,
+	/// Size of a GBA pixel on screen
+	scale: f32,
+	mute: bool,
+	/// Start paused in the debugger
+	debug: bool,
+	/// Run headless and print the state at this frame
+	dump_state_at_frame: Option<u32>,
+}
+
+impl Options {
+	/// Parse the command line, exits with the usage on invalid arguments
+	fn parse() -> Self {
+		let matches = App::new("gba_rustmulator")
+			.about("Game Boy Advance emulator and debugger")
+			.arg(
+				Arg::with_name("rom")
+					.long("rom")
+					.value_name("ROM")
+					.default_value("data/demos/sbb_aff.gba")
+					.help("ROM to run (.gba, .mb or .zip)"),
+			)
+			.arg(
+				Arg::with_name("bios")
+					.long("bios")
+					.value_name("BIOS")
+					.default_value("data/bios.gba")
+					.help("BIOS dump, the BIOS calls are high level emulated without it"),
+			)
+			.arg(
+				Arg::with_name("scale")
+					.long("scale")
+					.value_name("FACTOR")
+					.default_value("2")
+					.validator(|value| match value.parse::<f32>() {
+						Ok(scale) if scale > 0.0 => Ok(()),
+						_ => Err(String::from("expects a positive number")),
+					})
+					.help("Size of a GBA pixel on screen"),
+			)
+			.arg(Arg::with_name("mute").long("mute").help("Start without audio output"))
+			.arg(Arg::with_name("debug").long("debug").help("Start paused in the debugger"))
+			.arg(
+				Arg::with_name("dump-state-at-frame")
+					.long("dump-state-at-frame")
+					.value_name("FRAME")
+					.help("Run headless and print the state at this frame as JSON"),
+			)
+			.get_matches();
+
+		Self {
+			rom_path: PathBuf::from(matches.value_of_os("rom").unwrap()),
+			bios_path: PathBuf::from(matches.value_of_os("bios").unwrap()),
+			scale: value_t!(matches, "scale", f32).unwrap_or_else(|e| e.exit()),
+			mute: matches.is_present("mute"),
+			debug: matches.is_present("debug"),
+			dump_state_at_frame: matches
+				.is_present("dump-state-at-frame")
+				.then(|| value_t!(matches, "dump-state-at-frame", u32).unwrap_or_else(|e| e.exit())),
+		}
+	}
+}
 
 fn main() {
-	let system = windowing::init("GBA Rustmulator");
-
-	let mut cpu = CPU::new();
-	// Start in System mode
-	cpu.get_mut_cpsr().set_mode_bits(0x1f);
-
-	let mut bios_data = Vec::<u8>::new();
-	File::open("data/bios.gba").expect("Bios couldn't be opened!").read_to_end(&mut bios_data).unwrap();
-
-	let mut cartridge_data = Vec::<u8>::new();
-	if File::open("data/demos/sbb_aff.gba")
-		.expect("Cartridge couldn't be opened!")
-		.read_to_end(&mut cartridge_data)
-		.is_ok()
-	{
-		if cartridge_data.len() < CARTRIDGE_ROM_SIZE {
-			cartridge_data.resize(CARTRIDGE_ROM_SIZE - cartridge_data.len(), 0);
+	let options = Options::parse();
+	let dump_state_at_frame = options.dump_state_at_frame;
+	let scale = options.scale;
+
+	// NOTE: Without a BIOS dump the BIOS calls are high level emulated
+	let bios_data = std::fs::read(&options.bios_path).unwrap_or_else(|e| {
+		eprintln!("Failed to open {}, the BIOS is high level emulated: {}", options.bios_path.display(), e);
+		Vec::new()
+	});
+
+	let rom_path = options.rom_path.as_path();
+	if let Ok(cartridge_data) = load_rom_file(rom_path) {
+		// NOTE: Kept to start the A/B comparison instances and dropped ROMs from scratch
+		let ab_bios_data = bios_data.clone();
+		let mut ab_cartridge_data = cartridge_data.clone();
+		let mut gba = Gba::new_with_type(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice(), get_cartridge_type(rom_path));
+
+		// NOTE: Run headless and print the state, so runs can be diffed with text tools
+		if let Some(frame) = dump_state_at_frame {
+			for _ in 0..frame {
+				gba.run_frame();
+			}
+			println!("{}", serde_json::to_string_pretty(&dump_state_json(&gba.cpu, &gba.bus, frame)).unwrap());
+			return;
 		}
-		let mut bus = SystemBus::new_with_cartridge(bios_data.into_boxed_slice(), cartridge_data.into_boxed_slice());
-		//		let mut bus = SystemBus::new(bios_data.into_boxed_slice());
+
+		// NOTE: Loaded after the state dump, so dumps don't depend on the saves on disk
+		if let Err(e) = gba.bus.load_backup(&get_save_path(rom_path)) {
+			eprintln!("Failed to load the save memory of {}: {}", rom_path.display(), e);
+		}
+		gba.set_rewind_enabled(true);
+
+		let system = match windowing::init("GBA Rustmulator") {
+			Ok(system) => system,
+			Err(e) => {
+				eprintln!("{}", e);
+				eprintln!("Without a window the emulator can still run headless, see --dump-state-at-frame and the screenshot example");
+				std::process::exit(1);
+			}
+		};
 
 		let mut show_cpu_debug_window = true;
 		let mut show_memory_debug_window = true;
 		let mut show_io_registers_window = true;
 		let mut show_tiles_window = true;
 		let mut show_sprites_window = true;
+		let mut show_bg_map_window = false;
+		let mut show_palette_window = false;
+		let mut show_health_window = false;
+		let mut show_ppu_stats_window = false;
+		let mut show_breakpoints_window = false;
+		let mut show_callstack_window = false;
+		let mut show_profiler_window = false;
+		let mut show_cheats_window = false;
+		let mut show_ram_search_window = false;
+		let mut show_hex_editor_window = false;
+		let mut show_ab_compare_window = false;
 		let mut show_demo_window = false;
 
-		let mut debug_mode = true;
-		let mut execute_step = false;
-		let mut breakpoint_set = false;
-		let mut write_flow_to_file = false;
+		let mut cheats = CheatManager::new();
+		let mut cheat_name = ImString::with_capacity(64);
+		let mut cheat_code = ImString::with_capacity(256);
+		let mut cheat_format = 0;
+		let mut ram_search = RamSearch::new();
+		let mut ram_search_comparison = 0;
+		let mut ram_search_value = ImString::with_capacity(32);
+		let mut hex_editor = HexEditor::new();
+		let mut hex_editor_text = ImString::with_capacity(32);
 		let mut tiles_is_palette = false;
-		let mut breakpoint_address = 0x0u32;
-		let mut current_inspected_address = 0;
-		let mut selected_io_register = 0;
+		let mut bg_map_grid = false;
+		let mut selected_sprite: Option<usize> = None;
+		let mut selected_palette_color = 0;
+		let mut ab_comparison: Option<AbComparison> = None;
+		let mut ab_option = EAbOption::InputLatching;
+		let mut ab_texture_ids: (Option<TextureId>, Option<TextureId>) = (None, None);
+		let mut frame_texture_id: Option<TextureId> = None;
+		let mut bg_map_texture_ids: [Option<TextureId>; 4] = [None; 4];
+		let mut dropped_rom_path: Option<PathBuf> = None;
+		let mut current_rom_path = rom_path.to_path_buf();
+		let mut ram_init = ERamInit::Zero;
+		let bookmarks_path = get_bookmarks_path(&ab_cartridge_data);
+		let mut debugger = DebuggerState::new(load_symbol_table(&bookmarks_path, rom_path), bookmarks_path, options.debug);
+		let mut profile_path = get_profile_path(&ab_cartridge_data);
+		let mut pending_profile = Profile::load(&profile_path);
+		let mut saved_profile: Option<Profile> = None;
+		let compat_database = CompatDatabase::builtin();
+		let mut compat_entry = compat_database.get(&get_game_code(&ab_cartridge_data)).cloned();
+		let mut backup_snapshot_path = get_backup_snapshot_path(&ab_cartridge_data);
+		// NOTE: Save memory replaced by the last import, so it can be undone
+		let mut backup_before_import: Option<Vec<u8>> = None;
+		let mut backup_message: Option<String> = None;
+		let mut export_backup = false;
+		let mut import_backup = false;
+		let mut save_state_path = get_save_state_path(&ab_cartridge_data);
+		let mut save_state = false;
+		let mut load_state = false;
+		// NOTE: Rewinding while the key is held, one snapshot per frame
+		let mut rewinding = false;
+		let mut rewind_once = false;
 
 		let System {
 			event_loop,
@@ -67,10 +222,21 @@ fn main() {
 			..
 		} = system;
 		let mut last_frame = Instant::now();
-		let target_frame_duration: Duration = Duration::from_secs_f32(1.0 / 60.0);
+		let mut last_backup_flush = Instant::now();
+		let mut refresh_rate = ERefreshRate::Hardware;
+		let mut rotation = ERotation::None;
+		let mut color_filter = EColorFilter::None;
+		let mut frame_blender = FrameBlender::new(0.0);
+		let mut gyro_keys = DEFAULT_GYRO_KEYS;
+		// NOTE: Whether each gyro key is held, both cancel each other out
+		let mut gyro_keys_held = [false; 2];
+		let mut audio_output = if options.mute { None } else { AudioOutput::new() };
+		if audio_output.is_none() && !options.mute {
+			eprintln!("No audio output device, the frames are paced with the system timer");
+		}
 
-		let mut flow = Vec::<u8>::with_capacity(10000);
-		let mut current_cycle = 0u32;
+		let mut cpu_snapshot = CpuSnapshot::new(&gba.cpu);
+		let mut was_paused = debugger.debug_mode;
 
 		event_loop.run(move |event, _, control_flow| {
 			*control_flow = ControlFlow::Poll;
@@ -81,10 +247,19 @@ fn main() {
 			match event {
 				Event::NewEvents(_) => {
 					// Lock FPS
-					let elapsed_time = last_frame.elapsed();
-					if elapsed_time < target_frame_duration {
-						spin_sleep::sleep(target_frame_duration - elapsed_time);
+					let idle_start = Instant::now();
+					// NOTE: At the hardware rate the audio device is the clock, so the audio doesn't drift (not while paused, nothing gets queued)
+					match audio_output.as_ref().filter(|_| refresh_rate == ERefreshRate::Hardware && !debugger.debug_mode) {
+						Some(audio) => audio.wait_for_device(),
+						None => {
+							let target_frame_duration = refresh_rate.get_frame_duration();
+							let elapsed_time = last_frame.elapsed();
+							if elapsed_time < target_frame_duration {
+								spin_sleep::sleep(target_frame_duration - elapsed_time);
+							}
+						}
 					}
+					gba.get_mut_speed_meter().add_time(ESubsystem::Idle, idle_start.elapsed());
 					let duration_elapsed_for_frame = last_frame.elapsed();
 
 					let ms_per_frame = duration_elapsed_for_frame.as_micros() as f32 / 1000.0;
@@ -96,80 +271,192 @@ fn main() {
 				}
 				Event::MainEventsCleared => {
 					// NOTE: Advance GBA by one frame
-					const CYCLES_PER_FRAME: u32 = 280_896;
-					if !debug_mode || execute_step {
-						if execute_step {
-							execute_step = false;
-							current_cycle = (current_cycle + 1) % CYCLES_PER_FRAME;
-							bus.ppu.step(current_cycle);
-
-							cpu.step(&mut bus);
-						} else {
-							for _ in 0..=CYCLES_PER_FRAME {
-								current_cycle = (current_cycle + 1) % CYCLES_PER_FRAME;
-								let (h_blank_irq, v_blank_irq) = bus.ppu.step(current_cycle);
-
-								// TODO: Check interrupts!!!
-								if bus.ppu.get_disp_stat().get_v_counter_flag()
-									&& bus.io_regs.get_ime() && bus.io_regs.get_ie().get_v_counter_match()
-									&& bus.ppu.get_disp_stat().get_v_counter_irq()
-								{
-									bus.io_regs.get_mut_if().set_v_counter_match(true);
-									cpu.exception(EExceptionType::Irq);
-									bus.io_regs.halted = false;
-								}
-
-								// H-Blank
-								if h_blank_irq && bus.io_regs.get_ime() && bus.io_regs.get_ie().get_h_blank() && bus.ppu.get_disp_stat().get_h_blank_irq() {
-									bus.io_regs.get_mut_if().set_h_blank(true);
-									cpu.exception(EExceptionType::Irq);
-									bus.io_regs.halted = false;
-								} else if v_blank_irq && bus.io_regs.get_ime() && bus.io_regs.get_ie().get_v_blank() && bus.ppu.get_disp_stat().get_v_blank_irq() {
-									// V-Blank
-									bus.io_regs.get_mut_if().set_v_blank(true);
-									cpu.exception(EExceptionType::Irq);
-									bus.io_regs.halted = false;
-								}
+					if (rewinding || rewind_once) && !debugger.debug_mode {
+						rewind_once = false;
+						match gba.rewind() {
+							Ok(true) => debugger.clear_history(),
+							Ok(false) => rewinding = false,
+							Err(e) => eprintln!("Failed to rewind: {}", e),
+						}
+					} else if !debugger.debug_mode || debugger.execute_step {
+						// NOTE: Drop what the debug windows reported, only accesses of the game count
+						gba.bus.take_anomaly();
+						gba.bus.take_watchpoint_hit();
+						if was_paused {
+							cpu_snapshot = CpuSnapshot::new(&gba.cpu);
+						}
 
-								if !bus.io_regs.halted {
-									if write_flow_to_file {
-										writeln!(&mut flow, "{:#X}: {}", cpu.get_current_pc(), disassemble_instruction(&cpu, &bus)).unwrap();
-									}
+						if debugger.execute_step {
+							debugger.execute_step = false;
+							// NOTE: The rest of the system runs while the CPU waits out the previous instruction or is halted
+							gba.step_instruction_with(&mut debugger);
+						} else {
+							debugger.clear_messages();
+							gba.cpu.take_last_exception();
+							cheats.apply(&mut gba.bus);
+							gba.run_frame_with(&mut debugger);
+						}
+					}
+					was_paused = debugger.debug_mode;
+					// NOTE: Whatever stopped the game, a step over/out or run to cursor is done
+					if debugger.debug_mode {
+						debugger.run_target = None;
+					}
 
-									cpu.step(&mut bus);
-
-									// NOTE: Breakpoint
-									if breakpoint_set && cpu.get_current_pc() == breakpoint_address {
-										debug_mode = true;
-
-										// Write flow to file
-										if write_flow_to_file {
-											let mut flow_file = OpenOptions::new()
-												.append(true)
-												.create(true)
-												.open("C:\\Users\\gbAgostPa\\Downloads\\Tests\\BIOS_Flow.txt")
-												.unwrap();
-											flow_file.write_all(&flow).unwrap();
-											flow.clear();
-										}
+					// NOTE: Paused or rewinding isn't a slowdown, the speed is measured again once the game runs
+					if debugger.debug_mode || rewinding {
+						gba.get_mut_speed_meter().reset();
+					} else if let Some(report) = gba.get_mut_speed_meter().update() {
+						eprintln!("Slowdown: {}", report.get_description());
+					}
 
-										break;
-									}
-								}
-							}
+					// NOTE: Persist the save memory regularly, so a crash doesn't lose the progress
+					if last_backup_flush.elapsed() >= BACKUP_FLUSH_INTERVAL {
+						last_backup_flush = Instant::now();
+						if let Err(e) = gba.bus.flush_backup() {
+							eprintln!("Failed to save the save memory: {}", e);
 						}
 					}
 
+					let samples = gba.bus.apu.take_samples();
+					if let Some(audio) = audio_output.as_mut() {
+						audio.push_samples(&samples);
+					}
+
 					let gl_window = display.gl_window();
 					platform.prepare_frame(imgui.io_mut(), gl_window.window()).expect("Failed to prepare frame");
 					gl_window.window().request_redraw();
 				}
 				Event::RedrawRequested(_) => {
+					let redraw_start = Instant::now();
+					let mut rendering_time = Duration::ZERO;
+
+					// NOTE: Restore the profile of the game before the layout is used
+					if let Some(profile) = pending_profile.take() {
+						imgui.load_ini_settings(&profile.layout);
+						show_cpu_debug_window = profile.is_window_open("CPU", show_cpu_debug_window);
+						show_memory_debug_window = profile.is_window_open("Memory", show_memory_debug_window);
+						show_io_registers_window = profile.is_window_open("I/O Registers", show_io_registers_window);
+						show_tiles_window = profile.is_window_open("Tiles", show_tiles_window);
+						show_sprites_window = profile.is_window_open("Sprites", show_sprites_window);
+						show_bg_map_window = profile.is_window_open("BG Maps", show_bg_map_window);
+						show_palette_window = profile.is_window_open("Palettes", show_palette_window);
+						show_ppu_stats_window = profile.is_window_open("PPU Stats", show_ppu_stats_window);
+						show_health_window = profile.is_window_open("Health", show_health_window);
+						show_ab_compare_window = profile.is_window_open("A/B Compare", show_ab_compare_window);
+						show_breakpoints_window = profile.is_window_open("Breakpoints", show_breakpoints_window);
+						show_callstack_window = profile.is_window_open("Call Stack", show_callstack_window);
+						show_profiler_window = profile.is_window_open("Profiler", show_profiler_window);
+						show_cheats_window = profile.is_window_open("Cheats", show_cheats_window);
+						show_ram_search_window = profile.is_window_open("RAM Search", show_ram_search_window);
+						show_hex_editor_window = profile.is_window_open("Hex Editor", show_hex_editor_window);
+						debugger.breakpoints.set_breakpoints(profile.breakpoints.clone());
+						cheats.set_cheats(profile.cheats.clone());
+						rotation = profile.rotation;
+						color_filter = profile.color_filter;
+						frame_blender.set_weight(profile.frame_blend_weight);
+						refresh_rate = profile.refresh_rate;
+						gyro_keys = profile.gyro_keys;
+						saved_profile = Some(profile);
+					}
+
 					let mut ui = imgui.frame();
 
 					// NOTE: UI BEGIN!!!
 					let run = true;
+					let mut soft_reset = false;
+					let mut power_cycle = false;
+					let mut undo_import_backup = false;
 					ui.main_menu_bar(|| {
+						ui.menu(im_str!("Emulation"), true, || {
+							if MenuItem::new(im_str!("Soft Reset")).build(&ui) {
+								soft_reset = true;
+							}
+							if MenuItem::new(im_str!("Power Cycle")).build(&ui) {
+								power_cycle = true;
+							}
+
+							if MenuItem::new(im_str!("Save State")).shortcut(im_str!("F5")).build(&ui) {
+								save_state = true;
+							}
+							if MenuItem::new(im_str!("Load State")).shortcut(im_str!("F8")).enabled(save_state_path.exists()).build(&ui) {
+								load_state = true;
+							}
+
+							let rewind_buffer = gba.get_rewind_buffer();
+							let rewind_label = im_str!("Rewind ({:.1}s)", rewind_buffer.map_or(0.0, RewindBuffer::get_duration));
+							if MenuItem::new(&rewind_label)
+								.shortcut(im_str!("Backspace"))
+								.enabled(rewind_buffer.is_some_and(|rewind_buffer| !rewind_buffer.is_empty()))
+								.build(&ui)
+							{
+								rewind_once = true;
+							}
+
+							if MenuItem::new(im_str!("Cheats")).build(&ui) {
+								show_cheats_window = true;
+							}
+							if MenuItem::new(im_str!("RAM Search")).build(&ui) {
+								show_ram_search_window = true;
+							}
+
+							ui.menu(im_str!("Save Memory"), true, || {
+								let has_backup = gba.bus.get_cartridge().get_backup().get_type() != EBackupType::None;
+								if MenuItem::new(im_str!("Export Snapshot")).shortcut(im_str!("F6")).enabled(has_backup).build(&ui) {
+									export_backup = true;
+								}
+								if MenuItem::new(im_str!("Import Snapshot")).shortcut(im_str!("F7")).enabled(has_backup).build(&ui) {
+									import_backup = true;
+								}
+								if MenuItem::new(im_str!("Undo Import")).enabled(backup_before_import.is_some()).build(&ui) {
+									undo_import_backup = true;
+								}
+							});
+
+							ui.menu(im_str!("Initial RAM"), true, || {
+								ui.text_disabled("Applied on the next Power Cycle");
+								for option in [ERamInit::Zero, ERamInit::PseudoRandom(RAM_INIT_SEED)].iter() {
+									if MenuItem::new(&im_str!("{}", option.get_name())).selected(ram_init == *option).build(&ui) {
+										ram_init = *option;
+									}
+								}
+							});
+
+							let mut latch_at_v_blank = gba.bus.io_regs.get_input_latching() == EInputLatching::VBlank;
+							if MenuItem::new(im_str!("Latch Input at V-Blank")).build_with_ref(&ui, &mut latch_at_v_blank) {
+								gba.bus
+									.io_regs
+									.set_input_latching(if latch_at_v_blank { EInputLatching::VBlank } else { EInputLatching::Immediate });
+							}
+
+							let mut match_monitor = refresh_rate == ERefreshRate::MatchMonitor;
+							if MenuItem::new(im_str!("Match Monitor (60 Hz)")).build_with_ref(&ui, &mut match_monitor) {
+								refresh_rate = if match_monitor { ERefreshRate::MatchMonitor } else { ERefreshRate::Hardware };
+							}
+
+							ui.menu(im_str!("Rotation"), true, || {
+								for option in [ERotation::None, ERotation::Rotate90, ERotation::Rotate180, ERotation::Rotate270].iter().cloned() {
+									if MenuItem::new(&im_str!("{}", option.get_name())).selected(rotation == option).build(&ui) {
+										rotation = option;
+									}
+								}
+							});
+
+							ui.menu(im_str!("Color Filter"), true, || {
+								for option in EColorFilter::ALL.iter().cloned() {
+									if MenuItem::new(&im_str!("{}", option.get_name())).selected(color_filter == option).build(&ui) {
+										color_filter = option;
+									}
+								}
+							});
+
+							ui.menu(im_str!("LCD Ghosting"), true, || {
+								let mut weight = frame_blender.get_weight();
+								if Slider::new(im_str!("Previous Frame")).range(0.0..=MAX_FRAME_BLEND_WEIGHT).build(&ui, &mut weight) {
+									frame_blender.set_weight(weight);
+								}
+							});
+						});
 						ui.menu(im_str!("Debug"), true, || {
 							if MenuItem::new(im_str!("CPU")).build(&ui) {
 								show_cpu_debug_window = true;
@@ -177,6 +464,15 @@ fn main() {
 							if MenuItem::new(im_str!("Memory")).build(&ui) {
 								show_memory_debug_window = true;
 							}
+							if MenuItem::new(im_str!("Hex Editor")).build(&ui) {
+								show_hex_editor_window = true;
+							}
+							if MenuItem::new(im_str!("Breakpoints")).build(&ui) {
+								show_breakpoints_window = true;
+							}
+							if MenuItem::new(im_str!("Call Stack")).build(&ui) {
+								show_callstack_window = true;
+							}
 							if MenuItem::new(im_str!("I/O Registers")).build(&ui) {
 								show_io_registers_window = true;
 							}
@@ -186,88 +482,316 @@ fn main() {
 							if MenuItem::new(im_str!("Sprites")).build(&ui) {
 								show_sprites_window = true;
 							}
+							if MenuItem::new(im_str!("BG Maps")).build(&ui) {
+								show_bg_map_window = true;
+							}
+							if MenuItem::new(im_str!("Palettes")).build(&ui) {
+								show_palette_window = true;
+							}
+							if MenuItem::new(im_str!("PPU Stats")).build(&ui) {
+								show_ppu_stats_window = true;
+							}
+							if MenuItem::new(im_str!("Profiler")).build(&ui) {
+								show_profiler_window = true;
+							}
+							if MenuItem::new(im_str!("Health")).build(&ui) {
+								show_health_window = true;
+							}
+							if MenuItem::new(im_str!("A/B Compare")).build(&ui) {
+								show_ab_compare_window = true;
+							}
+
+							ui.separator();
+							let render_debug_options = gba.bus.ppu.get_mut_render_debug_options();
+							let mut override_backdrop = render_debug_options.backdrop_override.is_some();
+							if MenuItem::new(im_str!("Override Backdrop")).build_with_ref(&ui, &mut override_backdrop) {
+								// NOTE: Magenta, rarely used by games
+								render_debug_options.backdrop_override = if override_backdrop { Some([1.0, 0.0, 1.0]) } else { None };
+							}
+							MenuItem::new(im_str!("Tint Layers")).build_with_ref(&ui, &mut render_debug_options.tint_layers);
+							MenuItem::new(im_str!("Layer Map")).build_with_ref(&ui, &mut render_debug_options.layer_map);
+							ui.menu(im_str!("Layer Colors"), true, || {
+								for layer in ELayer::ALL.iter() {
+									let [red, green, blue] = layer.get_tint_color();
+									ui.text_colored([red, green, blue, 1.0], layer.get_name());
+								}
+							});
+							let mut break_on_anomaly = gba.bus.is_break_on_anomaly_enabled();
+							if MenuItem::new(im_str!("Break on Anomaly")).build_with_ref(&ui, &mut break_on_anomaly) {
+								gba.bus.set_break_on_anomaly(break_on_anomaly);
+							}
+							ui.menu(im_str!("Break on Exception"), true, || {
+								MenuItem::new(im_str!("SWI")).build_with_ref(&ui, &mut debugger.exception_breakpoints.swi);
+								MenuItem::new(im_str!("IRQ")).build_with_ref(&ui, &mut debugger.exception_breakpoints.irq);
+								MenuItem::new(im_str!("Undefined")).build_with_ref(&ui, &mut debugger.exception_breakpoints.undefined);
+								ui.separator();
+								let mut all = debugger.exception_breakpoints.all();
+								if MenuItem::new(im_str!("All")).build_with_ref(&ui, &mut all) {
+									debugger.exception_breakpoints = ExceptionBreakpoints {
+										swi: all,
+										irq: all,
+										undefined: all,
+									};
+								}
+							});
+							let mut alignment_diagnostics = gba.cpu.is_alignment_diagnostics_enabled();
+							if MenuItem::new(im_str!("Log Misaligned Accesses")).build_with_ref(&ui, &mut alignment_diagnostics) {
+								gba.cpu.set_alignment_diagnostics(alignment_diagnostics);
+							}
+
+							let gpio = gba.bus.get_cartridge().get_gpio().cloned().unwrap_or_default();
+							let has_sensors = gpio.get_solar_sensor().is_some() || gpio.get_gyro_sensor().is_some();
+							ui.menu(im_str!("Cartridge Sensors"), has_sensors, || {
+								if let Some(sensor) = gba.bus.get_mut_cartridge().get_mut_gpio().and_then(|gpio| gpio.get_mut_solar_sensor()) {
+									let mut level = sensor.get_level();
+									if Slider::new(im_str!("Light Level")).range(0..=SOLAR_SENSOR_MAX_LEVEL).build(&ui, &mut level) {
+										sensor.set_level(level);
+									}
+								}
+								if gpio.get_gyro_sensor().is_some() {
+									let key_names: Vec<ImString> = BINDABLE_KEYS.iter().map(|(_, name)| ImString::new(*name)).collect();
+									let key_names: Vec<&ImStr> = key_names.iter().map(|name| name.as_ref()).collect();
+									for (key, label) in gyro_keys.iter_mut().zip([im_str!("Rotate Left"), im_str!("Rotate Right")].iter()) {
+										let mut selected = BINDABLE_KEYS.iter().position(|(bindable, _)| bindable == key).unwrap_or(0);
+										if ComboBox::new(label).build_simple_string(&ui, &mut selected, &key_names) {
+											*key = BINDABLE_KEYS[selected].0;
+										}
+									}
+								}
+							});
 						});
 						ui.menu(im_str!("Help"), true, || {
 							if MenuItem::new(im_str!("Demo")).build(&ui) {
 								show_demo_window = true;
 							}
 						});
+
+						// NOTE: Status
+						let refresh_rate_name = if refresh_rate == ERefreshRate::Hardware { "Hardware" } else { "Match Monitor" };
+						ui.text_disabled(format!("{:.4} Hz ({})", refresh_rate.get_frequency(), refresh_rate_name));
+						if let Some(speed) = gba.get_speed_meter().get_speed() {
+							if speed < SLOWDOWN_THRESHOLD {
+								ui.text_colored([1.0, 0.8, 0.0, 1.0], format!("{:.0}%", speed));
+							} else {
+								ui.text_disabled(format!("{:.0}%", speed));
+							}
+						}
+						if let Some(message) = &debugger.anomaly_message {
+							ui.text_colored([1.0, 0.3, 0.3, 1.0], message);
+						}
+						if let Some(entry) = compat_entry.as_ref().filter(|entry| entry.status != ECompatStatus::Working) {
+							ui.text_colored([1.0, 0.8, 0.0, 1.0], entry.get_summary());
+						}
+						if let Some(message) = &debugger.exception_message {
+							ui.text_colored([1.0, 0.8, 0.3, 1.0], message);
+						}
+						if let Some(message) = &debugger.watchpoint_message {
+							ui.text_colored([0.4, 0.8, 1.0, 1.0], message);
+						}
+						if let Some(message) = &debugger.trace_message {
+							ui.text_disabled(message);
+						}
+						if let Some(message) = &backup_message {
+							ui.text_disabled(message);
+						}
+						// NOTE: No host device rumbles, the motor is shown instead
+						let gpio = gba.bus.get_cartridge().get_gpio().cloned().unwrap_or_default();
+						if gpio.get_gyro_sensor().map(|sensor| sensor.is_rumbling()) == Some(true) {
+							ui.text_colored([1.0, 0.5, 0.2, 1.0], "Rumble");
+						}
 					});
 
+					if soft_reset {
+						bios::soft_reset(&mut gba.cpu, &mut gba.bus);
+					}
+
+					// NOTE: Everything the game can see starts over, the debugger settings (breakpoints, windows, layout, render options) are kept
+					if power_cycle {
+						if let Err(e) = gba.bus.flush_backup() {
+							eprintln!("Failed to save the save memory: {}", e);
+						}
+
+						let mut new_gba = boot(&ab_bios_data, &ab_cartridge_data, &current_rom_path, ram_init);
+						new_gba.bus.io_regs.set_input_latching(gba.bus.io_regs.get_input_latching());
+						new_gba.bus.set_break_on_anomaly(gba.bus.is_break_on_anomaly_enabled());
+						*new_gba.bus.ppu.get_mut_render_debug_options() = *gba.bus.ppu.get_render_debug_options();
+						for watchpoint in gba.bus.get_watchpoints() {
+							new_gba.bus.add_watchpoint(*watchpoint);
+						}
+						new_gba.cpu.set_alignment_diagnostics(gba.cpu.is_alignment_diagnostics_enabled());
+						gba = new_gba;
+
+						debugger.clear_history();
+						cpu_snapshot = CpuSnapshot::new(&gba.cpu);
+						debugger.clear_messages();
+						backup_before_import = None;
+						backup_message = Some("Power cycled".to_string());
+					}
+
+					if save_state {
+						let mut data = Vec::new();
+						let result = gba
+							.save_state(&mut data)
+							.and_then(|_| save_state_path.parent().map_or(Ok(()), std::fs::create_dir_all))
+							.and_then(|_| std::fs::write(&save_state_path, data));
+						backup_message = Some(match result {
+							Ok(_) => format!("State saved to {}", save_state_path.display()),
+							Err(e) => format!("Failed to save the state to {}: {}", save_state_path.display(), e),
+						});
+					} else if load_state {
+						let result = std::fs::read(&save_state_path).and_then(|data| gba.load_state(&mut data.as_slice()));
+						backup_message = Some(match result {
+							Ok(_) => {
+								debugger.clear_history();
+								format!("State loaded from {}", save_state_path.display())
+							}
+							Err(e) => format!("Failed to load the state from {}: {}", save_state_path.display(), e),
+						});
+					}
+					save_state = false;
+					load_state = false;
+
+					// NOTE: Save memory snapshots are plain .sav files, separate from the emulator state
+					if (export_backup || import_backup) && gba.bus.get_cartridge().get_backup().get_type() == EBackupType::None {
+						backup_message = Some("The cartridge has no save memory".to_string());
+					} else if export_backup {
+						let data = gba.bus.get_cartridge().get_backup().get_data();
+						let result = backup_snapshot_path
+							.parent()
+							.map_or(Ok(()), std::fs::create_dir_all)
+							.and_then(|_| std::fs::write(&backup_snapshot_path, data));
+						backup_message = Some(match result {
+							Ok(_) => format!("Save memory exported to {}", backup_snapshot_path.display()),
+							Err(e) => format!("Failed to export the save memory to {}: {}", backup_snapshot_path.display(), e),
+						});
+					} else if import_backup {
+						backup_message = Some(match std::fs::read(&backup_snapshot_path) {
+							Ok(data) => {
+								let cartridge = gba.bus.get_mut_cartridge();
+								backup_before_import = Some(cartridge.get_backup().get_data().to_vec());
+								cartridge.load_backup_data(&data);
+								format!("Save memory imported from {}", backup_snapshot_path.display())
+							}
+							Err(e) => format!("Failed to import the save memory from {}: {}", backup_snapshot_path.display(), e),
+						});
+					}
+					export_backup = false;
+					import_backup = false;
+
+					if undo_import_backup {
+						if let Some(data) = backup_before_import.take() {
+							gba.bus.get_mut_cartridge().load_backup_data(&data);
+							backup_message = Some("Save memory import undone".to_string());
+						}
+					}
+
 					// NOTE: Render window!!!
 					Window::new(im_str!("Render"))
 						.size([0.0, 0.0], Condition::Always)
 						.resizable(true)
 						.position([900.0, 600.0], Condition::FirstUseEver)
 						.build(&ui, || {
-							let frame_texture = bus.ppu.render();
-
-							let image = glium::texture::RawImage2d::from_raw_rgb(frame_texture, (240, 160));
-							let gl_texture = glium::texture::Texture2d::new(&display, image).unwrap();
+							let rendering_start = Instant::now();
+							let (width, height) = rotation.get_dimensions(FRAME_WIDTH, FRAME_HEIGHT);
+							let mut frame = gba.bus.ppu.render();
+							frame_blender.apply(&mut frame);
+							color_filter.apply(&mut frame);
+							let frame = rotation.rotate_frame(&frame, FRAME_WIDTH, FRAME_HEIGHT);
+							let texture_id = update_frame_texture(&display, &mut renderer, &mut frame_texture_id, frame, (width, height));
+							rendering_time = rendering_start.elapsed();
+							Image::new(texture_id, [width as f32 * scale, height as f32 * scale]).build(&ui);
 
-							let texture = imgui_glium_renderer::Texture {
-								texture: Rc::new(gl_texture),
-								sampler: SamplerBehavior {
-									wrap_function: (SamplerWrapFunction::BorderClamp, SamplerWrapFunction::BorderClamp, SamplerWrapFunction::BorderClamp),
-									..Default::default()
-								},
-							};
-							let texture_id = renderer.textures().insert(texture);
-							Image::new(texture_id, [480.0, 320.0]).build(&ui);
+							// NOTE: Outline the sprite selected in the Sprites window
+							if let Some(sprite) = selected_sprite.filter(|_| show_sprites_window).and_then(|index| gba.bus.ppu.get_sprites().get(index)) {
+								let (bounds_width, bounds_height) = sprite.get_bounds_size();
+								let (x, y) = (sprite.get_x_coord() as f32, sprite.get_y_coord() as f32);
+								let (x1, y1) = rotation.rotate_point(x, y, FRAME_WIDTH, FRAME_HEIGHT);
+								let (x2, y2) = rotation.rotate_point(x + bounds_width as f32, y + bounds_height as f32, FRAME_WIDTH, FRAME_HEIGHT);
+								let origin = ui.item_rect_min();
+								let draw_list = ui.get_window_draw_list();
+								draw_list.with_clip_rect_intersect(origin, ui.item_rect_max(), || {
+									draw_list
+										.add_rect(
+											[origin[0] + x1.min(x2) * scale, origin[1] + y1.min(y2) * scale],
+											[origin[0] + x1.max(x2) * scale, origin[1] + y1.max(y2) * scale],
+											[1.0, 1.0, 0.0, 1.0],
+										)
+										.thickness(2.0)
+										.build();
+								});
+							}
 						});
 
 					if show_cpu_debug_window {
-						build_cpu_debug_window(&cpu, &&mut ui, &mut show_cpu_debug_window);
+						build_cpu_debug_window(
+							&mut gba.cpu,
+							Some(&cpu_snapshot).filter(|_| debugger.debug_mode),
+							debugger.debug_mode,
+							&&mut ui,
+							&mut show_cpu_debug_window,
+						);
 					}
 
 					if show_memory_debug_window {
-						build_memory_debug_window(
-							&cpu,
-							&bus,
-							&mut show_memory_debug_window,
-							&mut current_inspected_address,
-							&mut debug_mode,
-							&mut execute_step,
-							&mut breakpoint_set,
-							&mut write_flow_to_file,
-							&mut breakpoint_address,
+						build_memory_debug_window(&gba.cpu, &mut gba.bus, &mut debugger, &mut show_memory_debug_window, &&mut ui);
+					}
+
+					if show_hex_editor_window {
+						build_hex_editor_window(&mut hex_editor, &mut gba.bus, &mut debugger, &mut hex_editor_text, &mut show_hex_editor_window, &&mut ui);
+					}
+
+					if show_breakpoints_window {
+						build_breakpoints_window(&mut debugger, &mut show_breakpoints_window, &&mut ui);
+					}
+
+					if show_callstack_window {
+						build_callstack_debug_window(
+							&debugger.call_stack,
+							&gba.bus,
+							&debugger.symbols,
+							&mut debugger.inspected_address,
+							&mut show_callstack_window,
 							&&mut ui,
 						);
 					}
 
 					if show_io_registers_window {
-						build_io_registers_window(&bus, &mut show_io_registers_window, &mut selected_io_register, &&mut ui);
+						build_io_registers_window(&mut gba.bus, &mut show_io_registers_window, &&mut ui);
 					}
 
 					if show_tiles_window {
-						if let Some(video_mode) = bus.ppu.get_disp_cnt().get_bg_mode() {
+						if let Some(video_mode) = gba.bus.ppu.get_disp_cnt().get_bg_mode() {
 							let obj_tiles_start = match video_mode {
 								EVideoMode::Mode0 | EVideoMode::Mode1 | EVideoMode::Mode2 => 0x10000,
 								EVideoMode::Mode3 | EVideoMode::Mode4 | EVideoMode::Mode5 => 0x14000,
 							};
 
-							let mut pixels = vec![0.0; VRAM_SIZE * 3];
-							for i in 0..VRAM_SIZE as u32 {
-								let palette_color_index = if i >= obj_tiles_start {
-									bus.ppu.read_8(VRAM_ADDR + i) as usize + 256
-								} else {
-									bus.ppu.read_8(VRAM_ADDR + i) as usize
-								};
-								// One color every 2 bytes
-								let color = bus.ppu.palette_ram[palette_color_index];
+							const TILES_PER_ROW: usize = 32;
+							let format = ETileFormat::from_is_256_palette(tiles_is_palette);
+							let tile_length = format.get_tile_length();
+							let tile_count = VRAM_SIZE / tile_length;
+							let (width, height) = (TILES_PER_ROW * TILE_WIDTH, tile_count / TILES_PER_ROW * TILE_WIDTH);
 
-								const TILES_PER_ROW: u32 = 32;
-								let tile_offset = ((i / 64) % TILES_PER_ROW) * 8;
-								let row_offset = ((i % 64) / 8) * TILES_PER_ROW * 8;
-								let tiles_row_offset = ((i / 64) / TILES_PER_ROW) * 64 * TILES_PER_ROW;
-								let pixel_index = ((i % 8) + tile_offset + tiles_row_offset + row_offset) * 3;
+							let vram = gba.bus.ppu.get_vram();
+							let mut pixels = vec![0.0; width * height * 3];
+							for tile in 0..tile_count {
+								let address = tile * tile_length;
+								// NOTE: 4bpp tiles are shown with the first BG/OBJ palette
+								let palette_start = if address >= obj_tiles_start { SPRITE_PALETTE_START_INDEX } else { 0 };
+								let indices = decode_tile(&vram[address..address + tile_length], format);
+								for (pixel, index) in indices.iter().enumerate() {
+									let color = gba.bus.ppu.palette_ram[palette_start + get_palette_index(*index, format, 0).unwrap_or(0)];
 
-								pixels[pixel_index as usize] = color.get_red();
-								pixels[pixel_index as usize + 1] = color.get_green();
-								pixels[pixel_index as usize + 2] = color.get_blue();
+									let x = (tile % TILES_PER_ROW) * TILE_WIDTH + pixel % TILE_WIDTH;
+									let y = (tile / TILES_PER_ROW) * TILE_WIDTH + pixel / TILE_WIDTH;
+									let pixel_index = (x + y * width) * 3;
+									pixels[pixel_index] = color.get_red();
+									pixels[pixel_index + 1] = color.get_green();
+									pixels[pixel_index + 2] = color.get_blue();
+								}
 							}
 
-							let image = glium::texture::RawImage2d::from_raw_rgb(pixels, (256, 384));
+							let image = glium::texture::RawImage2d::from_raw_rgb(pixels, (width as u32, height as u32));
 							let gl_texture = glium::texture::Texture2d::new(&display, image).unwrap();
 
 							let texture = imgui_glium_renderer::Texture {
@@ -279,56 +803,42 @@ fn main() {
 							};
 							let texture_id = renderer.textures().insert(texture);
 
-							build_tiles_debug_window(&bus, &mut show_tiles_window, &mut tiles_is_palette, texture_id, &&mut ui);
+							build_tiles_debug_window(&mut show_tiles_window, &mut tiles_is_palette, texture_id, [width as f32, height as f32], &&mut ui);
 						}
 					}
 
 					if show_sprites_window {
-						if let Some(video_mode) = bus.ppu.get_disp_cnt().get_bg_mode() {
-							let sprite_tiles_start = match video_mode {
-								EVideoMode::Mode0 | EVideoMode::Mode1 | EVideoMode::Mode2 => SPRITE_TILES_START_ADDRESS,
-								EVideoMode::Mode3 | EVideoMode::Mode4 | EVideoMode::Mode5 => 0x14000,
-							};
-
-							let is_1d_mapping = bus.ppu.get_disp_cnt().get_sprite_1d_mapping();
+						if let Some(video_mode) = gba.bus.ppu.get_disp_cnt().get_bg_mode() {
+							let is_1d_mapping = gba.bus.ppu.get_disp_cnt().get_sprite_1d_mapping();
 							let mut texture_ids = Vec::<TextureId>::with_capacity(128);
-							for sprite in bus.ppu.get_sprites() {
+							for sprite in gba.bus.ppu.get_sprites() {
 								let (width, height) = sprite.get_size();
+								let format = ETileFormat::from_is_256_palette(sprite.get_is_256_palette());
 								let tiles_per_row = if sprite.get_is_256_palette() { 16 } else { 32 };
-								let tile_length = if sprite.get_is_256_palette() { 64 } else { 32 };
-								let start_tile_address = sprite_tiles_start + sprite.get_tile_index() * 32;
+								let tile_length = format.get_tile_length();
+								let start_tile_address = SPRITE_TILES_START_ADDRESS + sprite.get_tile_index() * 32;
 
 								let mut pixels = vec![0.0; width * height * 3];
 								let tiles_x = width / 8;
-								for tx in 0..tiles_x {
-									for ty in 0..height / 8 {
-										let tile_address = if is_1d_mapping {
-											let tile = tx + ty * tiles_x;
-											start_tile_address + tile * tile_length
-										} else {
-											let tile = tx + ty * tiles_per_row;
-											start_tile_address + tile * tile_length
-										};
-
-										for x in 0..8 {
-											for y in 0..8 {
-												let tile_pixel = x + y * 8;
-												let pixel_index = (tx * 8 + ty * 64 * tiles_x + (x + y * width as u32) as usize) * 3;
-
-												let color;
-												if sprite.get_is_256_palette() {
-													let palette_entry = bus.ppu.read_8(VRAM_ADDR + tile_address as u32 + tile_pixel) as usize;
-
-													color = bus.ppu.palette_ram[SPRITE_PALETTE_START_INDEX + palette_entry];
-												} else {
-													let palette_entry = bus.ppu.read_8(VRAM_ADDR + tile_address as u32 + tile_pixel / 2) as usize;
-
-													let palette_offset = sprite.get_palette_number() as usize * 16;
-													let palette_index = (palette_entry >> ((tile_pixel & 1) * 4)) & 0xf;
-													let color_address = SPRITE_PALETTE_START_INDEX + palette_offset + palette_index;
-
-													color = bus.ppu.palette_ram[color_address];
-												}
+								// NOTE: Sprites the PPU won't display are left blank
+								if sprite.is_tile_available(video_mode) {
+									for tx in 0..tiles_x {
+										for ty in 0..height / 8 {
+											let tile_address = if is_1d_mapping {
+												let tile = tx + ty * tiles_x;
+												start_tile_address + tile * tile_length
+											} else {
+												let tile = tx + ty * tiles_per_row;
+												start_tile_address + tile * tile_length
+											};
+
+											let indices = decode_tile(gba.bus.ppu.get_obj_tile(tile_address, format), format);
+											for (pixel, index) in indices.iter().enumerate() {
+												let (x, y) = (pixel % TILE_WIDTH, pixel / TILE_WIDTH);
+												let pixel_index = (tx * 8 + ty * 64 * tiles_x + x + y * width) * 3;
+
+												let palette_index = get_palette_index(*index, format, sprite.get_palette_number() as usize).unwrap_or(0);
+												let color = gba.bus.ppu.palette_ram[SPRITE_PALETTE_START_INDEX + palette_index];
 
 												pixels[pixel_index] = color.get_red();
 												pixels[pixel_index + 1] = color.get_green();
@@ -353,7 +863,134 @@ fn main() {
 								texture_ids.push(texture_id);
 							}
 
-							build_sprites_debug_window(&mut show_sprites_window, &texture_ids, &&mut ui);
+							build_sprites_debug_window(&gba.bus, &mut show_sprites_window, &texture_ids, &mut selected_sprite, &&mut ui);
+						}
+					}
+
+					if show_palette_window {
+						build_palette_debug_window(&mut gba.bus, &mut selected_palette_color, &mut show_palette_window, &&mut ui);
+					}
+
+					if show_bg_map_window {
+						let enabled_bgs: Vec<usize> = (0..4).filter(|bg| gba.bus.ppu.get_disp_cnt().get_screen_display_bg(*bg)).collect();
+						let maps: Vec<(usize, TextureId, [f32; 2])> = enabled_bgs
+							.into_iter()
+							.filter(|bg| gba.bus.ppu.is_bg_affine(*bg).is_some())
+							.map(|bg| {
+								let (width, height) = gba.bus.ppu.get_bg_map_size(bg);
+								let texture_id = update_frame_texture(&display, &mut renderer, &mut bg_map_texture_ids[bg], gba.bus.ppu.render_bg_map(bg), (width, height));
+								(bg, texture_id, [width as f32, height as f32])
+							})
+							.collect();
+						build_bg_map_debug_window(&gba.bus, &maps, &mut bg_map_grid, &mut show_bg_map_window, &&mut ui);
+					}
+
+					if show_cheats_window {
+						build_cheats_window(&mut cheats, &mut cheat_name, &mut cheat_code, &mut cheat_format, &mut show_cheats_window, &&mut ui);
+					}
+
+					if show_ram_search_window {
+						build_ram_search_window(
+							&mut ram_search,
+							&mut gba.bus,
+							&mut ram_search_comparison,
+							&mut ram_search_value,
+							&mut debugger.inspected_address,
+							&mut show_ram_search_window,
+							&&mut ui,
+						);
+					}
+
+					if show_profiler_window {
+						build_profiler_window(
+							&mut debugger.profiler,
+							&debugger.symbols,
+							&mut debugger.inspected_address,
+							&mut show_profiler_window,
+							&&mut ui,
+						);
+					}
+
+					if show_health_window {
+						build_health_window(&gba.bus, &ab_cartridge_data, compat_entry.as_ref(), &mut show_health_window, &&mut ui);
+					}
+
+					if show_ppu_stats_window {
+						build_ppu_stats_window(&gba.bus, &mut show_ppu_stats_window, &&mut ui);
+					}
+
+					if show_ab_compare_window {
+						if let Some(comparison) = ab_comparison.as_mut() {
+							if !debugger.debug_mode {
+								comparison.run_frame(gba.bus.io_regs.get_host_key_input());
+							}
+						}
+
+						let texture_ids = ab_comparison.as_ref().filter(|comparison| comparison.get_frame() > 0).map(|comparison| {
+							let (frame_a, frame_b) = comparison.get_frames();
+							(
+								update_frame_texture(&display, &mut renderer, &mut ab_texture_ids.0, frame_a.to_vec(), (FRAME_WIDTH, FRAME_HEIGHT)),
+								update_frame_texture(&display, &mut renderer, &mut ab_texture_ids.1, frame_b.to_vec(), (FRAME_WIDTH, FRAME_HEIGHT)),
+							)
+						});
+
+						let mut restart = false;
+						build_ab_compare_window(&ab_comparison, &mut ab_option, &mut show_ab_compare_window, &mut restart, texture_ids, &&mut ui);
+						if restart || ab_comparison.is_none() {
+							ab_comparison = Some(AbComparison::new_with_option(
+								&ab_bios_data,
+								&ab_cartridge_data,
+								get_cartridge_type(&current_rom_path),
+								gba.bus.io_regs.get_input_latching(),
+								ram_init,
+								ab_option,
+							));
+						}
+					}
+
+					if let Some(path) = dropped_rom_path.clone() {
+						let mut load = false;
+						let mut cancel = false;
+						Window::new(im_str!("Load ROM")).always_auto_resize(true).collapsible(false).build(&ui, || {
+							ui.text(format!("Load {}?", path.display()));
+							ui.text("The current game will be reset and its unsaved progress lost.");
+							load = ui.button(im_str!("Load"), [0.0, 0.0]);
+							ui.same_line(0.0);
+							cancel = ui.button(im_str!("Cancel"), [0.0, 0.0]);
+						});
+
+						if load {
+							match load_rom_file(&path) {
+								Ok(data) => {
+									if let Err(e) = gba.bus.flush_backup() {
+										eprintln!("Failed to save the save memory: {}", e);
+									}
+
+									gba = boot(&ab_bios_data, &data, &path, ram_init);
+									debugger.clear_history();
+									debugger.profiler.clear();
+									profile_path = get_profile_path(&data);
+									debugger.bookmarks_path = get_bookmarks_path(&data);
+									debugger.symbols = load_symbol_table(&debugger.bookmarks_path, &path);
+									pending_profile = Profile::load(&profile_path);
+									saved_profile = None;
+									compat_entry = compat_database.get(&get_game_code(&data)).cloned();
+									backup_snapshot_path = get_backup_snapshot_path(&data);
+									save_state_path = get_save_state_path(&data);
+									backup_before_import = None;
+									backup_message = None;
+									ab_cartridge_data = data;
+									ab_comparison = None;
+									current_rom_path = path.clone();
+									if let Some(file_name) = path.file_name() {
+										display.gl_window().window().set_title(&file_name.to_string_lossy());
+									}
+								}
+								Err(e) => eprintln!("Failed to load {}: {}", path.display(), e),
+							}
+						}
+						if load || cancel {
+							dropped_rom_path = None;
 						}
 					}
 
@@ -373,11 +1010,62 @@ fn main() {
 					let draw_data = ui.render();
 					renderer.render(&mut target, draw_data).expect("Rendering failed");
 					target.finish().expect("Failed to swap buffers");
+					let speed_meter = gba.get_mut_speed_meter();
+					speed_meter.add_time(ESubsystem::Rendering, rendering_time);
+					speed_meter.add_time(ESubsystem::Interface, redraw_start.elapsed().saturating_sub(rendering_time));
+
+					// NOTE: Keep the profile of the game up to date
+					let mut layout = String::new();
+					imgui.save_ini_settings(&mut layout);
+					let windows = [
+						("CPU", show_cpu_debug_window),
+						("Memory", show_memory_debug_window),
+						("Hex Editor", show_hex_editor_window),
+						("Breakpoints", show_breakpoints_window),
+						("Call Stack", show_callstack_window),
+						("I/O Registers", show_io_registers_window),
+						("Tiles", show_tiles_window),
+						("Sprites", show_sprites_window),
+						("BG Maps", show_bg_map_window),
+						("Palettes", show_palette_window),
+						("PPU Stats", show_ppu_stats_window),
+						("Profiler", show_profiler_window),
+						("Health", show_health_window),
+						("A/B Compare", show_ab_compare_window),
+						("Cheats", show_cheats_window),
+						("RAM Search", show_ram_search_window),
+					];
+					let profile = Profile {
+						layout,
+						windows: windows.iter().map(|(name, open)| (name.to_string(), *open)).collect(),
+						breakpoints: debugger.breakpoints.get_breakpoints().to_vec(),
+						cheats: cheats.get_cheats().to_vec(),
+						rotation,
+						color_filter,
+						frame_blend_weight: frame_blender.get_weight(),
+						refresh_rate,
+						gyro_keys,
+					};
+					if saved_profile.as_ref() != Some(&profile) {
+						if let Err(e) = profile.save(&profile_path) {
+							eprintln!("Failed to save the profile to {}: {}", profile_path.display(), e);
+						}
+						saved_profile = Some(profile);
+					}
 				}
 				Event::WindowEvent {
 					event: WindowEvent::CloseRequested,
 					..
-				} => *control_flow = ControlFlow::Exit,
+				} => {
+					if let Err(e) = gba.bus.flush_backup() {
+						eprintln!("Failed to save the save memory: {}", e);
+					}
+					*control_flow = ControlFlow::Exit;
+				}
+				Event::WindowEvent {
+					event: WindowEvent::DroppedFile(path),
+					..
+				} if is_rom_file(&path) => dropped_rom_path = Some(path),
 				Event::WindowEvent {
 					event: WindowEvent::KeyboardInput { input, .. },
 					..
@@ -385,17 +1073,28 @@ fn main() {
 					if !imgui.io().want_capture_keyboard {
 						let released = input.state == ElementState::Released;
 						if let Some(key_code) = input.virtual_keycode {
-							match key_code {
-								VirtualKeyCode::A => bus.io_regs.get_mut_key_input().set_button_a(released),
-								VirtualKeyCode::S => bus.io_regs.get_mut_key_input().set_button_b(released),
-								VirtualKeyCode::Z => bus.io_regs.get_mut_key_input().set_select(released),
-								VirtualKeyCode::X => bus.io_regs.get_mut_key_input().set_start(released),
-								VirtualKeyCode::Right => bus.io_regs.get_mut_key_input().set_right(released),
-								VirtualKeyCode::Left => bus.io_regs.get_mut_key_input().set_left(released),
-								VirtualKeyCode::Up => bus.io_regs.get_mut_key_input().set_up(released),
-								VirtualKeyCode::Down => bus.io_regs.get_mut_key_input().set_down(released),
-								VirtualKeyCode::LShift => bus.io_regs.get_mut_key_input().set_button_l(released),
-								VirtualKeyCode::LAlt => bus.io_regs.get_mut_key_input().set_button_r(released),
+							match rotation.remap_direction(key_code) {
+								VirtualKeyCode::F5 if !released => save_state = true,
+								VirtualKeyCode::F6 if !released => export_backup = true,
+								VirtualKeyCode::F7 if !released => import_backup = true,
+								VirtualKeyCode::F8 if !released => load_state = true,
+								VirtualKeyCode::Back => rewinding = !released,
+								VirtualKeyCode::A => gba.bus.io_regs.get_mut_key_input().set_button_a(released),
+								VirtualKeyCode::S => gba.bus.io_regs.get_mut_key_input().set_button_b(released),
+								VirtualKeyCode::Z => gba.bus.io_regs.get_mut_key_input().set_select(released),
+								VirtualKeyCode::X => gba.bus.io_regs.get_mut_key_input().set_start(released),
+								VirtualKeyCode::Right => gba.bus.io_regs.get_mut_key_input().set_right(released),
+								VirtualKeyCode::Left => gba.bus.io_regs.get_mut_key_input().set_left(released),
+								VirtualKeyCode::Up => gba.bus.io_regs.get_mut_key_input().set_up(released),
+								VirtualKeyCode::Down => gba.bus.io_regs.get_mut_key_input().set_down(released),
+								VirtualKeyCode::LShift => gba.bus.io_regs.get_mut_key_input().set_button_l(released),
+								VirtualKeyCode::LAlt => gba.bus.io_regs.get_mut_key_input().set_button_r(released),
+								key_code if gyro_keys.contains(&key_code) => {
+									gyro_keys_held[gyro_keys.iter().position(|key| *key == key_code).unwrap()] = !released;
+									if let Some(sensor) = gba.bus.get_mut_cartridge().get_mut_gpio().and_then(|gpio| gpio.get_mut_gyro_sensor()) {
+										sensor.set_rotation(gyro_keys_held[1] as i32 as f32 - gyro_keys_held[0] as i32 as f32);
+									}
+								}
 								_ => {}
 							}
 						}
@@ -405,6 +1104,69 @@ fn main() {
 			}
 		});
 	} else {
-		println!("Cartridge couldn't be read!");
+		eprintln!("Failed to read {}", rom_path.display());
+	}
+}
+
+/// Bookmarks of the game, and the symbols of the .elf next to the ROM if there is one
+fn load_symbol_table(bookmarks_path: &Path, rom_path: &Path) -> SymbolTable {
+	let mut symbol_table = SymbolTable::new();
+	if let Err(e) = symbol_table.load_bookmarks(bookmarks_path) {
+		eprintln!("Failed to load the bookmarks from {}: {}", bookmarks_path.display(), e);
+	}
+
+	let elf_path = rom_path.with_extension("elf");
+	if let Ok(data) = std::fs::read(&elf_path) {
+		if let Err(e) = symbol_table.load_elf_symbols(&data) {
+			eprintln!("Failed to load the symbols of {}: {}", elf_path.display(), e);
+		}
+	}
+
+	// NOTE: ELF symbols win over the .sym ones with the same name
+	let sym_path = rom_path.with_extension("sym");
+	if let Ok(text) = std::fs::read_to_string(&sym_path) {
+		if let Err(e) = symbol_table.load_sym_symbols(&text) {
+			eprintln!("Failed to load the symbols of {}: {}", sym_path.display(), e);
+		}
+	}
+
+	symbol_table
+}
+
+/// Start the system from scratch as if the console was just turned on, with the save memory of the ROM loaded from disk
+fn boot(bios_data: &[u8], rom: &[u8], rom_path: &Path, ram_init: ERamInit) -> Gba {
+	let cartridge = Cartridge::new_with_type(rom.to_vec().into_boxed_slice(), get_cartridge_type(rom_path));
+	let mut gba = Gba::new_with_bus(SystemBus::new_with_ram_init(bios_data.to_vec().into_boxed_slice(), cartridge, ram_init));
+	if let Err(e) = gba.bus.load_backup(&get_save_path(rom_path)) {
+		eprintln!("Failed to load the save memory of {}: {}", rom_path.display(), e);
+	}
+	gba.set_rewind_enabled(true);
+	gba
+}
+
+/// Upload a rendered frame (RGB triplets) as an imgui texture, the texture of the previous frame is replaced instead of piling up a new one every frame
+fn update_frame_texture(
+	display: &glium::Display,
+	renderer: &mut imgui_glium_renderer::Renderer,
+	texture_id: &mut Option<TextureId>,
+	pixels: Vec<f32>,
+	(width, height): (usize, usize),
+) -> TextureId {
+	let image = glium::texture::RawImage2d::from_raw_rgb(pixels, (width as u32, height as u32));
+	let gl_texture = glium::texture::Texture2d::new(display, image).unwrap();
+
+	let texture = imgui_glium_renderer::Texture {
+		texture: Rc::new(gl_texture),
+		sampler: SamplerBehavior {
+			wrap_function: (SamplerWrapFunction::BorderClamp, SamplerWrapFunction::BorderClamp, SamplerWrapFunction::BorderClamp),
+			..Default::default()
+		},
+	};
+	match *texture_id {
+		Some(id) => {
+			renderer.textures().replace(id, texture);
+			id
+		}
+		None => *texture_id.insert(renderer.textures().insert(texture)),
 	}
 }
@@ -0,0 +1,124 @@
+use crate::ppu::tiles::{decode_tile_pixel, get_palette_index, ETileFormat, TILE_WIDTH};
+use crate::ppu::{BackgroundMap, EVideoMode, PPU};
+use crate::system::{MemoryInterface, VRAM_ADDR};
+
+/// Map entry of a tile of a BG, as shown by the debugger
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BgMapTile {
+	pub tile_number: usize,
+	/// None for 256 color tiles
+	pub palette_number: Option<usize>,
+	pub h_flip: bool,
+	pub v_flip: bool,
+}
+
+/// Offset of the map entry of a tile of a regular BG, in entries from the map data address
+/// NOTE: The map is made of 32x32 screen blocks, laid out left to right then top to bottom
+pub(crate) fn get_regular_map_offset(width: usize, tx: usize, ty: usize) -> usize {
+	let screen_block = tx / 32 + ty / 32 * (width / 256);
+	tx % 32 + (ty % 32) * 32 + screen_block * 0x400
+}
+
+/// Format of the tiles of affine BGs, the color mode bit of BGxCNT is ignored
+pub(crate) const AFFINE_BG_TILE_FORMAT: ETileFormat = ETileFormat::Bpp8;
+
+/// Offset of the map entry of a tile of an affine BG, in bytes from the map data address
+/// NOTE: Affine maps are square with one byte per entry, the tile number
+pub(crate) fn get_affine_map_offset(width: usize, tx: usize, ty: usize) -> usize {
+	tx + ty * (width / TILE_WIDTH)
+}
+
+impl PPU {
+	/// Whether a BG is affine, None if it isn't a tiled BG in the current video mode
+	pub fn is_bg_affine(&self, bg: usize) -> Option<bool> {
+		match (self.disp_cnt.get_bg_mode()?, bg) {
+			(EVideoMode::Mode0, 0..=3) | (EVideoMode::Mode1, 0..=1) => Some(false),
+			(EVideoMode::Mode1, 2) | (EVideoMode::Mode2, 2..=3) => Some(true),
+			_ => None,
+		}
+	}
+
+	/// Size of the whole map of a tiled BG in pixels
+	pub fn get_bg_map_size(&self, bg: usize) -> (usize, usize) {
+		let size = self.get_bg_cnt(bg).get_size();
+		match self.is_bg_affine(bg) {
+			Some(true) => (128 << size, 128 << size),
+			_ => (256 << (size & 0x1), 256 << (size >> 1)),
+		}
+	}
+
+	pub fn get_bg_map_tile(&self, bg: usize, tx: usize, ty: usize) -> BgMapTile {
+		let bg_cnt = self.get_bg_cnt(bg);
+		let (width, _) = self.get_bg_map_size(bg);
+		if self.is_bg_affine(bg) == Some(true) {
+			return BgMapTile {
+				tile_number: self.read_bg_vram(bg_cnt.get_map_data_address() + get_affine_map_offset(width, tx, ty)) as usize,
+				palette_number: None,
+				h_flip: false,
+				v_flip: false,
+			};
+		}
+
+		let address = bg_cnt.get_map_data_address() + get_regular_map_offset(width, tx, ty) * 2;
+		let bg_map = BackgroundMap(self.read_16(VRAM_ADDR + address as u32));
+		BgMapTile {
+			tile_number: bg_map.get_tile_number(),
+			palette_number: Some(bg_map.get_palette_number()).filter(|_| !bg_cnt.get_is_256_palette()),
+			h_flip: bg_map.get_h_flip(),
+			v_flip: bg_map.get_v_flip(),
+		}
+	}
+
+	/// Whole map of a tiled BG as RGB triplets, transparent pixels show the backdrop color
+	pub fn render_bg_map(&self, bg: usize) -> Vec<f32> {
+		let (width, height) = self.get_bg_map_size(bg);
+		let bg_cnt = self.get_bg_cnt(bg);
+		let format = match self.is_bg_affine(bg) {
+			Some(true) => AFFINE_BG_TILE_FORMAT,
+			_ => ETileFormat::from_is_256_palette(bg_cnt.get_is_256_palette()),
+		};
+
+		let mut pixels = vec![0.0; width * height * 3];
+		for ty in 0..height / TILE_WIDTH {
+			for tx in 0..width / TILE_WIDTH {
+				let tile = self.get_bg_map_tile(bg, tx, ty);
+				let tile_data = self.get_bg_tile(bg_cnt.get_tile_data_address() + tile.tile_number * format.get_tile_length(), format);
+				for y in 0..TILE_WIDTH {
+					for x in 0..TILE_WIDTH {
+						let tile_x = if tile.h_flip { TILE_WIDTH - 1 - x } else { x };
+						let tile_y = if tile.v_flip { TILE_WIDTH - 1 - y } else { y };
+						let index = decode_tile_pixel(tile_data, format, tile_x, tile_y);
+						let palette_index = get_palette_index(index, format, tile.palette_number.unwrap_or_default()).unwrap_or(0);
+						let color = self.palette_ram[palette_index];
+
+						let pixel_index = (tx * TILE_WIDTH + x + (ty * TILE_WIDTH + y) * width) * 3;
+						pixels[pixel_index] = color.get_red();
+						pixels[pixel_index + 1] = color.get_green();
+						pixels[pixel_index + 2] = color.get_blue();
+					}
+				}
+			}
+		}
+
+		pixels
+	}
+
+	/// Map coordinates of the corners of the screen (top left, top right, bottom right, bottom left), the visible part of a BG
+	/// NOTE: The scroll of regular BGs wraps around the map, the top left corner is inside it but the others can be past its size
+	pub fn get_bg_screen_corners(&self, bg: usize) -> [[i32; 2]; 4] {
+		let screen = [[0, 0], [240, 0], [240, 160], [0, 160]];
+		if self.is_bg_affine(bg) == Some(true) {
+			let matrix = self.get_bg_affine_matrix(bg - 2);
+			screen.map(|[x, y]| {
+				[
+					(matrix.get_x().get_value() + matrix.get_pa().get_value() * x + matrix.get_pb().get_value() * y) >> 8,
+					(matrix.get_y().get_value() + matrix.get_pc().get_value() * x + matrix.get_pd().get_value() * y) >> 8,
+				]
+			})
+		} else {
+			let (width, height) = self.get_bg_map_size(bg);
+			let (x, y) = (self.get_bg_hofs(bg) as i32 % width as i32, self.get_bg_vofs(bg) as i32 % height as i32);
+			screen.map(|[screen_x, screen_y]| [x + screen_x, y + screen_y])
+		}
+	}
+}
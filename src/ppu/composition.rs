@@ -0,0 +1,191 @@
+use bitfield::Bit;
+
+use crate::ppu::{BlendAlpha, BlendControl, EBlendMode, ELayer, ESpriteMode};
+
+/// A color in the 15 bit BGR format of the palettes and bitmaps
+pub type Rgb15 = u16;
+
+/// Bit of the window masks enabling the color effects
+pub const EFFECTS_WINDOW_BIT: usize = 5;
+/// The backdrop is behind every layer, whatever their priority
+const BACKDROP_DEPTH: u8 = u8::MAX;
+
+/// A non transparent pixel of a layer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LayerPixel {
+	pub layer: ELayer,
+	pub priority: u8,
+	pub color: Rgb15,
+	/// Mode of the sprite an OBJ pixel comes from, Normal for the other layers
+	pub sprite_mode: ESpriteMode,
+}
+
+impl LayerPixel {
+	pub fn new(layer: ELayer, priority: u8, color: Rgb15) -> Self {
+		Self {
+			layer,
+			priority,
+			color,
+			sprite_mode: ESpriteMode::Normal,
+		}
+	}
+
+	pub fn new_obj(priority: u8, color: Rgb15, sprite_mode: ESpriteMode) -> Self {
+		Self {
+			layer: ELayer::Obj,
+			priority,
+			color,
+			sprite_mode,
+		}
+	}
+
+	pub fn backdrop(color: Rgb15) -> Self {
+		Self::new(ELayer::Backdrop, 0, color)
+	}
+
+	/// Depth of the pixel, smaller is in front
+	/// NOTE: Priority first (0 is in front), then OBJ in front of the BGs and a lower BG in front of a higher one
+	pub fn get_depth(&self) -> u8 {
+		let rank = match self.layer {
+			ELayer::Obj => 0,
+			ELayer::Bg0 => 1,
+			ELayer::Bg1 => 2,
+			ELayer::Bg2 => 3,
+			ELayer::Bg3 => 4,
+			ELayer::Backdrop => return BACKDROP_DEPTH,
+		};
+
+		(self.priority & 0x3) * 8 + rank
+	}
+}
+
+/// Everything the color of a screen pixel depends on, no PPU state is involved
+#[derive(Debug, Copy, Clone)]
+pub struct PixelInputs<'a> {
+	/// Non transparent pixels of the displayed layers at this position, in any order
+	pub layers: &'a [LayerPixel],
+	pub backdrop: Rgb15,
+	/// Layers (WININ/WINOUT bits: BG0-3, OBJ, color effects) enabled by the windows at this position
+	pub window_mask: u8,
+	pub blend_control: BlendControl,
+	pub blend_alpha: BlendAlpha,
+	/// BLDY coefficient
+	pub blend_brightness: u8,
+}
+
+/// The two front layers of a pixel, the second one is what alpha blending mixes the first with
+/// NOTE: The backdrop fills in for missing layers, OBJ window pixels and layers disabled by the windows never show up
+pub fn get_top_layers(inputs: &PixelInputs) -> [LayerPixel; 2] {
+	let backdrop = LayerPixel::backdrop(inputs.backdrop);
+	let mut top_layers = [backdrop, backdrop];
+	let visible_pixels = inputs
+		.layers
+		.iter()
+		.filter(|pixel| pixel.sprite_mode != ESpriteMode::ObjWindow && pixel.layer != ELayer::Backdrop && inputs.window_mask.bit(pixel.layer.get_blend_bit()));
+	for pixel in visible_pixels {
+		if pixel.get_depth() < top_layers[0].get_depth() {
+			top_layers = [*pixel, top_layers[0]];
+		} else if pixel.get_depth() < top_layers[1].get_depth() {
+			top_layers[1] = *pixel;
+		}
+	}
+
+	top_layers
+}
+
+/// Color of a screen pixel: the front layer, with the BLDCNT color effect if the windows enable it
+/// NOTE: Semi-transparent OBJs are blended with a second target below them whatever BLDCNT selects, otherwise the front layer has to be a first target
+/// and alpha blending also needs the layer right below to be a second target
+pub fn compose_pixel(inputs: &PixelInputs) -> Rgb15 {
+	let [top, below] = get_top_layers(inputs);
+	if !inputs.window_mask.bit(EFFECTS_WINDOW_BIT) {
+		return top.color;
+	}
+
+	let blend_control = &inputs.blend_control;
+	let is_below_second_target = blend_control.get_second_target(below.layer);
+	let blend_mode = if top.sprite_mode == ESpriteMode::SemiTransparent && is_below_second_target {
+		EBlendMode::AlphaBlending
+	} else if blend_control.get_first_target(top.layer) {
+		blend_control.get_blend_mode()
+	} else {
+		return top.color;
+	};
+
+	// NOTE: Coefficients above 16 act as 16
+	let eva = std::cmp::min(inputs.blend_alpha.get_alpha_a(), 16) as u16;
+	let evb = std::cmp::min(inputs.blend_alpha.get_alpha_b(), 16) as u16;
+	let evy = std::cmp::min(inputs.blend_brightness, 16) as u16;
+	match blend_mode {
+		EBlendMode::AlphaBlending if is_below_second_target => map_channels(top.color, below.color, |a, b| (a * eva + b * evb) >> 4),
+		EBlendMode::Lighten => map_channels(top.color, 0, |c, _| c + (((31 - c) * evy) >> 4)),
+		EBlendMode::Darken => map_channels(top.color, 0, |c, _| c - ((c * evy) >> 4)),
+		_ => top.color,
+	}
+}
+
+/// Combine the red, green and blue channels of two colors, results are clamped to 31
+fn map_channels(a: Rgb15, b: Rgb15, f: impl Fn(u16, u16) -> u16) -> Rgb15 {
+	(0..3).fold(0, |color, channel| {
+		let shift = channel * 5;
+		color | (std::cmp::min(f((a >> shift) & 0x1f, (b >> shift) & 0x1f), 31) << shift)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RED: Rgb15 = 0x001f;
+	const GREEN: Rgb15 = 0x03e0;
+	const BLUE: Rgb15 = 0x7c00;
+	const ALL_WINDOW_MASK: u8 = 0x3f;
+
+	fn compose(layers: &[LayerPixel], blend_control: u16, blend_alpha: u16, blend_brightness: u8) -> Rgb15 {
+		compose_pixel(&PixelInputs {
+			layers,
+			backdrop: BLUE,
+			window_mask: ALL_WINDOW_MASK,
+			blend_control: BlendControl(blend_control),
+			blend_alpha: BlendAlpha(blend_alpha),
+			blend_brightness,
+		})
+	}
+
+	#[test]
+	fn backdrop_sorts_last() {
+		let bg3 = LayerPixel::new(ELayer::Bg3, 3, RED);
+		assert!(LayerPixel::backdrop(BLUE).get_depth() > bg3.get_depth());
+		assert_eq!(compose(&[], 0x0, 0x0, 0), BLUE);
+		assert_eq!(compose(&[bg3], 0x0, 0x0, 0), RED);
+		// NOTE: A backdrop pixel among the layers is ignored, whatever its priority
+		assert_eq!(compose(&[LayerPixel::backdrop(GREEN), bg3], 0x0, 0x0, 0), RED);
+	}
+
+	#[test]
+	fn obj_window_never_draws() {
+		let obj_window = LayerPixel::new_obj(0, RED, ESpriteMode::ObjWindow);
+		assert_eq!(compose(&[obj_window], 0x0, 0x0, 0), BLUE);
+		assert_eq!(compose(&[obj_window, LayerPixel::new(ELayer::Bg0, 3, GREEN)], 0x0, 0x0, 0), GREEN);
+	}
+
+	#[test]
+	fn semi_transparent_obj_forces_alpha_blending() {
+		let layers = [LayerPixel::new_obj(0, RED, ESpriteMode::SemiTransparent), LayerPixel::new(ELayer::Bg0, 0, BLUE)];
+		// NOTE: No first target and no effect, or brightness increase, only BG0 as a second target
+		for blend_control in [0x0100, 0x0180] {
+			assert_eq!(compose(&layers, blend_control, 0x0808, 16), 0x3c0f, "BLDCNT {:#06x}", blend_control);
+		}
+		// NOTE: Not blended when the layer below isn't a second target
+		assert_eq!(compose(&layers, 0x0200, 0x0808, 16), RED);
+	}
+
+	#[test]
+	fn blend_coefficients_are_clamped_to_16() {
+		let layers = [LayerPixel::new(ELayer::Bg0, 0, 0x10), LayerPixel::new(ELayer::Bg1, 0, 0x10 << 5)];
+		// NOTE: Alpha blending BG0 on BG1 with EVA = EVB = 20, which act as 16
+		assert_eq!(compose(&layers, 0x0241, 0x1414, 0), 0x0210);
+		// NOTE: Darken with EVY = 20, which acts as 16
+		assert_eq!(compose(&layers, 0x00c1, 0x0, 20), 0x0);
+	}
+}
@@ -1,21 +1,44 @@
+use std::io::Result;
+
 use bitfield::*;
 use num_derive::*;
 use num_traits::FromPrimitive;
 
 use crate::arm7tdmi::sign_extend;
-use crate::system::MemoryInterface;
-use crate::system::{OAM_ADDR, PALETTE_RAM_ADDR, VRAM_ADDR};
+use crate::ppu::bg_map::{get_affine_map_offset, get_regular_map_offset, AFFINE_BG_TILE_FORMAT};
+use crate::ppu::composition::{compose_pixel, get_top_layers, LayerPixel, PixelInputs, Rgb15};
+use crate::ppu::tiles::{decode_tile_pixel, get_palette_index, ETileFormat, TILE_PIXELS};
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::system::{EAccessWidth, EMemoryRegion, MemoryInterface, SystemBus, VRAM_ADDR};
+
+pub(crate) mod bg_map;
+pub mod composition;
+pub mod tiles;
 
 pub const PPU_REGISTERS_END: u32 = 0x56;
 pub const SCREEN_TOTAL_PIXELS: usize = 38400;
 pub const SPRITE_TILES_START_ADDRESS: usize = 0x10000;
 pub const SPRITE_PALETTE_START_INDEX: usize = 0x100;
+/// In bitmap modes the frame buffers overlap the first half of OBJ VRAM, sprites can only use the tiles after it
+pub const BITMAP_MODE_FIRST_SPRITE_TILE: usize = 512;
 
 pub const PALETTE_RAM_SIZE: usize = 1024;
 pub const VRAM_SIZE: usize = 0x1_8000;
 pub const VRAM_MIRRORED_SIZE: usize = 0x2_0000;
 pub const OAM_SIZE: usize = 1024;
 
+const EMPTY_TILE: [u8; TILE_PIXELS] = [0; TILE_PIXELS];
+/// Cycles the PPU has to render the sprites of a scanline, fewer when OAM is left free for the CPU during H-Blank
+const OBJ_LINE_CYCLES: usize = 1210;
+const OBJ_LINE_CYCLES_H_BLANK_FREE: usize = 954;
+/// Window mask with every layer and the color effects enabled
+const ALL_LAYERS_MASK: u8 = 0x3f;
+/// DISPSTAT bits the CPU can write: the IRQ enables and the V-Count trigger
+/// NOTE: The status flags (bits 0-2) are read-only and bits 6-7 are unused
+const DISP_STAT_WRITE_MASK: u16 = 0xff38;
+/// DISPSTAT status flags, set by the PPU: V-Blank, H-Blank and V-Counter
+const DISP_STAT_FLAGS_MASK: u16 = 0x0007;
+
 // TODO: Add green swap
 pub const DISP_CNT_ADDRESS: u32 = 0x0;
 pub const DISP_STAT_ADDRESS: u32 = 0x4;
@@ -71,6 +94,13 @@ pub enum EVideoMode {
 	Mode5,
 }
 
+impl EVideoMode {
+	/// Modes 3-5 draw BG2 from a frame buffer instead of tiles
+	pub fn is_bitmap(self) -> bool {
+		matches!(self, EVideoMode::Mode3 | EVideoMode::Mode4 | EVideoMode::Mode5)
+	}
+}
+
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, PartialEq)]
 pub enum EBlendMode {
 	None,
@@ -167,6 +197,12 @@ impl WindowDimensions {
 	pub fn get_y2(&self) -> u8 {
 		self.v.bit_range(7, 0)
 	}
+
+	/// NOTE: A window whose start is past its end wraps around the screen
+	pub fn contains(&self, x: u8, y: u8) -> bool {
+		let in_range = |start: u8, end: u8, value: u8| if start <= end { value >= start && value < end } else { value >= start || value < end };
+		in_range(self.get_x1(), self.get_x2(), x) && in_range(self.get_y1(), self.get_y2(), y)
+	}
 }
 
 bitfield! {
@@ -193,6 +229,12 @@ bitfield! {
 }
 
 impl SpriteEntry {
+	/// Whether the tiles of the sprite can be displayed in a video mode
+	/// NOTE: Sprites using the tiles under BITMAP_MODE_FIRST_SPRITE_TILE aren't displayed in bitmap modes
+	pub fn is_tile_available(&self, video_mode: EVideoMode) -> bool {
+		!video_mode.is_bitmap() || self.get_tile_index() >= BITMAP_MODE_FIRST_SPRITE_TILE
+	}
+
 	pub fn get_y_coord(&self) -> i32 {
 		let y = self.raw_y_coord() as i32;
 		// NOTE: Check if it's wrapping!!!
@@ -203,8 +245,9 @@ impl SpriteEntry {
 		}
 	}
 
+	/// NOTE: The prohibited mode 3 is shown as a normal sprite
 	pub fn get_sprite_mode(&self) -> ESpriteMode {
-		FromPrimitive::from_u8(self.raw_sprite_mode()).unwrap()
+		FromPrimitive::from_u8(self.raw_sprite_mode()).unwrap_or(ESpriteMode::Normal)
 	}
 
 	pub fn get_size(&self) -> (usize, usize) {
@@ -228,12 +271,55 @@ impl SpriteEntry {
 	}
 
 	pub fn get_x_coord(&self) -> i32 {
-		sign_extend(self.raw_x_coord(), 9) as i32
+		sign_extend(self.raw_x_coord(), 9)
+	}
+
+	/// Size of the area the sprite covers on screen, twice its size for double-sized affine sprites
+	pub fn get_bounds_size(&self) -> (usize, usize) {
+		let (width, height) = self.get_size();
+		if self.get_is_affine() && self.get_is_virtual_double_sized() {
+			(width * 2, height * 2)
+		} else {
+			(width, height)
+		}
+	}
+
+	/// Regular sprites with the double size bit set aren't displayed
+	pub fn is_hidden(&self) -> bool {
+		!self.get_is_affine() && self.get_is_virtual_double_sized()
+	}
+
+	pub fn is_displayed(&self, video_mode: EVideoMode) -> bool {
+		!self.is_hidden() && self.is_tile_available(video_mode)
+	}
+
+	/// Row of the bounding box of the sprite on a scanline, None if the sprite isn't on it
+	/// NOTE: Y wraps around at 256, so sprites near the bottom edge also show up at the top
+	pub fn get_bounds_row(&self, line: u8) -> Option<usize> {
+		let row = line.wrapping_sub(self.raw_y_coord()) as usize;
+		Some(row).filter(|row| *row < self.get_bounds_size().1)
+	}
+
+	/// Cycles the PPU takes to render the sprite on each of its scanlines, whether it's on screen horizontally or not
+	pub fn get_render_cycles(&self) -> usize {
+		let (bounds_width, _) = self.get_bounds_size();
+		if self.get_is_affine() {
+			10 + bounds_width * 2
+		} else {
+			bounds_width
+		}
 	}
 }
 
-fn compute_vram_address(address: u32) -> usize {
-	let clamped_address = address as usize & (VRAM_MIRRORED_SIZE - 1);
+/// Offset inside VRAM of an access, 96KB of VRAM are mirrored in 128KB steps (the upper 32KB mirror the OBJ tiles)
+/// NOTE: The halfword/word containing the address is accessed, so accesses never straddle the mirror boundary
+fn compute_vram_address(address: u32, width: EAccessWidth) -> usize {
+	let aligned_address = match width {
+		EAccessWidth::Byte => address,
+		EAccessWidth::Halfword => address & !0x1,
+		EAccessWidth::Word => address & !0x3,
+	};
+	let clamped_address = aligned_address as usize & (VRAM_MIRRORED_SIZE - 1);
 	if (VRAM_SIZE..VRAM_MIRRORED_SIZE).contains(&clamped_address) {
 		clamped_address - (VRAM_MIRRORED_SIZE - VRAM_SIZE)
 	} else {
@@ -241,6 +327,38 @@ fn compute_vram_address(address: u32) -> usize {
 	}
 }
 
+/// Usage counters of the last rendered frame
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RenderStats {
+	/// Non transparent OBJ pixels drawn (overdraw included)
+	pub obj_pixels: usize,
+	/// Sprites with at least one pixel on screen
+	pub visible_sprites: usize,
+	/// Visible sprites using an affine transformation
+	pub affine_sprites: usize,
+	/// Distinct BG and OBJ tiles sampled
+	pub unique_tiles: usize,
+}
+
+/// Rendering overrides to tell which layer produces each pixel, they don't affect emulation
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct RenderDebugOptions {
+	/// Draw the backdrop with this color (RGB) instead of the first palette entry
+	pub backdrop_override: Option<[f32; 3]>,
+	/// Mix every pixel of the final frame with the tint color of the layer that produced it
+	pub tint_layers: bool,
+	/// Replace every pixel with the tint color of the layer that won the composition, shows priority mistakes at a glance
+	pub layer_map: bool,
+}
+
+/// Write counters of the PPU memories, a region changed since it was last decoded if its counter changed
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DirtyCounters {
+	pub palette_ram: u32,
+	pub vram: u32,
+	pub oam: u32,
+}
+
 pub struct PPU {
 	// Registers
 	disp_cnt: DisplayControl,
@@ -261,8 +379,14 @@ pub struct PPU {
 
 	// Memory
 	pub palette_ram: Box<[Color]>,
+	// NOTE: Palette RAM as written by the CPU, palette_ram holds the decoded colors
+	raw_palette_ram: Box<[u16]>,
 	vram: Box<[u8]>,
 	oam: Box<[SpriteEntry]>,
+
+	render_stats: RenderStats,
+	dirty_counters: DirtyCounters,
+	render_debug_options: RenderDebugOptions,
 }
 
 impl PPU {
@@ -284,8 +408,13 @@ impl PPU {
 			bld_y: 0,
 
 			palette_ram: vec![Color::zeroed(); PALETTE_RAM_SIZE / 2].into_boxed_slice(),
+			raw_palette_ram: vec![0; PALETTE_RAM_SIZE / 2].into_boxed_slice(),
 			vram: vec![0; VRAM_SIZE].into_boxed_slice(),
 			oam: vec![SpriteEntry(0); OAM_SIZE / 8].into_boxed_slice(),
+
+			render_stats: RenderStats::default(),
+			dirty_counters: DirtyCounters::default(),
+			render_debug_options: RenderDebugOptions::default(),
 		}
 	}
 
@@ -305,6 +434,12 @@ impl PPU {
 		self.v_count = value
 	}
 
+	/// CPU write to DISPSTAT, the status flags are kept
+	/// NOTE: A V-Count trigger above 227 never matches
+	fn write_disp_stat(&mut self, value: u16) {
+		self.disp_stat.0 = (self.disp_stat.0 & DISP_STAT_FLAGS_MASK) | (value & DISP_STAT_WRITE_MASK);
+	}
+
 	fn get_bg_cnt(&self, index: usize) -> &BackgroundControl {
 		&self.bg_controls[index]
 	}
@@ -334,10 +469,6 @@ impl PPU {
 		&self.win_out
 	}
 
-	fn get_mosaic(&self) -> &Mosaic {
-		&self.mosaic
-	}
-
 	fn get_blend_control(&self) -> &BlendControl {
 		&self.bld_cnt
 	}
@@ -347,8 +478,9 @@ impl PPU {
 	}
 
 	/// Brightness (Fade-In/Out) Coefficient (W)
+	/// NOTE: Values above 16 act as 16
 	fn get_blend_brightness(&self) -> u8 {
-		self.bld_y.bit_range(3, 0)
+		std::cmp::min(self.bld_y.bit_range(4, 0), 16)
 	}
 
 	/// Get all the colors currently in Paletter RAM
@@ -361,6 +493,133 @@ impl PPU {
 		&self.oam
 	}
 
+	/// Palette RAM as seen by the CPU (BGR555 halfwords)
+	pub fn get_raw_palette_ram(&self) -> &[u16] {
+		&self.raw_palette_ram
+	}
+
+	pub fn get_vram(&self) -> &[u8] {
+		&self.vram
+	}
+
+	/// OAM as seen by the CPU
+	pub fn get_raw_oam(&self) -> &[u8] {
+		unsafe { std::slice::from_raw_parts(self.oam.as_ptr() as *const u8, OAM_SIZE) }
+	}
+
+	/// Write counters of Palette RAM, VRAM and OAM, so debug tools only decode them again when they changed
+	pub fn get_dirty_counters(&self) -> &DirtyCounters {
+		&self.dirty_counters
+	}
+
+	/// Read map or tile data for a tiled BG
+	/// NOTE: BGs can't reach OBJ VRAM, data past the end of BG VRAM (eg. high tile numbers in the last character blocks) reads as 0 (transparent)
+	fn read_bg_vram(&self, address: usize) -> u8 {
+		if address < SPRITE_TILES_START_ADDRESS {
+			self.vram[address]
+		} else {
+			0
+		}
+	}
+
+	/// Tile data for a tiled BG, tiles past the end of BG VRAM are empty like in read_bg_vram
+	fn get_bg_tile(&self, address: usize, format: ETileFormat) -> &[u8] {
+		let end = address + format.get_tile_length();
+		if end <= SPRITE_TILES_START_ADDRESS {
+			&self.vram[address..end]
+		} else {
+			&EMPTY_TILE[..format.get_tile_length()]
+		}
+	}
+
+	/// Tile data for a sprite, tiles past the end of OBJ VRAM are empty
+	pub fn get_obj_tile(&self, address: usize, format: ETileFormat) -> &[u8] {
+		let end = address + format.get_tile_length();
+		if end <= VRAM_SIZE {
+			&self.vram[address..end]
+		} else {
+			&EMPTY_TILE[..format.get_tile_length()]
+		}
+	}
+
+	fn set_palette_entry(&mut self, index: usize, value: u16) {
+		self.raw_palette_ram[index] = value;
+		self.palette_ram[index] = Color::new(value);
+		self.dirty_counters.palette_ram = self.dirty_counters.palette_ram.wrapping_add(1);
+	}
+
+	/// Replace the whole VRAM content
+	pub fn load_vram(&mut self, data: &[u8]) {
+		self.vram.copy_from_slice(data);
+		self.mark_vram_dirty();
+	}
+
+	fn mark_vram_dirty(&mut self) {
+		self.dirty_counters.vram = self.dirty_counters.vram.wrapping_add(1);
+	}
+
+	fn mark_oam_dirty(&mut self) {
+		self.dirty_counters.oam = self.dirty_counters.oam.wrapping_add(1);
+	}
+
+	/// Fast path for bulk (eg. DMA) transfers into Palette RAM, VRAM or OAM
+	/// Halfwords are copied straight into the backing memory instead of being dispatched one at a time
+	/// NOTE: Transfers wrap around inside the region of the starting address
+	pub fn write_halfwords(&mut self, address: u32, data: &[u16]) {
+		let address = address & !0x1;
+		match SystemBus::resolve(address).0 {
+			EMemoryRegion::PaletteRam => {
+				let start_index = (address as usize & 0x3ff) / 2;
+				for (i, value) in data.iter().enumerate() {
+					self.set_palette_entry((start_index + i) % (PALETTE_RAM_SIZE / 2), *value);
+				}
+			}
+			EMemoryRegion::Vram => {
+				self.mark_vram_dirty();
+				let mut remaining = data;
+				let mut current_address = address;
+				while !remaining.is_empty() {
+					// NOTE: Copy in runs that end where VRAM mirrors back
+					let offset = compute_vram_address(current_address, EAccessWidth::Halfword);
+					let count = std::cmp::min(remaining.len(), (VRAM_SIZE - offset) / 2);
+					for (destination, value) in self.vram[offset..offset + count * 2].chunks_exact_mut(2).zip(remaining) {
+						destination.copy_from_slice(&value.to_le_bytes());
+					}
+
+					remaining = &remaining[count..];
+					current_address = current_address.wrapping_add(count as u32 * 2);
+				}
+			}
+			EMemoryRegion::Oam => {
+				self.mark_oam_dirty();
+				let start_offset = address as usize & 0x3ff;
+				for (i, value) in data.iter().enumerate() {
+					unsafe {
+						*((self.oam.as_ptr() as *mut u8).add((start_offset + i * 2) & 0x3ff) as *mut u16) = *value;
+					}
+				}
+			}
+			_ => {
+				for (i, value) in data.iter().enumerate() {
+					self.write_16(address.wrapping_add(i as u32 * 2), *value);
+				}
+			}
+		}
+	}
+
+	pub fn get_render_debug_options(&self) -> &RenderDebugOptions {
+		&self.render_debug_options
+	}
+
+	pub fn get_mut_render_debug_options(&mut self) -> &mut RenderDebugOptions {
+		&mut self.render_debug_options
+	}
+
+	/// Usage counters of the last rendered frame
+	pub fn get_render_stats(&self) -> &RenderStats {
+		&self.render_stats
+	}
+
 	/// Calculate PPU status based on provided cycle
 	/// Returns (h_blank_irq, v_blank_irq)
 	pub fn step(&mut self, current_cycle: u32) -> (bool, bool) {
@@ -383,7 +642,7 @@ impl PPU {
 		} else if current_cycle % 1232 == 0 {
 			// H-Blank end
 			self.disp_stat.set_h_blank(false);
-		} else if current_cycle.wrapping_sub(960) % 1232 == 0 {
+		} else if current_cycle % 1232 == 960 {
 			// H-Blank
 			self.disp_stat.set_h_blank(true);
 			return (true, false);
@@ -394,14 +653,19 @@ impl PPU {
 
 	pub fn render(&mut self) -> Vec<f32> {
 		let mut pixels: Vec<f32>;
+		let mut stats = RenderStats::default();
+		// NOTE: Tiles are tracked by their 32 byte slot in VRAM
+		let mut used_tiles = vec![false; VRAM_SIZE / 32];
 		if !self.get_disp_cnt().get_forced_blank() {
-			let backdrop_color = &self.palette_ram[0];
-			pixels = [backdrop_color.get_red(), backdrop_color.get_green(), backdrop_color.get_blue()]
-				.iter()
-				.cloned()
-				.cycle()
-				.take(SCREEN_TOTAL_PIXELS * 3)
-				.collect();
+			let backdrop = match self.render_debug_options.backdrop_override {
+				Some(color) => to_rgb15(color),
+				None => self.raw_palette_ram[0],
+			};
+			let window_masks = match self.disp_cnt.get_bg_mode() {
+				Some(video_mode) => self.compute_window_masks(video_mode),
+				None => vec![ALL_LAYERS_MASK; SCREEN_TOTAL_PIXELS],
+			};
+			let mut composition = Composition::new(window_masks);
 
 			if let Some(video_mode) = self.disp_cnt.get_bg_mode() {
 				match video_mode {
@@ -412,11 +676,11 @@ impl PPU {
 							if self.disp_cnt.get_screen_display_bg(i) {
 								let bg_cnt = self.get_bg_cnt(i);
 								if i >= 2 && video_mode == EVideoMode::Mode1 || video_mode == EVideoMode::Mode2 {
-									let (bg_tiles, bg_size) = match bg_cnt.get_size() {
-										0x0 => (16, 128),
-										0x1 => (32, 256),
-										0x2 => (64, 512),
-										0x3 => (128, 1024),
+									let bg_size = match bg_cnt.get_size() {
+										0x0 => 128,
+										0x1 => 256,
+										0x2 => 512,
+										0x3 => 1024,
 										_ => {
 											panic!("IMPOSSIBLE!")
 										}
@@ -444,21 +708,19 @@ impl PPU {
 
 											let pixel_index = (screen_x as usize + (screen_y as usize * 240)) * 3;
 
-											let tx = pixel_x / 8;
-											let ty = pixel_y / 8;
-											let tile = (tx + ty * bg_tiles) as usize;
-											let tile_number = self.vram[bg_cnt.get_map_data_address() + tile] as usize;
+											let tile = get_affine_map_offset(bg_size as usize, pixel_x as usize / 8, pixel_y as usize / 8);
+											let tile_number = self.read_bg_vram(bg_cnt.get_map_data_address() + tile) as usize;
 
-											let tile_pixel = ((pixel_x % 8) + (pixel_y % 8) * 8) as usize;
-											let tile_address = bg_cnt.get_tile_data_address() + (tile_number * 64);
-											let palette_entry = self.vram[tile_address + tile_pixel] as usize;
+											// NOTE: The color mode bit of BGxCNT is ignored, affine BGs always use 8bpp tiles
+											let tile_address = bg_cnt.get_tile_data_address() + (tile_number * AFFINE_BG_TILE_FORMAT.get_tile_length());
+											used_tiles[(tile_address / 32) % (VRAM_SIZE / 32)] = true;
+											let tile_data = self.get_bg_tile(tile_address, AFFINE_BG_TILE_FORMAT);
+											let index = decode_tile_pixel(tile_data, AFFINE_BG_TILE_FORMAT, (pixel_x % 8) as usize, (pixel_y % 8) as usize);
 
-											if palette_entry != 0 {
-												let color = self.palette_ram[palette_entry];
+											if let Some(palette_index) = get_palette_index(index, AFFINE_BG_TILE_FORMAT, 0) {
+												let color = self.raw_palette_ram[palette_index];
 
-												pixels[pixel_index] = color.get_red();
-												pixels[pixel_index + 1] = color.get_green();
-												pixels[pixel_index + 2] = color.get_blue();
+												composition.draw(pixel_index / 3, LayerPixel::new(ELayer::from_bg(i), bg_cnt.get_bg_priority(), color));
 											}
 										}
 									}
@@ -484,40 +746,22 @@ impl PPU {
 
 											let pixel_index = (screen_x as usize + (screen_y as usize * 240)) * 3;
 
-											let tx = pixel_x as usize / 8;
-											let ty = pixel_y as usize / 8;
-											let tile = tx % 32 + ((ty % 32) * 32) + ((tx / 32 + ty / 32 * 2) * 0x400);
+											let tile = get_regular_map_offset(width as usize, pixel_x as usize / 8, pixel_y as usize / 8);
 											let bg_map = BackgroundMap(self.read_16(VRAM_ADDR + (bg_cnt.get_map_data_address() + tile * 2) as u32));
 											let tile_number = bg_map.get_tile_number();
-											let h_flip = bg_map.get_h_flip();
-											let v_flip = bg_map.get_v_flip();
-
-											let tile_pixel = ((pixel_x % 8) + (pixel_y % 8) * 8) as usize;
-											if bg_cnt.get_is_256_palette() {
-												let tile_address = bg_cnt.get_tile_data_address() + (tile_number * 64);
-												let palette_entry = self.vram[tile_address + tile_pixel] as usize;
-
-												if palette_entry != 0 {
-													let color = self.palette_ram[palette_entry];
-
-													pixels[pixel_index] = color.get_red();
-													pixels[pixel_index + 1] = color.get_green();
-													pixels[pixel_index + 2] = color.get_blue();
-												}
-											} else {
-												let tile_address = bg_cnt.get_tile_data_address() + (tile_number * 32);
-												let palette_entry = self.vram[tile_address + tile_pixel / 2] as usize;
-
-												if palette_entry != 0 {
-													let palette_offset = bg_map.get_palette_number() * 16;
-													let palette_index = (palette_entry >> ((tile_pixel & 1) * 4)) & 0xf;
-													let color_address = palette_offset + palette_index;
-													let color = self.palette_ram[color_address];
-
-													pixels[pixel_index] = color.get_red();
-													pixels[pixel_index + 1] = color.get_green();
-													pixels[pixel_index + 2] = color.get_blue();
-												}
+											let tile_x = if bg_map.get_h_flip() { 7 - pixel_x % 8 } else { pixel_x % 8 };
+											let tile_y = if bg_map.get_v_flip() { 7 - pixel_y % 8 } else { pixel_y % 8 };
+
+											let format = ETileFormat::from_is_256_palette(bg_cnt.get_is_256_palette());
+											let tile_address = bg_cnt.get_tile_data_address() + (tile_number * format.get_tile_length());
+											used_tiles[(tile_address / 32) % (VRAM_SIZE / 32)] = true;
+											let tile_data = self.get_bg_tile(tile_address, format);
+											let index = decode_tile_pixel(tile_data, format, tile_x as usize, tile_y as usize);
+
+											if let Some(palette_index) = get_palette_index(index, format, bg_map.get_palette_number()) {
+												let color = self.raw_palette_ram[palette_index];
+
+												composition.draw(pixel_index / 3, LayerPixel::new(ELayer::from_bg(i), bg_cnt.get_bg_priority(), color));
 											}
 										}
 									}
@@ -525,8 +769,22 @@ impl PPU {
 							}
 						}
 					}
-					EVideoMode::Mode3 => {}
+					EVideoMode::Mode3 => {
+						let bg2_priority = self.get_bg_cnt(2).get_bg_priority();
+
+						// NOTE: A single 240x160 frame of direct 15 bit colors
+						for y in 0..160 {
+							for x in 0..240 {
+								let bitmap_index = x as usize + (y as usize * 240);
+								let address = bitmap_index * 2;
+								let color = u16::from_le_bytes([self.vram[address], self.vram[address + 1]]);
+
+								composition.draw(bitmap_index, LayerPixel::new(ELayer::Bg2, bg2_priority, color));
+							}
+						}
+					}
 					EVideoMode::Mode4 => {
+						let bg2_priority = self.get_bg_cnt(2).get_bg_priority();
 						let starting_address = if self.get_disp_cnt().get_display_frame_1() { 0xA000 } else { 0x0 };
 
 						for y in 0..160 {
@@ -535,111 +793,441 @@ impl PPU {
 								let pixel_index = bitmap_index * 3;
 								let palette_entry = self.vram[starting_address + bitmap_index] as usize;
 
-								let color = self.palette_ram[palette_entry];
+								let color = self.raw_palette_ram[palette_entry];
+
+								composition.draw(pixel_index / 3, LayerPixel::new(ELayer::Bg2, bg2_priority, color));
+							}
+						}
+					}
+					EVideoMode::Mode5 => {
+						let bg2_priority = self.get_bg_cnt(2).get_bg_priority();
+						let starting_address = if self.get_disp_cnt().get_display_frame_1() { 0xA000 } else { 0x0 };
+
+						// NOTE: Two 160x128 frames of direct 15 bit colors, the rest of the screen shows what is below BG2
+						for y in 0..128 {
+							for x in 0..160 {
+								let address = starting_address + (x as usize + (y as usize * 160)) * 2;
+								let color = u16::from_le_bytes([self.vram[address], self.vram[address + 1]]);
 
-								pixels[pixel_index] = color.get_red();
-								pixels[pixel_index + 1] = color.get_green();
-								pixels[pixel_index + 2] = color.get_blue();
+								composition.draw(x as usize + (y as usize * 240), LayerPixel::new(ELayer::Bg2, bg2_priority, color));
 							}
 						}
 					}
-					EVideoMode::Mode5 => {}
 				}
 
 				// Sprites
 				if self.get_disp_cnt().get_screen_display_sprites() {
-					let is_1d_mapping = self.get_disp_cnt().get_sprite_1d_mapping();
-					// Reverse sprites for priority order (Sprite 0 = Front, Last Sprite = back)
-					let sprites = self.oam.iter().rev();
-					for sprite in sprites.filter(|s| s.get_is_affine() || !s.get_is_virtual_double_sized()) {
-						let (width, height) = sprite.get_size();
-						let tiles_per_row = if sprite.get_is_256_palette() { 16 } else { 32 };
-						let tile_length = if sprite.get_is_256_palette() { 64 } else { 32 };
-						let start_tile_address = SPRITE_TILES_START_ADDRESS + sprite.get_tile_index() as usize * 32;
-
-						let pixel_x0 = (width / 2) as i32;
-						let pixel_y0 = (height / 2) as i32;
-
-						let half_width = if sprite.get_is_virtual_double_sized() { width as i32 } else { pixel_x0 };
-						let half_height = if sprite.get_is_virtual_double_sized() { height as i32 } else { pixel_y0 };
-
-						for y in -half_height..half_height {
-							for x in -half_width..half_width {
-								let pixel_x;
-								let pixel_y;
-								if sprite.get_is_affine() {
-									let affine_matrix_starting_sprite = sprite.get_affine_matrix_index() * 4;
-									let pa = self.oam[affine_matrix_starting_sprite].get_affine_data().get_value();
-									let pb = self.oam[affine_matrix_starting_sprite + 1].get_affine_data().get_value();
-									let pc = self.oam[affine_matrix_starting_sprite + 2].get_affine_data().get_value();
-									let pd = self.oam[affine_matrix_starting_sprite + 3].get_affine_data().get_value();
-
-									pixel_x = pixel_x0 + ((pa * x + pb * y) >> 8);
-									pixel_y = pixel_y0 + ((pc * x + pd * y) >> 8);
-								} else {
-									pixel_x = pixel_x0 + x;
-									pixel_y = pixel_y0 + y;
-								}
+					// NOTE: Sprites are resolved among themselves first, the one with the lowest OAM index wins even over sprites with a better priority
+					let mut obj_pixels: Vec<Option<LayerPixel>> = vec![None; SCREEN_TOTAL_PIXELS];
+					let line_limits = self.compute_sprite_line_limits(video_mode);
+					// NOTE: OBJ window sprites aren't drawn, they only shape the OBJ window
+					for (oam_index, sprite) in self.get_displayed_sprites(video_mode).filter(|(_, sprite)| sprite.get_sprite_mode() != ESpriteMode::ObjWindow) {
+						let mut is_visible = false;
+						self.for_each_sprite_pixel(oam_index, sprite, &line_limits, |index, tile_address, palette_index| {
+							is_visible = true;
+							used_tiles[(tile_address / 32) % (VRAM_SIZE / 32)] = true;
+
+							if let Some(palette_index) = palette_index {
+								obj_pixels[index] = Some(LayerPixel::new_obj(
+									sprite.get_priority(),
+									self.raw_palette_ram[SPRITE_PALETTE_START_INDEX + palette_index],
+									sprite.get_sprite_mode(),
+								));
+							}
+						});
 
-								// NOTE: These values wrap around
-								let screen_x = sprite.get_x_coord() + half_width + x;
-								let screen_y = sprite.get_y_coord() + half_height + y;
-
-								// Y has range -127/127 (within 160 vertical screen size)
-								if screen_x >= 0
-									&& screen_y >= 0 && screen_x < 240 && screen_y < 160
-									&& pixel_x >= 0 && pixel_x < width as i32
-									&& pixel_y >= 0 && pixel_y < height as i32
-								{
-									let pixel_index = (screen_x as usize + (screen_y as usize * 240)) * 3;
-
-									let tx = pixel_x as usize / 8;
-									let ty = pixel_y as usize / 8;
-									let tile_address = if is_1d_mapping {
-										let tile = tx + ty * (width / 8);
-										start_tile_address + tile * tile_length
-									} else {
-										let tile = tx + ty * tiles_per_row;
-										start_tile_address + tile * tile_length
-									};
+						if is_visible {
+							stats.visible_sprites += 1;
+							if sprite.get_is_affine() {
+								stats.affine_sprites += 1;
+							}
+						}
+					}
 
-									let tile_pixel = ((pixel_x % 8) + (pixel_y % 8) * 8) as usize;
-									if sprite.get_is_256_palette() {
-										let palette_entry = self.vram[tile_address + tile_pixel] as usize;
+					for (index, pixel) in obj_pixels.iter().enumerate().filter_map(|(index, pixel)| pixel.map(|pixel| (index, pixel))) {
+						if composition.draw(index, pixel) {
+							stats.obj_pixels += 1;
+						}
+					}
+				}
+			}
 
-										if palette_entry != 0 {
-											let color = self.palette_ram[SPRITE_PALETTE_START_INDEX + palette_entry];
+			let (composed_pixels, layers) = composition.compose(backdrop, *self.get_blend_control(), *self.get_blend_alpha(), self.get_blend_brightness());
+			pixels = composed_pixels;
+			if self.render_debug_options.layer_map {
+				draw_layer_map(&mut pixels, &layers);
+			} else if self.render_debug_options.tint_layers {
+				apply_layer_tints(&mut pixels, &layers);
+			}
+		} else {
+			pixels = vec![1.0; SCREEN_TOTAL_PIXELS * 3];
+		}
 
-											pixels[pixel_index] = color.get_red();
-											pixels[pixel_index + 1] = color.get_green();
-											pixels[pixel_index + 2] = color.get_blue();
-										}
-									} else {
-										let palette_entry = self.vram[tile_address + tile_pixel / 2] as usize;
+		stats.unique_tiles = used_tiles.iter().filter(|used| **used).count();
+		self.render_stats = stats;
 
-										if palette_entry != 0 {
-											let palette_offset = sprite.get_palette_number() as usize * 16;
-											let palette_index = (palette_entry >> ((tile_pixel & 1) * 4)) & 0xf;
-											let color_address = SPRITE_PALETTE_START_INDEX + palette_offset + palette_index;
+		pixels
+	}
 
-											let color = self.palette_ram[color_address];
+	/// Sprites that can show up in the video mode, in drawing order (Sprite 0 = Front, Last Sprite = back)
+	fn get_displayed_sprites(&self, video_mode: EVideoMode) -> impl Iterator<Item = (usize, &SpriteEntry)> {
+		self.oam.iter().enumerate().rev().filter(move |(_, s)| s.is_displayed(video_mode))
+	}
 
-											pixels[pixel_index] = color.get_red();
-											pixels[pixel_index + 1] = color.get_green();
-											pixels[pixel_index + 2] = color.get_blue();
-										}
-									}
-								}
-							}
-						}
-					}
+	/// Number of OAM entries rendered on each scanline, the sprites after them don't fit in the OBJ cycles of the line
+	/// NOTE: Sprites are rendered in OAM order, the first one that doesn't fit is dropped whole along with the following ones
+	fn compute_sprite_line_limits(&self, video_mode: EVideoMode) -> Vec<usize> {
+		let line_cycles = if self.disp_cnt.get_h_blank_interval_free() {
+			OBJ_LINE_CYCLES_H_BLANK_FREE
+		} else {
+			OBJ_LINE_CYCLES
+		};
+		let mut limits = vec![self.oam.len(); 160];
+		let mut cycles = vec![0; 160];
+		for (index, sprite) in self.oam.iter().enumerate().filter(|(_, sprite)| sprite.is_displayed(video_mode)) {
+			for line in 0..160 {
+				if index >= limits[line] || sprite.get_bounds_row(line as u8).is_none() {
+					continue;
+				}
+
+				cycles[line] += sprite.get_render_cycles();
+				if cycles[line] > line_cycles {
+					limits[line] = index;
 				}
 			}
+		}
+
+		limits
+	}
+
+	/// Call f with the screen pixel, tile address and palette index (None if transparent) of each pixel of the sprite that is on screen
+	/// NOTE: The sprite is skipped on the scanlines it doesn't fit in the OBJ cycles of, see compute_sprite_line_limits
+	fn for_each_sprite_pixel(&self, index: usize, sprite: &SpriteEntry, line_limits: &[usize], mut f: impl FnMut(usize, usize, Option<usize>)) {
+		for line in (0..160).filter(|line| index < line_limits[*line as usize]) {
+			self.render_sprite_line(sprite, line, |x, tile_address, palette_index| f(x + line as usize * 240, tile_address, palette_index));
+		}
+	}
+
+	/// Call f with the screen X, tile address and palette index (None if transparent) of each pixel of the sprite on a scanline
+	/// NOTE: Coordinates wrap around (Y at 256, X at 512), so sprites near the bottom/right edge also show up at the top/left
+	/// Affine sprites sample their texture around the center of their bounding box, which is twice their size when double-sized
+	fn render_sprite_line(&self, sprite: &SpriteEntry, line: u8, mut f: impl FnMut(usize, usize, Option<usize>)) {
+		let (width, height) = sprite.get_size();
+		let (bounds_width, bounds_height) = sprite.get_bounds_size();
+
+		let sprite_y = match sprite.get_bounds_row(line) {
+			Some(row) => row,
+			None => return,
+		};
+
+		let is_1d_mapping = self.disp_cnt.get_sprite_1d_mapping();
+		let format = ETileFormat::from_is_256_palette(sprite.get_is_256_palette());
+		let tiles_per_row = if is_1d_mapping {
+			width / 8
+		} else if sprite.get_is_256_palette() {
+			16
 		} else {
-			pixels = vec![1.0; SCREEN_TOTAL_PIXELS * 3];
+			32
+		};
+		let tile_length = format.get_tile_length();
+		let start_tile_address = SPRITE_TILES_START_ADDRESS + sprite.get_tile_index() * 32;
+
+		let affine_matrix = if sprite.get_is_affine() {
+			// NOTE: PA, PB, PC and PD are spread over the unused attribute of 4 consecutive OAM entries
+			let group = sprite.get_affine_matrix_index() * 4;
+			Some([0, 1, 2, 3].map(|i| self.oam[group + i].get_affine_data().get_value()))
+		} else {
+			None
+		};
+
+		for x in 0..bounds_width {
+			let screen_x = ((sprite.get_x_coord() + x as i32) & 0x1ff) as usize;
+			if screen_x >= 240 {
+				continue;
+			}
+
+			let (pixel_x, pixel_y) = match &affine_matrix {
+				Some(matrix) => {
+					// NOTE: Relative to the center of the bounding box, the matrix maps screen to texture
+					let dx = x as i32 - (bounds_width / 2) as i32;
+					let dy = sprite_y as i32 - (bounds_height / 2) as i32;
+					let pixel_x = ((matrix[0] * dx + matrix[1] * dy) >> 8) + (width / 2) as i32;
+					let pixel_y = ((matrix[2] * dx + matrix[3] * dy) >> 8) + (height / 2) as i32;
+					if pixel_x < 0 || pixel_x >= width as i32 || pixel_y < 0 || pixel_y >= height as i32 {
+						continue;
+					}
+
+					(pixel_x as usize, pixel_y as usize)
+				}
+				None => (
+					if sprite.get_h_flip() { width - 1 - x } else { x },
+					if sprite.get_v_flip() { height - 1 - sprite_y } else { sprite_y },
+				),
+			};
+
+			let tile = pixel_x / 8 + (pixel_y / 8) * tiles_per_row;
+			let tile_address = start_tile_address + tile * tile_length;
+			let tile_data = self.get_obj_tile(tile_address, format);
+			let index = decode_tile_pixel(tile_data, format, pixel_x % 8, pixel_y % 8);
+			let palette_index = get_palette_index(index, format, sprite.get_palette_number() as usize);
+			f(screen_x, tile_address, palette_index);
 		}
+	}
 
-		pixels
+	/// Layers (WININ/WINOUT bits: BG0-3, OBJ, color effects) enabled at each pixel by the windows
+	/// NOTE: Win0 has priority over Win1, which has priority over the OBJ window, everything else is outside
+	fn compute_window_masks(&self, video_mode: EVideoMode) -> Vec<u8> {
+		let disp_cnt = &self.disp_cnt;
+		if !disp_cnt.get_window0_display() && !disp_cnt.get_window1_display() && !disp_cnt.get_sprite_window_display() {
+			return vec![ALL_LAYERS_MASK; SCREEN_TOTAL_PIXELS];
+		}
+
+		let win_in = self.get_win_in();
+		let win_out = self.get_win_out();
+		let mut masks = vec![win_out.get_outside_win_mask(); SCREEN_TOTAL_PIXELS];
+		if disp_cnt.get_sprite_window_display() && disp_cnt.get_screen_display_sprites() {
+			let line_limits = self.compute_sprite_line_limits(video_mode);
+			for (oam_index, sprite) in self.get_displayed_sprites(video_mode).filter(|(_, sprite)| sprite.get_sprite_mode() == ESpriteMode::ObjWindow) {
+				self.for_each_sprite_pixel(oam_index, sprite, &line_limits, |index, _, palette_index| {
+					if palette_index.is_some() {
+						masks[index] = win_out.get_obj_win_mask();
+					}
+				});
+			}
+		}
+
+		for win in (0..2).rev().filter(|win| disp_cnt.bit(13 + win)) {
+			let dimensions = self.get_win_dimensions(win);
+			for y in 0..160 {
+				for x in 0..240 {
+					if dimensions.contains(x, y) {
+						masks[x as usize + y as usize * 240] = win_in.get_win_mask(win);
+					}
+				}
+			}
+		}
+
+		masks
+	}
+}
+
+impl SaveState for PPU {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u16(self.disp_cnt.0)?;
+		writer.write_u16(self.disp_stat.0)?;
+		writer.write_u8(self.v_count)?;
+		for bg in 0..4 {
+			writer.write_u16(self.bg_controls[bg].0)?;
+			writer.write_u16(self.bg_hofs[bg])?;
+			writer.write_u16(self.bg_vofs[bg])?;
+		}
+		for matrix in self.bg_affine_matrices.iter() {
+			for parameter in [&matrix.pa, &matrix.pb, &matrix.pc, &matrix.pd].iter() {
+				writer.write_u16(parameter.0)?;
+			}
+			writer.write_u32(matrix.x.0)?;
+			writer.write_u32(matrix.y.0)?;
+		}
+		for dimensions in self.win_dimensions.iter() {
+			writer.write_u16(dimensions.h)?;
+			writer.write_u16(dimensions.v)?;
+		}
+		writer.write_u16(self.win_in.0)?;
+		writer.write_u16(self.win_out.0)?;
+		writer.write_u16(self.mosaic.0)?;
+		writer.write_u16(self.bld_cnt.0)?;
+		writer.write_u16(self.bld_alpha.0)?;
+		writer.write_u16(self.bld_y)?;
+
+		for value in self.raw_palette_ram.iter() {
+			writer.write_u16(*value)?;
+		}
+		writer.write_bytes(&self.vram)?;
+		for sprite in self.oam.iter() {
+			writer.write_u64(sprite.0)?;
+		}
+
+		Ok(())
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.disp_cnt.0 = reader.read_u16()?;
+		self.disp_stat.0 = reader.read_u16()?;
+		self.v_count = reader.read_u8()?;
+		for bg in 0..4 {
+			self.bg_controls[bg].0 = reader.read_u16()?;
+			self.bg_hofs[bg] = reader.read_u16()?;
+			self.bg_vofs[bg] = reader.read_u16()?;
+		}
+		for matrix in self.bg_affine_matrices.iter_mut() {
+			for parameter in [&mut matrix.pa, &mut matrix.pb, &mut matrix.pc, &mut matrix.pd].iter_mut() {
+				parameter.0 = reader.read_u16()?;
+			}
+			matrix.x.0 = reader.read_u32()?;
+			matrix.y.0 = reader.read_u32()?;
+		}
+		for dimensions in self.win_dimensions.iter_mut() {
+			dimensions.h = reader.read_u16()?;
+			dimensions.v = reader.read_u16()?;
+		}
+		self.win_in.0 = reader.read_u16()?;
+		self.win_out.0 = reader.read_u16()?;
+		self.mosaic.0 = reader.read_u16()?;
+		self.bld_cnt.0 = reader.read_u16()?;
+		self.bld_alpha.0 = reader.read_u16()?;
+		self.bld_y = reader.read_u16()?;
+
+		// NOTE: Goes through set_palette_entry, so the decoded colors and the dirty counters follow
+		for index in 0..self.raw_palette_ram.len() {
+			let value = reader.read_u16()?;
+			self.set_palette_entry(index, value);
+		}
+		reader.read_bytes(&mut self.vram)?;
+		self.mark_vram_dirty();
+		for sprite in self.oam.iter_mut() {
+			sprite.0 = reader.read_u64()?;
+		}
+		self.mark_oam_dirty();
+
+		Ok(())
+	}
+}
+
+/// Non transparent pixels of every layer at each screen position, resolved by compose_pixel once all the layers are drawn
+struct Composition {
+	/// Indexed by the blend bit of the layer (BG0-3, OBJ)
+	layer_pixels: Vec<[Option<LayerPixel>; 5]>,
+	/// Layers enabled by the windows, see compute_window_masks
+	window_masks: Vec<u8>,
+}
+
+impl Composition {
+	fn new(window_masks: Vec<u8>) -> Self {
+		Self {
+			layer_pixels: vec![[None; 5]; SCREEN_TOTAL_PIXELS],
+			window_masks,
+		}
+	}
+
+	/// Set the pixel of a layer, layers can be drawn in any order
+	/// NOTE: Returns false if the windows hide the layer at this position
+	fn draw(&mut self, index: usize, pixel: LayerPixel) -> bool {
+		let layer_bit = pixel.layer.get_blend_bit();
+		self.layer_pixels[index][layer_bit] = Some(pixel);
+		self.window_masks[index].bit(layer_bit)
+	}
+
+	/// Colors (RGB) of the screen and the front layer of each pixel
+	fn compose(&self, backdrop: Rgb15, blend_control: BlendControl, blend_alpha: BlendAlpha, blend_brightness: u8) -> (Vec<f32>, Vec<ELayer>) {
+		let mut pixels = Vec::with_capacity(SCREEN_TOTAL_PIXELS * 3);
+		let mut layers = Vec::with_capacity(SCREEN_TOTAL_PIXELS);
+		for (layer_pixels, window_mask) in self.layer_pixels.iter().zip(self.window_masks.iter()) {
+			let mut drawn_pixels = [LayerPixel::backdrop(backdrop); 5];
+			let mut count = 0;
+			for pixel in layer_pixels.iter().flatten() {
+				drawn_pixels[count] = *pixel;
+				count += 1;
+			}
+
+			let inputs = PixelInputs {
+				layers: &drawn_pixels[..count],
+				backdrop,
+				window_mask: *window_mask,
+				blend_control,
+				blend_alpha,
+				blend_brightness,
+			};
+			let color = Color::new(compose_pixel(&inputs));
+			pixels.extend_from_slice(&[color.get_red(), color.get_green(), color.get_blue()]);
+			layers.push(get_top_layers(&inputs)[0].layer);
+		}
+
+		(pixels, layers)
+	}
+}
+
+/// Closest 15 bit color of a RGB color
+pub fn to_rgb15(color: [f32; 3]) -> Rgb15 {
+	color
+		.iter()
+		.enumerate()
+		.fold(0, |value, (i, channel)| value | (((channel.clamp(0.0, 1.0) * 31.0).round() as u16) << (i * 5)))
+}
+
+/// Mix each pixel halfway with the tint color of its layer
+fn apply_layer_tints(pixels: &mut [f32], layers: &[ELayer]) {
+	for (pixel, layer) in pixels.chunks_exact_mut(3).zip(layers) {
+		for (channel, tint) in pixel.iter_mut().zip(layer.get_tint_color().iter()) {
+			*channel = (*channel + tint) / 2.0;
+		}
+	}
+}
+
+/// Color each pixel with the tint color of its layer
+fn draw_layer_map(pixels: &mut [f32], layers: &[ELayer]) {
+	for (pixel, layer) in pixels.chunks_exact_mut(3).zip(layers) {
+		pixel.copy_from_slice(&layer.get_tint_color());
+	}
+}
+
+/// Layers (and the backdrop) that can end up as the visible pixel of the screen
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ELayer {
+	Bg0,
+	Bg1,
+	Bg2,
+	Bg3,
+	Obj,
+	/// Shown where no layer wrote a (non transparent) pixel
+	Backdrop,
+}
+
+impl ELayer {
+	pub const ALL: [ELayer; 6] = [ELayer::Bg0, ELayer::Bg1, ELayer::Bg2, ELayer::Bg3, ELayer::Obj, ELayer::Backdrop];
+
+	pub fn from_bg(bg: usize) -> Self {
+		match bg {
+			0 => ELayer::Bg0,
+			1 => ELayer::Bg1,
+			2 => ELayer::Bg2,
+			3 => ELayer::Bg3,
+			_ => panic!("IMPOSSIBLE!"),
+		}
+	}
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ELayer::Bg0 => "BG0",
+			ELayer::Bg1 => "BG1",
+			ELayer::Bg2 => "BG2",
+			ELayer::Bg3 => "BG3",
+			ELayer::Obj => "OBJ",
+			ELayer::Backdrop => "Backdrop",
+		}
+	}
+
+	/// Color (RGB) the layer is tinted with when debugging the composition
+	pub fn get_tint_color(&self) -> [f32; 3] {
+		match self {
+			ELayer::Bg0 => [1.0, 0.0, 0.0],
+			ELayer::Bg1 => [0.0, 1.0, 0.0],
+			ELayer::Bg2 => [0.0, 0.0, 1.0],
+			ELayer::Bg3 => [1.0, 1.0, 0.0],
+			ELayer::Obj => [0.0, 1.0, 1.0],
+			ELayer::Backdrop => [0.5, 0.5, 0.5],
+		}
+	}
+
+	/// Bit of the layer within the BLDCNT target groups
+	pub fn get_blend_bit(&self) -> usize {
+		match self {
+			ELayer::Bg0 => 0,
+			ELayer::Bg1 => 1,
+			ELayer::Bg2 => 2,
+			ELayer::Bg3 => 3,
+			ELayer::Obj => 4,
+			ELayer::Backdrop => 5,
+		}
 	}
 }
 
@@ -736,12 +1324,13 @@ pub struct BackgroundAffineMatrix {
 }
 
 impl BackgroundAffineMatrix {
+	/// NOTE: Starts as the identity matrix (PA = PD = 1.0) like after the BIOS boot, so BGs display untransformed
 	pub fn new() -> Self {
 		Self {
-			pa: FixedPoint16Bit(0),
+			pa: FixedPoint16Bit(0x100),
 			pb: FixedPoint16Bit(0),
 			pc: FixedPoint16Bit(0),
-			pd: FixedPoint16Bit(0),
+			pd: FixedPoint16Bit(0x100),
 			x: FixedPoint28Bit(0),
 			y: FixedPoint28Bit(0),
 		}
@@ -834,6 +1423,11 @@ impl WinIn {
 	pub fn get_win_blend_enabled(&self, win: usize) -> bool {
 		self.bit(win * 8 + 5)
 	}
+
+	/// Layers enabled inside the window, see compute_window_masks
+	pub fn get_win_mask(&self, win: usize) -> u8 {
+		self.bit_range(win * 8 + 5, win * 8)
+	}
 }
 
 bitfield! {
@@ -862,6 +1456,14 @@ impl WinOut {
 	pub fn get_obj_win_bg_enabled(&self, bg: usize) -> bool {
 		self.bit(8 + bg)
 	}
+
+	pub fn get_outside_win_mask(&self) -> u8 {
+		self.bit_range(5, 0)
+	}
+
+	pub fn get_obj_win_mask(&self) -> u8 {
+		self.bit_range(13, 8)
+	}
 }
 
 bitfield! {
@@ -877,6 +1479,7 @@ bitfield! {
 
 bitfield! {
 	/// Color Special Effects Selection (R/W)
+	#[derive(Clone, Copy)]
 	pub struct BlendControl(u16);
 	impl Debug;
 	u8;
@@ -907,10 +1510,21 @@ impl BlendControl {
 	pub fn get_blend_bg_target(&self, bg: usize) -> bool {
 		self.bit(8 + bg)
 	}
+
+	/// Whether the layer (backdrop included) is selected as first target of the color effect
+	pub fn get_first_target(&self, layer: ELayer) -> bool {
+		self.bit(layer.get_blend_bit())
+	}
+
+	/// Whether the layer (backdrop included) is selected as second target of the color effect
+	pub fn get_second_target(&self, layer: ELayer) -> bool {
+		self.bit(8 + layer.get_blend_bit())
+	}
 }
 
 bitfield! {
 	/// Alpha Blending Coefficients (R/W)
+	#[derive(Clone, Copy)]
 	pub struct BlendAlpha(u16);
 	impl Debug;
 	u8;
@@ -920,14 +1534,14 @@ bitfield! {
 
 impl MemoryInterface for PPU {
 	fn read_8(&self, address: u32) -> u8 {
-		match address & 0xff00_0000 {
-			crate::system::IO_ADDR => {
+		match SystemBus::resolve(address).0 {
+			EMemoryRegion::Io => {
 				let addr = address & 0x00ff_ffff;
 				let shift = (addr as usize & 0x1) * 8;
 				match addr & !0x1 {
 					DISP_CNT_ADDRESS => self.disp_cnt.bit_range(shift + 7, shift),
 					DISP_STAT_ADDRESS => self.disp_stat.bit_range(shift + 7, shift),
-					VCOUNT_ADDRESS => self.v_count >> shift, // 0 if addressing the upper bits
+					VCOUNT_ADDRESS => (self.v_count as u16 >> shift) as u8, // 0 if addressing the upper bits
 					BG0_CNT_ADDRESS => self.bg_controls[0].bit_range(shift + 7, shift),
 					BG1_CNT_ADDRESS => self.bg_controls[1].bit_range(shift + 7, shift),
 					BG2_CNT_ADDRESS => self.bg_controls[2].bit_range(shift + 7, shift),
@@ -939,16 +1553,16 @@ impl MemoryInterface for PPU {
 					_ => 0x0,
 				}
 			}
-			PALETTE_RAM_ADDR => {
+			EMemoryRegion::PaletteRam => {
 				let addr = address as usize & 0x3ff;
 				let shift = (addr & 0x1) * 8;
-				(self.palette_ram[addr / 2].get_value() >> shift) as u8
+				(self.raw_palette_ram[addr / 2] >> shift) as u8
 			}
-			VRAM_ADDR => {
-				let clamped_address = compute_vram_address(address);
+			EMemoryRegion::Vram => {
+				let clamped_address = compute_vram_address(address, EAccessWidth::Byte);
 				self.vram[clamped_address]
 			}
-			OAM_ADDR => unsafe {
+			EMemoryRegion::Oam => unsafe {
 				*((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize))
 			}
 			_ => 0x0, // TODO: Return proper invalid value
@@ -956,14 +1570,19 @@ impl MemoryInterface for PPU {
 	}
 
 	fn write_8(&mut self, address: u32, value: u8) {
-		match address & 0xff00_0000 {
-			crate::system::IO_ADDR => {
+		match SystemBus::resolve(address).0 {
+			EMemoryRegion::Io => {
 				let addr = address & 0x00ff_ffff;
 				let shift16 = (addr as usize & 0x1) * 8;
 				let shift32 = (addr as usize & 0x3) * 8;
 				match addr & !0x1 {
 					DISP_CNT_ADDRESS => self.disp_cnt.set_bit_range(shift16 + 7, shift16, value),
-					DISP_STAT_ADDRESS => self.disp_stat.set_bit_range(shift16 + 7, shift16, value),
+					DISP_STAT_ADDRESS => {
+						let mut disp_stat = self.disp_stat.0;
+						disp_stat.set_bit_range(shift16 + 7, shift16, value);
+						self.write_disp_stat(disp_stat);
+					}
+					// NOTE: VCOUNT is read-only
 					VCOUNT_ADDRESS => {}
 					BG0_CNT_ADDRESS => self.bg_controls[0].set_bit_range(shift16 + 7, shift16, value),
 					BG1_CNT_ADDRESS => self.bg_controls[1].set_bit_range(shift16 + 7, shift16, value),
@@ -1007,39 +1626,34 @@ impl MemoryInterface for PPU {
 				}
 			}
 			// NOTE: Writes to BG (6000000h-600FFFFh) (or 6000000h-6013FFFh in Bitmap mode) and to Palette (5000000h-50003FFh) are writing the new 8bit value to BOTH upper and lower 8bits of the addressed halfword, ie. "[addr AND NOT 1]=data*101h"
-			PALETTE_RAM_ADDR => {
+			EMemoryRegion::PaletteRam => {
 				let addr = address as usize & 0x3ff;
-				let color = Color::new((value as u16) * 0x101);
-				self.palette_ram[addr / 2] = color;
+				self.set_palette_entry(addr / 2, (value as u16) * 0x101);
 			}
-			VRAM_ADDR => {
-				let clamped_address = compute_vram_address(address);
-				let end_bg_address;
-				if let Some(video_mode) = self.get_disp_cnt().get_bg_mode() {
-					end_bg_address = if video_mode == EVideoMode::Mode3 || video_mode == EVideoMode::Mode4 || video_mode == EVideoMode::Mode5 {
-						0x0600_FFFF
-					} else {
-						0x0601_3FFF
-					};
-				} else {
-					end_bg_address = 0x0600_FFFF;
-				}
-
-				if clamped_address >= 0x0600_0000 && clamped_address < end_bg_address {
+			EMemoryRegion::Vram => {
+				let clamped_address = compute_vram_address(address, EAccessWidth::Byte);
+				// NOTE: OBJ VRAM ignores 8bit writes
+				let end_bg_address = match self.get_disp_cnt().get_bg_mode() {
+					Some(EVideoMode::Mode3) | Some(EVideoMode::Mode4) | Some(EVideoMode::Mode5) => 0x1_4000,
+					_ => 0x1_0000,
+				};
+
+				if clamped_address < end_bg_address {
+					self.mark_vram_dirty();
 					unsafe {
 						*(self.vram.as_ptr().add(clamped_address & !0x1) as *mut u16) = (value as u16) * 0x101;
 					}
 				}
 			}
-			OAM_ADDR => {} // NOTE: No 8bit write is allowed to OAM
+			EMemoryRegion::Oam => {} // NOTE: No 8bit write is allowed to OAM
 			_ => {}
 		}
 	}
 
 	fn read_16(&self, address: u32) -> u16 {
 		unsafe {
-			match address & 0xff00_0000 {
-				crate::system::IO_ADDR => {
+			match SystemBus::resolve(address).0 {
+				EMemoryRegion::Io => {
 					let addr = address & 0x00ff_ffff;
 					match addr {
 						DISP_CNT_ADDRESS => self.disp_cnt.0,
@@ -1056,15 +1670,15 @@ impl MemoryInterface for PPU {
 						_ => 0x0,
 					}
 				}
-				PALETTE_RAM_ADDR => {
+				EMemoryRegion::PaletteRam => {
 					let addr = address as usize & 0x3ff;
-					self.palette_ram[addr / 2].get_value()
+					self.raw_palette_ram[addr / 2]
 				}
-				VRAM_ADDR => {
-					let clamped_address = compute_vram_address(address);
-					*(self.vram.as_ptr().add(clamped_address) as *mut u16) as u16
+				EMemoryRegion::Vram => {
+					let clamped_address = compute_vram_address(address, EAccessWidth::Halfword);
+					*(self.vram.as_ptr().add(clamped_address) as *mut u16)
 				}
-				OAM_ADDR => *((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u16) as u16,
+				EMemoryRegion::Oam => *((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u16),
 				_ => 0x0, // TODO: Return proper invalid value
 			}
 		}
@@ -1072,12 +1686,12 @@ impl MemoryInterface for PPU {
 
 	fn write_16(&mut self, address: u32, value: u16) {
 		unsafe {
-			match address & 0xff00_0000 {
-				crate::system::IO_ADDR => {
+			match SystemBus::resolve(address).0 {
+				EMemoryRegion::Io => {
 					let addr = address & 0x00ff_ffff;
 					match addr {
 						DISP_CNT_ADDRESS => self.disp_cnt.0 = value,
-						DISP_STAT_ADDRESS => self.disp_stat.0 = value,
+						DISP_STAT_ADDRESS => self.write_disp_stat(value),
 						VCOUNT_ADDRESS => {}
 						BG0_CNT_ADDRESS => self.bg_controls[0].0 = value,
 						BG1_CNT_ADDRESS => self.bg_controls[1].0 = value,
@@ -1120,16 +1734,19 @@ impl MemoryInterface for PPU {
 						_ => {}
 					}
 				}
-				PALETTE_RAM_ADDR => {
+				EMemoryRegion::PaletteRam => {
 					let addr = address as usize & 0x3ff;
-					let color = Color::new(value);
-					self.palette_ram[addr / 2] = color;
+					self.set_palette_entry(addr / 2, value);
 				}
-				VRAM_ADDR => {
-					let clamped_address = compute_vram_address(address);
+				EMemoryRegion::Vram => {
+					self.mark_vram_dirty();
+					let clamped_address = compute_vram_address(address, EAccessWidth::Halfword);
 					*(self.vram.as_ptr().add(clamped_address) as *mut u16) = value
 				}
-				OAM_ADDR => *((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u16) = value,
+				EMemoryRegion::Oam => {
+					self.mark_oam_dirty();
+					*((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u16) = value
+				}
 				_ => {}
 			}
 		}
@@ -1137,8 +1754,8 @@ impl MemoryInterface for PPU {
 
 	fn read_32(&self, address: u32) -> u32 {
 		unsafe {
-			match address & 0xff00_0000 {
-				crate::system::IO_ADDR => {
+			match SystemBus::resolve(address).0 {
+				EMemoryRegion::Io => {
 					let addr = address & 0x00ff_ffff;
 					// NOTE: Memory accesses are always aligned!!!
 					match addr {
@@ -1151,15 +1768,15 @@ impl MemoryInterface for PPU {
 						_ => 0x0,
 					}
 				}
-				PALETTE_RAM_ADDR => {
+				EMemoryRegion::PaletteRam => {
 					let addr = (address as usize & 0x3ff) / 2;
-					self.palette_ram[addr].get_value() as u32 | (self.palette_ram[addr + 1].get_value() as u32) << 16
+					self.raw_palette_ram[addr] as u32 | (self.raw_palette_ram[addr + 1] as u32) << 16
 				}
-				VRAM_ADDR => {
-					let clamped_address = compute_vram_address(address);
-					*(self.vram.as_ptr().add(clamped_address) as *mut u32) as u32
+				EMemoryRegion::Vram => {
+					let clamped_address = compute_vram_address(address, EAccessWidth::Word);
+					*(self.vram.as_ptr().add(clamped_address) as *mut u32)
 				}
-				OAM_ADDR => *((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u32) as u32,
+				EMemoryRegion::Oam => *((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u32),
 				_ => 0x0, // TODO: Return proper invalid value
 			}
 		}
@@ -1167,12 +1784,13 @@ impl MemoryInterface for PPU {
 
 	fn write_32(&mut self, address: u32, value: u32) {
 		unsafe {
-			match address & 0xff00_0000 {
-				crate::system::IO_ADDR => {
+			match SystemBus::resolve(address).0 {
+				EMemoryRegion::Io => {
 					let addr = address & 0x00ff_ffff;
 					match addr {
 						DISP_CNT_ADDRESS => self.disp_cnt.0 = value as u16,
-						DISP_STAT_ADDRESS => self.disp_stat.0 = value as u16,
+						// NOTE: The upper halfword is VCOUNT, which is read-only
+						DISP_STAT_ADDRESS => self.write_disp_stat(value as u16),
 						BG0_CNT_ADDRESS => {
 							self.bg_controls[0].0 = value as u16;
 							self.bg_controls[1].0 = (value >> 16) as u16;
@@ -1246,20 +1864,354 @@ impl MemoryInterface for PPU {
 						_ => {}
 					}
 				}
-				PALETTE_RAM_ADDR => {
+				EMemoryRegion::PaletteRam => {
 					let addr = (address as usize & 0x3ff) / 2;
-					let color_lo = Color::new(value.bit_range(15, 0));
-					let color_hi = Color::new(value.bit_range(31, 16));
-					self.palette_ram[addr] = color_lo;
-					self.palette_ram[addr + 1] = color_hi;
+					self.set_palette_entry(addr, value.bit_range(15, 0));
+					self.set_palette_entry(addr + 1, value.bit_range(31, 16));
 				}
-				VRAM_ADDR => {
-					let clamped_address = compute_vram_address(address);
+				EMemoryRegion::Vram => {
+					self.mark_vram_dirty();
+					let clamped_address = compute_vram_address(address, EAccessWidth::Word);
 					*(self.vram.as_ptr().add(clamped_address) as *mut u32) = value
 				}
-				OAM_ADDR => *((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u32) = value,
+				EMemoryRegion::Oam => {
+					self.mark_oam_dirty();
+					*((self.oam.as_ptr() as *mut u8).add((address & 0x3ff) as usize) as *mut u32) = value
+				}
 				_ => {}
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::system::{IO_ADDR, OAM_ADDR, PALETTE_RAM_ADDR};
+
+	const RED: Rgb15 = 0x001f;
+
+	fn get_screen_pixel(pixels: &[f32], x: usize, y: usize) -> Rgb15 {
+		let index = (x + y * 240) * 3;
+		to_rgb15([pixels[index], pixels[index + 1], pixels[index + 2]])
+	}
+
+	/// Mode 2 with BG2 of a size showing its last tile, made of palette index 5, at the top left of the screen
+	fn setup_affine_bg(size: u16) -> PPU {
+		let mut ppu = PPU::new();
+		let bg_size = 128 << size;
+		let bg_tiles = bg_size / 8;
+		// NOTE: Mode 2 and BG2 enabled, map at 0x8000 and tiles at 0x0, the 256 color bit is left clear
+		ppu.write_16(IO_ADDR + DISP_CNT_ADDRESS, 0x0402);
+		ppu.write_16(IO_ADDR + BG2_CNT_ADDRESS, (size << 14) | (16 << 8));
+		ppu.write_16(IO_ADDR + BG2_PA_ADDRESS, 0x100);
+		ppu.write_16(IO_ADDR + BG2_PB_ADDRESS, 0x0);
+		ppu.write_16(IO_ADDR + BG2_PC_ADDRESS, 0x0);
+		ppu.write_16(IO_ADDR + BG2_PD_ADDRESS, 0x100);
+		ppu.write_32(IO_ADDR + BG2_X_LO_ADDRESS, (bg_size as u32 - 8) << 8);
+		ppu.write_32(IO_ADDR + BG2_Y_LO_ADDRESS, (bg_size as u32 - 8) << 8);
+		ppu.write_16(PALETTE_RAM_ADDR + 5 * 2, RED);
+
+		// NOTE: Tile 1 is 64 bytes long as 8bpp, as 4bpp it would start at 0x20 which is left transparent
+		for offset in (0x40..0x80).step_by(2) {
+			ppu.write_16(VRAM_ADDR + offset, 0x0505);
+		}
+		// NOTE: One byte per entry, the last tile is the high byte of the last halfword of the map
+		let last_tile = (bg_tiles * bg_tiles - 1) as u32;
+		ppu.write_16(VRAM_ADDR + 0x8000 + last_tile - 1, 0x0100);
+		ppu
+	}
+
+	fn check_affine_bg(size: u16) {
+		let mut ppu = setup_affine_bg(size);
+		let pixels = ppu.render();
+		for (x, y) in [(0, 0), (7, 0), (0, 7), (7, 7)] {
+			assert_eq!(get_screen_pixel(&pixels, x, y), RED, "size {} pixel ({}, {})", size, x, y);
+		}
+		// NOTE: Past the end of the map without wraparound, the backdrop shows
+		for (x, y) in [(8, 0), (0, 8), (8, 8)] {
+			assert_eq!(get_screen_pixel(&pixels, x, y), 0x0, "size {} pixel ({}, {})", size, x, y);
+		}
+	}
+
+	fn check_identity_affine_matrices(ppu: &PPU) {
+		for index in 0..2 {
+			let matrix = ppu.get_bg_affine_matrix(index);
+			let bg = index + 2;
+			assert_eq!(matrix.get_pa().get_value(), 0x100, "BG{} PA", bg);
+			assert_eq!(matrix.get_pb().get_value(), 0x0, "BG{} PB", bg);
+			assert_eq!(matrix.get_pc().get_value(), 0x0, "BG{} PC", bg);
+			assert_eq!(matrix.get_pd().get_value(), 0x100, "BG{} PD", bg);
+			assert_eq!(matrix.get_x().get_value(), 0x0, "BG{} X", bg);
+			assert_eq!(matrix.get_y().get_value(), 0x0, "BG{} Y", bg);
+		}
+	}
+
+	#[test]
+	fn affine_matrices_are_the_identity_after_reset() {
+		check_identity_affine_matrices(&PPU::new());
+		// NOTE: Also once the boot is skipped without a BIOS
+		let gba = crate::gba::Gba::new(Box::new([]), vec![0; 0x200].into_boxed_slice());
+		check_identity_affine_matrices(&gba.bus.ppu);
+	}
+
+	#[test]
+	fn affine_bg_128x128() {
+		check_affine_bg(0);
+	}
+
+	#[test]
+	fn affine_bg_256x256() {
+		check_affine_bg(1);
+	}
+
+	#[test]
+	fn affine_bg_512x512() {
+		check_affine_bg(2);
+	}
+
+	#[test]
+	fn affine_bg_1024x1024() {
+		check_affine_bg(3);
+	}
+
+	#[test]
+	fn affine_bg_wraps_around_when_enabled() {
+		let mut ppu = setup_affine_bg(0);
+		ppu.write_16(IO_ADDR + BG2_CNT_ADDRESS, (1 << 13) | (16 << 8));
+		let pixels = ppu.render();
+		// NOTE: The map is 128 pixels wide, the last tile shows again 128 pixels further
+		assert_eq!(get_screen_pixel(&pixels, 128, 0), RED);
+		assert_eq!(get_screen_pixel(&pixels, 8, 0), 0x0);
+	}
+
+	#[test]
+	fn text_bg_256x512_second_screen_block_is_below_the_first() {
+		let mut ppu = PPU::new();
+		// NOTE: Mode 0 and BG0 enabled, 256x512 map at 0x8000 and 4bpp tiles at 0x0
+		ppu.write_16(IO_ADDR + DISP_CNT_ADDRESS, 0x0100);
+		ppu.write_16(IO_ADDR + BG0_CNT_ADDRESS, (2 << 14) | (16 << 8));
+		ppu.write_16(IO_ADDR + BG0_VOFS_ADDRESS, 256);
+		ppu.write_16(PALETTE_RAM_ADDR + 2, RED);
+		// NOTE: Tile 1 only has its left column set
+		for y in 0..8 {
+			ppu.write_16(VRAM_ADDR + 0x20 + y * 4, 0x0001);
+		}
+		// NOTE: Tile 1 flipped horizontally at the top left of the second screen block
+		ppu.write_16(VRAM_ADDR + 0x8800, 0x0401);
+
+		let pixels = ppu.render();
+		for y in 0..8 {
+			assert_eq!(get_screen_pixel(&pixels, 7, y), RED, "pixel (7, {})", y);
+			assert_eq!(get_screen_pixel(&pixels, 0, y), 0x0, "pixel (0, {})", y);
+		}
+	}
+
+	/// BG VRAM filled with 0x11, OBJ VRAM with 0x22
+	fn setup_filled_vram() -> PPU {
+		let mut ppu = PPU::new();
+		for offset in (0..VRAM_SIZE as u32).step_by(2) {
+			let value = if offset < SPRITE_TILES_START_ADDRESS as u32 { 0x1111 } else { 0x2222 };
+			ppu.write_16(VRAM_ADDR + offset, value);
+		}
+		ppu
+	}
+
+	#[test]
+	fn bg_vram_reads_stop_at_obj_vram() {
+		let ppu = setup_filled_vram();
+		assert_eq!(ppu.read_bg_vram(0x0), 0x11);
+		assert_eq!(ppu.read_bg_vram(SPRITE_TILES_START_ADDRESS - 1), 0x11);
+		assert_eq!(ppu.read_bg_vram(SPRITE_TILES_START_ADDRESS), 0x0);
+		assert_eq!(ppu.read_bg_vram(VRAM_SIZE - 1), 0x0);
+	}
+
+	#[test]
+	fn bg_tiles_of_high_tile_numbers() {
+		let ppu = setup_filled_vram();
+		// NOTE: Tile 1023 of char block 0 is still BG VRAM
+		assert_eq!(ppu.get_bg_tile(1023 * 32, ETileFormat::Bpp4), [0x11; 32]);
+		// NOTE: The last tiles of char block 3, then the first ones past its end which would be OBJ VRAM
+		let char_block_3 = 3 * 0x4000;
+		assert_eq!(ppu.get_bg_tile(char_block_3 + 511 * 32, ETileFormat::Bpp4), [0x11; 32]);
+		assert_eq!(ppu.get_bg_tile(char_block_3 + 512 * 32, ETileFormat::Bpp4), [0x0; 32]);
+		assert_eq!(ppu.get_bg_tile(char_block_3 + 1023 * 32, ETileFormat::Bpp4), [0x0; 32]);
+		assert_eq!(ppu.get_bg_tile(char_block_3 + 255 * 64, ETileFormat::Bpp8), [0x11; 64]);
+		assert_eq!(ppu.get_bg_tile(char_block_3 + 256 * 64, ETileFormat::Bpp8), [0x0; 64]);
+		assert_eq!(ppu.get_bg_tile(char_block_3 + 1023 * 64, ETileFormat::Bpp8), [0x0; 64]);
+	}
+
+	#[test]
+	fn bg_tiles_past_the_char_blocks_are_transparent() {
+		let mut ppu = PPU::new();
+		// NOTE: Mode 0 and BG0 enabled, map at 0x8000 and 4bpp tiles of char block 3
+		ppu.write_16(IO_ADDR + DISP_CNT_ADDRESS, 0x0100);
+		ppu.write_16(IO_ADDR + BG0_CNT_ADDRESS, (16 << 8) | (3 << 2));
+		ppu.write_16(PALETTE_RAM_ADDR + 2, RED);
+		// NOTE: Tile 511 is the last of char block 3, tile 512 would be the first one of OBJ VRAM, both are filled
+		for offset in (0..0x40).step_by(2) {
+			ppu.write_16(VRAM_ADDR + 0xc000 + 511 * 32 + offset, 0x1111);
+		}
+		ppu.write_16(VRAM_ADDR + 0x8000, 511);
+		ppu.write_16(VRAM_ADDR + 0x8002, 512);
+
+		let pixels = ppu.render();
+		assert_eq!(get_screen_pixel(&pixels, 0, 0), RED);
+		assert_eq!(get_screen_pixel(&pixels, 8, 0), 0x0);
+	}
+
+	const OBJ_TILES_ADDR: u32 = VRAM_ADDR + SPRITE_TILES_START_ADDRESS as u32;
+	/// Attribute 0 of sprites that aren't displayed (double size without affine)
+	const HIDDEN_SPRITE: u16 = 0x0200;
+
+	fn set_sprite(ppu: &mut PPU, index: usize, attributes: [u16; 3]) {
+		for (i, attribute) in attributes.iter().enumerate() {
+			ppu.write_16(OAM_ADDR + (index * 8 + i * 2) as u32, *attribute);
+		}
+	}
+
+	/// Screen X and palette index of the pixels of a sprite on a scanline
+	fn render_sprite(ppu: &PPU, index: usize, line: u8) -> Vec<(usize, Option<usize>)> {
+		let mut pixels = Vec::new();
+		ppu.render_sprite_line(&ppu.oam[index], line, |x, _, palette_index| pixels.push((x, palette_index)));
+		pixels
+	}
+
+	/// 8x8 256 color sprite whose pixels are 1 + X + Y * 8
+	fn setup_numbered_sprite(x: u16, y: u16) -> PPU {
+		let mut ppu = PPU::new();
+		for offset in (0..64).step_by(2) {
+			ppu.write_16(OBJ_TILES_ADDR + offset, (offset as u16 + 1) | (offset as u16 + 2) << 8);
+		}
+		set_sprite(&mut ppu, 0, [0x2000 | y, x, 0x0]);
+		ppu
+	}
+
+	#[test]
+	fn sprite_line_clipped_at_the_left_and_right_edges() {
+		// NOTE: X = -4
+		let ppu = setup_numbered_sprite(0x1fc, 0);
+		assert_eq!(render_sprite(&ppu, 0, 0), vec![(0, Some(5)), (1, Some(6)), (2, Some(7)), (3, Some(8))]);
+
+		let ppu = setup_numbered_sprite(236, 0);
+		assert_eq!(render_sprite(&ppu, 0, 2), vec![(236, Some(17)), (237, Some(18)), (238, Some(19)), (239, Some(20))]);
+	}
+
+	#[test]
+	fn sprite_line_clipped_at_the_top_and_bottom_edges() {
+		// NOTE: Y = -4, the bottom rows show on the first scanlines
+		let ppu = setup_numbered_sprite(0, 252);
+		assert_eq!(render_sprite(&ppu, 0, 0)[0], (0, Some(33)));
+		assert_eq!(render_sprite(&ppu, 0, 3)[0], (0, Some(57)));
+		assert!(render_sprite(&ppu, 0, 4).is_empty());
+		assert!(render_sprite(&ppu, 0, 251).is_empty());
+
+		let ppu = setup_numbered_sprite(0, 156);
+		assert!(render_sprite(&ppu, 0, 155).is_empty());
+		assert_eq!(render_sprite(&ppu, 0, 156)[0], (0, Some(1)));
+		assert_eq!(render_sprite(&ppu, 0, 159)[0], (0, Some(25)));
+	}
+
+	/// Tile address of the top left pixel of the second row and column of tiles of a 16x16 sprite using tile 2
+	fn get_second_tile_row_address(is_256_palette: bool, is_1d_mapping: bool) -> usize {
+		let mut ppu = PPU::new();
+		ppu.write_16(IO_ADDR + DISP_CNT_ADDRESS, if is_1d_mapping { 0x1040 } else { 0x1000 });
+		set_sprite(&mut ppu, 0, [if is_256_palette { 0x2000 } else { 0x0 }, 0x4000, 0x2]);
+
+		let mut tile_addresses = vec![0; 240];
+		ppu.render_sprite_line(&ppu.oam[0], 8, |x, tile_address, _| tile_addresses[x] = tile_address);
+		tile_addresses[8]
+	}
+
+	#[test]
+	fn sprite_tiles_with_1d_and_2d_mapping() {
+		let first_tile = SPRITE_TILES_START_ADDRESS + 2 * 32;
+		// NOTE: 1D rows are as wide as the sprite, 2D rows are 32 tiles of 32 bytes wide
+		assert_eq!(get_second_tile_row_address(false, true), first_tile + 3 * 32);
+		assert_eq!(get_second_tile_row_address(false, false), first_tile + 33 * 32);
+		assert_eq!(get_second_tile_row_address(true, true), first_tile + 3 * 64);
+		assert_eq!(get_second_tile_row_address(true, false), first_tile + 17 * 64);
+	}
+
+	/// 64x64 sprites at Y = 0 taking 64 cycles a scanline each, all of them off screen but the last one, the other OAM entries hidden
+	fn setup_sprite_line_limit(sprite_count: usize, disp_cnt: u16) -> PPU {
+		let mut ppu = PPU::new();
+		ppu.write_16(IO_ADDR + DISP_CNT_ADDRESS, disp_cnt);
+		ppu.write_16(PALETTE_RAM_ADDR + (SPRITE_PALETTE_START_INDEX as u32 + 1) * 2, RED);
+		for offset in (0..64 * 32).step_by(2) {
+			ppu.write_16(OBJ_TILES_ADDR + offset, 0x1111);
+		}
+		for index in 0..OAM_SIZE / 8 {
+			let x = if index + 1 == sprite_count { 0 } else { 300 };
+			let attribute_0 = if index < sprite_count { 0x0 } else { HIDDEN_SPRITE };
+			set_sprite(&mut ppu, index, [attribute_0, 0xc000 | x, 0x0]);
+		}
+		ppu
+	}
+
+	#[test]
+	fn sprite_line_limit() {
+		let ppu = setup_sprite_line_limit(19, 0x1040);
+		let limits = ppu.compute_sprite_line_limits(EVideoMode::Mode0);
+		// NOTE: 18 sprites take 1152 of the 1210 cycles of the scanlines, there isn't enough left for the 19th
+		assert!(limits[..64].iter().all(|limit| *limit == 18));
+		assert!(limits[64..].iter().all(|limit| *limit == OAM_SIZE / 8));
+
+		let mut ppu = setup_sprite_line_limit(19, 0x1040);
+		assert_eq!(get_screen_pixel(&ppu.render(), 0, 0), 0x0);
+		let mut ppu = setup_sprite_line_limit(18, 0x1040);
+		assert_eq!(get_screen_pixel(&ppu.render(), 0, 0), RED);
+	}
+
+	#[test]
+	fn sprite_line_limit_with_h_blank_interval_free() {
+		let ppu = setup_sprite_line_limit(19, 0x1060);
+		// NOTE: 954 cycles, 14 sprites
+		assert_eq!(ppu.compute_sprite_line_limits(EVideoMode::Mode0)[0], 14);
+	}
+
+	#[test]
+	fn sprite_line_limit_of_affine_sprites() {
+		let mut ppu = setup_sprite_line_limit(8, 0x1040);
+		for index in 0..8 {
+			// NOTE: Double-sized, 10 + 128 * 2 cycles each
+			set_sprite(&mut ppu, index, [0x0300, 0xc000, 0x0]);
+		}
+		let limits = ppu.compute_sprite_line_limits(EVideoMode::Mode0);
+		assert_eq!(limits[0], 4);
+		assert_eq!(limits[127], 4);
+		assert_eq!(limits[128], OAM_SIZE / 8);
+	}
+
+	#[test]
+	fn vram_mirror_boundaries() {
+		for (width, alignment_mask) in [(EAccessWidth::Byte, 0x0), (EAccessWidth::Halfword, 0x1), (EAccessWidth::Word, 0x3)] {
+			let last_obj_tiles_offset = 0x1_7ffe & !alignment_mask;
+			// NOTE: End of the OBJ tiles, then the upper 32KB mirroring them
+			assert_eq!(compute_vram_address(0x0601_7ffe, width), last_obj_tiles_offset, "{:?}", width);
+			assert_eq!(compute_vram_address(0x0601_8000, width), 0x1_0000, "{:?}", width);
+			assert_eq!(compute_vram_address(0x0601_fffe, width), last_obj_tiles_offset, "{:?}", width);
+			// NOTE: Then the whole 128KB again
+			assert_eq!(compute_vram_address(0x0602_0000, width), 0x0, "{:?}", width);
+			assert_eq!(compute_vram_address(0x0603_7ffe, width), last_obj_tiles_offset, "{:?}", width);
+			assert_eq!(compute_vram_address(0x0603_fffe, width), last_obj_tiles_offset, "{:?}", width);
+		}
+	}
+
+	#[test]
+	fn vram_mirror_accesses_reach_the_obj_tiles() {
+		let mut ppu = PPU::new();
+		ppu.write_16(0x0601_fffe, 0x1234);
+		assert_eq!(ppu.read_16(0x0601_7ffe), 0x1234);
+		assert_eq!(ppu.read_8(0x0601_ffff), 0x12);
+
+		ppu.write_32(0x0601_7ffc, 0xaabb_ccdd);
+		assert_eq!(ppu.read_32(0x0601_fffc), 0xaabb_ccdd);
+		assert_eq!(ppu.read_16(0x0603_fffe), 0xaabb);
+
+		// NOTE: Accesses of the containing halfword/word don't straddle the boundary
+		ppu.write_16(0x0601_8000, 0x5678);
+		assert_eq!(ppu.read_16(0x0601_0000), 0x5678);
+		assert_eq!(ppu.read_32(0x0601_7ffe), 0xaabb_ccdd);
+	}
+}
@@ -0,0 +1,59 @@
+/// Tiles are 8x8 pixels
+pub const TILE_WIDTH: usize = 8;
+pub const TILE_PIXELS: usize = TILE_WIDTH * TILE_WIDTH;
+
+/// Pixel format of a tile
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ETileFormat {
+	/// 4 bits per pixel, indexing one of the 16 palettes of 16 colors
+	Bpp4,
+	/// 8 bits per pixel, indexing the whole 256 colors palette
+	Bpp8,
+}
+
+impl ETileFormat {
+	pub fn from_is_256_palette(is_256_palette: bool) -> Self {
+		if is_256_palette {
+			ETileFormat::Bpp8
+		} else {
+			ETileFormat::Bpp4
+		}
+	}
+
+	/// Size of a tile in bytes
+	pub fn get_tile_length(&self) -> usize {
+		match self {
+			ETileFormat::Bpp4 => 32,
+			ETileFormat::Bpp8 => 64,
+		}
+	}
+}
+
+/// Color index of one pixel of a tile, 0 is transparent
+/// NOTE: For 4bpp tiles the index is inside the 16 color palette, the lower nibble is the left pixel
+pub fn decode_tile_pixel(tile: &[u8], format: ETileFormat, x: usize, y: usize) -> u8 {
+	let pixel = x + y * TILE_WIDTH;
+	match format {
+		ETileFormat::Bpp4 => (tile[pixel / 2] >> ((pixel & 1) * 4)) & 0xf,
+		ETileFormat::Bpp8 => tile[pixel],
+	}
+}
+
+/// Color indices of a whole tile, row by row
+pub fn decode_tile(tile: &[u8], format: ETileFormat) -> [u8; TILE_PIXELS] {
+	let mut indices = [0; TILE_PIXELS];
+	for (pixel, index) in indices.iter_mut().enumerate() {
+		*index = decode_tile_pixel(tile, format, pixel % TILE_WIDTH, pixel / TILE_WIDTH);
+	}
+
+	indices
+}
+
+/// Palette entry of a decoded color index (relative to the BG or OBJ palette), None for transparent pixels
+pub fn get_palette_index(index: u8, format: ETileFormat, palette_number: usize) -> Option<usize> {
+	match (index, format) {
+		(0, _) => None,
+		(_, ETileFormat::Bpp4) => Some(palette_number * 16 + index as usize),
+		(_, ETileFormat::Bpp8) => Some(index as usize),
+	}
+}
@@ -0,0 +1,259 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use crate::arm7tdmi::cpu::CPU;
+use crate::system::SystemBus;
+
+pub mod rewind;
+
+/// First bytes of every save state
+pub const SAVE_STATE_MAGIC: [u8; 8] = *b"GBASTATE";
+// NOTE: Bump whenever the layout of the state changes, states of other versions are refused instead of being misread
+pub const SAVE_STATE_VERSION: u32 = 9;
+
+/// Writes the values of a save state, little endian
+pub struct StateWriter<'a> {
+	writer: &'a mut dyn Write,
+}
+
+impl<'a> StateWriter<'a> {
+	pub fn new(writer: &'a mut dyn Write) -> Self {
+		Self { writer }
+	}
+
+	pub fn write_bytes(&mut self, data: &[u8]) -> Result<()> {
+		self.writer.write_all(data)
+	}
+
+	pub fn write_bool(&mut self, value: bool) -> Result<()> {
+		self.write_u8(value as u8)
+	}
+
+	pub fn write_u8(&mut self, value: u8) -> Result<()> {
+		self.write_bytes(&[value])
+	}
+
+	pub fn write_u16(&mut self, value: u16) -> Result<()> {
+		self.write_bytes(&value.to_le_bytes())
+	}
+
+	pub fn write_u32(&mut self, value: u32) -> Result<()> {
+		self.write_bytes(&value.to_le_bytes())
+	}
+
+	pub fn write_u64(&mut self, value: u64) -> Result<()> {
+		self.write_bytes(&value.to_le_bytes())
+	}
+
+	/// Data whose size can change (eg. save memory), prefixed by its length
+	pub fn write_sized_bytes(&mut self, data: &[u8]) -> Result<()> {
+		self.write_u32(data.len() as u32)?;
+		self.write_bytes(data)
+	}
+}
+
+/// Reads the values written by a StateWriter
+pub struct StateReader<'a> {
+	reader: &'a mut dyn Read,
+}
+
+impl<'a> StateReader<'a> {
+	pub fn new(reader: &'a mut dyn Read) -> Self {
+		Self { reader }
+	}
+
+	pub fn read_bytes(&mut self, data: &mut [u8]) -> Result<()> {
+		self.reader.read_exact(data)
+	}
+
+	pub fn read_bool(&mut self) -> Result<bool> {
+		Ok(self.read_u8()? != 0)
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8> {
+		let mut data = [0; 1];
+		self.read_bytes(&mut data)?;
+		Ok(data[0])
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16> {
+		let mut data = [0; 2];
+		self.read_bytes(&mut data)?;
+		Ok(u16::from_le_bytes(data))
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32> {
+		let mut data = [0; 4];
+		self.read_bytes(&mut data)?;
+		Ok(u32::from_le_bytes(data))
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64> {
+		let mut data = [0; 8];
+		self.read_bytes(&mut data)?;
+		Ok(u64::from_le_bytes(data))
+	}
+
+	/// NOTE: The length is checked against a maximum, so a corrupted state can't allocate all the memory
+	pub fn read_sized_bytes(&mut self, max_length: usize) -> Result<Vec<u8>> {
+		let length = self.read_u32()? as usize;
+		if length > max_length {
+			return Err(Error::new(ErrorKind::InvalidData, "Save state data is too long"));
+		}
+
+		let mut data = vec![0; length];
+		self.read_bytes(&mut data)?;
+		Ok(data)
+	}
+}
+
+/// A component whose state is part of save states
+///
+/// Only the emulated hardware state is saved, frontend settings (eg. input latching, debug options) and host input are left as they are.
+pub trait SaveState {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()>;
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()>;
+}
+
+/// Save the whole system, the state can only be loaded back with the same ROM
+pub fn save_state(writer: &mut dyn Write, cpu: &CPU, bus: &SystemBus, current_cycle: u32) -> Result<()> {
+	let mut writer = StateWriter::new(writer);
+	writer.write_bytes(&SAVE_STATE_MAGIC)?;
+	writer.write_u32(SAVE_STATE_VERSION)?;
+	writer.write_u32(crc32fast::hash(bus.get_cartridge().get_rom()))?;
+
+	writer.write_u32(current_cycle)?;
+	cpu.save_state(&mut writer)?;
+	bus.save_state(&mut writer)
+}
+
+/// Load a state written by save_state
+/// NOTE: The system is restored to how it was if the state turns out to be invalid midway
+pub fn load_state(reader: &mut dyn Read, cpu: &mut CPU, bus: &mut SystemBus, current_cycle: &mut u32) -> Result<()> {
+	let mut reader = StateReader::new(reader);
+	let mut magic = [0; 8];
+	reader.read_bytes(&mut magic)?;
+	if magic != SAVE_STATE_MAGIC {
+		return Err(Error::new(ErrorKind::InvalidData, "Not a save state"));
+	}
+
+	let version = reader.read_u32()?;
+	if version != SAVE_STATE_VERSION {
+		return Err(Error::new(
+			ErrorKind::InvalidData,
+			format!("Save state version {} is not supported (expected {})", version, SAVE_STATE_VERSION),
+		));
+	}
+
+	if reader.read_u32()? != crc32fast::hash(bus.get_cartridge().get_rom()) {
+		return Err(Error::new(ErrorKind::InvalidData, "Save state of a different ROM"));
+	}
+
+	let mut previous_state = Vec::new();
+	save_state(&mut previous_state, cpu, bus, *current_cycle)?;
+
+	let result = load_system(&mut reader, cpu, bus, current_cycle);
+	if result.is_err() {
+		load_state(&mut previous_state.as_slice(), cpu, bus, current_cycle).expect("The state before loading couldn't be restored");
+	}
+
+	result
+}
+
+fn load_system(reader: &mut StateReader, cpu: &mut CPU, bus: &mut SystemBus, current_cycle: &mut u32) -> Result<()> {
+	*current_cycle = reader.read_u32()?;
+	cpu.load_state(reader)?;
+	bus.load_state(reader)
+}
+
+#[cfg(test)]
+mod tests {
+	// NOTE: The fixtures are states of tests/arm/arm.gba after 10 frames with the HLE BIOS, gzipped, saved by the version in their name
+	// Add one whenever the version is bumped, the previous ones have to keep loading or be refused with a clear error
+	use std::io::Read;
+
+	use flate2::read::GzDecoder;
+
+	use super::*;
+	use crate::gba::Gba;
+
+	const ARM_ROM: &[u8] = include_bytes!("../../tests/arm/arm.gba");
+	const ARM_V8_STATE: &[u8] = include_bytes!("../../tests/savestates/arm_v8.state.gz");
+	const ARM_V9_STATE: &[u8] = include_bytes!("../../tests/savestates/arm_v9.state.gz");
+
+	fn decompress(fixture: &[u8]) -> Vec<u8> {
+		let mut state = Vec::new();
+		GzDecoder::new(fixture).read_to_end(&mut state).unwrap();
+		state
+	}
+
+	fn save(gba: &Gba) -> Vec<u8> {
+		let mut state = Vec::new();
+		gba.save_state(&mut state).unwrap();
+		state
+	}
+
+	fn new_gba(rom: &[u8]) -> Gba {
+		Gba::new(Box::new([]), rom.to_vec().into_boxed_slice())
+	}
+
+	#[test]
+	fn current_version_fixture_loads() {
+		let state = decompress(ARM_V9_STATE);
+		assert_eq!(state[8..12], SAVE_STATE_VERSION.to_le_bytes(), "Add a fixture for the new version");
+
+		let mut gba = new_gba(ARM_ROM);
+		gba.load_state(&mut state.as_slice()).unwrap();
+		assert_eq!(save(&gba), state);
+		// NOTE: The loaded system keeps running
+		gba.run_frame();
+	}
+
+	#[test]
+	fn round_trip_restores_the_whole_state() {
+		let mut gba = new_gba(ARM_ROM);
+		for _ in 0..5 {
+			gba.run_frame();
+		}
+		let state = save(&gba);
+		gba.run_frame();
+		let next_frame = gba.render_rgb8();
+
+		let mut loaded = new_gba(ARM_ROM);
+		loaded.load_state(&mut state.as_slice()).unwrap();
+		assert_eq!(save(&loaded), state);
+		loaded.run_frame();
+		assert_eq!(loaded.render_rgb8(), next_frame);
+		assert_eq!(save(&loaded), save(&gba));
+	}
+
+	#[test]
+	fn previous_version_fixture_is_refused() {
+		let mut gba = new_gba(ARM_ROM);
+		gba.run_frame();
+		let before = save(&gba);
+
+		let error = gba.load_state(&mut decompress(ARM_V8_STATE).as_slice()).unwrap_err();
+		assert_eq!(error.kind(), ErrorKind::InvalidData);
+		assert_eq!(error.to_string(), format!("Save state version 8 is not supported (expected {})", SAVE_STATE_VERSION));
+		assert_eq!(save(&gba), before);
+	}
+
+	#[test]
+	fn state_of_another_rom_is_refused() {
+		let mut gba = new_gba(include_bytes!("../../tests/thumb/thumb.gba"));
+		let error = gba.load_state(&mut decompress(ARM_V9_STATE).as_slice()).unwrap_err();
+		assert_eq!(error.to_string(), "Save state of a different ROM");
+	}
+
+	#[test]
+	fn truncated_state_is_refused_and_the_system_restored() {
+		let mut gba = new_gba(ARM_ROM);
+		gba.run_frame();
+		let before = save(&gba);
+
+		let state = decompress(ARM_V9_STATE);
+		let error = gba.load_state(&mut &state[..state.len() / 2]).unwrap_err();
+		assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+		assert_eq!(save(&gba), before);
+	}
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use std::io::Result;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::arm7tdmi::cpu::CPU;
+use crate::gba::ERefreshRate;
+use crate::savestate::{load_state, save_state};
+use crate::system::SystemBus;
+
+/// Frames between two rewind snapshots
+pub const REWIND_INTERVAL: u32 = 6;
+/// ~10 seconds of gameplay at one snapshot every REWIND_INTERVAL frames
+pub const REWIND_CAPACITY: usize = 100;
+
+/// Ring buffer of compressed save states taken while the game runs, to step backwards through the last seconds of gameplay
+pub struct RewindBuffer {
+	snapshots: VecDeque<Vec<u8>>,
+	capacity: usize,
+	interval: u32,
+	frames_since_snapshot: u32,
+}
+
+impl RewindBuffer {
+	pub fn new(interval: u32, capacity: usize) -> Self {
+		Self {
+			snapshots: VecDeque::with_capacity(capacity),
+			capacity,
+			interval: interval.max(1),
+			frames_since_snapshot: 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.snapshots.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.snapshots.is_empty()
+	}
+
+	/// Seconds of gameplay that can be rewound, at the hardware refresh rate
+	pub fn get_duration(&self) -> f32 {
+		(self.snapshots.len() as u32 * self.interval) as f32 / ERefreshRate::Hardware.get_frequency() as f32
+	}
+
+	/// NOTE: Call after a new game is loaded, the snapshots of the previous one can't be loaded anymore
+	pub fn clear(&mut self) {
+		self.snapshots.clear();
+		self.frames_since_snapshot = 0;
+	}
+
+	/// Call once per emulated frame, a snapshot is taken every interval frames and the oldest is dropped when the buffer is full
+	pub fn on_frame(&mut self, cpu: &CPU, bus: &SystemBus, current_cycle: u32) -> Result<()> {
+		self.frames_since_snapshot += 1;
+		if self.frames_since_snapshot < self.interval {
+			return Ok(());
+		}
+
+		self.frames_since_snapshot = 0;
+		self.push(cpu, bus, current_cycle)
+	}
+
+	pub fn push(&mut self, cpu: &CPU, bus: &SystemBus, current_cycle: u32) -> Result<()> {
+		let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+		save_state(&mut encoder, cpu, bus, current_cycle)?;
+		let snapshot = encoder.finish()?;
+
+		if self.snapshots.len() == self.capacity {
+			self.snapshots.pop_front();
+		}
+		self.snapshots.push_back(snapshot);
+		Ok(())
+	}
+
+	/// Load the most recent snapshot and drop it, so the next call goes further back
+	/// NOTE: Returns false once there is nothing left to rewind
+	pub fn rewind(&mut self, cpu: &mut CPU, bus: &mut SystemBus, current_cycle: &mut u32) -> Result<bool> {
+		let snapshot = match self.snapshots.pop_back() {
+			Some(snapshot) => snapshot,
+			None => return Ok(false),
+		};
+
+		self.frames_since_snapshot = 0;
+		load_state(&mut DeflateDecoder::new(snapshot.as_slice()), cpu, bus, current_cycle)?;
+		Ok(true)
+	}
+}
+
+impl Default for RewindBuffer {
+	fn default() -> Self {
+		Self::new(REWIND_INTERVAL, REWIND_CAPACITY)
+	}
+}
@@ -0,0 +1,451 @@
+use std::cell::Cell;
+
+pub const SRAM_SIZE: usize = 32 * 1024;
+pub const FLASH_BANK_SIZE: usize = 64 * 1024;
+pub const EEPROM_512_SIZE: usize = 512;
+pub const EEPROM_8K_SIZE: usize = 8 * 1024;
+
+// Flash command addresses
+const FLASH_COMMAND_ADDRESS_1: u32 = 0x5555;
+const FLASH_COMMAND_ADDRESS_2: u32 = 0x2aaa;
+
+// Flash commands, written to 0x5555 after the 0xaa/0x55 unlock sequence
+const FLASH_ENTER_ID_MODE: u8 = 0x90;
+const FLASH_EXIT_ID_MODE: u8 = 0xf0;
+const FLASH_PREPARE_ERASE: u8 = 0x80;
+const FLASH_ERASE_CHIP: u8 = 0x10;
+const FLASH_ERASE_SECTOR: u8 = 0x30;
+const FLASH_WRITE_BYTE: u8 = 0xa0;
+const FLASH_SET_BANK: u8 = 0xb0;
+
+const FLASH_SECTOR_SIZE: usize = 4 * 1024;
+
+/// Copy a .sav file into save memory, longer files are cut and shorter ones are padded with erased (0xff) bytes
+fn copy_padded(memory: &mut [u8], data: &[u8]) {
+	let length = data.len().min(memory.len());
+	memory[..length].copy_from_slice(&data[..length]);
+	memory[length..].iter_mut().for_each(|byte| *byte = 0xff);
+}
+
+/// Save memory types, the official libraries leave their name and version in the ROM (eg. "FLASH1M_V103")
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EBackupType {
+	None,
+	Sram,
+	Flash64K,
+	Flash128K,
+	/// NOTE: The size of the EEPROM (512B or 8KB) is only known once the game accesses it
+	Eeprom,
+}
+
+impl EBackupType {
+	/// Look for the library strings in the ROM, None if there are none
+	pub fn detect(rom: &[u8]) -> Self {
+		// NOTE: Ordered so the shorter names aren't matched first (FLASH_V vs FLASH1M_V)
+		const PATTERNS: [(&[u8], EBackupType); 5] = [
+			(b"EEPROM_V", EBackupType::Eeprom),
+			(b"SRAM_V", EBackupType::Sram),
+			(b"SRAM_F_V", EBackupType::Sram),
+			(b"FLASH1M_V", EBackupType::Flash128K),
+			(b"FLASH", EBackupType::Flash64K),
+		];
+
+		// NOTE: The strings are word aligned
+		for offset in (0..rom.len()).step_by(4) {
+			for (pattern, backup_type) in PATTERNS.iter() {
+				if rom[offset..].starts_with(pattern) {
+					return *backup_type;
+				}
+			}
+		}
+
+		EBackupType::None
+	}
+
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			EBackupType::None => "None",
+			EBackupType::Sram => "SRAM",
+			EBackupType::Flash64K => "Flash 64K",
+			EBackupType::Flash128K => "Flash 128K",
+			EBackupType::Eeprom => "EEPROM",
+		}
+	}
+}
+
+/// Flash chips found on cartridges, they only differ by their ID
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EFlashChip {
+	/// Atmel AT29LV512 (64K)
+	Atmel,
+	/// SST 39VF512 (64K)
+	Sst,
+	/// Macronix MX29L512 (64K)
+	Macronix64K,
+	/// Macronix MX29L010 (128K)
+	Macronix128K,
+}
+
+impl EFlashChip {
+	/// Manufacturer and device IDs, read at 0x0E000000/1 in ID mode
+	pub fn get_id(&self) -> (u8, u8) {
+		match self {
+			EFlashChip::Atmel => (0x1f, 0x3d),
+			EFlashChip::Sst => (0xbf, 0xd4),
+			EFlashChip::Macronix64K => (0xc2, 0x1c),
+			EFlashChip::Macronix128K => (0xc2, 0x09),
+		}
+	}
+
+	pub fn get_size(&self) -> usize {
+		match self {
+			EFlashChip::Macronix128K => 2 * FLASH_BANK_SIZE,
+			_ => FLASH_BANK_SIZE,
+		}
+	}
+}
+
+/// Progress through the unlock sequence of a command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EFlashState {
+	Ready,
+	/// 0xaa written to 0x5555
+	Unlock1,
+	/// 0x55 written to 0x2aaa
+	Unlock2,
+	/// Waiting for the byte to program
+	Write,
+	/// Waiting for the bank number at 0x0000
+	SetBank,
+}
+
+/// Flash memory, written through a command protocol and erased to 0xff
+pub struct Flash {
+	chip: EFlashChip,
+	data: Box<[u8]>,
+	state: EFlashState,
+	id_mode: bool,
+	/// Set by the erase prepare command, the next command is an erase
+	erase_mode: bool,
+	bank: usize,
+}
+
+impl Flash {
+	pub fn new(chip: EFlashChip) -> Self {
+		Self {
+			chip,
+			data: vec![0xff; chip.get_size()].into_boxed_slice(),
+			state: EFlashState::Ready,
+			id_mode: false,
+			erase_mode: false,
+			bank: 0,
+		}
+	}
+
+	pub fn get_chip(&self) -> EFlashChip {
+		self.chip
+	}
+
+	fn get_index(&self, offset: u32) -> usize {
+		self.bank * FLASH_BANK_SIZE + (offset as usize & (FLASH_BANK_SIZE - 1))
+	}
+
+	pub fn read_8(&self, offset: u32) -> u8 {
+		if self.id_mode && offset < 2 {
+			let (manufacturer, device) = self.chip.get_id();
+			if offset == 0 {
+				manufacturer
+			} else {
+				device
+			}
+		} else {
+			self.data[self.get_index(offset)]
+		}
+	}
+
+	pub fn write_8(&mut self, offset: u32, value: u8) {
+		let offset = offset & 0xffff;
+		self.state = match (self.state, offset, value) {
+			(EFlashState::Write, _, _) => {
+				// NOTE: Programming can only clear bits, they're set back by erasing
+				let index = self.get_index(offset);
+				self.data[index] &= value;
+				EFlashState::Ready
+			}
+			(EFlashState::SetBank, 0, _) => {
+				if self.chip.get_size() > FLASH_BANK_SIZE {
+					self.bank = value as usize & 0x1;
+				}
+				EFlashState::Ready
+			}
+			(EFlashState::Ready, FLASH_COMMAND_ADDRESS_1, 0xaa) => EFlashState::Unlock1,
+			(EFlashState::Unlock1, FLASH_COMMAND_ADDRESS_2, 0x55) => EFlashState::Unlock2,
+			(EFlashState::Unlock2, _, _) => self.execute_command(offset, value),
+			_ => EFlashState::Ready,
+		};
+	}
+
+	fn execute_command(&mut self, offset: u32, command: u8) -> EFlashState {
+		if self.erase_mode {
+			self.erase_mode = false;
+			match (offset, command) {
+				(FLASH_COMMAND_ADDRESS_1, FLASH_ERASE_CHIP) => self.data.iter_mut().for_each(|byte| *byte = 0xff),
+				(_, FLASH_ERASE_SECTOR) => {
+					let start = self.get_index(offset) & !(FLASH_SECTOR_SIZE - 1);
+					self.data[start..start + FLASH_SECTOR_SIZE].iter_mut().for_each(|byte| *byte = 0xff);
+				}
+				_ => {}
+			}
+
+			return EFlashState::Ready;
+		}
+
+		if offset != FLASH_COMMAND_ADDRESS_1 {
+			return EFlashState::Ready;
+		}
+
+		match command {
+			FLASH_ENTER_ID_MODE => self.id_mode = true,
+			FLASH_EXIT_ID_MODE => self.id_mode = false,
+			FLASH_PREPARE_ERASE => self.erase_mode = true,
+			FLASH_WRITE_BYTE => return EFlashState::Write,
+			FLASH_SET_BANK => return EFlashState::SetBank,
+			_ => {}
+		}
+
+		EFlashState::Ready
+	}
+}
+
+/// What the EEPROM is doing with the bits it receives
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EEepromState {
+	/// Receiving a request, up to its end bit
+	Receiving,
+	/// Sending the 4 dummy bits and the 64 bits of a read request
+	Sending { index: u32 },
+}
+
+/// Serial EEPROM, accessed one bit at a time at 0x0D000000 through (usually DMA) 16 bit transfers
+///
+/// Requests are "11" + address + "0" to read and "10" + address + 64 data bits + "0" to write, most significant bit first.
+/// The address is 6 bits for the 512B EEPROM and 14 bits for the 8KB one, each address selects a block of 8 bytes.
+pub struct Eeprom {
+	data: Box<[u8]>,
+	/// 6 or 14, None until the size is detected
+	address_bits: Option<u32>,
+	// NOTE: Reads shift the bits out, the bus reads through a shared reference
+	state: Cell<EEepromState>,
+	/// Bits received with the current request
+	buffer: u128,
+	buffer_length: u32,
+	read_address: usize,
+}
+
+impl Eeprom {
+	pub fn new() -> Self {
+		Self {
+			data: vec![0xff; EEPROM_8K_SIZE].into_boxed_slice(),
+			address_bits: None,
+			state: Cell::new(EEepromState::Receiving),
+			buffer: 0,
+			buffer_length: 0,
+			read_address: 0,
+		}
+	}
+
+	/// Size of the EEPROM, the largest one until it's detected
+	pub fn get_size(&self) -> usize {
+		match self.address_bits {
+			Some(6) => EEPROM_512_SIZE,
+			_ => EEPROM_8K_SIZE,
+		}
+	}
+
+	pub fn is_size_detected(&self) -> bool {
+		self.address_bits.is_some()
+	}
+
+	/// NOTE: Games always send whole requests with a single DMA, so its length tells the size of the address
+	pub fn detect_size(&mut self, transfer_length: u32) {
+		if self.address_bits.is_none() {
+			self.address_bits = match transfer_length {
+				9 | 73 => Some(6),
+				17 | 81 => Some(14),
+				_ => None,
+			};
+		}
+	}
+
+	/// Load a .sav file, its length tells the size of the EEPROM
+	pub fn load_data(&mut self, data: &[u8]) {
+		self.address_bits = Some(if data.len() <= EEPROM_512_SIZE { 6 } else { 14 });
+		let size = self.get_size();
+		copy_padded(&mut self.data[..size], data);
+	}
+
+	fn get_address_bits(&self) -> u32 {
+		self.address_bits.unwrap_or(14)
+	}
+
+	fn get_block_offset(&self, address: u128) -> usize {
+		(address as usize * 8) % self.get_size()
+	}
+
+	pub fn read_16(&self) -> u16 {
+		match self.state.get() {
+			EEepromState::Sending { index } => {
+				if index + 1 == 68 {
+					self.state.set(EEepromState::Receiving);
+				} else {
+					self.state.set(EEepromState::Sending { index: index + 1 });
+				}
+
+				// NOTE: The first 4 bits are ignored
+				if index < 4 {
+					0
+				} else {
+					let bit = index - 4;
+					((self.data[self.read_address + bit as usize / 8] >> (7 - bit % 8)) & 0x1) as u16
+				}
+			}
+			// NOTE: Writes are instant, the EEPROM is always ready
+			EEepromState::Receiving => 1,
+		}
+	}
+
+	pub fn write_16(&mut self, value: u16) {
+		if self.state.get() != EEepromState::Receiving {
+			return;
+		}
+
+		self.buffer = (self.buffer << 1) | (value & 0x1) as u128;
+		self.buffer_length += 1;
+
+		let address_bits = self.get_address_bits();
+		let is_read = self.buffer_length >= 2 && (self.buffer >> (self.buffer_length - 2)) == 0b11;
+		let request_length = if is_read { 2 + address_bits + 1 } else { 2 + address_bits + 64 + 1 };
+		if self.buffer_length < request_length {
+			return;
+		}
+
+		// NOTE: Drop the end bit
+		let request = self.buffer >> 1;
+		if is_read {
+			let address = request & ((1 << address_bits) - 1);
+			self.read_address = self.get_block_offset(address);
+			self.state.set(EEepromState::Sending { index: 0 });
+		} else {
+			let address = (request >> 64) & ((1 << address_bits) - 1);
+			let offset = self.get_block_offset(address);
+			self.data[offset..offset + 8].copy_from_slice(&(request as u64).to_be_bytes());
+		}
+
+		self.buffer = 0;
+		self.buffer_length = 0;
+	}
+}
+
+impl Default for Eeprom {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Save memory of the cartridge
+pub enum BackupDevice {
+	None,
+	Sram(Box<[u8]>),
+	Flash(Flash),
+	Eeprom(Eeprom),
+}
+
+impl BackupDevice {
+	pub fn new(backup_type: EBackupType) -> Self {
+		match backup_type {
+			EBackupType::None => BackupDevice::None,
+			EBackupType::Sram => BackupDevice::Sram(vec![0xff; SRAM_SIZE].into_boxed_slice()),
+			EBackupType::Flash64K => BackupDevice::Flash(Flash::new(EFlashChip::Sst)),
+			EBackupType::Flash128K => BackupDevice::Flash(Flash::new(EFlashChip::Macronix128K)),
+			EBackupType::Eeprom => BackupDevice::Eeprom(Eeprom::new()),
+		}
+	}
+
+	pub fn get_type(&self) -> EBackupType {
+		match self {
+			BackupDevice::None => EBackupType::None,
+			BackupDevice::Sram(_) => EBackupType::Sram,
+			BackupDevice::Flash(flash) if flash.chip.get_size() > FLASH_BANK_SIZE => EBackupType::Flash128K,
+			BackupDevice::Flash(_) => EBackupType::Flash64K,
+			BackupDevice::Eeprom(_) => EBackupType::Eeprom,
+		}
+	}
+
+	pub fn get_eeprom(&self) -> Option<&Eeprom> {
+		match self {
+			BackupDevice::Eeprom(eeprom) => Some(eeprom),
+			_ => None,
+		}
+	}
+
+	pub fn get_mut_eeprom(&mut self) -> Option<&mut Eeprom> {
+		match self {
+			BackupDevice::Eeprom(eeprom) => Some(eeprom),
+			_ => None,
+		}
+	}
+
+	/// Raw content of the save memory (the .sav format), empty without backup
+	pub fn get_data(&self) -> &[u8] {
+		match self {
+			BackupDevice::None => &[],
+			BackupDevice::Sram(data) => data,
+			BackupDevice::Flash(flash) => &flash.data,
+			BackupDevice::Eeprom(eeprom) => &eeprom.data[..eeprom.get_size()],
+		}
+	}
+
+	/// Replace the content of the save memory with a .sav file
+	/// NOTE: The files of other emulators are the raw chip content too, so they're used as is
+	pub fn load_data(&mut self, data: &[u8]) {
+		match self {
+			BackupDevice::None => {}
+			BackupDevice::Sram(sram) => copy_padded(sram, data),
+			BackupDevice::Flash(flash) => copy_padded(&mut flash.data, data),
+			BackupDevice::Eeprom(eeprom) => eeprom.load_data(data),
+		}
+	}
+
+	/// Read from the SRAM/Flash region (0x0E000000, mirrored at 0x0F000000)
+	pub fn read_8(&self, offset: u32) -> u8 {
+		match self {
+			BackupDevice::Sram(data) => data[offset as usize % data.len()],
+			BackupDevice::Flash(flash) => flash.read_8(offset),
+			// NOTE: Unconnected data lines are pulled up
+			BackupDevice::None | BackupDevice::Eeprom(_) => 0xff,
+		}
+	}
+
+	/// Write to the SRAM/Flash region (0x0E000000, mirrored at 0x0F000000)
+	pub fn write_8(&mut self, offset: u32, value: u8) {
+		match self {
+			BackupDevice::Sram(data) => {
+				let index = offset as usize % data.len();
+				data[index] = value;
+			}
+			BackupDevice::Flash(flash) => flash.write_8(offset, value),
+			BackupDevice::None | BackupDevice::Eeprom(_) => {}
+		}
+	}
+
+	/// Overwrite a byte of the SRAM/Flash region for the debugger, Flash bytes are stored as is instead of being commands
+	pub fn patch_8(&mut self, offset: u32, value: u8) {
+		match self {
+			BackupDevice::Flash(flash) => {
+				let index = flash.get_index(offset);
+				flash.data[index] = value;
+			}
+			_ => self.write_8(offset, value),
+		}
+	}
+}
@@ -0,0 +1,509 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::path::{Path, PathBuf};
+
+use bitfield::Bit;
+
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::system::backup::{BackupDevice, EBackupType, FLASH_BANK_SIZE};
+use crate::system::rtc::Rtc;
+use crate::system::sensors::{GyroSensor, SolarSensor};
+
+/// GPIO port registers, mapped over the ROM header
+pub const GPIO_DATA_ADDRESS: u32 = 0xc4;
+pub const GPIO_DIRECTION_ADDRESS: u32 = 0xc6;
+pub const GPIO_CONTROL_ADDRESS: u32 = 0xc8;
+
+/// The EEPROM is mapped over the upper half of the ROM address space (0x0D000000)
+/// NOTE: On 32MB ROMs it's only at the last 256 bytes, but those don't use EEPROM saves
+pub const EEPROM_ROM_OFFSET: u32 = 0x0100_0000;
+
+fn get_extension(path: &Path) -> Option<String> {
+	path.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase())
+}
+
+/// Whether a file looks like something load_rom_file can open, going by its extension
+pub fn is_rom_file(path: &Path) -> bool {
+	matches!(get_extension(path).as_deref(), Some("gba") | Some("mb") | Some("zip"))
+}
+
+/// The .sav file next to a ROM, where its battery backed save memory is kept (eg. game.gba -> game.sav)
+pub fn get_save_path(rom_path: &Path) -> PathBuf {
+	rom_path.with_extension("sav")
+}
+
+/// Cartridge type to emulate for a file, going by its extension
+/// NOTE: The save type isn't stored in the header, the cartridge detects it from the ROM
+pub fn get_cartridge_type(path: &Path) -> ECartridgeType {
+	match get_extension(path).as_deref() {
+		Some("mb") => ECartridgeType::Multiboot,
+		_ => ECartridgeType::RomSram,
+	}
+}
+
+/// Read a cartridge ROM from a .gba (or .mb) file or the first .gba file inside a .zip archive
+/// NOTE: The ROM keeps its size, reads past its end return the open bus pattern
+pub fn load_rom_file(path: &Path) -> std::io::Result<Vec<u8>> {
+	let mut file = File::open(path)?;
+	let mut data = Vec::<u8>::new();
+
+	if get_extension(path).as_deref() == Some("zip") {
+		let mut archive = zip::ZipArchive::new(file).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+		let name = archive
+			.file_names()
+			.find(|name| name.to_ascii_lowercase().ends_with(".gba"))
+			.map(|name| name.to_owned())
+			.ok_or_else(|| Error::new(ErrorKind::NotFound, "No .gba file in the archive"))?;
+		archive.by_name(&name).map_err(|e| Error::new(ErrorKind::InvalidData, e))?.read_to_end(&mut data)?;
+	} else {
+		file.read_to_end(&mut data)?;
+	}
+
+	Ok(data)
+}
+
+/// The 4 character game code in the cartridge header (eg. "AXVE"), "????" for ROMs without a printable one
+pub fn get_game_code(rom: &[u8]) -> String {
+	match rom.get(0xac..0xb0) {
+		Some(code) if code.iter().all(|c| c.is_ascii_alphanumeric()) => code.iter().map(|c| *c as char).collect(),
+		_ => "????".to_string(),
+	}
+}
+
+/// Hardware found on a cartridge
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ECartridgeType {
+	/// Empty slot, ROM reads return the open bus pattern
+	None,
+	RomOnly,
+	RomSram,
+	RomFlashRtc,
+	/// Image sent through the link cable, it runs from EWRAM without a cartridge
+	Multiboot,
+}
+
+impl ECartridgeType {
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ECartridgeType::None => "None",
+			ECartridgeType::RomOnly => "ROM",
+			ECartridgeType::RomSram => "ROM+SRAM",
+			ECartridgeType::RomFlashRtc => "ROM+Flash+RTC",
+			ECartridgeType::Multiboot => "Multiboot",
+		}
+	}
+}
+
+/// Metadata of the cartridge header
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+	pub title: String,
+	pub game_code: String,
+	pub maker_code: String,
+	pub version: u8,
+	pub is_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+	pub fn parse(rom: &[u8]) -> Self {
+		let get_string = |start: usize, end: usize| match rom.get(start..end) {
+			Some(bytes) => bytes.iter().take_while(|c| **c != 0).map(|c| *c as char).collect::<String>().trim().to_string(),
+			None => String::new(),
+		};
+		// NOTE: Complement check of 0xa0-0xbc, checked by the BIOS before booting
+		let is_checksum_valid = match rom.get(0xa0..=0xbd) {
+			Some(bytes) => bytes[..0x1d].iter().fold(0u8, |sum, c| sum.wrapping_sub(*c)).wrapping_sub(0x19) == bytes[0x1d],
+			None => false,
+		};
+
+		Self {
+			title: get_string(0xa0, 0xac),
+			game_code: get_game_code(rom),
+			maker_code: get_string(0xb0, 0xb2),
+			version: rom.get(0xbc).cloned().unwrap_or_default(),
+			is_checksum_valid,
+		}
+	}
+}
+
+/// Name of the RTC library the games using one contain
+const RTC_LIBRARY_NAME: &[u8] = b"SIIRTC_V";
+
+/// Whether a ROM talks to an RTC, going by the library it contains
+/// NOTE: Like the save memory names, the string is word aligned
+pub fn has_rtc(rom: &[u8]) -> bool {
+	(0..rom.len()).step_by(4).any(|offset| rom[offset..].starts_with(RTC_LIBRARY_NAME))
+}
+
+/// Game codes (without the region letter) of the games with a solar sensor: Boktai 1, 2 and 3
+const SOLAR_SENSOR_GAMES: [&str; 3] = ["U3I", "U32", "U33"];
+/// Game codes of the games with a gyro sensor and a rumble motor: WarioWare: Twisted!
+const GYRO_SENSOR_GAMES: [&str; 1] = ["RZW"];
+
+/// General purpose IO port used by the RTC, solar and tilt sensors
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Gpio {
+	/// Pins written by the GBA, the input pins are read from the devices
+	data: u16,
+	/// Bits set are outputs (written by the GBA)
+	direction: u16,
+	/// Whether the registers can be read back, otherwise the ROM is visible
+	readable: bool,
+	rtc: Option<Rtc>,
+	solar_sensor: Option<SolarSensor>,
+	gyro_sensor: Option<GyroSensor>,
+}
+
+impl Gpio {
+	/// Value of the pins: outputs as written by the GBA, inputs as driven by the devices
+	pub fn get_data(&self) -> u16 {
+		let input = self.rtc.map_or(0, |rtc| rtc.read_pins()) | self.solar_sensor.map_or(0, |sensor| sensor.read_pins()) | self.gyro_sensor.map_or(0, |sensor| sensor.read_pins());
+		((self.data & self.direction) | (input & !self.direction)) & 0xf
+	}
+
+	pub fn get_direction(&self) -> u16 {
+		self.direction
+	}
+
+	pub fn is_readable(&self) -> bool {
+		self.readable
+	}
+
+	pub fn get_rtc(&self) -> Option<&Rtc> {
+		self.rtc.as_ref()
+	}
+
+	pub fn get_solar_sensor(&self) -> Option<&SolarSensor> {
+		self.solar_sensor.as_ref()
+	}
+
+	pub fn get_mut_solar_sensor(&mut self) -> Option<&mut SolarSensor> {
+		self.solar_sensor.as_mut()
+	}
+
+	pub fn get_gyro_sensor(&self) -> Option<&GyroSensor> {
+		self.gyro_sensor.as_ref()
+	}
+
+	pub fn get_mut_gyro_sensor(&mut self) -> Option<&mut GyroSensor> {
+		self.gyro_sensor.as_mut()
+	}
+
+	fn read_16(&self, offset: u32) -> Option<u16> {
+		if !self.readable {
+			return None;
+		}
+
+		match offset {
+			GPIO_DATA_ADDRESS => Some(self.get_data()),
+			GPIO_DIRECTION_ADDRESS => Some(self.direction & 0xf),
+			GPIO_CONTROL_ADDRESS => Some(self.readable as u16),
+			_ => None,
+		}
+	}
+
+	fn write_16(&mut self, offset: u32, value: u16) {
+		match offset {
+			GPIO_DATA_ADDRESS => self.data = (self.data & !self.direction) | (value & self.direction & 0xf),
+			GPIO_DIRECTION_ADDRESS => self.direction = value & 0xf,
+			GPIO_CONTROL_ADDRESS => self.readable = value.bit(0),
+			_ => {}
+		}
+
+		// NOTE: Input pins are pulled low for the devices
+		let pins = self.data & self.direction;
+		if let Some(rtc) = self.rtc.as_mut() {
+			rtc.write_pins(pins);
+		}
+		if let Some(sensor) = self.solar_sensor.as_mut() {
+			sensor.write_pins(pins);
+		}
+		if let Some(sensor) = self.gyro_sensor.as_mut() {
+			sensor.write_pins(pins);
+		}
+	}
+}
+
+/// A GamePak: ROM, save memory and extra hardware
+pub struct Cartridge {
+	cartridge_type: ECartridgeType,
+	rom: Box<[u8]>,
+	header: CartridgeHeader,
+	backup: BackupDevice,
+	/// Whether the game wrote to the save memory since it was last persisted
+	backup_dirty: bool,
+	gpio: Option<Gpio>,
+}
+
+impl Cartridge {
+	/// ROM with SRAM, the most common setup
+	pub fn new(rom: Box<[u8]>) -> Self {
+		Self::new_with_type(rom, ECartridgeType::RomSram)
+	}
+
+	/// NOTE: ROM+SRAM cartridges become ROM+Flash+RTC ones when the game uses the RTC library
+	pub fn new_with_type(rom: Box<[u8]>, cartridge_type: ECartridgeType) -> Self {
+		let header = CartridgeHeader::parse(&rom);
+		let cartridge_type = match cartridge_type {
+			ECartridgeType::RomSram if has_rtc(&rom) => ECartridgeType::RomFlashRtc,
+			_ => cartridge_type,
+		};
+		// NOTE: Games without the library strings get the save memory of the cartridge type
+		let backup_type = match (cartridge_type, EBackupType::detect(&rom)) {
+			(ECartridgeType::RomSram, EBackupType::None) => EBackupType::Sram,
+			(ECartridgeType::RomFlashRtc, EBackupType::None) => EBackupType::Flash128K,
+			(ECartridgeType::RomSram, detected) | (ECartridgeType::RomFlashRtc, detected) => detected,
+			_ => EBackupType::None,
+		};
+		let backup = BackupDevice::new(backup_type);
+		let game_code = &header.game_code[..3];
+		let gpio = Gpio {
+			rtc: (cartridge_type == ECartridgeType::RomFlashRtc).then(Rtc::new),
+			solar_sensor: SOLAR_SENSOR_GAMES.contains(&game_code).then(SolarSensor::new),
+			gyro_sensor: GYRO_SENSOR_GAMES.contains(&game_code).then(GyroSensor::new),
+			..Gpio::default()
+		};
+		let has_gpio = gpio.rtc.is_some() || gpio.solar_sensor.is_some() || gpio.gyro_sensor.is_some();
+
+		Self {
+			cartridge_type,
+			rom,
+			header,
+			backup,
+			backup_dirty: false,
+			gpio: if has_gpio { Some(gpio) } else { None },
+		}
+	}
+
+	/// Empty cartridge slot
+	pub fn none() -> Self {
+		Self::new_with_type(Vec::new().into_boxed_slice(), ECartridgeType::None)
+	}
+
+	pub fn get_type(&self) -> ECartridgeType {
+		self.cartridge_type
+	}
+
+	pub fn get_header(&self) -> &CartridgeHeader {
+		&self.header
+	}
+
+	/// NOTE: Multiboot images are in here too, the bus copies them to EWRAM
+	pub fn get_rom(&self) -> &[u8] {
+		&self.rom
+	}
+
+	/// Overwrite ROM bytes, eg. for the debugger to patch instructions, the bytes past the end of the ROM are dropped
+	pub fn patch_rom(&mut self, offset: u32, data: &[u8]) {
+		let start = (offset as usize).min(self.rom.len());
+		let end = (start + data.len()).min(self.rom.len());
+		self.rom[start..end].copy_from_slice(&data[..end - start]);
+	}
+
+	/// Overwrite bytes of the SRAM/Flash region, eg. to load a dump of it from the debugger
+	pub fn patch_backup(&mut self, offset: u32, data: &[u8]) {
+		for (i, value) in data.iter().enumerate() {
+			self.backup.patch_8(offset.wrapping_add(i as u32), *value);
+		}
+		self.backup_dirty = true;
+	}
+
+	pub fn get_backup(&self) -> &BackupDevice {
+		&self.backup
+	}
+
+	pub fn get_mut_backup(&mut self) -> &mut BackupDevice {
+		&mut self.backup
+	}
+
+	/// Replace the content of the save memory with a .sav file
+	pub fn load_backup_data(&mut self, data: &[u8]) {
+		self.backup.load_data(data);
+		self.backup_dirty = true;
+	}
+
+	pub fn is_backup_dirty(&self) -> bool {
+		self.backup_dirty
+	}
+
+	pub fn set_backup_dirty(&mut self, value: bool) {
+		self.backup_dirty = value;
+	}
+
+	pub fn get_gpio(&self) -> Option<&Gpio> {
+		self.gpio.as_ref()
+	}
+
+	/// NOTE: For the frontend to feed the sensors with host input
+	pub fn get_mut_gpio(&mut self) -> Option<&mut Gpio> {
+		self.gpio.as_mut()
+	}
+
+	/// Whether a ROM offset accesses the EEPROM instead of the ROM
+	pub fn is_eeprom_offset(&self, offset: u32) -> bool {
+		offset >= EEPROM_ROM_OFFSET && self.backup.get_eeprom().is_some()
+	}
+
+	/// NOTE: The EEPROM is only accessed with DMA, its transfer length tells the size of the chip
+	pub fn on_eeprom_dma(&mut self, transfer_length: u32) {
+		if let Some(eeprom) = self.backup.get_mut_eeprom() {
+			eeprom.detect_size(transfer_length);
+		}
+	}
+
+	fn is_rom_mapped(&self) -> bool {
+		!matches!(self.cartridge_type, ECartridgeType::None | ECartridgeType::Multiboot)
+	}
+
+	/// Read from the ROM regions (WS0-2), offset is relative to the start of the region (the WS0-2 mirrors are already masked to 32MB)
+	/// NOTE: Past the end of the ROM, or without a ROM, the bus returns the address lines: (address / 2) & 0xffff for each halfword
+	pub fn read_rom_8(&self, offset: u32) -> u8 {
+		match self.rom.get(offset as usize).filter(|_| self.is_rom_mapped()) {
+			Some(value) => *value,
+			None => (Self::get_open_bus_16(offset) >> ((offset & 0x1) * 8)) as u8,
+		}
+	}
+
+	pub fn read_rom_16(&self, offset: u32) -> u16 {
+		if let Some(eeprom) = self.backup.get_eeprom().filter(|_| offset >= EEPROM_ROM_OFFSET) {
+			return eeprom.read_16();
+		}
+		if let Some(value) = self.gpio.as_ref().and_then(|gpio| gpio.read_16(offset)) {
+			return value;
+		}
+
+		// NOTE: The bus ignores the lowest address bit of halfword accesses
+		let addr = (offset & !0x1) as usize;
+		match self.rom.get(addr..addr + 2).filter(|_| self.is_rom_mapped()) {
+			Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+			None => Self::get_open_bus_16(offset),
+		}
+	}
+
+	pub fn read_rom_32(&self, offset: u32) -> u32 {
+		let addr = (offset & !0x3) as usize;
+		let is_plain_rom = self.is_rom_mapped() && self.gpio.is_none() && !self.is_eeprom_offset(offset);
+		match self.rom.get(addr..addr + 4).filter(|_| is_plain_rom) {
+			Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+			None => self.read_rom_16(addr as u32) as u32 | (self.read_rom_16(addr as u32 + 2) as u32) << 16,
+		}
+	}
+
+	/// Value of the address lines left on the bus by a halfword read past the end of the ROM
+	fn get_open_bus_16(offset: u32) -> u16 {
+		((offset / 2) & 0xffff) as u16
+	}
+
+	/// ROM is read-only, writes only reach the EEPROM and the GPIO port, returns whether anything handled the write
+	pub fn write_rom_16(&mut self, offset: u32, value: u16) -> bool {
+		if let Some(eeprom) = self.backup.get_mut_eeprom().filter(|_| offset >= EEPROM_ROM_OFFSET) {
+			eeprom.write_16(value);
+			self.backup_dirty = true;
+			return true;
+		}
+
+		match self.gpio.as_mut() {
+			Some(gpio) if (GPIO_DATA_ADDRESS..=GPIO_CONTROL_ADDRESS).contains(&offset) => {
+				gpio.write_16(offset, value);
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Save memory has an 8 bit bus, wider reads repeat the byte
+	pub fn read_backup_8(&self, offset: u32) -> u8 {
+		self.backup.read_8(offset)
+	}
+
+	pub fn read_backup_16(&self, offset: u32) -> u16 {
+		self.backup.read_8(offset) as u16 * 0x0101
+	}
+
+	pub fn read_backup_32(&self, offset: u32) -> u32 {
+		self.backup.read_8(offset) as u32 * 0x0101_0101
+	}
+
+	/// Save memory has an 8 bit bus, wider writes only store the byte of the addressed lane
+	/// NOTE: Flash commands mark the save memory as dirty too, it only costs a redundant flush
+	pub fn write_backup_8(&mut self, offset: u32, value: u8) {
+		self.backup.write_8(offset, value);
+		self.backup_dirty = true;
+	}
+
+	pub fn write_backup_16(&mut self, offset: u32, value: u16) {
+		self.write_backup_8(offset, (value >> ((offset & 0x1) * 8)) as u8);
+	}
+
+	pub fn write_backup_32(&mut self, offset: u32, value: u32) {
+		self.write_backup_8(offset, (value >> ((offset & 0x3) * 8)) as u8);
+	}
+}
+
+impl SaveState for Cartridge {
+	/// NOTE: The ROM isn't part of the state, only what the game can change
+	/// The Flash/EEPROM command progress isn't either, games don't save in the middle of a command
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_sized_bytes(self.backup.get_data())?;
+		let gpio = self.gpio.unwrap_or_default();
+		writer.write_u16(gpio.data)?;
+		writer.write_u16(gpio.direction)?;
+		writer.write_bool(gpio.readable)?;
+		gpio.rtc.unwrap_or_default().save_state(writer)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		let backup_data = reader.read_sized_bytes(2 * FLASH_BANK_SIZE)?;
+		// NOTE: Not a write of the game, the .sav is only rewritten once the game saves again
+		self.backup.load_data(&backup_data);
+		let (data, direction, readable) = (reader.read_u16()?, reader.read_u16()?, reader.read_bool()?);
+		let mut rtc = Rtc::new();
+		rtc.load_state(reader)?;
+		// NOTE: The sensors only hold host input and the progress of the current sample, they're left as they are
+		if let Some(gpio) = self.gpio.as_mut() {
+			gpio.data = data;
+			gpio.direction = direction;
+			gpio.readable = readable;
+			if let Some(current) = gpio.rtc.as_mut() {
+				*current = rtc;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rom_file_keeps_its_size() {
+		let path = std::env::temp_dir().join("gba_rustmulator_rom_size.gba");
+		std::fs::write(&path, [0x12, 0x34, 0x56, 0x78]).unwrap();
+		let data = load_rom_file(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(data, [0x12, 0x34, 0x56, 0x78]);
+	}
+
+	#[test]
+	fn reads_past_the_rom_return_the_open_bus_pattern() {
+		let cartridge = Cartridge::new(vec![0x12, 0x34, 0x56, 0x78].into_boxed_slice());
+
+		assert_eq!(cartridge.read_rom_16(0x2), 0x7856);
+		assert_eq!(cartridge.read_rom_16(0x4), 0x0002);
+		assert_eq!(cartridge.read_rom_8(0x1_0003), 0x80);
+		assert_eq!(cartridge.read_rom_32(0x20_0000), 0x0001_0000);
+	}
+
+	#[test]
+	fn loading_a_state_keeps_the_backup_clean() {
+		let mut cartridge = Cartridge::new(vec![0; 0x200].into_boxed_slice());
+		let mut state = Vec::new();
+		cartridge.save_state(&mut StateWriter::new(&mut state)).unwrap();
+
+		cartridge.load_state(&mut StateReader::new(&mut state.as_slice())).unwrap();
+		assert!(!cartridge.is_backup_dirty());
+	}
+}
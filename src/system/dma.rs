@@ -0,0 +1,545 @@
+use std::io::Result;
+
+use bitfield::*;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::system::MemoryInterface;
+
+pub const DMA_REGISTERS_START: u32 = 0xb0;
+pub const DMA_REGISTERS_END: u32 = 0xdf;
+pub const DMA_CHANNEL_COUNT: usize = 4;
+/// Size of the registers of one channel: SAD (32), DAD (32), CNT_L (16), CNT_H (16)
+const DMA_CHANNEL_REGISTERS_SIZE: u32 = 12;
+/// Bits of DMA CNT_H that exist, the others always read back as 0
+const DMA_CONTROL_MASK: u16 = 0xf7e0;
+/// Only DMA3 has the Game Pak DRQ bit
+const DMA3_CONTROL_MASK: u16 = 0xffe0;
+
+#[derive(Debug, Copy, Clone, FromPrimitive, PartialEq)]
+pub enum EDmaAddressControl {
+	Increment,
+	Decrement,
+	Fixed,
+	/// Increment during the transfer and go back to the start address on repeats (destination only)
+	IncrementReload,
+}
+
+#[derive(Debug, Copy, Clone, FromPrimitive, PartialEq)]
+pub enum EDmaStartTiming {
+	Immediately,
+	VBlank,
+	HBlank,
+	/// Sound FIFO (DMA1/2) or Video Capture (DMA3)
+	Special,
+}
+
+bitfield! {
+	/// DMA Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct DmaControl(u16);
+	impl Debug;
+	pub u8, get_dest_address_control_bits, _: 6, 5;
+	pub u8, get_source_address_control_bits, _: 8, 7;
+	pub get_repeat, _: 9;
+	pub get_is_32bit, _: 10;
+	pub get_game_pak_drq, _: 11;
+	pub u8, get_start_timing_bits, _: 13, 12;
+	pub get_irq, _: 14;
+	pub get_enabled, set_enabled: 15;
+}
+
+impl DmaControl {
+	pub fn get_dest_address_control(&self) -> EDmaAddressControl {
+		EDmaAddressControl::from_u8(self.get_dest_address_control_bits()).unwrap()
+	}
+
+	pub fn get_source_address_control(&self) -> EDmaAddressControl {
+		EDmaAddressControl::from_u8(self.get_source_address_control_bits()).unwrap()
+	}
+
+	pub fn get_start_timing(&self) -> EDmaStartTiming {
+		EDmaStartTiming::from_u8(self.get_start_timing_bits()).unwrap()
+	}
+}
+
+/// One of the 4 DMA channels, lower channels have higher priority
+#[derive(Debug, Copy, Clone)]
+pub struct DmaChannel {
+	index: usize,
+	// Registers
+	source_address: u32,
+	dest_address: u32,
+	word_count: u16,
+	control: DmaControl,
+	// Internal state, latched when the channel gets enabled
+	internal_source: u32,
+	internal_dest: u32,
+	internal_count: u32,
+	pending: bool,
+}
+
+impl DmaChannel {
+	fn new(index: usize) -> Self {
+		Self {
+			index,
+			source_address: 0,
+			dest_address: 0,
+			word_count: 0,
+			control: DmaControl(0),
+			internal_source: 0,
+			internal_dest: 0,
+			internal_count: 0,
+			pending: false,
+		}
+	}
+
+	pub fn get_control(&self) -> &DmaControl {
+		&self.control
+	}
+
+	pub fn is_pending(&self) -> bool {
+		self.pending
+	}
+
+	/// Only DMA3 can access the GamePak ROM as a source and every region above it as a destination
+	fn get_source_mask(&self) -> u32 {
+		if self.index == 0 {
+			0x07ff_ffff
+		} else {
+			0x0fff_ffff
+		}
+	}
+
+	fn get_dest_mask(&self) -> u32 {
+		if self.index == 3 {
+			0x0fff_ffff
+		} else {
+			0x07ff_ffff
+		}
+	}
+
+	/// Number of units to transfer, a count of 0 means the maximum
+	fn get_count(&self) -> u32 {
+		if self.index == 3 {
+			if self.word_count == 0 {
+				0x1_0000
+			} else {
+				self.word_count as u32
+			}
+		} else {
+			let count = self.word_count as u32 & 0x3fff;
+			if count == 0 {
+				0x4000
+			} else {
+				count
+			}
+		}
+	}
+
+	fn is_fifo(&self) -> bool {
+		(self.index == 1 || self.index == 2) && self.control.get_start_timing() == EDmaStartTiming::Special
+	}
+
+	fn get_control_mask(&self) -> u16 {
+		if self.index == 3 {
+			DMA3_CONTROL_MASK
+		} else {
+			DMA_CONTROL_MASK
+		}
+	}
+
+	fn set_control(&mut self, value: u16) {
+		let was_enabled = self.control.get_enabled();
+		self.control.0 = value & self.get_control_mask();
+
+		if !was_enabled && self.control.get_enabled() {
+			self.internal_source = self.source_address & self.get_source_mask();
+			self.internal_dest = self.dest_address & self.get_dest_mask();
+			self.internal_count = self.get_count();
+			self.pending = self.control.get_start_timing() == EDmaStartTiming::Immediately;
+		} else if !self.control.get_enabled() {
+			self.pending = false;
+		}
+	}
+
+	fn trigger(&mut self, timing: EDmaStartTiming) {
+		if self.control.get_enabled() && self.control.get_start_timing() == timing {
+			self.pending = true;
+		}
+	}
+}
+
+/// A single transfer unit (halfword or word) of a DMA transfer
+#[derive(Debug, Copy, Clone)]
+pub struct DmaTransfer {
+	pub channel: usize,
+	pub source: u32,
+	pub dest: u32,
+	pub count: u32,
+	pub is_32bit: bool,
+	pub source_step: i32,
+	pub dest_step: i32,
+	pub irq: bool,
+}
+
+/// The 4 DMA channels, transfers are performed by the system bus which owns the memories
+pub struct DmaController {
+	channels: [DmaChannel; DMA_CHANNEL_COUNT],
+}
+
+impl DmaController {
+	pub fn new() -> Self {
+		Self {
+			channels: [DmaChannel::new(0), DmaChannel::new(1), DmaChannel::new(2), DmaChannel::new(3)],
+		}
+	}
+
+	pub fn get_channels(&self) -> &[DmaChannel] {
+		&self.channels
+	}
+
+	/// Start the channels waiting for V-Blank
+	pub fn on_v_blank(&mut self) {
+		for channel in self.channels.iter_mut() {
+			channel.trigger(EDmaStartTiming::VBlank);
+		}
+	}
+
+	/// Start the channels waiting for H-Blank
+	/// NOTE: H-Blank transfers don't happen during V-Blank
+	pub fn on_h_blank(&mut self) {
+		for channel in self.channels.iter_mut() {
+			channel.trigger(EDmaStartTiming::HBlank);
+		}
+	}
+
+	/// Sound FIFO A/B asking for more samples, starts the DMA1/2 channels with special timing writing to it
+	pub fn request_fifo(&mut self, fifo_address: u32) {
+		for channel in self.channels[1..=2].iter_mut() {
+			if channel.internal_dest == fifo_address {
+				channel.trigger(EDmaStartTiming::Special);
+			}
+		}
+	}
+
+	/// Take the highest priority pending transfer and advance the channel past it
+	pub fn take_transfer(&mut self) -> Option<DmaTransfer> {
+		let channel = self.channels.iter_mut().find(|c| c.pending)?;
+		channel.pending = false;
+
+		let control = channel.control;
+		let is_fifo = channel.is_fifo();
+		// NOTE: FIFO transfers are always 4 words to a fixed address
+		let is_32bit = is_fifo || control.get_is_32bit();
+		let count = if is_fifo { 4 } else { channel.internal_count };
+		let unit_size = if is_32bit { 4 } else { 2 };
+		let step = |address_control| match address_control {
+			EDmaAddressControl::Increment | EDmaAddressControl::IncrementReload => unit_size,
+			EDmaAddressControl::Decrement => -unit_size,
+			EDmaAddressControl::Fixed => 0,
+		};
+		let source_step = step(control.get_source_address_control());
+		let dest_step = if is_fifo { 0 } else { step(control.get_dest_address_control()) };
+
+		let transfer = DmaTransfer {
+			channel: channel.index,
+			source: channel.internal_source,
+			dest: channel.internal_dest,
+			count,
+			is_32bit,
+			source_step,
+			dest_step,
+			irq: control.get_irq(),
+		};
+
+		channel.internal_source = channel.internal_source.wrapping_add((source_step * count as i32) as u32);
+		channel.internal_dest = channel.internal_dest.wrapping_add((dest_step * count as i32) as u32);
+
+		if control.get_repeat() && control.get_start_timing() != EDmaStartTiming::Immediately {
+			channel.internal_count = channel.get_count();
+			if control.get_dest_address_control() == EDmaAddressControl::IncrementReload {
+				channel.internal_dest = channel.dest_address & channel.get_dest_mask();
+			}
+		} else {
+			channel.control.set_enabled(false);
+		}
+
+		Some(transfer)
+	}
+}
+
+impl SaveState for DmaController {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		for channel in self.channels.iter() {
+			writer.write_u32(channel.source_address)?;
+			writer.write_u32(channel.dest_address)?;
+			writer.write_u16(channel.word_count)?;
+			writer.write_u16(channel.control.0)?;
+			writer.write_u32(channel.internal_source)?;
+			writer.write_u32(channel.internal_dest)?;
+			writer.write_u32(channel.internal_count)?;
+			writer.write_bool(channel.pending)?;
+		}
+
+		Ok(())
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		for channel in self.channels.iter_mut() {
+			channel.source_address = reader.read_u32()?;
+			channel.dest_address = reader.read_u32()?;
+			channel.word_count = reader.read_u16()?;
+			channel.control.0 = reader.read_u16()?;
+			channel.internal_source = reader.read_u32()?;
+			channel.internal_dest = reader.read_u32()?;
+			channel.internal_count = reader.read_u32()?;
+			channel.pending = reader.read_bool()?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for DmaController {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl DmaController {
+	/// Channel and register offset of an address inside the DMA registers
+	fn decode_address(address: u32) -> (usize, u32) {
+		let offset = (address & 0x00ff_ffff) - DMA_REGISTERS_START;
+		((offset / DMA_CHANNEL_REGISTERS_SIZE) as usize, offset % DMA_CHANNEL_REGISTERS_SIZE)
+	}
+
+	/// Whether an address inside the DMA registers belongs to SAD or DAD, which are write-only and read as the open bus
+	pub fn is_write_only(address: u32) -> bool {
+		Self::decode_address(address).1 < 0x8
+	}
+}
+
+impl MemoryInterface for DmaController {
+	fn read_8(&self, address: u32) -> u8 {
+		(self.read_16(address & !0x1) >> ((address & 0x1) * 8)) as u8
+	}
+
+	fn write_8(&mut self, address: u32, value: u8) {
+		let aligned_address = address & !0x1;
+		let shift = (address & 0x1) * 8;
+		let (index, register) = Self::decode_address(aligned_address);
+		let current = match register {
+			0x0 => self.channels[index].source_address as u16,
+			0x2 => (self.channels[index].source_address >> 16) as u16,
+			0x4 => self.channels[index].dest_address as u16,
+			0x6 => (self.channels[index].dest_address >> 16) as u16,
+			0x8 => self.channels[index].word_count,
+			_ => self.channels[index].control.0,
+		};
+
+		self.write_16(aligned_address, (current & !(0xff << shift)) | ((value as u16) << shift));
+	}
+
+	fn read_16(&self, address: u32) -> u16 {
+		let (index, register) = Self::decode_address(address & !0x1);
+		match register {
+			// NOTE: Only the control register can be read back, its enable bit is cleared once a non repeating transfer is done
+			0xa => self.channels[index].control.0,
+			// NOTE: The word count reads as 0
+			0x8 => 0x0,
+			// NOTE: SAD/DAD are write-only, the bus returns the open bus value for them
+			_ => 0x0,
+		}
+	}
+
+	fn write_16(&mut self, address: u32, value: u16) {
+		let (index, register) = Self::decode_address(address & !0x1);
+		let channel = &mut self.channels[index];
+		match register {
+			0x0 => channel.source_address = (channel.source_address & 0xffff_0000) | value as u32,
+			0x2 => channel.source_address = (channel.source_address & 0x0000_ffff) | (value as u32) << 16,
+			0x4 => channel.dest_address = (channel.dest_address & 0xffff_0000) | value as u32,
+			0x6 => channel.dest_address = (channel.dest_address & 0x0000_ffff) | (value as u32) << 16,
+			0x8 => channel.word_count = value,
+			0xa => channel.set_control(value),
+			_ => {}
+		}
+	}
+
+	fn read_32(&self, address: u32) -> u32 {
+		self.read_16(address) as u32 | (self.read_16(address.wrapping_add(2)) as u32) << 16
+	}
+
+	fn write_32(&mut self, address: u32, value: u32) {
+		self.write_16(address, value as u16);
+		self.write_16(address.wrapping_add(2), (value >> 16) as u16);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gba::CYCLES_PER_FRAME;
+	use crate::system::{EAccessWidth, SystemBus, EWRAM_ADDR, IO_ADDR, IWRAM_ADDR};
+
+	const OPEN_BUS: u32 = 0xe1a0_0000;
+
+	fn get_channel_address(index: usize) -> u32 {
+		IO_ADDR + DMA_REGISTERS_START + index as u32 * DMA_CHANNEL_REGISTERS_SIZE
+	}
+
+	fn setup() -> SystemBus {
+		let mut bus = SystemBus::new(Box::new([]));
+		bus.latch_opcode(OPEN_BUS, EAccessWidth::Word);
+		bus
+	}
+
+	#[test]
+	fn address_registers_read_as_the_open_bus() {
+		let mut bus = setup();
+		for index in 0..DMA_CHANNEL_COUNT {
+			for register in [0x0, 0x4] {
+				let address = get_channel_address(index) + register;
+				bus.write_32(address, 0x0200_1234);
+				assert_eq!(bus.read_32(address), OPEN_BUS, "DMA{} register {:#x}", index, register);
+				assert_eq!(bus.read_16(address), OPEN_BUS as u16, "DMA{} register {:#x}", index, register);
+				assert_eq!(bus.read_16(address + 2), (OPEN_BUS >> 16) as u16, "DMA{} register {:#x}", index, register + 2);
+				for byte in 0..4 {
+					assert_eq!(bus.read_8(address + byte), (OPEN_BUS >> (byte * 8)) as u8, "DMA{} register {:#x}", index, register + byte);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn address_registers_read_as_the_thumb_opcode_on_both_halves() {
+		let mut bus = setup();
+		bus.latch_opcode(0x4770, EAccessWidth::Halfword);
+		assert_eq!(bus.read_32(get_channel_address(0)), 0x4770_4770);
+		assert_eq!(bus.read_16(get_channel_address(3) + 0x6), 0x4770);
+	}
+
+	#[test]
+	fn address_registers_write_every_width() {
+		let mut bus = setup();
+		for index in 0..DMA_CHANNEL_COUNT {
+			let address = get_channel_address(index);
+			bus.write_32(address, 0x0800_0000);
+			bus.write_16(address + 0x4, 0x5678);
+			bus.write_16(address + 0x6, 0x0600);
+			bus.write_8(address + 0x4, 0x9a);
+			bus.write_8(address + 0x7, 0x02);
+
+			let channel = &bus.dma.channels[index];
+			assert_eq!(channel.source_address, 0x0800_0000, "DMA{}", index);
+			assert_eq!(channel.dest_address, 0x0200_569a, "DMA{}", index);
+		}
+	}
+
+	#[test]
+	fn word_count_reads_as_zero() {
+		let mut bus = setup();
+		for index in 0..DMA_CHANNEL_COUNT {
+			let address = get_channel_address(index) + 0x8;
+			bus.write_16(address, 0x1234);
+			assert_eq!(bus.dma.channels[index].word_count, 0x1234, "DMA{}", index);
+			assert_eq!(bus.read_16(address), 0x0, "DMA{}", index);
+			assert_eq!(bus.read_8(address + 1), 0x0, "DMA{}", index);
+		}
+	}
+
+	#[test]
+	fn control_reads_back_its_existing_bits() {
+		let mut bus = setup();
+		for index in 0..DMA_CHANNEL_COUNT {
+			let address = get_channel_address(index) + 0x8;
+			let mask = if index == 3 { DMA3_CONTROL_MASK } else { DMA_CONTROL_MASK };
+			// NOTE: Disabled, so the write doesn't start a transfer
+			bus.write_32(address, 0x7fff_0010);
+			assert_eq!(bus.read_16(address + 0x2), 0x7fff & mask, "DMA{}", index);
+			assert_eq!(bus.read_32(address), (0x7fff & mask as u32) << 16, "DMA{}", index);
+			assert_eq!(bus.read_8(address + 0x3), ((0x7fff & mask) >> 8) as u8, "DMA{}", index);
+		}
+	}
+	const ENABLE: u16 = 1 << 15;
+	const REPEAT: u16 = 1 << 9;
+	const FIFO_A_ADDRESS: u32 = IO_ADDR + 0xa0;
+
+	/// Enable a channel copying from EWRAM (halfwords 1, 2, 3...) to IWRAM
+	fn start(bus: &mut SystemBus, index: usize, dest: u32, count: u16, control: u16) {
+		for i in 0..8 {
+			bus.write_16(EWRAM_ADDR + i * 2, i as u16 + 1);
+		}
+		let address = get_channel_address(index);
+		bus.write_32(address, EWRAM_ADDR);
+		bus.write_32(address + 0x4, dest);
+		bus.write_16(address + 0x8, count);
+		bus.write_16(address + 0xa, control);
+	}
+
+	fn read_dest(bus: &SystemBus, count: u32) -> Vec<u16> {
+		(0..count).map(|i| bus.read_16(IWRAM_ADDR + i * 2)).collect()
+	}
+
+	#[test]
+	fn immediate_transfer_runs_on_the_next_step() {
+		let mut bus = setup();
+		start(&mut bus, 3, IWRAM_ADDR, 4, ENABLE);
+		assert_eq!(read_dest(&bus, 4), [0, 0, 0, 0]);
+
+		bus.step_dma(false, false);
+		assert_eq!(read_dest(&bus, 4), [1, 2, 3, 4]);
+		assert!(!bus.dma.channels[3].control.get_enabled());
+		// NOTE: 2N + 2(n-1)S + 2I, EWRAM halfwords take 3 cycles and IWRAM ones 1
+		assert_eq!(bus.take_dma_cycles(), 4 * (3 + 1) + 2);
+	}
+
+	#[test]
+	fn h_blank_transfers_skip_the_v_blank_lines() {
+		let mut bus = setup();
+		start(&mut bus, 0, IWRAM_ADDR, 1, ENABLE | REPEAT | (EDmaStartTiming::HBlank as u16) << 12);
+
+		let mut transfers = 0;
+		for cycle in 1..=CYCLES_PER_FRAME {
+			bus.step_hardware(cycle % CYCLES_PER_FRAME);
+			if bus.take_dma_cycles() > 0 {
+				transfers += 1;
+			}
+		}
+		assert_eq!(transfers, 160);
+	}
+
+	#[test]
+	fn repeat_reloads_the_destination() {
+		let mut bus = setup();
+		let dest_control = (EDmaAddressControl::IncrementReload as u16) << 5;
+		start(&mut bus, 0, IWRAM_ADDR, 2, ENABLE | REPEAT | dest_control | (EDmaStartTiming::VBlank as u16) << 12);
+
+		bus.step_dma(false, false);
+		assert_eq!(bus.take_dma_cycles(), 0);
+		bus.step_dma(false, true);
+		assert_eq!(read_dest(&bus, 2), [1, 2]);
+		bus.step_dma(false, true);
+		assert_eq!(read_dest(&bus, 4), [3, 4, 0, 0]);
+		assert!(bus.dma.channels[0].control.get_enabled());
+	}
+
+	#[test]
+	fn fifo_transfers_4_words_to_the_fifo() {
+		let mut bus = setup();
+		start(&mut bus, 1, FIFO_A_ADDRESS, 1, ENABLE | REPEAT | (EDmaStartTiming::Special as u16) << 12);
+		bus.step_dma(false, false);
+		assert_eq!(bus.take_dma_cycles(), 0);
+
+		bus.dma.request_fifo(FIFO_A_ADDRESS);
+		bus.step_dma(false, false);
+		// NOTE: 32bit whatever the control says, EWRAM words take 6 cycles
+		assert_eq!(bus.take_dma_cycles(), 4 * (6 + 1) + 2);
+		let channel = &bus.dma.channels[1];
+		assert!(channel.control.get_enabled());
+		assert_eq!(channel.internal_source, EWRAM_ADDR + 16);
+		assert_eq!(channel.internal_dest, FIFO_A_ADDRESS);
+	}
+}
@@ -1,19 +1,55 @@
+use std::io::Result;
+
 use bitfield::*;
 
+use crate::savestate::{SaveState, StateReader, StateWriter};
 use crate::system::MemoryInterface;
 
 //pub const IO_REGISTERS_END: u32 = 0x3fe;
 
-pub const SOUNDBIAS_ADDRESS: u32 = 0x88;
+pub const TM0CNT_L_ADDRESS: u32 = 0x100;
+pub const TM3CNT_H_ADDRESS: u32 = 0x10e;
+/// SIODATA32 in Normal 32 bit mode, SIOMULTI0-3 in Multiplayer mode
+pub const SIODATA32_ADDRESS: u32 = 0x120;
+pub const SIOMULTI3_ADDRESS: u32 = 0x126;
+pub const SIOCNT_ADDRESS: u32 = 0x128;
+/// SIODATA8 in Normal 8 bit mode, SIOMLT_SEND in Multiplayer mode
+pub const SIODATA8_ADDRESS: u32 = 0x12a;
 pub const KEYINPUT_ADDRESS: u32 = 0x130;
+pub const KEYCNT_ADDRESS: u32 = 0x132;
+pub const RCNT_ADDRESS: u32 = 0x134;
 pub const IE_ADDRESS: u32 = 0x200;
 pub const IF_ADDRESS: u32 = 0x202;
+pub const WAITCNT_ADDRESS: u32 = 0x204;
 pub const IME_ADDRESS: u32 = 0x208;
 pub const POSTFLG_ADDRESS: u32 = 0x300;
 pub const HALTCNT_ADDRESS: u32 = 0x301;
+/// Bits of TMxCNT_H that exist, the others always read back as 0
+const TIMER_CONTROL_MASK: u16 = 0x00c7;
+/// Bits of KEYINPUT mapped to keys, the others always read back as 0
+const KEY_INPUT_MASK: u16 = 0x3ff;
+/// Bits of KEYCNT that exist, the others always read back as 0
+const KEY_CONTROL_MASK: u16 = 0xc3ff;
+/// Bits of WAITCNT the CPU can write, bit 13 is unused and the Game Pak type (bit 15) is read-only
+const WAIT_CONTROL_WRITE_MASK: u16 = 0x5fff;
+/// Wait states selected by the SRAM and first access fields of WAITCNT
+const FIRST_ACCESS_WAIT_STATES: [u32; 4] = [4, 3, 2, 8];
+/// Second access wait states of WS0, WS1 and WS2 when their WAITCNT bit is clear, they're 1 otherwise
+const SECOND_ACCESS_WAIT_STATES: [u32; 3] = [2, 4, 8];
+/// Bits of SIOCNT the CPU can write, the terminal states, the multiplayer ID and the error flag are read-only
+const SERIAL_CONTROL_WRITE_MASK: u16 = 0x7f8b;
+/// Bits of RCNT that exist, the others always read back as 0
+const SERIAL_MODE_MASK: u16 = 0xc1ff;
+/// Cycles taken by a bit in Normal mode at 256KHz and 2MHz
+const NORMAL_BIT_CYCLES: [u32; 2] = [64, 8];
+/// Cycles taken by a bit in Multiplayer mode at 9600, 38400, 57600 and 115200 bauds
+const MULTIPLAYER_BIT_CYCLES: [u32; 4] = [1748, 437, 291, 146];
+/// Bits of a Multiplayer transfer: the start bit, the 16 data bits and the stop bit
+const MULTIPLAYER_TRANSFER_BITS: u32 = 18;
 
 bitfield! {
 	/// Key Status (R)
+	#[derive(Clone, Copy)]
 	pub struct KeyInput(u16);
 	impl Debug;
 	pub _, set_button_a: 0;
@@ -28,6 +64,43 @@ bitfield! {
 	pub _, set_button_l: 9;
 }
 
+/// Interrupt sources, the value is their bit in IE and IF
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EIrq {
+	VBlank = 0,
+	HBlank = 1,
+	VCounterMatch = 2,
+	Timer0 = 3,
+	Timer1 = 4,
+	Timer2 = 5,
+	Timer3 = 6,
+	Serial = 7,
+	Dma0 = 8,
+	Dma1 = 9,
+	Dma2 = 10,
+	Dma3 = 11,
+	Keypad = 12,
+	Cartridge = 13,
+}
+
+impl EIrq {
+	/// The only interrupts that wake the CPU up from Stop mode, the hardware raising the others is frozen
+	pub const STOP_WAKE_UP: [EIrq; 3] = [EIrq::Serial, EIrq::Keypad, EIrq::Cartridge];
+	pub const TIMERS: [EIrq; 4] = [EIrq::Timer0, EIrq::Timer1, EIrq::Timer2, EIrq::Timer3];
+	pub const DMAS: [EIrq; 4] = [EIrq::Dma0, EIrq::Dma1, EIrq::Dma2, EIrq::Dma3];
+}
+
+bitfield! {
+	/// Key Interrupt Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct KeyControl(u16);
+	impl Debug;
+	pub u16, get_keys, _: 9, 0;
+	pub get_irq, _: 14;
+	/// 0 = Any of the keys pressed (OR), 1 = All of the keys pressed (AND)
+	pub get_irq_condition, _: 15;
+}
+
 bitfield! {
 	/// Interrupt Enable Register (R/W)
 	pub struct IE(u16);
@@ -68,6 +141,225 @@ bitfield! {
 	pub get_cartridge, set_cartridge: 13;
 }
 
+bitfield! {
+	/// Waitstate Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct WaitControl(u16);
+	impl Debug;
+	pub u8, get_sram_wait_control, _: 1, 0;
+	pub u8, get_ws0_first_access, _: 3, 2;
+	pub get_ws0_second_access, _: 4;
+	pub u8, get_ws1_first_access, _: 6, 5;
+	pub get_ws1_second_access, _: 7;
+	pub u8, get_ws2_first_access, _: 9, 8;
+	pub get_ws2_second_access, _: 10;
+	pub u8, get_phi_terminal_output, _: 12, 11;
+	pub get_prefetch_buffer, _: 14;
+	/// 0 = GBA cartridge, 1 = CGB cartridge
+	pub get_is_cgb_cartridge, _: 15;
+}
+
+impl WaitControl {
+	pub fn get_sram_wait_states(&self) -> u32 {
+		FIRST_ACCESS_WAIT_STATES[self.get_sram_wait_control() as usize]
+	}
+
+	/// Wait states of the first (non-sequential) and second (sequential) accesses to one of the 3 GamePak ROM mirrors
+	pub fn get_rom_wait_states(&self, wait_state: usize) -> (u32, u32) {
+		let first_access = (self.0 >> (2 + wait_state * 3)) & 0x3;
+		let second_access = if self.0.bit(4 + wait_state * 3) { 1 } else { SECOND_ACCESS_WAIT_STATES[wait_state] };
+		(FIRST_ACCESS_WAIT_STATES[first_access as usize], second_access)
+	}
+
+	/// Frequency of the clock output on the PHI pin of the cartridge slot, None when disabled
+	pub fn get_phi_frequency(&self) -> Option<u32> {
+		match self.get_phi_terminal_output() {
+			0 => None,
+			1 => Some(4_194_304),
+			2 => Some(8_388_608),
+			_ => Some(16_777_216),
+		}
+	}
+}
+
+bitfield! {
+	/// Serial Control (R/W), the meaning of the low bits depends on the mode
+	#[derive(Clone, Copy)]
+	pub struct SerialControl(u16);
+	impl Debug;
+	/// Normal mode: 0 = external clock, 1 = internal clock
+	pub get_internal_clock, _: 0;
+	/// Normal mode: 0 = 256KHz, 1 = 2MHz
+	pub get_fast_clock, _: 1;
+	/// Multiplayer mode: 9600, 38400, 57600 or 115200 bauds
+	pub u8, get_baud_rate, _: 1, 0;
+	/// Normal mode: SI terminal (the SO of the other GBA), Multiplayer mode: 0 = parent, 1 = child
+	pub get_si_terminal, set_si_terminal: 2;
+	/// Multiplayer mode: 0 = bad connection, 1 = all GBAs ready
+	pub get_sd_terminal, set_sd_terminal: 3;
+	/// Multiplayer mode: position of this GBA, 0 is the parent
+	pub u8, get_multiplayer_id, set_multiplayer_id: 5, 4;
+	pub get_multiplayer_error, set_multiplayer_error: 6;
+	/// Written to start a transfer, reads back as set until it ends
+	pub get_busy, set_busy: 7;
+	/// 0 = Normal 8 bit, 1 = Normal 32 bit, 2 = Multiplayer, 3 = UART
+	pub u8, get_transfer_mode, _: 13, 12;
+	pub get_irq, _: 14;
+}
+
+/// Mode of the serial port, selected by RCNT bits 14-15 and SIOCNT bits 12-13
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ESerialMode {
+	Normal8,
+	Normal32,
+	Multiplayer,
+	Uart,
+	/// The 4 terminals are driven directly through RCNT
+	GeneralPurpose,
+	JoyBus,
+}
+
+/// Serial port with nothing plugged in the link port
+///
+/// Transfers still end, the terminals are pulled up so everything received is 1s: a Multiplayer parent sees 0xFFFF from the missing children.
+/// NOTE: Transfers clocked by another GBA (Normal mode with the external clock) would never end, they time out like 256KHz transfers instead
+#[derive(Debug, Copy, Clone)]
+pub struct SerialPort {
+	control: SerialControl,
+	/// SIODATA32 (low and high halfwords) or SIOMULTI0-3
+	data: [u16; 4],
+	/// SIODATA8 or SIOMLT_SEND
+	send_data: u16,
+	rcnt: u16,
+	/// Cycles before the transfer in progress ends, 0 without one
+	transfer_cycles: u32,
+}
+
+impl SerialPort {
+	fn new() -> Self {
+		Self {
+			control: SerialControl(0),
+			data: [0; 4],
+			send_data: 0,
+			rcnt: 0,
+			transfer_cycles: 0,
+		}
+	}
+
+	pub fn get_mode(&self) -> ESerialMode {
+		match (self.rcnt >> 14, self.control.get_transfer_mode()) {
+			(2, _) => ESerialMode::GeneralPurpose,
+			(3, _) => ESerialMode::JoyBus,
+			(_, 0) => ESerialMode::Normal8,
+			(_, 1) => ESerialMode::Normal32,
+			(_, 2) => ESerialMode::Multiplayer,
+			_ => ESerialMode::Uart,
+		}
+	}
+
+	pub fn get_control(&self) -> &SerialControl {
+		&self.control
+	}
+
+	pub fn is_transferring(&self) -> bool {
+		self.transfer_cycles > 0
+	}
+
+	/// SIOCNT with the terminals of an empty link port: SI is high in Normal mode, the GBA is a parent on its own in Multiplayer mode
+	fn read_control(&self) -> u16 {
+		let mut control = self.control;
+		match self.get_mode() {
+			ESerialMode::Normal8 | ESerialMode::Normal32 => control.set_si_terminal(true),
+			ESerialMode::Multiplayer => {
+				control.set_si_terminal(false);
+				control.set_sd_terminal(true);
+				control.set_multiplayer_id(0);
+			}
+			_ => {}
+		}
+
+		control.0
+	}
+
+	fn write_control(&mut self, value: u16) {
+		let was_busy = self.control.get_busy();
+		self.control.0 = (self.control.0 & !SERIAL_CONTROL_WRITE_MASK) | (value & SERIAL_CONTROL_WRITE_MASK);
+		if !self.control.get_busy() {
+			self.transfer_cycles = 0;
+		} else if !was_busy {
+			self.transfer_cycles = self.get_transfer_cycles();
+			if self.transfer_cycles == 0 {
+				self.control.set_busy(false);
+			}
+		}
+	}
+
+	/// Duration of a transfer started in the current mode, 0 in the modes without start bit
+	fn get_transfer_cycles(&self) -> u32 {
+		let normal_bit_cycles = if self.control.get_internal_clock() {
+			NORMAL_BIT_CYCLES[self.control.get_fast_clock() as usize]
+		} else {
+			NORMAL_BIT_CYCLES[0]
+		};
+		match self.get_mode() {
+			ESerialMode::Normal8 => 8 * normal_bit_cycles,
+			ESerialMode::Normal32 => 32 * normal_bit_cycles,
+			ESerialMode::Multiplayer => MULTIPLAYER_TRANSFER_BITS * MULTIPLAYER_BIT_CYCLES[self.control.get_baud_rate() as usize],
+			_ => 0,
+		}
+	}
+
+	/// RCNT, in General Purpose mode the terminals set as inputs read as pulled up
+	fn read_rcnt(&self) -> u16 {
+		if self.get_mode() == ESerialMode::GeneralPurpose {
+			let inputs = !(self.rcnt >> 4) & 0xf;
+			self.rcnt | inputs
+		} else {
+			self.rcnt
+		}
+	}
+
+	/// Advance the transfer in progress by one cycle, returns whether it ended and the serial interrupt is requested
+	fn step(&mut self) -> bool {
+		if self.transfer_cycles == 0 {
+			return false;
+		}
+		self.transfer_cycles -= 1;
+		if self.transfer_cycles > 0 {
+			return false;
+		}
+
+		match self.get_mode() {
+			ESerialMode::Normal8 => self.send_data = 0xff,
+			ESerialMode::Normal32 => self.data = [0xffff, 0xffff, self.data[2], self.data[3]],
+			ESerialMode::Multiplayer => self.data = [self.send_data, 0xffff, 0xffff, 0xffff],
+			_ => {}
+		}
+		self.control.set_busy(false);
+		self.control.get_irq()
+	}
+
+	fn read_16(&self, addr: u32) -> u16 {
+		match addr {
+			SIODATA32_ADDRESS..=SIOMULTI3_ADDRESS => self.data[((addr - SIODATA32_ADDRESS) / 2) as usize],
+			SIOCNT_ADDRESS => self.read_control(),
+			SIODATA8_ADDRESS => self.send_data,
+			RCNT_ADDRESS => self.read_rcnt(),
+			_ => 0,
+		}
+	}
+
+	fn write_16(&mut self, addr: u32, value: u16) {
+		match addr {
+			SIODATA32_ADDRESS..=SIOMULTI3_ADDRESS => self.data[((addr - SIODATA32_ADDRESS) / 2) as usize] = value,
+			SIOCNT_ADDRESS => self.write_control(value),
+			SIODATA8_ADDRESS => self.send_data = value,
+			RCNT_ADDRESS => self.rcnt = value & SERIAL_MODE_MASK,
+			_ => {}
+		}
+	}
+}
+
 bitfield! {
 	/// Undocumented - Post Boot / Debug Control (R/W)
 	pub struct PostBootFlag(u8);
@@ -83,41 +375,175 @@ bitfield! {
 }
 
 bitfield! {
-	/// Sound PWM Control (R/W)
-	pub struct SoundBias(u32);
+	/// Timer Control (R/W)
+	#[derive(Clone, Copy)]
+	pub struct TimerControl(u16);
 	impl Debug;
-	pub u16, get_bias_level, set_bias_level: 9, 1;
-	pub u8, get_amplitude_res, _: 15, 14;
+	pub u8, get_prescaler, _: 1, 0;
+	pub get_count_up, _: 2;
+	pub get_irq, _: 6;
+	pub get_enabled, _: 7;
+}
+
+impl TimerControl {
+	/// Number of cycles between two increments of the counter
+	pub fn get_prescaler_cycles(&self) -> u32 {
+		match self.get_prescaler() {
+			0 => 1,
+			1 => 64,
+			2 => 256,
+			_ => 1024,
+		}
+	}
+}
+
+/// One of the 4 hardware timers
+#[derive(Debug, Copy, Clone)]
+pub struct Timer {
+	reload: u16,
+	counter: u16,
+	control: TimerControl,
+	prescaler_counter: u32,
+}
+
+impl Timer {
+	fn new() -> Self {
+		Self {
+			reload: 0,
+			counter: 0,
+			control: TimerControl(0),
+			prescaler_counter: 0,
+		}
+	}
+
+	pub fn get_reload(&self) -> u16 {
+		self.reload
+	}
+
+	pub fn get_counter(&self) -> u16 {
+		self.counter
+	}
+
+	pub fn get_control(&self) -> &TimerControl {
+		&self.control
+	}
+
+	fn set_control(&mut self, value: u16) {
+		// NOTE: The counter is reloaded when the timer gets started
+		if !self.control.get_enabled() && value.bit(7) {
+			self.counter = self.reload;
+			self.prescaler_counter = 0;
+		}
+		self.control.0 = value & TIMER_CONTROL_MASK;
+	}
+
+	/// Add ticks to the counter, returns whether it overflowed
+	fn tick(&mut self, ticks: u32) -> bool {
+		let (counter, overflowed) = self.counter.overflowing_add(ticks as u16);
+		self.counter = if overflowed { self.reload } else { counter };
+		overflowed
+	}
+}
+
+/// When host key changes become visible to KEYINPUT reads
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EInputLatching {
+	/// Keys change as soon as the host event arrives (lowest latency)
+	Immediate,
+	/// Keys are sampled once per frame at V-Blank (deterministic, used by movies/netplay)
+	VBlank,
 }
 
 /// Represents the hardware registers mapped to memory
 pub struct IORegisters {
-	sound_bias: SoundBias,
 	key_input: KeyInput,
+	pending_key_input: KeyInput,
+	input_latching: EInputLatching,
+	key_cnt: KeyControl,
 	interrupt_enable: IE,
 	interrupt_request: IF,
 	ime: bool,
+	fiq_requested: bool,
 	post_flag: PostBootFlag,
 	halt_cnt: HaltControl,
+	wait_cnt: WaitControl,
+	timers: [Timer; 4],
+	serial: SerialPort,
 	pub halted: bool,
+	/// The high level emulated IntrWait is waiting, its SWI is executed again after each interrupt
+	pub intr_waiting: bool,
 }
 
 impl IORegisters {
 	pub fn new() -> Self {
 		Self {
-			sound_bias: SoundBias(0x200),
 			key_input: KeyInput(0x3ff),
+			pending_key_input: KeyInput(0x3ff),
+			input_latching: EInputLatching::Immediate,
+			key_cnt: KeyControl(0),
 			interrupt_enable: IE(0),
 			interrupt_request: IF(0),
 			ime: false,
+			fiq_requested: false,
 			post_flag: PostBootFlag(0),
 			halt_cnt: HaltControl(0),
+			wait_cnt: WaitControl(0),
+			timers: [Timer::new(); 4],
+			serial: SerialPort::new(),
 			halted: false,
+			intr_waiting: false,
 		}
 	}
 
+	/// Host side key state, visible to the game according to the input latching policy
 	pub fn get_mut_key_input(&mut self) -> &mut KeyInput {
-		&mut self.key_input
+		&mut self.pending_key_input
+	}
+
+	/// Host side key state, regardless of whether it was latched yet
+	pub fn get_host_key_input(&self) -> &KeyInput {
+		&self.pending_key_input
+	}
+
+	/// Key state as seen by the game, active low (0 = pressed)
+	pub fn get_key_input(&self) -> &KeyInput {
+		match self.input_latching {
+			EInputLatching::Immediate => &self.pending_key_input,
+			EInputLatching::VBlank => &self.key_input,
+		}
+	}
+
+	pub fn get_input_latching(&self) -> EInputLatching {
+		self.input_latching
+	}
+
+	pub fn set_input_latching(&mut self, input_latching: EInputLatching) {
+		self.input_latching = input_latching;
+		self.key_input.0 = self.pending_key_input.0;
+	}
+
+	/// Sample the host key state, must be called at the start of V-Blank
+	pub fn latch_key_input(&mut self) {
+		self.key_input.0 = self.pending_key_input.0;
+	}
+
+	pub fn get_key_cnt(&self) -> &KeyControl {
+		&self.key_cnt
+	}
+
+	/// Request the keypad interrupt while the keys selected in KEYCNT are pressed
+	/// NOTE: The condition is checked continuously, so acknowledging the interrupt while the keys are held requests it again
+	pub fn request_keypad_interrupt(&mut self) {
+		if !self.key_cnt.get_irq() {
+			return;
+		}
+
+		let keys = self.key_cnt.get_keys();
+		let pressed = !self.get_key_input().0 & keys;
+		let condition_met = if self.key_cnt.get_irq_condition() { keys != 0 && pressed == keys } else { pressed != 0 };
+		if condition_met {
+			self.request(EIrq::Keypad);
+		}
 	}
 
 	pub fn get_ie(&self) -> &IE {
@@ -136,13 +562,185 @@ impl IORegisters {
 		self.ime
 	}
 
-	pub fn get_sound_bias(&self) -> &SoundBias {
-		&self.sound_bias
+	/// Set the IF bit of an interrupt source, whether it gets delivered is decided by SystemBus::poll_interrupts
+	pub fn request(&mut self, irq: EIrq) {
+		self.interrupt_request.0 |= 1 << irq as u16;
+	}
+
+	/// Interrupts both enabled in IE and requested in IF
+	pub fn get_pending_interrupts(&self) -> u16 {
+		self.interrupt_enable.0 & self.interrupt_request.0
+	}
+
+	/// Pending interrupts that end Halt or Stop mode, see EIrq::STOP_WAKE_UP
+	pub fn get_wake_up_interrupts(&self) -> u16 {
+		if !self.is_stopped() {
+			return self.get_pending_interrupts();
+		}
+
+		EIrq::STOP_WAKE_UP.iter().map(|irq| 1 << *irq as u16).fold(0, |mask, bit| mask | bit) & self.get_pending_interrupts()
+	}
+
+	/// Stop mode (HALTCNT bit 7): the CPU is halted and the PPU, sound and timers are frozen too
+	pub fn is_stopped(&self) -> bool {
+		self.halted && self.halt_cnt.get_is_stop()
+	}
+
+	/// FIQ line, the GBA has no internal FIQ source but external hardware (eg. debuggers) can drive it
+	pub fn is_fiq_requested(&self) -> bool {
+		self.fiq_requested
+	}
+
+	pub fn set_fiq_requested(&mut self, fiq_requested: bool) {
+		self.fiq_requested = fiq_requested;
 	}
 
 	pub fn get_is_stop(&self) -> bool {
 		self.halt_cnt.get_is_stop()
 	}
+
+	pub fn get_wait_cnt(&self) -> &WaitControl {
+		&self.wait_cnt
+	}
+
+	/// NOTE: The Game Pak type bit is kept, only GBA cartridges can be inserted
+	fn write_wait_cnt(&mut self, value: u16) {
+		self.wait_cnt.0 = (self.wait_cnt.0 & !WAIT_CONTROL_WRITE_MASK) | (value & WAIT_CONTROL_WRITE_MASK);
+	}
+
+	pub fn get_timers(&self) -> &[Timer] {
+		&self.timers
+	}
+
+	/// Advance the timers by one cycle, requesting their overflow interrupts
+	/// Returns a mask of the timers that overflowed (eg. to feed the sound FIFOs)
+	pub fn step_timers(&mut self) -> u8 {
+		let mut overflows = 0u8;
+		for i in 0..self.timers.len() {
+			let timer = &mut self.timers[i];
+			if !timer.control.get_enabled() {
+				continue;
+			}
+
+			// NOTE: Count-up timers are incremented by the overflows of the previous one instead of the prescaler (not for timer 0)
+			let overflowed = if i > 0 && timer.control.get_count_up() {
+				overflows.bit(i - 1) && timer.tick(1)
+			} else {
+				timer.prescaler_counter += 1;
+				if timer.prescaler_counter >= timer.control.get_prescaler_cycles() {
+					timer.prescaler_counter = 0;
+					timer.tick(1)
+				} else {
+					false
+				}
+			};
+
+			if overflowed {
+				overflows.set_bit(i, true);
+				if timer.control.get_irq() {
+					self.request(EIrq::TIMERS[i]);
+				}
+			}
+		}
+
+		overflows
+	}
+
+	pub fn get_serial(&self) -> &SerialPort {
+		&self.serial
+	}
+
+	/// Advance the serial transfer in progress by one cycle, requesting the serial interrupt when it ends
+	pub fn step_serial(&mut self) {
+		if self.serial.step() {
+			self.request(EIrq::Serial);
+		}
+	}
+
+	fn read_timer_16(&self, addr: u32) -> u16 {
+		let timer = &self.timers[((addr - TM0CNT_L_ADDRESS) / 4) as usize];
+		// NOTE: CNT_L reads the live counter, the reload value written to it can't be read back
+		if addr & 0x2 == 0 {
+			timer.counter
+		} else {
+			timer.control.0
+		}
+	}
+
+	fn write_timer_16(&mut self, addr: u32, value: u16) {
+		let timer = &mut self.timers[((addr - TM0CNT_L_ADDRESS) / 4) as usize];
+		if addr & 0x2 == 0 {
+			timer.reload = value;
+		} else {
+			timer.set_control(value);
+		}
+	}
+}
+
+impl SaveState for IORegisters {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		// NOTE: Only the keys the game saw, the host keys are whatever is held now
+		writer.write_u16(self.key_input.0)?;
+		writer.write_u16(self.key_cnt.0)?;
+		writer.write_u16(self.interrupt_enable.0)?;
+		writer.write_u16(self.interrupt_request.0)?;
+		writer.write_bool(self.ime)?;
+		writer.write_bool(self.fiq_requested)?;
+		writer.write_u8(self.post_flag.0)?;
+		writer.write_u8(self.halt_cnt.0)?;
+		writer.write_u16(self.wait_cnt.0)?;
+		for timer in self.timers.iter() {
+			writer.write_u16(timer.reload)?;
+			writer.write_u16(timer.counter)?;
+			writer.write_u16(timer.control.0)?;
+			writer.write_u32(timer.prescaler_counter)?;
+		}
+		writer.write_u16(self.serial.control.0)?;
+		for value in self.serial.data.iter() {
+			writer.write_u16(*value)?;
+		}
+		writer.write_u16(self.serial.send_data)?;
+		writer.write_u16(self.serial.rcnt)?;
+		writer.write_u32(self.serial.transfer_cycles)?;
+
+		writer.write_bool(self.halted)?;
+		writer.write_bool(self.intr_waiting)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.key_input.0 = reader.read_u16()?;
+		self.key_cnt.0 = reader.read_u16()? & KEY_CONTROL_MASK;
+		self.interrupt_enable.0 = reader.read_u16()?;
+		self.interrupt_request.0 = reader.read_u16()?;
+		self.ime = reader.read_bool()?;
+		self.fiq_requested = reader.read_bool()?;
+		self.post_flag.0 = reader.read_u8()?;
+		self.halt_cnt.0 = reader.read_u8()?;
+		self.wait_cnt.0 = reader.read_u16()?;
+		for timer in self.timers.iter_mut() {
+			timer.reload = reader.read_u16()?;
+			timer.counter = reader.read_u16()?;
+			timer.control.0 = reader.read_u16()?;
+			timer.prescaler_counter = reader.read_u32()?;
+		}
+		self.serial.control.0 = reader.read_u16()?;
+		for value in self.serial.data.iter_mut() {
+			*value = reader.read_u16()?;
+		}
+		self.serial.send_data = reader.read_u16()?;
+		self.serial.rcnt = reader.read_u16()? & SERIAL_MODE_MASK;
+		self.serial.transfer_cycles = reader.read_u32()?;
+
+		self.halted = reader.read_bool()?;
+		self.intr_waiting = reader.read_bool()?;
+		Ok(())
+	}
+}
+
+impl Default for IORegisters {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl MemoryInterface for IORegisters {
@@ -150,10 +748,13 @@ impl MemoryInterface for IORegisters {
 		let addr = if address & 0xffff == 0x8000 { 0x800 } else { address & 0x00ff_ffff };
 		let shift = (addr as usize & 0x1) * 8;
 		match addr & !0x1 {
-			SOUNDBIAS_ADDRESS => self.sound_bias.bit_range(shift + 7, shift),
-			KEYINPUT_ADDRESS => self.key_input.bit_range(shift + 7, shift),
+			TM0CNT_L_ADDRESS..=TM3CNT_H_ADDRESS => (self.read_timer_16(addr & !0x1) >> shift) as u8,
+			SIODATA32_ADDRESS..=SIODATA8_ADDRESS | RCNT_ADDRESS => (self.serial.read_16(addr & !0x1) >> shift) as u8,
+			KEYINPUT_ADDRESS => ((self.get_key_input().0 & KEY_INPUT_MASK) >> shift) as u8,
+			KEYCNT_ADDRESS => self.key_cnt.bit_range(shift + 7, shift),
 			IE_ADDRESS => self.interrupt_enable.bit_range(shift + 7, shift),
 			IF_ADDRESS => self.interrupt_request.bit_range(shift + 7, shift),
+			WAITCNT_ADDRESS => self.wait_cnt.bit_range(shift + 7, shift),
 			IME_ADDRESS => {
 				if shift == 0 {
 					self.ime as u8
@@ -176,12 +777,37 @@ impl MemoryInterface for IORegisters {
 		let addr = if address & 0xffff == 0x8000 { 0x800 } else { address & 0x00ff_ffff };
 		let shift = (addr as usize & 0x1) * 8;
 		match addr & !0x1 {
-			SOUNDBIAS_ADDRESS => self.sound_bias.set_bit_range(shift + 7, shift, value),
+			TM0CNT_L_ADDRESS..=TM3CNT_H_ADDRESS => {
+				let aligned_addr = addr & !0x1;
+				let timer = &self.timers[((aligned_addr - TM0CNT_L_ADDRESS) / 4) as usize];
+				// NOTE: Reads of the low halfword give the counter, keep the reload value for the other byte
+				let current = if aligned_addr & 0x2 == 0 { timer.reload } else { timer.control.0 };
+				self.write_timer_16(aligned_addr, (current & !(0xff << shift)) | ((value as u16) << shift));
+			}
+			SIODATA32_ADDRESS..=SIODATA8_ADDRESS | RCNT_ADDRESS => {
+				let aligned_addr = addr & !0x1;
+				// NOTE: The stored values, without the terminal states read back from SIOCNT and RCNT
+				let current = match aligned_addr {
+					SIOCNT_ADDRESS => self.serial.control.0,
+					RCNT_ADDRESS => self.serial.rcnt,
+					_ => self.serial.read_16(aligned_addr),
+				};
+				self.serial.write_16(aligned_addr, (current & !(0xff << shift)) | ((value as u16) << shift));
+			}
+			KEYCNT_ADDRESS => {
+				let key_cnt = (self.key_cnt.0 & !(0xff << shift)) | ((value as u16) << shift);
+				self.key_cnt.0 = key_cnt & KEY_CONTROL_MASK;
+			}
 			IE_ADDRESS => self.interrupt_enable.set_bit_range(shift + 7, shift, value),
 			IF_ADDRESS => {
 				let current_if = self.interrupt_request.0;
 				self.interrupt_request.0 = !((value as u16) << shift) & current_if;
 			}
+			WAITCNT_ADDRESS => {
+				let mut wait_cnt = self.wait_cnt.0;
+				wait_cnt.set_bit_range(shift + 7, shift, value);
+				self.write_wait_cnt(wait_cnt);
+			}
 			IME_ADDRESS => {
 				if shift == 0 {
 					self.ime = value.bit(0);
@@ -202,10 +828,13 @@ impl MemoryInterface for IORegisters {
 	fn read_16(&self, address: u32) -> u16 {
 		let addr = if address & 0xffff == 0x8000 { 0x800 } else { address & 0x00ff_ffff };
 		match addr {
-			SOUNDBIAS_ADDRESS => self.sound_bias.0 as u16,
-			KEYINPUT_ADDRESS => self.key_input.0,
+			TM0CNT_L_ADDRESS..=TM3CNT_H_ADDRESS => self.read_timer_16(addr & !0x1),
+			SIODATA32_ADDRESS..=SIODATA8_ADDRESS | RCNT_ADDRESS => self.serial.read_16(addr & !0x1),
+			KEYINPUT_ADDRESS => self.get_key_input().0 & KEY_INPUT_MASK,
+			KEYCNT_ADDRESS => self.key_cnt.0,
 			IE_ADDRESS => self.interrupt_enable.0,
 			IF_ADDRESS => self.interrupt_request.0,
+			WAITCNT_ADDRESS => self.wait_cnt.0,
 			IME_ADDRESS => self.ime as u16,
 			POSTFLG_ADDRESS => self.post_flag.0 as u16,
 			_ => 0x0, // TODO: Return proper invalid value
@@ -214,13 +843,16 @@ impl MemoryInterface for IORegisters {
 
 	fn write_16(&mut self, address: u32, value: u16) {
 		let addr = if address & 0xffff == 0x8000 { 0x800 } else { address & 0x00ff_ffff };
-		let shift = (addr as usize & 0x2) * 16;
 		match addr {
+			TM0CNT_L_ADDRESS..=TM3CNT_H_ADDRESS => self.write_timer_16(addr & !0x1, value),
+			SIODATA32_ADDRESS..=SIODATA8_ADDRESS | RCNT_ADDRESS => self.serial.write_16(addr & !0x1, value),
+			KEYCNT_ADDRESS => self.key_cnt.0 = value & KEY_CONTROL_MASK,
 			IE_ADDRESS => self.interrupt_enable.0 = value,
 			IF_ADDRESS => {
 				let current_if = self.interrupt_request.0;
 				self.interrupt_request.0 = !value & current_if;
 			}
+			WAITCNT_ADDRESS => self.write_wait_cnt(value),
 			IME_ADDRESS => {
 				self.ime = value.bit(0);
 			}
@@ -229,7 +861,6 @@ impl MemoryInterface for IORegisters {
 				self.halt_cnt.0 = (value >> 8) as u8;
 				self.halted = true;
 			}
-			SOUNDBIAS_ADDRESS => self.sound_bias.set_bit_range(shift + 15, shift, value),
 			_ => {}
 		}
 	}
@@ -237,9 +868,14 @@ impl MemoryInterface for IORegisters {
 	fn read_32(&self, address: u32) -> u32 {
 		let addr = if address & 0xffff == 0x8000 { 0x800 } else { address & 0x00ff_ffff };
 		match addr {
-			SOUNDBIAS_ADDRESS => self.sound_bias.0,
-			KEYINPUT_ADDRESS => self.key_input.0 as u32,
+			TM0CNT_L_ADDRESS..=TM3CNT_H_ADDRESS => self.read_timer_16(addr & !0x3) as u32 | (self.read_timer_16((addr & !0x3) + 2) as u32) << 16,
+			SIODATA32_ADDRESS..=SIODATA8_ADDRESS => self.serial.read_16(addr & !0x3) as u32 | (self.serial.read_16((addr & !0x3) + 2) as u32) << 16,
+			KEYINPUT_ADDRESS => (self.get_key_input().0 & KEY_INPUT_MASK) as u32 | ((self.key_cnt.0 as u32) << 16),
+			// NOTE: The upper halfword is unused
+			RCNT_ADDRESS => self.serial.read_16(RCNT_ADDRESS) as u32,
 			IE_ADDRESS => self.interrupt_enable.0 as u32 | ((self.interrupt_request.0 as u32) << 16),
+			// NOTE: The upper halfword is unused
+			WAITCNT_ADDRESS => self.wait_cnt.0 as u32,
 			IME_ADDRESS => self.ime as u32,
 			POSTFLG_ADDRESS => self.post_flag.0 as u32,
 			_ => 0x0, // TODO: Return proper invalid value
@@ -249,12 +885,25 @@ impl MemoryInterface for IORegisters {
 	fn write_32(&mut self, address: u32, value: u32) {
 		let addr = if address & 0xffff == 0x8000 { 0x800 } else { address & 0x00ff_ffff };
 		match addr {
+			TM0CNT_L_ADDRESS..=TM3CNT_H_ADDRESS => {
+				self.write_timer_16(addr & !0x3, value as u16);
+				self.write_timer_16((addr & !0x3) + 2, (value >> 16) as u16);
+			}
+			// NOTE: The data is written before SIOCNT, in case the write starts a transfer
+			SIODATA32_ADDRESS..=SIODATA8_ADDRESS => {
+				self.serial.write_16((addr & !0x3) + 2, (value >> 16) as u16);
+				self.serial.write_16(addr & !0x3, value as u16);
+			}
+			RCNT_ADDRESS => self.serial.write_16(RCNT_ADDRESS, value as u16),
+			// NOTE: KEYINPUT is read-only, only KEYCNT in the upper halfword is written
+			KEYINPUT_ADDRESS => self.key_cnt.0 = (value >> 16) as u16 & KEY_CONTROL_MASK,
 			IE_ADDRESS => {
 				self.interrupt_enable.0 = value as u16;
 
 				let current_if = self.interrupt_request.0;
 				self.interrupt_request.0 = !((value << 16) as u16) & current_if;
 			}
+			WAITCNT_ADDRESS => self.write_wait_cnt(value as u16),
 			IME_ADDRESS => {
 				self.ime = value.bit(0);
 			}
@@ -263,8 +912,72 @@ impl MemoryInterface for IORegisters {
 				self.halt_cnt.0 = (value >> 8) as u8;
 				self.halted = true;
 			}
-			SOUNDBIAS_ADDRESS => self.sound_bias.0 = value,
 			_ => {}
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TM0CNT_H_ADDRESS: u32 = TM0CNT_L_ADDRESS + 2;
+	const TIMER_START: u16 = 1 << 7;
+
+	fn step(io: &mut IORegisters, cycles: u32) {
+		for _ in 0..cycles {
+			io.step_timers();
+		}
+	}
+
+	#[test]
+	fn counter_reads_the_live_counter() {
+		let mut io = IORegisters::new();
+		io.write_16(TM0CNT_L_ADDRESS, 0xfff0);
+		assert_eq!(io.read_16(TM0CNT_L_ADDRESS), 0x0);
+
+		io.write_16(TM0CNT_H_ADDRESS, TIMER_START);
+		step(&mut io, 3);
+		assert_eq!(io.read_16(TM0CNT_L_ADDRESS), 0xfff3);
+		assert_eq!(io.read_8(TM0CNT_L_ADDRESS), 0xf3);
+		assert_eq!(io.read_32(TM0CNT_L_ADDRESS) & 0xffff, 0xfff3);
+	}
+
+	#[test]
+	fn reload_is_used_on_overflow_or_restart() {
+		let mut io = IORegisters::new();
+		io.write_16(TM0CNT_L_ADDRESS, 0xfff0);
+		io.write_16(TM0CNT_H_ADDRESS, TIMER_START);
+		io.write_16(TM0CNT_L_ADDRESS, 0xff00);
+		// NOTE: Writing the control again while running doesn't restart the timer
+		io.write_16(TM0CNT_H_ADDRESS, TIMER_START);
+		step(&mut io, 0xf);
+		assert_eq!(io.read_16(TM0CNT_L_ADDRESS), 0xffff);
+
+		step(&mut io, 1);
+		assert_eq!(io.read_16(TM0CNT_L_ADDRESS), 0xff00);
+
+		io.write_16(TM0CNT_H_ADDRESS, 0);
+		io.write_8(TM0CNT_L_ADDRESS + 1, 0x12);
+		assert_eq!(io.read_16(TM0CNT_L_ADDRESS), 0xff00);
+		io.write_16(TM0CNT_H_ADDRESS, TIMER_START);
+		assert_eq!(io.read_16(TM0CNT_L_ADDRESS), 0x1200);
+	}
+
+	#[test]
+	fn control_reads_back_its_bits() {
+		let mut io = IORegisters::new();
+		io.write_16(TM0CNT_H_ADDRESS, 0xffff);
+		assert_eq!(io.read_16(TM0CNT_H_ADDRESS), TIMER_CONTROL_MASK);
+
+		let tm1cnt_h_address = TM0CNT_H_ADDRESS + 4;
+		io.write_8(tm1cnt_h_address, 0xff);
+		io.write_8(tm1cnt_h_address + 1, 0xff);
+		assert_eq!(io.read_8(tm1cnt_h_address), TIMER_CONTROL_MASK as u8);
+		assert_eq!(io.read_8(tm1cnt_h_address + 1), 0x0);
+
+		let tm2cnt_l_address = TM0CNT_L_ADDRESS + 8;
+		io.write_32(tm2cnt_l_address, 0xffff_0000);
+		assert_eq!(io.read_32(tm2cnt_l_address) >> 16, TIMER_CONTROL_MASK as u32);
+	}
+}
@@ -1,9 +1,29 @@
-use crate::ppu::{PPU, PPU_REGISTERS_END};
-use crate::system::io::IORegisters;
+use std::cell::Cell;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
 
+use crate::apu::{APU, APU_REGISTERS_END, APU_REGISTERS_START, FIFO_A_ADDRESS, FIFO_B_ADDRESS};
+use crate::arm7tdmi::cpu::CPU;
+use crate::arm7tdmi::EExceptionType;
+use crate::bios::hle;
+use crate::ppu::{PPU, PPU_REGISTERS_END, VRAM_SIZE};
+use crate::savestate::{SaveState, StateReader, StateWriter};
+use crate::system::cartridge::{Cartridge, ECartridgeType};
+use crate::system::dma::{DmaController, DMA_REGISTERS_END, DMA_REGISTERS_START};
+use crate::system::prefetch::PrefetchBuffer;
+
+pub mod backup;
+pub mod cartridge;
+pub(crate) mod dma;
 mod io;
+mod prefetch;
+pub(crate) mod rtc;
+pub mod sensors;
+
+pub use io::{EInputLatching, EIrq, HaltControl, IORegisters, KeyControl, KeyInput, PostBootFlag, Timer, TimerControl, IE, IF};
 
 // Sizes
+pub const BIOS_SIZE: usize = 16 * 1024;
 pub const EWRAM_SIZE: usize = 256 * 1024;
 pub const IWRAM_SIZE: usize = 32 * 1024;
 
@@ -25,6 +45,60 @@ pub const CARTRIDGE_WS1_HI: u32 = 0x0B00_0000;
 pub const CARTRIDGE_WS2_LO: u32 = 0x0C00_0000;
 pub const CARTRIDGE_WS2_HI: u32 = 0x0D00_0000;
 pub const CARTRIDGE_SRAM_LO: u32 = 0x0E00_0000;
+pub const CARTRIDGE_SRAM_HI: u32 = 0x0F00_0000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EAccessWidth {
+	Byte,
+	Halfword,
+	Word,
+}
+
+/// Regions of the memory map, selected by the upper 8 bits of an address
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EMemoryRegion {
+	Bios,
+	Ewram,
+	Iwram,
+	Io,
+	PaletteRam,
+	Vram,
+	Oam,
+	CartridgeWs0,
+	CartridgeWs1,
+	CartridgeWs2,
+	CartridgeSram,
+	/// Nothing is mapped here, reads return open bus values
+	Unmapped,
+}
+
+impl EMemoryRegion {
+	pub fn is_cartridge_rom(self) -> bool {
+		matches!(self, EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2)
+	}
+
+	/// Regions accessed through the GamePak bus: the ROM and the save memory
+	pub fn is_gamepak(self) -> bool {
+		self.is_cartridge_rom() || self == EMemoryRegion::CartridgeSram
+	}
+
+	pub fn get_name(self) -> &'static str {
+		match self {
+			EMemoryRegion::Bios => "BIOS",
+			EMemoryRegion::Ewram => "EWRAM",
+			EMemoryRegion::Iwram => "IWRAM",
+			EMemoryRegion::Io => "I/O",
+			EMemoryRegion::PaletteRam => "Palette",
+			EMemoryRegion::Vram => "VRAM",
+			EMemoryRegion::Oam => "OAM",
+			EMemoryRegion::CartridgeWs0 => "ROM (WS0)",
+			EMemoryRegion::CartridgeWs1 => "ROM (WS1)",
+			EMemoryRegion::CartridgeWs2 => "ROM (WS2)",
+			EMemoryRegion::CartridgeSram => "SRAM",
+			EMemoryRegion::Unmapped => "Unmapped",
+		}
+	}
+}
 
 // pub type Gba32BitSlice = BitSlice<Lsb0, u32>;
 // pub type Gba16BitSlice = BitSlice<Lsb0, u16>;
@@ -41,6 +115,119 @@ pub trait MemoryInterface {
 	fn write_16(&mut self, address: u32, value: u16);
 	fn read_32(&self, address: u32) -> u32;
 	fn write_32(&mut self, address: u32, value: u32);
+
+	/// Read consecutive bytes starting at address
+	fn read_block(&self, address: u32, buffer: &mut [u8]) {
+		for (i, byte) in buffer.iter_mut().enumerate() {
+			*byte = self.read_8(address.wrapping_add(i as u32));
+		}
+	}
+
+	/// Write consecutive bytes starting at address
+	/// NOTE: Uses halfword writes where possible, since 8bit writes are mirrored or ignored by video memory
+	fn write_block(&mut self, address: u32, data: &[u8]) {
+		let mut offset = 0;
+		if address & 0x1 != 0 && !data.is_empty() {
+			self.write_8(address, data[0]);
+			offset = 1;
+		}
+
+		while offset + 1 < data.len() {
+			self.write_16(address.wrapping_add(offset as u32), data[offset] as u16 | (data[offset + 1] as u16) << 8);
+			offset += 2;
+		}
+
+		if offset < data.len() {
+			self.write_8(address.wrapping_add(offset as u32), data[offset]);
+		}
+	}
+}
+
+/// Suspicious behavior of the running game, usually a game or emulator bug
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EAnomaly {
+	/// Opcode of an instruction without a valid encoding
+	UndefinedInstruction(u32),
+	/// Access past the IO registers
+	UnmappedIo { address: u32, is_write: bool },
+	/// Read from an address no memory answers (open bus)
+	OpenBusRead(u32),
+}
+
+impl EAnomaly {
+	pub fn get_description(&self) -> String {
+		match self {
+			EAnomaly::UndefinedInstruction(opcode) => format!("Undefined instruction {:#010x}", opcode),
+			EAnomaly::UnmappedIo { address, is_write } => format!("Unmapped IO {} at {:#010x}", if *is_write { "write" } else { "read" }, address),
+			EAnomaly::OpenBusRead(address) => format!("Open bus read at {:#010x}", address),
+		}
+	}
+}
+
+/// Accesses a watchpoint breaks on
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EWatchKind {
+	Read,
+	Write,
+	Access,
+}
+
+/// Range of memory the debugger watches, the first access of the game to it is reported by SystemBus::take_watchpoint_hit
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Watchpoint {
+	pub address: u32,
+	pub length: u32,
+	pub kind: EWatchKind,
+}
+
+impl Watchpoint {
+	fn matches(&self, address: u32, size: u32, is_write: bool) -> bool {
+		let kind_matches = match self.kind {
+			EWatchKind::Read => !is_write,
+			EWatchKind::Write => is_write,
+			EWatchKind::Access => true,
+		};
+		kind_matches && address < self.address.wrapping_add(self.length) && self.address < address.wrapping_add(size)
+	}
+}
+
+/// Seed of the pseudo-random RAM content, fixed so runs stay reproducible
+pub const RAM_INIT_SEED: u64 = 0x5eed_0000_09ba;
+
+/// Content of EWRAM, IWRAM and VRAM at boot
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ERamInit {
+	#[default]
+	Zero,
+	/// Seeded pattern standing in for the semi-random content real RAM powers up with, some games depend on it not being zero
+	PseudoRandom(u64),
+}
+
+impl ERamInit {
+	pub fn get_name(&self) -> &'static str {
+		match self {
+			ERamInit::Zero => "Zero",
+			ERamInit::PseudoRandom(_) => "Pseudo-Random",
+		}
+	}
+
+	/// Fill memory with the pattern, each region gets its own stream so the pattern doesn't depend on the fill order
+	pub fn fill(&self, data: &mut [u8], stream: u64) {
+		match self {
+			ERamInit::Zero => data.iter_mut().for_each(|value| *value = 0),
+			ERamInit::PseudoRandom(seed) => {
+				// NOTE: xorshift64*, the state can't be 0
+				let mut state = (seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1;
+				for chunk in data.chunks_mut(8) {
+					state ^= state >> 12;
+					state ^= state << 25;
+					state ^= state >> 27;
+					let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d).to_le_bytes();
+					chunk.copy_from_slice(&value[..chunk.len()]);
+				}
+			}
+		}
+	}
 }
 
 /// The system bus
@@ -48,214 +235,809 @@ pub trait MemoryInterface {
 /// This unit emulates the memory bus by redirecting data requests to the right components (eg. PPU, IWRAM, etc...)
 pub struct SystemBus {
 	bios: Box<[u8]>,
+	/// No BIOS was given, the BIOS calls are high level emulated
+	hle_bios: bool,
 	external_wram: Box<[u8]>,
 	internal_wram: Box<[u8]>,
 	pub io_regs: IORegisters,
+	pub dma: DmaController,
 	pub ppu: PPU,
-	cartridge_rom: Box<[u8]>,
-	cartridge_sram: Box<[u8]>,
+	pub apu: APU,
+	cartridge: Cartridge,
+	prefetch: PrefetchBuffer,
+	/// Last opcode fetched, reads of the write-only registers return it
+	// NOTE: Not part of the save states, the next opcode fetch latches it again
+	open_bus: u32,
+	ram_init: ERamInit,
+	/// .sav file the save memory is persisted to
+	backup_path: Option<PathBuf>,
+
+	/// Cycles of the DMA transfers since the last take_dma_cycles, the CPU is stalled for them
+	dma_cycles: u32,
+
+	// Compatibility counters
+	rom_write_attempts: u32,
+
+	// Debugging
+	break_on_anomaly: bool,
+	// NOTE: First anomaly since the last take_anomaly, reads report them too so it needs interior mutability
+	anomaly: Cell<Option<EAnomaly>>,
+	watchpoints: Vec<Watchpoint>,
+	// NOTE: First watchpoint hit since the last take_watchpoint_hit with the accessed address, interior mutability for the reads too
+	watchpoint_hit: Cell<Option<(Watchpoint, u32)>>,
+	// NOTE: Cycles of the accesses since the last take_access_cycles, reads count them too so it needs interior mutability
+	access_cycles: Cell<u32>,
+	gamepak_accessed: Cell<bool>,
 }
 
 impl SystemBus {
-	pub fn new_with_cartridge(bios_data: Box<[u8]>, cartridge_data: Box<[u8]>) -> Self {
-		Self {
-			bios: bios_data,
+	pub fn new_with_cartridge(bios_data: Box<[u8]>, cartridge: Cartridge) -> Self {
+		Self::new_with_ram_init(bios_data, cartridge, ERamInit::Zero)
+	}
+
+	pub fn new_with_ram_init(bios_data: Box<[u8]>, cartridge: Cartridge, ram_init: ERamInit) -> Self {
+		let mut bus = Self {
+			hle_bios: bios_data.is_empty(),
+			bios: if bios_data.is_empty() { hle::build_hle_image() } else { bios_data },
 			external_wram: vec![0; EWRAM_SIZE].into_boxed_slice(),
 			internal_wram: vec![0; IWRAM_SIZE].into_boxed_slice(),
 			io_regs: IORegisters::new(),
+			dma: DmaController::new(),
 			ppu: PPU::new(),
-			cartridge_rom: cartridge_data,
-			cartridge_sram: vec![0; CARTRIDGE_SRAM_SIZE].into_boxed_slice(),
+			apu: APU::new(),
+			cartridge,
+			prefetch: PrefetchBuffer::new(),
+			open_bus: 0,
+			ram_init,
+			backup_path: None,
+			dma_cycles: 0,
+			rom_write_attempts: 0,
+			break_on_anomaly: cfg!(debug_assertions),
+			anomaly: Cell::new(None),
+			watchpoints: Vec::new(),
+			watchpoint_hit: Cell::new(None),
+			access_cycles: Cell::new(0),
+			gamepak_accessed: Cell::new(false),
+		};
+
+		if ram_init != ERamInit::Zero {
+			ram_init.fill(&mut bus.external_wram, 0);
+			ram_init.fill(&mut bus.internal_wram, 1);
+			let mut vram = vec![0; VRAM_SIZE];
+			ram_init.fill(&mut vram, 2);
+			bus.ppu.load_vram(&vram);
+		}
+
+		// NOTE: Multiboot images are downloaded by the BIOS into EWRAM, skip the transfer and put them there directly
+		if bus.cartridge.get_type() == ECartridgeType::Multiboot {
+			let image = bus.cartridge.get_rom();
+			let length = image.len().min(EWRAM_SIZE);
+			bus.external_wram[..length].copy_from_slice(&image[..length]);
 		}
+
+		bus
 	}
 
 	pub fn new(bios_data: Box<[u8]>) -> Self {
-		Self {
-			bios: bios_data,
-			external_wram: vec![0; EWRAM_SIZE].into_boxed_slice(),
-			internal_wram: vec![0; IWRAM_SIZE].into_boxed_slice(),
-			io_regs: IORegisters::new(),
-			ppu: PPU::new(),
-			cartridge_rom: Vec::<u8>::new().into_boxed_slice(),
-			cartridge_sram: vec![0; CARTRIDGE_SRAM_SIZE].into_boxed_slice(),
+		Self::new_with_cartridge(bios_data, Cartridge::none())
+	}
+
+	pub fn get_cartridge(&self) -> &Cartridge {
+		&self.cartridge
+	}
+
+	pub fn get_mut_cartridge(&mut self) -> &mut Cartridge {
+		&mut self.cartridge
+	}
+
+	/// How the RAM was filled at boot, kept in save states so a run can be reproduced
+	pub fn get_ram_init(&self) -> ERamInit {
+		self.ram_init
+	}
+
+	/// Keep the battery backed save memory in a .sav file, it's read now and written back by flush_backup
+	/// NOTE: A missing file is a new game, it gets created by the first flush after the game saves
+	pub fn load_backup(&mut self, path: &Path) -> std::io::Result<()> {
+		self.backup_path = Some(path.to_path_buf());
+		match std::fs::read(path) {
+			Ok(data) => {
+				self.cartridge.load_backup_data(&data);
+				self.cartridge.set_backup_dirty(false);
+				Ok(())
+			}
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e),
 		}
 	}
-}
 
-impl MemoryInterface for SystemBus {
-	fn read_8(&self, address: u32) -> u8 {
+	/// Write the save memory to the .sav file if it changed since the last flush
+	pub fn flush_backup(&mut self) -> std::io::Result<()> {
+		if let Some(path) = self.backup_path.as_ref().filter(|_| self.cartridge.is_backup_dirty()) {
+			std::fs::write(path, self.cartridge.get_backup().get_data())?;
+			self.cartridge.set_backup_dirty(false);
+		}
+
+		Ok(())
+	}
+
+	/// Region an address belongs to and the offset inside of it, with the region mirrors applied
+	/// NOTE: PPU memory offsets are left unmirrored, the PPU resolves them according to the access width
+	pub fn resolve(address: u32) -> (EMemoryRegion, u32) {
 		match address & 0xff00_0000 {
-			BIOS_ADDR => {
-				if address <= 0x3fff {
-					self.bios[address as usize]
-				} else {
-					// TODO: Return proper invalid value
-					0x0
+			BIOS_ADDR if address <= 0x3fff => (EMemoryRegion::Bios, address),
+			EWRAM_ADDR => (EMemoryRegion::Ewram, address & 0x3_ffff),
+			IWRAM_ADDR => (EMemoryRegion::Iwram, address & 0x7fff),
+			IO_ADDR => (EMemoryRegion::Io, address & 0x00ff_ffff),
+			PALETTE_RAM_ADDR => (EMemoryRegion::PaletteRam, address & 0x00ff_ffff),
+			VRAM_ADDR => (EMemoryRegion::Vram, address & 0x00ff_ffff),
+			OAM_ADDR => (EMemoryRegion::Oam, address & 0x00ff_ffff),
+			CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI => (EMemoryRegion::CartridgeWs0, address & 0x01ff_ffff),
+			CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI => (EMemoryRegion::CartridgeWs1, address & 0x01ff_ffff),
+			CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => (EMemoryRegion::CartridgeWs2, address & 0x01ff_ffff),
+			// NOTE: 0x0F000000 mirrors the save memory, reads and writes both reach the backup chip
+			CARTRIDGE_SRAM_LO | CARTRIDGE_SRAM_HI => (EMemoryRegion::CartridgeSram, address & 0xffff),
+			_ => (EMemoryRegion::Unmapped, address),
+		}
+	}
+
+	/// Read for the debugger, eg. to dump the state
+	/// NOTE: Not an access of the game, it isn't an anomaly, a watchpoint hit or counted in the access cycles
+	pub fn peek_8(&self, address: u32) -> u8 {
+		self.load_8(address)
+	}
+
+	pub fn peek_16(&self, address: u32) -> u16 {
+		self.load_16(address)
+	}
+
+	pub fn peek_32(&self, address: u32) -> u32 {
+		self.load_32(address)
+	}
+
+	/// Read consecutive bytes for the debugger like peek_8
+	pub fn peek_block(&self, address: u32, buffer: &mut [u8]) {
+		for (i, byte) in buffer.iter_mut().enumerate() {
+			*byte = self.load_8(address.wrapping_add(i as u32));
+		}
+	}
+
+	/// Write bytes for the debugger, the ROM included, eg. to patch instructions
+	/// NOTE: Not an access of the game, it isn't an anomaly, a ROM write attempt or a watchpoint hit
+	pub fn patch(&mut self, address: u32, data: &[u8]) {
+		let (region, offset) = Self::resolve(address);
+		if region.is_cartridge_rom() {
+			self.cartridge.patch_rom(offset, data);
+		} else if region == EMemoryRegion::CartridgeSram {
+			// NOTE: Save memory has an 8 bit bus, and writes to Flash are commands
+			self.cartridge.patch_backup(offset, data);
+		} else {
+			self.write_block(address, data);
+			self.take_anomaly();
+			self.take_watchpoint_hit();
+		}
+	}
+
+	/// Number of writes the game attempted on the (read-only) ROM
+	pub fn get_rom_write_attempts(&self) -> u32 {
+		self.rom_write_attempts
+	}
+
+	/// Whether the anomalies of the game are recorded, so the frontend can pause on them (on by default in debug builds)
+	pub fn is_break_on_anomaly_enabled(&self) -> bool {
+		self.break_on_anomaly
+	}
+
+	pub fn set_break_on_anomaly(&mut self, value: bool) {
+		self.break_on_anomaly = value;
+		self.anomaly.set(None);
+	}
+
+	/// Record an anomaly if break on anomaly is enabled, only the first one is kept until it's taken
+	pub fn report_anomaly(&self, anomaly: EAnomaly) {
+		if self.break_on_anomaly && self.anomaly.get().is_none() {
+			self.anomaly.set(Some(anomaly));
+		}
+	}
+
+	pub fn take_anomaly(&mut self) -> Option<EAnomaly> {
+		self.anomaly.take()
+	}
+
+	pub fn get_watchpoints(&self) -> &[Watchpoint] {
+		&self.watchpoints
+	}
+
+	pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+		if !self.watchpoints.contains(&watchpoint) {
+			self.watchpoints.push(watchpoint);
+		}
+	}
+
+	pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+		self.watchpoints.retain(|current| *current != watchpoint);
+	}
+
+	/// The first watchpoint hit since the last call and the address accessed
+	/// NOTE: Accesses of the debugger hit them too, take the hit after them to drop it
+	pub fn take_watchpoint_hit(&self) -> Option<(Watchpoint, u32)> {
+		self.watchpoint_hit.take()
+	}
+
+	/// Step the hardware running alongside the CPU by one cycle, returns the H-Blank and V-Blank IRQ lines of the PPU
+	/// NOTE: Nothing runs in Stop mode, only the keys are still sampled so the keypad interrupt can end it
+	pub fn step_hardware(&mut self, current_cycle: u32) -> (bool, bool) {
+		if self.io_regs.is_stopped() {
+			self.io_regs.latch_key_input();
+			return (false, false);
+		}
+
+		let (h_blank, v_blank) = self.ppu.step(current_cycle);
+		if v_blank {
+			self.io_regs.latch_key_input();
+		}
+
+		self.step_timers();
+		self.io_regs.step_serial();
+		self.apu.step();
+		self.step_dma(h_blank, v_blank);
+		(h_blank, v_blank)
+	}
+
+	/// Request the PPU interrupts enabled in DISPSTAT, h_blank/v_blank being the PPU step results
+	pub fn request_ppu_interrupts(&mut self, current_cycle: u32, h_blank: bool, v_blank: bool) {
+		let disp_stat = self.ppu.get_disp_stat();
+		// NOTE: V-Counter match is requested once when the matching scanline starts
+		if current_cycle % 1232 == 0 && disp_stat.get_v_counter_flag() && disp_stat.get_v_counter_irq() {
+			self.io_regs.request(EIrq::VCounterMatch);
+		}
+		if h_blank && disp_stat.get_h_blank_irq() {
+			self.io_regs.request(EIrq::HBlank);
+		}
+		if v_blank && disp_stat.get_v_blank_irq() {
+			self.io_regs.request(EIrq::VBlank);
+		}
+	}
+
+	/// Check the interrupt lines against IME/IE/IF and CPSR.I/F, returns the exception the CPU has to take
+	///
+	/// Requests stay pending in IF while IME is off or CPSR.I/F are set, and are delivered as soon as they get enabled.
+	/// Any pending interrupt wakes the CPU from Halt, even if it can't be delivered, Stop mode only ends with the interrupts of EIrq::STOP_WAKE_UP.
+	pub fn poll_interrupts(&mut self, cpu: &CPU) -> Option<EExceptionType> {
+		if self.io_regs.is_fiq_requested() {
+			self.io_regs.halted = false;
+			if !cpu.get_cpsr().get_f() {
+				self.io_regs.set_fiq_requested(false);
+				return Some(EExceptionType::Fiq);
+			}
+		}
+
+		if self.io_regs.get_wake_up_interrupts() == 0 {
+			return None;
+		}
+
+		self.io_regs.halted = false;
+		if self.io_regs.get_ime() && !cpu.get_cpsr().get_i() {
+			Some(EExceptionType::Irq)
+		} else {
+			None
+		}
+	}
+
+	/// Advance the timers by one cycle, their overflows clock the Direct Sound channels which ask DMA for more samples
+	pub fn step_timers(&mut self) {
+		let overflows = self.io_regs.step_timers();
+		if overflows != 0 {
+			let refill_requests = self.apu.on_timer_overflow(overflows);
+			for (fifo_address, requested) in [FIFO_A_ADDRESS, FIFO_B_ADDRESS].iter().zip(refill_requests.iter()) {
+				if *requested {
+					self.dma.request_fifo(IO_ADDR + fifo_address);
 				}
 			}
-			EWRAM_ADDR => self.external_wram[(address & 0x3_ffff) as usize],
-			IWRAM_ADDR => self.internal_wram[(address & 0x7fff) as usize],
-			IO_ADDR => {
-				if address & 0x00ff_ffff <= PPU_REGISTERS_END {
-					self.ppu.read_8(address)
-				} else {
-					self.io_regs.read_8(address)
+		}
+	}
+
+	/// Start the DMA channels waiting for the blanking periods and run all the pending transfers
+	/// NOTE: Transfers are done all at once, the CPU is stalled for their cycles afterwards (see take_dma_cycles)
+	pub fn step_dma(&mut self, h_blank: bool, v_blank: bool) {
+		if v_blank {
+			self.dma.on_v_blank();
+		}
+		if h_blank && !self.ppu.get_disp_stat().get_v_blank() {
+			self.dma.on_h_blank();
+		}
+
+		while let Some(transfer) = self.dma.take_transfer() {
+			if let (EMemoryRegion::CartridgeWs2, offset) = Self::resolve(transfer.dest) {
+				if self.cartridge.is_eeprom_offset(offset) {
+					self.cartridge.on_eeprom_dma(transfer.count);
 				}
 			}
-			PALETTE_RAM_ADDR | VRAM_ADDR | OAM_ADDR => self.ppu.read_8(address),
-			CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI | CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI | CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => {
-				let addr = address as usize & 0xff_ffff;
-				if self.cartridge_rom.len() > addr {
-					self.cartridge_rom[addr]
+
+			// NOTE: 2N + 2(n-1)S + xI, the internal cycles are 4 from GamePak to GamePak and 2 otherwise
+			let width = if transfer.is_32bit { EAccessWidth::Word } else { EAccessWidth::Halfword };
+			let is_gamepak_to_gamepak = Self::resolve(transfer.source).0.is_gamepak() && Self::resolve(transfer.dest).0.is_gamepak();
+			self.dma_cycles += if is_gamepak_to_gamepak { 4 } else { 2 };
+
+			let mut source = transfer.source;
+			let mut dest = transfer.dest;
+			for i in 0..transfer.count {
+				let sequential = i > 0;
+				self.dma_cycles += self.get_access_cycles(source, width, sequential) + self.get_access_cycles(dest, width, sequential);
+				if transfer.is_32bit {
+					let value = self.read_32(source & !0x3);
+					self.write_32(dest & !0x3, value);
 				} else {
-					((address / 2) & 0xffff) as u8
+					let value = self.read_16(source & !0x1);
+					self.write_16(dest & !0x1, value);
 				}
+
+				source = source.wrapping_add(transfer.source_step as u32);
+				dest = dest.wrapping_add(transfer.dest_step as u32);
+			}
+
+			if transfer.irq {
+				self.io_regs.request(EIrq::DMAS[transfer.channel]);
 			}
-			CARTRIDGE_SRAM_LO => self.cartridge_sram[(address & 0xffff) as usize],
-			_ => 0x0, // TODO: Return proper invalid value
 		}
 	}
 
-	fn write_8(&mut self, address: u32, value: u8) {
-		match address & 0xff00_0000 {
-			EWRAM_ADDR => self.external_wram[(address & 0x3_ffff) as usize] = value,
-			IWRAM_ADDR => self.internal_wram[(address & 0x7fff) as usize] = value,
-			IO_ADDR => {
-				if address & 0x00ff_ffff <= PPU_REGISTERS_END {
-					self.ppu.write_8(address, value);
-				} else {
-					self.io_regs.write_8(address, value);
-				}
-			}
-			PALETTE_RAM_ADDR | VRAM_ADDR | OAM_ADDR => self.ppu.write_8(address, value),
-			CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI | CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI | CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => {
-				let addr = address as usize & 0xff_ffff;
-				if self.cartridge_rom.len() > addr {
-					self.cartridge_rom[addr] = value
-				}
+	fn check_access(&self, address: u32, width: EAccessWidth, is_write: bool) {
+		if !self.watchpoints.is_empty() && self.watchpoint_hit.get().is_none() {
+			let size = match width {
+				EAccessWidth::Byte => 1,
+				EAccessWidth::Halfword => 2,
+				EAccessWidth::Word => 4,
+			};
+			if let Some(watchpoint) = self.watchpoints.iter().find(|watchpoint| watchpoint.matches(address, size, is_write)) {
+				self.watchpoint_hit.set(Some((*watchpoint, address)));
 			}
-			CARTRIDGE_SRAM_LO => self.cartridge_sram[(address & 0xffff) as usize] = value,
+		}
+		if !self.break_on_anomaly {
+			return;
+		}
+
+		match Self::resolve(address) {
+			// NOTE: Only the IO registers below 0x400 and the memory control register (0x800, mirrored every 64KB) exist
+			(EMemoryRegion::Io, offset) if offset >= 0x400 && !(0x800..0x804).contains(&(offset & 0xffff)) => self.report_anomaly(EAnomaly::UnmappedIo { address, is_write }),
+			(EMemoryRegion::Unmapped, _) if !is_write => self.report_anomaly(EAnomaly::OpenBusRead(address)),
 			_ => {}
 		}
 	}
 
-	fn read_16(&self, address: u32) -> u16 {
-		unsafe {
-			match address & 0xff00_0000 {
-				BIOS_ADDR => {
-					if address <= 0x3fff {
-						*(self.bios.as_ptr().offset(address as isize) as *mut u16) as u16
+	/// Read of the memory without the side effects of an access of the game (see check_access and count_access)
+	fn load_8(&self, address: u32) -> u8 {
+		let (region, offset) = Self::resolve(address);
+		match region {
+			EMemoryRegion::Bios => self.bios[offset as usize],
+			EMemoryRegion::Ewram => self.external_wram[offset as usize],
+			EMemoryRegion::Iwram => self.internal_wram[offset as usize],
+			EMemoryRegion::Io => {
+				if offset <= PPU_REGISTERS_END {
+					self.ppu.read_8(address)
+				} else if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&offset) {
+					self.apu.read_8(address)
+				} else if (DMA_REGISTERS_START..=DMA_REGISTERS_END).contains(&offset) {
+					if DmaController::is_write_only(address) {
+						self.get_open_bus(address) as u8
 					} else {
-						// TODO: Return proper invalid value
-						0x0
+						self.dma.read_8(address)
 					}
+				} else {
+					self.io_regs.read_8(address)
 				}
-				EWRAM_ADDR => *(self.external_wram.as_ptr().offset((address & 0x3_ffff) as isize) as *mut u16) as u16,
-				IWRAM_ADDR => *(self.internal_wram.as_ptr().offset((address & 0x7fff) as isize) as *mut u16) as u16,
-				IO_ADDR => {
-					if address & 0x00ff_ffff <= PPU_REGISTERS_END {
+			}
+			EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.read_8(address),
+			EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2 => self.cartridge.read_rom_8(offset),
+			EMemoryRegion::CartridgeSram => self.cartridge.read_backup_8(offset),
+			_ => 0x0, // TODO: Return proper invalid value
+		}
+	}
+
+	fn load_16(&self, address: u32) -> u16 {
+		unsafe {
+			let (region, offset) = Self::resolve(address);
+			match region {
+				EMemoryRegion::Bios => *(self.bios.as_ptr().add(offset as usize) as *mut u16),
+				EMemoryRegion::Ewram => *(self.external_wram.as_ptr().add(offset as usize) as *mut u16),
+				EMemoryRegion::Iwram => *(self.internal_wram.as_ptr().add(offset as usize) as *mut u16),
+				EMemoryRegion::Io => {
+					if offset <= PPU_REGISTERS_END {
 						self.ppu.read_16(address)
+					} else if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&offset) {
+						self.apu.read_16(address)
+					} else if (DMA_REGISTERS_START..=DMA_REGISTERS_END).contains(&offset) {
+						if DmaController::is_write_only(address) {
+							self.get_open_bus(address) as u16
+						} else {
+							self.dma.read_16(address)
+						}
 					} else {
 						self.io_regs.read_16(address)
 					}
 				}
-				PALETTE_RAM_ADDR | VRAM_ADDR | OAM_ADDR => self.ppu.read_16(address),
-				CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI | CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI | CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => {
-					let addr = address as usize & 0xff_ffff;
-					if self.cartridge_rom.len() > addr {
-						*(self.cartridge_rom.as_ptr().add(addr) as *mut u16) as u16
+				EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.read_16(address),
+				EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2 => self.cartridge.read_rom_16(offset),
+				EMemoryRegion::CartridgeSram => self.cartridge.read_backup_16(offset),
+				_ => 0x0, // TODO: Return proper invalid value
+			}
+		}
+	}
+
+	fn load_32(&self, address: u32) -> u32 {
+		unsafe {
+			let (region, offset) = Self::resolve(address);
+			match region {
+				EMemoryRegion::Bios => *(self.bios.as_ptr().add(offset as usize) as *mut u32),
+				EMemoryRegion::Ewram => *(self.external_wram.as_ptr().add(offset as usize) as *mut u32),
+				EMemoryRegion::Iwram => *(self.internal_wram.as_ptr().add(offset as usize) as *mut u32),
+				EMemoryRegion::Io => {
+					if offset <= PPU_REGISTERS_END {
+						self.ppu.read_32(address)
+					} else if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&offset) {
+						self.apu.read_32(address)
+					} else if (DMA_REGISTERS_START..=DMA_REGISTERS_END).contains(&offset) {
+						if DmaController::is_write_only(address) {
+							self.get_open_bus(address)
+						} else {
+							self.dma.read_32(address)
+						}
 					} else {
-						((address / 2) & 0xffff) as u16
+						self.io_regs.read_32(address)
 					}
 				}
-				CARTRIDGE_SRAM_LO => *(self.cartridge_sram.as_ptr().offset((address & 0xffff) as isize) as *mut u16) as u16,
+				EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.read_32(address),
+				EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2 => self.cartridge.read_rom_32(offset),
+				EMemoryRegion::CartridgeSram => self.cartridge.read_backup_32(offset),
 				_ => 0x0, // TODO: Return proper invalid value
 			}
 		}
 	}
 
+	/// Cycles of the DMA transfers made since the last call
+	pub fn take_dma_cycles(&mut self) -> u32 {
+		std::mem::take(&mut self.dma_cycles)
+	}
+
+	/// Cycles of the accesses made since the last call, and whether one of them used the GamePak bus
+	/// The CPU charges them to the instruction that made them
+	pub fn take_access_cycles(&self) -> (u32, bool) {
+		(self.access_cycles.replace(0), self.gamepak_accessed.replace(false))
+	}
+
+	/// NOTE: Data accesses are all counted as non-sequential, even the bursts of LDM/STM and DMA
+	fn count_access(&self, address: u32, width: EAccessWidth) {
+		self.access_cycles.set(self.access_cycles.get() + self.get_access_cycles(address, width, false));
+		if Self::resolve(address).0.is_gamepak() {
+			self.gamepak_accessed.set(true);
+		}
+	}
+
+	/// Whether the GamePak prefetch buffer is enabled in WAITCNT
+	pub fn is_prefetch_enabled(&self) -> bool {
+		self.io_regs.get_wait_cnt().get_prefetch_buffer()
+	}
+
+	/// Cycles taken by a data access, the GamePak wait states are the ones selected in WAITCNT
+	/// The GamePak bus is 16bit wide, so 32bit accesses are split into a first and a second halfword access
+	pub fn get_access_cycles(&self, address: u32, width: EAccessWidth, sequential: bool) -> u32 {
+		let wait_cnt = self.io_regs.get_wait_cnt();
+		let rom_wait_states = match Self::resolve(address).0 {
+			EMemoryRegion::CartridgeWs0 => wait_cnt.get_rom_wait_states(0),
+			EMemoryRegion::CartridgeWs1 => wait_cnt.get_rom_wait_states(1),
+			EMemoryRegion::CartridgeWs2 => wait_cnt.get_rom_wait_states(2),
+			EMemoryRegion::Ewram => return if width == EAccessWidth::Word { 6 } else { 3 },
+			EMemoryRegion::PaletteRam | EMemoryRegion::Vram => return if width == EAccessWidth::Word { 2 } else { 1 },
+			EMemoryRegion::CartridgeSram => return 1 + wait_cnt.get_sram_wait_states(),
+			_ => return 1,
+		};
+
+		let (first, second) = rom_wait_states;
+		let halfword_cycles = 1 + if sequential { second } else { first };
+		if width == EAccessWidth::Word {
+			halfword_cycles + 1 + second
+		} else {
+			halfword_cycles
+		}
+	}
+
+	/// Cycles taken by an opcode fetch, ROM fetches go through the prefetch buffer when it's enabled
+	/// Without prefetch every GamePak opcode fetch pays the full wait states, which makes ARM code in ROM twice as slow as THUMB
+	pub fn fetch_opcode_cycles(&mut self, address: u32, width: EAccessWidth, sequential: bool) -> u32 {
+		let access_cycles = self.get_access_cycles(address, width, sequential);
+		if !Self::resolve(address).0.is_cartridge_rom() {
+			// NOTE: The prefetch buffer keeps reading ahead while the CPU runs from another memory
+			self.run_prefetch(access_cycles, false);
+			return access_cycles;
+		}
+		if !self.is_prefetch_enabled() {
+			self.prefetch.stop();
+			return access_cycles;
+		}
+
+		let halfwords = if width == EAccessWidth::Word { 2 } else { 1 };
+		let halfword_cycles = self.get_access_cycles(address.wrapping_add(halfwords * 2), EAccessWidth::Halfword, true);
+		self.prefetch.fetch(address, halfwords, halfword_cycles, access_cycles)
+	}
+
+	/// Latch an opcode on the bus, THUMB opcodes show up on both of its halves
+	pub fn latch_opcode(&mut self, opcode: u32, width: EAccessWidth) {
+		self.open_bus = if width == EAccessWidth::Halfword { (opcode & 0xffff) * 0x0001_0001 } else { opcode };
+	}
+
+	/// Open bus value at the byte lanes of an address
+	fn get_open_bus(&self, address: u32) -> u32 {
+		self.open_bus >> ((address & 0x3) * 8)
+	}
+
+	/// Let the prefetch buffer read ahead during the data access cycles of an instruction, an access on the GamePak bus stops it instead
+	pub fn run_prefetch(&mut self, cycles: u32, gamepak_accessed: bool) {
+		if gamepak_accessed || !self.is_prefetch_enabled() {
+			self.prefetch.stop();
+			return;
+		}
+
+		let halfword_cycles = self.get_access_cycles(self.prefetch.get_next_address(), EAccessWidth::Halfword, true);
+		self.prefetch.run(cycles, halfword_cycles);
+	}
+
+	/// Write consecutive halfwords (eg. DMA transfers), video memory takes the PPU bulk path
+	pub fn write_block_16(&mut self, address: u32, data: &[u16]) {
+		match Self::resolve(address).0 {
+			EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.write_halfwords(address, data),
+			_ => {
+				for (i, value) in data.iter().enumerate() {
+					self.write_16(address.wrapping_add(i as u32 * 2), *value);
+				}
+			}
+		}
+	}
+
+	/// If no BIOS image is loaded BIOS calls should be high level emulated
+	pub fn is_bios_loaded(&self) -> bool {
+		!self.hle_bios
+	}
+}
+
+impl SaveState for SystemBus {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		match self.ram_init {
+			ERamInit::Zero => writer.write_bool(false)?,
+			ERamInit::PseudoRandom(seed) => {
+				writer.write_bool(true)?;
+				writer.write_u64(seed)?;
+			}
+		}
+		writer.write_bytes(&self.external_wram)?;
+		writer.write_bytes(&self.internal_wram)?;
+		self.io_regs.save_state(writer)?;
+		self.dma.save_state(writer)?;
+		self.ppu.save_state(writer)?;
+		self.apu.save_state(writer)?;
+		self.cartridge.save_state(writer)?;
+		self.prefetch.save_state(writer)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.ram_init = if reader.read_bool()? {
+			ERamInit::PseudoRandom(reader.read_u64()?)
+		} else {
+			ERamInit::Zero
+		};
+		reader.read_bytes(&mut self.external_wram)?;
+		reader.read_bytes(&mut self.internal_wram)?;
+		self.io_regs.load_state(reader)?;
+		self.dma.load_state(reader)?;
+		self.ppu.load_state(reader)?;
+		self.apu.load_state(reader)?;
+		self.cartridge.load_state(reader)?;
+		self.prefetch.load_state(reader)
+	}
+}
+
+impl MemoryInterface for SystemBus {
+	fn read_8(&self, address: u32) -> u8 {
+		self.check_access(address, EAccessWidth::Byte, false);
+		self.count_access(address, EAccessWidth::Byte);
+		self.load_8(address)
+	}
+
+	fn write_8(&mut self, address: u32, value: u8) {
+		self.check_access(address, EAccessWidth::Byte, true);
+		self.count_access(address, EAccessWidth::Byte);
+
+		let (region, offset) = Self::resolve(address);
+		match region {
+			EMemoryRegion::Ewram => self.external_wram[offset as usize] = value,
+			EMemoryRegion::Iwram => self.internal_wram[offset as usize] = value,
+			EMemoryRegion::Io => {
+				if offset <= PPU_REGISTERS_END {
+					self.ppu.write_8(address, value);
+				} else if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&offset) {
+					self.apu.write_8(address, value);
+				} else if (DMA_REGISTERS_START..=DMA_REGISTERS_END).contains(&offset) {
+					self.dma.write_8(address, value);
+				} else {
+					self.io_regs.write_8(address, value);
+				}
+			}
+			EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.write_8(address, value),
+			// NOTE: ROM is read-only, games often do harmless dummy writes
+			EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2 => self.rom_write_attempts = self.rom_write_attempts.saturating_add(1),
+			EMemoryRegion::CartridgeSram => self.cartridge.write_backup_8(offset, value),
+			_ => {}
+		}
+	}
+
+	fn read_16(&self, address: u32) -> u16 {
+		self.check_access(address, EAccessWidth::Halfword, false);
+		self.count_access(address, EAccessWidth::Halfword);
+		self.load_16(address)
+	}
+
 	fn write_16(&mut self, address: u32, value: u16) {
+		self.check_access(address, EAccessWidth::Halfword, true);
+		self.count_access(address, EAccessWidth::Halfword);
+
 		unsafe {
-			match address & 0xff00_0000 {
-				EWRAM_ADDR => *(self.external_wram.as_ptr().offset((address & 0x3_ffff) as isize) as *mut u16) = value,
-				IWRAM_ADDR => *(self.internal_wram.as_ptr().offset((address & 0x7fff) as isize) as *mut u16) = value,
-				IO_ADDR => {
-					if address & 0x00ff_ffff <= PPU_REGISTERS_END {
+			let (region, offset) = Self::resolve(address);
+			match region {
+				EMemoryRegion::Ewram => *(self.external_wram.as_ptr().add(offset as usize) as *mut u16) = value,
+				EMemoryRegion::Iwram => *(self.internal_wram.as_ptr().add(offset as usize) as *mut u16) = value,
+				EMemoryRegion::Io => {
+					if offset <= PPU_REGISTERS_END {
 						self.ppu.write_16(address, value);
+					} else if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&offset) {
+						self.apu.write_16(address, value);
+					} else if (DMA_REGISTERS_START..=DMA_REGISTERS_END).contains(&offset) {
+						self.dma.write_16(address, value);
 					} else {
 						self.io_regs.write_16(address, value);
 					}
 				}
-				PALETTE_RAM_ADDR | VRAM_ADDR | OAM_ADDR => self.ppu.write_16(address, value),
-				CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI | CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI | CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => {
-					let addr = address as usize & 0xff_ffff;
-					if self.cartridge_rom.len() > addr {
-						*(self.cartridge_rom.as_ptr().add(addr) as *mut u16) = value
+				EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.write_16(address, value),
+				EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2 => {
+					// NOTE: Writes to the GPIO port aren't dummy writes
+					let is_gpio_write = self.cartridge.write_rom_16(offset, value);
+					if !is_gpio_write {
+						self.rom_write_attempts = self.rom_write_attempts.saturating_add(1);
 					}
 				}
-				CARTRIDGE_SRAM_LO => *(self.cartridge_sram.as_ptr().offset((address & 0xffff) as isize) as *mut u16) = value,
+				EMemoryRegion::CartridgeSram => self.cartridge.write_backup_16(offset, value),
 				_ => {}
 			}
 		}
 	}
 
 	fn read_32(&self, address: u32) -> u32 {
-		unsafe {
-			match address & 0xff00_0000 {
-				BIOS_ADDR => {
-					if address <= 0x3fff {
-						*(self.bios.as_ptr().offset(address as isize) as *mut u32) as u32
-					} else {
-						// TODO: Return proper invalid value
-						0x0
-					}
-				}
-				EWRAM_ADDR => *(self.external_wram.as_ptr().offset((address & 0x3_ffff) as isize) as *mut u32) as u32,
-				IWRAM_ADDR => *(self.internal_wram.as_ptr().offset((address & 0x7fff) as isize) as *mut u32) as u32,
-				IO_ADDR => {
-					if address & 0x00ff_ffff <= PPU_REGISTERS_END {
-						self.ppu.read_32(address)
-					} else {
-						self.io_regs.read_32(address)
-					}
-				}
-				PALETTE_RAM_ADDR | VRAM_ADDR | OAM_ADDR => self.ppu.read_32(address),
-				CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI | CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI | CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => {
-					let addr = address as usize & 0xff_ffff;
-					if self.cartridge_rom.len() > addr {
-						*(self.cartridge_rom.as_ptr().add(addr) as *mut u32) as u32
-					} else {
-						(address / 2) & 0xffff
-					}
-				}
-				CARTRIDGE_SRAM_LO => *(self.cartridge_sram.as_ptr().offset((address & 0xffff) as isize) as *mut u32) as u32,
-				_ => 0x0, // TODO: Return proper invalid value
-			}
-		}
+		self.check_access(address, EAccessWidth::Word, false);
+		self.count_access(address, EAccessWidth::Word);
+		self.load_32(address)
 	}
 
 	fn write_32(&mut self, address: u32, value: u32) {
+		self.check_access(address, EAccessWidth::Word, true);
+		self.count_access(address, EAccessWidth::Word);
+
 		unsafe {
-			match address & 0xff00_0000 {
-				EWRAM_ADDR => *(self.external_wram.as_ptr().offset((address & 0x3_ffff) as isize) as *mut u32) = value,
-				IWRAM_ADDR => *(self.internal_wram.as_ptr().offset((address & 0x7fff) as isize) as *mut u32) = value,
-				IO_ADDR => {
-					if address & 0x00ff_ffff <= PPU_REGISTERS_END {
+			let (region, offset) = Self::resolve(address);
+			match region {
+				EMemoryRegion::Ewram => *(self.external_wram.as_ptr().add(offset as usize) as *mut u32) = value,
+				EMemoryRegion::Iwram => *(self.internal_wram.as_ptr().add(offset as usize) as *mut u32) = value,
+				EMemoryRegion::Io => {
+					if offset <= PPU_REGISTERS_END {
 						self.ppu.write_32(address, value);
+					} else if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&offset) {
+						self.apu.write_32(address, value);
+					} else if (DMA_REGISTERS_START..=DMA_REGISTERS_END).contains(&offset) {
+						self.dma.write_32(address, value);
 					} else {
 						self.io_regs.write_32(address, value);
 					}
 				}
-				PALETTE_RAM_ADDR | VRAM_ADDR | OAM_ADDR => self.ppu.write_32(address, value),
-				CARTRIDGE_WS0_LO | CARTRIDGE_WS0_HI | CARTRIDGE_WS1_LO | CARTRIDGE_WS1_HI | CARTRIDGE_WS2_LO | CARTRIDGE_WS2_HI => {
-					let addr = address as usize & 0xff_ffff;
-					if self.cartridge_rom.len() > addr {
-						*(self.cartridge_rom.as_ptr().add(addr) as *mut u32) = value
-					}
-				}
-				CARTRIDGE_SRAM_LO => *(self.cartridge_sram.as_ptr().offset((address & 0xffff) as isize) as *mut u32) = value,
+				EMemoryRegion::PaletteRam | EMemoryRegion::Vram | EMemoryRegion::Oam => self.ppu.write_32(address, value),
+				EMemoryRegion::CartridgeWs0 | EMemoryRegion::CartridgeWs1 | EMemoryRegion::CartridgeWs2 => self.rom_write_attempts = self.rom_write_attempts.saturating_add(1),
+				EMemoryRegion::CartridgeSram => self.cartridge.write_backup_32(offset, value),
 				_ => {}
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn setup() -> SystemBus {
+		let mut bus = SystemBus::new(Box::new([]));
+		bus.set_break_on_anomaly(true);
+		bus
+	}
+
+	#[test]
+	fn memory_control_register_and_its_mirrors_are_mapped() {
+		let mut bus = setup();
+		for address in [0x400_0800, 0x401_0800, 0x4ff_0800] {
+			bus.read_32(address);
+			assert_eq!(bus.take_anomaly(), None, "{:#010x}", address);
+		}
+
+		for address in [0x400_0400, 0x401_0000] {
+			bus.read_16(address);
+			assert_eq!(bus.take_anomaly(), Some(EAnomaly::UnmappedIo { address, is_write: false }));
+		}
+	}
+	#[test]
+	fn rom_write_attempts_saturate() {
+		let mut bus = setup();
+		bus.rom_write_attempts = u32::MAX - 1;
+		bus.write_8(CARTRIDGE_WS0_LO, 0);
+		bus.write_16(CARTRIDGE_WS1_LO, 0);
+		bus.write_32(CARTRIDGE_WS2_LO, 0);
+		assert_eq!(bus.get_rom_write_attempts(), u32::MAX);
+	}
+
+	const WAITCNT: u32 = IO_ADDR + io::WAITCNT_ADDRESS;
+	/// WAITCNT bit enabling the GamePak prefetch buffer
+	const PREFETCH_ENABLE: u16 = 1 << 14;
+
+	#[test]
+	fn wait_states_are_selected_per_rom_mirror() {
+		let mut bus = setup();
+		// NOTE: Power on settings, N = 4 for all of them and S = 2/4/8 for WS0/1/2
+		for (address, sequential) in [(CARTRIDGE_WS0_LO, 3), (CARTRIDGE_WS1_LO, 5), (CARTRIDGE_WS2_LO, 9)] {
+			assert_eq!(bus.get_access_cycles(address, EAccessWidth::Halfword, false), 5, "{:#010x}", address);
+			assert_eq!(bus.get_access_cycles(address, EAccessWidth::Halfword, true), sequential, "{:#010x}", address);
+		}
+
+		// NOTE: WS0 N = 8 S = 1, WS1 N = 3 S = 1, WS2 N = 2 S = 8
+		bus.write_16(WAITCNT, 0b10_1011_1100);
+		for (address, non_sequential, sequential) in [(CARTRIDGE_WS0_HI, 9, 2), (CARTRIDGE_WS1_HI, 4, 2), (CARTRIDGE_WS2_HI, 3, 9)] {
+			assert_eq!(bus.get_access_cycles(address, EAccessWidth::Halfword, false), non_sequential, "{:#010x}", address);
+			assert_eq!(bus.get_access_cycles(address, EAccessWidth::Halfword, true), sequential, "{:#010x}", address);
+		}
+	}
+
+	#[test]
+	fn arm_rom_fetch_costs_two_halfword_accesses() {
+		let mut bus = setup();
+		bus.write_16(WAITCNT, 0b1_0100);
+		// NOTE: WS0 N = 3 S = 1, the second halfword is always sequential
+		assert_eq!(bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO, EAccessWidth::Word, false), (1 + 3) + (1 + 1));
+		assert_eq!(bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO + 4, EAccessWidth::Word, true), (1 + 1) + (1 + 1));
+	}
+
+	#[test]
+	fn thumb_rom_fetch_costs_one_halfword_access() {
+		let mut bus = setup();
+		bus.write_16(WAITCNT, 0b1_0100);
+		assert_eq!(bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO, EAccessWidth::Halfword, false), 1 + 3);
+		assert_eq!(bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO + 2, EAccessWidth::Halfword, true), 1 + 1);
+	}
+
+	/// Sequential THUMB fetches from ROM, while the CPU leaves the GamePak bus free for 6 cycles between them
+	fn run_thumb_fetches(bus: &mut SystemBus) -> Vec<u32> {
+		let mut cycles = vec![bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO, EAccessWidth::Halfword, false)];
+		for i in 1..4 {
+			bus.run_prefetch(6, false);
+			cycles.push(bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO + i * 2, EAccessWidth::Halfword, true));
+		}
+		cycles
+	}
+
+	#[test]
+	fn prefetch_hides_the_rom_wait_states() {
+		let mut bus = setup();
+		bus.write_16(WAITCNT, PREFETCH_ENABLE);
+		assert_eq!(run_thumb_fetches(&mut bus), [5, 1, 1, 1]);
+
+		// NOTE: A data access on the GamePak bus empties the buffer
+		bus.run_prefetch(6, true);
+		assert_eq!(bus.fetch_opcode_cycles(CARTRIDGE_WS0_LO + 8, EAccessWidth::Halfword, true), 3);
+	}
+
+	#[test]
+	fn without_prefetch_every_rom_fetch_pays_the_wait_states() {
+		let mut bus = setup();
+		assert_eq!(run_thumb_fetches(&mut bus), [5, 3, 3, 3]);
+	}
+}
@@ -0,0 +1,141 @@
+use std::io::Result;
+
+use crate::savestate::{SaveState, StateReader, StateWriter};
+
+/// Halfwords the GamePak prefetch buffer holds
+pub const PREFETCH_BUFFER_SIZE: u32 = 8;
+
+/// GamePak prefetch unit: while the CPU doesn't use the GamePak bus, it reads ahead the halfwords following the last opcode fetched from ROM
+/// Sequential opcode fetches then find them in the buffer and take 1 cycle per halfword
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PrefetchBuffer {
+	/// Address of the first buffered halfword, or of the one being read when the buffer is empty
+	head_address: u32,
+	/// Halfwords in the buffer
+	count: u32,
+	/// Cycles already spent reading the halfword after the buffered ones
+	progress: u32,
+	/// The buffer only reads ahead after an opcode fetch from ROM
+	active: bool,
+}
+
+impl PrefetchBuffer {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Empty the buffer and stop reading ahead, eg. when the CPU accesses data on the GamePak bus
+	pub fn stop(&mut self) {
+		*self = Self::new();
+	}
+
+	/// Address of the next halfword to read ahead
+	pub fn get_next_address(&self) -> u32 {
+		self.head_address.wrapping_add(self.count * 2)
+	}
+
+	/// Read ahead during cycles the CPU leaves the GamePak bus free, a halfword takes halfword_cycles (a sequential access)
+	pub fn run(&mut self, cycles: u32, halfword_cycles: u32) {
+		if !self.active || self.count == PREFETCH_BUFFER_SIZE {
+			return;
+		}
+
+		self.progress += cycles;
+		while self.count < PREFETCH_BUFFER_SIZE && self.progress >= halfword_cycles {
+			self.progress -= halfword_cycles;
+			self.count += 1;
+		}
+		if self.count == PREFETCH_BUFFER_SIZE {
+			self.progress = 0;
+		}
+	}
+
+	/// Cycles of an opcode fetch from ROM, buffered halfwords take 1 cycle and the one being read takes what's left of its access
+	/// A fetch the buffer can't serve (eg. after a branch) is a normal access taking access_cycles, the buffer then reads ahead from there
+	pub fn fetch(&mut self, address: u32, halfwords: u32, halfword_cycles: u32, access_cycles: u32) -> u32 {
+		if !self.active || address != self.head_address {
+			self.active = true;
+			self.head_address = address.wrapping_add(halfwords * 2);
+			self.count = 0;
+			self.progress = 0;
+			return access_cycles;
+		}
+
+		let mut cycles = 0;
+		let mut buffered_cycles = 0;
+		for _ in 0..halfwords {
+			if self.count > 0 {
+				self.count -= 1;
+				buffered_cycles += 1;
+			} else {
+				cycles += halfword_cycles.saturating_sub(self.progress).max(1);
+				self.progress = 0;
+			}
+			self.head_address = self.head_address.wrapping_add(2);
+		}
+
+		// NOTE: Taking halfwords out of the buffer leaves the GamePak bus free
+		self.run(buffered_cycles, halfword_cycles);
+		cycles + buffered_cycles
+	}
+}
+
+impl SaveState for PrefetchBuffer {
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u32(self.head_address)?;
+		writer.write_u32(self.count)?;
+		writer.write_u32(self.progress)?;
+		writer.write_bool(self.active)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.head_address = reader.read_u32()?;
+		self.count = reader.read_u32()?.min(PREFETCH_BUFFER_SIZE);
+		self.progress = reader.read_u32()?;
+		self.active = reader.read_bool()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const ROM_ADDRESS: u32 = 0x0800_0000;
+
+	#[test]
+	fn a_fetch_the_buffer_does_not_hold_is_a_normal_access() {
+		let mut prefetch = PrefetchBuffer::new();
+		assert_eq!(prefetch.fetch(ROM_ADDRESS, 1, 3, 5), 5);
+		assert_eq!(prefetch.get_next_address(), ROM_ADDRESS + 2);
+
+		// NOTE: A branch elsewhere starts over from the target
+		prefetch.run(6, 3);
+		assert_eq!(prefetch.fetch(ROM_ADDRESS + 0x100, 1, 3, 5), 5);
+	}
+
+	#[test]
+	fn buffered_halfwords_take_one_cycle() {
+		let mut prefetch = PrefetchBuffer::new();
+		prefetch.fetch(ROM_ADDRESS, 2, 3, 8);
+		prefetch.run(6, 3);
+		assert_eq!(prefetch.fetch(ROM_ADDRESS + 4, 2, 3, 6), 2);
+	}
+
+	#[test]
+	fn the_halfword_being_read_takes_what_is_left_of_its_access() {
+		let mut prefetch = PrefetchBuffer::new();
+		prefetch.fetch(ROM_ADDRESS, 1, 3, 5);
+		prefetch.run(2, 3);
+		assert_eq!(prefetch.fetch(ROM_ADDRESS + 2, 1, 3, 3), 1);
+		assert_eq!(prefetch.fetch(ROM_ADDRESS + 4, 1, 3, 3), 3);
+	}
+
+	#[test]
+	fn the_buffer_holds_8_halfwords() {
+		let mut prefetch = PrefetchBuffer::new();
+		prefetch.fetch(ROM_ADDRESS, 1, 3, 5);
+		prefetch.run(1000, 3);
+		assert_eq!(prefetch.get_next_address(), ROM_ADDRESS + 2 + PREFETCH_BUFFER_SIZE * 2);
+	}
+}
@@ -0,0 +1,305 @@
+use std::io::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::savestate::{SaveState, StateReader, StateWriter};
+
+/// GPIO pins of the RTC
+pub const RTC_SCK_PIN: u16 = 1 << 0;
+pub const RTC_SIO_PIN: u16 = 1 << 1;
+pub const RTC_CS_PIN: u16 = 1 << 2;
+
+/// Bits 0-3 of a command byte, the byte is sent MSB first so games write 0x6x
+const RTC_COMMAND_MAGIC: u8 = 0x6;
+/// Command bit 7, the RTC sends the data bytes instead of receiving them
+const RTC_COMMAND_READ_BIT: u8 = 1 << 7;
+
+/// Control register bits
+const RTC_CONTROL_24_HOUR: u8 = 1 << 6;
+const RTC_CONTROL_WRITABLE: u8 = 0b0110_1010;
+/// Hour bit 7, set in the afternoon in both hour modes
+const RTC_HOUR_PM: u8 = 1 << 7;
+
+/// Commands of the RTC registers, bits 4-6 of the command byte
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ERtcCommand {
+	Reset,
+	/// Year, month, day, day of the week, hour, minute and second
+	DateTime,
+	ForceIrq,
+	Control,
+	/// Hour, minute and second
+	Time,
+	Unused,
+}
+
+impl ERtcCommand {
+	fn from_bits(value: u8) -> Self {
+		match value & 0x7 {
+			0 => ERtcCommand::Reset,
+			2 => ERtcCommand::DateTime,
+			3 => ERtcCommand::ForceIrq,
+			4 => ERtcCommand::Control,
+			6 => ERtcCommand::Time,
+			_ => ERtcCommand::Unused,
+		}
+	}
+
+	/// Data bytes following the command byte
+	fn get_length(&self) -> usize {
+		match self {
+			ERtcCommand::DateTime => 7,
+			ERtcCommand::Control => 1,
+			ERtcCommand::Time => 3,
+			_ => 0,
+		}
+	}
+}
+
+/// Seiko S-3511 real-time clock, talked to through the GPIO port with a serial protocol:
+/// CS goes high to start a transfer, then each bit is sent on SIO and latched when SCK rises, LSB first
+/// The first byte is the command, followed by the data bytes of its register
+///
+/// NOTE: The clock follows the host clock (UTC), the time set by the game is kept as an offset to it
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Rtc {
+	/// Pins written by the GBA in the last GPIO write
+	pins: u16,
+	/// Value the RTC drives on SIO during reads
+	output: bool,
+	command: Option<(ERtcCommand, bool)>,
+	/// Byte being shifted in or out and its number of bits done
+	shift: u8,
+	bit_count: u32,
+	/// Data bytes of the current command, read or written
+	data: [u8; 7],
+	data_index: usize,
+	control: u8,
+	/// Seconds between the host clock and the clock of the RTC
+	offset: i64,
+}
+
+impl Rtc {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn get_control(&self) -> u8 {
+		self.control
+	}
+
+	pub fn get_offset(&self) -> i64 {
+		self.offset
+	}
+
+	/// Current time of the RTC, in seconds since 1970-01-01
+	pub fn get_time(&self) -> i64 {
+		get_host_time() + self.offset
+	}
+
+	/// Value of SIO for the GBA to read, only used while the pin is an input
+	pub fn read_pins(&self) -> u16 {
+		RTC_SIO_PIN * self.output as u16
+	}
+
+	/// Update the pins driven by the GBA, transfers advance on the rising edges of SCK
+	pub fn write_pins(&mut self, pins: u16) {
+		let previous = self.pins;
+		self.pins = pins;
+
+		if pins & RTC_CS_PIN == 0 {
+			self.end_transfer();
+			return;
+		}
+		if previous & RTC_CS_PIN == 0 || previous & RTC_SCK_PIN != 0 || pins & RTC_SCK_PIN == 0 {
+			return;
+		}
+
+		match self.command {
+			Some((_, true)) => {
+				self.output = (self.shift >> self.bit_count) & 1 != 0;
+				self.bit_count += 1;
+				if self.bit_count == 8 {
+					self.data_index += 1;
+					self.shift = self.data.get(self.data_index).cloned().unwrap_or_default();
+					self.bit_count = 0;
+				}
+			}
+			_ => {
+				self.shift |= ((pins & RTC_SIO_PIN != 0) as u8) << self.bit_count;
+				self.bit_count += 1;
+				if self.bit_count == 8 {
+					self.receive_byte(self.shift);
+					self.shift = if matches!(self.command, Some((_, true))) { self.data[0] } else { 0 };
+					self.bit_count = 0;
+				}
+			}
+		}
+	}
+
+	fn end_transfer(&mut self) {
+		self.command = None;
+		self.shift = 0;
+		self.bit_count = 0;
+		self.data_index = 0;
+	}
+
+	fn receive_byte(&mut self, value: u8) {
+		let (command, _) = match self.command {
+			Some(command) => command,
+			None => {
+				if value & 0xf != RTC_COMMAND_MAGIC {
+					return;
+				}
+				let command = ERtcCommand::from_bits(value >> 4);
+				let is_read = value & RTC_COMMAND_READ_BIT != 0;
+				self.start_command(command, is_read);
+				return;
+			}
+		};
+
+		if self.data_index < command.get_length() {
+			self.data[self.data_index] = value;
+			self.data_index += 1;
+		}
+		if self.data_index == command.get_length() {
+			self.apply_write(command);
+		}
+	}
+
+	fn start_command(&mut self, command: ERtcCommand, is_read: bool) {
+		self.command = Some((command, is_read));
+		self.data_index = 0;
+		self.data = [0; 7];
+
+		let date_time = self.get_date_time();
+		match command {
+			// NOTE: The reset also sets the clock to 2000-01-01, games only reset it after a power failure which never happens here
+			ERtcCommand::Reset => self.control = 0,
+			ERtcCommand::DateTime if is_read => self.data = date_time,
+			ERtcCommand::Time if is_read => self.data[..3].copy_from_slice(&date_time[4..]),
+			ERtcCommand::Control if is_read => self.data[0] = self.control,
+			_ => {}
+		}
+		if command.get_length() == 0 {
+			self.command = None;
+		}
+	}
+
+	/// NOTE: Written dates are only checked for being in range, like the chip which ignores invalid ones
+	fn apply_write(&mut self, command: ERtcCommand) {
+		match command {
+			ERtcCommand::Control => self.control = self.data[0] & RTC_CONTROL_WRITABLE,
+			ERtcCommand::DateTime => {
+				let mut date_time = self.data;
+				date_time[4] = self.get_24_hour(date_time[4]);
+				if let Some(time) = from_date_time(&date_time) {
+					self.offset = time - get_host_time();
+				}
+			}
+			ERtcCommand::Time => {
+				let mut date_time = self.get_date_time();
+				date_time[4..].copy_from_slice(&self.data[..3]);
+				date_time[4] = self.get_24_hour(date_time[4]);
+				if let Some(time) = from_date_time(&date_time) {
+					self.offset = time - get_host_time();
+				}
+			}
+			_ => {}
+		}
+		self.command = None;
+	}
+
+	/// BCD hour written by the game, in 24 hour format
+	fn get_24_hour(&self, hour: u8) -> u8 {
+		let value = from_bcd(hour & !RTC_HOUR_PM);
+		if self.control & RTC_CONTROL_24_HOUR != 0 {
+			to_bcd(value)
+		} else {
+			to_bcd(value % 12 + if hour & RTC_HOUR_PM != 0 { 12 } else { 0 })
+		}
+	}
+
+	/// Registers of the DateTime command: BCD year (2000-2099), month, day, day of the week (0 is Sunday), hour, minute and second
+	fn get_date_time(&self) -> [u8; 7] {
+		let time = self.get_time();
+		let days = time.div_euclid(86400);
+		let seconds = time.rem_euclid(86400);
+		let (year, month, day) = civil_from_days(days);
+		let hour = (seconds / 3600) as u8;
+		let hour_register = if self.control & RTC_CONTROL_24_HOUR != 0 { hour } else { hour % 12 };
+
+		[
+			to_bcd(year.rem_euclid(100) as u8),
+			to_bcd(month),
+			to_bcd(day),
+			// NOTE: 1970-01-01 was a Thursday
+			(days + 4).rem_euclid(7) as u8,
+			to_bcd(hour_register) | if hour >= 12 { RTC_HOUR_PM } else { 0 },
+			to_bcd((seconds / 60 % 60) as u8),
+			to_bcd((seconds % 60) as u8),
+		]
+	}
+}
+
+impl SaveState for Rtc {
+	/// NOTE: Only the clock is saved, games don't save in the middle of a transfer
+	fn save_state(&self, writer: &mut StateWriter) -> Result<()> {
+		writer.write_u8(self.control)?;
+		writer.write_u64(self.offset as u64)
+	}
+
+	fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+		self.end_transfer();
+		self.control = reader.read_u8()?;
+		self.offset = reader.read_u64()? as i64;
+		Ok(())
+	}
+}
+
+fn get_host_time() -> i64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64)
+}
+
+fn to_bcd(value: u8) -> u8 {
+	((value / 10) << 4) | (value % 10)
+}
+
+fn from_bcd(value: u8) -> u8 {
+	(value >> 4) * 10 + (value & 0xf)
+}
+
+/// Seconds since 1970-01-01 of DateTime registers, None if a field is out of range
+fn from_date_time(date_time: &[u8; 7]) -> Option<i64> {
+	let [year, month, day, _, hour, minute, second] = date_time.map(from_bcd);
+	if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+		return None;
+	}
+
+	let days = days_from_civil(2000 + year as i64, month, day);
+	Some(days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let month = month as i64;
+	let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+/// Date (year, month, day) of a number of days since 1970-01-01, the inverse of days_from_civil
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+	let days = days + 719468;
+	let era = days.div_euclid(146097);
+	let day_of_era = days - era * 146097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_index = (5 * day_of_year + 2) / 153;
+	let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u8;
+	let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u8;
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
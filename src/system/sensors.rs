@@ -0,0 +1,125 @@
+/// GPIO pins of the solar sensor
+const SOLAR_CLOCK_PIN: u16 = 1 << 0;
+const SOLAR_RESET_PIN: u16 = 1 << 1;
+/// Chip select, active low
+const SOLAR_SELECT_PIN: u16 = 1 << 2;
+const SOLAR_FLAG_PIN: u16 = 1 << 3;
+
+/// GPIO pins of the gyro sensor and the rumble motor
+const GYRO_SAMPLE_PIN: u16 = 1 << 0;
+const GYRO_CLOCK_PIN: u16 = 1 << 1;
+const GYRO_DATA_PIN: u16 = 1 << 2;
+const RUMBLE_PIN: u16 = 1 << 3;
+
+pub const SOLAR_SENSOR_MAX_LEVEL: u8 = 10;
+/// Light added to the darkness value by each level, the values of a real sensor in the sun as measured by other emulators
+const SOLAR_SENSOR_LEVELS: [u8; SOLAR_SENSOR_MAX_LEVEL as usize + 1] = [0, 5, 11, 18, 27, 42, 62, 84, 109, 139, 183];
+const SOLAR_SENSOR_DARKNESS: u8 = 0x16;
+
+/// Sample of the gyro sensor at rest, the rotation moves it by up to GYRO_SENSOR_RANGE
+const GYRO_SENSOR_CENTER: i32 = 0x6c0;
+const GYRO_SENSOR_RANGE: i32 = 0x400;
+
+/// Light sensor of the Boktai games: the game resets a counter, then clocks it until the flag tells it reached the light level
+/// NOTE: The level is host input, set from the debug menu
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SolarSensor {
+	level: u8,
+	counter: u16,
+	/// Counter value at which the flag is set, lower with more light
+	threshold: u16,
+	clock: bool,
+}
+
+impl SolarSensor {
+	pub fn new() -> Self {
+		Self {
+			threshold: Self::get_threshold(0),
+			..Self::default()
+		}
+	}
+
+	/// Light level from 0 (darkness) to SOLAR_SENSOR_MAX_LEVEL (full sun)
+	pub fn get_level(&self) -> u8 {
+		self.level
+	}
+
+	pub fn set_level(&mut self, level: u8) {
+		self.level = level.min(SOLAR_SENSOR_MAX_LEVEL);
+	}
+
+	fn get_threshold(level: u8) -> u16 {
+		0xff - (SOLAR_SENSOR_DARKNESS + SOLAR_SENSOR_LEVELS[level as usize]) as u16
+	}
+
+	pub fn read_pins(&self) -> u16 {
+		SOLAR_FLAG_PIN * (self.counter >= self.threshold) as u16
+	}
+
+	/// The level is sampled when the counter is reset, each rising edge of the clock increments it
+	pub fn write_pins(&mut self, pins: u16) {
+		if pins & SOLAR_SELECT_PIN != 0 {
+			return;
+		}
+
+		if pins & SOLAR_RESET_PIN != 0 {
+			self.counter = 0;
+			self.threshold = Self::get_threshold(self.level);
+		}
+		let clock = pins & SOLAR_CLOCK_PIN != 0;
+		if clock && !self.clock {
+			self.counter = self.counter.saturating_add(1);
+		}
+		self.clock = clock;
+	}
+}
+
+/// Gyro sensor and rumble motor of WarioWare: Twisted!, the game latches a sample of the rotation speed then shifts it out MSB first
+/// NOTE: The rotation is host input, set by the frontend from the keys bound to it
+#[derive(Debug, Copy, Clone, Default)]
+pub struct GyroSensor {
+	/// Rotation speed from -1.0 (counter-clockwise) to 1.0 (clockwise)
+	rotation: f32,
+	sample: u16,
+	clock: bool,
+	output: bool,
+	rumble: bool,
+}
+
+impl GyroSensor {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn get_rotation(&self) -> f32 {
+		self.rotation
+	}
+
+	pub fn set_rotation(&mut self, rotation: f32) {
+		self.rotation = rotation.clamp(-1.0, 1.0);
+	}
+
+	/// Whether the game turned the rumble motor on
+	pub fn is_rumbling(&self) -> bool {
+		self.rumble
+	}
+
+	pub fn read_pins(&self) -> u16 {
+		GYRO_DATA_PIN * self.output as u16
+	}
+
+	/// A bit of the sample is output on each falling edge of the clock
+	pub fn write_pins(&mut self, pins: u16) {
+		if pins & GYRO_SAMPLE_PIN != 0 {
+			self.sample = (GYRO_SENSOR_CENTER + (self.rotation * GYRO_SENSOR_RANGE as f32) as i32) as u16;
+		}
+
+		let clock = pins & GYRO_CLOCK_PIN != 0;
+		if self.clock && !clock {
+			self.output = self.sample & 0x8000 != 0;
+			self.sample <<= 1;
+		}
+		self.clock = clock;
+		self.rumble = pins & RUMBLE_PIN != 0;
+	}
+}
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Sample, SampleFormat, Stream, StreamConfig};
+
+use crate::apu::SAMPLE_RATE;
+
+/// Audio kept queued for the device, the emulation waits while there's more than this
+pub const TARGET_LATENCY: Duration = Duration::from_millis(50);
+/// Above this the oldest samples are dropped, eg. when the emulation runs faster than the audio
+const MAX_LATENCY: Duration = Duration::from_millis(200);
+
+/// Host audio output, plays the APU samples on the default device
+///
+/// The samples are resampled from the APU rate to the device rate, and the device consuming them is the clock the emulation
+/// is paced with, so the audio never drifts from the video.
+pub struct AudioOutput {
+	// NOTE: The stream stops when dropped
+	_stream: Stream,
+	/// Interleaved left/right samples at the device rate
+	queue: Arc<Mutex<VecDeque<f32>>>,
+	device_rate: u32,
+	/// Position between the last 2 APU samples of the resampler, in APU samples
+	resample_position: f64,
+	last_sample: [f32; 2],
+}
+
+impl AudioOutput {
+	/// Open a stream on the default output device, None when there's no usable device
+	pub fn new() -> Option<Self> {
+		let device = cpal::default_host().default_output_device()?;
+		let supported_config = device.default_output_config().ok()?;
+		let sample_format = supported_config.sample_format();
+		let config: StreamConfig = supported_config.into();
+
+		let queue = Arc::new(Mutex::new(VecDeque::new()));
+		let stream = match sample_format {
+			SampleFormat::F32 => Self::build_stream::<f32>(&device, &config, queue.clone()),
+			SampleFormat::I16 => Self::build_stream::<i16>(&device, &config, queue.clone()),
+			SampleFormat::U16 => Self::build_stream::<u16>(&device, &config, queue.clone()),
+		}?;
+		stream.play().ok()?;
+
+		Some(Self {
+			_stream: stream,
+			queue,
+			device_rate: config.sample_rate.0,
+			resample_position: 0.0,
+			last_sample: [0.0; 2],
+		})
+	}
+
+	fn build_stream<T: Sample>(device: &cpal::Device, config: &StreamConfig, queue: Arc<Mutex<VecDeque<f32>>>) -> Option<Stream> {
+		let channels = config.channels as usize;
+		let stream = device.build_output_stream(
+			config,
+			move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+				let mut queue = queue.lock().unwrap();
+				for frame in data.chunks_mut(channels) {
+					// NOTE: Underruns play silence
+					let left = queue.pop_front().unwrap_or_default();
+					let right = queue.pop_front().unwrap_or_default();
+					for (i, sample) in frame.iter_mut().enumerate() {
+						let value = match (channels, i) {
+							(1, _) => (left + right) / 2.0,
+							(_, 0) => left,
+							(_, 1) => right,
+							_ => 0.0,
+						};
+						*sample = T::from(&value);
+					}
+				}
+			},
+			|e| eprintln!("Audio stream error: {}", e),
+		);
+
+		stream.ok()
+	}
+
+	pub fn get_device_rate(&self) -> u32 {
+		self.device_rate
+	}
+
+	/// Duration of the audio queued and not played yet
+	pub fn get_queued_duration(&self) -> Duration {
+		let frames = self.queue.lock().unwrap().len() / 2;
+		Duration::from_secs_f64(frames as f64 / self.device_rate as f64)
+	}
+
+	/// Queue interleaved left/right samples at the APU rate, resampled (linearly) to the device rate
+	pub fn push_samples(&mut self, samples: &[f32]) {
+		let step = SAMPLE_RATE as f64 / self.device_rate as f64;
+		let max_length = (MAX_LATENCY.as_secs_f64() * self.device_rate as f64) as usize * 2;
+
+		let mut queue = self.queue.lock().unwrap();
+		for sample in samples.chunks_exact(2) {
+			while self.resample_position < 1.0 {
+				let t = self.resample_position as f32;
+				queue.push_back(self.last_sample[0] + (sample[0] - self.last_sample[0]) * t);
+				queue.push_back(self.last_sample[1] + (sample[1] - self.last_sample[1]) * t);
+				self.resample_position += step;
+			}
+
+			self.resample_position -= 1.0;
+			self.last_sample = [sample[0], sample[1]];
+		}
+
+		if queue.len() > max_length {
+			let excess = queue.len() - max_length;
+			queue.drain(..excess);
+		}
+	}
+
+	/// Block until the device has played enough of the queue, this is what paces the emulation
+	/// NOTE: Returns immediately when the queue runs dry (eg. while paused in the debugger)
+	pub fn wait_for_device(&self) {
+		let queued = self.get_queued_duration();
+		if queued > TARGET_LATENCY {
+			spin_sleep::sleep(queued - TARGET_LATENCY);
+		}
+	}
+}
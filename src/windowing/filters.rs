@@ -0,0 +1,129 @@
+/// Accessibility filters applied to the presented frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EColorFilter {
+	None,
+	Grayscale,
+	/// Simulate how a protanope (no red cones) sees the frame
+	ProtanopiaSimulation,
+	/// Simulate how a deuteranope (no green cones) sees the frame
+	DeuteranopiaSimulation,
+	/// Shift the colors a protanope can't tell apart to ones they can (daltonization)
+	ProtanopiaCorrection,
+	/// Shift the colors a deuteranope can't tell apart to ones they can (daltonization)
+	DeuteranopiaCorrection,
+}
+
+type ColorMatrix = [[f32; 3]; 3];
+
+// NOTE: Machado et al. (2009) matrices for full severity
+const PROTANOPIA: ColorMatrix = [[0.152_286, 1.052_583, -0.204_868], [0.114_503, 0.786_281, 0.099_216], [-0.003_882, -0.048_116, 1.051_998]];
+const DEUTERANOPIA: ColorMatrix = [[0.367_322, 0.860_646, -0.227_968], [0.280_085, 0.672_501, 0.047_413], [-0.011_820, 0.042_940, 0.968_881]];
+// NOTE: Moves the lost information to the channels that are still seen
+const ERROR_SHIFT: ColorMatrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+fn multiply(matrix: &ColorMatrix, color: [f32; 3]) -> [f32; 3] {
+	let mut result = [0.0; 3];
+	for (value, row) in result.iter_mut().zip(matrix.iter()) {
+		*value = row[0] * color[0] + row[1] * color[1] + row[2] * color[2];
+	}
+
+	result
+}
+
+fn correct(matrix: &ColorMatrix, color: [f32; 3]) -> [f32; 3] {
+	let simulated = multiply(matrix, color);
+	let error = [color[0] - simulated[0], color[1] - simulated[1], color[2] - simulated[2]];
+	let shift = multiply(&ERROR_SHIFT, error);
+
+	[color[0] + shift[0], color[1] + shift[1], color[2] + shift[2]]
+}
+
+impl EColorFilter {
+	pub const ALL: [EColorFilter; 6] = [
+		EColorFilter::None,
+		EColorFilter::Grayscale,
+		EColorFilter::ProtanopiaSimulation,
+		EColorFilter::DeuteranopiaSimulation,
+		EColorFilter::ProtanopiaCorrection,
+		EColorFilter::DeuteranopiaCorrection,
+	];
+
+	pub fn get_name(self) -> &'static str {
+		match self {
+			EColorFilter::None => "None",
+			EColorFilter::Grayscale => "Grayscale",
+			EColorFilter::ProtanopiaSimulation => "Protanopia (Simulation)",
+			EColorFilter::DeuteranopiaSimulation => "Deuteranopia (Simulation)",
+			EColorFilter::ProtanopiaCorrection => "Protanopia (Correction)",
+			EColorFilter::DeuteranopiaCorrection => "Deuteranopia (Correction)",
+		}
+	}
+
+	/// Filter a frame of RGB triplets in place
+	pub fn apply(self, pixels: &mut [f32]) {
+		if self == EColorFilter::None {
+			return;
+		}
+
+		for pixel in pixels.chunks_exact_mut(3) {
+			let color = [pixel[0], pixel[1], pixel[2]];
+			let filtered = match self {
+				EColorFilter::Grayscale => {
+					let luma = 0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2];
+					[luma; 3]
+				}
+				EColorFilter::ProtanopiaSimulation => multiply(&PROTANOPIA, color),
+				EColorFilter::DeuteranopiaSimulation => multiply(&DEUTERANOPIA, color),
+				EColorFilter::ProtanopiaCorrection => correct(&PROTANOPIA, color),
+				EColorFilter::DeuteranopiaCorrection => correct(&DEUTERANOPIA, color),
+				EColorFilter::None => color,
+			};
+
+			for (channel, value) in pixel.iter_mut().zip(filtered.iter()) {
+				*channel = value.clamp(0.0, 1.0);
+			}
+		}
+	}
+}
+
+/// Highest weight of the previous frame, above it moving objects never fade out
+pub const MAX_FRAME_BLEND_WEIGHT: f32 = 0.9;
+
+/// LCD ghosting emulation, mixes each frame with the previous one
+///
+/// The slow response of the GBA LCD blends consecutive frames, some games rely on it to draw transparent objects by flickering them.
+pub struct FrameBlender {
+	/// Weight of the previous frame, 0 disables the blending
+	weight: f32,
+	previous: Vec<f32>,
+}
+
+impl FrameBlender {
+	pub fn new(weight: f32) -> Self {
+		Self {
+			weight: weight.clamp(0.0, MAX_FRAME_BLEND_WEIGHT),
+			previous: Vec::new(),
+		}
+	}
+
+	pub fn get_weight(&self) -> f32 {
+		self.weight
+	}
+
+	pub fn set_weight(&mut self, weight: f32) {
+		self.weight = weight.clamp(0.0, MAX_FRAME_BLEND_WEIGHT);
+	}
+
+	/// Blend a frame of RGB triplets in place with the previous frame given
+	/// NOTE: The unblended frame is kept, so a sprite shown every other frame settles at a fixed opacity
+	pub fn apply(&mut self, pixels: &mut [f32]) {
+		let current = pixels.to_vec();
+		if self.weight > 0.0 && self.previous.len() == pixels.len() {
+			for (channel, previous) in pixels.iter_mut().zip(self.previous.iter()) {
+				*channel = *channel * (1.0 - self.weight) + previous * self.weight;
+			}
+		}
+
+		self.previous = current;
+	}
+}
@@ -1,14 +1,162 @@
+use std::fmt;
 use std::path::Path;
 
+use glium::backend::glutin::DisplayCreationError;
 use glium::glutin;
+use glium::glutin::event::VirtualKeyCode;
 use glium::glutin::event_loop::EventLoop;
 use glium::glutin::window::WindowBuilder;
 use glium::Display;
 use imgui::{Context, FontConfig, FontSource};
-use imgui_glium_renderer::Renderer;
+use imgui_glium_renderer::{Renderer, RendererError};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
 
+pub mod audio;
 mod clipboard;
+pub mod filters;
+pub mod profile;
+
+/// Clockwise rotation of the presented frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ERotation {
+	None,
+	Rotate90,
+	Rotate180,
+	Rotate270,
+}
+
+impl ERotation {
+	pub fn get_name(self) -> &'static str {
+		match self {
+			ERotation::None => "None",
+			ERotation::Rotate90 => "90°",
+			ERotation::Rotate180 => "180°",
+			ERotation::Rotate270 => "270°",
+		}
+	}
+
+	pub fn get_degrees(self) -> u32 {
+		match self {
+			ERotation::None => 0,
+			ERotation::Rotate90 => 90,
+			ERotation::Rotate180 => 180,
+			ERotation::Rotate270 => 270,
+		}
+	}
+
+	/// Rotation for an angle in degrees, angles that aren't a multiple of 90 aren't rotated
+	pub fn from_degrees(degrees: u32) -> Self {
+		match degrees % 360 {
+			90 => ERotation::Rotate90,
+			180 => ERotation::Rotate180,
+			270 => ERotation::Rotate270,
+			_ => ERotation::None,
+		}
+	}
+
+	/// Size of a width x height frame once rotated
+	pub fn get_dimensions(self, width: usize, height: usize) -> (usize, usize) {
+		match self {
+			ERotation::None | ERotation::Rotate180 => (width, height),
+			ERotation::Rotate90 | ERotation::Rotate270 => (height, width),
+		}
+	}
+
+	/// Rotate a frame of RGB triplets
+	/// Position on the rotated screen of a point of the GBA screen, eg. a corner of a sprite
+	pub fn rotate_point(self, x: f32, y: f32, width: usize, height: usize) -> (f32, f32) {
+		match self {
+			ERotation::None => (x, y),
+			ERotation::Rotate90 => (height as f32 - y, x),
+			ERotation::Rotate180 => (width as f32 - x, height as f32 - y),
+			ERotation::Rotate270 => (y, width as f32 - x),
+		}
+	}
+
+	pub fn rotate_frame(self, pixels: &[f32], width: usize, height: usize) -> Vec<f32> {
+		if self == ERotation::None {
+			return pixels.to_vec();
+		}
+
+		let (rotated_width, _) = self.get_dimensions(width, height);
+		let mut rotated = vec![0.0; pixels.len()];
+		for y in 0..height {
+			for x in 0..width {
+				let (rotated_x, rotated_y) = match self {
+					ERotation::Rotate90 => (height - 1 - y, x),
+					ERotation::Rotate180 => (width - 1 - x, height - 1 - y),
+					ERotation::Rotate270 => (y, width - 1 - x),
+					ERotation::None => (x, y),
+				};
+
+				let source = (x + y * width) * 3;
+				let destination = (rotated_x + rotated_y * rotated_width) * 3;
+				rotated[destination..destination + 3].copy_from_slice(&pixels[source..source + 3]);
+			}
+		}
+
+		rotated
+	}
+
+	/// Map an arrow key on the rotated screen to the direction it points to on the GBA screen
+	pub fn remap_direction(self, key_code: VirtualKeyCode) -> VirtualKeyCode {
+		const CLOCKWISE: [VirtualKeyCode; 4] = [VirtualKeyCode::Up, VirtualKeyCode::Right, VirtualKeyCode::Down, VirtualKeyCode::Left];
+		let steps = match self {
+			ERotation::None => 0,
+			ERotation::Rotate90 => 3,
+			ERotation::Rotate180 => 2,
+			ERotation::Rotate270 => 1,
+		};
+
+		match CLOCKWISE.iter().position(|direction| *direction == key_code) {
+			Some(index) => CLOCKWISE[(index + steps) % 4],
+			None => key_code,
+		}
+	}
+}
+
+/// Keys that can be bound to the cartridge sensors, by name, the others are taken by the GBA buttons and the shortcuts
+pub const BINDABLE_KEYS: [(VirtualKeyCode, &str); 12] = [
+	(VirtualKeyCode::Q, "Q"),
+	(VirtualKeyCode::W, "W"),
+	(VirtualKeyCode::E, "E"),
+	(VirtualKeyCode::D, "D"),
+	(VirtualKeyCode::C, "C"),
+	(VirtualKeyCode::V, "V"),
+	(VirtualKeyCode::Key1, "1"),
+	(VirtualKeyCode::Key2, "2"),
+	(VirtualKeyCode::Comma, "Comma"),
+	(VirtualKeyCode::Period, "Period"),
+	(VirtualKeyCode::PageUp, "Page Up"),
+	(VirtualKeyCode::PageDown, "Page Down"),
+];
+
+pub fn get_key_name(key_code: VirtualKeyCode) -> &'static str {
+	BINDABLE_KEYS.iter().find(|(key, _)| *key == key_code).map_or("?", |(_, name)| name)
+}
+
+pub fn find_key(name: &str) -> Option<VirtualKeyCode> {
+	BINDABLE_KEYS.iter().find(|(_, key_name)| *key_name == name).map(|(key, _)| *key)
+}
+
+/// Why the window couldn't be created, usually a machine without a display or proper OpenGL drivers
+#[derive(Debug)]
+pub enum EInitError {
+	/// No display server to open a window on
+	NoWindowingSystem,
+	Display(DisplayCreationError),
+	Renderer(RendererError),
+}
+
+impl fmt::Display for EInitError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EInitError::NoWindowingSystem => write!(f, "No windowing system available"),
+			EInitError::Display(e) => write!(f, "Failed to initialize display: {}", e),
+			EInitError::Renderer(e) => write!(f, "Failed to initialize renderer: {}", e),
+		}
+	}
+}
 
 pub struct System {
 	pub event_loop: EventLoop<()>,
@@ -19,15 +167,19 @@ pub struct System {
 	pub font_size: f32,
 }
 
-pub fn init(title: &str) -> System {
+/// Open the window and its renderer, an error leaves the frontend to fall back to the headless modes
+pub fn init(title: &str) -> Result<System, EInitError> {
 	let title = match Path::new(&title).file_name() {
 		Some(file_name) => file_name.to_str().unwrap(),
 		None => title,
 	};
-	let event_loop = EventLoop::new();
-	let context = glutin::ContextBuilder::new().with_vsync(false);
+	// NOTE: winit panics instead of returning an error when there's no X11/Wayland display
+	let event_loop = std::panic::catch_unwind(EventLoop::new).map_err(|_| EInitError::NoWindowingSystem)?;
 	let builder = WindowBuilder::new().with_title(title.to_owned()).with_maximized(true);
-	let display = Display::new(builder, context, &event_loop).expect("Failed to initialize display");
+	// NOTE: Retry without requiring hardware acceleration, software OpenGL (eg. Mesa llvmpipe) is often all there is over remote desktop or in VMs
+	let display = Display::new(builder.clone(), glutin::ContextBuilder::new().with_vsync(false), &event_loop)
+		.or_else(|_| Display::new(builder, glutin::ContextBuilder::new().with_vsync(false).with_hardware_acceleration(None), &event_loop))
+		.map_err(EInitError::Display)?;
 
 	let mut imgui = Context::create();
 	imgui.set_ini_filename(None);
@@ -56,14 +208,14 @@ pub fn init(title: &str) -> System {
 
 	imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
 
-	let renderer = Renderer::init(&mut imgui, &display).expect("Failed to initialize renderer");
+	let renderer = Renderer::init(&mut imgui, &display).map_err(EInitError::Renderer)?;
 
-	System {
+	Ok(System {
 		event_loop,
 		display,
 		imgui,
 		platform,
 		renderer,
 		font_size,
-	}
+	})
 }
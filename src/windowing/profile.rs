@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use glium::glutin::event::VirtualKeyCode;
+use serde_json::{json, Value};
+
+use crate::cheats::{Cheat, ECheatFormat};
+use crate::debugging::breakpoints::{Breakpoint, BreakpointCondition};
+use crate::gba::ERefreshRate;
+use crate::system::cartridge::get_game_code;
+use crate::windowing::filters::EColorFilter;
+use crate::windowing::{find_key, get_key_name, ERotation};
+
+pub const PROFILES_DIRECTORY: &str = "config/profiles";
+pub const SNAPSHOTS_DIRECTORY: &str = "config/snapshots";
+pub const SAVE_STATES_DIRECTORY: &str = "config/states";
+pub const BOOKMARKS_DIRECTORY: &str = "config/bookmarks";
+
+/// Keys turning the gyro sensor counter-clockwise and clockwise
+pub const DEFAULT_GYRO_KEYS: [VirtualKeyCode; 2] = [VirtualKeyCode::Q, VirtualKeyCode::W];
+
+/// Debugging context and video settings that are restored when a game is opened again
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+	/// imgui ini settings (window positions and sizes)
+	pub layout: String,
+	/// Whether each debug window is opened, by name
+	pub windows: BTreeMap<String, bool>,
+	pub breakpoints: Vec<Breakpoint>,
+	pub cheats: Vec<Cheat>,
+	pub rotation: ERotation,
+	pub color_filter: EColorFilter,
+	/// Weight of the previous frame in the LCD ghosting emulation, 0 is disabled
+	pub frame_blend_weight: f32,
+	pub refresh_rate: ERefreshRate,
+	pub gyro_keys: [VirtualKeyCode; 2],
+}
+
+impl Profile {
+	pub fn is_window_open(&self, name: &str, default: bool) -> bool {
+		self.windows.get(name).cloned().unwrap_or(default)
+	}
+
+	pub fn to_json(&self) -> Value {
+		json!({
+			"layout": self.layout,
+			"windows": self.windows,
+			"breakpoints": self.breakpoints.iter().map(|breakpoint| json!({
+				"address": breakpoint.address,
+				"condition": breakpoint.condition.map(|condition| condition.get_text()),
+				"ignore_count": breakpoint.ignore_count,
+				"enabled": breakpoint.enabled,
+			})).collect::<Vec<Value>>(),
+			"cheats": self.cheats.iter().map(|cheat| json!({
+				"name": cheat.name,
+				"format": cheat.format.get_name(),
+				"code": cheat.code,
+				"enabled": cheat.enabled,
+			})).collect::<Vec<Value>>(),
+			"rotation": self.rotation.get_degrees(),
+			"color_filter": self.color_filter.get_name(),
+			"frame_blend_weight": self.frame_blend_weight,
+			"refresh_rate": match self.refresh_rate {
+				ERefreshRate::Hardware => "Hardware",
+				ERefreshRate::MatchMonitor => "MatchMonitor",
+			},
+			"gyro_keys": [get_key_name(self.gyro_keys[0]), get_key_name(self.gyro_keys[1])],
+		})
+	}
+
+	/// Parse a profile, missing or unknown values fall back to the defaults
+	pub fn from_json(value: &Value) -> Self {
+		let windows = match value["windows"].as_object() {
+			Some(windows) => windows.iter().filter_map(|(name, open)| open.as_bool().map(|open| (name.clone(), open))).collect(),
+			None => BTreeMap::new(),
+		};
+
+		// NOTE: Profiles from before the breakpoint list have a single "breakpoint" address
+		let breakpoints = match value["breakpoints"].as_array() {
+			Some(breakpoints) => breakpoints
+				.iter()
+				.filter_map(|breakpoint| {
+					Some(Breakpoint {
+						condition: breakpoint["condition"].as_str().and_then(BreakpointCondition::parse),
+						ignore_count: breakpoint["ignore_count"].as_u64().unwrap_or_default() as u32,
+						enabled: breakpoint["enabled"].as_bool().unwrap_or(true),
+						..Breakpoint::new(breakpoint["address"].as_u64()? as u32)
+					})
+				})
+				.collect(),
+			None => value["breakpoint"].as_u64().map(|address| Breakpoint::new(address as u32)).into_iter().collect(),
+		};
+
+		// NOTE: Codes are decoded again, the ones that fail to are dropped
+		let cheats = value["cheats"]
+			.as_array()
+			.map(|cheats| {
+				cheats
+					.iter()
+					.filter_map(|cheat| {
+						let format = ECheatFormat::ALL.iter().cloned().find(|format| Some(format.get_name()) == cheat["format"].as_str())?;
+						let mut parsed = Cheat::parse(cheat["name"].as_str()?, format, cheat["code"].as_str()?).ok()?;
+						parsed.enabled = cheat["enabled"].as_bool().unwrap_or(true);
+						Some(parsed)
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+
+		Self {
+			layout: value["layout"].as_str().unwrap_or_default().to_string(),
+			windows,
+			breakpoints,
+			cheats,
+			rotation: value["rotation"].as_u64().map_or(ERotation::None, |degrees| ERotation::from_degrees(degrees as u32)),
+			color_filter: EColorFilter::ALL
+				.iter()
+				.cloned()
+				.find(|filter| Some(filter.get_name()) == value["color_filter"].as_str())
+				.unwrap_or(EColorFilter::None),
+			frame_blend_weight: value["frame_blend_weight"].as_f64().unwrap_or_default() as f32,
+			refresh_rate: match value["refresh_rate"].as_str() {
+				Some("MatchMonitor") => ERefreshRate::MatchMonitor,
+				_ => ERefreshRate::Hardware,
+			},
+			gyro_keys: [0, 1].map(|i| value["gyro_keys"][i].as_str().and_then(find_key).unwrap_or(DEFAULT_GYRO_KEYS[i])),
+		}
+	}
+
+	pub fn load(path: &Path) -> Option<Self> {
+		let data = std::fs::read_to_string(path).ok()?;
+		let value = serde_json::from_str(&data).ok()?;
+
+		Some(Self::from_json(&value))
+	}
+
+	pub fn save(&self, path: &Path) -> std::io::Result<()> {
+		if let Some(directory) = path.parent() {
+			std::fs::create_dir_all(directory)?;
+		}
+
+		std::fs::write(path, serde_json::to_string_pretty(&self.to_json()).unwrap())
+	}
+}
+
+/// File the profile of a ROM is stored in, keyed by game code and ROM hash so different revisions of a game don't share it
+pub fn get_profile_path(rom: &[u8]) -> PathBuf {
+	Path::new(PROFILES_DIRECTORY).join(format!("{}_{:08x}.json", get_game_code(rom), crc32fast::hash(rom)))
+}
+
+/// File the exported save memory of a ROM is stored in, a plain .sav so it can be exchanged with other emulators
+pub fn get_backup_snapshot_path(rom: &[u8]) -> PathBuf {
+	Path::new(SNAPSHOTS_DIRECTORY).join(format!("{}_{:08x}.sav", get_game_code(rom), crc32fast::hash(rom)))
+}
+
+pub fn get_save_state_path(rom: &[u8]) -> PathBuf {
+	Path::new(SAVE_STATES_DIRECTORY).join(format!("{}_{:08x}.state", get_game_code(rom), crc32fast::hash(rom)))
+}
+
+/// File the memory bookmarks of a ROM are stored in, a text file so they can be edited by hand
+pub fn get_bookmarks_path(rom: &[u8]) -> PathBuf {
+	Path::new(BOOKMARKS_DIRECTORY).join(format!("{}_{:08x}.txt", get_game_code(rom), crc32fast::hash(rom)))
+}